@@ -23,9 +23,10 @@ pub use micro_http::{
     ServerResponse, StatusCode, Version,
 };
 use mmds::data_store;
-use mmds::data_store::Mmds;
+use mmds::data_store::{Mmds, PatchFormat};
 use seccomp::{BpfProgram, SeccompFilter};
 use utils::eventfd::EventFd;
+use vmm::measured_boot::BootMeasurements;
 use vmm::rpc_interface::{VmmAction, VmmActionError, VmmData};
 use vmm::vmm_config::instance_info::InstanceInfo;
 use vmm::vmm_config::snapshot::SnapshotType;
@@ -113,6 +114,8 @@ impl ApiServer {
     /// * `start_time_us` - the timestamp for when the process was started in us.
     /// * `start_time_cpu_us` - the timestamp for when the process was started in CPU us.
     /// * `seccomp_filter` - the seccomp filter to apply.
+    /// * `affinity_receiver` - the channel on which the VMM sends the host CPUs of the API
+    ///   thread, once the microVM configuration is known.
     ///
     /// # Example
     ///
@@ -136,11 +139,13 @@ impl ApiServer {
     ///     id: "test_serve_action_req".to_string(),
     ///     vmm_version: "version 0.1.0".to_string(),
     ///     app_name: "app name".to_string(),
+    ///     boot_measurements: None,
     /// };
     ///
     /// let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
     /// let (api_request_sender, _from_api) = channel();
     /// let (to_api, vmm_response_receiver) = channel();
+    /// let (_api_affinity_sender, api_affinity_receiver) = channel();
     /// let mmds_info = MMDS.clone();
     ///
     /// thread::Builder::new()
@@ -158,6 +163,7 @@ impl ApiServer {
     ///             Some(1),
     ///             Some(1),
     ///             SeccompFilter::empty().try_into().unwrap(),
+    ///             api_affinity_receiver,
     ///         )
     ///         .unwrap();
     ///     })
@@ -176,6 +182,7 @@ impl ApiServer {
         start_time_us: Option<u64>,
         start_time_cpu_us: Option<u64>,
         seccomp_filter: BpfProgram,
+        affinity_receiver: mpsc::Receiver<Vec<usize>>,
     ) -> Result<()> {
         let mut server = HttpServer::new(path).unwrap_or_else(|e| {
             error!("Error creating the HTTP server: {}", e);
@@ -211,7 +218,15 @@ impl ApiServer {
 
         server.start_server().expect("Cannot start HTTP server");
         loop {
-            match server.requests() {
+            let requests = server.requests();
+            // The API thread pins itself before serving the requests, since the seccomp filters
+            // only let a thread change its own affinity.
+            if let Ok(api_affinity) = affinity_receiver.try_recv() {
+                if let Err(e) = utils::sched::set_current_thread_affinity(&api_affinity) {
+                    error!("Cannot set the affinity of the API thread: {}", e);
+                }
+            }
+            match requests {
                 Ok(request_vec) => {
                     for server_request in request_vec {
                         let request_processing_start_us =
@@ -265,9 +280,12 @@ impl ApiServer {
                 self.serve_vmm_action_request(vmm_action, request_processing_start_us)
             }
             Ok(ParsedRequest::GetInstanceInfo) => self.get_instance_info(),
-            Ok(ParsedRequest::GetMMDS) => self.get_mmds(),
-            Ok(ParsedRequest::PatchMMDS(value)) => self.patch_mmds(value),
-            Ok(ParsedRequest::PutMMDS(value)) => self.put_mmds(value),
+            Ok(ParsedRequest::GetMMDS(mmds_id)) => self.get_mmds(mmds_id),
+            Ok(ParsedRequest::GetGuestMMDS(mmds_id)) => self.get_guest_mmds(mmds_id),
+            Ok(ParsedRequest::PatchMMDS(mmds_id, format, value)) => {
+                self.patch_mmds(mmds_id, format, value)
+            }
+            Ok(ParsedRequest::PutMMDS(mmds_id, value)) => self.put_mmds(mmds_id, value),
             Err(e) => {
                 error!("{}", e);
                 e.into()
@@ -331,7 +349,12 @@ impl ApiServer {
         }
     }
 
-    fn get_instance_info(&self) -> Response {
+    fn get_instance_info(&mut self) -> Response {
+        // The boot measurements do not change once the microVM is booted, so they are only
+        // requested from the VMM until they are available.
+        if self.instance_info.boot_measurements.is_none() {
+            self.instance_info.boot_measurements = self.request_boot_measurements();
+        }
         let shared_info = self.instance_info.clone();
         // Serialize it to a JSON string.
         let body_result = serde_json::to_string(&shared_info);
@@ -348,44 +371,116 @@ impl ApiServer {
         }
     }
 
-    fn get_mmds(&self) -> Response {
+    fn request_boot_measurements(&mut self) -> Option<BootMeasurements> {
+        self.api_request_sender
+            .send(Box::new(VmmAction::GetBootMeasurements))
+            .expect("Failed to send VMM message");
+        self.to_vmm_fd.write(1).expect("Cannot update send VMM fd");
+        match *self.vmm_response_receiver.recv().expect("VMM disconnected") {
+            Ok(VmmData::BootMeasurements(boot_measurements)) => boot_measurements,
+            _ => None,
+        }
+    }
+
+    // Returns the MMDS instance named `mmds_id`, or the default instance if no name is given.
+    fn mmds_instance(&self, mmds_id: Option<String>) -> Result<Arc<Mutex<Mmds>>, Response> {
+        match mmds_id {
+            Some(mmds_id) => mmds::find_mmds_instance(&mmds_id).ok_or_else(|| {
+                ApiServer::json_response(
+                    StatusCode::BadRequest,
+                    ApiServer::json_fault_message(format!(
+                        "The MMDS instance {} does not exist.",
+                        mmds_id
+                    )),
+                )
+            }),
+            None => Ok(self.mmds_info.clone()),
+        }
+    }
+
+    fn get_mmds(&self, mmds_id: Option<String>) -> Response {
+        let mmds_info = match self.mmds_instance(mmds_id) {
+            Ok(mmds_info) => mmds_info,
+            Err(response) => return response,
+        };
         ApiServer::json_response(
             StatusCode::OK,
-            self.mmds_info
+            mmds_info
                 .lock()
                 .expect("Failed to acquire lock on MMDS info")
                 .get_data_str(),
         )
     }
 
-    fn patch_mmds(&self, value: serde_json::Value) -> Response {
-        let mmds_response = self
-            .mmds_info
+    fn get_guest_mmds(&self, mmds_id: Option<String>) -> Response {
+        let mmds_info = match self.mmds_instance(mmds_id) {
+            Ok(mmds_info) => mmds_info,
+            Err(response) => return response,
+        };
+        let guest_data = mmds_info
+            .lock()
+            .expect("Failed to acquire lock on MMDS info")
+            .get_guest_data_str();
+        match guest_data {
+            Ok(guest_data) => ApiServer::json_response(StatusCode::OK, guest_data),
+            Err(e) => ApiServer::json_response(
+                StatusCode::BadRequest,
+                ApiServer::json_fault_message(e.to_string()),
+            ),
+        }
+    }
+
+    fn patch_mmds(
+        &self,
+        mmds_id: Option<String>,
+        format: PatchFormat,
+        value: serde_json::Value,
+    ) -> Response {
+        let mmds_info = match self.mmds_instance(mmds_id) {
+            Ok(mmds_info) => mmds_info,
+            Err(response) => return response,
+        };
+        let mmds_response = mmds_info
             .lock()
             .expect("Failed to acquire lock on MMDS info")
-            .patch_data(value);
+            .patch_data_as(format, value);
 
         match mmds_response {
             Ok(_) => Response::new(Version::Http11, StatusCode::NoContent),
             Err(e) => match e {
-                data_store::Error::NotFound => unreachable!(),
-                data_store::Error::UnsupportedValueType => unreachable!(),
-                data_store::Error::NotInitialized => ApiServer::json_response(
-                    StatusCode::BadRequest,
+                data_store::Error::NotFound
+                | data_store::Error::UnsupportedValueType
+                | data_store::Error::GuestWriteRateExceeded
+                | data_store::Error::GuestWritesNotAllowed => unreachable!(),
+                data_store::Error::DataStoreLimitExceeded => ApiServer::json_response(
+                    StatusCode::PayloadTooLarge,
                     ApiServer::json_fault_message(e.to_string()),
                 ),
+                data_store::Error::InvalidPatch(_) | data_store::Error::NotInitialized => {
+                    ApiServer::json_response(
+                        StatusCode::BadRequest,
+                        ApiServer::json_fault_message(e.to_string()),
+                    )
+                }
             },
         }
     }
 
-    fn put_mmds(&self, value: serde_json::Value) -> Response {
-        let mmds_response = self
-            .mmds_info
+    fn put_mmds(&self, mmds_id: Option<String>, value: serde_json::Value) -> Response {
+        let mmds_info = match self.mmds_instance(mmds_id) {
+            Ok(mmds_info) => mmds_info,
+            Err(response) => return response,
+        };
+        let mmds_response = mmds_info
             .lock()
             .expect("Failed to acquire lock on MMDS info")
             .put_data(value);
         match mmds_response {
             Ok(_) => Response::new(Version::Http11, StatusCode::NoContent),
+            Err(e @ data_store::Error::DataStoreLimitExceeded) => ApiServer::json_response(
+                StatusCode::PayloadTooLarge,
+                ApiServer::json_fault_message(e.to_string()),
+            ),
             Err(e) => ApiServer::json_response(
                 StatusCode::BadRequest,
                 ApiServer::json_fault_message(e.to_string()),
@@ -459,6 +554,7 @@ mod tests {
             id: "test_serve_action_req".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            boot_measurements: None,
         };
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -499,6 +595,8 @@ mod tests {
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
                 version: None,
+                integrity_key_path: None,
+                background: false,
             })),
             start_time_us,
         );
@@ -513,6 +611,8 @@ mod tests {
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
                 version: None,
+                integrity_key_path: None,
+                background: false,
             })),
             start_time_us,
         );
@@ -528,13 +628,14 @@ mod tests {
             id: "test_get_instance_info".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            boot_measurements: None,
         };
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
         let (api_request_sender, _from_api) = channel();
-        let (_to_api, vmm_response_receiver) = channel();
+        let (to_api, vmm_response_receiver) = channel();
         let mmds_info = MMDS.clone();
 
-        let api_server = ApiServer::new(
+        let mut api_server = ApiServer::new(
             mmds_info,
             instance_info,
             api_request_sender,
@@ -542,8 +643,31 @@ mod tests {
             to_vmm_fd,
         );
 
+        to_api
+            .send(Box::new(Ok(VmmData::BootMeasurements(None))))
+            .unwrap();
+        let response = api_server.get_instance_info();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The boot measurements are fetched until the microVM reports them.
+        let boot_measurements = BootMeasurements {
+            kernel: "kernel digest".to_string(),
+            initrd: None,
+            cmdline: "cmdline digest".to_string(),
+            devices: "devices digest".to_string(),
+        };
+        to_api
+            .send(Box::new(Ok(VmmData::BootMeasurements(Some(
+                boot_measurements.clone(),
+            )))))
+            .unwrap();
+        api_server.get_instance_info();
         let response = api_server.get_instance_info();
         assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            api_server.instance_info.boot_measurements,
+            Some(boot_measurements)
+        );
     }
 
     #[test]
@@ -553,6 +677,7 @@ mod tests {
             id: "test_get_mmds".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            boot_measurements: None,
         };
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -568,7 +693,39 @@ mod tests {
             to_vmm_fd,
         );
 
-        let response = api_server.get_mmds();
+        let response = api_server.get_mmds(None);
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Named instances exist once configured.
+        let response = api_server.get_mmds(Some("api_server_tenant".to_string()));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        let response = api_server.put_mmds(
+            Some("api_server_tenant".to_string()),
+            serde_json::Value::Bool(true),
+        );
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        mmds::mmds_instance(Some("api_server_tenant"));
+        let response = api_server.put_mmds(
+            Some("api_server_tenant".to_string()),
+            serde_json::Value::Bool(true),
+        );
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let response = api_server.get_mmds(Some("api_server_tenant".to_string()));
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The guest data store is only readable when the guest can write it.
+        let response = api_server.get_guest_mmds(Some("api_server_guest".to_string()));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        let guest_mmds = mmds::mmds_instance(Some("api_server_guest"));
+        let response = api_server.get_guest_mmds(Some("api_server_guest".to_string()));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        guest_mmds.lock().unwrap().set_guest_write_limits(Some(
+            mmds::data_store::GuestWriteLimits {
+                data_store_limit: 64,
+                rate_limiter: None,
+            },
+        ));
+        let response = api_server.get_guest_mmds(Some("api_server_guest".to_string()));
         assert_eq!(response.status(), StatusCode::OK);
     }
 
@@ -579,6 +736,7 @@ mod tests {
             id: "test_put_mmds".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            boot_measurements: None,
         };
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -593,7 +751,7 @@ mod tests {
             vmm_response_receiver,
             to_vmm_fd,
         );
-        let response = api_server.put_mmds(serde_json::Value::String("string".to_string()));
+        let response = api_server.put_mmds(None, serde_json::Value::String("string".to_string()));
         assert_eq!(response.status(), StatusCode::NoContent);
     }
 
@@ -604,6 +762,7 @@ mod tests {
             id: "test_patch_mmds".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            boot_measurements: None,
         };
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -612,7 +771,7 @@ mod tests {
         let mmds_info = Arc::new(Mutex::new(Mmds::default()));
 
         let api_server = ApiServer::new(
-            mmds_info,
+            mmds_info.clone(),
             instance_info,
             api_request_sender,
             vmm_response_receiver,
@@ -620,16 +779,55 @@ mod tests {
         );
 
         // MMDS data store is not yet initialized.
-        let response = api_server.patch_mmds(serde_json::Value::Bool(true));
+        let merge_patch = PatchFormat::MergePatch;
+        let response = api_server.patch_mmds(None, merge_patch, serde_json::Value::Bool(true));
         assert_eq!(response.status(), StatusCode::BadRequest);
 
-        let response = api_server.put_mmds(serde_json::Value::String("string".to_string()));
+        let response = api_server.put_mmds(None, serde_json::Value::String("string".to_string()));
         assert_eq!(response.status(), StatusCode::NoContent);
 
-        let response = api_server.patch_mmds(serde_json::Value::String(
-            "{ \"key\" : \"value\" }".to_string(),
-        ));
+        let response = api_server.patch_mmds(
+            None,
+            merge_patch,
+            serde_json::Value::String("{ \"key\" : \"value\" }".to_string()),
+        );
         assert_eq!(response.status(), StatusCode::NoContent);
+
+        // The data store cannot grow past its size limit.
+        mmds_info.lock().unwrap().set_data_store_limit(16);
+        let response = api_server.put_mmds(None, serde_json::json!({ "key": "a longer value" }));
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+        let response = api_server.patch_mmds(
+            None,
+            merge_patch,
+            serde_json::json!({ "key": "a longer value" }),
+        );
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+
+        // JSON Patch operations apply to the data store as a whole, or not at all.
+        mmds_info.lock().unwrap().set_data_store_limit(64);
+        let json_patch = PatchFormat::JsonPatch;
+        let response = api_server.put_mmds(None, serde_json::json!({ "key": "value" }));
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let response = api_server.patch_mmds(
+            None,
+            json_patch,
+            serde_json::json!([{ "op": "move", "from": "/key", "path": "/other" }]),
+        );
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let response = api_server.patch_mmds(
+            None,
+            json_patch,
+            serde_json::json!([
+                { "op": "remove", "path": "/other" },
+                { "op": "remove", "path": "/key" }
+            ]),
+        );
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert_eq!(
+            mmds_info.lock().unwrap().get_data_str(),
+            r#"{"other":"value"}"#
+        );
     }
 
     #[test]
@@ -639,6 +837,7 @@ mod tests {
             id: "test_handle_request".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            boot_measurements: None,
         };
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
@@ -743,12 +942,18 @@ mod tests {
             id: "test_handle_request".to_string(),
             vmm_version: "version 0.1.0".to_string(),
             app_name: "app name".to_string(),
+            boot_measurements: None,
         };
 
         let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
         let (api_request_sender, _from_api) = channel();
-        let (_to_api, vmm_response_receiver) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+        let (_api_affinity_sender, api_affinity_receiver) = channel();
         let mmds_info = MMDS.clone();
+        // Response to the boot measurements request of the GET instance-info request.
+        to_api
+            .send(Box::new(Ok(VmmData::BootMeasurements(None))))
+            .unwrap();
 
         thread::Builder::new()
             .name("fc_api_test".to_owned())
@@ -765,6 +970,7 @@ mod tests {
                     Some(1),
                     Some(1),
                     SeccompFilter::empty().try_into().unwrap(),
+                    api_affinity_receiver,
                 )
                 .unwrap();
             })
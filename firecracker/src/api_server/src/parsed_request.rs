@@ -1,12 +1,15 @@
 // Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use mmds::data_store::PatchFormat;
 use serde_json::Value;
 
 use super::VmmData;
 use crate::request::actions::parse_put_actions;
 use crate::request::balloon::{parse_get_balloon, parse_patch_balloon, parse_put_balloon};
 use crate::request::boot_source::parse_put_boot_source;
+use crate::request::cpu_configuration::parse_put_cpu_config;
+use crate::request::dns::parse_put_dns;
 use crate::request::drive::{parse_patch_drive, parse_put_drive};
 use crate::request::instance_info::parse_get_instance_info;
 use crate::request::logger::parse_put_logger;
@@ -17,7 +20,9 @@ use crate::request::metrics::parse_put_metrics;
 use crate::request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
 use crate::request::net::{parse_patch_net, parse_put_net};
 use crate::request::snapshot::parse_patch_vm_state;
-use crate::request::snapshot::parse_put_snapshot;
+use crate::request::snapshot::{parse_get_snapshot, parse_put_snapshot};
+use crate::request::tpm::parse_put_tpm;
+use crate::request::vcpu_stats::parse_get_vcpu_stats;
 use crate::request::vsock::parse_put_vsock;
 use crate::ApiServer;
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
@@ -27,9 +32,10 @@ use vmm::rpc_interface::{VmmAction, VmmActionError};
 
 pub(crate) enum ParsedRequest {
     GetInstanceInfo,
-    GetMMDS,
-    PatchMMDS(Value),
-    PutMMDS(Value),
+    GetMMDS(Option<String>),
+    GetGuestMMDS(Option<String>),
+    PatchMMDS(Option<String>, PatchFormat, Value),
+    PutMMDS(Option<String>, Value),
     Sync(Box<VmmAction>),
 }
 
@@ -59,26 +65,40 @@ impl ParsedRequest {
             (Method::Get, "", None) => parse_get_instance_info(),
             (Method::Get, "balloon", None) => parse_get_balloon(path_tokens.get(1)),
             (Method::Get, "machine-config", None) => parse_get_machine_config(),
-            (Method::Get, "mmds", None) => parse_get_mmds(),
+            (Method::Get, "mmds", None) => {
+                parse_get_mmds(path_tokens.get(1), path_tokens.get(2), path_tokens.get(3))
+            }
+            (Method::Get, "snapshot", None) => parse_get_snapshot(path_tokens.get(1)),
+            (Method::Get, "vcpu-stats", None) => parse_get_vcpu_stats(),
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
             (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
             (Method::Put, "boot-source", Some(body)) => parse_put_boot_source(body),
+            (Method::Put, "cpu-config", Some(body)) => parse_put_cpu_config(body),
+            (Method::Put, "dns", Some(body)) => parse_put_dns(body),
             (Method::Put, "drives", Some(body)) => parse_put_drive(body, path_tokens.get(1)),
             (Method::Put, "logger", Some(body)) => parse_put_logger(body),
             (Method::Put, "machine-config", Some(body)) => parse_put_machine_config(body),
             (Method::Put, "metrics", Some(body)) => parse_put_metrics(body),
-            (Method::Put, "mmds", Some(body)) => parse_put_mmds(body, path_tokens.get(1)),
+            (Method::Put, "mmds", Some(body)) => {
+                parse_put_mmds(body, path_tokens.get(1), path_tokens.get(2))
+            }
             (Method::Put, "network-interfaces", Some(body)) => {
                 parse_put_net(body, path_tokens.get(1))
             }
             (Method::Put, "snapshot", Some(body)) => parse_put_snapshot(body, path_tokens.get(1)),
+            (Method::Put, "tpm", Some(body)) => parse_put_tpm(body),
             (Method::Put, "vsock", Some(body)) => parse_put_vsock(body),
             (Method::Put, _, None) => method_to_error(Method::Put),
             (Method::Patch, "balloon", Some(body)) => parse_patch_balloon(body, path_tokens.get(1)),
             (Method::Patch, "drives", Some(body)) => parse_patch_drive(body, path_tokens.get(1)),
             (Method::Patch, "machine-config", Some(body)) => parse_patch_machine_config(body),
-            (Method::Patch, "mmds", Some(body)) => parse_patch_mmds(body),
+            (Method::Patch, "mmds", Some(body)) => parse_patch_mmds(
+                body,
+                request.headers.content_type(),
+                path_tokens.get(1),
+                path_tokens.get(2),
+            ),
             (Method::Patch, "network-interfaces", Some(body)) => {
                 parse_patch_net(body, path_tokens.get(1))
             }
@@ -117,6 +137,24 @@ impl ParsedRequest {
                     response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
                     response
                 }
+                VmmData::BootMeasurements(boot_measurements) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(boot_measurements).unwrap()));
+                    response
+                }
+                VmmData::SnapshotCreateStatus(status) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(status).unwrap()));
+                    response
+                }
+                VmmData::VcpuStats(stats) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
+                    response
+                }
             },
             Err(vmm_action_error) => {
                 error!(
@@ -265,6 +303,8 @@ pub(crate) mod tests {
     use vmm::rpc_interface::VmmActionError;
     use vmm::vmm_config::balloon::BalloonStats;
     use vmm::vmm_config::machine_config::VmConfig;
+    use vmm::vmm_config::snapshot::{SnapshotCreateState, SnapshotCreateStatus};
+    use vmm::vmm_config::vcpu_stats::VcpuStats;
 
     impl PartialEq for ParsedRequest {
         fn eq(&self, other: &ParsedRequest) -> bool {
@@ -273,13 +313,21 @@ pub(crate) mod tests {
                     sync_req == other_sync_req
                 }
                 (&ParsedRequest::GetInstanceInfo, &ParsedRequest::GetInstanceInfo) => true,
-                (&ParsedRequest::GetMMDS, &ParsedRequest::GetMMDS) => true,
-                (&ParsedRequest::PutMMDS(ref val), &ParsedRequest::PutMMDS(ref other_val)) => {
-                    val == other_val
-                }
-                (&ParsedRequest::PatchMMDS(ref val), &ParsedRequest::PatchMMDS(ref other_val)) => {
-                    val == other_val
+                (&ParsedRequest::GetMMDS(ref id), &ParsedRequest::GetMMDS(ref other_id)) => {
+                    id == other_id
                 }
+                (
+                    &ParsedRequest::GetGuestMMDS(ref id),
+                    &ParsedRequest::GetGuestMMDS(ref other_id),
+                ) => id == other_id,
+                (
+                    &ParsedRequest::PutMMDS(ref id, ref val),
+                    &ParsedRequest::PutMMDS(ref other_id, ref other_val),
+                ) => id == other_id && val == other_val,
+                (
+                    &ParsedRequest::PatchMMDS(ref id, format, ref val),
+                    &ParsedRequest::PatchMMDS(ref other_id, other_format, ref other_val),
+                ) => id == other_id && format == other_format && val == other_val,
                 _ => false,
             }
         }
@@ -514,6 +562,26 @@ pub(crate) mod tests {
         let expected_response = http_response(&serde_json::to_string(&stats).unwrap(), 200);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
 
+        // With Snapshot Create Status Vmm data.
+        let status = SnapshotCreateStatus {
+            state: SnapshotCreateState::Failed,
+            error: Some("foo".to_string()),
+        };
+        let mut buf = Cursor::new(vec![0]);
+        let response =
+            ParsedRequest::convert_to_response(&Ok(VmmData::SnapshotCreateStatus(status.clone())));
+        assert!(response.write_all(&mut buf).is_ok());
+        let expected_response = http_response(&serde_json::to_string(&status).unwrap(), 200);
+        assert_eq!(buf.into_inner(), expected_response.as_bytes());
+
+        // With vCPU Stats Vmm data.
+        let stats = vec![VcpuStats::default()];
+        let mut buf = Cursor::new(vec![0]);
+        let response = ParsedRequest::convert_to_response(&Ok(VmmData::VcpuStats(stats.clone())));
+        assert!(response.write_all(&mut buf).is_ok());
+        let expected_response = http_response(&serde_json::to_string(&stats).unwrap(), 200);
+        assert_eq!(buf.into_inner(), expected_response.as_bytes());
+
         // Error.
         let error = VmmActionError::StartMicrovm(StartMicrovmError::MissingKernelConfig);
         let mut buf = Cursor::new(vec![0]);
@@ -583,6 +651,48 @@ pub(crate) mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
+        sender
+            .write_all(http_request("GET", "/mmds/instances/tenant", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(
+            ParsedRequest::try_from_request(&req).unwrap()
+                == ParsedRequest::GetMMDS(Some("tenant".to_string()))
+        );
+        sender
+            .write_all(http_request("GET", "/mmds/instances/tenant/guest", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(
+            ParsedRequest::try_from_request(&req).unwrap()
+                == ParsedRequest::GetGuestMMDS(Some("tenant".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_try_from_get_snapshot() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/snapshot/create", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_vcpu_stats() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vcpu-stats", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
     #[test]
@@ -633,6 +743,28 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_put_cpu_config() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \
+            \"cpuid_modifiers\": [{ \
+                \"leaf\": 1, \
+                \"subleaf\": 0, \
+                \"register\": \"ecx\", \
+                \"mask\": 0, \
+                \"value\": 0 \
+            }], \
+            \"msr_modifiers\": [] \
+        }";
+        sender
+            .write_all(http_request("PUT", "/cpu-config", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_put_drives() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -820,6 +952,32 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_put_dns() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"network_interfaces\": [\"eth0\"] }";
+        sender
+            .write_all(http_request("PUT", "/dns", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_tpm() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"socket\": \"string\" }";
+        sender
+            .write_all(http_request("PUT", "/tpm", Some(&body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_put_vsock() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -917,6 +1075,33 @@ pub(crate) mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
+        sender
+            .write_all(http_request("PATCH", "/mmds/instances/tenant", Some(&"{}")).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(
+            ParsedRequest::try_from_request(&req).unwrap()
+                == ParsedRequest::PatchMMDS(
+                    Some("tenant".to_string()),
+                    PatchFormat::MergePatch,
+                    serde_json::json!({})
+                )
+        );
+
+        sender
+            .write_all(
+                b"PATCH /mmds HTTP/1.1\r\n\
+                Content-Type: application/json-patch+json\r\n\
+                Content-Length: 2\r\n\r\n[]",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(
+            ParsedRequest::try_from_request(&req).unwrap()
+                == ParsedRequest::PatchMMDS(None, PatchFormat::JsonPatch, serde_json::json!([]))
+        );
     }
 
     #[test]
@@ -20,6 +20,7 @@ pub(crate) fn parse_put_boot_source(body: &Body) -> Result<ParsedRequest, Error>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use vmm::vmm_config::boot_source::BootArgsPolicy;
 
     #[test]
     fn test_parse_boot_request() {
@@ -34,11 +35,35 @@ mod tests {
             kernel_image_path: String::from("/foo/bar"),
             initrd_path: Some(String::from("/bar/foo")),
             boot_args: Some(String::from("foobar")),
+            boot_args_policy: None,
+            measured_boot: None,
         };
         let result = parse_put_boot_source(&Body::new(body));
         assert!(result.is_ok());
         let parsed_req = result.unwrap_or_else(|_e| panic!("Failed test."));
 
         assert!(parsed_req == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(same_body)));
+
+        let body = r#"{
+                "kernel_image_path": "/foo/bundle",
+                "boot_args": "foobar",
+                "boot_args_policy": "replace",
+                "measured_boot": true
+              }"#;
+        let same_body = BootSourceConfig {
+            kernel_image_path: String::from("/foo/bundle"),
+            initrd_path: None,
+            boot_args: Some(String::from("foobar")),
+            boot_args_policy: Some(BootArgsPolicy::Replace),
+            measured_boot: Some(true),
+        };
+        let parsed_req = parse_put_boot_source(&Body::new(body)).unwrap();
+        assert!(parsed_req == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(same_body)));
+
+        let body = r#"{
+                "kernel_image_path": "/foo/bundle",
+                "boot_args_policy": "merge"
+              }"#;
+        assert!(parse_put_boot_source(&Body::new(body)).is_err());
     }
 }
@@ -0,0 +1,83 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+use logger::{IncMetric, METRICS};
+use vmm::vmm_config::cpu_config::CustomCpuTemplate;
+
+pub(crate) fn parse_put_cpu_config(body: &Body) -> Result<ParsedRequest, Error> {
+    METRICS.put_api_requests.cpu_cfg_count.inc();
+    Ok(ParsedRequest::new_sync(VmmAction::SetCpuConfiguration(
+        serde_json::from_slice::<CustomCpuTemplate>(body.raw()).map_err(|e| {
+            METRICS.put_api_requests.cpu_cfg_fails.inc();
+            Error::SerdeJson(e)
+        })?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::cpu_config::{CpuidModifier, CpuidRegister, MsrModifier};
+
+    #[test]
+    fn test_parse_put_cpu_config_request() {
+        let body = r#"{
+                "cpuid_modifiers": [
+                    {
+                        "leaf": 7,
+                        "subleaf": 0,
+                        "register": "ebx",
+                        "mask": 32,
+                        "value": 0
+                    }
+                ],
+                "msr_modifiers": [
+                    {
+                        "addr": 416,
+                        "mask": 1,
+                        "value": 0
+                    }
+                ]
+              }"#;
+
+        let expected_cfg = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidModifier {
+                leaf: 7,
+                subleaf: 0,
+                register: CpuidRegister::Ebx,
+                mask: 32,
+                value: 0,
+            }],
+            msr_modifiers: vec![MsrModifier {
+                addr: 416,
+                mask: 1,
+                value: 0,
+            }],
+        };
+        match vmm_action_from_request(parse_put_cpu_config(&Body::new(body)).unwrap()) {
+            VmmAction::SetCpuConfiguration(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        let invalid_body = r#"{
+                "cpuid_modifiers": [
+                    {
+                        "leaf": 7,
+                        "register": "ebx",
+                        "mask": 4294967296,
+                        "value": 0
+                    }
+                ]
+              }"#;
+        assert!(parse_put_cpu_config(&Body::new(invalid_body)).is_err());
+
+        let invalid_body = r#"{
+                "invalid_field": []
+              }"#;
+        assert!(parse_put_cpu_config(&Body::new(invalid_body)).is_err());
+    }
+}
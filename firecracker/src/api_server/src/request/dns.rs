@@ -0,0 +1,85 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::dns::DnsConfig;
+
+pub(crate) fn parse_put_dns(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetDnsConfiguration(
+        serde_json::from_slice::<DnsConfig>(body.raw()).map_err(Error::SerdeJson)?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+    use std::net::Ipv4Addr;
+    use vmm::vmm_config::dns::{DnsRecordConfig, DnsRecordType};
+
+    #[test]
+    fn test_parse_put_dns_request() {
+        let body = r#"{
+                "ipv4_address": "169.254.169.253",
+                "network_interfaces": ["eth0"],
+                "ttl": 30,
+                "records": [
+                    { "name": "api.internal", "type": "A", "value": "10.0.0.1" },
+                    { "name": "api.internal", "type": "AAAA", "value": "fd00::1" },
+                    { "name": "1.0.0.10.in-addr.arpa", "type": "PTR", "value": "api.internal" }
+                ]
+              }"#;
+        let record = |record_type, value: &str| DnsRecordConfig {
+            name: "api.internal".to_string(),
+            record_type,
+            value: value.to_string(),
+        };
+        assert_eq!(
+            vmm_action_from_request(parse_put_dns(&Body::new(body)).unwrap()),
+            VmmAction::SetDnsConfiguration(DnsConfig {
+                ipv4_address: Some(Ipv4Addr::new(169, 254, 169, 253)),
+                network_interfaces: vec!["eth0".to_string()],
+                ttl: 30,
+                records: vec![
+                    record(DnsRecordType::A, "10.0.0.1"),
+                    record(DnsRecordType::Aaaa, "fd00::1"),
+                    DnsRecordConfig {
+                        name: "1.0.0.10.in-addr.arpa".to_string(),
+                        ..record(DnsRecordType::Ptr, "api.internal")
+                    },
+                ],
+            })
+        );
+
+        // The address, the TTL and the records are optional.
+        let body = r#"{
+                "network_interfaces": ["eth0"]
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_dns(&Body::new(body)).unwrap()),
+            VmmAction::SetDnsConfiguration(DnsConfig {
+                ipv4_address: None,
+                network_interfaces: vec!["eth0".to_string()],
+                ttl: 60,
+                records: vec![],
+            })
+        );
+
+        let body = r#"{
+                "network_interfaces": ["eth0"],
+                "records": [{ "name": "api.internal", "type": "MX", "value": "10.0.0.1" }]
+              }"#;
+        assert!(parse_put_dns(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "network_interfaces": ["eth0"],
+                "invalid_field": false
+              }"#;
+        assert!(parse_put_dns(&Body::new(body)).is_err());
+
+        let body = r#"{}"#;
+        assert!(parse_put_dns(&Body::new(body)).is_err());
+    }
+}
@@ -5,7 +5,7 @@ use super::super::VmmAction;
 use crate::parsed_request::{method_to_error, Error, ParsedRequest};
 use crate::request::{Body, Method, StatusCode};
 use logger::{IncMetric, METRICS};
-use vmm::vmm_config::machine_config::VmConfig;
+use vmm::vmm_config::machine_config::{OnlineVcpuCountUpdate, VmConfig, VmConfigError};
 
 pub(crate) fn parse_get_machine_config() -> Result<ParsedRequest, Error> {
     METRICS.get_api_requests.machine_cfg_count.inc();
@@ -38,6 +38,13 @@ pub(crate) fn parse_put_machine_config(body: &Body) -> Result<ParsedRequest, Err
 
 pub(crate) fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.patch_api_requests.machine_cfg_count.inc();
+    // A request only holding the vCPU number can also change the online vCPU count after boot.
+    if let Ok(update) = serde_json::from_slice::<OnlineVcpuCountUpdate>(body.raw()) {
+        return Ok(ParsedRequest::new_sync(VmmAction::UpdateOnlineVcpuCount(
+            update,
+        )));
+    }
+
     let vm_config = serde_json::from_slice::<VmConfig>(body.raw()).map_err(|e| {
         METRICS.patch_api_requests.machine_cfg_fails.inc();
         Error::SerdeJson(e)
@@ -57,14 +64,12 @@ pub(crate) fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, E
     )))
 }
 
-fn check_unsupported_fields(_vm_config: &VmConfig) -> Result<(), Error> {
-    #[cfg(target_arch = "aarch64")]
-    {
-        if _vm_config.cpu_template.is_some() {
-            // cpu_template is not supported on aarch64
+fn check_unsupported_fields(vm_config: &VmConfig) -> Result<(), Error> {
+    if let Some(template) = vm_config.cpu_template {
+        if !template.is_supported_by_arch() {
             return Err(Error::Generic(
                 StatusCode::BadRequest,
-                "CPU templates are not supported on aarch64".to_string(),
+                VmConfigError::CpuTemplateNotSupported(template).to_string(),
             ));
         }
     }
@@ -75,6 +80,7 @@ fn check_unsupported_fields(_vm_config: &VmConfig) -> Result<(), Error> {
 mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::machine_config::MemoryBackend;
 
     #[test]
     fn test_parse_get_machine_config_request() {
@@ -116,10 +122,18 @@ mod tests {
               }"#;
         let expected_config = VmConfig {
             vcpu_count: Some(8),
+            max_vcpu_count: None,
             mem_size_mib: Some(1024),
             ht_enabled: Some(true),
             cpu_template: None,
             track_dirty_pages: true,
+            mem_backend: MemoryBackend::Anonymous,
+            vcpu_affinity: None,
+            vmm_affinity: None,
+            api_affinity: None,
+            vcpu_sched_policy: None,
+            #[cfg(feature = "gdb")]
+            gdb_socket_path: None,
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -127,36 +141,63 @@ mod tests {
             _ => panic!("Test failed."),
         }
 
-        // 4. Test that applying a CPU template is successful on x86_64 while on aarch64, it is not.
-        let body = r#"{
+        // 4. Test that applying a CPU template for the host architecture is successful, while
+        // templates for other architectures are rejected.
+        #[cfg(target_arch = "x86_64")]
+        let (template, foreign_template) = ("T2A", "V1N1");
+        #[cfg(target_arch = "aarch64")]
+        let (template, foreign_template) = ("V1N1", "T2A");
+
+        let body = format!(
+            r#"{{
                 "vcpu_count": 8,
                 "mem_size_mib": 1024,
                 "ht_enabled": true,
-                "cpu_template": "T2",
+                "cpu_template": "{}",
                 "track_dirty_pages": true
-              }"#;
-
-        #[cfg(target_arch = "x86_64")]
-        {
-            use vmm::vmm_config::machine_config::CpuFeaturesTemplate;
-            let expected_config = VmConfig {
-                vcpu_count: Some(8),
-                mem_size_mib: Some(1024),
-                ht_enabled: Some(true),
-                cpu_template: Some(CpuFeaturesTemplate::T2),
-                track_dirty_pages: true,
-            };
-
-            match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
-                VmmAction::SetVmConfiguration(config) => assert_eq!(config, expected_config),
-                _ => panic!("Test failed."),
+              }}"#,
+            template
+        );
+        match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+            VmmAction::SetVmConfiguration(config) => {
+                assert_eq!(config.cpu_template.unwrap().to_string(), template)
             }
+            _ => panic!("Test failed."),
         }
 
-        #[cfg(target_arch = "aarch64")]
-        {
-            assert!(parse_put_machine_config(&Body::new(body)).is_err());
+        let body = format!(
+            r#"{{
+                "vcpu_count": 8,
+                "mem_size_mib": 1024,
+                "ht_enabled": true,
+                "cpu_template": "{}"
+              }}"#,
+            foreign_template
+        );
+        assert!(parse_put_machine_config(&Body::new(body.clone())).is_err());
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+
+        // 5. Test that the memory backend is parsed.
+        let body = r#"{
+                "vcpu_count": 8,
+                "mem_size_mib": 1024,
+                "ht_enabled": true,
+                "mem_backend": "Hugetlbfs2M"
+              }"#;
+        match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+            VmmAction::SetVmConfiguration(config) => {
+                assert_eq!(config.mem_backend, MemoryBackend::Hugetlbfs2M)
+            }
+            _ => panic!("Test failed."),
         }
+
+        let body = r#"{
+                "vcpu_count": 8,
+                "mem_size_mib": 1024,
+                "ht_enabled": true,
+                "mem_backend": "Hugetlbfs4M"
+              }"#;
+        assert!(parse_put_machine_config(&Body::new(body)).is_err());
     }
 
     #[test]
@@ -171,7 +212,7 @@ mod tests {
               }"#;
         assert!(parse_patch_machine_config(&Body::new(body)).is_err());
 
-        // On aarch64, CPU template is also not patch compatible.
+        // On aarch64, the T2 CPU template is rejected.
         let body = r#"{
                 "cpu_template": "T2"
               }"#;
@@ -191,5 +232,20 @@ mod tests {
                 "ht_enabled": false
               }"#;
         assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
+        // 3. Requests only holding the vCPU number update the online vCPU count after boot.
+        let body = r#"{
+                "vcpu_count": 4
+              }"#;
+        match vmm_action_from_request(parse_patch_machine_config(&Body::new(body)).unwrap()) {
+            VmmAction::UpdateOnlineVcpuCount(update) => {
+                assert_eq!(update, OnlineVcpuCountUpdate { vcpu_count: 4 })
+            }
+            _ => panic!("Test failed."),
+        }
+        let body = r#"{
+                "vcpu_count": 64
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
     }
 }
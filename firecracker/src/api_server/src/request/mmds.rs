@@ -3,36 +3,85 @@
 
 use crate::parsed_request::{Error, ParsedRequest};
 use crate::request::Body;
-use micro_http::StatusCode;
+use micro_http::{MediaType, StatusCode};
+use mmds::data_store::PatchFormat;
 use vmm::rpc_interface::VmmAction::SetMmdsConfiguration;
 use vmm::vmm_config::mmds::MmdsConfig;
 
-pub(crate) fn parse_get_mmds() -> Result<ParsedRequest, Error> {
-    Ok(ParsedRequest::GetMMDS)
+// Returns the name of the MMDS instance addressed by `/mmds/instances/{mmds_id}`, or None for
+// the default instance.
+fn parse_mmds_id(
+    path_second_token: Option<&&str>,
+    path_third_token: Option<&&str>,
+) -> Result<Option<String>, Error> {
+    match path_second_token {
+        Some(&"instances") => path_third_token
+            .map(|mmds_id| Some(mmds_id.to_string()))
+            .ok_or_else(|| {
+                Error::Generic(
+                    StatusCode::BadRequest,
+                    "Missing MMDS instance name.".to_string(),
+                )
+            }),
+        _ => Ok(None),
+    }
+}
+
+pub(crate) fn parse_get_mmds(
+    path_second_token: Option<&&str>,
+    path_third_token: Option<&&str>,
+    path_fourth_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let mmds_id = parse_mmds_id(path_second_token, path_third_token)?;
+    match (path_second_token, path_fourth_token) {
+        (Some(&"guest"), _) | (Some(&"instances"), Some(&"guest")) => {
+            Ok(ParsedRequest::GetGuestMMDS(mmds_id))
+        }
+        _ => Ok(ParsedRequest::GetMMDS(mmds_id)),
+    }
 }
 
 pub(crate) fn parse_put_mmds(
     body: &Body,
     path_second_token: Option<&&str>,
+    path_third_token: Option<&&str>,
 ) -> Result<ParsedRequest, Error> {
     match path_second_token {
         Some(config_path) => match *config_path {
             "config" => Ok(ParsedRequest::new_sync(SetMmdsConfiguration(
                 serde_json::from_slice::<MmdsConfig>(body.raw()).map_err(Error::SerdeJson)?,
             ))),
+            "instances" => Ok(ParsedRequest::PutMMDS(
+                parse_mmds_id(path_second_token, path_third_token)?,
+                serde_json::from_slice(body.raw()).map_err(Error::SerdeJson)?,
+            )),
             _ => Err(Error::Generic(
                 StatusCode::BadRequest,
                 format!("Unrecognized PUT request path `{}`.", *config_path),
             )),
         },
         None => Ok(ParsedRequest::PutMMDS(
+            None,
             serde_json::from_slice(body.raw()).map_err(Error::SerdeJson)?,
         )),
     }
 }
 
-pub(crate) fn parse_patch_mmds(body: &Body) -> Result<ParsedRequest, Error> {
+// The request body is a JSON Patch document only if announced as such; any other JSON body is
+// merged into the data store.
+pub(crate) fn parse_patch_mmds(
+    body: &Body,
+    content_type: MediaType,
+    path_second_token: Option<&&str>,
+    path_third_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let format = match content_type {
+        MediaType::ApplicationJsonPatch => PatchFormat::JsonPatch,
+        _ => PatchFormat::MergePatch,
+    };
     Ok(ParsedRequest::PatchMMDS(
+        parse_mmds_id(path_second_token, path_third_token)?,
+        format,
         serde_json::from_slice(body.raw()).map_err(Error::SerdeJson)?,
     ))
 }
@@ -40,10 +89,30 @@ pub(crate) fn parse_patch_mmds(body: &Body) -> Result<ParsedRequest, Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+    use mmds::data_store::MmdsVersion;
 
     #[test]
     fn test_parse_get_mmds_request() {
-        assert!(parse_get_mmds().is_ok());
+        assert!(parse_get_mmds(None, None, None).is_ok());
+
+        let path = "instances";
+        let mmds_id = "tenant";
+        assert!(matches!(
+            parse_get_mmds(Some(&path), Some(&mmds_id), None),
+            Ok(ParsedRequest::GetMMDS(Some(id))) if id == mmds_id
+        ));
+        assert!(parse_get_mmds(Some(&path), None, None).is_err());
+
+        let guest = "guest";
+        assert!(matches!(
+            parse_get_mmds(Some(&guest), None, None),
+            Ok(ParsedRequest::GetGuestMMDS(None))
+        ));
+        assert!(matches!(
+            parse_get_mmds(Some(&path), Some(&mmds_id), Some(&guest)),
+            Ok(ParsedRequest::GetGuestMMDS(Some(id))) if id == mmds_id
+        ));
     }
 
     #[test]
@@ -51,31 +120,57 @@ mod tests {
         let body = r#"{
                 "foo": "bar"
               }"#;
-        assert!(parse_put_mmds(&Body::new(body), None).is_ok());
+        assert!(parse_put_mmds(&Body::new(body), None, None).is_ok());
         let invalid_body = "invalid_body";
-        assert!(parse_put_mmds(&Body::new(invalid_body), None).is_err());
+        assert!(parse_put_mmds(&Body::new(invalid_body), None, None).is_err());
 
         let body = r#"{
                 "ipv4_address": "169.254.170.2"
               }"#;
         let path = "config";
-        assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_ok());
+        assert!(parse_put_mmds(&Body::new(body), Some(&path), None).is_ok());
 
         let body = r#"{
                 "ipv4_address": ""
               }"#;
-        assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_err());
+        assert!(parse_put_mmds(&Body::new(body), Some(&path), None).is_err());
+
+        let body = r#"{
+                "ipv4_address": "169.254.170.2",
+                "version": "V2"
+              }"#;
+        match vmm_action_from_request(parse_put_mmds(&Body::new(body), Some(&path), None).unwrap())
+        {
+            SetMmdsConfiguration(config) => assert_eq!(config.version, MmdsVersion::V2),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "version": "V3"
+              }"#;
+        assert!(parse_put_mmds(&Body::new(body), Some(&path), None).is_err());
 
         // Equivalent to reset the mmds configuration.
         let empty_body = r#"{}"#;
-        assert!(parse_put_mmds(&Body::new(empty_body), Some(&path)).is_ok());
+        assert!(parse_put_mmds(&Body::new(empty_body), Some(&path), None).is_ok());
 
         let invalid_config_body = r#"{
                 "invalid_config": "invalid_value"
               }"#;
-        assert!(parse_put_mmds(&Body::new(invalid_config_body), Some(&path)).is_err());
-        assert!(parse_put_mmds(&Body::new(body), Some(&"invalid_path")).is_err());
-        assert!(parse_put_mmds(&Body::new(invalid_body), Some(&path)).is_err());
+        assert!(parse_put_mmds(&Body::new(invalid_config_body), Some(&path), None).is_err());
+        assert!(parse_put_mmds(&Body::new(body), Some(&"invalid_path"), None).is_err());
+        assert!(parse_put_mmds(&Body::new(invalid_body), Some(&path), None).is_err());
+
+        let body = r#"{
+                "foo": "bar"
+              }"#;
+        let path = "instances";
+        let mmds_id = "tenant";
+        assert!(matches!(
+            parse_put_mmds(&Body::new(body), Some(&path), Some(&mmds_id)),
+            Ok(ParsedRequest::PutMMDS(Some(id), _)) if id == mmds_id
+        ));
+        assert!(parse_put_mmds(&Body::new(body), Some(&path), None).is_err());
     }
 
     #[test]
@@ -83,7 +178,41 @@ mod tests {
         let body = r#"{
                 "foo": "bar"
               }"#;
-        assert!(parse_patch_mmds(&Body::new(body)).is_ok());
-        assert!(parse_patch_mmds(&Body::new("invalid_body")).is_err());
+        let json = MediaType::ApplicationJson;
+        assert!(matches!(
+            parse_patch_mmds(&Body::new(body), json, None, None),
+            Ok(ParsedRequest::PatchMMDS(None, PatchFormat::MergePatch, _))
+        ));
+        assert!(parse_patch_mmds(&Body::new("invalid_body"), json, None, None).is_err());
+
+        let path = "instances";
+        let mmds_id = "tenant";
+        assert!(matches!(
+            parse_patch_mmds(&Body::new(body), json, Some(&path), Some(&mmds_id)),
+            Ok(ParsedRequest::PatchMMDS(Some(id), _, _)) if id == mmds_id
+        ));
+        assert!(parse_patch_mmds(&Body::new(body), json, Some(&path), None).is_err());
+
+        let body = r#"[
+                { "op": "add", "path": "/foo", "value": "bar" }
+              ]"#;
+        assert!(matches!(
+            parse_patch_mmds(
+                &Body::new(body),
+                MediaType::ApplicationJsonPatch,
+                None,
+                None
+            ),
+            Ok(ParsedRequest::PatchMMDS(None, PatchFormat::JsonPatch, _))
+        ));
+        assert!(matches!(
+            parse_patch_mmds(
+                &Body::new(body),
+                MediaType::ApplicationMergePatch,
+                None,
+                None
+            ),
+            Ok(ParsedRequest::PatchMMDS(None, PatchFormat::MergePatch, _))
+        ));
     }
 }
@@ -4,6 +4,8 @@
 pub mod actions;
 pub mod balloon;
 pub mod boot_source;
+pub mod cpu_configuration;
+pub mod dns;
 pub mod drive;
 pub mod instance_info;
 pub mod logger;
@@ -12,6 +14,8 @@ pub mod metrics;
 pub mod mmds;
 pub mod net;
 pub mod snapshot;
+pub mod tpm;
+pub mod vcpu_stats;
 pub mod vsock;
 pub use micro_http::{
     Body, HttpServer, Method, Request, RequestError, Response, StatusCode, Version,
@@ -8,6 +8,22 @@ use crate::request::{Method, StatusCode};
 use vmm::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams};
 use vmm::vmm_config::snapshot::{Vm, VmState};
 
+pub(crate) fn parse_get_snapshot(
+    request_type_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    match request_type_from_path {
+        Some(&"create") => Ok(ParsedRequest::new_sync(VmmAction::GetSnapshotCreateStatus)),
+        Some(&request_type) => Err(Error::InvalidPathMethod(
+            format!("/snapshot/{}", request_type),
+            Method::Get,
+        )),
+        None => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Missing snapshot operation type.".to_string(),
+        )),
+    }
+}
+
 pub(crate) fn parse_put_snapshot(
     body: &Body,
     request_type_from_path: Option<&&str>,
@@ -48,6 +64,17 @@ mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_snapshot() {
+        match vmm_action_from_request(parse_get_snapshot(Some(&"create")).unwrap()) {
+            VmmAction::GetSnapshotCreateStatus => {}
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_get_snapshot(Some(&"load")).is_err());
+        assert!(parse_get_snapshot(None).is_err());
+    }
+
     #[test]
     fn test_parse_put_snapshot() {
         use std::path::PathBuf;
@@ -65,6 +92,8 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             version: Some(String::from("0.23.0")),
+            integrity_key_path: None,
+            background: false,
         };
 
         match vmm_action_from_request(
@@ -84,6 +113,30 @@ mod tests {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
             version: None,
+            integrity_key_path: None,
+            background: false,
+        };
+
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create")).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "background": true
+              }"#;
+
+        expected_cfg = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            version: None,
+            integrity_key_path: None,
+            background: true,
         };
 
         match vmm_action_from_request(
@@ -108,8 +161,10 @@ mod tests {
         let mut expected_cfg = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            mem_base_path: None,
             enable_diff_snapshots: false,
             resume_vm: false,
+            integrity_key_path: None,
         };
         match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
         {
@@ -126,8 +181,10 @@ mod tests {
         expected_cfg = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            mem_base_path: None,
             enable_diff_snapshots: true,
             resume_vm: false,
+            integrity_key_path: None,
         };
 
         match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
@@ -145,8 +202,52 @@ mod tests {
         expected_cfg = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            mem_base_path: None,
             enable_diff_snapshots: false,
             resume_vm: true,
+            integrity_key_path: None,
+        };
+
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
+        {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "integrity_key_path": "baz"
+              }"#;
+
+        expected_cfg = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            mem_base_path: None,
+            enable_diff_snapshots: false,
+            resume_vm: false,
+            integrity_key_path: Some(PathBuf::from("baz")),
+        };
+
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
+        {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mem_base_path": "baz"
+              }"#;
+
+        expected_cfg = LoadSnapshotParams {
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+            mem_base_path: Some(PathBuf::from("baz")),
+            enable_diff_snapshots: false,
+            resume_vm: false,
+            integrity_key_path: None,
         };
 
         match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
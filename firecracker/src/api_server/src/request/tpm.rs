@@ -0,0 +1,41 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::tpm::TpmConfig;
+
+pub(crate) fn parse_put_tpm(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetTpmDevice(
+        serde_json::from_slice::<TpmConfig>(body.raw()).map_err(Error::SerdeJson)?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_tpm_request() {
+        let body = r#"{
+                "socket": "/tmp/swtpm.sock"
+              }"#;
+        assert_eq!(
+            vmm_action_from_request(parse_put_tpm(&Body::new(body)).unwrap()),
+            VmmAction::SetTpmDevice(TpmConfig {
+                socket: "/tmp/swtpm.sock".to_string()
+            })
+        );
+
+        let body = r#"{
+                "socket": "/tmp/swtpm.sock",
+                "invalid_field": false
+              }"#;
+        assert!(parse_put_tpm(&Body::new(body)).is_err());
+
+        let body = r#"{}"#;
+        assert!(parse_put_tpm(&Body::new(body)).is_err());
+    }
+}
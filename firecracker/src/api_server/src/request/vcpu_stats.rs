@@ -0,0 +1,23 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::parsed_request::{Error, ParsedRequest};
+use vmm::rpc_interface::VmmAction;
+
+pub(crate) fn parse_get_vcpu_stats() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetVcpuStats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_vcpu_stats() {
+        match vmm_action_from_request(parse_get_vcpu_stats().unwrap()) {
+            VmmAction::GetVcpuStats => {}
+            _ => panic!("Test failed."),
+        }
+    }
+}
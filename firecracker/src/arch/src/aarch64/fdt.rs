@@ -13,7 +13,7 @@ use std::ptr::null;
 use std::{io, result};
 
 use super::super::DeviceType;
-use super::super::InitrdConfig;
+use super::super::{EventLogConfig, InitrdConfig};
 use super::cache_info::{sysfs_read_caches, CacheInfo, Error as CacheError};
 use super::get_fdt_addr;
 use super::gic::GICDevice;
@@ -98,6 +98,7 @@ pub fn create_fdt<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher
     device_info: &HashMap<(DeviceType, String), T, S>,
     gic_device: &dyn GICDevice,
     initrd: &Option<InitrdConfig>,
+    event_log: &Option<EventLogConfig>,
 ) -> Result<Vec<u8>> {
     // Allocate stuff necessary for storing the blob.
     let mut fdt = vec![0; FDT_MAX_SIZE];
@@ -120,6 +121,7 @@ pub fn create_fdt<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher
     append_property_u32(&mut fdt, "interrupt-parent", GIC_PHANDLE)?;
     create_cpu_nodes(&mut fdt, &vcpu_mpidr)?;
     create_memory_node(&mut fdt, guest_mem)?;
+    create_reserved_memory_node(&mut fdt, event_log)?;
     create_chosen_node(&mut fdt, cmdline, initrd)?;
     create_gic_node(&mut fdt, gic_device)?;
     create_timer_node(&mut fdt)?;
@@ -447,6 +449,30 @@ fn create_memory_node(fdt: &mut Vec<u8>, guest_mem: &GuestMemoryMmap) -> Result<
     Ok(())
 }
 
+fn create_reserved_memory_node(
+    fdt: &mut Vec<u8>,
+    event_log: &Option<EventLogConfig>,
+) -> Result<()> {
+    // See https://www.kernel.org/doc/Documentation/devicetree/bindings/reserved-memory/reserved-memory.txt
+    // for the description of reserved memory regions.
+    if let Some(event_log_config) = event_log {
+        let addr = event_log_config.address.raw_value();
+        let reg_prop = generate_prop64(&[addr, event_log_config.size as u64]);
+
+        append_begin_node(fdt, "reserved-memory")?;
+        append_property_u32(fdt, "#address-cells", ADDRESS_CELLS)?;
+        append_property_u32(fdt, "#size-cells", SIZE_CELLS)?;
+        append_property_null(fdt, "ranges")?;
+        append_begin_node(fdt, &format!("event-log@{:x}", addr))?;
+        append_property_string(fdt, "compatible", "firecracker,event-log")?;
+        append_property(fdt, "reg", &reg_prop)?;
+        append_property_null(fdt, "no-map")?;
+        append_end_node(fdt)?;
+        append_end_node(fdt)?;
+    }
+    Ok(())
+}
+
 fn create_chosen_node(
     fdt: &mut Vec<u8>,
     cmdline: &CStr,
@@ -607,6 +633,21 @@ fn create_rtc_node<T: DeviceInfoForFDT + Clone + Debug>(
     Ok(())
 }
 
+fn create_tpm_node<T: DeviceInfoForFDT + Clone + Debug>(
+    fdt: &mut Vec<u8>,
+    dev_info: &T,
+) -> Result<()> {
+    // The TPM has no interrupt, its driver polls the status register.
+    let tpm_reg_prop = generate_prop64(&[dev_info.addr(), dev_info.length()]);
+
+    append_begin_node(fdt, &format!("tpm@{:x}", dev_info.addr()))?;
+    append_property_string(fdt, "compatible", "tcg,tpm-tis-mmio")?;
+    append_property(fdt, "reg", &tpm_reg_prop)?;
+    append_end_node(fdt)?;
+
+    Ok(())
+}
+
 fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher>(
     fdt: &mut Vec<u8>,
     dev_info: &HashMap<(DeviceType, String), T, S>,
@@ -619,6 +660,7 @@ fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildH
             DeviceType::BootTimer => (), // since it's not a real device
             DeviceType::RTC => create_rtc_node(fdt, info)?,
             DeviceType::Serial => create_serial_node(fdt, info)?,
+            DeviceType::Tpm => create_tpm_node(fdt, info)?,
             DeviceType::Virtio(_) => {
                 ordered_virtio_device.push(info);
             }
@@ -690,6 +732,13 @@ mod tests {
                     irq: 3,
                 },
             ),
+            (
+                (DeviceType::Tpm, DeviceType::Tpm.to_string()),
+                MMIODeviceInfo {
+                    addr: 3 * LEN,
+                    irq: 0,
+                },
+            ),
         ]
         .iter()
         .cloned()
@@ -704,6 +753,7 @@ mod tests {
             &dev_info,
             gic.as_ref(),
             &None,
+            &None,
         )
         .is_ok())
     }
@@ -722,6 +772,7 @@ mod tests {
             &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
             gic.as_ref(),
             &None,
+            &None,
         )
         .unwrap();
 
@@ -775,6 +826,7 @@ mod tests {
             &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
             gic.as_ref(),
             &Some(initrd),
+            &None,
         )
         .unwrap();
 
@@ -808,4 +860,73 @@ mod tests {
             format!("{:?}", generated_fdt)
         );
     }
+
+    #[test]
+    fn test_create_fdt_with_event_log() {
+        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000);
+        let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let gic = create_gic(&vm, 1).unwrap();
+        let event_log = EventLogConfig {
+            address: GuestAddress(0x8000_0000),
+            size: 0x100,
+        };
+
+        let mut dtb = create_fdt(
+            &mem,
+            vec![0],
+            &CString::new("console=tty0").unwrap(),
+            &HashMap::<(DeviceType, std::string::String), MMIODeviceInfo>::new(),
+            gic.as_ref(),
+            &None,
+            &Some(event_log),
+        )
+        .unwrap();
+
+        set_size(&mut dtb, 4, layout::FDT_MAX_SIZE);
+        let generated_fdt = device_tree::DeviceTree::load(&dtb).unwrap();
+        let node = generated_fdt
+            .find("/reserved-memory/event-log@80000000")
+            .unwrap();
+        assert_eq!(
+            node.prop_str("compatible").unwrap(),
+            "firecracker,event-log"
+        );
+    }
+
+    #[test]
+    fn test_create_fdt_with_tpm() {
+        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000);
+        let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let gic = create_gic(&vm, 1).unwrap();
+        let dev_info: HashMap<(DeviceType, std::string::String), MMIODeviceInfo> = [(
+            (DeviceType::Tpm, DeviceType::Tpm.to_string()),
+            MMIODeviceInfo {
+                addr: 0x4000_1000,
+                irq: 0,
+            },
+        )]
+        .iter()
+        .cloned()
+        .collect();
+
+        let mut dtb = create_fdt(
+            &mem,
+            vec![0],
+            &CString::new("console=tty0").unwrap(),
+            &dev_info,
+            gic.as_ref(),
+            &None,
+            &None,
+        )
+        .unwrap();
+
+        set_size(&mut dtb, 4, layout::FDT_MAX_SIZE);
+        let generated_fdt = device_tree::DeviceTree::load(&dtb).unwrap();
+        let node = generated_fdt.find("/tpm@40001000").unwrap();
+        assert_eq!(node.prop_str("compatible").unwrap(), "tcg,tpm-tis-mmio");
+    }
 }
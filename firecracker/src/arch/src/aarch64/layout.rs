@@ -61,6 +61,9 @@ pub const CMDLINE_MAX_SIZE: usize = 2048;
 /// Maximum size of the device tree blob as specified in https://www.kernel.org/doc/Documentation/arm64/booting.txt.
 pub const FDT_MAX_SIZE: usize = 0x20_0000;
 
+/// Maximum size of the measured boot event log, which is placed right below the device tree blob.
+pub const EVENT_LOG_MAX_SIZE: usize = 0x1_0000;
+
 // As per virt/kvm/arm/vgic/vgic-kvm-device.c we need
 // the number of interrupts our GIC will support to be:
 // * bigger than 32
@@ -50,6 +50,7 @@ pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
 /// * `device_info` - A hashmap containing the attached devices for building FDT device nodes.
 /// * `gic_device` - The GIC device.
 /// * `initrd` - Information about an optional initrd.
+/// * `event_log` - Information about an optional measured boot event log.
 pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher>(
     guest_mem: &GuestMemoryMmap,
     cmdline_cstring: &CStr,
@@ -57,6 +58,7 @@ pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::Build
     device_info: &HashMap<(DeviceType, String), T, S>,
     gic_device: &dyn GICDevice,
     initrd: &Option<super::InitrdConfig>,
+    event_log: &Option<super::EventLogConfig>,
 ) -> super::Result<()> {
     fdt::create_fdt(
         guest_mem,
@@ -65,6 +67,7 @@ pub fn configure_system<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::Build
         device_info,
         gic_device,
         initrd,
+        event_log,
     )
     .map_err(Error::SetupFDT)?;
     Ok(())
@@ -75,10 +78,17 @@ pub fn get_kernel_start() -> u64 {
     layout::DRAM_MEM_START
 }
 
+/// Returns the memory address where the measured boot event log is written.
+pub fn event_log_addr(guest_mem: &GuestMemoryMmap) -> u64 {
+    get_fdt_addr(guest_mem).saturating_sub(layout::EVENT_LOG_MAX_SIZE as u64)
+}
+
 /// Returns the memory address where the initrd could be loaded.
 pub fn initrd_load_addr(guest_mem: &GuestMemoryMmap, initrd_size: usize) -> super::Result<u64> {
     let round_to_pagesize = |size| (size + (super::PAGE_SIZE - 1)) & !(super::PAGE_SIZE - 1);
-    match GuestAddress(get_fdt_addr(&guest_mem)).checked_sub(round_to_pagesize(initrd_size) as u64)
+    // The initrd is placed below the event log region.
+    match GuestAddress(event_log_addr(&guest_mem))
+        .checked_sub(round_to_pagesize(initrd_size) as u64)
     {
         Some(offset) => {
             if guest_mem.address_in_range(offset) {
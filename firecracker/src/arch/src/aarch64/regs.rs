@@ -5,12 +5,15 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+use std::os::raw::c_ulong;
 use std::{fmt, fs, mem, result, u32};
 
 use super::get_fdt_addr;
 use kvm_bindings::*;
-use kvm_ioctls::VcpuFd;
+use kvm_ioctls::{VcpuFd, VmFd};
 use std::path::PathBuf;
+use utils::ioctl::{ioctl_with_ref, ioctl_with_val};
+use utils::{ioctl_expr, ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr};
 use vm_memory::GuestMemoryMmap;
 
 /// Errors thrown while setting aarch64 registers.
@@ -24,6 +27,12 @@ pub enum Error {
     GetRegList(kvm_ioctls::Error),
     /// Failed to get a system register.
     GetSysRegister(kvm_ioctls::Error),
+    /// Failed to get the writable bits of the ID registers.
+    GetIdRegisterMasks(kvm_ioctls::Error),
+    /// The host does not allow an ID register field to be changed.
+    IdRegisterFieldNotWritable(u64, u32),
+    /// An ID register does not read back as written.
+    IdRegisterMismatch(u64, u64, u64),
     /// A FamStructWrapper operation has failed.
     FamError(utils::fam::Error),
     /// Failed to set core register (PC, PSTATE or general purpose ones).
@@ -46,6 +55,21 @@ impl fmt::Display for Error {
             GetMP(ref e) => write!(f, "Failed to get multiprocessor state: {}", e),
             GetRegList(ref e) => write!(f, "Failed to retrieve list of registers: {}", e),
             GetSysRegister(ref e) => write!(f, "Failed to get system register: {}", e),
+            GetIdRegisterMasks(ref e) => write!(
+                f,
+                "Failed to get the writable bits of the ID registers: {}",
+                e
+            ),
+            IdRegisterFieldNotWritable(reg, shift) => write!(
+                f,
+                "The host does not allow the field at bit {} of the ID register {:#x} to be changed",
+                shift, reg
+            ),
+            IdRegisterMismatch(reg, expected, actual) => write!(
+                f,
+                "The ID register {:#x} reads back as {:#x} instead of {:#x}",
+                reg, actual, expected
+            ),
             SetCoreRegister(ref e, ref desc) => write!(f, "Failed to set {} register: {}", desc, e),
             SetMP(ref e) => write!(f, "Failed to set multiprocessor state: {}", e),
             SetRegister(ref e) => write!(f, "Failed to set register: {}", e),
@@ -147,6 +171,227 @@ macro_rules! arm64_sys_reg {
 // https://elixir.bootlin.com/linux/v4.20.17/source/arch/arm64/include/asm/sysreg.h#L135
 arm64_sys_reg!(MPIDR_EL1, 3, 0, 0, 0, 5);
 arm64_sys_reg!(MIDR_EL1, 3, 0, 0, 0, 0);
+arm64_sys_reg!(ID_AA64PFR0_EL1, 3, 0, 0, 4, 0);
+arm64_sys_reg!(ID_AA64ISAR0_EL1, 3, 0, 0, 6, 0);
+arm64_sys_reg!(ID_AA64ISAR1_EL1, 3, 0, 0, 6, 1);
+
+// Constants imported from the Linux kernel, which lets the ID registers be changed since v6.7:
+// https://elixir.bootlin.com/linux/v6.7/source/arch/arm64/include/uapi/asm/kvm.h#L508
+const KVM_CAP_ARM_SUPPORTED_REG_MASK_RANGES: c_ulong = 230;
+const KVM_ARM_FEATURE_ID_RANGE: u32 = 0;
+/// The number of ID registers covered by the writable masks: op0 is 3, op1 is 0, 1 or 3,
+/// CRn is 0 and CRm and op2 are lower than 8.
+pub const ID_REGISTER_MASKS_LEN: usize = 3 * 8 * 8;
+
+// The argument of KVM_ARM_GET_REG_WRITABLE_MASKS, `struct reg_mask_range` in the kernel.
+#[repr(C)]
+#[derive(Default)]
+struct RegMaskRange {
+    addr: u64,
+    range: u32,
+    reserved: [u32; 13],
+}
+
+// Not wrapped by kvm-ioctls at the version we depend on.
+ioctl_io_nr!(KVM_CHECK_EXTENSION, KVMIO, 0x03);
+ioctl_ior_nr!(KVM_ARM_GET_REG_WRITABLE_MASKS, KVMIO, 0xb6, RegMaskRange);
+
+/// Access to the registers of a vCPU, implemented by `VcpuFd`.
+pub trait VcpuRegisters {
+    /// Returns the value of the register `reg_id`.
+    fn get_one_reg(&self, reg_id: u64) -> result::Result<u64, kvm_ioctls::Error>;
+    /// Sets the register `reg_id` to `data`.
+    fn set_one_reg(&self, reg_id: u64, data: u64) -> result::Result<(), kvm_ioctls::Error>;
+}
+
+impl VcpuRegisters for VcpuFd {
+    fn get_one_reg(&self, reg_id: u64) -> result::Result<u64, kvm_ioctls::Error> {
+        VcpuFd::get_one_reg(self, reg_id)
+    }
+
+    fn set_one_reg(&self, reg_id: u64, data: u64) -> result::Result<(), kvm_ioctls::Error> {
+        VcpuFd::set_one_reg(self, reg_id, data)
+    }
+}
+
+/// Returns the position of the ID register `reg` in the writable masks, or `None` if the
+/// register is outside of the ID register space.
+pub fn id_register_index(reg: u64) -> Option<usize> {
+    let op0 = (reg & KVM_REG_ARM64_SYSREG_OP0_MASK as u64) >> KVM_REG_ARM64_SYSREG_OP0_SHIFT;
+    let op1 = (reg & KVM_REG_ARM64_SYSREG_OP1_MASK as u64) >> KVM_REG_ARM64_SYSREG_OP1_SHIFT;
+    let crn = (reg & KVM_REG_ARM64_SYSREG_CRN_MASK as u64) >> KVM_REG_ARM64_SYSREG_CRN_SHIFT;
+    let crm = (reg & KVM_REG_ARM64_SYSREG_CRM_MASK as u64) >> KVM_REG_ARM64_SYSREG_CRM_SHIFT;
+    let op2 = (reg & KVM_REG_ARM64_SYSREG_OP2_MASK as u64) >> KVM_REG_ARM64_SYSREG_OP2_SHIFT;
+    // Same as the KVM_ARM_FEATURE_ID_RANGE_IDX kernel macro.
+    let op1 = match op1 {
+        0 | 1 => op1,
+        3 => 2,
+        _ => return None,
+    };
+    if op0 != 3 || crn != 0 || crm >= 8 {
+        return None;
+    }
+    Some((op1 << 6 | crm << 3 | op2) as usize)
+}
+
+/// Returns the bits of the ID registers which KVM lets userspace change, indexed as by
+/// `id_register_index`, or `None` if the host kernel does not allow ID registers to be
+/// changed.
+///
+/// # Arguments
+///
+/// * `vm` - The VM whose vCPUs will have their ID registers changed.
+pub fn get_id_register_writable_masks(vm: &VmFd) -> Result<Option<Vec<u64>>> {
+    // Safe because we know that our file is a VM fd and we verify the return result.
+    // The capability is a bitmap of the register ranges with writable masks.
+    let ranges = unsafe {
+        ioctl_with_val(
+            vm,
+            KVM_CHECK_EXTENSION(),
+            KVM_CAP_ARM_SUPPORTED_REG_MASK_RANGES,
+        )
+    };
+    if ranges <= 0 || ranges & (1 << KVM_ARM_FEATURE_ID_RANGE) == 0 {
+        return Ok(None);
+    }
+
+    let mut masks = vec![0u64; ID_REGISTER_MASKS_LEN];
+    let range = RegMaskRange {
+        addr: masks.as_mut_ptr() as u64,
+        range: KVM_ARM_FEATURE_ID_RANGE,
+        ..Default::default()
+    };
+    // Safe because we know that our file is a VM fd, `masks` is large enough for the
+    // requested range, and we verify the return result.
+    let ret = unsafe { ioctl_with_ref(vm, KVM_ARM_GET_REG_WRITABLE_MASKS(), &range) };
+    if ret < 0 {
+        return Err(Error::GetIdRegisterMasks(kvm_ioctls::Error::last()));
+    }
+    Ok(Some(masks))
+}
+
+/// A 4-bit unsigned feature field of an ID register, along with the highest
+/// value the guest is allowed to see.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IdRegisterField {
+    /// The ID of the system register holding the field.
+    pub reg: u64,
+    /// The position of the lowest bit of the field.
+    pub shift: u32,
+    /// The highest feature level exposed to the guest.
+    pub max: u64,
+}
+
+impl IdRegisterField {
+    /// Creates a field capped at `max`.
+    pub const fn new(reg: u64, shift: u32, max: u64) -> Self {
+        IdRegisterField { reg, shift, max }
+    }
+
+    /// Lowers the field in `value` to `max`, if the host reports a higher level.
+    pub fn cap(&self, value: u64) -> u64 {
+        let mask = 0xf << self.shift;
+        if (value & mask) >> self.shift > self.max {
+            (value & !mask) | (self.max << self.shift)
+        } else {
+            value
+        }
+    }
+}
+
+/// The ID register fields capped by the V1N1 template, so that the guest sees the
+/// features of a Neoverse N1 core.
+///
+/// SVE and pointer authentication are not listed, since KVM only exposes them to guests
+/// which request them when initializing their vCPUs.
+// Field positions are taken from the Linux kernel:
+// https://elixir.bootlin.com/linux/v5.10/source/arch/arm64/include/asm/sysreg.h#L660
+pub const V1N1_ID_REGISTER_FIELDS: [IdRegisterField; 16] = [
+    // SHA2 (no SHA512)
+    IdRegisterField::new(ID_AA64ISAR0_EL1, 12, 1),
+    // SHA3
+    IdRegisterField::new(ID_AA64ISAR0_EL1, 32, 0),
+    // SM3
+    IdRegisterField::new(ID_AA64ISAR0_EL1, 36, 0),
+    // SM4
+    IdRegisterField::new(ID_AA64ISAR0_EL1, 40, 0),
+    // FHM
+    IdRegisterField::new(ID_AA64ISAR0_EL1, 48, 0),
+    // TS
+    IdRegisterField::new(ID_AA64ISAR0_EL1, 52, 0),
+    // TLB
+    IdRegisterField::new(ID_AA64ISAR0_EL1, 56, 0),
+    // RNDR
+    IdRegisterField::new(ID_AA64ISAR0_EL1, 60, 0),
+    // DPB (no DC CVADP)
+    IdRegisterField::new(ID_AA64ISAR1_EL1, 0, 1),
+    // JSCVT
+    IdRegisterField::new(ID_AA64ISAR1_EL1, 12, 0),
+    // FCMA
+    IdRegisterField::new(ID_AA64ISAR1_EL1, 16, 0),
+    // LRCPC (no LDAPUR)
+    IdRegisterField::new(ID_AA64ISAR1_EL1, 20, 1),
+    // FRINTTS
+    IdRegisterField::new(ID_AA64ISAR1_EL1, 32, 0),
+    // SB
+    IdRegisterField::new(ID_AA64ISAR1_EL1, 36, 0),
+    // BF16
+    IdRegisterField::new(ID_AA64ISAR1_EL1, 44, 0),
+    // I8MM
+    IdRegisterField::new(ID_AA64ISAR1_EL1, 52, 0),
+];
+
+/// Caps the ID register fields of a vCPU, hiding the features above the given levels.
+///
+/// Registers are only written when one of their fields has to be lowered. Nothing is
+/// written unless `writable_masks` allows all the lowered fields to be changed, and the
+/// written registers are read back to check that KVM applied them.
+///
+/// # Arguments
+///
+/// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
+/// * `writable_masks` - The masks returned by `get_id_register_writable_masks`.
+/// * `fields` - The fields to cap.
+pub fn cap_id_register_fields<V: VcpuRegisters>(
+    vcpu: &V,
+    writable_masks: &[u64],
+    fields: &[IdRegisterField],
+) -> Result<()> {
+    let mut regs: Vec<u64> = fields.iter().map(|field| field.reg).collect();
+    regs.sort_unstable();
+    regs.dedup();
+
+    let mut writes = Vec::new();
+    for reg in regs {
+        let value = vcpu.get_one_reg(reg).map_err(Error::GetSysRegister)?;
+        let writable = id_register_index(reg)
+            .and_then(|index| writable_masks.get(index))
+            .copied()
+            .unwrap_or(0);
+        let mut capped = value;
+        for field in fields.iter().filter(|field| field.reg == reg) {
+            let lowered = field.cap(capped);
+            let mask = 0xf << field.shift;
+            if lowered != capped && writable & mask != mask {
+                return Err(Error::IdRegisterFieldNotWritable(reg, field.shift));
+            }
+            capped = lowered;
+        }
+        if capped != value {
+            writes.push((reg, capped));
+        }
+    }
+
+    for (reg, capped) in writes {
+        vcpu.set_one_reg(reg, capped).map_err(Error::SetRegister)?;
+        let value = vcpu.get_one_reg(reg).map_err(Error::GetSysRegister)?;
+        if value != capped {
+            return Err(Error::IdRegisterMismatch(reg, capped, value));
+        }
+    }
+
+    Ok(())
+}
 
 /// Extract the Manufacturer ID from a VCPU state's registers.
 /// The ID is found between bits 24-31 of MIDR_EL1 register.
@@ -454,6 +699,9 @@ mod tests {
     use super::*;
     use crate::aarch64::{arch_memory_regions, layout};
     use kvm_ioctls::Kvm;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::os::unix::io::AsRawFd;
 
     #[test]
     fn test_setup_regs() {
@@ -496,6 +744,205 @@ mod tests {
         assert_eq!(read_mpidr(&vcpu).unwrap(), 0x8000_0000);
     }
 
+    #[test]
+    fn test_id_register_field_cap() {
+        let field = IdRegisterField::new(ID_AA64ISAR0_EL1, 12, 1);
+        assert_eq!(field.cap(0x0000_2f00), 0x0000_1f00);
+        assert_eq!(field.cap(0xf000_1000), 0xf000_1000);
+        assert_eq!(field.cap(0), 0);
+
+        let value = 0x1111_2222_1111_2222;
+        let capped = V1N1_ID_REGISTER_FIELDS
+            .iter()
+            .filter(|field| field.reg == ID_AA64ISAR1_EL1)
+            .fold(value, |value, field| field.cap(value));
+        assert_eq!(capped, 0x1101_0200_1110_0001);
+    }
+
+    // Stands in for a vCPU of a host which allows the `writable` bits of the ID registers
+    // to be changed, like KVM does.
+    struct FakeVcpu {
+        regs: RefCell<HashMap<u64, u64>>,
+        writable: u64,
+        writes: RefCell<Vec<u64>>,
+        drop_writes: bool,
+    }
+
+    impl FakeVcpu {
+        fn new(isar0: u64, isar1: u64, writable: u64) -> Self {
+            let regs = [(ID_AA64ISAR0_EL1, isar0), (ID_AA64ISAR1_EL1, isar1)];
+            FakeVcpu {
+                regs: RefCell::new(regs.iter().cloned().collect()),
+                writable,
+                writes: RefCell::new(Vec::new()),
+                drop_writes: false,
+            }
+        }
+    }
+
+    impl VcpuRegisters for FakeVcpu {
+        fn get_one_reg(&self, reg_id: u64) -> result::Result<u64, kvm_ioctls::Error> {
+            self.regs
+                .borrow()
+                .get(&reg_id)
+                .copied()
+                .ok_or_else(|| kvm_ioctls::Error::new(libc::ENOENT))
+        }
+
+        fn set_one_reg(&self, reg_id: u64, data: u64) -> result::Result<(), kvm_ioctls::Error> {
+            let mut regs = self.regs.borrow_mut();
+            let value = regs
+                .get_mut(&reg_id)
+                .ok_or_else(|| kvm_ioctls::Error::new(libc::ENOENT))?;
+            if (*value ^ data) & !self.writable != 0 {
+                return Err(kvm_ioctls::Error::new(libc::EINVAL));
+            }
+            self.writes.borrow_mut().push(reg_id);
+            if !self.drop_writes {
+                *value = data;
+            }
+            Ok(())
+        }
+    }
+
+    fn writable_masks(writable: u64) -> Vec<u64> {
+        let mut masks = vec![0; ID_REGISTER_MASKS_LEN];
+        masks[id_register_index(ID_AA64ISAR0_EL1).unwrap()] = writable;
+        masks[id_register_index(ID_AA64ISAR1_EL1).unwrap()] = writable;
+        masks
+    }
+
+    #[test]
+    fn test_id_register_index() {
+        assert_eq!(id_register_index(ID_AA64PFR0_EL1), Some(32));
+        assert_eq!(id_register_index(ID_AA64ISAR0_EL1), Some(48));
+        assert_eq!(id_register_index(ID_AA64ISAR1_EL1), Some(49));
+        // MIDR_EL1 is the first register of the ID register space.
+        assert_eq!(id_register_index(MIDR_EL1), Some(0));
+        arm64_sys_reg!(CTR_EL0, 3, 3, 0, 0, 1);
+        assert_eq!(id_register_index(CTR_EL0), Some(129));
+        arm64_sys_reg!(SCTLR_EL1, 3, 0, 1, 0, 0);
+        assert_eq!(id_register_index(SCTLR_EL1), None);
+        arm64_sys_reg!(CCSIDR2_EL1, 3, 1, 0, 0, 2);
+        assert_eq!(id_register_index(CCSIDR2_EL1), Some(66));
+        arm64_sys_reg!(CSSELR_EL1, 3, 2, 0, 0, 0);
+        assert_eq!(id_register_index(CSSELR_EL1), None);
+    }
+
+    #[test]
+    fn test_get_id_register_writable_masks() {
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        // Whether the ID registers can be changed depends on the host kernel.
+        if let Some(masks) = get_id_register_writable_masks(&vm).unwrap() {
+            assert_eq!(masks.len(), ID_REGISTER_MASKS_LEN);
+            assert_ne!(masks[id_register_index(ID_AA64ISAR0_EL1).unwrap()], 0);
+        }
+
+        unsafe { libc::close(vm.as_raw_fd()) };
+        let res = get_id_register_writable_masks(&vm);
+        assert!(matches!(res, Ok(None) | Err(Error::GetIdRegisterMasks(_))));
+    }
+
+    #[test]
+    fn test_cap_id_register_fields() {
+        // The host implements every feature at level 2.
+        let (isar0, isar1) = (0x2222_2222_2222_2222, 0x2222_2222_2222_2222);
+
+        // The lowered fields are written and read back.
+        let vcpu = FakeVcpu::new(isar0, isar1, !0);
+        cap_id_register_fields(&vcpu, &writable_masks(!0), &V1N1_ID_REGISTER_FIELDS).unwrap();
+        let isar0_capped = vcpu.get_one_reg(ID_AA64ISAR0_EL1).unwrap();
+        let isar1_capped = vcpu.get_one_reg(ID_AA64ISAR1_EL1).unwrap();
+        assert_eq!(isar0_capped, 0x0000_2000_2222_1222);
+        assert_eq!(isar1_capped, 0x2202_0200_2210_0221);
+        for field in V1N1_ID_REGISTER_FIELDS.iter() {
+            let value = if field.reg == ID_AA64ISAR0_EL1 {
+                isar0_capped
+            } else {
+                isar1_capped
+            };
+            assert_eq!((value >> field.shift) & 0xf, field.max);
+        }
+        assert_eq!(
+            *vcpu.writes.borrow(),
+            vec![ID_AA64ISAR0_EL1, ID_AA64ISAR1_EL1]
+        );
+
+        // Fields already at or below the caps leave the registers untouched, even if the
+        // host does not allow them to be changed.
+        let vcpu = FakeVcpu::new(isar0_capped, isar1_capped, 0);
+        cap_id_register_fields(&vcpu, &writable_masks(0), &V1N1_ID_REGISTER_FIELDS).unwrap();
+        assert!(vcpu.writes.borrow().is_empty());
+
+        // Nothing is written if one of the lowered fields is not writable.
+        let vcpu = FakeVcpu::new(isar0, isar1, !0);
+        let res = cap_id_register_fields(
+            &vcpu,
+            &writable_masks(!(0xf << 52)),
+            &V1N1_ID_REGISTER_FIELDS,
+        );
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            format!(
+                "The host does not allow the field at bit 52 of the ID register {:#x} to be changed",
+                ID_AA64ISAR0_EL1
+            )
+        );
+        assert!(vcpu.writes.borrow().is_empty());
+        assert_eq!(vcpu.get_one_reg(ID_AA64ISAR0_EL1).unwrap(), isar0);
+
+        // A register which KVM does not apply is reported.
+        let mut vcpu = FakeVcpu::new(isar0, isar1, !0);
+        vcpu.drop_writes = true;
+        let res = cap_id_register_fields(&vcpu, &writable_masks(!0), &V1N1_ID_REGISTER_FIELDS);
+        assert!(matches!(
+            res,
+            Err(Error::IdRegisterMismatch(
+                ID_AA64ISAR0_EL1,
+                0x0000_2000_2222_1222,
+                0x2222_2222_2222_2222
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_cap_id_register_fields_vcpu() {
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let vcpu = vm.create_vcpu(0).unwrap();
+        let mut kvi: kvm_bindings::kvm_vcpu_init = kvm_bindings::kvm_vcpu_init::default();
+        vm.get_preferred_target(&mut kvi).unwrap();
+        let masks = writable_masks(0);
+
+        // Must fail when vcpu is not initialized yet.
+        let res = cap_id_register_fields(&vcpu, &masks, &V1N1_ID_REGISTER_FIELDS);
+        assert_eq!(
+            format!("{}", res.unwrap_err()),
+            "Failed to get system register: Exec format error (os error 8)"
+        );
+
+        vcpu.vcpu_init(&kvi).unwrap();
+        // SVE and pointer authentication are hidden, since their vCPU features aren't requested.
+        assert_eq!((vcpu.get_one_reg(ID_AA64PFR0_EL1).unwrap() >> 32) & 0xf, 0);
+        assert_eq!((vcpu.get_one_reg(ID_AA64ISAR1_EL1).unwrap() >> 4) & 0xff, 0);
+
+        // Fields already at or below the caps leave the registers untouched.
+        let isar0 = vcpu.get_one_reg(ID_AA64ISAR0_EL1).unwrap();
+        let fields = [IdRegisterField::new(ID_AA64ISAR0_EL1, 4, 0xf)];
+        assert!(cap_id_register_fields(&vcpu, &masks, &fields).is_ok());
+        assert_eq!(vcpu.get_one_reg(ID_AA64ISAR0_EL1).unwrap(), isar0);
+
+        // Fields which are not writable are not lowered. AES is implemented by all the
+        // supported hosts.
+        let fields = [IdRegisterField::new(ID_AA64ISAR0_EL1, 4, 0)];
+        assert!(matches!(
+            cap_id_register_fields(&vcpu, &masks, &fields),
+            Err(Error::IdRegisterFieldNotWritable(ID_AA64ISAR0_EL1, 4))
+        ));
+        assert_eq!(vcpu.get_one_reg(ID_AA64ISAR0_EL1).unwrap(), isar0);
+    }
+
     #[test]
     fn test_is_system_register() {
         let offset = offset__of!(user_pt_regs, pc);
@@ -552,8 +999,6 @@ mod tests {
 
     #[test]
     fn test_mpstate() {
-        use std::os::unix::io::AsRawFd;
-
         let kvm = Kvm::new().unwrap();
         let vm = kvm.create_vm().unwrap();
         let vcpu = vm.create_vcpu(0).unwrap();
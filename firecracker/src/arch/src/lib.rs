@@ -16,8 +16,9 @@ pub mod aarch64;
 
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::{
-    arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr,
-    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, regs, Error, MMIO_MEM_START,
+    arch_memory_regions, configure_system, event_log_addr, get_kernel_start, initrd_load_addr,
+    layout::CMDLINE_MAX_SIZE, layout::EVENT_LOG_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, regs,
+    Error, MMIO_MEM_START,
 };
 
 /// Module for x86_64 related functionality.
@@ -26,8 +27,9 @@ pub mod x86_64;
 
 #[cfg(target_arch = "x86_64")]
 pub use crate::x86_64::{
-    arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr,
-    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, Error, MMIO_MEM_START,
+    arch_memory_regions, configure_system, event_log_addr, get_kernel_start, initrd_load_addr,
+    layout::CMDLINE_MAX_SIZE, layout::EVENT_LOG_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, Error,
+    MMIO_MEM_START,
 };
 
 /// Type for returning public functions outcome.
@@ -46,6 +48,8 @@ pub enum DeviceType {
     RTC,
     /// Device Type: BootTimer.
     BootTimer,
+    /// Device Type: TPM.
+    Tpm,
 }
 
 /// Type for passing information about the initrd in the guest memory.
@@ -56,6 +60,14 @@ pub struct InitrdConfig {
     pub size: usize,
 }
 
+/// Type for passing information about the measured boot event log in the guest memory.
+pub struct EventLogConfig {
+    /// Address of the event log in guest memory
+    pub address: vm_memory::GuestAddress,
+    /// Size of the event log in guest memory
+    pub size: usize,
+}
+
 /// Default (smallest) memory page size for the supported architectures.
 pub const PAGE_SIZE: usize = 4096;
 
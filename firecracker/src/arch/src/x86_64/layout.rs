@@ -15,6 +15,14 @@ pub const CMDLINE_START: u64 = 0x20000;
 /// Kernel command line start address maximum size.
 pub const CMDLINE_MAX_SIZE: usize = 0x10000;
 
+/// Start of the memory region reserved for the measured boot event log.
+pub const EVENT_LOG_REGION_START: u64 = 0x30000;
+/// Size of the memory region reserved for the measured boot event log.
+pub const EVENT_LOG_REGION_SIZE: u64 = 0x10000;
+/// Event log maximum size, the region also holding the `setup_data` header describing the event
+/// log to kernels booted through the Linux boot protocol.
+pub const EVENT_LOG_MAX_SIZE: usize = 0xfff0;
+
 /// Start of the high memory.
 pub const HIMEM_START: u64 = 0x0010_0000; //1 MB.
 
@@ -24,8 +32,19 @@ pub const IRQ_BASE: u32 = 5;
 /// Last usable IRQ ID for virtio device interrupts on x86_64.
 pub const IRQ_MAX: u32 = 23;
 
+/// Address of the TPM TIS registers, where the guest kernel expects them when the TPM is not
+/// described by ACPI.
+pub const TPM_TIS_START: u64 = 0xfed4_0000;
+
 /// Address for the TSS setup.
 pub const KVM_TSS_ADDRESS: u64 = 0xfffb_d000;
 
 /// The 'zero page', a.k.a linux kernel bootparams.
 pub const ZERO_PAGE_START: u64 = 0x7000;
+
+/// Address of the `hvm_start_info` structure of kernels booted through PVH.
+pub const PVH_INFO_START: u64 = 0x6000;
+/// Address of the module list of kernels booted through PVH.
+pub const MODLIST_START: u64 = 0x6040;
+/// Address of the memory map table of kernels booted through PVH, which don't use the zero page.
+pub const MEMMAP_START: u64 = 0x7000;
@@ -16,8 +16,14 @@ pub mod msr;
 /// Logic for configuring x86_64 registers.
 pub mod regs;
 
-use crate::InitrdConfig;
-use arch_gen::x86::bootparam::{boot_params, E820_RAM};
+use std::mem;
+
+use crate::{EventLogConfig, InitrdConfig};
+use arch_gen::x86::bootparam::{boot_params, setup_data, setup_header, E820_RAM, E820_RESERVED};
+use arch_gen::x86::start_info::{
+    hvm_memmap_table_entry, hvm_modlist_entry, hvm_start_info, XEN_HVM_MEMMAP_TYPE_RAM,
+    XEN_HVM_MEMMAP_TYPE_RESERVED, XEN_HVM_START_MAGIC_VALUE,
+};
 use vm_memory::{
     Address, ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
 };
@@ -33,6 +39,29 @@ struct BootParamsWrapper(boot_params);
 // It is safe to initialize BootParamsWrap which is a wrapper over `boot_params` (a series of ints).
 unsafe impl ByteValued for BootParamsWrapper {}
 
+#[derive(Copy, Clone, Default)]
+struct StartInfoWrapper(hvm_start_info);
+#[derive(Copy, Clone, Default)]
+struct ModlistEntryWrapper(hvm_modlist_entry);
+#[derive(Copy, Clone, Default)]
+struct MemmapTableEntryWrapper(hvm_memmap_table_entry);
+
+// It is safe to initialize the PVH structures, which are series of ints.
+unsafe impl ByteValued for StartInfoWrapper {}
+unsafe impl ByteValued for ModlistEntryWrapper {}
+unsafe impl ByteValued for MemmapTableEntryWrapper {}
+
+/// Protocols through which the guest kernel is booted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BootProtocol {
+    /// The 64-bit Linux boot protocol: the kernel starts in long mode, with the boot parameters
+    /// in the zero page.
+    Linux,
+    /// The Xen PVH boot protocol: the kernel starts in 32-bit protected mode, with the
+    /// `hvm_start_info` structure describing the guest.
+    Pvh,
+}
+
 /// Errors thrown while configuring x86_64 system.
 #[derive(Debug, PartialEq)]
 pub enum Error {
@@ -42,10 +71,20 @@ pub enum Error {
     MpTableSetup(mptable::Error),
     /// Error writing the zero page of guest memory.
     ZeroPageSetup,
+    /// Error writing the PVH start info, module list or memory map to guest memory.
+    StartInfoSetup,
+    /// Error writing the `setup_data` header of the event log to guest memory.
+    EventLogSetup,
+    /// The PVH boot protocol has no way to describe the event log to the guest.
+    PvhEventLog,
     /// Failed to compute initrd address.
     InitrdAddress,
 }
 
+/// Type of the `setup_data` entry holding the measured boot event log ("FCLG"), which is not
+/// used by Linux.
+pub const SETUP_EVENT_LOG: u32 = 0x4643_4c47;
+
 // Where BIOS/VGA magic would live on a real PC.
 const EBDA_START: u64 = 0x9fc00;
 const FIRST_ADDR_PAST_32BITS: u64 = 1 << 32;
@@ -92,6 +131,11 @@ pub fn initrd_load_addr(guest_mem: &GuestMemoryMmap, initrd_size: usize) -> supe
     Ok(align_to_pagesize(lowmem_size - initrd_size) as u64)
 }
 
+/// Returns the memory address where the measured boot event log is written.
+pub fn event_log_addr(_guest_mem: &GuestMemoryMmap) -> u64 {
+    layout::EVENT_LOG_REGION_START + mem::size_of::<setup_data>() as u64
+}
+
 /// Configures the system and should be called once per vm before starting vcpu threads.
 ///
 /// # Arguments
@@ -100,79 +144,204 @@ pub fn initrd_load_addr(guest_mem: &GuestMemoryMmap, initrd_size: usize) -> supe
 /// * `cmdline_addr` - Address in `guest_mem` where the kernel command line was loaded.
 /// * `cmdline_size` - Size of the kernel command line in bytes including the null terminator.
 /// * `initrd` - Information about where the ramdisk image was loaded in the `guest_mem`.
+/// * `event_log` - Information about where the measured boot event log was written, which is
+///   only supported with the Linux boot protocol.
 /// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `boot_protocol` - Protocol through which the kernel is booted.
+/// * `setup_header` - Setup header of a bzImage kernel, which is passed in the boot parameters.
 pub fn configure_system(
     guest_mem: &GuestMemoryMmap,
     cmdline_addr: GuestAddress,
     cmdline_size: usize,
     initrd: &Option<InitrdConfig>,
+    event_log: &Option<EventLogConfig>,
     num_cpus: u8,
+    boot_protocol: BootProtocol,
+    setup_header: Option<setup_header>,
+) -> super::Result<()> {
+    // Note that this puts the mptable at the last 1k of Linux's 640k base RAM
+    mptable::setup_mptable(guest_mem, num_cpus).map_err(Error::MpTableSetup)?;
+
+    match boot_protocol {
+        BootProtocol::Linux => configure_64bit_boot(
+            guest_mem,
+            cmdline_addr,
+            cmdline_size,
+            initrd,
+            event_log,
+            setup_header,
+        ),
+        BootProtocol::Pvh if event_log.is_some() => Err(Error::PvhEventLog),
+        BootProtocol::Pvh => configure_pvh(guest_mem, cmdline_addr, initrd),
+    }
+}
+
+fn configure_64bit_boot(
+    guest_mem: &GuestMemoryMmap,
+    cmdline_addr: GuestAddress,
+    cmdline_size: usize,
+    initrd: &Option<InitrdConfig>,
+    event_log: &Option<EventLogConfig>,
+    setup_header: Option<setup_header>,
 ) -> super::Result<()> {
     const KERNEL_BOOT_FLAG_MAGIC: u16 = 0xaa55;
     const KERNEL_HDR_MAGIC: u32 = 0x5372_6448;
     const KERNEL_LOADER_OTHER: u8 = 0xff;
     const KERNEL_MIN_ALIGNMENT_BYTES: u32 = 0x0100_0000; // Must be non-zero.
-    let first_addr_past_32bits = GuestAddress(FIRST_ADDR_PAST_32BITS);
-    let end_32bit_gap_start = GuestAddress(MMIO_MEM_START);
-
-    let himem_start = GuestAddress(layout::HIMEM_START);
-
-    // Note that this puts the mptable at the last 1k of Linux's 640k base RAM
-    mptable::setup_mptable(guest_mem, num_cpus).map_err(Error::MpTableSetup)?;
 
     let mut params: BootParamsWrapper = BootParamsWrapper(boot_params::default());
 
+    // The setup header of a bzImage describes the kernel, and is completed by the loader.
+    match setup_header {
+        Some(hdr) => params.0.hdr = hdr,
+        None => params.0.hdr.kernel_alignment = KERNEL_MIN_ALIGNMENT_BYTES,
+    }
     params.0.hdr.type_of_loader = KERNEL_LOADER_OTHER;
     params.0.hdr.boot_flag = KERNEL_BOOT_FLAG_MAGIC;
     params.0.hdr.header = KERNEL_HDR_MAGIC;
     params.0.hdr.cmd_line_ptr = cmdline_addr.raw_value() as u32;
     params.0.hdr.cmdline_size = cmdline_size as u32;
-    params.0.hdr.kernel_alignment = KERNEL_MIN_ALIGNMENT_BYTES;
     if let Some(initrd_config) = initrd {
         params.0.hdr.ramdisk_image = initrd_config.address.raw_value() as u32;
         params.0.hdr.ramdisk_size = initrd_config.size as u32;
     }
 
-    add_e820_entry(&mut params.0, 0, EBDA_START, E820_RAM)?;
+    // The event log is described by a `setup_data` entry preceding it.
+    if let Some(event_log_config) = event_log {
+        let setup_data_addr = GuestAddress(layout::EVENT_LOG_REGION_START);
+        guest_mem
+            .write_obj(0u64, setup_data_addr)
+            .and_then(|_| guest_mem.write_obj(SETUP_EVENT_LOG, setup_data_addr.unchecked_add(8)))
+            .and_then(|_| {
+                guest_mem.write_obj(
+                    event_log_config.size as u32,
+                    setup_data_addr.unchecked_add(12),
+                )
+            })
+            .map_err(|_| Error::EventLogSetup)?;
+        params.0.hdr.setup_data = setup_data_addr.raw_value();
+    }
+
+    for (addr, size, mem_type) in memory_map(guest_mem, event_log.is_some()) {
+        add_e820_entry(&mut params.0, addr, size, mem_type)?;
+    }
+
+    let zero_page_addr = GuestAddress(layout::ZERO_PAGE_START);
+    guest_mem
+        .write_obj(params, zero_page_addr)
+        .map_err(|_| Error::ZeroPageSetup)?;
+
+    Ok(())
+}
+
+fn configure_pvh(
+    guest_mem: &GuestMemoryMmap,
+    cmdline_addr: GuestAddress,
+    initrd: &Option<InitrdConfig>,
+) -> super::Result<()> {
+    let mut start_info = StartInfoWrapper(hvm_start_info {
+        magic: XEN_HVM_START_MAGIC_VALUE,
+        version: 1,
+        cmdline_paddr: cmdline_addr.raw_value(),
+        memmap_paddr: layout::MEMMAP_START,
+        ..Default::default()
+    });
+
+    // The initrd is the first and only module.
+    if let Some(initrd_config) = initrd {
+        let modlist_entry = ModlistEntryWrapper(hvm_modlist_entry {
+            paddr: initrd_config.address.raw_value(),
+            size: initrd_config.size as u64,
+            ..Default::default()
+        });
+        guest_mem
+            .write_obj(modlist_entry, GuestAddress(layout::MODLIST_START))
+            .map_err(|_| Error::StartInfoSetup)?;
+        start_info.0.nr_modules = 1;
+        start_info.0.modlist_paddr = layout::MODLIST_START;
+    }
+
+    let mut memmap_addr = GuestAddress(layout::MEMMAP_START);
+    for (addr, size, mem_type) in memory_map(guest_mem, false) {
+        let memmap_entry = MemmapTableEntryWrapper(hvm_memmap_table_entry {
+            addr,
+            size,
+            type_: match mem_type {
+                E820_RAM => XEN_HVM_MEMMAP_TYPE_RAM,
+                _ => XEN_HVM_MEMMAP_TYPE_RESERVED,
+            },
+            reserved: 0,
+        });
+        guest_mem
+            .write_obj(memmap_entry, memmap_addr)
+            .map_err(|_| Error::StartInfoSetup)?;
+        memmap_addr = memmap_addr.unchecked_add(mem::size_of::<hvm_memmap_table_entry>() as u64);
+        start_info.0.memmap_entries += 1;
+    }
+
+    guest_mem
+        .write_obj(start_info, GuestAddress(layout::PVH_INFO_START))
+        .map_err(|_| Error::StartInfoSetup)
+}
+
+/// Returns the start address and size of the guest RAM regions, that is the guest memory except
+/// the legacy BIOS area below 1 MiB.
+fn ram_regions(guest_mem: &GuestMemoryMmap) -> Vec<(u64, u64)> {
+    let first_addr_past_32bits = GuestAddress(FIRST_ADDR_PAST_32BITS);
+    let end_32bit_gap_start = GuestAddress(MMIO_MEM_START);
+    let himem_start = GuestAddress(layout::HIMEM_START);
 
+    let mut regions = vec![(0, EBDA_START)];
     let last_addr = guest_mem.last_addr();
     if last_addr < end_32bit_gap_start {
-        add_e820_entry(
-            &mut params.0,
+        regions.push((
             himem_start.raw_value() as u64,
             // it's safe to use unchecked_offset_from because
             // mem_end > himem_start
             last_addr.unchecked_offset_from(himem_start) as u64 + 1,
-            E820_RAM,
-        )?;
+        ));
     } else {
-        add_e820_entry(
-            &mut params.0,
+        regions.push((
             himem_start.raw_value(),
             // it's safe to use unchecked_offset_from because
             // end_32bit_gap_start > himem_start
             end_32bit_gap_start.unchecked_offset_from(himem_start),
-            E820_RAM,
-        )?;
+        ));
 
         if last_addr > first_addr_past_32bits {
-            add_e820_entry(
-                &mut params.0,
+            regions.push((
                 first_addr_past_32bits.raw_value(),
                 // it's safe to use unchecked_offset_from because
                 // mem_end > first_addr_past_32bits
                 last_addr.unchecked_offset_from(first_addr_past_32bits) + 1,
-                E820_RAM,
-            )?;
+            ));
         }
     }
+    regions
+}
 
-    let zero_page_addr = GuestAddress(layout::ZERO_PAGE_START);
-    guest_mem
-        .write_obj(params, zero_page_addr)
-        .map_err(|_| Error::ZeroPageSetup)?;
-
-    Ok(())
+/// Returns the start address, size and e820 type of the guest memory map entries, that is the RAM
+/// regions, out of which the event log region is carved and reserved if `reserve_event_log`.
+fn memory_map(guest_mem: &GuestMemoryMmap, reserve_event_log: bool) -> Vec<(u64, u64, u32)> {
+    let log_start = layout::EVENT_LOG_REGION_START;
+    let log_end = log_start + layout::EVENT_LOG_REGION_SIZE;
+
+    let mut entries = Vec::new();
+    for (addr, size) in ram_regions(guest_mem) {
+        let end = addr + size;
+        if !reserve_event_log || log_start < addr || end < log_end {
+            entries.push((addr, size, E820_RAM));
+            continue;
+        }
+        if addr < log_start {
+            entries.push((addr, log_start - addr, E820_RAM));
+        }
+        entries.push((log_start, layout::EVENT_LOG_REGION_SIZE, E820_RESERVED));
+        if log_end < end {
+            entries.push((log_end, end - log_end, E820_RAM));
+        }
+    }
+    entries
 }
 
 /// Add an e820 region to the e820 map.
@@ -220,7 +389,16 @@ mod tests {
     fn test_system_configuration() {
         let no_vcpus = 4;
         let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
-        let config_err = configure_system(&gm, GuestAddress(0), 0, &None, 1);
+        let config_err = configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            &None,
+            1,
+            BootProtocol::Linux,
+            None,
+        );
         assert!(config_err.is_err());
         assert_eq!(
             config_err.unwrap_err(),
@@ -231,19 +409,208 @@ mod tests {
         let mem_size = 128 << 20;
         let arch_mem_regions = arch_memory_regions(mem_size);
         let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            &None,
+            no_vcpus,
+            BootProtocol::Linux,
+            None,
+        )
+        .unwrap();
 
         // Now assigning some memory that is equal to the start of the 32bit memory hole.
         let mem_size = 3328 << 20;
         let arch_mem_regions = arch_memory_regions(mem_size);
         let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            &None,
+            no_vcpus,
+            BootProtocol::Linux,
+            None,
+        )
+        .unwrap();
 
         // Now assigning some memory that falls after the 32bit memory hole.
         let mem_size = 3330 << 20;
         let arch_mem_regions = arch_memory_regions(mem_size);
         let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            &None,
+            no_vcpus,
+            BootProtocol::Linux,
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_configure_64bit_boot_setup_header() {
+        let gm = GuestMemoryMmap::from_ranges(&arch_memory_regions(128 << 20)).unwrap();
+        let hdr = setup_header {
+            setup_sects: 30,
+            kernel_alignment: 0x20_0000,
+            ..Default::default()
+        };
+        configure_system(
+            &gm,
+            GuestAddress(layout::CMDLINE_START),
+            10,
+            &None,
+            &None,
+            1,
+            BootProtocol::Linux,
+            Some(hdr),
+        )
+        .unwrap();
+
+        // The fields of the setup header are kept, except the ones filled in by the loader.
+        let params: BootParamsWrapper = gm.read_obj(GuestAddress(layout::ZERO_PAGE_START)).unwrap();
+        let hdr = params.0.hdr;
+        assert_eq!({ hdr.setup_sects }, 30);
+        assert_eq!({ hdr.kernel_alignment }, 0x20_0000);
+        assert_eq!({ hdr.cmd_line_ptr }, layout::CMDLINE_START as u32);
+        assert_eq!({ hdr.cmdline_size }, 10);
+        assert_eq!({ hdr.type_of_loader }, 0xff);
+        assert_eq!({ params.0.e820_entries }, 2);
+    }
+
+    #[test]
+    fn test_configure_pvh() {
+        let mem_size = 3330 << 20;
+        let gm = GuestMemoryMmap::from_ranges(&arch_memory_regions(mem_size)).unwrap();
+        let initrd = InitrdConfig {
+            address: GuestAddress(0x100_0000),
+            size: 0x1000,
+        };
+        configure_system(
+            &gm,
+            GuestAddress(layout::CMDLINE_START),
+            10,
+            &Some(initrd),
+            &None,
+            1,
+            BootProtocol::Pvh,
+            None,
+        )
+        .unwrap();
+
+        let start_info: StartInfoWrapper =
+            gm.read_obj(GuestAddress(layout::PVH_INFO_START)).unwrap();
+        assert_eq!(start_info.0.magic, XEN_HVM_START_MAGIC_VALUE);
+        assert_eq!(start_info.0.version, 1);
+        assert_eq!(start_info.0.cmdline_paddr, layout::CMDLINE_START);
+        assert_eq!(start_info.0.nr_modules, 1);
+        assert_eq!(start_info.0.modlist_paddr, layout::MODLIST_START);
+        assert_eq!(start_info.0.memmap_paddr, layout::MEMMAP_START);
+        assert_eq!(start_info.0.memmap_entries, 3);
+
+        let modlist_entry: ModlistEntryWrapper =
+            gm.read_obj(GuestAddress(layout::MODLIST_START)).unwrap();
+        assert_eq!(modlist_entry.0.paddr, 0x100_0000);
+        assert_eq!(modlist_entry.0.size, 0x1000);
+
+        // The last memory region starts past the 32-bit MMIO gap.
+        let memmap_entry: MemmapTableEntryWrapper = gm
+            .read_obj(GuestAddress(
+                layout::MEMMAP_START + 2 * mem::size_of::<hvm_memmap_table_entry>() as u64,
+            ))
+            .unwrap();
+        assert_eq!(memmap_entry.0.addr, FIRST_ADDR_PAST_32BITS);
+        assert_eq!(memmap_entry.0.size, (3330 << 20) - MMIO_MEM_START);
+        assert_eq!(memmap_entry.0.type_, XEN_HVM_MEMMAP_TYPE_RAM);
+    }
+
+    #[test]
+    fn test_configure_event_log() {
+        let gm = GuestMemoryMmap::from_ranges(&arch_memory_regions(128 << 20)).unwrap();
+        let event_log = EventLogConfig {
+            address: GuestAddress(event_log_addr(&gm)),
+            size: 0x100,
+        };
+        configure_system(
+            &gm,
+            GuestAddress(layout::CMDLINE_START),
+            10,
+            &None,
+            &Some(event_log),
+            1,
+            BootProtocol::Linux,
+            None,
+        )
+        .unwrap();
+
+        // The event log follows the `setup_data` header describing it.
+        let params: BootParamsWrapper = gm.read_obj(GuestAddress(layout::ZERO_PAGE_START)).unwrap();
+        assert_eq!({ params.0.hdr.setup_data }, layout::EVENT_LOG_REGION_START);
+        let setup_data_addr = GuestAddress(layout::EVENT_LOG_REGION_START);
+        assert_eq!(gm.read_obj::<u64>(setup_data_addr).unwrap(), 0);
+        assert_eq!(
+            gm.read_obj::<u32>(setup_data_addr.unchecked_add(8))
+                .unwrap(),
+            SETUP_EVENT_LOG
+        );
+        assert_eq!(
+            gm.read_obj::<u32>(setup_data_addr.unchecked_add(12))
+                .unwrap(),
+            0x100
+        );
+        assert_eq!(event_log_addr(&gm), layout::EVENT_LOG_REGION_START + 16);
+
+        // The event log region is carved out of the low memory and reserved.
+        let expected_entries = [
+            (0, layout::EVENT_LOG_REGION_START, E820_RAM),
+            (
+                layout::EVENT_LOG_REGION_START,
+                layout::EVENT_LOG_REGION_SIZE,
+                E820_RESERVED,
+            ),
+            (
+                layout::EVENT_LOG_REGION_START + layout::EVENT_LOG_REGION_SIZE,
+                EBDA_START - layout::EVENT_LOG_REGION_START - layout::EVENT_LOG_REGION_SIZE,
+                E820_RAM,
+            ),
+            (
+                layout::HIMEM_START,
+                (128 << 20) - layout::HIMEM_START,
+                E820_RAM,
+            ),
+        ];
+        assert_eq!({ params.0.e820_entries }, 4);
+        for (entry, (addr, size, mem_type)) in params.0.e820_map.iter().zip(&expected_entries) {
+            assert_eq!({ entry.addr }, *addr);
+            assert_eq!({ entry.size }, *size);
+            assert_eq!({ entry.type_ }, *mem_type);
+        }
+
+        // PVH guests would not find the event log.
+        let event_log = EventLogConfig {
+            address: GuestAddress(event_log_addr(&gm)),
+            size: 0x100,
+        };
+        assert_eq!(
+            configure_system(
+                &gm,
+                GuestAddress(layout::CMDLINE_START),
+                10,
+                &None,
+                &Some(event_log),
+                1,
+                BootProtocol::Pvh,
+                None,
+            ),
+            Err(Error::PvhEventLog)
+        );
     }
 
     #[test]
@@ -8,6 +8,7 @@
 use std::mem;
 
 use super::gdt::{gdt_entry, kvm_segment_from_gdt};
+use super::BootProtocol;
 use kvm_bindings::{kvm_fpu, kvm_regs, kvm_sregs};
 use kvm_ioctls::VcpuFd;
 use vm_memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
@@ -62,8 +63,24 @@ pub fn setup_fpu(vcpu: &VcpuFd) -> Result<()> {
 ///
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
 /// * `boot_ip` - Starting instruction pointer.
-pub fn setup_regs(vcpu: &VcpuFd, boot_ip: u64) -> Result<()> {
-    let regs: kvm_regs = kvm_regs {
+/// * `boot_protocol` - Protocol through which the kernel is booted.
+pub fn setup_regs(vcpu: &VcpuFd, boot_ip: u64, boot_protocol: BootProtocol) -> Result<()> {
+    let regs: kvm_regs = match boot_protocol {
+        BootProtocol::Linux => linux_regs(boot_ip),
+        BootProtocol::Pvh => kvm_regs {
+            rflags: 0x0000_0000_0000_0002u64,
+            rip: boot_ip,
+            // Must point to the `hvm_start_info` structure per PVH ABI.
+            rbx: super::layout::PVH_INFO_START,
+            ..Default::default()
+        },
+    };
+
+    vcpu.set_regs(&regs).map_err(Error::SetBaseRegisters)
+}
+
+fn linux_regs(boot_ip: u64) -> kvm_regs {
+    kvm_regs {
         rflags: 0x0000_0000_0000_0002u64,
         rip: boot_ip,
         // Frame pointer. It gets a snapshot of the stack pointer (rsp) so that when adjustments are
@@ -75,9 +92,7 @@ pub fn setup_regs(vcpu: &VcpuFd, boot_ip: u64) -> Result<()> {
         // Must point to zero page address per Linux ABI. This is x86_64 specific.
         rsi: super::layout::ZERO_PAGE_START as u64,
         ..Default::default()
-    };
-
-    vcpu.set_regs(&regs).map_err(Error::SetBaseRegisters)
+    }
 }
 
 /// Configures the segment registers and system page tables for a given CPU.
@@ -86,11 +101,19 @@ pub fn setup_regs(vcpu: &VcpuFd, boot_ip: u64) -> Result<()> {
 ///
 /// * `mem` - The memory that will be passed to the guest.
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
-pub fn setup_sregs(mem: &GuestMemoryMmap, vcpu: &VcpuFd) -> Result<()> {
+/// * `boot_protocol` - Protocol through which the kernel is booted.
+pub fn setup_sregs(
+    mem: &GuestMemoryMmap,
+    vcpu: &VcpuFd,
+    boot_protocol: BootProtocol,
+) -> Result<()> {
     let mut sregs: kvm_sregs = vcpu.get_sregs().map_err(Error::GetStatusRegisters)?;
 
-    configure_segments_and_sregs(mem, &mut sregs)?;
-    setup_page_tables(mem, &mut sregs)?; // TODO(dgreid) - Can this be done once per system instead?
+    configure_segments_and_sregs(mem, &mut sregs, boot_protocol)?;
+    // PVH kernels start with paging disabled.
+    if boot_protocol == BootProtocol::Linux {
+        setup_page_tables(mem, &mut sregs)?; // TODO(dgreid) - Can this be done once per system instead?
+    }
 
     vcpu.set_sregs(&sregs).map_err(Error::SetStatusRegisters)
 }
@@ -127,13 +150,26 @@ fn write_idt_value(val: u64, guest_mem: &GuestMemoryMmap) -> Result<()> {
         .map_err(|_| Error::WriteIDT)
 }
 
-fn configure_segments_and_sregs(mem: &GuestMemoryMmap, sregs: &mut kvm_sregs) -> Result<()> {
-    let gdt_table: [u64; BOOT_GDT_MAX as usize] = [
-        gdt_entry(0, 0, 0),            // NULL
-        gdt_entry(0xa09b, 0, 0xfffff), // CODE
-        gdt_entry(0xc093, 0, 0xfffff), // DATA
-        gdt_entry(0x808b, 0, 0xfffff), // TSS
-    ];
+fn configure_segments_and_sregs(
+    mem: &GuestMemoryMmap,
+    sregs: &mut kvm_sregs,
+    boot_protocol: BootProtocol,
+) -> Result<()> {
+    let gdt_table: [u64; BOOT_GDT_MAX as usize] = match boot_protocol {
+        BootProtocol::Linux => [
+            gdt_entry(0, 0, 0),            // NULL
+            gdt_entry(0xa09b, 0, 0xfffff), // CODE
+            gdt_entry(0xc093, 0, 0xfffff), // DATA
+            gdt_entry(0x808b, 0, 0xfffff), // TSS
+        ],
+        // Flat 32-bit segments, as required by the PVH ABI.
+        BootProtocol::Pvh => [
+            gdt_entry(0, 0, 0),            // NULL
+            gdt_entry(0xc09b, 0, 0xfffff), // CODE
+            gdt_entry(0xc093, 0, 0xfffff), // DATA
+            gdt_entry(0x008b, 0, 0x67),    // TSS
+        ],
+    };
 
     let code_seg = kvm_segment_from_gdt(gdt_table[1], 1);
     let data_seg = kvm_segment_from_gdt(gdt_table[2], 2);
@@ -156,9 +192,18 @@ fn configure_segments_and_sregs(mem: &GuestMemoryMmap, sregs: &mut kvm_sregs) ->
     sregs.ss = data_seg;
     sregs.tr = tss_seg;
 
-    /* 64-bit protected mode */
-    sregs.cr0 |= X86_CR0_PE;
-    sregs.efer |= EFER_LME | EFER_LMA;
+    match boot_protocol {
+        BootProtocol::Linux => {
+            /* 64-bit protected mode */
+            sregs.cr0 |= X86_CR0_PE;
+            sregs.efer |= EFER_LME | EFER_LMA;
+        }
+        BootProtocol::Pvh => {
+            /* 32-bit protected mode, without paging */
+            sregs.cr0 = X86_CR0_PE;
+            sregs.cr4 = 0;
+        }
+    }
 
     Ok(())
 }
@@ -274,7 +319,19 @@ mod tests {
             ..Default::default()
         };
 
-        setup_regs(&vcpu, expected_regs.rip).unwrap();
+        setup_regs(&vcpu, expected_regs.rip, BootProtocol::Linux).unwrap();
+
+        let actual_regs: kvm_regs = vcpu.get_regs().unwrap();
+        assert_eq!(actual_regs, expected_regs);
+
+        let expected_regs: kvm_regs = kvm_regs {
+            rflags: 0x0000_0000_0000_0002u64,
+            rip: 1,
+            rbx: super::super::layout::PVH_INFO_START,
+            ..Default::default()
+        };
+
+        setup_regs(&vcpu, expected_regs.rip, BootProtocol::Pvh).unwrap();
 
         let actual_regs: kvm_regs = vcpu.get_regs().unwrap();
         assert_eq!(actual_regs, expected_regs);
@@ -288,7 +345,7 @@ mod tests {
         let gm = create_guest_mem(None);
 
         assert!(vcpu.set_sregs(&Default::default()).is_ok());
-        setup_sregs(&gm, &vcpu).unwrap();
+        setup_sregs(&gm, &vcpu, BootProtocol::Linux).unwrap();
 
         let mut sregs: kvm_sregs = vcpu.get_sregs().unwrap();
         // for AMD KVM_GET_SREGS returns g = 0 for each kvm_segment.
@@ -299,6 +356,29 @@ mod tests {
         validate_page_tables(&gm, &sregs);
     }
 
+    #[test]
+    fn test_setup_sregs_pvh() {
+        let kvm = Kvm::new().unwrap();
+        let vm = kvm.create_vm().unwrap();
+        let vcpu = vm.create_vcpu(0).unwrap();
+        let gm = create_guest_mem(None);
+
+        setup_sregs(&gm, &vcpu, BootProtocol::Pvh).unwrap();
+
+        let sregs: kvm_sregs = vcpu.get_sregs().unwrap();
+        assert_eq!(0xcf_9b00_0000_ffff, read_u64(&gm, BOOT_GDT_OFFSET + 8));
+        assert_eq!(0xcf_9300_0000_ffff, read_u64(&gm, BOOT_GDT_OFFSET + 16));
+        assert_eq!(0x8b00_0000_0067, read_u64(&gm, BOOT_GDT_OFFSET + 24));
+        assert_eq!(1, sregs.cs.db);
+        assert_eq!(0, sregs.cs.l);
+        assert!(sregs.cr0 & X86_CR0_PE != 0);
+        assert_eq!(sregs.cr0 & X86_CR0_PG, 0);
+        assert_eq!(sregs.cr4, 0);
+        assert_eq!(sregs.efer & (EFER_LME | EFER_LMA), 0);
+        // The page tables are left untouched.
+        assert_eq!(0, read_u64(&gm, PML4_START));
+    }
+
     #[test]
     fn test_write_gdt_table() {
         // Not enough memory for the gdt table to be written.
@@ -341,7 +421,7 @@ mod tests {
     fn test_configure_segments_and_sregs() {
         let mut sregs: kvm_sregs = Default::default();
         let gm = create_guest_mem(None);
-        configure_segments_and_sregs(&gm, &mut sregs).unwrap();
+        configure_segments_and_sregs(&gm, &mut sregs, BootProtocol::Linux).unwrap();
 
         validate_segments_and_sregs(&gm, &sregs);
     }
@@ -14,3 +14,5 @@ pub mod bootparam;
 pub mod mpspec;
 #[allow(non_upper_case_globals)]
 pub mod msr_index;
+#[allow(non_camel_case_types)]
+pub mod start_info;
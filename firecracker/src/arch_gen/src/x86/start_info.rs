@@ -0,0 +1,83 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+/*
+ * automatically generated by rust-bindgen
+ * From upstream xen include/public/arch-x86/hvm/start_info.h
+ */
+
+pub const XEN_HVM_START_MAGIC_VALUE: u32 = 0x336e_c578;
+pub const XEN_HVM_MEMMAP_TYPE_RAM: u32 = 1;
+pub const XEN_HVM_MEMMAP_TYPE_RESERVED: u32 = 2;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct hvm_start_info {
+    pub magic: u32,
+    pub version: u32,
+    pub flags: u32,
+    pub nr_modules: u32,
+    pub modlist_paddr: u64,
+    pub cmdline_paddr: u64,
+    pub rsdp_paddr: u64,
+    pub memmap_paddr: u64,
+    pub memmap_entries: u32,
+    pub reserved: u32,
+}
+#[test]
+fn bindgen_test_layout_hvm_start_info() {
+    assert_eq!(
+        ::std::mem::size_of::<hvm_start_info>(),
+        56usize,
+        concat!("Size of: ", stringify!(hvm_start_info))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<hvm_start_info>(),
+        8usize,
+        concat!("Alignment of ", stringify!(hvm_start_info))
+    );
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct hvm_modlist_entry {
+    pub paddr: u64,
+    pub size: u64,
+    pub cmdline_paddr: u64,
+    pub reserved: u64,
+}
+#[test]
+fn bindgen_test_layout_hvm_modlist_entry() {
+    assert_eq!(
+        ::std::mem::size_of::<hvm_modlist_entry>(),
+        32usize,
+        concat!("Size of: ", stringify!(hvm_modlist_entry))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<hvm_modlist_entry>(),
+        8usize,
+        concat!("Alignment of ", stringify!(hvm_modlist_entry))
+    );
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct hvm_memmap_table_entry {
+    pub addr: u64,
+    pub size: u64,
+    pub type_: u32,
+    pub reserved: u32,
+}
+#[test]
+fn bindgen_test_layout_hvm_memmap_table_entry() {
+    assert_eq!(
+        ::std::mem::size_of::<hvm_memmap_table_entry>(),
+        24usize,
+        concat!("Size of: ", stringify!(hvm_memmap_table_entry))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<hvm_memmap_table_entry>(),
+        8usize,
+        concat!("Alignment of ", stringify!(hvm_memmap_table_entry))
+    );
+}
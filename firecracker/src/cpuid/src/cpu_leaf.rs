@@ -173,7 +173,11 @@ pub mod leaf_0x7 {
             // 5 = WAITPKG
             // 7-6 reserved
             // 8 = GFNI
-            // 13-09 reserved
+            // VAES = Vector AES instructions
+            pub const VAES_BITINDEX: u32 = 9;
+            // VPCLMULQDQ = Vector carry-less multiplication
+            pub const VPCLMULQDQ_BITINDEX: u32 = 10;
+            // 13-11 reserved
             // AVX512_VPOPCNTDQ = Vector population count instruction (Intel® Xeon Phi™ only.)
             pub const AVX512_VPOPCNTDQ_BITINDEX: u32 = 14;
             // 21 - 17 = The value of MAWAU used by the BNDLDX and BNDSTX instructions in 64-bit mode.
@@ -270,16 +274,35 @@ pub mod leaf_0x80000001 {
         pub const TOPOEXT_INDEX: u32 = 22;
         pub const PREFETCH_BITINDEX: u32 = 8; // 3DNow! PREFETCH/PREFETCHW instructions
         pub const LZCNT_BITINDEX: u32 = 5; // advanced bit manipulation
+        pub const SSE4A_BITINDEX: u32 = 6; // EXTRQ, INSERTQ, MOVNTSS and MOVNTSD instructions
+        pub const MISALIGNSSE_BITINDEX: u32 = 7; // Misaligned SSE mode
+        pub const XOP_BITINDEX: u32 = 11; // Extended operation support
+        pub const FMA4_BITINDEX: u32 = 16; // Four-operand FMA instructions
+        pub const TBM_BITINDEX: u32 = 21; // Trailing bit manipulation instructions
+        pub const PERFCTR_CORE_BITINDEX: u32 = 23; // Core performance counter extensions
+        pub const PERFCTR_NB_BITINDEX: u32 = 24; // Northbridge performance counter extensions
+        pub const MWAITX_BITINDEX: u32 = 29; // MONITORX and MWAITX instructions
     }
 
     pub mod edx {
+        pub const MMXEXT_BITINDEX: u32 = 22; // AMD extensions to MMX instructions
+        pub const FFXSR_BITINDEX: u32 = 25; // FXSAVE and FXRSTOR instruction optimizations
         pub const PDPE1GB_BITINDEX: u32 = 26; // 1-GByte pages are available if 1.
+        pub const AMD_3DNOW_EXT_BITINDEX: u32 = 30; // AMD extensions to 3DNow! instructions
+        pub const AMD_3DNOW_BITINDEX: u32 = 31; // 3DNow! instructions
     }
 }
 
 pub mod leaf_0x80000008 {
     pub const LEAF_NUM: u32 = 0x8000_0008;
 
+    pub mod ebx {
+        pub const CLZERO_BITINDEX: u32 = 0; // CLZERO instruction
+        pub const RDPRU_BITINDEX: u32 = 4; // RDPRU instruction
+        pub const MCOMMIT_BITINDEX: u32 = 8; // MCOMMIT instruction
+        pub const WBNOINVD_BITINDEX: u32 = 9; // WBNOINVD instruction
+    }
+
     pub mod ecx {
         use crate::bit_helper::BitRange;
 
@@ -0,0 +1,457 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter};
+
+use crate::cpu_leaf::*;
+use kvm_bindings::{kvm_cpuid_entry2, CpuId, KVM_CPUID_FLAG_SIGNIFCANT_INDEX};
+
+/// Registers of a CPUID entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    /// EAX register.
+    EAX,
+    /// EBX register.
+    EBX,
+    /// ECX register.
+    ECX,
+    /// EDX register.
+    EDX,
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Register::EAX => write!(f, "EAX"),
+            Register::EBX => write!(f, "EBX"),
+            Register::ECX => write!(f, "ECX"),
+            Register::EDX => write!(f, "EDX"),
+        }
+    }
+}
+
+/// A CPUID register holding feature flags.
+struct FeatureRegister {
+    function: u32,
+    index: u32,
+    register: Register,
+    // Bits reflecting guest controlled state (e.g. OSXSAVE mirrors CR4.OSXSAVE) rather than
+    // capabilities of the host.
+    dynamic_bits: u32,
+    names: &'static [(u32, &'static str)],
+}
+
+// Feature flags compared when checking whether a host can run a guest. The names follow the
+// ones reported by Linux in /proc/cpuinfo.
+const FEATURE_REGISTERS: &[FeatureRegister] = &[
+    FeatureRegister {
+        function: leaf_0x1::LEAF_NUM,
+        index: 0,
+        register: Register::ECX,
+        dynamic_bits: 1 << leaf_0x1::ecx::OSXSAVE_BITINDEX
+            | 1 << leaf_0x1::ecx::HYPERVISOR_BITINDEX,
+        names: &[
+            (0, "pni"),
+            (1, "pclmulqdq"),
+            (2, "dtes64"),
+            (3, "monitor"),
+            (4, "ds_cpl"),
+            (5, "vmx"),
+            (6, "smx"),
+            (7, "est"),
+            (8, "tm2"),
+            (9, "ssse3"),
+            (10, "cid"),
+            (11, "sdbg"),
+            (12, "fma"),
+            (13, "cx16"),
+            (14, "xtpr"),
+            (15, "pdcm"),
+            (17, "pcid"),
+            (18, "dca"),
+            (19, "sse4_1"),
+            (20, "sse4_2"),
+            (21, "x2apic"),
+            (22, "movbe"),
+            (23, "popcnt"),
+            (24, "tsc_deadline_timer"),
+            (25, "aes"),
+            (26, "xsave"),
+            (28, "avx"),
+            (29, "f16c"),
+            (30, "rdrand"),
+        ],
+    },
+    FeatureRegister {
+        function: leaf_0x1::LEAF_NUM,
+        index: 0,
+        register: Register::EDX,
+        dynamic_bits: 0,
+        names: &[
+            (0, "fpu"),
+            (1, "vme"),
+            (2, "de"),
+            (3, "pse"),
+            (4, "tsc"),
+            (5, "msr"),
+            (6, "pae"),
+            (7, "mce"),
+            (8, "cx8"),
+            (9, "apic"),
+            (11, "sep"),
+            (12, "mtrr"),
+            (13, "pge"),
+            (14, "mca"),
+            (15, "cmov"),
+            (16, "pat"),
+            (17, "pse36"),
+            (18, "pn"),
+            (19, "clflush"),
+            (21, "dts"),
+            (22, "acpi"),
+            (23, "mmx"),
+            (24, "fxsr"),
+            (25, "sse"),
+            (26, "sse2"),
+            (27, "ss"),
+            (28, "ht"),
+            (29, "tm"),
+            (31, "pbe"),
+        ],
+    },
+    FeatureRegister {
+        function: leaf_0x7::LEAF_NUM,
+        index: 0,
+        register: Register::EBX,
+        dynamic_bits: 0,
+        names: &[
+            (0, "fsgsbase"),
+            (1, "tsc_adjust"),
+            (2, "sgx"),
+            (3, "bmi1"),
+            (4, "hle"),
+            (5, "avx2"),
+            (7, "smep"),
+            (8, "bmi2"),
+            (9, "erms"),
+            (10, "invpcid"),
+            (11, "rtm"),
+            (12, "cqm"),
+            (14, "mpx"),
+            (15, "rdt_a"),
+            (16, "avx512f"),
+            (17, "avx512dq"),
+            (18, "rdseed"),
+            (19, "adx"),
+            (20, "smap"),
+            (21, "avx512ifma"),
+            (23, "clflushopt"),
+            (24, "clwb"),
+            (25, "intel_pt"),
+            (26, "avx512pf"),
+            (27, "avx512er"),
+            (28, "avx512cd"),
+            (29, "sha_ni"),
+            (30, "avx512bw"),
+            (31, "avx512vl"),
+        ],
+    },
+    FeatureRegister {
+        function: leaf_0x7::LEAF_NUM,
+        index: 0,
+        register: Register::ECX,
+        dynamic_bits: 1 << leaf_0x7::index0::ecx::OSPKE_BITINDEX,
+        names: &[
+            (1, "avx512vbmi"),
+            (2, "umip"),
+            (3, "pku"),
+            (5, "waitpkg"),
+            (6, "avx512_vbmi2"),
+            (8, "gfni"),
+            (9, "vaes"),
+            (10, "vpclmulqdq"),
+            (11, "avx512_vnni"),
+            (12, "avx512_bitalg"),
+            (14, "avx512_vpopcntdq"),
+            (16, "la57"),
+            (22, "rdpid"),
+            (25, "cldemote"),
+            (27, "movdiri"),
+            (28, "movdir64b"),
+            (30, "sgx_lc"),
+        ],
+    },
+    FeatureRegister {
+        function: leaf_0x7::LEAF_NUM,
+        index: 0,
+        register: Register::EDX,
+        dynamic_bits: 0,
+        names: &[
+            (2, "avx512_4vnniw"),
+            (3, "avx512_4fmaps"),
+            (4, "fsrm"),
+            (8, "avx512_vp2intersect"),
+            (10, "md_clear"),
+            (14, "serialize"),
+            (18, "pconfig"),
+            (26, "spec_ctrl"),
+            (27, "intel_stibp"),
+            (28, "flush_l1d"),
+            (29, "arch_capabilities"),
+            (31, "spec_ctrl_ssbd"),
+        ],
+    },
+    FeatureRegister {
+        function: leaf_0xd::LEAF_NUM,
+        index: 1,
+        register: Register::EAX,
+        dynamic_bits: 0,
+        names: &[
+            (0, "xsaveopt"),
+            (1, "xsavec"),
+            (2, "xgetbv1"),
+            (3, "xsaves"),
+        ],
+    },
+    FeatureRegister {
+        function: leaf_0x80000001::LEAF_NUM,
+        index: 0,
+        register: Register::ECX,
+        dynamic_bits: 0,
+        names: &[
+            (0, "lahf_lm"),
+            (1, "cmp_legacy"),
+            (2, "svm"),
+            (3, "extapic"),
+            (4, "cr8_legacy"),
+            (5, "abm"),
+            (6, "sse4a"),
+            (7, "misalignsse"),
+            (8, "3dnowprefetch"),
+            (9, "osvw"),
+            (10, "ibs"),
+            (11, "xop"),
+            (12, "skinit"),
+            (13, "wdt"),
+            (15, "lwp"),
+            (16, "fma4"),
+            (17, "tce"),
+            (19, "nodeid_msr"),
+            (21, "tbm"),
+            (22, "topoext"),
+            (23, "perfctr_core"),
+            (24, "perfctr_nb"),
+            (26, "bpext"),
+            (27, "ptsc"),
+            (28, "perfctr_llc"),
+            (29, "mwaitx"),
+        ],
+    },
+    FeatureRegister {
+        function: leaf_0x80000001::LEAF_NUM,
+        index: 0,
+        register: Register::EDX,
+        dynamic_bits: 0,
+        names: &[
+            (11, "syscall"),
+            (19, "mp"),
+            (20, "nx"),
+            (22, "mmxext"),
+            (25, "fxsr_opt"),
+            (26, "pdpe1gb"),
+            (27, "rdtscp"),
+            (29, "lm"),
+            (30, "3dnowext"),
+            (31, "3dnow"),
+        ],
+    },
+];
+
+/// A CPU feature exposed to a guest that is not available on the host.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MissingFeature {
+    /// The CPUID leaf of the feature flag.
+    pub function: u32,
+    /// The CPUID subleaf of the feature flag.
+    pub index: u32,
+    /// The register holding the feature flag.
+    pub register: Register,
+    /// The index of the feature flag within the register.
+    pub bit: u32,
+    /// The name of the feature, if known.
+    pub name: Option<&'static str>,
+}
+
+impl Display for MissingFeature {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        if let Some(name) = self.name {
+            write!(f, "{} (", name)?;
+        }
+        write!(
+            f,
+            "CPUID.(EAX={:#x},ECX={:#x}):{}[{}]",
+            self.function, self.index, self.register, self.bit
+        )?;
+        if self.name.is_some() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn entry_matches(entry: &kvm_cpuid_entry2, function: u32, index: u32) -> bool {
+    entry.function == function
+        && (entry.flags & KVM_CPUID_FLAG_SIGNIFCANT_INDEX == 0 || entry.index == index)
+}
+
+fn read_feature_register(cpuid: &CpuId, feature_register: &FeatureRegister) -> u32 {
+    cpuid
+        .as_slice()
+        .iter()
+        .find(|entry| entry_matches(entry, feature_register.function, feature_register.index))
+        .map_or(0, |entry| match feature_register.register {
+            Register::EAX => entry.eax,
+            Register::EBX => entry.ebx,
+            Register::ECX => entry.ecx,
+            Register::EDX => entry.edx,
+        })
+}
+
+pub(crate) fn register_mut(entry: &mut kvm_cpuid_entry2, register: Register) -> &mut u32 {
+    match register {
+        Register::EAX => &mut entry.eax,
+        Register::EBX => &mut entry.ebx,
+        Register::ECX => &mut entry.ecx,
+        Register::EDX => &mut entry.edx,
+    }
+}
+
+/// Returns the features that are exposed in `guest_cpuid` but are missing from `host_cpuid`.
+///
+/// Only the feature flag registers are compared; identification, topology and cache
+/// information are expected to differ between hosts.
+///
+/// # Arguments
+///
+/// * `guest_cpuid` - The CPUID exposed to a guest.
+/// * `host_cpuid` - The CPUID supported by the host, as reported by `KVM_GET_SUPPORTED_CPUID`.
+pub fn missing_features(guest_cpuid: &CpuId, host_cpuid: &CpuId) -> Vec<MissingFeature> {
+    let mut missing = Vec::new();
+    for feature_register in FEATURE_REGISTERS {
+        let guest_flags =
+            read_feature_register(guest_cpuid, feature_register) & !feature_register.dynamic_bits;
+        let missing_flags = guest_flags & !read_feature_register(host_cpuid, feature_register);
+        for bit in (0..32).filter(|bit| missing_flags & (1 << bit) != 0) {
+            missing.push(MissingFeature {
+                function: feature_register.function,
+                index: feature_register.index,
+                register: feature_register.register,
+                bit,
+                name: feature_register
+                    .names
+                    .iter()
+                    .find(|(index, _)| *index == bit)
+                    .map(|(_, name)| *name),
+            });
+        }
+    }
+
+    missing
+}
+
+/// Clears the feature flags of `cpuid` that are not also set in `reference_cpuid`.
+///
+/// Flags reflecting guest controlled state are left untouched.
+pub fn mask_features(cpuid: &mut CpuId, reference_cpuid: &CpuId) {
+    for feature_register in FEATURE_REGISTERS {
+        let allowed_flags = read_feature_register(reference_cpuid, feature_register)
+            | feature_register.dynamic_bits;
+        for entry in cpuid
+            .as_mut_slice()
+            .iter_mut()
+            .filter(|entry| entry_matches(entry, feature_register.function, feature_register.index))
+        {
+            *register_mut(entry, feature_register.register) &= allowed_flags;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_cpuid(leaf_0x7_ebx: u32, leaf_0x1_ecx: u32) -> CpuId {
+        CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                ecx: leaf_0x1_ecx,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x7::LEAF_NUM,
+                index: 0,
+                flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                ebx: leaf_0x7_ebx,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x7::LEAF_NUM,
+                index: 1,
+                flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                ebx: 0xffff_ffff,
+                ..Default::default()
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_missing_features() {
+        use crate::cpu_leaf::leaf_0x7::index0::ebx;
+
+        let avx2 = 1 << ebx::AVX2_BITINDEX;
+        let avx512f = 1 << ebx::AVX512F_BITINDEX;
+        let host_cpuid = build_cpuid(avx2, 0);
+
+        // Nothing is missing when the guest exposes a subset of the host features.
+        assert!(missing_features(&build_cpuid(avx2, 0), &host_cpuid).is_empty());
+        assert!(missing_features(&build_cpuid(0, 0), &host_cpuid).is_empty());
+
+        // Subleaf 1 of leaf 0x7 is not confused with subleaf 0.
+        let missing = missing_features(&build_cpuid(avx2 | avx512f | 1 << 22, 0), &host_cpuid);
+        assert_eq!(missing.len(), 2);
+        assert_eq!(missing[0].name, Some("avx512f"));
+        assert_eq!(
+            missing[0].to_string(),
+            "avx512f (CPUID.(EAX=0x7,ECX=0x0):EBX[16])"
+        );
+        assert_eq!(missing[1].name, None);
+        assert_eq!(missing[1].to_string(), "CPUID.(EAX=0x7,ECX=0x0):EBX[22]");
+
+        // Flags reflecting guest state are not host capabilities.
+        let osxsave = 1 << leaf_0x1::ecx::OSXSAVE_BITINDEX;
+        assert!(missing_features(&build_cpuid(0, osxsave), &host_cpuid).is_empty());
+
+        // Every flag is missing when the host lacks the leaf altogether.
+        let empty_cpuid = CpuId::new(0).unwrap();
+        assert_eq!(missing_features(&host_cpuid, &empty_cpuid).len(), 1);
+    }
+
+    #[test]
+    fn test_mask_features() {
+        use crate::cpu_leaf::leaf_0x7::index0::ebx;
+
+        let avx2 = 1 << ebx::AVX2_BITINDEX;
+        let avx512f = 1 << ebx::AVX512F_BITINDEX;
+        let osxsave = 1 << leaf_0x1::ecx::OSXSAVE_BITINDEX;
+
+        let mut cpuid = build_cpuid(avx2 | avx512f, osxsave);
+        mask_features(&mut cpuid, &build_cpuid(avx2, 0));
+
+        let entries = cpuid.as_slice();
+        assert_eq!(entries[0].ecx, osxsave);
+        assert_eq!(entries[1].ebx, avx2);
+        // Registers that do not hold feature flags are left untouched.
+        assert_eq!(entries[2].ebx, 0xffff_ffff);
+    }
+}
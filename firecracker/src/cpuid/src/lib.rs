@@ -18,7 +18,12 @@ use crate::common::*;
 /// Contains helper methods for bit operations.
 pub mod bit_helper;
 
+/// Compares the CPU features exposed by different CPUID configurations.
+pub mod features;
+
 mod template;
+pub use crate::template::amd::t2a;
+pub use crate::template::custom;
 pub use crate::template::intel::c3;
 pub use crate::template::intel::t2;
 
@@ -0,0 +1,187 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::bit_helper::BitHelper;
+use crate::cpu_leaf::*;
+use crate::template::amd::validate_vendor_id;
+use crate::transformer::*;
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+
+// The family, model and stepping of the host are kept, since AMD guests rely on them to
+// apply errata workarounds. Only the feature flags are aligned with the T2 template.
+
+fn update_feature_info_entry(entry: &mut kvm_cpuid_entry2, _vm_spec: &VmSpec) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x1::*;
+
+    // Disable Features
+    entry
+        .ecx
+        .write_bit(ecx::DTES64_BITINDEX, false)
+        .write_bit(ecx::MONITOR_BITINDEX, false)
+        .write_bit(ecx::DS_CPL_SHIFT, false)
+        .write_bit(ecx::TM2_BITINDEX, false)
+        .write_bit(ecx::CNXT_ID_BITINDEX, false)
+        .write_bit(ecx::SDBG_BITINDEX, false)
+        .write_bit(ecx::XTPR_UPDATE_BITINDEX, false)
+        .write_bit(ecx::PDCM_BITINDEX, false)
+        .write_bit(ecx::OSXSAVE_BITINDEX, false);
+
+    entry
+        .edx
+        .write_bit(edx::PSN_BITINDEX, false)
+        .write_bit(edx::DS_BITINDEX, false)
+        .write_bit(edx::ACPI_BITINDEX, false)
+        .write_bit(edx::SS_BITINDEX, false)
+        .write_bit(edx::TM_BITINDEX, false)
+        .write_bit(edx::PBE_BITINDEX, false);
+
+    Ok(())
+}
+
+fn update_structured_extended_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x7::index0::*;
+
+    if entry.index == 0 {
+        entry
+            .ebx
+            .write_bit(ebx::SGX_BITINDEX, false)
+            .write_bit(ebx::HLE_BITINDEX, false)
+            .write_bit(ebx::FPDP_BITINDEX, false)
+            .write_bit(ebx::RTM_BITINDEX, false)
+            .write_bit(ebx::RDT_M_BITINDEX, false)
+            .write_bit(ebx::RDT_A_BITINDEX, false)
+            .write_bit(ebx::MPX_BITINDEX, false)
+            .write_bit(ebx::AVX512F_BITINDEX, false)
+            .write_bit(ebx::AVX512DQ_BITINDEX, false)
+            .write_bit(ebx::RDSEED_BITINDEX, false)
+            .write_bit(ebx::ADX_BITINDEX, false)
+            .write_bit(ebx::AVX512IFMA_BITINDEX, false)
+            .write_bit(ebx::CLFLUSHOPT_BITINDEX, false)
+            .write_bit(ebx::CLWB_BITINDEX, false)
+            .write_bit(ebx::PT_BITINDEX, false)
+            .write_bit(ebx::AVX512PF_BITINDEX, false)
+            .write_bit(ebx::AVX512ER_BITINDEX, false)
+            .write_bit(ebx::AVX512CD_BITINDEX, false)
+            .write_bit(ebx::SHA_BITINDEX, false)
+            .write_bit(ebx::AVX512BW_BITINDEX, false)
+            .write_bit(ebx::AVX512VL_BITINDEX, false);
+
+        entry
+            .ecx
+            .write_bit(ecx::AVX512_VBMI_BITINDEX, false)
+            .write_bit(ecx::PKU_BITINDEX, false)
+            .write_bit(ecx::OSPKE_BITINDEX, false)
+            .write_bit(ecx::VAES_BITINDEX, false)
+            .write_bit(ecx::VPCLMULQDQ_BITINDEX, false)
+            .write_bit(ecx::AVX512_VPOPCNTDQ_BITINDEX, false)
+            .write_bit(ecx::RDPID_BITINDEX, false)
+            .write_bit(ecx::SGX_LC_BITINDEX, false);
+
+        entry
+            .edx
+            .write_bit(edx::AVX512_4VNNIW_BITINDEX, false)
+            .write_bit(edx::AVX512_4FMAPS_BITINDEX, false);
+    }
+
+    Ok(())
+}
+
+fn update_xsave_features_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0xd::*;
+
+    if entry.index == 0 {
+        // MPX and AVX-512 are masked out with the current template so the size in bytes of
+        // their save areas should be 0 (or invalid).
+        entry
+            .eax
+            .write_bits_in_range(&index0::eax::MPX_STATE_BITRANGE, 0)
+            .write_bits_in_range(&index0::eax::AVX512_STATE_BITRANGE, 0);
+
+        // OSPKE is masked in leaf_0x7 index 0 - RDPKRU/WRPKRU not exposed.
+        // Here we mask the XSAVE PKRU capabilities.
+        entry.eax.write_bit(index0::eax::PKRU_BITINDEX, false);
+    }
+
+    if entry.index == 1 {
+        entry
+            .eax
+            .write_bit(index1::eax::XSAVEC_SHIFT, false)
+            .write_bit(index1::eax::XGETBV_SHIFT, false)
+            .write_bit(index1::eax::XSAVES_SHIFT, false);
+    }
+
+    Ok(())
+}
+
+fn update_extended_feature_info_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x80000001::*;
+
+    // Disable the AMD specific extensions, which a T2 instance does not provide.
+    entry
+        .ecx
+        .write_bit(ecx::SSE4A_BITINDEX, false)
+        .write_bit(ecx::MISALIGNSSE_BITINDEX, false)
+        .write_bit(ecx::PREFETCH_BITINDEX, false)
+        .write_bit(ecx::XOP_BITINDEX, false)
+        .write_bit(ecx::FMA4_BITINDEX, false)
+        .write_bit(ecx::TBM_BITINDEX, false)
+        .write_bit(ecx::PERFCTR_CORE_BITINDEX, false)
+        .write_bit(ecx::PERFCTR_NB_BITINDEX, false)
+        .write_bit(ecx::MWAITX_BITINDEX, false);
+
+    entry
+        .edx
+        .write_bit(edx::MMXEXT_BITINDEX, false)
+        .write_bit(edx::FFXSR_BITINDEX, false)
+        .write_bit(edx::PDPE1GB_BITINDEX, false)
+        .write_bit(edx::AMD_3DNOW_EXT_BITINDEX, false)
+        .write_bit(edx::AMD_3DNOW_BITINDEX, false);
+
+    Ok(())
+}
+
+fn update_amd_features_entry(entry: &mut kvm_cpuid_entry2, _vm_spec: &VmSpec) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x80000008::*;
+
+    // The speculation control flags are left untouched, so that guests keep their mitigations.
+    entry
+        .ebx
+        .write_bit(ebx::CLZERO_BITINDEX, false)
+        .write_bit(ebx::RDPRU_BITINDEX, false)
+        .write_bit(ebx::MCOMMIT_BITINDEX, false)
+        .write_bit(ebx::WBNOINVD_BITINDEX, false);
+
+    Ok(())
+}
+
+/// Sets up the cpuid entries for a given VCPU following a T2A template.
+struct T2ACpuidTransformer {}
+
+impl CpuidTransformer for T2ACpuidTransformer {
+    fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+        match entry.function {
+            leaf_0x1::LEAF_NUM => Some(update_feature_info_entry),
+            leaf_0x7::LEAF_NUM => Some(update_structured_extended_entry),
+            leaf_0xd::LEAF_NUM => Some(update_xsave_features_entry),
+            leaf_0x80000001::LEAF_NUM => Some(update_extended_feature_info_entry),
+            leaf_0x80000008::LEAF_NUM => Some(update_amd_features_entry),
+            _ => None,
+        }
+    }
+}
+
+/// Sets up the cpuid entries for a given VCPU following a T2A template, which exposes the
+/// features of a T2 instance on AMD hosts.
+pub fn set_cpuid_entries(kvm_cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+    validate_vendor_id()?;
+    T2ACpuidTransformer {}.process_cpuid(kvm_cpuid, vm_spec)
+}
@@ -0,0 +1,127 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::features::{entry_matches, register_mut, Register};
+use crate::transformer::Error;
+use kvm_bindings::CpuId;
+
+/// Overrides some of the bits of a register of a CPUID leaf.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpuidRegisterModifier {
+    /// The CPUID leaf.
+    pub leaf: u32,
+    /// The CPUID subleaf. Ignored for leaves that do not have subleaves.
+    pub subleaf: u32,
+    /// The modified register.
+    pub register: Register,
+    /// The bits of the register that are overridden.
+    pub mask: u32,
+    /// The values of the overridden bits.
+    pub value: u32,
+}
+
+impl CpuidRegisterModifier {
+    fn apply(&self, register: &mut u32) {
+        *register = (*register & !self.mask) | (self.value & self.mask);
+    }
+}
+
+/// Overrides the CPUID entries as described by `modifiers`.
+///
+/// Every modified leaf has to be present in `kvm_cpuid`; leaves cannot be added.
+///
+/// # Arguments
+///
+/// * `kvm_cpuid` - The CPUID to modify.
+/// * `modifiers` - The modifications to apply, in order.
+pub fn set_cpuid_entries(
+    kvm_cpuid: &mut CpuId,
+    modifiers: &[CpuidRegisterModifier],
+) -> Result<(), Error> {
+    for modifier in modifiers {
+        let mut found = false;
+        for entry in kvm_cpuid
+            .as_mut_slice()
+            .iter_mut()
+            .filter(|entry| entry_matches(entry, modifier.leaf, modifier.subleaf))
+        {
+            modifier.apply(register_mut(entry, modifier.register));
+            found = true;
+        }
+
+        if !found {
+            return Err(Error::LeafNotFound(modifier.leaf, modifier.subleaf));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvm_bindings::{kvm_cpuid_entry2, KVM_CPUID_FLAG_SIGNIFCANT_INDEX};
+
+    #[test]
+    fn test_set_cpuid_entries() {
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: 0x1,
+                ecx: 0xf0f0,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x7,
+                index: 0,
+                flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                ebx: 0xffff,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x7,
+                index: 1,
+                flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                ebx: 0xffff,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        let modifiers = [
+            // The subleaf is ignored for leaves without subleaves.
+            CpuidRegisterModifier {
+                leaf: 0x1,
+                subleaf: 3,
+                register: Register::ECX,
+                mask: 0xff,
+                value: 0x0f,
+            },
+            // Bits outside of the mask are left untouched.
+            CpuidRegisterModifier {
+                leaf: 0x7,
+                subleaf: 1,
+                register: Register::EBX,
+                mask: 0xf,
+                value: 0xf0,
+            },
+        ];
+        set_cpuid_entries(&mut cpuid, &modifiers).unwrap();
+
+        let entries = cpuid.as_slice();
+        assert_eq!(entries[0].ecx, 0xf00f);
+        assert_eq!(entries[1].ebx, 0xffff);
+        assert_eq!(entries[2].ebx, 0xfff0);
+
+        let modifiers = [CpuidRegisterModifier {
+            leaf: 0x7,
+            subleaf: 2,
+            register: Register::EBX,
+            mask: 0x1,
+            value: 0x0,
+        }];
+        match set_cpuid_entries(&mut cpuid, &modifiers) {
+            Err(Error::LeafNotFound(0x7, 2)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -1,5 +1,9 @@
 // Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+// Contains AMD specific templates.
+pub mod amd;
+/// Applies user-defined templates to the CPUID.
+pub mod custom;
 // Contains Intel specific templates.
 pub mod intel;
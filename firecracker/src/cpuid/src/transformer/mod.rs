@@ -62,6 +62,8 @@ pub enum Error {
     InternalError(super::common::Error),
     /// The operation is not permitted for the current vendor
     InvalidVendor,
+    /// The leaf (first value) and subleaf (second value) are not present in the CPUID.
+    LeafNotFound(u32, u32),
     /// The maximum number of addressable logical CPUs cannot be stored in an `u8`.
     VcpuCountOverflow,
 }
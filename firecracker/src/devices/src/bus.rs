@@ -140,6 +140,11 @@ impl Bus {
         Ok(())
     }
 
+    /// Returns the `(base, len)` ranges of the devices, in ascending address order.
+    pub fn ranges(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.devices.keys().map(|range| (range.0, range.1))
+    }
+
     /// Reads data from the device that owns the range containing `addr` and puts it into `data`.
     ///
     /// Returns true on success, otherwise `data` is untouched.
@@ -253,6 +258,20 @@ mod tests {
         assert!(bus.write(0x15, &values));
     }
 
+    #[test]
+    fn bus_ranges() {
+        let mut bus = Bus::new();
+        assert_eq!(bus.ranges().count(), 0);
+
+        let dummy = Arc::new(Mutex::new(DummyDevice));
+        assert!(bus.insert(dummy.clone(), 0x20, 0x10).is_ok());
+        assert!(bus.insert(dummy, 0x10, 0x8).is_ok());
+        assert_eq!(
+            bus.ranges().collect::<Vec<_>>(),
+            vec![(0x10, 0x8), (0x20, 0x10)]
+        );
+    }
+
     #[test]
     fn busrange_cmp_and_clone() {
         assert_eq!(BusRange(0x10, 2), BusRange(0x10, 3));
@@ -0,0 +1,426 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Client of the control and data channels of a software TPM emulator, such as `swtpm`.
+//!
+//! The control channel is the Unix socket the emulator listens on. Once connected, the data
+//! channel is one end of a socket pair which is handed over to the emulator through the
+//! `CMD_SET_DATAFD` control command, and carries the raw TPM commands and responses.
+//!
+//! Both channels time out after `EMULATOR_TIMEOUT`, so that a stuck emulator cannot block the
+//! vCPU or API threads forever. An exchange cut short leaves the channels out of step with the
+//! emulator, which is then no longer used.
+
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::{Error, Result};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+// Control commands, as defined by the `tpm_ioctl.h` header of `swtpm`.
+pub(crate) const CMD_GET_CAPABILITY: u32 = 1;
+pub(crate) const CMD_INIT: u32 = 2;
+pub(crate) const CMD_GET_STATEBLOB: u32 = 12;
+pub(crate) const CMD_SET_STATEBLOB: u32 = 13;
+pub(crate) const CMD_STOP: u32 = 14;
+pub(crate) const CMD_SET_DATAFD: u32 = 16;
+
+// Capabilities the emulator must report for the control commands used by the device.
+pub(crate) const PTM_CAP_INIT: u64 = 1;
+pub(crate) const PTM_CAP_GET_STATEBLOB: u64 = 1 << 8;
+pub(crate) const PTM_CAP_SET_STATEBLOB: u64 = 1 << 9;
+pub(crate) const PTM_CAP_STOP: u64 = 1 << 10;
+pub(crate) const PTM_CAP_SET_DATAFD: u64 = 1 << 12;
+const REQUIRED_CAPABILITIES: u64 = PTM_CAP_INIT
+    | PTM_CAP_GET_STATEBLOB
+    | PTM_CAP_SET_STATEBLOB
+    | PTM_CAP_STOP
+    | PTM_CAP_SET_DATAFD;
+
+/// Flag of `CMD_INIT` dropping the volatile state of the TPM.
+const PTM_INIT_FLAG_DELETE_VOLATILE: u32 = 1;
+/// Flag of `CMD_GET_STATEBLOB` requesting the state blobs to be decrypted.
+const PTM_STATE_FLAG_DECRYPTED: u32 = 1;
+/// Flag of the state blobs which are encrypted.
+const PTM_STATE_FLAG_ENCRYPTED: u32 = 2;
+
+/// Types of the state blobs of the TPM, in the order they are restored.
+pub(crate) const BLOB_TYPES: [u32; 3] = [
+    1, // Permanent state.
+    2, // Volatile state.
+    3, // Save state.
+];
+
+/// Size of the header of the TPM commands and responses.
+pub(crate) const TPM_HEADER_SIZE: usize = 10;
+/// Maximum size of the TPM commands and responses.
+pub(crate) const TPM_BUFFER_MAX: usize = 4096;
+/// Maximum size of a state blob of the emulator. The permanent state of `libtpms`, the largest
+/// blob, stays well below it.
+pub(crate) const STATE_BLOB_MAX: usize = 1 << 20;
+
+/// Time the emulator has to complete an exchange on either channel. Commands generating keys in
+/// software take up to a few seconds.
+pub(crate) const EMULATOR_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A state blob of the TPM emulator.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct StateBlob {
+    /// Type of the blob.
+    pub blob_type: u32,
+    /// Whether the blob is encrypted.
+    pub encrypted: bool,
+    /// Content of the blob.
+    pub data: Vec<u8>,
+}
+
+/// Connection to a software TPM emulator.
+pub struct Emulator {
+    socket_path: PathBuf,
+    control: UnixStream,
+    data: UnixStream,
+    // Whether an exchange was cut short, leaving unread data on the channels.
+    desynchronized: bool,
+}
+
+impl Emulator {
+    /// Connects to the control socket of the emulator at `socket_path` and sets up the data
+    /// channel.
+    pub fn new<P: AsRef<Path>>(socket_path: P) -> Result<Self> {
+        Self::with_timeout(socket_path, EMULATOR_TIMEOUT)
+    }
+
+    /// Same as `new`, with the channels timing out after `timeout`.
+    pub(crate) fn with_timeout<P: AsRef<Path>>(socket_path: P, timeout: Duration) -> Result<Self> {
+        let socket_path = socket_path.as_ref().to_path_buf();
+        let control = UnixStream::connect(&socket_path).map_err(Error::Connect)?;
+        control
+            .set_read_timeout(Some(timeout))
+            .and_then(|_| control.set_write_timeout(Some(timeout)))
+            .map_err(Error::ControlChannel)?;
+        let (data, emulator_data) = UnixStream::pair().map_err(Error::DataChannel)?;
+        data.set_read_timeout(Some(timeout))
+            .and_then(|_| data.set_write_timeout(Some(timeout)))
+            .map_err(Error::DataChannel)?;
+        let mut emulator = Emulator {
+            socket_path,
+            control,
+            data,
+            desynchronized: false,
+        };
+
+        let response = emulator.control_command(CMD_GET_CAPABILITY, &[], 8)?;
+        let mut capabilities = [0u8; 8];
+        capabilities.copy_from_slice(&response);
+        let capabilities = u64::from_be_bytes(capabilities);
+        if capabilities & REQUIRED_CAPABILITIES != REQUIRED_CAPABILITIES {
+            return Err(Error::MissingCapabilities(capabilities));
+        }
+
+        // The emulator gets its own duplicate of the descriptor, so our end can be dropped.
+        send_with_fd(
+            &emulator.control,
+            &CMD_SET_DATAFD.to_be_bytes(),
+            emulator_data.as_raw_fd(),
+        )
+        .map_err(Error::ControlChannel)?;
+        let mut result = [0u8; 4];
+        emulator
+            .control
+            .read_exact(&mut result)
+            .map_err(Error::ControlChannel)?;
+        check_result(CMD_SET_DATAFD, &result)?;
+
+        Ok(emulator)
+    }
+
+    /// Returns the path of the control socket of the emulator.
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Starts the TPM, dropping its volatile state if `delete_volatile` is set.
+    pub fn init(&mut self, delete_volatile: bool) -> Result<()> {
+        let flags = if delete_volatile {
+            PTM_INIT_FLAG_DELETE_VOLATILE
+        } else {
+            0
+        };
+        let result = self.control_command(CMD_INIT, &flags.to_be_bytes(), 4)?;
+        check_result(CMD_INIT, &result)
+    }
+
+    /// Stops the TPM, which is required before getting or setting its state.
+    pub fn stop(&mut self) -> Result<()> {
+        let result = self.control_command(CMD_STOP, &[], 4)?;
+        check_result(CMD_STOP, &result)
+    }
+
+    /// Sends a TPM command to the emulator and returns its response.
+    pub fn execute(&mut self, command: &[u8]) -> Result<Vec<u8>> {
+        self.check_synchronized()?;
+        let result = self.exchange_data(command);
+        self.desynchronized = result.is_err();
+        result
+    }
+
+    /// Gets the state blobs of the stopped TPM.
+    pub fn get_state(&mut self) -> Result<Vec<StateBlob>> {
+        let mut blobs = Vec::with_capacity(BLOB_TYPES.len());
+        for &blob_type in BLOB_TYPES.iter() {
+            let mut request = Vec::with_capacity(12);
+            request.extend_from_slice(&PTM_STATE_FLAG_DECRYPTED.to_be_bytes());
+            request.extend_from_slice(&blob_type.to_be_bytes());
+            // Offset of the blob, which has to be 0 on a socket.
+            request.extend_from_slice(&0u32.to_be_bytes());
+            // The response holds the result, the state flags, the total length and the length of
+            // the blob, followed by the whole blob.
+            let response = self.control_command(CMD_GET_STATEBLOB, &request, 16)?;
+            check_result(CMD_GET_STATEBLOB, &response)?;
+            let flags = u32::from_be_bytes([response[4], response[5], response[6], response[7]]);
+            let length = u32::from_be_bytes([response[8], response[9], response[10], response[11]]);
+            let length = length as usize;
+            if length > STATE_BLOB_MAX {
+                self.desynchronized = true;
+                return Err(Error::InvalidStateBlobSize(length));
+            }
+
+            let mut data = vec![0u8; length];
+            self.control.read_exact(&mut data).map_err(|e| {
+                self.desynchronized = true;
+                Error::ControlChannel(e)
+            })?;
+            blobs.push(StateBlob {
+                blob_type,
+                encrypted: flags & PTM_STATE_FLAG_ENCRYPTED != 0,
+                data,
+            });
+        }
+
+        Ok(blobs)
+    }
+
+    /// Sets the state blobs of the stopped TPM.
+    pub fn set_state(&mut self, blobs: &[StateBlob]) -> Result<()> {
+        for blob in blobs {
+            let flags = if blob.encrypted {
+                PTM_STATE_FLAG_ENCRYPTED
+            } else {
+                0
+            };
+            let mut request = Vec::with_capacity(12 + blob.data.len());
+            request.extend_from_slice(&flags.to_be_bytes());
+            request.extend_from_slice(&blob.blob_type.to_be_bytes());
+            request.extend_from_slice(&(blob.data.len() as u32).to_be_bytes());
+            request.extend_from_slice(&blob.data);
+            let result = self.control_command(CMD_SET_STATEBLOB, &request, 4)?;
+            check_result(CMD_SET_STATEBLOB, &result)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a control command along with its `payload` and reads `response_len` bytes of its
+    /// response.
+    fn control_command(
+        &mut self,
+        command: u32,
+        payload: &[u8],
+        response_len: usize,
+    ) -> Result<Vec<u8>> {
+        self.check_synchronized()?;
+        let mut request = Vec::with_capacity(4 + payload.len());
+        request.extend_from_slice(&command.to_be_bytes());
+        request.extend_from_slice(payload);
+
+        let mut response = vec![0u8; response_len];
+        let result = self
+            .control
+            .write_all(&request)
+            .and_then(|_| self.control.read_exact(&mut response));
+        self.desynchronized = result.is_err();
+        result.map_err(Error::ControlChannel)?;
+        Ok(response)
+    }
+
+    /// Sends `command` on the data channel and reads its response.
+    fn exchange_data(&mut self, command: &[u8]) -> Result<Vec<u8>> {
+        self.data.write_all(command).map_err(Error::DataChannel)?;
+
+        let mut response = vec![0u8; TPM_HEADER_SIZE];
+        self.data
+            .read_exact(&mut response)
+            .map_err(Error::DataChannel)?;
+        let size = u32::from_be_bytes([response[2], response[3], response[4], response[5]]);
+        let size = size as usize;
+        if size < TPM_HEADER_SIZE || size > TPM_BUFFER_MAX {
+            return Err(Error::InvalidResponseSize(size));
+        }
+        response.resize(size, 0);
+        self.data
+            .read_exact(&mut response[TPM_HEADER_SIZE..])
+            .map_err(Error::DataChannel)?;
+
+        Ok(response)
+    }
+
+    /// Fails once an exchange with the emulator was cut short, as its next answers could belong
+    /// to the previous requests.
+    fn check_synchronized(&self) -> Result<()> {
+        if self.desynchronized {
+            return Err(Error::Desynchronized);
+        }
+        Ok(())
+    }
+}
+
+/// Checks the result code at the start of the response to a control command.
+fn check_result(command: u32, response: &[u8]) -> Result<()> {
+    let result = u32::from_be_bytes([response[0], response[1], response[2], response[3]]);
+    if result != 0 {
+        return Err(Error::ControlCommand(command, result));
+    }
+    Ok(())
+}
+
+/// Sends `data` on `socket` along with the file descriptor `fd`.
+fn send_with_fd(socket: &UnixStream, data: &[u8], fd: RawFd) -> io::Result<()> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    // Safe because `CMSG_SPACE` only computes a size.
+    let cmsg_space = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize;
+    // A buffer of u64 keeps the control message header aligned.
+    let mut cmsg_buffer = vec![0u64; (cmsg_space + 7) / 8];
+
+    // Safe because the zeroed `msghdr` is a valid value, which is then filled in with pointers
+    // to buffers outliving the `sendmsg` call.
+    let ret = unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buffer.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = cmsg_space as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+
+        libc::sendmsg(socket.as_raw_fd(), &msg, 0)
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if ret as usize != data.len() {
+        return Err(io::Error::from(io::ErrorKind::WriteZero));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tpm::test_utils::{StandInEmulator, UNANSWERED_COMMAND};
+
+    #[test]
+    fn test_emulator() {
+        let stand_in = StandInEmulator::new();
+        let mut emulator = Emulator::new(stand_in.socket_path()).unwrap();
+        assert_eq!(emulator.socket_path(), stand_in.socket_path());
+        emulator.init(true).unwrap();
+
+        // The stand-in emulator answers with the command code of the request.
+        let command = [0x80, 0x01, 0, 0, 0, 0x0c, 0, 0, 0x01, 0x44, 0, 0];
+        let response = emulator.execute(&command).unwrap();
+        assert_eq!(
+            response,
+            [0x80, 0x01, 0, 0, 0, 0x0e, 0, 0, 0, 0, 0, 0, 0x01, 0x44]
+        );
+
+        emulator.stop().unwrap();
+        let blobs = emulator.get_state().unwrap();
+        assert_eq!(blobs.len(), BLOB_TYPES.len());
+        assert_eq!(blobs[0].data, b"permanent".to_vec());
+        assert!(!blobs[0].encrypted);
+
+        let mut new_blobs = blobs.clone();
+        new_blobs[1].data = b"other volatile".to_vec();
+        emulator.set_state(&new_blobs).unwrap();
+        assert_eq!(emulator.get_state().unwrap(), new_blobs);
+    }
+
+    #[test]
+    fn test_emulator_errors() {
+        // Connecting to a missing socket.
+        let stand_in = StandInEmulator::new();
+        let path = stand_in.socket_path().with_extension("missing");
+        assert!(matches!(Emulator::new(&path), Err(Error::Connect(_))));
+
+        // An emulator missing required capabilities.
+        let stand_in = StandInEmulator::with_capabilities(PTM_CAP_INIT | PTM_CAP_SET_DATAFD);
+        assert!(matches!(
+            Emulator::new(stand_in.socket_path()),
+            Err(Error::MissingCapabilities(caps)) if caps == PTM_CAP_INIT | PTM_CAP_SET_DATAFD
+        ));
+
+        // A failing control command.
+        let stand_in = StandInEmulator::new();
+        let mut emulator = Emulator::new(stand_in.socket_path()).unwrap();
+        stand_in.fail_next_control_command();
+        assert!(matches!(
+            emulator.init(false),
+            Err(Error::ControlCommand(CMD_INIT, 1))
+        ));
+
+        // An invalid response size, after which the emulator is no longer used.
+        let command = [0x80, 0x01, 0, 0, 0, 0x0a, 0xff, 0xff, 0xff, 0xff];
+        assert!(matches!(
+            emulator.execute(&command),
+            Err(Error::InvalidResponseSize(0x1_0000))
+        ));
+        assert!(matches!(
+            emulator.execute(&command),
+            Err(Error::Desynchronized)
+        ));
+        assert!(matches!(emulator.stop(), Err(Error::Desynchronized)));
+
+        // An oversized state blob.
+        let stand_in = StandInEmulator::new();
+        let mut emulator = Emulator::new(stand_in.socket_path()).unwrap();
+        stand_in.set_blob(BLOB_TYPES[0], vec![0u8; STATE_BLOB_MAX + 1]);
+        assert!(matches!(
+            emulator.get_state(),
+            Err(Error::InvalidStateBlobSize(size)) if size == STATE_BLOB_MAX + 1
+        ));
+    }
+
+    #[test]
+    fn test_emulator_timeout() {
+        let stand_in = StandInEmulator::new();
+        let mut emulator =
+            Emulator::with_timeout(stand_in.socket_path(), Duration::from_millis(100)).unwrap();
+
+        let mut command = [0x80, 0x01, 0, 0, 0, 0x0a, 0, 0, 0, 0];
+        command[6..].copy_from_slice(&UNANSWERED_COMMAND.to_be_bytes());
+        match emulator.execute(&command) {
+            Err(Error::DataChannel(e)) => assert!(matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            )),
+            _ => panic!("expected a data channel timeout"),
+        }
+        assert!(matches!(
+            emulator.execute(&command),
+            Err(Error::Desynchronized)
+        ));
+    }
+}
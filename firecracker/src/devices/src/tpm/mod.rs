@@ -0,0 +1,66 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Emulates a TPM 2.0 device, forwarding the commands of the guest to a software TPM.
+
+mod emulator;
+pub mod persist;
+pub mod test_utils;
+mod tis;
+
+use std::{fmt, io, result};
+
+pub use self::emulator::{Emulator, StateBlob};
+pub use self::tis::Tpm;
+
+/// Errors of the TPM device.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to connect to the control socket of the emulator.
+    Connect(io::Error),
+    /// Failed to communicate through the control channel of the emulator.
+    ControlChannel(io::Error),
+    /// A control command failed, with the given result code.
+    ControlCommand(u32, u32),
+    /// Failed to communicate through the data channel of the emulator.
+    DataChannel(io::Error),
+    /// A previous exchange with the emulator was cut short.
+    Desynchronized,
+    /// The emulator sent a response with an invalid size.
+    InvalidResponseSize(usize),
+    /// The emulator sent a state blob with an invalid size.
+    InvalidStateBlobSize(usize),
+    /// The emulator lacks capabilities required by the device.
+    MissingCapabilities(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+
+        match self {
+            Connect(e) => write!(f, "Cannot connect to the TPM emulator: {}", e),
+            ControlChannel(e) => write!(f, "TPM emulator control channel error: {}", e),
+            ControlCommand(command, result) => write!(
+                f,
+                "TPM emulator control command {} failed with result {:#x}",
+                command, result
+            ),
+            DataChannel(e) => write!(f, "TPM emulator data channel error: {}", e),
+            Desynchronized => write!(f, "The TPM emulator is out of step after a failed exchange"),
+            InvalidResponseSize(size) => {
+                write!(f, "Invalid TPM response size from the emulator: {}", size)
+            }
+            InvalidStateBlobSize(size) => {
+                write!(f, "Invalid TPM state blob size from the emulator: {}", size)
+            }
+            MissingCapabilities(caps) => write!(
+                f,
+                "The TPM emulator lacks required capabilities, it reports {:#x}",
+                caps
+            ),
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
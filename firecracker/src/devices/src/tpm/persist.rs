@@ -0,0 +1,111 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring the TPM device.
+
+use snapshot::Persist;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use super::tis::TisState;
+use super::{Emulator, Error, StateBlob, Tpm};
+
+/// The TPM device serializable state.
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct TpmState {
+    /// Path of the control socket of the emulator.
+    pub socket_path: String,
+    locality_active: bool,
+    tis_state: TisState,
+    int_enable: u32,
+    int_vector: u8,
+    command: Vec<u8>,
+    response: Vec<u8>,
+    response_offset: usize,
+    /// State blobs of the emulator.
+    pub emulator_state: Vec<StateBlob>,
+}
+
+impl Persist<'_> for Tpm {
+    type State = TpmState;
+    type ConstructorArgs = ();
+    type Error = Error;
+
+    fn save(&self) -> Self::State {
+        TpmState {
+            socket_path: self.emulator.socket_path().to_string_lossy().into_owned(),
+            locality_active: self.locality_active,
+            tis_state: self.state,
+            int_enable: self.int_enable,
+            int_vector: self.int_vector,
+            command: self.command.clone(),
+            response: self.response.clone(),
+            response_offset: self.response_offset,
+            emulator_state: self.emulator_state.clone(),
+        }
+    }
+
+    fn restore(_: Self::ConstructorArgs, state: &Self::State) -> Result<Self, Self::Error> {
+        // The emulator listening on the socket is expected to be a fresh instance, which gets
+        // the state of the one the snapshot was taken with.
+        let mut emulator = Emulator::new(&state.socket_path)?;
+        if !state.emulator_state.is_empty() {
+            emulator.stop()?;
+            emulator.set_state(&state.emulator_state)?;
+        }
+        emulator.init(false)?;
+
+        let mut tpm = Tpm::with_emulator(emulator);
+        tpm.locality_active = state.locality_active;
+        tpm.state = state.tis_state;
+        tpm.int_enable = state.int_enable;
+        tpm.int_vector = state.int_vector;
+        tpm.command = state.command.clone();
+        tpm.response = state.response.clone();
+        tpm.response_offset = state.response_offset.min(state.response.len());
+        Ok(tpm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tpm::test_utils::StandInEmulator;
+    use crate::BusDevice;
+
+    #[test]
+    fn test_persistence() {
+        let stand_in = StandInEmulator::new();
+        let mut tpm = Tpm::new(Emulator::new(stand_in.socket_path()).unwrap()).unwrap();
+        // Request the locality and get the TPM ready.
+        tpm.write(0x00, &[0x02]);
+        tpm.write(0x18, &[0x40]);
+        tpm.write(0x24, &[0x80, 0x01, 0, 0]);
+        tpm.fetch_emulator_state().unwrap();
+
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+        tpm.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // Restore the device with another emulator, which gets the state of the first one.
+        let new_stand_in = StandInEmulator::new();
+        let mut state = TpmState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap();
+        assert_eq!(state.socket_path, stand_in.socket_path().to_str().unwrap());
+        assert_eq!(state.emulator_state, tpm.emulator_state);
+        state.socket_path = new_stand_in.socket_path().to_str().unwrap().to_string();
+        state.emulator_state[1].data = b"restored volatile".to_vec();
+        let restored_tpm = Tpm::restore((), &state).unwrap();
+
+        assert!(restored_tpm.locality_active);
+        assert_eq!(restored_tpm.state, TisState::Reception);
+        assert_eq!(restored_tpm.command, vec![0x80, 0x01, 0, 0]);
+        assert_eq!(new_stand_in.blob(2), b"restored volatile".to_vec());
+
+        // Restoring fails when the emulator is gone.
+        drop(new_stand_in);
+        assert!(matches!(Tpm::restore((), &state), Err(Error::Connect(_))));
+    }
+}
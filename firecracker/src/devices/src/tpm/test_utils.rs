@@ -0,0 +1,232 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A stand-in for `swtpm`, implementing the subset of its control protocol used by the TPM
+//! device and answering every TPM command with its own command code.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::emulator::*;
+use utils::tempfile::TempFile;
+
+/// Command code making the stand-in emulator answer with an oversized response.
+pub const OVERSIZED_RESPONSE_COMMAND: u32 = 0xffff_ffff;
+/// Command code the stand-in emulator never answers.
+pub const UNANSWERED_COMMAND: u32 = 0xffff_fffe;
+
+pub struct StandInEmulator {
+    socket_file: TempFile,
+    socket_path: PathBuf,
+    fail_next: Arc<AtomicBool>,
+    blobs: Arc<Mutex<HashMap<u32, Vec<u8>>>>,
+}
+
+impl StandInEmulator {
+    pub fn new() -> Self {
+        Self::with_capabilities(
+            PTM_CAP_INIT
+                | PTM_CAP_GET_STATEBLOB
+                | PTM_CAP_SET_STATEBLOB
+                | PTM_CAP_STOP
+                | PTM_CAP_SET_DATAFD,
+        )
+    }
+
+    pub fn with_capabilities(capabilities: u64) -> Self {
+        let mut socket_file = TempFile::new().unwrap();
+        socket_file.remove().unwrap();
+        let socket_path = socket_file.as_path().to_path_buf();
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let fail_next = Arc::new(AtomicBool::new(false));
+        let blobs = Arc::new(Mutex::new(
+            BLOB_TYPES
+                .iter()
+                .zip(["permanent", "volatile", "savestate"].iter())
+                .map(|(&blob_type, data)| (blob_type, data.as_bytes().to_vec()))
+                .collect::<HashMap<_, _>>(),
+        ));
+
+        let stand_in_fail_next = fail_next.clone();
+        let stand_in_blobs = blobs.clone();
+        thread::spawn(move || {
+            for control in listener.incoming() {
+                let fail_next = stand_in_fail_next.clone();
+                let blobs = stand_in_blobs.clone();
+                thread::spawn(move || {
+                    serve_control(control.unwrap(), capabilities, &fail_next, &blobs)
+                });
+            }
+        });
+
+        StandInEmulator {
+            socket_file,
+            socket_path,
+            fail_next,
+            blobs,
+        }
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Makes the next control command fail.
+    pub fn fail_next_control_command(&self) {
+        self.fail_next.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns the state blob of type `blob_type`.
+    pub fn blob(&self, blob_type: u32) -> Vec<u8> {
+        self.blobs.lock().unwrap()[&blob_type].clone()
+    }
+
+    /// Replaces the state blob of type `blob_type`.
+    pub fn set_blob(&self, blob_type: u32, data: Vec<u8>) {
+        self.blobs.lock().unwrap().insert(blob_type, data);
+    }
+}
+
+impl Drop for StandInEmulator {
+    fn drop(&mut self) {
+        let _ = self.socket_file.remove();
+    }
+}
+
+fn serve_control(
+    mut control: UnixStream,
+    capabilities: u64,
+    fail_next: &AtomicBool,
+    blobs: &Mutex<HashMap<u32, Vec<u8>>>,
+) -> io::Result<()> {
+    loop {
+        let mut command = [0u8; 4];
+        let (len, fd) = recv_with_fd(&control, &mut command)?;
+        if len == 0 {
+            return Ok(());
+        }
+        let command = u32::from_be_bytes(command);
+        let result: u32 = if fail_next.swap(false, Ordering::SeqCst) {
+            1
+        } else {
+            0
+        };
+
+        match command {
+            CMD_GET_CAPABILITY => control.write_all(&capabilities.to_be_bytes())?,
+            CMD_INIT => {
+                let mut flags = [0u8; 4];
+                control.read_exact(&mut flags)?;
+                control.write_all(&result.to_be_bytes())?;
+            }
+            CMD_STOP => control.write_all(&result.to_be_bytes())?,
+            CMD_SET_DATAFD => {
+                // Safe because the descriptor was just received and is owned by nobody else.
+                let data = unsafe { UnixStream::from_raw_fd(fd.unwrap()) };
+                thread::spawn(move || serve_data(data));
+                control.write_all(&result.to_be_bytes())?;
+            }
+            CMD_GET_STATEBLOB => {
+                let mut request = [0u8; 12];
+                control.read_exact(&mut request)?;
+                let blob_type =
+                    u32::from_be_bytes([request[4], request[5], request[6], request[7]]);
+                let blob = blobs.lock().unwrap()[&blob_type].clone();
+                let mut response = Vec::new();
+                response.extend_from_slice(&result.to_be_bytes());
+                response.extend_from_slice(&0u32.to_be_bytes());
+                response.extend_from_slice(&(blob.len() as u32).to_be_bytes());
+                response.extend_from_slice(&(blob.len() as u32).to_be_bytes());
+                response.extend_from_slice(&blob);
+                control.write_all(&response)?;
+            }
+            CMD_SET_STATEBLOB => {
+                let mut request = [0u8; 12];
+                control.read_exact(&mut request)?;
+                let blob_type =
+                    u32::from_be_bytes([request[4], request[5], request[6], request[7]]);
+                let len = u32::from_be_bytes([request[8], request[9], request[10], request[11]]);
+                let mut blob = vec![0u8; len as usize];
+                control.read_exact(&mut blob)?;
+                if result == 0 {
+                    blobs.lock().unwrap().insert(blob_type, blob);
+                }
+                control.write_all(&result.to_be_bytes())?;
+            }
+            _ => control.write_all(&1u32.to_be_bytes())?,
+        }
+    }
+}
+
+fn serve_data(mut data: UnixStream) -> io::Result<()> {
+    loop {
+        let mut command = vec![0u8; TPM_HEADER_SIZE];
+        data.read_exact(&mut command)?;
+        let size = u32::from_be_bytes([command[2], command[3], command[4], command[5]]) as usize;
+        command.resize(size, 0);
+        data.read_exact(&mut command[TPM_HEADER_SIZE..])?;
+
+        let code = u32::from_be_bytes([command[6], command[7], command[8], command[9]]);
+        if code == UNANSWERED_COMMAND {
+            continue;
+        }
+        let mut response = vec![0x80, 0x01];
+        if code == OVERSIZED_RESPONSE_COMMAND {
+            response.extend_from_slice(&0x1_0000u32.to_be_bytes());
+            response.extend_from_slice(&0u32.to_be_bytes());
+        } else {
+            response.extend_from_slice(&(TPM_HEADER_SIZE as u32 + 4).to_be_bytes());
+            response.extend_from_slice(&0u32.to_be_bytes());
+            response.extend_from_slice(&code.to_be_bytes());
+        }
+        data.write_all(&response)?;
+    }
+}
+
+/// Receives data from `socket` into `buf`, along with the file descriptor sent with it if any.
+fn recv_with_fd(socket: &UnixStream, buf: &mut [u8]) -> io::Result<(usize, Option<RawFd>)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // Safe because `CMSG_SPACE` only computes a size.
+    let cmsg_space = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize;
+    let mut cmsg_buffer = vec![0u64; (cmsg_space + 7) / 8];
+
+    // Safe because the zeroed `msghdr` is a valid value, which is then filled in with pointers
+    // to buffers outliving the `recvmsg` call, and the control message is only read if the
+    // kernel filled it in.
+    unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buffer.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = cmsg_space as _;
+
+        let ret = libc::recvmsg(socket.as_raw_fd(), &mut msg, 0);
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        let fd = if !cmsg.is_null()
+            && (*cmsg).cmsg_level == libc::SOL_SOCKET
+            && (*cmsg).cmsg_type == libc::SCM_RIGHTS
+        {
+            Some(std::ptr::read_unaligned(
+                libc::CMSG_DATA(cmsg) as *const RawFd
+            ))
+        } else {
+            None
+        };
+        Ok((ret as usize, fd))
+    }
+}
@@ -0,0 +1,464 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! TPM Interface Specification (TIS) frontend of the TPM device.
+//!
+//! Implements the FIFO interface of locality 0, as described by the TCG PC Client Platform TPM
+//! Profile specification, without interrupts: the guest driver polls the status register.
+
+use logger::{error, warn};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use super::emulator::{Emulator, StateBlob, TPM_BUFFER_MAX, TPM_HEADER_SIZE};
+use super::Result;
+use crate::bus::BusDevice;
+
+// Registers of locality 0.
+const TPM_ACCESS: u64 = 0x00;
+const TPM_INT_ENABLE: u64 = 0x08;
+const TPM_INT_VECTOR: u64 = 0x0c;
+const TPM_INT_STATUS: u64 = 0x10;
+const TPM_INTF_CAPABILITY: u64 = 0x14;
+const TPM_STS: u64 = 0x18;
+const TPM_DATA_FIFO: u64 = 0x24;
+const TPM_INTERFACE_ID: u64 = 0x30;
+const TPM_XDATA_FIFO: u64 = 0x80;
+const TPM_DID_VID: u64 = 0xf00;
+const TPM_RID: u64 = 0xf04;
+
+// Bits of the access register.
+const ACCESS_ESTABLISHMENT: u8 = 0x01;
+const ACCESS_REQUEST_USE: u8 = 0x02;
+const ACCESS_ACTIVE_LOCALITY: u8 = 0x20;
+const ACCESS_VALID: u8 = 0x80;
+
+// Bits of the status register.
+const STS_RESPONSE_RETRY: u32 = 0x02;
+const STS_EXPECT: u32 = 0x08;
+const STS_DATA_AVAIL: u32 = 0x10;
+const STS_GO: u32 = 0x20;
+const STS_COMMAND_READY: u32 = 0x40;
+const STS_VALID: u32 = 0x80;
+const STS_BURST_COUNT_SHIFT: u32 = 8;
+const STS_FAMILY_TPM2: u32 = 0x0400_0000;
+
+/// Number of bytes the guest may transfer through the FIFO before checking the status again.
+const BURST_COUNT: u32 = 64;
+/// Interface version 1.3 for TPM 2.0, with 64 bytes transfers.
+const INTF_CAPABILITY: u32 = 0x3000_0600;
+/// FIFO interface, as implemented by other hypervisors.
+const INTERFACE_ID: u32 = 0xffff_fff0;
+const DID_VID: u32 = 0x0001_1014;
+const RID: u32 = 0x01;
+
+/// Response to the commands the emulator failed to execute: `TPM_RC_FAILURE`.
+const FAILURE_RESPONSE: [u8; TPM_HEADER_SIZE] = [0x80, 0x01, 0, 0, 0, 0x0a, 0, 0, 0x01, 0x01];
+
+/// States of the TIS interface.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub enum TisState {
+    /// No command is being handled.
+    Idle,
+    /// The TPM is ready to receive a command.
+    Ready,
+    /// The TPM is receiving a command.
+    Reception,
+    /// The response to the last command is available.
+    Completion,
+}
+
+/// TPM device with a TIS interface, forwarding the commands of the guest to a software TPM.
+pub struct Tpm {
+    pub(crate) emulator: Emulator,
+    pub(crate) locality_active: bool,
+    pub(crate) state: TisState,
+    pub(crate) int_enable: u32,
+    pub(crate) int_vector: u8,
+    pub(crate) command: Vec<u8>,
+    pub(crate) response: Vec<u8>,
+    pub(crate) response_offset: usize,
+    pub(crate) emulator_state: Vec<StateBlob>,
+}
+
+impl Tpm {
+    /// Creates a TPM device backed by the emulator, which is started with a fresh volatile
+    /// state.
+    pub fn new(mut emulator: Emulator) -> Result<Self> {
+        emulator.init(true)?;
+        Ok(Self::with_emulator(emulator))
+    }
+
+    pub(crate) fn with_emulator(emulator: Emulator) -> Self {
+        Tpm {
+            emulator,
+            locality_active: false,
+            state: TisState::Idle,
+            int_enable: 0,
+            int_vector: 0,
+            command: Vec::new(),
+            response: Vec::new(),
+            response_offset: 0,
+            emulator_state: Vec::new(),
+        }
+    }
+
+    /// Returns the emulator backing the device.
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    /// Fetches the state of the emulator so that it gets saved along with the device.
+    ///
+    /// The emulator is stopped while its state is fetched, and then started again.
+    pub fn fetch_emulator_state(&mut self) -> Result<()> {
+        self.emulator.stop()?;
+        self.emulator_state = self.emulator.get_state()?;
+        self.emulator.init(false)
+    }
+
+    /// Returns whether the command being received is complete.
+    fn command_complete(&self) -> bool {
+        self.command.len() >= TPM_HEADER_SIZE && self.command.len() >= self.command_size()
+    }
+
+    /// Returns the size of the command being received, as found in its header.
+    fn command_size(&self) -> usize {
+        u32::from_be_bytes([
+            self.command[2],
+            self.command[3],
+            self.command[4],
+            self.command[5],
+        ]) as usize
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = STS_VALID | STS_FAMILY_TPM2;
+        match self.state {
+            TisState::Idle => (),
+            TisState::Ready => {
+                status |= STS_COMMAND_READY | STS_EXPECT | (BURST_COUNT << STS_BURST_COUNT_SHIFT)
+            }
+            TisState::Reception => {
+                if !self.command_complete() {
+                    status |= STS_EXPECT | (BURST_COUNT << STS_BURST_COUNT_SHIFT);
+                }
+            }
+            TisState::Completion => {
+                let remaining = (self.response.len() - self.response_offset) as u32;
+                if remaining > 0 {
+                    status |=
+                        STS_DATA_AVAIL | (remaining.min(BURST_COUNT) << STS_BURST_COUNT_SHIFT);
+                }
+            }
+        }
+        status
+    }
+
+    fn register(&self, register: u64) -> u32 {
+        match register {
+            TPM_ACCESS => {
+                let mut access = ACCESS_VALID | ACCESS_ESTABLISHMENT;
+                if self.locality_active {
+                    access |= ACCESS_ACTIVE_LOCALITY;
+                }
+                u32::from(access)
+            }
+            TPM_INT_ENABLE => self.int_enable,
+            TPM_INT_VECTOR => u32::from(self.int_vector),
+            TPM_INT_STATUS => 0,
+            TPM_INTF_CAPABILITY => INTF_CAPABILITY,
+            TPM_STS => self.status(),
+            TPM_INTERFACE_ID => INTERFACE_ID,
+            TPM_DID_VID => DID_VID,
+            TPM_RID => RID,
+            _ => 0xffff_ffff,
+        }
+    }
+
+    fn write_access(&mut self, value: u8) {
+        if value & ACCESS_REQUEST_USE != 0 {
+            self.locality_active = true;
+        }
+        if value & ACCESS_ACTIVE_LOCALITY != 0 {
+            // Relinquishing the locality aborts the command being handled.
+            self.locality_active = false;
+            self.reset();
+        }
+    }
+
+    fn write_status(&mut self, value: u32) {
+        if value & STS_COMMAND_READY != 0 {
+            self.reset();
+            self.state = TisState::Ready;
+        } else if value & STS_GO != 0 {
+            if self.state == TisState::Reception && self.command_complete() {
+                self.execute();
+            }
+        } else if value & STS_RESPONSE_RETRY != 0 && self.state == TisState::Completion {
+            self.response_offset = 0;
+        }
+    }
+
+    fn write_fifo(&mut self, data: &[u8]) {
+        if self.state == TisState::Ready {
+            self.state = TisState::Reception;
+        }
+        if self.state != TisState::Reception {
+            warn!("TPM: unexpected data written in {:?} state", self.state);
+            return;
+        }
+        for &byte in data {
+            if self.command_complete() || self.command.len() == TPM_BUFFER_MAX {
+                break;
+            }
+            self.command.push(byte);
+        }
+    }
+
+    fn read_fifo(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            *byte = match self.response.get(self.response_offset) {
+                Some(&value) if self.state == TisState::Completion => {
+                    self.response_offset += 1;
+                    value
+                }
+                _ => 0xff,
+            };
+        }
+    }
+
+    fn execute(&mut self) {
+        self.response = self.emulator.execute(&self.command).unwrap_or_else(|e| {
+            error!("TPM: failed to execute command: {}", e);
+            FAILURE_RESPONSE.to_vec()
+        });
+        self.response_offset = 0;
+        self.state = TisState::Completion;
+    }
+
+    fn reset(&mut self) {
+        self.state = TisState::Idle;
+        self.command.clear();
+        self.response.clear();
+        self.response_offset = 0;
+    }
+}
+
+/// Returns the register holding the byte at `offset`, along with the offset of the byte in it.
+fn register_at(offset: u64) -> (u64, u64) {
+    match offset {
+        TPM_INT_ENABLE..=0x0b
+        | TPM_INT_STATUS..=0x1b
+        | TPM_INTERFACE_ID..=0x33
+        | TPM_DID_VID..=0xf03 => (offset & !0x3, offset & 0x3),
+        _ => (offset, 0),
+    }
+}
+
+fn is_fifo(offset: u64) -> bool {
+    (TPM_DATA_FIFO..TPM_DATA_FIFO + 4).contains(&offset)
+        || (TPM_XDATA_FIFO..TPM_XDATA_FIFO + 4).contains(&offset)
+}
+
+impl BusDevice for Tpm {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        if data.is_empty() || data.len() > 4 {
+            return;
+        }
+        if is_fifo(offset) {
+            return self.read_fifo(data);
+        }
+
+        let (register, shift) = register_at(offset);
+        let value = self.register(register) >> (shift * 8);
+        let bytes = value.to_le_bytes();
+        data.copy_from_slice(&bytes[..data.len()]);
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if data.is_empty() || data.len() > 4 {
+            return;
+        }
+        if is_fifo(offset) {
+            return self.write_fifo(data);
+        }
+
+        let mut bytes = [0u8; 4];
+        bytes[..data.len()].copy_from_slice(data);
+        let value = u32::from_le_bytes(bytes);
+        match offset {
+            TPM_ACCESS => self.write_access(data[0]),
+            TPM_INT_ENABLE => self.int_enable = value,
+            TPM_INT_VECTOR => self.int_vector = data[0],
+            TPM_STS => self.write_status(value),
+            // Interrupts are never raised, so there is no status to clear.
+            TPM_INT_STATUS => (),
+            _ => warn!("TPM: unexpected write at offset {:#x}", offset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tpm::test_utils::{StandInEmulator, OVERSIZED_RESPONSE_COMMAND};
+
+    // A `TPM2_GetCapability` command.
+    const COMMAND: [u8; 22] = [
+        0x80, 0x01, 0, 0, 0, 0x16, 0, 0, 0x01, 0x7a, 0, 0, 0, 0x06, 0, 0, 0x01, 0x05, 0, 0, 0, 0x01,
+    ];
+
+    fn read_u8(tpm: &mut Tpm, offset: u64) -> u8 {
+        let mut data = [0u8; 1];
+        tpm.read(offset, &mut data);
+        data[0]
+    }
+
+    fn read_u32(tpm: &mut Tpm, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        tpm.read(offset, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    fn send_command(tpm: &mut Tpm, command: &[u8]) {
+        tpm.write(TPM_STS, &[STS_COMMAND_READY as u8]);
+        assert_ne!(read_u32(tpm, TPM_STS) & STS_COMMAND_READY, 0);
+        for chunk in command.chunks(BURST_COUNT as usize) {
+            assert_ne!(read_u32(tpm, TPM_STS) & STS_EXPECT, 0);
+            for byte in chunk {
+                tpm.write(TPM_DATA_FIFO, &[*byte]);
+            }
+        }
+        assert_eq!(read_u32(tpm, TPM_STS) & STS_EXPECT, 0);
+        tpm.write(TPM_STS, &[STS_GO as u8]);
+    }
+
+    fn read_response(tpm: &mut Tpm) -> Vec<u8> {
+        let mut response = Vec::new();
+        loop {
+            let status = read_u32(tpm, TPM_STS);
+            if status & STS_DATA_AVAIL == 0 {
+                break;
+            }
+            // Like Linux, read the burst count through a 16 bits access at offset 1.
+            let mut burst_count = [0u8; 2];
+            tpm.read(TPM_STS + 1, &mut burst_count);
+            let burst_count = u16::from_le_bytes(burst_count);
+            assert_eq!(
+                u32::from(burst_count),
+                (status >> STS_BURST_COUNT_SHIFT) & 0xffff
+            );
+            for _ in 0..burst_count {
+                response.push(read_u8(tpm, TPM_DATA_FIFO));
+            }
+        }
+        response
+    }
+
+    #[test]
+    fn test_registers() {
+        let stand_in = StandInEmulator::new();
+        let mut tpm = Tpm::new(Emulator::new(stand_in.socket_path()).unwrap()).unwrap();
+
+        assert_eq!(read_u8(&mut tpm, TPM_ACCESS), 0x81);
+        tpm.write(TPM_ACCESS, &[ACCESS_REQUEST_USE]);
+        assert_eq!(read_u8(&mut tpm, TPM_ACCESS), 0xa1);
+
+        assert_eq!(read_u32(&mut tpm, TPM_INTF_CAPABILITY), INTF_CAPABILITY);
+        assert_eq!(read_u32(&mut tpm, TPM_INTERFACE_ID), INTERFACE_ID);
+        assert_eq!(read_u32(&mut tpm, TPM_DID_VID), DID_VID);
+        assert_eq!(read_u8(&mut tpm, TPM_RID), RID as u8);
+        let mut vid = [0u8; 2];
+        tpm.read(TPM_DID_VID, &mut vid);
+        assert_eq!(vid, [0x14, 0x10]);
+
+        tpm.write(TPM_INT_ENABLE, &0x8000_0007u32.to_le_bytes());
+        assert_eq!(read_u32(&mut tpm, TPM_INT_ENABLE), 0x8000_0007);
+        tpm.write(TPM_INT_VECTOR, &[5]);
+        assert_eq!(read_u8(&mut tpm, TPM_INT_VECTOR), 5);
+        assert_eq!(read_u32(&mut tpm, TPM_INT_STATUS), 0);
+
+        // Idle state.
+        assert_eq!(read_u32(&mut tpm, TPM_STS), STS_VALID | STS_FAMILY_TPM2);
+        // Unknown registers.
+        assert_eq!(read_u32(&mut tpm, 0x40), 0xffff_ffff);
+        assert_eq!(read_u8(&mut tpm, TPM_DATA_FIFO), 0xff);
+        let mut data = [0u8; 8];
+        tpm.read(TPM_STS, &mut data);
+        assert_eq!(data, [0u8; 8]);
+
+        tpm.write(TPM_ACCESS, &[ACCESS_ACTIVE_LOCALITY]);
+        assert_eq!(read_u8(&mut tpm, TPM_ACCESS), 0x81);
+    }
+
+    #[test]
+    fn test_command() {
+        let stand_in = StandInEmulator::new();
+        let mut tpm = Tpm::new(Emulator::new(stand_in.socket_path()).unwrap()).unwrap();
+        tpm.write(TPM_ACCESS, &[ACCESS_REQUEST_USE]);
+
+        send_command(&mut tpm, &COMMAND);
+        assert_eq!(tpm.state, TisState::Completion);
+        let response = read_response(&mut tpm);
+        assert_eq!(
+            response,
+            [0x80, 0x01, 0, 0, 0, 0x0e, 0, 0, 0, 0, 0, 0, 0x01, 0x7a]
+        );
+        assert_eq!(read_u8(&mut tpm, TPM_DATA_FIFO), 0xff);
+
+        // The response can be read again.
+        tpm.write(TPM_STS, &[STS_RESPONSE_RETRY as u8]);
+        let mut data = [0u8; 4];
+        tpm.read(TPM_XDATA_FIFO, &mut data);
+        assert_eq!(data, [0x80, 0x01, 0, 0]);
+
+        // A command is ignored by tpmGo until it is complete, and the data written past its end
+        // is dropped.
+        tpm.write(TPM_STS, &[STS_COMMAND_READY as u8]);
+        tpm.write(TPM_DATA_FIFO, &COMMAND[..4]);
+        tpm.write(TPM_STS, &[STS_GO as u8]);
+        assert_eq!(tpm.state, TisState::Reception);
+        for chunk in COMMAND[4..].chunks(4) {
+            tpm.write(TPM_DATA_FIFO, chunk);
+        }
+        tpm.write(TPM_DATA_FIFO, &[0u8; 4]);
+        assert_eq!(tpm.command, COMMAND.to_vec());
+
+        // Aborting the command.
+        tpm.write(TPM_STS, &[STS_COMMAND_READY as u8]);
+        assert_eq!(tpm.state, TisState::Ready);
+        assert!(tpm.command.is_empty());
+
+        // Data is only accepted when the TPM is ready.
+        tpm.write(TPM_ACCESS, &[ACCESS_ACTIVE_LOCALITY]);
+        tpm.write(TPM_DATA_FIFO, &COMMAND[..4]);
+        assert_eq!(tpm.state, TisState::Idle);
+        assert!(tpm.command.is_empty());
+
+        // Commands the emulator fails to execute get a failure response.
+        let mut command = [0x80, 0x01, 0, 0, 0, 0x0a, 0, 0, 0, 0];
+        command[6..].copy_from_slice(&OVERSIZED_RESPONSE_COMMAND.to_be_bytes());
+        send_command(&mut tpm, &command);
+        assert_eq!(read_response(&mut tpm), FAILURE_RESPONSE.to_vec());
+    }
+
+    #[test]
+    fn test_fetch_emulator_state() {
+        let stand_in = StandInEmulator::new();
+        let mut tpm = Tpm::new(Emulator::new(stand_in.socket_path()).unwrap()).unwrap();
+        assert!(tpm.emulator_state.is_empty());
+
+        tpm.fetch_emulator_state().unwrap();
+        assert_eq!(tpm.emulator_state.len(), 3);
+        assert_eq!(tpm.emulator_state[2].data, b"savestate".to_vec());
+
+        // The device keeps working afterwards.
+        send_command(&mut tpm, &COMMAND);
+        assert_eq!(read_response(&mut tpm).len(), 14);
+
+        stand_in.fail_next_control_command();
+        assert!(tpm.fetch_emulator_state().is_err());
+    }
+}
@@ -5,6 +5,7 @@ use std::io;
 
 use super::{RemoveRegionError, MAX_PAGE_COMPACT_BUFFER};
 use logger::error;
+use vm_memory::mmap::hugetlbfs_page_size;
 use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
 /// This takes a vector of page frame numbers, and compacts them
@@ -79,6 +80,32 @@ pub(crate) fn remove_range(
             .get_host_address(guest_address)
             .map_err(|_| RemoveRegionError::AddressTranslation)?;
 
+        // Shared mappings keep the pages in the memory file backing them, where `MADV_DONTNEED`
+        // would not release them, so they are punched out of the file instead.
+        if region.flags() & libc::MAP_SHARED != 0 {
+            let (start, len) = match region
+                .file_offset()
+                .and_then(|file_offset| hugetlbfs_page_size(file_offset.file()))
+            {
+                // Huge pages can only be released as a whole.
+                Some(huge_page_size) => {
+                    let mask = huge_page_size as u64 - 1;
+                    let start = (phys_address as u64 + mask) & !mask;
+                    let end = (phys_address as u64 + range_len) & !mask;
+                    if end <= start {
+                        return Ok(());
+                    }
+                    (start, end - start)
+                }
+                None => (phys_address as u64, range_len),
+            };
+            let ret = unsafe { libc::madvise(start as *mut _, len as usize, libc::MADV_REMOVE) };
+            if ret < 0 {
+                return Err(RemoveRegionError::MadviseFail(io::Error::last_os_error()));
+            }
+            return Ok(());
+        }
+
         // Mmap a new anonymous region over the present one in order to create a hole.
         // This workaround is (only) needed after resuming from a snapshot because the guest memory
         // is mmaped from file as private and there is no `madvise` flag that works for this case.
@@ -227,6 +254,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_remove_range_on_shared_memory() {
+        use std::fs::File;
+        use std::io::{Read, Seek, SeekFrom};
+        use std::os::unix::io::FromRawFd;
+        use vm_memory::FileOffset;
+
+        let page_size: usize = 0x1000;
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_memfd_create,
+                b"test\0".as_ptr(),
+                libc::MFD_CLOEXEC,
+            )
+        };
+        assert!(fd >= 0);
+        let mut file = unsafe { File::from_raw_fd(fd as i32) };
+        file.set_len(2 * page_size as u64).unwrap();
+        let mem = GuestMemoryMmap::from_ranges_with_files_guarded(
+            &[(
+                GuestAddress(0),
+                2 * page_size,
+                Some(FileOffset::new(file.try_clone().unwrap(), 0)),
+            )],
+            false,
+        )
+        .unwrap();
+
+        // Fill the memory with ones.
+        let ones = vec![1u8; 2 * page_size];
+        mem.write(&ones[..], GuestAddress(0)).unwrap();
+
+        // Remove the first page.
+        assert!(remove_range(&mem, (GuestAddress(0), page_size as u64), false).is_ok());
+
+        // Check that the first page is zeroed, in the guest memory and in the file.
+        let mut actual_page = vec![0u8; page_size];
+        mem.read(&mut actual_page.as_mut_slice(), GuestAddress(0))
+            .unwrap();
+        assert_eq!(vec![0u8; page_size], actual_page);
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_exact(&mut actual_page).unwrap();
+        assert_eq!(vec![0u8; page_size], actual_page);
+        // Check that the second page still contains ones.
+        mem.read(
+            &mut actual_page.as_mut_slice(),
+            GuestAddress(page_size as u64),
+        )
+        .unwrap();
+        assert_eq!(vec![1u8; page_size], actual_page);
+
+        // Shared memory is not remapped after restoring a snapshot.
+        assert!(remove_range(
+            &mem,
+            (GuestAddress(page_size as u64), page_size as u64),
+            true
+        )
+        .is_ok());
+        mem.read(
+            &mut actual_page.as_mut_slice(),
+            GuestAddress(page_size as u64),
+        )
+        .unwrap();
+        assert_eq!(vec![0u8; page_size], actual_page);
+
+        // Madvise fail: the guest address is not aligned to the page size.
+        assert_match!(
+            remove_range(&mem, (GuestAddress(0x20), page_size as u64), false).unwrap_err(),
+            RemoveRegionError::MadviseFail(_)
+        );
+    }
+
     #[test]
     fn test_remove_range_on_restored() {
         let page_size: usize = 0x1000;
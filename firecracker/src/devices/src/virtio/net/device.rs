@@ -5,6 +5,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+use crate::virtio::net::dhcp::DhcpServer;
 use crate::virtio::net::tap::Tap;
 #[cfg(test)]
 use crate::virtio::net::test_utils::Mocks;
@@ -17,16 +18,20 @@ use crate::virtio::{
 use crate::{report_net_event_fail, Error as DeviceError};
 //Removed by Mihai
 // use dumbo::pdu::ethernet::EthernetFrame;
+use ::timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use libc::EAGAIN;
 use logger::{error, warn, IncMetric, METRICS};
-use mmds::ns::MmdsNetworkStack;
+use mmds::dns::DnsResponder;
+use mmds::ns::{MmdsNetworkStack, WAITING_REQUESTS_POLL_INTERVAL_MS};
 use rate_limiter::{BucketUpdate, RateLimiter, TokenType};
 #[cfg(not(test))]
 use std::io;
 use std::io::{Read, Write};
+use std::net::Ipv4Addr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{cmp, mem, result};
 use utils::eventfd::EventFd;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
@@ -128,6 +133,11 @@ pub struct Net {
     pub(crate) activate_evt: EventFd,
 
     pub(crate) mmds_ns: Option<MmdsNetworkStack>,
+    pub(crate) dhcp_server: Option<DhcpServer>,
+    pub(crate) dns_responder: Option<DnsResponder>,
+    // Fires while the MMDS holds requests back, so they are answered in time even if the guest
+    // sends nothing else.
+    pub(crate) mmds_watch_timer: TimerFd,
 
     #[cfg(test)]
     pub(crate) mocks: Mocks,
@@ -181,6 +191,9 @@ impl Net {
 
         let queues = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
 
+        let mmds_watch_timer =
+            TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(Error::Timer)?;
+
         let mmds_ns = if allow_mmds_requests {
             Some(MmdsNetworkStack::new_with_defaults(None))
         } else {
@@ -220,6 +233,9 @@ impl Net {
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
             config_space,
             mmds_ns,
+            dhcp_server: None,
+            dns_responder: None,
+            mmds_watch_timer,
             guest_mac: guest_mac.copied(),
 
             #[cfg(test)]
@@ -243,6 +259,38 @@ impl Net {
         self.mmds_ns.as_mut()
     }
 
+    /// Serves the MMDS instance named `mmds_id`, or the default instance if no name is given, to
+    /// the guest at `ipv4_addr`, enabling the MMDS on this net device if needed.
+    pub fn configure_mmds_network_stack(&mut self, ipv4_addr: Ipv4Addr, mmds_id: Option<String>) {
+        let mmds_ns = self
+            .mmds_ns
+            .get_or_insert_with(|| MmdsNetworkStack::new_with_defaults(Some(ipv4_addr)));
+        mmds_ns.set_ipv4_addr(ipv4_addr);
+        mmds_ns.set_mmds_instance(mmds_id);
+    }
+
+    /// Provides a reference to the `DhcpServer`, if the device leases an address to the guest.
+    pub fn dhcp_server(&self) -> Option<&DhcpServer> {
+        self.dhcp_server.as_ref()
+    }
+
+    /// Lets the device answer the DHCP messages of the guest with `dhcp_server`, or stops
+    /// answering them if `None` is given.
+    pub fn set_dhcp_server(&mut self, dhcp_server: Option<DhcpServer>) {
+        self.dhcp_server = dhcp_server;
+    }
+
+    /// Provides a reference to the `DnsResponder`, if the device answers DNS queries.
+    pub fn dns_responder(&self) -> Option<&DnsResponder> {
+        self.dns_responder.as_ref()
+    }
+
+    /// Lets the device answer the DNS queries of the guest with `dns_responder`, or stops
+    /// answering them if `None` is given.
+    pub fn set_dns_responder(&mut self, dns_responder: Option<DnsResponder>) {
+        self.dns_responder = dns_responder;
+    }
+
     fn signal_used_queue(&mut self) -> result::Result<(), DeviceError> {
         self.interrupt_status
             .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
@@ -390,11 +438,14 @@ impl Net {
         false
     }
 
-    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
+    // Tries to detour the frame to the DHCP server, to the DNS responder or to MMDS and if none of
+    // them accepts it, sends it on the host TAP.
     //
     // `frame_buf` should contain the frame bytes in a slice of exact length.
-    // Returns whether MMDS consumed the frame.
+    // Returns whether the DHCP server, the DNS responder or MMDS consumed the frame.
     fn write_to_mmds_or_tap(
+        dhcp_server: Option<&mut DhcpServer>,
+        dns_responder: Option<&mut DnsResponder>,
         mmds_ns: Option<&mut MmdsNetworkStack>,
         rate_limiter: &mut RateLimiter,
         frame_buf: &[u8],
@@ -408,6 +459,24 @@ impl Net {
                 e
             })
         };
+        if let Some(server) = dhcp_server {
+            if server.detour_frame(checked_frame(frame_buf)?) {
+                // DHCP frames are not accounted by the rate limiter either.
+                rate_limiter.manual_replenish(frame_buf.len() as u64, TokenType::Bytes);
+                rate_limiter.manual_replenish(1, TokenType::Ops);
+
+                return Ok(true);
+            }
+        }
+        if let Some(responder) = dns_responder {
+            if responder.detour_frame(checked_frame(frame_buf)?) {
+                // Neither are DNS frames.
+                rate_limiter.manual_replenish(frame_buf.len() as u64, TokenType::Bytes);
+                rate_limiter.manual_replenish(1, TokenType::Ops);
+
+                return Ok(true);
+            }
+        }
         if let Some(ns) = mmds_ns {
             if ns.detour_frame(checked_frame(frame_buf)?) {
                 METRICS.mmds.rx_accepted.inc();
@@ -453,8 +522,25 @@ impl Net {
         Ok(false)
     }
 
-    // We currently prioritize packets from the MMDS over regular network packets.
+    // We currently prioritize DHCP replies, DNS replies and packets from the MMDS over regular
+    // network packets.
     fn read_from_mmds_or_tap(&mut self) -> Result<usize> {
+        if let Some(server) = self.dhcp_server.as_mut() {
+            if let Some(len) =
+                server.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx_frame_buf)?)
+            {
+                init_vnet_hdr(&mut self.rx_frame_buf);
+                return Ok(vnet_hdr_len() + len.get());
+            }
+        }
+        if let Some(responder) = self.dns_responder.as_mut() {
+            if let Some(len) =
+                responder.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx_frame_buf)?)
+            {
+                init_vnet_hdr(&mut self.rx_frame_buf);
+                return Ok(vnet_hdr_len() + len.get());
+            }
+        }
         if let Some(ns) = self.mmds_ns.as_mut() {
             if let Some(len) =
                 ns.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx_frame_buf)?)
@@ -501,11 +587,28 @@ impl Net {
             }
         }
 
+        self.arm_mmds_watch_timer();
+
         // At this point we processed as many Rx frames as possible.
         // We have to wake the guest if at least one descriptor chain has been used.
         self.signal_rx_used_queue()
     }
 
+    // Makes sure we come back to the MMDS network stack if it holds requests back, since they
+    // may have to be answered before anything else happens.
+    fn arm_mmds_watch_timer(&mut self) {
+        if self
+            .mmds_ns
+            .as_ref()
+            .map_or(false, MmdsNetworkStack::has_waiting_requests)
+        {
+            self.mmds_watch_timer.set_state(
+                TimerState::Oneshot(Duration::from_millis(WAITING_REQUESTS_POLL_INTERVAL_MS)),
+                SetTimeFlags::Default,
+            );
+        }
+    }
+
     // Process the deferred frame first, then continue reading from tap.
     fn handle_deferred_frame(&mut self) -> result::Result<(), DeviceError> {
         if self.rate_limited_rx_single_frame() {
@@ -616,6 +719,8 @@ impl Net {
             self.tx_channel.send(99).unwrap();
 
             let frame_consumed_by_mmds = Self::write_to_mmds_or_tap(
+                self.dhcp_server.as_mut(),
+                self.dns_responder.as_mut(),
                 self.mmds_ns.as_mut(),
                 &mut self.tx_rate_limiter,
                 &self.tx_frame_buf[..read_count],
@@ -765,6 +870,23 @@ impl Net {
         }
     }
 
+    pub fn process_mmds_watch_timer_event(&mut self) {
+        self.mmds_watch_timer.read();
+
+        if self.rx_rate_limiter.is_blocked() {
+            // Check again later, since the rate limiter event only resumes the receiving of
+            // deferred frames.
+            METRICS.net.rx_rate_limiter_throttled.inc();
+            self.arm_mmds_watch_timer();
+        } else if self.rx_deferred_frame {
+            self.handle_deferred_frame()
+                .unwrap_or_else(report_net_event_fail);
+        } else {
+            // Reading from the MMDS network stack answers the requests which no longer wait.
+            self.process_rx().unwrap_or_else(report_net_event_fail);
+        }
+    }
+
     /// Process device virtio queue(s).
     pub fn process_virtio_queues(&mut self) {
         let _ = self.resume_rx();
@@ -883,8 +1005,14 @@ pub mod tests {
         Net, VirtioDevice, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX, TYPE_NET, VIRTIO_MMIO_INT_VRING,
         VIRTQ_DESC_F_WRITE,
     };
+    use crate::virtio::net::dhcp::DhcpServerConfig;
     use dumbo::pdu::arp::{EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
-    use dumbo::pdu::ethernet::ETHERTYPE_ARP;
+    use dumbo::pdu::dhcp::{
+        DhcpMessage, CLIENT_PORT, FLAG_BROADCAST, MESSAGE_DISCOVER, MESSAGE_OFFER,
+        OPTION_MESSAGE_TYPE, SERVER_PORT,
+    };
+    use dumbo::pdu::ethernet::{EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+    use dumbo::{IPv4Packet, UdpDatagram, PROTOCOL_UDP};
     use logger::{IncMetric, METRICS};
     use rate_limiter::{RateLimiter, TokenBucket, TokenType};
     use virtio_gen::virtio_net::{
@@ -1452,6 +1580,20 @@ pub mod tests {
         (frame_buf, frame_len)
     }
 
+    #[test]
+    fn test_configure_mmds_network_stack() {
+        let mut net = default_net();
+        net.mmds_ns = None;
+
+        // The MMDS gets enabled on the device.
+        net.configure_mmds_network_stack(Ipv4Addr::new(169, 254, 170, 2), Some("tenant".into()));
+        assert_eq!(net.mmds_ns_mut().unwrap().mmds_id(), Some("tenant"));
+
+        // Back to the default instance.
+        net.configure_mmds_network_stack(Ipv4Addr::new(169, 254, 169, 254), None);
+        assert_eq!(net.mmds_ns_mut().unwrap().mmds_id(), None);
+    }
+
     #[test]
     fn test_mmds_detour_and_injection() {
         let mut net = default_net();
@@ -1469,6 +1611,8 @@ pub mod tests {
             &METRICS.mmds.rx_accepted,
             1,
             assert!(Net::write_to_mmds_or_tap(
+                net.dhcp_server.as_mut(),
+                net.dns_responder.as_mut(),
                 net.mmds_ns.as_mut(),
                 &mut net.tx_rate_limiter,
                 &frame_buf[..frame_len],
@@ -1486,6 +1630,117 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_dhcp_detour_and_injection() {
+        let mut net = default_net();
+        let guest_addr = Ipv4Addr::new(192, 168, 241, 2);
+        net.set_dhcp_server(Some(DhcpServer::new(DhcpServerConfig {
+            guest_addr,
+            prefix_len: 24,
+            gateway: None,
+            dns_servers: vec![],
+            mtu: None,
+            lease_time: 3600,
+        })));
+
+        let client_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let broadcast_mac = MacAddr::from_bytes_unchecked(&[0xff; MAC_ADDR_LEN]);
+
+        // Create a broadcast DHCPDISCOVER frame.
+        let mut message_buf = [0u8; 300];
+        let message_len =
+            DhcpMessage::write_request(message_buf.as_mut(), 1, FLAG_BROADCAST, client_mac)
+                .unwrap()
+                .with_options(&[(OPTION_MESSAGE_TYPE, &[MESSAGE_DISCOVER][..])])
+                .unwrap()
+                .len();
+        let mut frame_buf = [0u8; MAX_BUFFER_SIZE];
+        let frame_len = {
+            let mut eth = EthernetFrame::write_incomplete(
+                frame_bytes_from_buf_mut(&mut frame_buf).unwrap(),
+                broadcast_mac,
+                client_mac,
+                ETHERTYPE_IPV4,
+            )
+            .unwrap();
+            let packet_len = {
+                let mut packet = IPv4Packet::write_header(
+                    eth.inner_mut().payload_mut(),
+                    PROTOCOL_UDP,
+                    Ipv4Addr::UNSPECIFIED,
+                    Ipv4Addr::BROADCAST,
+                )
+                .unwrap();
+                let datagram_len = UdpDatagram::write_incomplete_datagram(
+                    packet.inner_mut().payload_mut(),
+                    &message_buf[..message_len],
+                )
+                .unwrap()
+                .finalize(CLIENT_PORT, SERVER_PORT, None)
+                .len();
+                packet
+                    .with_payload_len_unchecked(datagram_len as usize, true)
+                    .len()
+            };
+            vnet_hdr_len() + eth.with_payload_len_unchecked(packet_len).len()
+        };
+
+        // The frame is consumed by the DHCP server instead of reaching the TAP.
+        assert!(Net::write_to_mmds_or_tap(
+            net.dhcp_server.as_mut(),
+            net.dns_responder.as_mut(),
+            net.mmds_ns.as_mut(),
+            &mut net.tx_rate_limiter,
+            &frame_buf[..frame_len],
+            &mut net.tap,
+            Some(client_mac),
+        )
+        .unwrap());
+
+        // The offer is written to the guest before anything else.
+        let len = net.read_from_mmds_or_tap().unwrap();
+        let eth = EthernetFrame::from_bytes(&net.rx_frame_buf[vnet_hdr_len()..len]).unwrap();
+        assert_eq!(eth.dst_mac(), broadcast_mac);
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        let udp = UdpDatagram::from_bytes(ip.payload(), None).unwrap();
+        assert_eq!(udp.destination_port(), CLIENT_PORT);
+        let message = DhcpMessage::from_bytes(udp.payload()).unwrap();
+        assert_eq!(message.message_type(), Some(MESSAGE_OFFER));
+        assert_eq!(message.yiaddr(), guest_addr);
+    }
+
+    #[test]
+    fn test_dns_detour_and_injection() {
+        let mut net = default_net();
+        let dns_addr = DnsResponder::default_ipv4_addr();
+        net.set_dns_responder(Some(DnsResponder::new(dns_addr, 60, vec![]).unwrap()));
+
+        let src_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let src_ip = Ipv4Addr::new(10, 1, 2, 3);
+        let dst_mac = MacAddr::from_bytes_unchecked(&[0xff; MAC_ADDR_LEN]);
+        let (frame_buf, frame_len) = create_arp_request(src_mac, src_ip, dst_mac, dns_addr);
+
+        // The ARP request is consumed by the DNS responder instead of reaching the TAP.
+        assert!(Net::write_to_mmds_or_tap(
+            net.dhcp_server.as_mut(),
+            net.dns_responder.as_mut(),
+            net.mmds_ns.as_mut(),
+            &mut net.tx_rate_limiter,
+            &frame_buf[..frame_len],
+            &mut net.tap,
+            Some(src_mac),
+        )
+        .unwrap());
+
+        let len = net.read_from_mmds_or_tap().unwrap();
+        let eth = EthernetFrame::from_bytes(&net.rx_frame_buf[vnet_hdr_len()..len]).unwrap();
+        assert_eq!(eth.ethertype(), ETHERTYPE_ARP);
+        assert_eq!(eth.dst_mac(), src_mac);
+        let arp = EthIPv4ArpFrame::from_bytes_unchecked(eth.payload());
+        assert_eq!(arp.spa(), dns_addr);
+        assert_eq!(arp.tpa(), src_ip);
+    }
+
     #[test]
     fn test_mac_spoofing_detection() {
         let mut net = default_net();
@@ -1503,6 +1758,8 @@ pub mod tests {
             &METRICS.net.tx_spoofed_mac_count,
             0,
             Net::write_to_mmds_or_tap(
+                net.dhcp_server.as_mut(),
+                net.dns_responder.as_mut(),
                 net.mmds_ns.as_mut(),
                 &mut net.tx_rate_limiter,
                 &frame_buf[..frame_len],
@@ -1516,6 +1773,8 @@ pub mod tests {
             &METRICS.net.tx_spoofed_mac_count,
             1,
             Net::write_to_mmds_or_tap(
+                net.dhcp_server.as_mut(),
+                net.dns_responder.as_mut(),
                 net.mmds_ns.as_mut(),
                 &mut net.tx_rate_limiter,
                 &frame_buf[..frame_len],
@@ -0,0 +1,695 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal DHCPv4 server, which leases a single preconfigured address to the guest behind a
+//! net device.
+//!
+//! Similarly to the MMDS network stack, the server inspects the frames sent by the guest and
+//! consumes the ones heading to the DHCP server port, instead of letting them reach the TAP.
+
+use std::net::Ipv4Addr;
+use std::num::NonZeroUsize;
+use std::result::Result;
+
+use dumbo::pdu::dhcp::{
+    DhcpMessage, Error as DhcpError, CLIENT_PORT, FLAG_BROADCAST, MESSAGE_ACK, MESSAGE_DECLINE,
+    MESSAGE_DISCOVER, MESSAGE_NAK, MESSAGE_OFFER, MESSAGE_RELEASE, MESSAGE_REQUEST,
+    OPTION_DNS_SERVERS, OPTION_INTERFACE_MTU, OPTION_LEASE_TIME, OPTION_MESSAGE_TYPE,
+    OPTION_ROUTER, OPTION_SERVER_ID, OPTION_SUBNET_MASK, OP_BOOTREQUEST, SERVER_PORT,
+};
+use dumbo::pdu::ethernet::Error as EthernetFrameError;
+use dumbo::pdu::ipv4::Error as IPv4PacketError;
+use dumbo::pdu::udp::Error as UdpDatagramError;
+use dumbo::{EthernetFrame, IPv4Packet, UdpDatagram, ETHERTYPE_IPV4, PROTOCOL_UDP};
+use logger::{IncMetric, METRICS};
+use utils::net::mac::MacAddr;
+use utils::time::{get_time_ns, ClockType, NANOS_PER_SECOND};
+
+/// The maximum number of DNS servers which can be advertised to the guest.
+pub const MAX_DNS_SERVERS: usize = 16;
+
+// The Ethernet MAC address of the DHCP server.
+const SERVER_MAC_ADDR: &str = "06:01:23:45:67:02";
+// The server identifier used when no gateway is configured.
+const DEFAULT_SERVER_ID: [u8; 4] = [169, 254, 169, 253];
+// Large enough for the fixed part of a message and all the options written by the server.
+const MAX_MESSAGE_LEN: usize = 576;
+
+#[derive(Debug)]
+enum WriteReplyError {
+    Dhcp(DhcpError),
+    Ethernet(EthernetFrameError),
+    IPv4Packet(IPv4PacketError),
+    UdpDatagram(UdpDatagramError),
+}
+
+/// The network configuration leased to the guest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DhcpServerConfig {
+    /// The IPv4 address leased to the guest.
+    pub guest_addr: Ipv4Addr,
+    /// The length of the network prefix of the guest address.
+    pub prefix_len: u8,
+    /// The default gateway of the guest.
+    pub gateway: Option<Ipv4Addr>,
+    /// The DNS servers used by the guest.
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// The MTU of the guest interface.
+    pub mtu: Option<u16>,
+    /// The lease duration, in seconds.
+    pub lease_time: u32,
+}
+
+impl DhcpServerConfig {
+    /// Returns the subnet mask of the guest address.
+    pub fn subnet_mask(&self) -> Ipv4Addr {
+        match self.prefix_len {
+            0 => Ipv4Addr::UNSPECIFIED,
+            len => Ipv4Addr::from(std::u32::MAX << (32 - u32::from(len.min(32)))),
+        }
+    }
+
+    /// Returns the address identifying the server to the guest.
+    pub fn server_id(&self) -> Ipv4Addr {
+        self.gateway
+            .unwrap_or_else(|| Ipv4Addr::from(DEFAULT_SERVER_ID))
+    }
+}
+
+/// The address leased to a client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Lease {
+    pub(crate) client_mac: MacAddr,
+    // Monotonic time, in seconds, after which the lease can be handed to another client.
+    pub(crate) expires_at: u64,
+}
+
+// A reply waiting to be written to the guest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PendingReply {
+    message_type: u8,
+    xid: u32,
+    flags: u16,
+    client_mac: MacAddr,
+    ciaddr: Ipv4Addr,
+}
+
+// Returns the current monotonic time, in seconds.
+pub(crate) fn now_secs() -> u64 {
+    get_time_ns(ClockType::Monotonic) / NANOS_PER_SECOND
+}
+
+pub struct DhcpServer {
+    pub(crate) config: DhcpServerConfig,
+    // The Ethernet MAC address of the server.
+    mac_addr: MacAddr,
+    // The current lease, if the address has been handed to a client.
+    pub(crate) lease: Option<Lease>,
+    // The reply to the last message received from the guest, if not yet written.
+    pending_reply: Option<PendingReply>,
+}
+
+impl DhcpServer {
+    /// Creates a server leasing the network configuration described by `config`.
+    pub fn new(config: DhcpServerConfig) -> Self {
+        DhcpServer {
+            config,
+            // The unwrap() is safe because the address is valid.
+            mac_addr: MacAddr::parse_str(SERVER_MAC_ADDR).unwrap(),
+            lease: None,
+            pending_reply: None,
+        }
+    }
+
+    /// Returns the network configuration leased to the guest.
+    pub fn config(&self) -> &DhcpServerConfig {
+        &self.config
+    }
+
+    // Tells whether the address can be leased to the client with the given MAC address.
+    fn lease_available(&self, client_mac: MacAddr, now: u64) -> bool {
+        self.lease.map_or(true, |lease| {
+            lease.client_mac == client_mac || lease.expires_at <= now
+        })
+    }
+
+    /// Inspects the frame sent by the guest, and consumes it if it is a DHCP message heading to a
+    /// server. Returns whether the frame was consumed.
+    pub fn detour_frame(&mut self, src: &[u8]) -> bool {
+        let eth = match EthernetFrame::from_bytes(src) {
+            Ok(eth) if eth.ethertype() == ETHERTYPE_IPV4 => eth,
+            _ => return false,
+        };
+        // The checksums are not verified, in case the guest driver relies on checksum offloading.
+        let ip = match IPv4Packet::from_bytes(eth.payload(), false) {
+            Ok(ip) if ip.protocol() == PROTOCOL_UDP => ip,
+            _ => return false,
+        };
+        let udp = match UdpDatagram::from_bytes(ip.payload(), None) {
+            Ok(udp) if udp.destination_port() == SERVER_PORT => udp,
+            _ => return false,
+        };
+
+        METRICS.net.dhcp_rx_count.inc();
+        if !self.handle_message(udp.payload()) {
+            METRICS.net.dhcp_fails.inc();
+        }
+
+        true
+    }
+
+    // Handles a DHCP message from the guest, returning false if the message is invalid.
+    fn handle_message(&mut self, bytes: &[u8]) -> bool {
+        let message = match DhcpMessage::from_bytes(bytes) {
+            Ok(message) => message,
+            Err(_) => return false,
+        };
+        // Messages forwarded by relay agents are not expected on the guest link.
+        if message.op() != OP_BOOTREQUEST || message.giaddr() != Ipv4Addr::UNSPECIFIED {
+            return false;
+        }
+        let client_mac = match message.client_mac() {
+            Some(mac) => mac,
+            None => return false,
+        };
+
+        let now = now_secs();
+        let reply_type = match message.message_type() {
+            Some(MESSAGE_DISCOVER) => {
+                if !self.lease_available(client_mac, now) {
+                    return true;
+                }
+                MESSAGE_OFFER
+            }
+            Some(MESSAGE_REQUEST) => {
+                // The client picked the offer of another server.
+                if message
+                    .server_id()
+                    .map_or(false, |id| id != self.config.server_id())
+                {
+                    return true;
+                }
+
+                let requested_addr = message.requested_ip().unwrap_or_else(|| message.ciaddr());
+                if requested_addr == self.config.guest_addr && self.lease_available(client_mac, now)
+                {
+                    self.lease = Some(Lease {
+                        client_mac,
+                        expires_at: now + u64::from(self.config.lease_time),
+                    });
+                    MESSAGE_ACK
+                } else {
+                    MESSAGE_NAK
+                }
+            }
+            Some(MESSAGE_DECLINE) | Some(MESSAGE_RELEASE) => {
+                if self
+                    .lease
+                    .map_or(false, |lease| lease.client_mac == client_mac)
+                {
+                    self.lease = None;
+                }
+                return true;
+            }
+            _ => return false,
+        };
+
+        self.pending_reply = Some(PendingReply {
+            message_type: reply_type,
+            xid: message.xid(),
+            flags: message.flags(),
+            client_mac,
+            ciaddr: message.ciaddr(),
+        });
+
+        true
+    }
+
+    // Allows the DHCP server to write a frame to the specified buffer. Will return:
+    // - None, if the DHCP server has no frame to send at this point. The buffer can be used for
+    // something else by the device model.
+    // - Some(len), if a frame of the given length has been written to the specified buffer.
+    pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
+        let reply = self.pending_reply.take()?;
+
+        match self.write_reply(buf, &reply) {
+            Ok(len) => {
+                METRICS.net.dhcp_tx_count.inc();
+                NonZeroUsize::new(len)
+            }
+            Err(_) => {
+                METRICS.net.dhcp_fails.inc();
+                None
+            }
+        }
+    }
+
+    fn write_reply(&self, buf: &mut [u8], reply: &PendingReply) -> Result<usize, WriteReplyError> {
+        let mut message_buf = [0u8; MAX_MESSAGE_LEN];
+        let message_len = self
+            .write_message(&mut message_buf, reply)
+            .map_err(WriteReplyError::Dhcp)?;
+
+        let server_id = self.config.server_id();
+        // See https://tools.ietf.org/html/rfc2131#section-4.1 for how replies are addressed.
+        let (dst_mac, dst_addr) =
+            if reply.ciaddr != Ipv4Addr::UNSPECIFIED && reply.message_type != MESSAGE_NAK {
+                (reply.client_mac, reply.ciaddr)
+            } else if reply.message_type == MESSAGE_NAK || reply.flags & FLAG_BROADCAST != 0 {
+                (
+                    MacAddr::from_bytes_unchecked(&[0xff; 6]),
+                    Ipv4Addr::BROADCAST,
+                )
+            } else {
+                (reply.client_mac, self.config.guest_addr)
+            };
+
+        let mut eth = EthernetFrame::write_incomplete(buf, dst_mac, self.mac_addr, ETHERTYPE_IPV4)
+            .map_err(WriteReplyError::Ethernet)?;
+        let packet_len = {
+            let mut packet = IPv4Packet::write_header(
+                eth.inner_mut().payload_mut(),
+                PROTOCOL_UDP,
+                server_id,
+                dst_addr,
+            )
+            .map_err(WriteReplyError::IPv4Packet)?;
+            let datagram_len = UdpDatagram::write_incomplete_datagram(
+                packet.inner_mut().payload_mut(),
+                &message_buf[..message_len],
+            )
+            .map_err(WriteReplyError::UdpDatagram)?
+            .finalize(SERVER_PORT, CLIENT_PORT, Some((server_id, dst_addr)))
+            .len();
+            packet
+                .with_payload_len_unchecked(datagram_len as usize, true)
+                .len()
+        };
+
+        Ok(eth.with_payload_len_unchecked(packet_len).len())
+    }
+
+    fn write_message(&self, buf: &mut [u8], reply: &PendingReply) -> Result<usize, DhcpError> {
+        let config = &self.config;
+        let mut message =
+            DhcpMessage::write_reply(&mut buf[..], reply.xid, reply.flags, reply.client_mac)?;
+
+        let message_type = [reply.message_type];
+        let server_id = config.server_id().octets();
+        let lease_time = config.lease_time.to_be_bytes();
+        let subnet_mask = config.subnet_mask().octets();
+        let router = config.gateway.map(|addr| addr.octets());
+        let dns_servers: Vec<u8> = config
+            .dns_servers
+            .iter()
+            .flat_map(|addr| addr.octets().to_vec())
+            .collect();
+        let mtu = config.mtu.map(u16::to_be_bytes);
+
+        let mut options: Vec<(u8, &[u8])> = vec![
+            (OPTION_MESSAGE_TYPE, &message_type[..]),
+            (OPTION_SERVER_ID, &server_id[..]),
+        ];
+
+        if reply.message_type != MESSAGE_NAK {
+            message.inner_mut().set_yiaddr(config.guest_addr);
+            if reply.message_type == MESSAGE_ACK {
+                message.inner_mut().set_ciaddr(reply.ciaddr);
+            }
+
+            options.push((OPTION_LEASE_TIME, &lease_time[..]));
+            options.push((OPTION_SUBNET_MASK, &subnet_mask[..]));
+            if let Some(router) = router.as_ref() {
+                options.push((OPTION_ROUTER, &router[..]));
+            }
+            if !dns_servers.is_empty() {
+                options.push((OPTION_DNS_SERVERS, &dns_servers[..]));
+            }
+            if let Some(mtu) = mtu.as_ref() {
+                options.push((OPTION_INTERFACE_MTU, &mtu[..]));
+            }
+        }
+
+        Ok(message.with_options(&options)?.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dumbo::pdu::dhcp::{OPTION_REQUESTED_IP, OP_BOOTREPLY};
+
+    const CLIENT_MAC_STR: &str = "11:11:11:22:22:22";
+    const OTHER_CLIENT_MAC_STR: &str = "11:11:11:33:33:33";
+
+    fn default_config() -> DhcpServerConfig {
+        DhcpServerConfig {
+            guest_addr: Ipv4Addr::new(192, 168, 241, 2),
+            prefix_len: 24,
+            gateway: Some(Ipv4Addr::new(192, 168, 241, 1)),
+            dns_servers: vec![Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(1, 1, 1, 1)],
+            mtu: Some(1500),
+            lease_time: 3600,
+        }
+    }
+
+    // Writes a frame carrying a DHCP message from the client to `buf`, returning its length.
+    fn write_client_frame(
+        buf: &mut [u8],
+        client_mac: MacAddr,
+        message_type: u8,
+        flags: u16,
+        ciaddr: Ipv4Addr,
+        options: &[(u8, &[u8])],
+    ) -> usize {
+        let mut message_buf = [0u8; MAX_MESSAGE_LEN];
+        let message_len = {
+            let mut message =
+                DhcpMessage::write_request(message_buf.as_mut(), 0xabcd, flags, client_mac)
+                    .unwrap();
+            message.inner_mut().set_ciaddr(ciaddr);
+            let message_type = [message_type];
+            let mut all_options: Vec<(u8, &[u8])> = vec![(OPTION_MESSAGE_TYPE, &message_type[..])];
+            all_options.extend_from_slice(options);
+            message.with_options(&all_options).unwrap().len()
+        };
+
+        let mut eth = EthernetFrame::write_incomplete(
+            buf,
+            MacAddr::from_bytes_unchecked(&[0xff; 6]),
+            client_mac,
+            ETHERTYPE_IPV4,
+        )
+        .unwrap();
+        let packet_len = {
+            let mut packet = IPv4Packet::write_header(
+                eth.inner_mut().payload_mut(),
+                PROTOCOL_UDP,
+                ciaddr,
+                Ipv4Addr::BROADCAST,
+            )
+            .unwrap();
+            let datagram_len = UdpDatagram::write_incomplete_datagram(
+                packet.inner_mut().payload_mut(),
+                &message_buf[..message_len],
+            )
+            .unwrap()
+            .finalize(CLIENT_PORT, SERVER_PORT, None)
+            .len();
+            packet
+                .with_payload_len_unchecked(datagram_len as usize, true)
+                .len()
+        };
+        eth.with_payload_len_unchecked(packet_len).len()
+    }
+
+    // Writes a request for the configured address from `client_mac`, returning the frame length.
+    fn write_request_frame(buf: &mut [u8], client_mac: MacAddr) -> usize {
+        let requested_ip = default_config().guest_addr.octets();
+        write_client_frame(
+            buf,
+            client_mac,
+            MESSAGE_REQUEST,
+            0,
+            Ipv4Addr::UNSPECIFIED,
+            &[(OPTION_REQUESTED_IP, &requested_ip[..])],
+        )
+    }
+
+    // Reads the reply written by the server, checking the UDP and IP layers along the way.
+    fn check_reply<F: Fn(&DhcpMessage<&[u8]>)>(
+        buf: &[u8],
+        dst_mac: MacAddr,
+        dst_addr: Ipv4Addr,
+        check_message: F,
+    ) {
+        let eth = EthernetFrame::from_bytes(buf).unwrap();
+        assert_eq!(eth.ethertype(), ETHERTYPE_IPV4);
+        assert_eq!(eth.dst_mac(), dst_mac);
+        assert_eq!(eth.src_mac(), MacAddr::parse_str(SERVER_MAC_ADDR).unwrap());
+
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        assert_eq!(ip.protocol(), PROTOCOL_UDP);
+        assert_eq!(ip.source_address(), default_config().server_id());
+        assert_eq!(ip.destination_address(), dst_addr);
+
+        let udp =
+            UdpDatagram::from_bytes(ip.payload(), Some((ip.source_address(), dst_addr))).unwrap();
+        assert_eq!(udp.source_port(), SERVER_PORT);
+        assert_eq!(udp.destination_port(), CLIENT_PORT);
+
+        let message = DhcpMessage::from_bytes(udp.payload()).unwrap();
+        assert_eq!(message.op(), OP_BOOTREPLY);
+        assert_eq!(message.xid(), 0xabcd);
+        check_message(&message);
+    }
+
+    #[test]
+    fn test_config() {
+        let mut config = default_config();
+        assert_eq!(config.subnet_mask(), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(config.server_id(), Ipv4Addr::new(192, 168, 241, 1));
+
+        config.prefix_len = 0;
+        config.gateway = None;
+        assert_eq!(config.subnet_mask(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(config.server_id(), Ipv4Addr::from(DEFAULT_SERVER_ID));
+
+        config.prefix_len = 32;
+        assert_eq!(config.subnet_mask(), Ipv4Addr::BROADCAST);
+    }
+
+    #[test]
+    fn test_lease() {
+        let config = default_config();
+        let mut server = DhcpServer::new(config.clone());
+        let client_mac = MacAddr::parse_str(CLIENT_MAC_STR).unwrap();
+        let broadcast_mac = MacAddr::from_bytes_unchecked(&[0xff; 6]);
+        let mut buf = [0u8; 2000];
+
+        // Nothing to write yet.
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        // Frames which are not DHCP messages for the server are left alone.
+        let len = write_request_frame(&mut buf, client_mac);
+        assert!(!server.detour_frame(&buf[..10]));
+        // The UDP destination port follows the Ethernet and IPv4 headers, and the source port.
+        buf[36..38].copy_from_slice(&53u16.to_be_bytes());
+        assert!(!server.detour_frame(&buf[..len]));
+
+        // Discover the server, asking for a broadcast reply.
+        let len = write_client_frame(
+            &mut buf,
+            client_mac,
+            MESSAGE_DISCOVER,
+            FLAG_BROADCAST,
+            Ipv4Addr::UNSPECIFIED,
+            &[],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(&buf[..len], broadcast_mac, Ipv4Addr::BROADCAST, |message| {
+            assert_eq!(message.message_type(), Some(MESSAGE_OFFER));
+            assert_eq!(message.yiaddr(), config.guest_addr);
+            assert_eq!(message.server_id(), Some(config.server_id()));
+            assert_eq!(
+                message.option(OPTION_LEASE_TIME),
+                Some(&3600u32.to_be_bytes()[..])
+            );
+            assert_eq!(
+                message.option(OPTION_SUBNET_MASK),
+                Some(&[255, 255, 255, 0][..])
+            );
+            assert_eq!(message.option(OPTION_ROUTER), Some(&[192, 168, 241, 1][..]));
+            assert_eq!(
+                message.option(OPTION_DNS_SERVERS),
+                Some(&[8, 8, 8, 8, 1, 1, 1, 1][..])
+            );
+            assert_eq!(
+                message.option(OPTION_INTERFACE_MTU),
+                Some(&1500u16.to_be_bytes()[..])
+            );
+        });
+        // An offer does not reserve the address.
+        assert!(server.lease.is_none());
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        // Request the offered address.
+        let server_id = config.server_id().octets();
+        let requested_ip = config.guest_addr.octets();
+        let len = write_client_frame(
+            &mut buf,
+            client_mac,
+            MESSAGE_REQUEST,
+            0,
+            Ipv4Addr::UNSPECIFIED,
+            &[
+                (OPTION_SERVER_ID, &server_id[..]),
+                (OPTION_REQUESTED_IP, &requested_ip[..]),
+            ],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(&buf[..len], client_mac, config.guest_addr, |message| {
+            assert_eq!(message.message_type(), Some(MESSAGE_ACK));
+            assert_eq!(message.yiaddr(), config.guest_addr);
+        });
+        let lease = server.lease.unwrap();
+        assert_eq!(lease.client_mac, client_mac);
+        assert!(lease.expires_at >= now_secs() + 3599);
+
+        // Renew the lease, replying to the client address.
+        let len = write_client_frame(
+            &mut buf,
+            client_mac,
+            MESSAGE_REQUEST,
+            0,
+            config.guest_addr,
+            &[],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(&buf[..len], client_mac, config.guest_addr, |message| {
+            assert_eq!(message.message_type(), Some(MESSAGE_ACK));
+            assert_eq!(message.ciaddr(), config.guest_addr);
+        });
+
+        // Requests for another server are ignored.
+        let other_server_id = [10, 0, 0, 1];
+        let len = write_client_frame(
+            &mut buf,
+            client_mac,
+            MESSAGE_REQUEST,
+            0,
+            Ipv4Addr::UNSPECIFIED,
+            &[
+                (OPTION_SERVER_ID, &other_server_id[..]),
+                (OPTION_REQUESTED_IP, &requested_ip[..]),
+            ],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        // Another client can neither be offered nor assigned the leased address.
+        let other_client_mac = MacAddr::parse_str(OTHER_CLIENT_MAC_STR).unwrap();
+        let len = write_client_frame(
+            &mut buf,
+            other_client_mac,
+            MESSAGE_DISCOVER,
+            0,
+            Ipv4Addr::UNSPECIFIED,
+            &[],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        let len = write_client_frame(
+            &mut buf,
+            other_client_mac,
+            MESSAGE_REQUEST,
+            0,
+            Ipv4Addr::UNSPECIFIED,
+            &[(OPTION_REQUESTED_IP, &requested_ip[..])],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(&buf[..len], broadcast_mac, Ipv4Addr::BROADCAST, |message| {
+            assert_eq!(message.message_type(), Some(MESSAGE_NAK));
+            assert_eq!(message.yiaddr(), Ipv4Addr::UNSPECIFIED);
+            assert_eq!(message.option(OPTION_LEASE_TIME), None);
+        });
+        assert_eq!(server.lease.unwrap().client_mac, client_mac);
+
+        // Until the lease expires.
+        server.lease.as_mut().unwrap().expires_at = now_secs();
+        let len = write_request_frame(&mut buf, other_client_mac);
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(
+            &buf[..len],
+            other_client_mac,
+            config.guest_addr,
+            |message| {
+                assert_eq!(message.message_type(), Some(MESSAGE_ACK));
+            },
+        );
+        assert_eq!(server.lease.unwrap().client_mac, other_client_mac);
+
+        // Only the lease holder can release the address.
+        let len = write_client_frame(
+            &mut buf,
+            client_mac,
+            MESSAGE_RELEASE,
+            0,
+            config.guest_addr,
+            &[],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        assert!(server.lease.is_some());
+        let len = write_client_frame(
+            &mut buf,
+            other_client_mac,
+            MESSAGE_RELEASE,
+            0,
+            config.guest_addr,
+            &[],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        assert!(server.lease.is_none());
+        assert!(server.write_next_frame(&mut buf).is_none());
+
+        // Requests for another address are refused.
+        let other_ip = [192, 168, 241, 3];
+        let len = write_client_frame(
+            &mut buf,
+            client_mac,
+            MESSAGE_REQUEST,
+            0,
+            Ipv4Addr::UNSPECIFIED,
+            &[(OPTION_REQUESTED_IP, &other_ip[..])],
+        );
+        assert!(server.detour_frame(&buf[..len]));
+        let len = server.write_next_frame(&mut buf).unwrap().get();
+        check_reply(&buf[..len], broadcast_mac, Ipv4Addr::BROADCAST, |message| {
+            assert_eq!(message.message_type(), Some(MESSAGE_NAK));
+        });
+        assert!(server.lease.is_none());
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        let mut server = DhcpServer::new(default_config());
+        let client_mac = MacAddr::parse_str(CLIENT_MAC_STR).unwrap();
+        let mut buf = [0u8; 2000];
+
+        let fails_count = METRICS.net.dhcp_fails.count();
+
+        // An unsupported message type is consumed, but not answered.
+        let len = write_client_frame(&mut buf, client_mac, 8, 0, Ipv4Addr::UNSPECIFIED, &[]);
+        assert!(server.detour_frame(&buf[..len]));
+        assert!(server.write_next_frame(&mut buf).is_none());
+        assert_eq!(METRICS.net.dhcp_fails.count(), fails_count + 1);
+
+        // So is a truncated message.
+        let len = write_client_frame(
+            &mut buf,
+            client_mac,
+            MESSAGE_DISCOVER,
+            0,
+            Ipv4Addr::UNSPECIFIED,
+            &[],
+        );
+        let truncated_len = len - 100;
+        // Fix up the IP total length, so that the packet is still parsed.
+        let ip_total_len = (truncated_len - 14) as u16;
+        buf[16..18].copy_from_slice(&ip_total_len.to_be_bytes());
+        assert!(server.detour_frame(&buf[..truncated_len]));
+        assert!(server.write_next_frame(&mut buf).is_none());
+        assert_eq!(METRICS.net.dhcp_fails.count(), fails_count + 2);
+
+        // Non-IPv4 frames are not consumed.
+        buf[12..14].copy_from_slice(&0x86ddu16.to_be_bytes());
+        assert!(!server.detour_frame(&buf[..len]));
+    }
+}
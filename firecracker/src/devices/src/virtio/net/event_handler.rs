@@ -65,6 +65,7 @@ impl Subscriber for Net {
             let rx_rate_limiter_fd = self.rx_rate_limiter.as_raw_fd();
             let tx_rate_limiter_fd = self.tx_rate_limiter.as_raw_fd();
             let tap_fd = self.tap.as_raw_fd();
+            let mmds_watch_timer_fd = self.mmds_watch_timer.as_raw_fd();
             let activate_fd = self.activate_evt.as_raw_fd();
 
             // Looks better than C style if/else if/else.
@@ -74,6 +75,7 @@ impl Subscriber for Net {
                 _ if source == virtq_tx_ev_fd => self.process_tx_queue_event(),
                 _ if source == rx_rate_limiter_fd => self.process_rx_rate_limiter_event(),
                 _ if source == tx_rate_limiter_fd => self.process_tx_rate_limiter_event(),
+                _ if source == mmds_watch_timer_fd => self.process_mmds_watch_timer_event(),
                 _ if activate_fd == source => self.process_activate_event(evmgr),
                 _ => {
                     warn!("Net: Spurious event received: {:?}", source);
@@ -103,6 +105,7 @@ impl Subscriber for Net {
                     EventSet::IN | EventSet::EDGE_TRIGGERED,
                     self.tap.as_raw_fd() as u64,
                 ),
+                EpollEvent::new(EventSet::IN, self.mmds_watch_timer.as_raw_fd() as u64),
             ]
         } else {
             vec![EpollEvent::new(
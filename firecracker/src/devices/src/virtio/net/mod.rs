@@ -13,12 +13,14 @@ pub const RX_INDEX: usize = 0;
 pub const TX_INDEX: usize = 1;
 
 pub mod device;
+pub mod dhcp;
 pub mod event_handler;
 pub mod persist;
 mod tap;
 pub mod test_utils;
 
 pub use self::device::Net;
+pub use self::dhcp::{DhcpServer, DhcpServerConfig};
 pub use self::event_handler::*;
 pub use tap::Error as TapError;
 
@@ -34,6 +36,8 @@ pub enum Error {
     TapEnable(TapError),
     /// EventFd error.
     EventFd(io::Error),
+    /// Creating the MMDS watch timer failed.
+    Timer(io::Error),
     /// IO error.
     IO(io::Error),
     /// The VNET header is missing from the frame.
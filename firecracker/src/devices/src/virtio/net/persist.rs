@@ -4,18 +4,22 @@
 //! Defines the structures needed for saving/restoring net devices.
 
 use std::io;
+use std::net::Ipv4Addr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
-use mmds::{ns::MmdsNetworkStack, persist::MmdsNetworkStackState};
+use mmds::dns::{DnsResponder, Error as DnsError};
+use mmds::persist::{DnsResponderState, MmdsNetworkStackState};
+use mmds::{ns::MmdsNetworkStack, token::Error as TokenError};
 use rate_limiter::{persist::RateLimiterState, RateLimiter};
 use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::GuestMemoryMmap;
 
 use super::device::{ConfigSpace, Net};
+use super::dhcp::{now_secs, DhcpServer, DhcpServerConfig, Lease};
 use super::{NUM_QUEUES, QUEUE_SIZE};
 
 use crate::virtio::persist::{Error as VirtioStateError, VirtioDeviceState};
@@ -27,6 +31,75 @@ pub struct NetConfigSpaceState {
     guest_mac: [u8; MAC_ADDR_LEN],
 }
 
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct DhcpLeaseState {
+    client_mac: [u8; MAC_ADDR_LEN],
+    remaining_secs: u64,
+}
+
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct DhcpServerState {
+    guest_addr: u32,
+    prefix_len: u8,
+    gateway: Option<u32>,
+    dns_servers: Vec<u32>,
+    mtu: Option<u16>,
+    lease_time: u32,
+    lease: Option<DhcpLeaseState>,
+}
+
+impl Persist<'_> for DhcpServer {
+    type State = DhcpServerState;
+    type ConstructorArgs = ();
+    type Error = ();
+
+    fn save(&self) -> Self::State {
+        let config = &self.config;
+        // The monotonic clock is not preserved across snapshots, so we save the remaining time.
+        let now = now_secs();
+
+        DhcpServerState {
+            guest_addr: config.guest_addr.into(),
+            prefix_len: config.prefix_len,
+            gateway: config.gateway.map(u32::from),
+            dns_servers: config.dns_servers.iter().map(|&addr| addr.into()).collect(),
+            mtu: config.mtu,
+            lease_time: config.lease_time,
+            lease: self.lease.map(|lease| {
+                let mut client_mac = [0; MAC_ADDR_LEN];
+                client_mac.copy_from_slice(lease.client_mac.get_bytes());
+                DhcpLeaseState {
+                    client_mac,
+                    remaining_secs: lease.expires_at.saturating_sub(now),
+                }
+            }),
+        }
+    }
+
+    fn restore(
+        _: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        let mut server = DhcpServer::new(DhcpServerConfig {
+            guest_addr: Ipv4Addr::from(state.guest_addr),
+            prefix_len: state.prefix_len,
+            gateway: state.gateway.map(Ipv4Addr::from),
+            dns_servers: state.dns_servers.iter().map(|&addr| addr.into()).collect(),
+            mtu: state.mtu,
+            lease_time: state.lease_time,
+        });
+        let now = now_secs();
+        server.lease = state.lease.as_ref().map(|lease| Lease {
+            client_mac: MacAddr::from_bytes_unchecked(&lease.client_mac),
+            expires_at: now + lease.remaining_secs,
+        });
+
+        Ok(server)
+    }
+}
+
 #[derive(Clone, Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct NetState {
@@ -37,6 +110,34 @@ pub struct NetState {
     mmds_ns: Option<MmdsNetworkStackState>,
     config_space: NetConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    #[version(start = 2, ser_fn = "dhcp_server_ser")]
+    dhcp_server: Option<DhcpServerState>,
+    #[version(start = 2, ser_fn = "dns_responder_ser")]
+    dns_responder: Option<DnsResponderState>,
+}
+
+impl NetState {
+    fn dhcp_server_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        // The guest would lose its address on the next lease renewal.
+        if target_version < 2 && self.dhcp_server.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the DHCP server.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn dns_responder_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        // The guest would no longer resolve the internal names.
+        if target_version < 2 && self.dns_responder.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the DNS responder.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 pub struct NetConstructorArgs {
@@ -47,6 +148,8 @@ pub struct NetConstructorArgs {
 pub enum Error {
     CreateNet(super::Error),
     CreateRateLimiter(io::Error),
+    DnsResponder(DnsError),
+    MmdsNetworkStack(TokenError),
     VirtioState(VirtioStateError),
 }
 
@@ -66,6 +169,11 @@ impl Persist<'_> for Net {
                 guest_mac: self.config_space.guest_mac,
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            dhcp_server: self.dhcp_server.as_ref().map(|server| server.save()),
+            dns_responder: self
+                .dns_responder
+                .as_ref()
+                .map(|responder| responder.save()),
         }
     }
 
@@ -88,11 +196,26 @@ impl Persist<'_> for Net {
         )
         .map_err(Error::CreateNet)?;
 
-        // Safe to unwrap because MmdsNetworkStack::restore() cannot fail.
+        // MmdsNetworkStack::restore() can fail at setting up the session tokens.
         net.mmds_ns = state
             .mmds_ns
             .as_ref()
-            .map(|mmds_state| MmdsNetworkStack::restore((), &mmds_state).unwrap());
+            .map(|mmds_state| MmdsNetworkStack::restore((), &mmds_state))
+            .transpose()
+            .map_err(Error::MmdsNetworkStack)?;
+
+        // Safe to unwrap, `DhcpServer::restore` has no error case.
+        net.dhcp_server = state
+            .dhcp_server
+            .as_ref()
+            .map(|dhcp_state| DhcpServer::restore((), dhcp_state).unwrap());
+
+        net.dns_responder = state
+            .dns_responder
+            .as_ref()
+            .map(|dns_state| DnsResponder::restore((), dns_state))
+            .transpose()
+            .map_err(Error::DnsResponder)?;
 
         net.queues = state
             .virtio_state
@@ -175,6 +298,91 @@ mod tests {
             assert_eq!(restored_net.mmds_ns.is_some(), allow_mmds_requests);
             assert_eq!(restored_net.rx_rate_limiter, RateLimiter::default());
             assert_eq!(restored_net.tx_rate_limiter, RateLimiter::default());
+            assert!(restored_net.dhcp_server.is_none());
+            assert!(restored_net.dns_responder.is_none());
         }
     }
+
+    #[test]
+    fn test_persistence_of_dhcp_server() {
+        let guest_mem = default_guest_memory();
+        let mut net = default_net();
+        let config = DhcpServerConfig {
+            guest_addr: Ipv4Addr::new(192, 168, 241, 2),
+            prefix_len: 24,
+            gateway: Some(Ipv4Addr::new(192, 168, 241, 1)),
+            dns_servers: vec![Ipv4Addr::new(8, 8, 8, 8)],
+            mtu: Some(9001),
+            lease_time: 3600,
+        };
+        let client_mac = MacAddr::parse_str("11:11:11:22:22:22").unwrap();
+        let mut server = DhcpServer::new(config.clone());
+        server.lease = Some(Lease {
+            client_mac,
+            expires_at: now_secs() + 600,
+        });
+        net.set_dhcp_server(Some(server));
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+
+        // Older versions would silently stop leasing the address to the guest.
+        assert!(matches!(
+            net.save()
+                .serialize(&mut mem.as_mut_slice(), &version_map, 1),
+            Err(VersionizeError::Semantic(_))
+        ));
+
+        net.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs { mem: guest_mem },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+
+        let restored_server = restored_net.dhcp_server().unwrap();
+        assert_eq!(restored_server.config(), &config);
+        let lease = restored_server.lease.unwrap();
+        assert_eq!(lease.client_mac, client_mac);
+        // The remaining lease time is preserved.
+        assert!(lease.expires_at <= now_secs() + 600);
+        assert!(lease.expires_at + 5 >= now_secs() + 600);
+    }
+
+    #[test]
+    fn test_persistence_of_dns_responder() {
+        let guest_mem = default_guest_memory();
+        let mut net = default_net();
+        let dns_addr = Ipv4Addr::new(169, 254, 0, 53);
+        net.set_dns_responder(Some(DnsResponder::new(dns_addr, 60, vec![]).unwrap()));
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+
+        // Older versions would silently stop answering the DNS queries of the guest.
+        assert!(matches!(
+            net.save()
+                .serialize(&mut mem.as_mut_slice(), &version_map, 1),
+            Err(VersionizeError::Semantic(_))
+        ));
+
+        net.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_net = Net::restore(
+            NetConstructorArgs { mem: guest_mem },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored_net.dns_responder().unwrap().ipv4_addr(), dns_addr);
+    }
 }
@@ -0,0 +1,517 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains support for parsing and writing DHCPv4 messages, as carried by UDP datagrams between
+//! the [`SERVER_PORT`] and the [`CLIENT_PORT`].
+//!
+//! Only Ethernet hardware addresses are supported. A more detailed view of DHCP messages can be
+//! found [here].
+//!
+//! [`SERVER_PORT`]: constant.SERVER_PORT.html
+//! [`CLIENT_PORT`]: constant.CLIENT_PORT.html
+//! [here]: https://tools.ietf.org/html/rfc2131#section-2
+use std::net::Ipv4Addr;
+use std::result::Result;
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use super::Incomplete;
+
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+/// The UDP port DHCP servers listen on.
+pub const SERVER_PORT: u16 = 67;
+/// The UDP port DHCP clients listen on.
+pub const CLIENT_PORT: u16 = 68;
+
+/// The `op` value of messages sent by clients.
+pub const OP_BOOTREQUEST: u8 = 1;
+/// The `op` value of messages sent by servers.
+pub const OP_BOOTREPLY: u8 = 2;
+
+/// The hardware type of Ethernet addresses.
+pub const HTYPE_ETHERNET: u8 = 1;
+
+/// The flag asking the server to broadcast its replies.
+pub const FLAG_BROADCAST: u16 = 1 << 15;
+
+/// Subnet mask option code.
+pub const OPTION_SUBNET_MASK: u8 = 1;
+/// Router option code.
+pub const OPTION_ROUTER: u8 = 3;
+/// Domain name server option code.
+pub const OPTION_DNS_SERVERS: u8 = 6;
+/// Interface MTU option code.
+pub const OPTION_INTERFACE_MTU: u8 = 26;
+/// Requested IP address option code.
+pub const OPTION_REQUESTED_IP: u8 = 50;
+/// IP address lease time option code.
+pub const OPTION_LEASE_TIME: u8 = 51;
+/// DHCP message type option code.
+pub const OPTION_MESSAGE_TYPE: u8 = 53;
+/// Server identifier option code.
+pub const OPTION_SERVER_ID: u8 = 54;
+
+/// DHCPDISCOVER message type.
+pub const MESSAGE_DISCOVER: u8 = 1;
+/// DHCPOFFER message type.
+pub const MESSAGE_OFFER: u8 = 2;
+/// DHCPREQUEST message type.
+pub const MESSAGE_REQUEST: u8 = 3;
+/// DHCPDECLINE message type.
+pub const MESSAGE_DECLINE: u8 = 4;
+/// DHCPACK message type.
+pub const MESSAGE_ACK: u8 = 5;
+/// DHCPNAK message type.
+pub const MESSAGE_NAK: u8 = 6;
+/// DHCPRELEASE message type.
+pub const MESSAGE_RELEASE: u8 = 7;
+
+/// The minimum length of a message, inherited from BOOTP.
+pub const MIN_MESSAGE_LEN: usize = 300;
+
+const OP_OFFSET: usize = 0;
+const HTYPE_OFFSET: usize = 1;
+const HLEN_OFFSET: usize = 2;
+const XID_OFFSET: usize = 4;
+const FLAGS_OFFSET: usize = 10;
+const CIADDR_OFFSET: usize = 12;
+const YIADDR_OFFSET: usize = 16;
+const SIADDR_OFFSET: usize = 20;
+const GIADDR_OFFSET: usize = 24;
+const CHADDR_OFFSET: usize = 28;
+const MAGIC_COOKIE_OFFSET: usize = 236;
+const OPTIONS_OFFSET: usize = 240;
+
+const MAGIC_COOKIE: u32 = 0x6382_5363;
+const OPTION_PAD: u8 = 0;
+const OPTION_END: u8 = 255;
+
+/// Represents errors which may occur while parsing or writing a message.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The magic cookie preceding the options is invalid.
+    MagicCookie,
+    /// The options are not properly encoded.
+    Options,
+    /// The provided slice is too short to hold the message.
+    SliceTooShort,
+}
+
+/// Interprets the inner bytes as a DHCP message.
+pub struct DhcpMessage<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl<'a, T: NetworkBytes> DhcpMessage<'a, T> {
+    /// Interprets `bytes` as a DHCP message without any validity checks.
+    ///
+    /// # Panics
+    ///
+    ///  This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        DhcpMessage {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Attempts to interpret `bytes` as a DHCP message, checking the validity of its options.
+    pub fn from_bytes(bytes: T) -> Result<Self, Error> {
+        if bytes.len() < OPTIONS_OFFSET {
+            return Err(Error::SliceTooShort);
+        }
+
+        let message = DhcpMessage::from_bytes_unchecked(bytes);
+
+        if message.bytes.ntohl_unchecked(MAGIC_COOKIE_OFFSET) != MAGIC_COOKIE {
+            return Err(Error::MagicCookie);
+        }
+
+        // Makes sure the options can be walked through.
+        message.find_option(OPTION_END)?;
+
+        Ok(message)
+    }
+
+    /// Returns the `op` field of the message.
+    #[inline]
+    pub fn op(&self) -> u8 {
+        self.bytes[OP_OFFSET]
+    }
+
+    /// Returns the hardware address type of the message.
+    #[inline]
+    pub fn htype(&self) -> u8 {
+        self.bytes[HTYPE_OFFSET]
+    }
+
+    /// Returns the hardware address length of the message.
+    #[inline]
+    pub fn hlen(&self) -> u8 {
+        self.bytes[HLEN_OFFSET]
+    }
+
+    /// Returns the transaction ID of the message.
+    #[inline]
+    pub fn xid(&self) -> u32 {
+        self.bytes.ntohl_unchecked(XID_OFFSET)
+    }
+
+    /// Returns the flags of the message.
+    #[inline]
+    pub fn flags(&self) -> u16 {
+        self.bytes.ntohs_unchecked(FLAGS_OFFSET)
+    }
+
+    /// Returns the client IP address of the message.
+    #[inline]
+    pub fn ciaddr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes.ntohl_unchecked(CIADDR_OFFSET))
+    }
+
+    /// Returns the IP address offered to or assigned to the client.
+    #[inline]
+    pub fn yiaddr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes.ntohl_unchecked(YIADDR_OFFSET))
+    }
+
+    /// Returns the IP address of the next server to use in bootstrap.
+    #[inline]
+    pub fn siaddr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes.ntohl_unchecked(SIADDR_OFFSET))
+    }
+
+    /// Returns the IP address of the relay agent.
+    #[inline]
+    pub fn giaddr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes.ntohl_unchecked(GIADDR_OFFSET))
+    }
+
+    /// Returns the client hardware address, when it is an Ethernet address.
+    #[inline]
+    pub fn client_mac(&self) -> Option<MacAddr> {
+        if self.htype() != HTYPE_ETHERNET || self.hlen() as usize != MAC_ADDR_LEN {
+            return None;
+        }
+
+        Some(MacAddr::from_bytes_unchecked(
+            &self.bytes[CHADDR_OFFSET..CHADDR_OFFSET + MAC_ADDR_LEN],
+        ))
+    }
+
+    /// Returns the value of the first option with the given code, if any.
+    #[inline]
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
+        match self.find_option(code) {
+            Ok(Some(offset)) => {
+                let len = self.bytes[offset + 1] as usize;
+                Some(&self.bytes[offset + 2..offset + 2 + len])
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the DHCP message type, if present.
+    #[inline]
+    pub fn message_type(&self) -> Option<u8> {
+        self.option(OPTION_MESSAGE_TYPE)
+            .filter(|value| value.len() == 1)
+            .map(|value| value[0])
+    }
+
+    /// Returns the IP address requested by the client, if present.
+    #[inline]
+    pub fn requested_ip(&self) -> Option<Ipv4Addr> {
+        self.addr_option(OPTION_REQUESTED_IP)
+    }
+
+    /// Returns the identifier of the server the message is intended for, if present.
+    #[inline]
+    pub fn server_id(&self) -> Option<Ipv4Addr> {
+        self.addr_option(OPTION_SERVER_ID)
+    }
+
+    fn addr_option(&self, code: u8) -> Option<Ipv4Addr> {
+        self.option(code)
+            .filter(|value| value.len() == 4)
+            .map(|value| Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+    }
+
+    // Returns the offset of the first option with the given code, if any. Looking for the end
+    // option checks whether all the options are properly encoded.
+    fn find_option(&self, code: u8) -> Result<Option<usize>, Error> {
+        let mut offset = OPTIONS_OFFSET;
+        // The end option may be missing from messages which fill up the options field.
+        while offset < self.bytes.len() {
+            match self.bytes[offset] {
+                OPTION_PAD => offset += 1,
+                OPTION_END if code == OPTION_END => return Ok(Some(offset)),
+                OPTION_END => return Ok(None),
+                option_code => {
+                    if offset + 2 > self.bytes.len()
+                        || offset + 2 + self.bytes[offset + 1] as usize > self.bytes.len()
+                    {
+                        return Err(Error::Options);
+                    }
+                    if option_code == code {
+                        return Ok(Some(offset));
+                    }
+                    offset += 2 + self.bytes[offset + 1] as usize;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl<'a, T: NetworkBytesMut> DhcpMessage<'a, T> {
+    fn write_incomplete(
+        buf: T,
+        op: u8,
+        xid: u32,
+        flags: u16,
+        client_mac: MacAddr,
+    ) -> Result<Incomplete<Self>, Error> {
+        if buf.len() < MIN_MESSAGE_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let mut message = DhcpMessage::from_bytes_unchecked(buf);
+        for byte in message.bytes[..OPTIONS_OFFSET].iter_mut() {
+            *byte = 0;
+        }
+
+        message.bytes[OP_OFFSET] = op;
+        message.bytes[HTYPE_OFFSET] = HTYPE_ETHERNET;
+        message.bytes[HLEN_OFFSET] = MAC_ADDR_LEN as u8;
+        message.bytes.htonl_unchecked(XID_OFFSET, xid);
+        message.bytes.htons_unchecked(FLAGS_OFFSET, flags);
+        message.bytes[CHADDR_OFFSET..CHADDR_OFFSET + MAC_ADDR_LEN]
+            .copy_from_slice(client_mac.get_bytes());
+        message
+            .bytes
+            .htonl_unchecked(MAGIC_COOKIE_OFFSET, MAGIC_COOKIE);
+
+        Ok(Incomplete::new(message))
+    }
+
+    /// Attempts to write the fixed part of a request from the client with the hardware address
+    /// `client_mac`, in the transaction `xid`, to `buf`.
+    ///
+    /// The options are written when completing the message.
+    #[inline]
+    pub fn write_request(
+        buf: T,
+        xid: u32,
+        flags: u16,
+        client_mac: MacAddr,
+    ) -> Result<Incomplete<Self>, Error> {
+        Self::write_incomplete(buf, OP_BOOTREQUEST, xid, flags, client_mac)
+    }
+
+    /// Attempts to write the fixed part of a reply to the client with the hardware address
+    /// `client_mac`, in the transaction `xid`, to `buf`.
+    ///
+    /// The options are written when completing the message.
+    #[inline]
+    pub fn write_reply(
+        buf: T,
+        xid: u32,
+        flags: u16,
+        client_mac: MacAddr,
+    ) -> Result<Incomplete<Self>, Error> {
+        Self::write_incomplete(buf, OP_BOOTREPLY, xid, flags, client_mac)
+    }
+
+    /// Sets the client IP address of the message.
+    #[inline]
+    pub fn set_ciaddr(&mut self, addr: Ipv4Addr) -> &mut Self {
+        self.bytes.htonl_unchecked(CIADDR_OFFSET, u32::from(addr));
+        self
+    }
+
+    /// Sets the IP address offered to or assigned to the client.
+    #[inline]
+    pub fn set_yiaddr(&mut self, addr: Ipv4Addr) -> &mut Self {
+        self.bytes.htonl_unchecked(YIADDR_OFFSET, u32::from(addr));
+        self
+    }
+
+    /// Sets the IP address of the next server to use in bootstrap.
+    #[inline]
+    pub fn set_siaddr(&mut self, addr: Ipv4Addr) -> &mut Self {
+        self.bytes.htonl_unchecked(SIADDR_OFFSET, u32::from(addr));
+        self
+    }
+}
+
+impl<'a, T: NetworkBytesMut> Incomplete<DhcpMessage<'a, T>> {
+    /// Completes the message with the given `(code, value)` options, followed by the end option
+    /// and by as much padding as required to reach the minimum message length.
+    pub fn with_options(mut self, options: &[(u8, &[u8])]) -> Result<DhcpMessage<'a, T>, Error> {
+        let bytes = &mut self.inner.bytes;
+        let mut offset = OPTIONS_OFFSET;
+        for (code, value) in options {
+            if value.len() > std::u8::MAX as usize {
+                return Err(Error::Options);
+            }
+            // Leaves room for the end option.
+            if offset + 2 + value.len() >= bytes.len() {
+                return Err(Error::SliceTooShort);
+            }
+            bytes[offset] = *code;
+            bytes[offset + 1] = value.len() as u8;
+            bytes[offset + 2..offset + 2 + value.len()].copy_from_slice(value);
+            offset += 2 + value.len();
+        }
+        bytes[offset] = OPTION_END;
+        offset += 1;
+
+        // The buffer is at least MIN_MESSAGE_LEN bytes long.
+        let len = std::cmp::max(offset, MIN_MESSAGE_LEN);
+        for byte in bytes[offset..len].iter_mut() {
+            *byte = 0;
+        }
+        bytes.shrink_unchecked(len);
+
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fmt;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for DhcpMessage<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(DHCP message)")
+        }
+    }
+
+    impl<'a, T: NetworkBytes> fmt::Debug for Incomplete<DhcpMessage<'a, T>> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(Incomplete DHCP message)")
+        }
+    }
+
+    #[test]
+    fn test_dhcp_message() {
+        let mut buf = [0u8; 600];
+        let mac = MacAddr::parse_str("06:01:23:45:67:01").unwrap();
+        let offered = Ipv4Addr::new(192, 168, 0, 2);
+        let server = Ipv4Addr::new(192, 168, 0, 1);
+
+        let len = {
+            let mut message =
+                DhcpMessage::write_request(buf.as_mut(), 0x1234_5678, 0, mac).unwrap();
+            message
+                .inner_mut()
+                .set_ciaddr(Ipv4Addr::UNSPECIFIED)
+                .set_yiaddr(offered)
+                .set_siaddr(server);
+            message
+                .with_options(&[
+                    (OPTION_MESSAGE_TYPE, &[MESSAGE_REQUEST][..]),
+                    (OPTION_REQUESTED_IP, &offered.octets()[..]),
+                    (OPTION_SERVER_ID, &server.octets()[..]),
+                ])
+                .unwrap()
+                .len()
+        };
+        assert_eq!(len, MIN_MESSAGE_LEN);
+
+        let message = DhcpMessage::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(message.op(), OP_BOOTREQUEST);
+        assert_eq!(message.htype(), HTYPE_ETHERNET);
+        assert_eq!(message.hlen() as usize, MAC_ADDR_LEN);
+        assert_eq!(message.xid(), 0x1234_5678);
+        assert_eq!(message.flags(), 0);
+        assert_eq!(message.ciaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(message.yiaddr(), offered);
+        assert_eq!(message.siaddr(), server);
+        assert_eq!(message.giaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(message.client_mac(), Some(mac));
+        assert_eq!(message.message_type(), Some(MESSAGE_REQUEST));
+        assert_eq!(message.requested_ip(), Some(offered));
+        assert_eq!(message.server_id(), Some(server));
+        assert_eq!(message.option(OPTION_ROUTER), None);
+
+        // Padding is skipped, and a missing end option is fine.
+        buf[OPTIONS_OFFSET] = OPTION_PAD;
+        buf[OPTIONS_OFFSET + 1] = OPTION_MESSAGE_TYPE;
+        buf[OPTIONS_OFFSET + 2] = 1;
+        buf[OPTIONS_OFFSET + 3] = MESSAGE_DISCOVER;
+        let message = DhcpMessage::from_bytes(&buf[..OPTIONS_OFFSET + 4]).unwrap();
+        assert_eq!(message.message_type(), Some(MESSAGE_DISCOVER));
+
+        // Options running past the end of the message.
+        assert_eq!(
+            DhcpMessage::from_bytes(&buf[..OPTIONS_OFFSET + 3]).unwrap_err(),
+            Error::Options
+        );
+
+        // Non-Ethernet hardware addresses.
+        buf[HLEN_OFFSET] = 16;
+        let message = DhcpMessage::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(message.client_mac(), None);
+
+        buf[MAGIC_COOKIE_OFFSET] = 0;
+        assert_eq!(
+            DhcpMessage::from_bytes(&buf[..len]).unwrap_err(),
+            Error::MagicCookie
+        );
+        assert_eq!(
+            DhcpMessage::from_bytes(&buf[..OPTIONS_OFFSET - 1]).unwrap_err(),
+            Error::SliceTooShort
+        );
+    }
+
+    #[test]
+    fn test_write_reply_errors() {
+        let mac = MacAddr::parse_str("06:01:23:45:67:01").unwrap();
+
+        let mut small_buf = [0u8; MIN_MESSAGE_LEN - 1];
+        assert_eq!(
+            DhcpMessage::write_reply(small_buf.as_mut(), 1, FLAG_BROADCAST, mac).unwrap_err(),
+            Error::SliceTooShort
+        );
+
+        let mut buf = [0u8; MIN_MESSAGE_LEN];
+        let long_value = [0u8; 256];
+        assert_eq!(
+            DhcpMessage::write_reply(buf.as_mut(), 1, FLAG_BROADCAST, mac)
+                .unwrap()
+                .with_options(&[(OPTION_DNS_SERVERS, &long_value[..])])
+                .unwrap_err(),
+            Error::Options
+        );
+        assert_eq!(
+            DhcpMessage::write_reply(buf.as_mut(), 1, FLAG_BROADCAST, mac)
+                .unwrap()
+                .with_options(&[(OPTION_DNS_SERVERS, &long_value[..255])])
+                .unwrap_err(),
+            Error::SliceTooShort
+        );
+
+        // The message grows past the minimum length when needed.
+        let mut buf = [0u8; 600];
+        let message = DhcpMessage::write_reply(buf.as_mut(), 1, FLAG_BROADCAST, mac)
+            .unwrap()
+            .with_options(&[(OPTION_DNS_SERVERS, &long_value[..255])])
+            .unwrap();
+        assert_eq!(message.len(), OPTIONS_OFFSET + 2 + 255 + 1);
+        assert_eq!(message.op(), OP_BOOTREPLY);
+        assert_eq!(message.flags(), FLAG_BROADCAST);
+        assert_eq!(message.option(OPTION_DNS_SERVERS).unwrap().len(), 255);
+    }
+}
@@ -0,0 +1,574 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains support for parsing DNS queries and writing the matching responses, as carried by UDP
+//! datagrams sent to the [`SERVER_PORT`].
+//!
+//! Only the subset required by a stub responder is supported: queries hold exactly one question,
+//! and answers always refer to the name of that question. A more detailed view of DNS messages
+//! can be found [here].
+//!
+//! [`SERVER_PORT`]: constant.SERVER_PORT.html
+//! [here]: https://tools.ietf.org/html/rfc1035#section-4
+use std::result::Result;
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use super::Incomplete;
+
+/// The UDP port DNS servers listen on.
+pub const SERVER_PORT: u16 = 53;
+
+/// The length of the fixed header of a message.
+pub const HEADER_LEN: usize = 12;
+/// The maximum length of a message carried by UDP, when no extension mechanism is in use.
+pub const MAX_UDP_MESSAGE_LEN: usize = 512;
+
+/// Host address record type.
+pub const TYPE_A: u16 = 1;
+/// Domain name pointer record type.
+pub const TYPE_PTR: u16 = 12;
+/// IPv6 host address record type.
+pub const TYPE_AAAA: u16 = 28;
+
+/// The Internet class.
+pub const CLASS_IN: u16 = 1;
+
+/// Standard query opcode.
+pub const OPCODE_QUERY: u8 = 0;
+
+/// No error condition.
+pub const RCODE_NOERROR: u8 = 0;
+/// The server was unable to interpret the query.
+pub const RCODE_FORMERR: u8 = 1;
+/// The server does not support the kind of query.
+pub const RCODE_NOTIMP: u8 = 4;
+/// The server refuses to perform the operation.
+pub const RCODE_REFUSED: u8 = 5;
+
+/// The maximum length of an encoded domain name.
+pub const MAX_NAME_LEN: usize = 255;
+/// The maximum length of a single label.
+pub const MAX_LABEL_LEN: usize = 63;
+
+const ID_OFFSET: usize = 0;
+const FLAGS_OFFSET: usize = 2;
+const QDCOUNT_OFFSET: usize = 4;
+const ANCOUNT_OFFSET: usize = 6;
+const NSCOUNT_OFFSET: usize = 8;
+const ARCOUNT_OFFSET: usize = 10;
+
+const FLAG_QR: u16 = 1 << 15;
+const FLAG_AA: u16 = 1 << 10;
+const FLAG_TC: u16 = 1 << 9;
+const FLAG_RD: u16 = 1 << 8;
+const OPCODE_SHIFT: u16 = 11;
+const OPCODE_MASK: u16 = 0xf;
+const RCODE_MASK: u16 = 0xf;
+
+// Answers refer to the name of the question, which always starts right after the header.
+const QUESTION_NAME_POINTER: u16 = 0xc000 | HEADER_LEN as u16;
+// Pointer, type, class, TTL and data length.
+const ANSWER_FIXED_LEN: usize = 12;
+
+/// Represents errors which may occur while parsing or writing a message.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// A domain name is not properly encoded.
+    Name,
+    /// The message does not hold exactly one question.
+    QuestionCount,
+    /// The provided slice is too short to hold the message.
+    SliceTooShort,
+}
+
+/// The question of a query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Question {
+    /// The queried domain name, lowercase and without the trailing dot.
+    pub name: String,
+    /// The queried record type.
+    pub qtype: u16,
+    /// The queried record class.
+    pub qclass: u16,
+    /// The length of the encoded question.
+    pub len: usize,
+}
+
+/// Encodes `name` as a sequence of length-prefixed labels, ended by the root label.
+///
+/// A trailing dot is optional, and the root domain can be written as either `""` or `"."`.
+pub fn encode_name(name: &str) -> Result<Vec<u8>, Error> {
+    let name = name.trim_end_matches('.');
+    let mut encoded = Vec::with_capacity(name.len() + 2);
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > MAX_LABEL_LEN || !label.is_ascii() {
+                return Err(Error::Name);
+            }
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label.as_bytes());
+        }
+    }
+    encoded.push(0);
+
+    if encoded.len() > MAX_NAME_LEN {
+        return Err(Error::Name);
+    }
+    Ok(encoded)
+}
+
+/// Interprets the inner bytes as a DNS message.
+pub struct DnsMessage<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl<'a, T: NetworkBytes> DnsMessage<'a, T> {
+    /// Interprets `bytes` as a DNS message without any validity checks.
+    ///
+    /// # Panics
+    ///
+    ///  This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        DnsMessage {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Attempts to interpret `bytes` as a DNS message, checking that it holds a complete header.
+    ///
+    /// The question is only validated when calling [`question`].
+    ///
+    /// [`question`]: #method.question
+    #[inline]
+    pub fn from_bytes(bytes: T) -> Result<Self, Error> {
+        if bytes.len() < HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+        Ok(DnsMessage::from_bytes_unchecked(bytes))
+    }
+
+    /// Returns the ID of the message.
+    #[inline]
+    pub fn id(&self) -> u16 {
+        self.bytes.ntohs_unchecked(ID_OFFSET)
+    }
+
+    /// Returns the flags of the message, including the opcode and the response code.
+    #[inline]
+    pub fn flags(&self) -> u16 {
+        self.bytes.ntohs_unchecked(FLAGS_OFFSET)
+    }
+
+    /// Returns `true` if the message is a response.
+    #[inline]
+    pub fn is_response(&self) -> bool {
+        self.flags() & FLAG_QR != 0
+    }
+
+    /// Returns `true` if the message is an authoritative answer.
+    #[inline]
+    pub fn is_authoritative(&self) -> bool {
+        self.flags() & FLAG_AA != 0
+    }
+
+    /// Returns `true` if the message was truncated.
+    #[inline]
+    pub fn is_truncated(&self) -> bool {
+        self.flags() & FLAG_TC != 0
+    }
+
+    /// Returns `true` if the query asks for recursion.
+    #[inline]
+    pub fn recursion_desired(&self) -> bool {
+        self.flags() & FLAG_RD != 0
+    }
+
+    /// Returns the kind of query.
+    #[inline]
+    pub fn opcode(&self) -> u8 {
+        ((self.flags() >> OPCODE_SHIFT) & OPCODE_MASK) as u8
+    }
+
+    /// Returns the response code.
+    #[inline]
+    pub fn rcode(&self) -> u8 {
+        (self.flags() & RCODE_MASK) as u8
+    }
+
+    /// Returns the number of entries in the question section.
+    #[inline]
+    pub fn question_count(&self) -> u16 {
+        self.bytes.ntohs_unchecked(QDCOUNT_OFFSET)
+    }
+
+    /// Returns the number of entries in the answer section.
+    #[inline]
+    pub fn answer_count(&self) -> u16 {
+        self.bytes.ntohs_unchecked(ANCOUNT_OFFSET)
+    }
+
+    /// Attempts to parse the only question of the message.
+    ///
+    /// Compressed names are not accepted, since there is no earlier name they could point to.
+    pub fn question(&self) -> Result<Question, Error> {
+        if self.question_count() != 1 {
+            return Err(Error::QuestionCount);
+        }
+
+        let mut name = String::new();
+        let mut offset = HEADER_LEN;
+        loop {
+            if offset >= self.bytes.len() {
+                return Err(Error::SliceTooShort);
+            }
+            let label_len = self.bytes[offset] as usize;
+            offset += 1;
+            if label_len == 0 {
+                break;
+            }
+            // This also rejects compression pointers, which have the top two bits set.
+            if label_len > MAX_LABEL_LEN || offset - HEADER_LEN + label_len >= MAX_NAME_LEN {
+                return Err(Error::Name);
+            }
+            if offset + label_len > self.bytes.len() {
+                return Err(Error::SliceTooShort);
+            }
+            if !name.is_empty() {
+                name.push('.');
+            }
+            for &byte in self.bytes[offset..offset + label_len].iter() {
+                if !byte.is_ascii() || byte == b'.' {
+                    return Err(Error::Name);
+                }
+                name.push(char::from(byte.to_ascii_lowercase()));
+            }
+            offset += label_len;
+        }
+
+        if offset + 4 > self.bytes.len() {
+            return Err(Error::SliceTooShort);
+        }
+
+        Ok(Question {
+            name,
+            qtype: self.bytes.ntohs_unchecked(offset),
+            qclass: self.bytes.ntohs_unchecked(offset + 2),
+            len: offset + 4 - HEADER_LEN,
+        })
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl<'a, T: NetworkBytesMut> DnsMessage<'a, T> {
+    /// Attempts to write the header and the question of a response to `query` to `buf`.
+    ///
+    /// The ID, the opcode and the recursion desired flag are copied from the query. The question
+    /// is copied as well when it can be parsed, and left out otherwise. Recursion is never
+    /// available. The answers are written when completing the message.
+    pub fn write_response<R: NetworkBytes>(
+        buf: T,
+        query: &DnsMessage<R>,
+        rcode: u8,
+        authoritative: bool,
+    ) -> Result<Incomplete<Self>, Error> {
+        if buf.len() < HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let question_len = query.question().map(|question| question.len).unwrap_or(0);
+        if buf.len() < HEADER_LEN + question_len {
+            return Err(Error::SliceTooShort);
+        }
+
+        let mut flags = FLAG_QR
+            | (u16::from(query.opcode()) << OPCODE_SHIFT)
+            | (query.flags() & FLAG_RD)
+            | (u16::from(rcode) & RCODE_MASK);
+        if authoritative {
+            flags |= FLAG_AA;
+        }
+
+        let mut message = DnsMessage::from_bytes_unchecked(buf);
+        message.bytes.htons_unchecked(ID_OFFSET, query.id());
+        message.bytes.htons_unchecked(FLAGS_OFFSET, flags);
+        message
+            .bytes
+            .htons_unchecked(QDCOUNT_OFFSET, if question_len > 0 { 1 } else { 0 });
+        message.bytes.htons_unchecked(ANCOUNT_OFFSET, 0);
+        message.bytes.htons_unchecked(NSCOUNT_OFFSET, 0);
+        message.bytes.htons_unchecked(ARCOUNT_OFFSET, 0);
+        message.bytes[HEADER_LEN..HEADER_LEN + question_len]
+            .copy_from_slice(&query.bytes[HEADER_LEN..HEADER_LEN + question_len]);
+
+        Ok(Incomplete::new(message))
+    }
+}
+
+impl<'a, T: NetworkBytesMut> Incomplete<DnsMessage<'a, T>> {
+    /// Completes the message with the given `(type, ttl, data)` answers, which all refer to the
+    /// name of the question.
+    ///
+    /// Answers which do not fit in the underlying slice are left out, and the message is marked
+    /// as truncated.
+    pub fn with_answers(
+        mut self,
+        answers: &[(u16, u32, &[u8])],
+    ) -> Result<DnsMessage<'a, T>, Error> {
+        let message = &mut self.inner;
+        let question_len = match message.question() {
+            Ok(question) => question.len,
+            Err(_) if answers.is_empty() => 0,
+            Err(_) => return Err(Error::QuestionCount),
+        };
+
+        let mut offset = HEADER_LEN + question_len;
+        let mut count = 0u16;
+        for (rtype, ttl, data) in answers {
+            if data.len() > std::u16::MAX as usize {
+                return Err(Error::SliceTooShort);
+            }
+            if offset + ANSWER_FIXED_LEN + data.len() > message.bytes.len() {
+                let flags = message.flags() | FLAG_TC;
+                message.bytes.htons_unchecked(FLAGS_OFFSET, flags);
+                break;
+            }
+            message.bytes.htons_unchecked(offset, QUESTION_NAME_POINTER);
+            message.bytes.htons_unchecked(offset + 2, *rtype);
+            message.bytes.htons_unchecked(offset + 4, CLASS_IN);
+            message.bytes.htonl_unchecked(offset + 6, *ttl);
+            message
+                .bytes
+                .htons_unchecked(offset + 10, data.len() as u16);
+            message.bytes[offset + ANSWER_FIXED_LEN..offset + ANSWER_FIXED_LEN + data.len()]
+                .copy_from_slice(data);
+            offset += ANSWER_FIXED_LEN + data.len();
+            count += 1;
+        }
+
+        message.bytes.htons_unchecked(ANCOUNT_OFFSET, count);
+        message.bytes.shrink_unchecked(offset);
+
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fmt;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for DnsMessage<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(DNS message)")
+        }
+    }
+
+    impl<'a, T: NetworkBytes> fmt::Debug for Incomplete<DnsMessage<'a, T>> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(Incomplete DNS message)")
+        }
+    }
+
+    // Writes a query with a single question to `buf`, returning its length.
+    fn write_query(buf: &mut [u8], id: u16, name: &str, qtype: u16) -> usize {
+        let name = encode_name(name).unwrap();
+        let len = HEADER_LEN + name.len() + 4;
+        for byte in buf[..HEADER_LEN].iter_mut() {
+            *byte = 0;
+        }
+        buf[..2].copy_from_slice(&id.to_be_bytes());
+        buf[FLAGS_OFFSET..FLAGS_OFFSET + 2].copy_from_slice(&FLAG_RD.to_be_bytes());
+        buf[QDCOUNT_OFFSET + 1] = 1;
+        buf[HEADER_LEN..HEADER_LEN + name.len()].copy_from_slice(&name);
+        buf[len - 4..len - 2].copy_from_slice(&qtype.to_be_bytes());
+        buf[len - 2..len].copy_from_slice(&CLASS_IN.to_be_bytes());
+        len
+    }
+
+    #[test]
+    fn test_encode_name() {
+        assert_eq!(encode_name("").unwrap(), vec![0]);
+        assert_eq!(encode_name(".").unwrap(), vec![0]);
+        assert_eq!(
+            encode_name("a.bc.").unwrap(),
+            vec![1, b'a', 2, b'b', b'c', 0]
+        );
+        assert_eq!(encode_name("a..b").unwrap_err(), Error::Name);
+        assert_eq!(encode_name(&"a".repeat(64)).unwrap_err(), Error::Name);
+        assert_eq!(encode_name("ä.b").unwrap_err(), Error::Name);
+
+        let long_name = vec!["a".repeat(63); 4].join(".");
+        assert_eq!(encode_name(&long_name).unwrap_err(), Error::Name);
+        assert_eq!(encode_name(&long_name[2..]).unwrap().len(), MAX_NAME_LEN);
+    }
+
+    #[test]
+    fn test_query() {
+        let mut buf = [0u8; 100];
+        let len = write_query(&mut buf, 0x1234, "Metadata.Internal", TYPE_AAAA);
+
+        let query = DnsMessage::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(query.id(), 0x1234);
+        assert!(!query.is_response());
+        assert!(query.recursion_desired());
+        assert_eq!(query.opcode(), OPCODE_QUERY);
+        assert_eq!(query.rcode(), RCODE_NOERROR);
+        assert_eq!(query.question_count(), 1);
+        assert_eq!(query.answer_count(), 0);
+        assert_eq!(query.len(), len);
+        assert_eq!(
+            query.question().unwrap(),
+            Question {
+                name: "metadata.internal".to_string(),
+                qtype: TYPE_AAAA,
+                qclass: CLASS_IN,
+                len: len - HEADER_LEN,
+            }
+        );
+
+        // Truncated questions.
+        assert_eq!(
+            DnsMessage::from_bytes(&buf[..len - 1])
+                .unwrap()
+                .question()
+                .unwrap_err(),
+            Error::SliceTooShort
+        );
+        assert_eq!(
+            DnsMessage::from_bytes(&buf[..HEADER_LEN + 3])
+                .unwrap()
+                .question()
+                .unwrap_err(),
+            Error::SliceTooShort
+        );
+
+        // Compressed names.
+        buf[HEADER_LEN] = 0xc0;
+        assert_eq!(
+            DnsMessage::from_bytes(&buf[..len])
+                .unwrap()
+                .question()
+                .unwrap_err(),
+            Error::Name
+        );
+
+        // Labels holding dots.
+        buf[HEADER_LEN] = 8;
+        buf[HEADER_LEN + 1] = b'.';
+        assert_eq!(
+            DnsMessage::from_bytes(&buf[..len])
+                .unwrap()
+                .question()
+                .unwrap_err(),
+            Error::Name
+        );
+
+        buf[QDCOUNT_OFFSET + 1] = 2;
+        assert_eq!(
+            DnsMessage::from_bytes(&buf[..len])
+                .unwrap()
+                .question()
+                .unwrap_err(),
+            Error::QuestionCount
+        );
+
+        assert_eq!(
+            DnsMessage::from_bytes(&buf[..HEADER_LEN - 1]).unwrap_err(),
+            Error::SliceTooShort
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        let mut query_buf = [0u8; 100];
+        let query_len = write_query(&mut query_buf, 0x4321, "host.internal.", TYPE_A);
+        let query = DnsMessage::from_bytes(&query_buf[..query_len]).unwrap();
+
+        let mut buf = [0u8; MAX_UDP_MESSAGE_LEN];
+        let len = DnsMessage::write_response(buf.as_mut(), &query, RCODE_NOERROR, true)
+            .unwrap()
+            .with_answers(&[
+                (TYPE_A, 60, &[10, 0, 0, 1][..]),
+                (TYPE_A, 60, &[10, 0, 0, 2][..]),
+            ])
+            .unwrap()
+            .len();
+        assert_eq!(len, query_len + 2 * (ANSWER_FIXED_LEN + 4));
+
+        let response = DnsMessage::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(response.id(), 0x4321);
+        assert!(response.is_response());
+        assert!(response.is_authoritative());
+        assert!(!response.is_truncated());
+        assert!(response.recursion_desired());
+        assert_eq!(response.rcode(), RCODE_NOERROR);
+        assert_eq!(response.answer_count(), 2);
+        assert_eq!(response.question().unwrap(), query.question().unwrap());
+        assert_eq!(
+            &buf[query_len..query_len + ANSWER_FIXED_LEN + 4],
+            &[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1]
+        );
+
+        // Answers which do not fit are left out.
+        let mut small_buf = [0u8; 100];
+        let response = DnsMessage::write_response(small_buf.as_mut(), &query, RCODE_NOERROR, true)
+            .unwrap()
+            .with_answers(&[
+                (TYPE_A, 60, &[10, 0, 0, 1][..]),
+                (TYPE_PTR, 60, &[0u8; 80][..]),
+            ])
+            .unwrap();
+        assert_eq!(response.len(), query_len + ANSWER_FIXED_LEN + 4);
+        assert_eq!(response.answer_count(), 1);
+        assert!(response.is_truncated());
+    }
+
+    #[test]
+    fn test_response_errors() {
+        let mut query_buf = [0u8; 100];
+        let query_len = write_query(&mut query_buf, 1, "host.internal", TYPE_A);
+
+        let mut small_buf = [0u8; HEADER_LEN + 1];
+        let query = DnsMessage::from_bytes(&query_buf[..query_len]).unwrap();
+        assert_eq!(
+            DnsMessage::write_response(small_buf.as_mut(), &query, RCODE_NOERROR, true)
+                .unwrap_err(),
+            Error::SliceTooShort
+        );
+        assert_eq!(
+            DnsMessage::write_response(&mut small_buf[..HEADER_LEN - 1], &query, 0, true)
+                .unwrap_err(),
+            Error::SliceTooShort
+        );
+
+        // Queries without a valid question are answered with just the header.
+        query_buf[QDCOUNT_OFFSET + 1] = 0;
+        let query = DnsMessage::from_bytes(&query_buf[..query_len]).unwrap();
+        let mut buf = [0u8; MAX_UDP_MESSAGE_LEN];
+        let response = DnsMessage::write_response(buf.as_mut(), &query, RCODE_FORMERR, false)
+            .unwrap()
+            .with_answers(&[])
+            .unwrap();
+        assert_eq!(response.len(), HEADER_LEN);
+        assert_eq!(response.question_count(), 0);
+        assert_eq!(response.rcode(), RCODE_FORMERR);
+        assert!(!response.is_authoritative());
+
+        assert_eq!(
+            DnsMessage::write_response(buf.as_mut(), &query, RCODE_NOERROR, true)
+                .unwrap()
+                .with_answers(&[(TYPE_A, 60, &[10, 0, 0, 1][..])])
+                .unwrap_err(),
+            Error::QuestionCount
+        );
+    }
+}
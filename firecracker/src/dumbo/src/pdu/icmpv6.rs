@@ -0,0 +1,385 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains logic that helps with handling the ICMPv6 messages of the Neighbor Discovery Protocol,
+//! which resolve IPv6 addresses to link-layer addresses, much like ARP does for IPv4.
+//!
+//! Only neighbor solicitations and advertisements are supported. A more detailed view of these
+//! messages can be found [here].
+//!
+//! [here]: https://tools.ietf.org/html/rfc4861#section-4.3
+use std::net::{IpAddr, Ipv6Addr};
+use std::result::Result;
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use super::ipv6::read_addr_unchecked;
+use super::ChecksumProto;
+
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+/// ICMPv6 message type of neighbor solicitations.
+pub const TYPE_NEIGHBOR_SOLICITATION: u8 = 135;
+
+/// ICMPv6 message type of neighbor advertisements.
+pub const TYPE_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// The length of a neighbor advertisement carrying the target link-layer address option.
+pub const NEIGHBOR_ADVERTISEMENT_LEN: usize = OPTIONS_OFFSET + OPTION_UNIT_LEN;
+
+/// Neighbor advertisement flag telling the advertisement answers a solicitation.
+pub const FLAG_SOLICITED: u8 = 1 << 6;
+/// Neighbor advertisement flag telling the advertisement overrides cached link-layer addresses.
+pub const FLAG_OVERRIDE: u8 = 1 << 5;
+
+const TYPE_OFFSET: usize = 0;
+const CODE_OFFSET: usize = 1;
+const CHECKSUM_OFFSET: usize = 2;
+const FLAGS_OFFSET: usize = 4;
+const TARGET_ADDRESS_OFFSET: usize = 8;
+const OPTIONS_OFFSET: usize = 24;
+
+// The options lengths are expressed in units of 8 bytes.
+const OPTION_UNIT_LEN: usize = 8;
+const OPTION_SOURCE_LINK_LAYER_ADDR: u8 = 1;
+const OPTION_TARGET_LINK_LAYER_ADDR: u8 = 2;
+
+/// Represents errors which may occur while parsing or writing a message.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Invalid checksum.
+    Checksum,
+    /// Invalid message code.
+    Code,
+    /// An option has an invalid length.
+    OptionLen,
+    /// The target address is a multicast address.
+    Target,
+    /// Invalid message type.
+    Type,
+    /// The provided slice is too short to hold the message.
+    SliceTooShort,
+}
+
+/// Interprets the inner bytes as a neighbor solicitation or advertisement.
+pub struct NeighborMessage<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl<'a, T: NetworkBytes> NeighborMessage<'a, T> {
+    /// Interprets the given bytes as a neighbor message, without doing any validity checks
+    /// beforehand.
+    ///
+    ///  # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        NeighborMessage {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Tries to interpret a byte slice as a valid neighbor solicitation.
+    ///
+    /// The `verify_checksum` parameter must contain the source and destination addresses from the
+    /// enclosing IPv6 packet if the ICMPv6 checksum must be validated.
+    pub fn solicitation_from_bytes(
+        bytes: T,
+        verify_checksum: Option<(Ipv6Addr, Ipv6Addr)>,
+    ) -> Result<Self, Error> {
+        if bytes.len() < OPTIONS_OFFSET {
+            return Err(Error::SliceTooShort);
+        }
+
+        let maybe = NeighborMessage::from_bytes_unchecked(bytes);
+
+        if maybe.message_type() != TYPE_NEIGHBOR_SOLICITATION {
+            return Err(Error::Type);
+        }
+
+        if maybe.code() != 0 {
+            return Err(Error::Code);
+        }
+
+        if maybe.target_address().is_multicast() {
+            return Err(Error::Target);
+        }
+
+        // Makes sure the options can be walked through.
+        maybe.find_option(OPTION_SOURCE_LINK_LAYER_ADDR)?;
+
+        if let Some((src_addr, dst_addr)) = verify_checksum {
+            if maybe.compute_checksum(src_addr, dst_addr) != 0 {
+                return Err(Error::Checksum);
+            }
+        }
+
+        Ok(maybe)
+    }
+
+    /// Returns the type of the message.
+    #[inline]
+    pub fn message_type(&self) -> u8 {
+        self.bytes[TYPE_OFFSET]
+    }
+
+    /// Returns the code of the message.
+    #[inline]
+    pub fn code(&self) -> u8 {
+        self.bytes[CODE_OFFSET]
+    }
+
+    /// Returns the checksum of the message.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        self.bytes.ntohs_unchecked(CHECKSUM_OFFSET)
+    }
+
+    /// Returns the flags of the message, which are only meaningful for advertisements.
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        self.bytes[FLAGS_OFFSET]
+    }
+
+    /// Returns the target address of the message.
+    #[inline]
+    pub fn target_address(&self) -> Ipv6Addr {
+        read_addr_unchecked(&self.bytes, TARGET_ADDRESS_OFFSET)
+    }
+
+    /// Returns the source link-layer address option of a solicitation, if present.
+    #[inline]
+    pub fn source_link_layer_addr(&self) -> Option<MacAddr> {
+        self.link_layer_addr(OPTION_SOURCE_LINK_LAYER_ADDR)
+    }
+
+    /// Returns the target link-layer address option of an advertisement, if present.
+    #[inline]
+    pub fn target_link_layer_addr(&self) -> Option<MacAddr> {
+        self.link_layer_addr(OPTION_TARGET_LINK_LAYER_ADDR)
+    }
+
+    fn link_layer_addr(&self, option_type: u8) -> Option<MacAddr> {
+        // Options are at least 8 bytes long, which leaves enough room for the address.
+        match self.find_option(option_type) {
+            Ok(Some(offset)) => Some(MacAddr::from_bytes_unchecked(
+                &self.bytes[offset + 2..offset + 2 + MAC_ADDR_LEN],
+            )),
+            _ => None,
+        }
+    }
+
+    // Returns the offset of the first option of the given type, if any.
+    fn find_option(&self, option_type: u8) -> Result<Option<usize>, Error> {
+        let mut offset = OPTIONS_OFFSET;
+        while offset < self.bytes.len() {
+            if offset + 2 > self.bytes.len() {
+                return Err(Error::OptionLen);
+            }
+            let option_len = self.bytes[offset + 1] as usize * OPTION_UNIT_LEN;
+            if option_len == 0 || offset + option_len > self.bytes.len() {
+                return Err(Error::OptionLen);
+            }
+            if self.bytes[offset] == option_type {
+                return Ok(Some(offset));
+            }
+            offset += option_len;
+        }
+        Ok(None)
+    }
+
+    /// Computes the ICMPv6 checksum of the message.
+    pub fn compute_checksum(&self, src_addr: Ipv6Addr, dst_addr: Ipv6Addr) -> u16 {
+        crate::pdu::compute_checksum(
+            &self.bytes,
+            IpAddr::V6(src_addr),
+            IpAddr::V6(dst_addr),
+            ChecksumProto::Icmpv6,
+        )
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl<'a, T: NetworkBytesMut> NeighborMessage<'a, T> {
+    /// Attempts to write a neighbor advertisement for `target_addr`, carrying the `mac_addr`
+    /// target link-layer address option, to `buf`.
+    ///
+    /// The `compute_checksum` parameter must contain the source and destination addresses of the
+    /// enclosing IPv6 packet.
+    pub fn write_advertisement(
+        buf: T,
+        flags: u8,
+        target_addr: Ipv6Addr,
+        mac_addr: MacAddr,
+        compute_checksum: (Ipv6Addr, Ipv6Addr),
+    ) -> Result<Self, Error> {
+        if buf.len() < NEIGHBOR_ADVERTISEMENT_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let mut message = NeighborMessage::from_bytes_unchecked(buf);
+        message.bytes.shrink_unchecked(NEIGHBOR_ADVERTISEMENT_LEN);
+
+        message.bytes[TYPE_OFFSET] = TYPE_NEIGHBOR_ADVERTISEMENT;
+        message.bytes[CODE_OFFSET] = 0;
+        message.bytes.htonl_unchecked(FLAGS_OFFSET, 0);
+        message.bytes[FLAGS_OFFSET] = flags;
+        message.bytes[TARGET_ADDRESS_OFFSET..OPTIONS_OFFSET].copy_from_slice(&target_addr.octets());
+        message.bytes[OPTIONS_OFFSET] = OPTION_TARGET_LINK_LAYER_ADDR;
+        message.bytes[OPTIONS_OFFSET + 1] = 1;
+        message.bytes[OPTIONS_OFFSET + 2..NEIGHBOR_ADVERTISEMENT_LEN]
+            .copy_from_slice(mac_addr.get_bytes());
+
+        let (src_addr, dst_addr) = compute_checksum;
+        message.set_checksum(0);
+        let checksum = message.compute_checksum(src_addr, dst_addr);
+        message.set_checksum(checksum);
+
+        Ok(message)
+    }
+
+    /// Sets the checksum of the message.
+    #[inline]
+    pub fn set_checksum(&mut self, value: u16) {
+        self.bytes.htons_unchecked(CHECKSUM_OFFSET, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fmt;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for NeighborMessage<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(Neighbor message)")
+        }
+    }
+
+    const GUEST_ADDR: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 1, 2, 3, 4);
+    const MMDS_ADDR: Ipv6Addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+
+    // Writes a neighbor solicitation for MMDS_ADDR, sent by GUEST_ADDR to `dst_addr`.
+    fn write_solicitation(buf: &mut [u8], mac: MacAddr, dst_addr: Ipv6Addr) -> usize {
+        let len = OPTIONS_OFFSET + OPTION_UNIT_LEN;
+        {
+            let mut message = NeighborMessage::from_bytes_unchecked(&mut buf[..len]);
+            message.bytes[TYPE_OFFSET] = TYPE_NEIGHBOR_SOLICITATION;
+            message.bytes[CODE_OFFSET] = 0;
+            message.bytes[TARGET_ADDRESS_OFFSET..OPTIONS_OFFSET]
+                .copy_from_slice(&MMDS_ADDR.octets());
+            message.bytes[OPTIONS_OFFSET] = OPTION_SOURCE_LINK_LAYER_ADDR;
+            message.bytes[OPTIONS_OFFSET + 1] = 1;
+            message.bytes[OPTIONS_OFFSET + 2..len].copy_from_slice(mac.get_bytes());
+            message.set_checksum(0);
+            let checksum = message.compute_checksum(GUEST_ADDR, dst_addr);
+            message.set_checksum(checksum);
+        }
+        len
+    }
+
+    #[test]
+    fn test_solicitation() {
+        let mut buf = [0u8; 100];
+        let mac = MacAddr::parse_str("01:23:45:67:89:ab").unwrap();
+        let dst_addr = crate::pdu::ipv6::solicited_node_multicast_addr(MMDS_ADDR);
+        let len = write_solicitation(buf.as_mut(), mac, dst_addr);
+
+        let message =
+            NeighborMessage::solicitation_from_bytes(&buf[..len], Some((GUEST_ADDR, dst_addr)))
+                .unwrap();
+        assert_eq!(message.message_type(), TYPE_NEIGHBOR_SOLICITATION);
+        assert_eq!(message.code(), 0);
+        assert_eq!(message.target_address(), MMDS_ADDR);
+        assert_eq!(message.source_link_layer_addr(), Some(mac));
+        assert_eq!(message.target_link_layer_addr(), None);
+        assert_eq!(message.len(), len);
+
+        // Invalid checksum.
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&buf[..len], Some((GUEST_ADDR, MMDS_ADDR)))
+                .unwrap_err(),
+            Error::Checksum
+        );
+
+        // Invalid option length.
+        buf[OPTIONS_OFFSET + 1] = 0;
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&buf[..len], None).unwrap_err(),
+            Error::OptionLen
+        );
+        buf[OPTIONS_OFFSET + 1] = 2;
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&buf[..len], None).unwrap_err(),
+            Error::OptionLen
+        );
+        buf[OPTIONS_OFFSET + 1] = 1;
+
+        // Multicast target.
+        buf[TARGET_ADDRESS_OFFSET] = 0xff;
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&buf[..len], None).unwrap_err(),
+            Error::Target
+        );
+
+        // Invalid code and type.
+        buf[CODE_OFFSET] = 1;
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&buf[..len], None).unwrap_err(),
+            Error::Code
+        );
+        buf[TYPE_OFFSET] = TYPE_NEIGHBOR_ADVERTISEMENT;
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&buf[..len], None).unwrap_err(),
+            Error::Type
+        );
+
+        assert_eq!(
+            NeighborMessage::solicitation_from_bytes(&buf[..OPTIONS_OFFSET - 1], None).unwrap_err(),
+            Error::SliceTooShort
+        );
+    }
+
+    #[test]
+    fn test_advertisement() {
+        let mut buf = [0u8; 100];
+        let mac = MacAddr::parse_str("06:01:23:45:67:01").unwrap();
+
+        let message = NeighborMessage::write_advertisement(
+            buf.as_mut(),
+            FLAG_SOLICITED | FLAG_OVERRIDE,
+            MMDS_ADDR,
+            mac,
+            (MMDS_ADDR, GUEST_ADDR),
+        )
+        .unwrap();
+        assert_eq!(message.len(), NEIGHBOR_ADVERTISEMENT_LEN);
+        assert_eq!(message.message_type(), TYPE_NEIGHBOR_ADVERTISEMENT);
+        assert_eq!(message.code(), 0);
+        assert_eq!(message.flags(), FLAG_SOLICITED | FLAG_OVERRIDE);
+        assert_eq!(message.target_address(), MMDS_ADDR);
+        assert_eq!(message.target_link_layer_addr(), Some(mac));
+        assert_eq!(message.compute_checksum(MMDS_ADDR, GUEST_ADDR), 0);
+
+        let mut small_buf = [0u8; 1];
+        assert_eq!(
+            NeighborMessage::write_advertisement(
+                small_buf.as_mut(),
+                0,
+                MMDS_ADDR,
+                mac,
+                (MMDS_ADDR, GUEST_ADDR)
+            )
+            .unwrap_err(),
+            Error::SliceTooShort
+        );
+    }
+}
@@ -7,15 +7,20 @@
 //! protocol. Ethernet frames, IP packets, and TCP segments are all examples of protocol data
 //! units.
 
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 
 use crate::pdu::bytes::NetworkBytes;
 use crate::pdu::ipv4::{PROTOCOL_TCP, PROTOCOL_UDP};
+use crate::pdu::ipv6::PROTOCOL_ICMPV6;
 
 pub mod arp;
 pub mod bytes;
+pub mod dhcp;
+pub mod dns;
 pub mod ethernet;
+pub mod icmpv6;
 pub mod ipv4;
+pub mod ipv6;
 pub mod tcp;
 pub mod udp;
 
@@ -53,19 +58,32 @@ impl<T> Incomplete<T> {
 #[repr(u8)]
 #[derive(Copy, Clone, PartialEq)]
 enum ChecksumProto {
+    Icmpv6 = PROTOCOL_ICMPV6,
     Tcp = PROTOCOL_TCP,
     Udp = PROTOCOL_UDP,
 }
 
-/// Computes the checksum of a TCP/UDP packet. Since both protocols use
+// Returns the sum of the 16 bit words making up an IP address.
+#[inline]
+fn addr_sum(addr: IpAddr) -> u32 {
+    match addr {
+        IpAddr::V4(addr) => {
+            let a = u32::from(addr);
+            (a & 0xffff) + (a >> 16)
+        }
+        IpAddr::V6(addr) => addr.segments().iter().map(|&word| u32::from(word)).sum(),
+    }
+}
+
+/// Computes the checksum of a TCP/UDP/ICMPv6 packet. Since all these protocols use
 /// the same algorithm to compute the checksum.
 ///
 /// # Arguments
-/// * `bytes` - Raw bytes of a TCP packet or a UDP datagram
-/// * `src_addr` - IPv4 source address
-/// * `dst_addr` - IPv4 destination address
+/// * `bytes` - Raw bytes of a TCP packet, a UDP datagram or an ICMPv6 message
+/// * `src_addr` - IPv4 or IPv6 source address
+/// * `dst_addr` - IPv4 or IPv6 destination address, of the same version as `src_addr`
 /// * `protocol` - **must** be either `PROTOCOL_TCP` or `PROTOCOL_UDP` defined in
-/// `ipv4` module
+/// `ipv4` module, or `PROTOCOL_ICMPV6` defined in `ipv6` module
 ///
 /// More details about TCP checksum computation can be found [here].
 ///
@@ -73,21 +91,16 @@ enum ChecksumProto {
 #[inline]
 fn compute_checksum<T: NetworkBytes>(
     bytes: &T,
-    src_addr: Ipv4Addr,
-    dst_addr: Ipv4Addr,
+    src_addr: IpAddr,
+    dst_addr: IpAddr,
     protocol: ChecksumProto,
 ) -> u16 {
-    // TODO: Is u32 enough to prevent overflow for the code in this function? I think so, but it
-    // would be nice to double-check.
+    // A u32 is enough to prevent overflows: the addresses add up to at most 16 words, and the
+    // packets are far shorter than 2^16 words.
     let mut sum = 0u32;
 
-    let a = u32::from(src_addr);
-    sum += a & 0xffff;
-    sum += a >> 16;
-
-    let b = u32::from(dst_addr);
-    sum += b & 0xffff;
-    sum += b >> 16;
+    sum += addr_sum(src_addr);
+    sum += addr_sum(dst_addr);
 
     let len = bytes.len();
     sum += protocol as u32;
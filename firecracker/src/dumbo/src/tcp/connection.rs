@@ -7,6 +7,7 @@
 //! [`Connection`]: struct.Connection.html
 
 use std::num::{NonZeroU16, NonZeroU64, NonZeroUsize, Wrapping};
+use std::ops::Index;
 
 use crate::pdu::bytes::NetworkBytes;
 use crate::pdu::tcp::{Error as TcpSegmentError, Flags as TcpFlags, TcpSegment};
@@ -207,6 +208,31 @@ fn is_valid_syn<T: NetworkBytes>(segment: &TcpSegment<T>) -> bool {
     segment.flags_after_ns() == TcpFlags::SYN && segment.payload_len() == 0
 }
 
+// Exposes the bytes of a payload source past some offset, so that data segments can start at
+// any of the sequence numbers the source covers (for example, when retransmitting).
+struct OffsetBuffer<'a, R: ?Sized> {
+    inner: &'a R,
+    offset: usize,
+}
+
+impl<'a, R: ByteBuffer + ?Sized> Index<usize> for OffsetBuffer<'a, R> {
+    type Output = u8;
+
+    fn index(&self, index: usize) -> &u8 {
+        &self.inner[self.offset + index]
+    }
+}
+
+impl<'a, R: ByteBuffer + ?Sized> ByteBuffer for OffsetBuffer<'a, R> {
+    fn len(&self) -> usize {
+        self.inner.len() - self.offset
+    }
+
+    fn read_to_slice(&self, offset: usize, buf: &mut [u8]) {
+        self.inner.read_to_slice(self.offset + offset, buf)
+    }
+}
+
 impl Connection {
     /// Attempts to create a new `Connection` in response to an incoming `SYN` segment.
     ///
@@ -935,6 +961,13 @@ impl Connection {
             // delimit a valid sequence number interval.
             if seq_after(actual_end, seq_to_send) {
                 let max_payload_len = (actual_end - seq_to_send).0 as usize;
+                // The payload starts with the byte carrying seq_to_send, which is not necessarily
+                // the first one from read_buf. The offset is within read_buf, because
+                // seq_to_send comes before actual_end.
+                let payload = OffsetBuffer {
+                    inner: read_buf,
+                    offset: (seq_to_send - payload_seq).0 as usize,
+                };
 
                 // We always set the ACK flag for data segments.
                 let tcp_flags = TcpFlags::ACK;
@@ -946,7 +979,7 @@ impl Connection {
                     seq_to_send,
                     ack_to_send,
                     tcp_flags,
-                    Some((read_buf, max_payload_len)),
+                    Some((&payload, max_payload_len)),
                 )?;
 
                 // If self.dup_ack was Some(_), we've just written the retransmission segment,
@@ -1017,6 +1050,7 @@ pub(crate) mod tests {
     use std::fmt;
 
     use super::*;
+    use crate::tcp::tests::FuzzRng;
 
     // A segment without options or a payload is 20 bytes long.
     const BASIC_SEGMENT_SIZE: usize = 20;
@@ -1777,4 +1811,143 @@ pub(crate) mod tests {
         // and we don't wait for our FIN to be ACKed.
         assert!(c.is_done());
     }
+
+    #[test]
+    fn test_fuzz_state_machine() {
+        // Feeds connections with random (but mostly plausible) segments, interleaved with writes,
+        // timeouts and closes, and checks that the connection never panics, keeps a consistent
+        // view of the sequence number space, and only sends bytes from the payload source.
+        let mut rng = FuzzRng::new(0x5eed_7c90);
+        let stream: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        let data = [0xaau8; 1000];
+        let flag_choices = [
+            TcpFlags::ACK,
+            TcpFlags::ACK,
+            TcpFlags::ACK | TcpFlags::FIN,
+            TcpFlags::ACK | TcpFlags::PSH,
+            TcpFlags::FIN,
+            TcpFlags::SYN,
+            TcpFlags::RST,
+            TcpFlags::RST | TcpFlags::ACK,
+            TcpFlags::empty(),
+        ];
+
+        for _ in 0..50 {
+            let mut t = ConnectionTester::new();
+            let mut syn_buf = [0u8; 100];
+            let mut segment_buf = [0u8; 2000];
+            let mss = usize::from(t.mss);
+
+            let syn = t.write_syn(syn_buf.as_mut());
+            let mut c = t.passive_open(&syn).unwrap();
+            // The sequence number of the first byte from the stream.
+            let base = c.first_not_sent;
+            let mut stream_len = 0;
+
+            for _ in 0..500 {
+                let ack_to_send = c.ack_to_send;
+
+                match rng.below(8) {
+                    0..=3 => {
+                        let payload_len = if rng.below(2) == 0 {
+                            0
+                        } else {
+                            rng.below(data.len() as u32) as usize
+                        };
+                        let seq = match rng.below(4) {
+                            0 => rng.next_u32(),
+                            1 => c.ack_to_send.0.wrapping_add(rng.below(64)).wrapping_sub(32),
+                            _ => c.ack_to_send.0,
+                        };
+                        let in_flight = (c.first_not_sent - c.highest_ack_received).0;
+                        let ack = match rng.below(4) {
+                            0 => rng.next_u32(),
+                            1 => c
+                                .highest_ack_received
+                                .0
+                                .wrapping_add(rng.below(in_flight + 1)),
+                            _ => c.first_not_sent.0,
+                        };
+                        let window_size = if rng.below(4) == 0 {
+                            rng.below(u32::from(u16::max_value()) + 1) as u16
+                        } else {
+                            t.remote_window_size
+                        };
+
+                        let mut s = t.write_data(segment_buf.as_mut(), &data[..payload_len]);
+                        s.set_flags_after_ns(
+                            flag_choices[rng.below(flag_choices.len() as u32) as usize],
+                        )
+                        .set_sequence_number(seq)
+                        .set_ack_number(ack)
+                        .set_window_size(window_size);
+
+                        let was_reset = c.is_reset() || c.rst_pending();
+                        match t.receive_segment(&mut c, &s) {
+                            Ok((len, _)) => {
+                                assert!(!was_reset);
+                                if let Some(len) = len {
+                                    assert_eq!(len.get(), payload_len);
+                                }
+                            }
+                            Err(e) => {
+                                assert_eq!(e, RecvError::ConnectionReset);
+                                assert!(was_reset);
+                            }
+                        }
+                    }
+                    4..=5 => {
+                        stream_len =
+                            std::cmp::max(stream_len, rng.below(stream.len() as u32 + 1) as usize);
+                        // The application does not send data past the FIN.
+                        if let Some(fin_seq) = c.send_fin {
+                            stream_len = std::cmp::min(stream_len, (fin_seq - base).0 as usize);
+                        }
+
+                        let highest_ack_received = c.highest_ack_received;
+                        let first_not_sent = c.first_not_sent;
+                        let was_reset = c.is_reset();
+                        match t.write_next_segment(&mut c, Some((&stream[..stream_len], base))) {
+                            Ok(Some(s)) => {
+                                assert!(!was_reset);
+                                assert!(s.payload_len() <= mss);
+                                if s.payload_len() > 0 {
+                                    // Data segments either retransmit bytes, or send new ones.
+                                    let seq = Wrapping(s.sequence_number());
+                                    assert!(seq_at_or_after(seq, highest_ack_received));
+                                    assert!(seq_at_or_after(first_not_sent, seq));
+
+                                    let offset = (seq - base).0 as usize;
+                                    assert_eq!(
+                                        s.payload(),
+                                        &stream[offset..offset + s.payload_len()]
+                                    );
+                                }
+                            }
+                            Ok(None) => assert!(!was_reset),
+                            Err(e) => {
+                                assert_eq!(e, WriteNextError::ConnectionReset);
+                                assert!(was_reset);
+                            }
+                        }
+                    }
+                    6 => t.now += u64::from(rng.below(3)) * t.rto_period / 2,
+                    _ => {
+                        if rng.below(4) == 0 {
+                            c.close();
+                        } else {
+                            c.advance_local_rwnd_edge(rng.below(2000));
+                        }
+                    }
+                }
+
+                assert!(seq_at_or_after(c.first_not_sent, c.highest_ack_received));
+                assert!(seq_at_or_after(c.ack_to_send, ack_to_send));
+
+                if c.is_done() {
+                    break;
+                }
+            }
+        }
+    }
 }
@@ -11,16 +11,17 @@
 // components, but since the separation/interface is not very well defined yet, we keep the
 // Endpoint in here too for the time being.
 
+use std::cmp::min;
 use std::num::{NonZeroU16, NonZeroU64, Wrapping};
 
 use crate::pdu::{bytes::NetworkBytes, tcp::TcpSegment, Incomplete};
 use crate::tcp::{
     connection::{Connection, PassiveOpenError, RecvStatusFlags},
-    seq_after, NextSegmentStatus, MAX_WINDOW_SIZE,
+    seq_after, EndpointLimits, NextSegmentStatus, Reply, MAX_WINDOW_SIZE,
 };
 use logger::{IncMetric, METRICS};
 use micro_http::{Body, Request, RequestError, Response, StatusCode, Version};
-use utils::time::timestamp_cycles;
+use utils::time::{get_time_ns, timestamp_cycles, ClockType};
 
 // TODO: These are currently expressed in cycles. Normally, they would be the equivalent of a
 // certain duration, depending on the frequency of the CPU, but we still have a bit to go until
@@ -30,27 +31,52 @@ const EVICTION_THRESHOLD: u64 = 40_000_000_000;
 const CONNECTION_RTO_PERIOD: u64 = 1_200_000_000;
 const CONNECTION_RTO_COUNT_MAX: u16 = 15;
 
-// This is one plus the size of the largest bytestream carrying an HTTP request we are willing to
-// accept. It's limited in order to have a bound on memory usage. This value should be plenty for
-// imaginable regular MMDS requests.
-// TODO: Maybe at some point include this in the checks we do when populating the MMDS via the API,
-// since it effectively limits the size of the keys (URIs) we're willing to use.
-const RCV_BUF_MAX_SIZE: usize = 2500;
+// The header field through which requests ask for the connection to be kept alive or closed.
+const CONNECTION_HEADER: &str = "Connection";
+// The request header field announcing the length of the body.
+const CONTENT_LENGTH_HEADER: &str = "Content-Length";
+// Upper bound for the bytes which frame a chunk: the hexadecimal chunk size, and two CRLFs.
+const CHUNK_FRAMING_MAX_LEN: usize = 20;
+// The last chunk of a body sent with the chunked transfer encoding.
+const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+// The part of a response which did not fit in the send buffer, and which is moved there piece by
+// piece, as the other endpoint acknowledges the previous bytes.
+struct StreamedBody {
+    bytes: Vec<u8>,
+    // How many bytes have been moved to the send buffer so far.
+    offset: usize,
+    // Whether the pieces are sent as chunks (the body of HTTP/1.1 responses), or as they are
+    // (the remainder of HTTP/1.0 responses, which announce their content length).
+    chunked: bool,
+}
 
-// Represents the local endpoint of a HTTP over TCP connection which carries GET requests
-// to the MMDS.
+// Represents the local endpoint of a HTTP over TCP connection which carries requests to the
+// MMDS. The connection is kept alive between requests, unless the other endpoint asks otherwise,
+// and pipelined requests are answered in order. Requests may also be held back, until their
+// answer changes, or they time out.
 pub struct Endpoint {
     // A fixed size buffer used to store bytes received via TCP. If the current request does not
     // fit within, we reset the connection, since we see this as a hard memory bound.
-    receive_buf: [u8; RCV_BUF_MAX_SIZE],
+    receive_buf: Vec<u8>,
     // Represents the next available position in the buffer.
     receive_buf_left: usize,
-    // This is filled with the HTTP response bytes after we parse a request and generate the reply.
+    // This is filled with the HTTP response bytes after we parse requests and generate the
+    // replies. Bytes are removed from the front once the other endpoint acknowledges them.
     response_buf: Vec<u8>,
-    // Initial response sequence, used to track if the entire `response_buf` was sent.
-    initial_response_seq: Wrapping<u32>,
+    // We stop answering requests while response_buf holds at least these many bytes, and
+    // stream the bodies which do not fit.
+    send_buf_size: usize,
     // Represents the sequence number associated with the first byte from response_buf.
     response_seq: Wrapping<u32>,
+    // The rest of a response too large for response_buf.
+    streamed_body: Option<StreamedBody>,
+    // Set after answering a request which asked for the connection to be closed. We close our
+    // half of the connection once the response is sent, and ignore any further requests.
+    close_after_response: bool,
+    // Set while the first request from receive_buf is held back, to the monotonic time (in
+    // nanoseconds) when it times out.
+    wait_deadline: Option<u64>,
     // The TCP connection that does all the receiving/sending work.
     connection: Connection,
     // Timestamp (in cycles) associated with the most recent reception of a segment.
@@ -72,6 +98,8 @@ pub struct Endpoint {
 // increases a metric).
 // - After calling either of the previous functions, the user should also call is_done() to see
 // if the Endpoint is finished.
+// - While is_waiting() returns true, poll_waiting_request() must be called every now and then, to
+// check whether the request which is held back can be answered.
 // - The is_evictable() function returns true if the Endpoint can be destroyed as far as its
 // internal logic is concerned. It's going to be used by the connection handler when trying to
 // find a new slot for incoming connections if none are free (when replacing an existing connection
@@ -80,30 +108,34 @@ pub struct Endpoint {
 impl Endpoint {
     pub fn new<T: NetworkBytes>(
         segment: &TcpSegment<T>,
+        limits: EndpointLimits,
         eviction_threshold: NonZeroU64,
         connection_rto_period: NonZeroU64,
         connection_rto_count_max: NonZeroU16,
     ) -> Result<Self, PassiveOpenError> {
         // TODO: mention this in doc comment for function
         // This simplifies things, and is a very reasonable assumption.
-        assert!(RCV_BUF_MAX_SIZE <= MAX_WINDOW_SIZE as usize);
+        assert!(limits.receive_buf_size <= MAX_WINDOW_SIZE as usize);
 
         let connection = Connection::passive_open(
             segment,
-            RCV_BUF_MAX_SIZE as u32,
+            limits.receive_buf_size as u32,
             connection_rto_period,
             connection_rto_count_max,
         )?;
 
         Ok(Endpoint {
-            receive_buf: [0u8; RCV_BUF_MAX_SIZE],
+            receive_buf: vec![0u8; limits.receive_buf_size],
             receive_buf_left: 0,
             response_buf: Vec::new(),
+            send_buf_size: limits.send_buf_size,
             // TODO: Using first_not_sent() makes sense here because a connection is currently
             // created via passive open only, so this points to the sequence number right after
             // the SYNACK. It might stop working like that if/when the implementation changes.
             response_seq: connection.first_not_sent(),
-            initial_response_seq: connection.first_not_sent(),
+            streamed_body: None,
+            close_after_response: false,
+            wait_deadline: None,
             connection,
             last_segment_received_timestamp: timestamp_cycles(),
             eviction_threshold: eviction_threshold.get(),
@@ -111,22 +143,24 @@ impl Endpoint {
         })
     }
 
-    pub fn new_with_defaults<T: NetworkBytes>(
+    pub fn new_with_limits<T: NetworkBytes>(
         segment: &TcpSegment<T>,
+        limits: EndpointLimits,
     ) -> Result<Self, PassiveOpenError> {
         // The unwraps are safe because the constants are greater than 0.
         Self::new(
             segment,
+            limits,
             NonZeroU64::new(EVICTION_THRESHOLD).unwrap(),
             NonZeroU64::new(CONNECTION_RTO_PERIOD).unwrap(),
             NonZeroU16::new(CONNECTION_RTO_COUNT_MAX).unwrap(),
         )
     }
 
-    pub fn receive_segment<T: NetworkBytes>(
+    pub fn receive_segment<T: NetworkBytes, R: Into<Reply>, F: Fn(Request) -> R>(
         &mut self,
         s: &TcpSegment<T>,
-        callback: fn(Request) -> Response,
+        callback: F,
     ) {
         if self.stop_receiving {
             return;
@@ -165,80 +199,46 @@ impl Endpoint {
             self.receive_buf_left += len.get();
         };
 
-        if !self.response_buf.is_empty()
-            && self.connection.highest_ack_received()
-                == self.initial_response_seq + Wrapping(self.response_buf.len() as u32)
-        {
-            // If we got here, then we still have some response bytes to send (which are
-            // stored in self.response_buf).
-
-            // It seems we just recevied the last ACK we were waiting for, so the entire
-            // response has been successfully received. Set the new response_seq and clear
-            // the response_buf.
-            self.response_seq = self.connection.highest_ack_received();
-            self.initial_response_seq = self.response_seq;
-            self.response_buf.clear();
-        }
-
-        if self.response_buf.is_empty() {
-            // There's no pending response currently, so we're back to waiting for a request to be
-            // available in self.receive_buf.
-
-            // The following is some ugly but workable code that attempts to find the end of an
-            // HTTP 1.x request in receive_buf. We need to do this for now because parse_request_bytes()
-            // expects the entire request contents as parameter.
-            if self.receive_buf_left > 2 {
-                let b = self.receive_buf.as_mut();
-                for i in 0..self.receive_buf_left - 1 {
-                    // We're basically looking for a double new line, which can only appear at the
-                    // end of a valid request.
-                    if b[i] == b'\n' {
-                        let end = if b[i + 1] == b'\n' {
-                            i + 2
-                        } else if i + 3 <= self.receive_buf_left && &b[i + 1..i + 3] == b"\r\n" {
-                            i + 3
-                        } else {
-                            continue;
-                        };
-
-                        // We found a potential request, let's parse it.
-                        let response = parse_request_bytes(&b[..end], callback);
-
-                        // The unwrap is safe because a Vec will allocate more space until all the
-                        // writes succeed.
-                        response.write_all(&mut self.response_buf).unwrap();
-
-                        // Sanity check because the current logic operates under this assumption.
-                        assert!(self.response_buf.len() < u32::max_value() as usize);
-
-                        // We have to remove the bytes up to end from receive_buf, by shifting the
-                        // others to the beginning of the buffer, and updating receive_buf_left.
-                        // Also, advance the rwnd edge of the inner connection.
-                        // TODO: Maximum efficiency.
-                        for j in 0..b.len() - end {
-                            b[j] = b[j + end];
-                        }
-                        self.receive_buf_left -= end;
-                        self.connection.advance_local_rwnd_edge(end as u32);
-                        break;
-                    }
-                }
-            }
+        // Drop the response bytes which the other endpoint acknowledged. The ACK may go one
+        // past the last response byte, because our FIN also takes up a sequence number.
+        let highest_ack_received = self.connection.highest_ack_received();
+        if seq_after(highest_ack_received, self.response_seq) {
+            let acked = min(
+                (highest_ack_received - self.response_seq).0 as usize,
+                self.response_buf.len(),
+            );
+            self.response_buf.drain(..acked);
+            self.response_seq += Wrapping(acked as u32);
+        }
 
-            if self.receive_buf_left == self.receive_buf.len() {
-                // If we get here the buffer is full, but we still couldn't identify the end of a
-                // request, so we reset because we are over the maximum request size.
-                self.connection.reset();
-                self.stop_receiving = true;
-                return;
-            }
+        self.answer_requests(&callback);
+
+        let request_fits = match request_len(&self.receive_buf[..self.receive_buf_left]) {
+            Some(len) => len <= self.receive_buf.len(),
+            None => self.receive_buf_left < self.receive_buf.len(),
+        };
+        if !request_fits && !self.close_after_response {
+            // If we get here the buffer cannot hold the current request, either because it's
+            // already full but we still couldn't identify the end of the request, or because of
+            // the announced length of the body. We reset because we are over the maximum request
+            // size.
+            self.connection.reset();
+            self.stop_receiving = true;
+            return;
         }
 
-        // We close the connection after receiving a FIN, and making sure there are no more
-        // responses to send.
-        if self.connection.fin_received() && self.response_buf.is_empty() {
-            self.connection.close();
+        self.close_if_done();
+    }
+
+    /// Hands the request which is held back to the callback again, and answers it (along with
+    /// the ones pipelined after it) if the callback no longer asks to wait, or if it timed out.
+    pub fn poll_waiting_request<R: Into<Reply>, F: Fn(Request) -> R>(&mut self, callback: F) {
+        if self.stop_receiving || self.wait_deadline.is_none() {
+            return;
         }
+
+        self.answer_requests(&callback);
+        self.close_if_done();
     }
 
     pub fn write_next_segment<'a>(
@@ -246,12 +246,10 @@ impl Endpoint {
         buf: &'a mut [u8],
         mss_reserved: u16,
     ) -> Option<Incomplete<TcpSegment<'a, &'a mut [u8]>>> {
+        // The connection picks the bytes to send (or retransmit) from the response bytes which
+        // have not been acknowledged yet.
         let tcp_payload_src = if !self.response_buf.is_empty() {
-            let offset = self.response_seq - self.initial_response_seq;
-            Some((
-                self.response_buf.split_at(offset.0 as usize).1,
-                self.response_seq,
-            ))
+            Some((self.response_buf.as_slice(), self.response_seq))
         } else {
             None
         };
@@ -262,10 +260,10 @@ impl Endpoint {
             tcp_payload_src,
             timestamp_cycles(),
         ) {
-            Ok(write_result) => write_result.map(|segment| {
-                self.response_seq += Wrapping(segment.inner().payload_len() as u32);
-                segment
-            }),
+            Ok(write_result) => {
+                self.close_if_done();
+                write_result
+            }
             Err(_) => {
                 METRICS.mmds.tx_errors.inc();
                 None
@@ -278,6 +276,19 @@ impl Endpoint {
         self.connection.is_done()
     }
 
+    /// Returns true while a request is held back, waiting to be answered.
+    #[inline]
+    pub fn is_waiting(&self) -> bool {
+        self.wait_deadline.is_some()
+    }
+
+    /// Returns true if the request which is held back timed out at `now`, a monotonic time in
+    /// nanoseconds.
+    #[inline]
+    pub fn wait_expired(&self, now: u64) -> bool {
+        self.wait_deadline.map_or(false, |deadline| now >= deadline)
+    }
+
     #[inline]
     pub fn is_evictable(&self) -> bool {
         timestamp_cycles().wrapping_sub(self.last_segment_received_timestamp)
@@ -285,7 +296,7 @@ impl Endpoint {
     }
 
     pub fn next_segment_status(&self) -> NextSegmentStatus {
-        let can_send_new_data = !self.response_buf.is_empty()
+        let can_send_new_data = seq_after(self.response_end(), self.connection.first_not_sent())
             && seq_after(
                 self.connection.remote_rwnd_edge(),
                 self.connection.first_not_sent(),
@@ -302,6 +313,201 @@ impl Endpoint {
     pub fn connection(&self) -> &Connection {
         &self.connection
     }
+
+    // Makes room for the rest of a streamed response first, and then answers the requests
+    // available in receive_buf, in the order they arrived, as long as there's room for the
+    // responses, and the first of them does not have to wait.
+    fn answer_requests<R: Into<Reply>, F: Fn(Request) -> R>(&mut self, callback: &F) {
+        self.stream_body();
+        while self.streamed_body.is_none()
+            && !self.close_after_response
+            && self.response_buf.len() < self.send_buf_size
+        {
+            let end = match request_len(&self.receive_buf[..self.receive_buf_left]) {
+                Some(len) if len <= self.receive_buf_left => len,
+                _ => break,
+            };
+
+            // Requests also tell us whether to keep the connection alive afterwards.
+            let mut keep_alive = true;
+            let mut wait_timeout_ns = None;
+            let mut response = parse_request_bytes(&self.receive_buf[..end], |request| {
+                keep_alive = wants_keep_alive(&request);
+                match callback(request).into() {
+                    Reply::Response(response) => response,
+                    Reply::Wait {
+                        timeout_ns,
+                        response,
+                    } => {
+                        wait_timeout_ns = Some(timeout_ns);
+                        response
+                    }
+                }
+            });
+
+            // The request stays in receive_buf until it no longer has to wait, or it times out,
+            // in which case it's answered with the latest response.
+            if let Some(timeout_ns) = wait_timeout_ns {
+                let now = get_time_ns(ClockType::Monotonic);
+                let deadline = *self
+                    .wait_deadline
+                    .get_or_insert_with(|| now.saturating_add(timeout_ns));
+                if now < deadline {
+                    break;
+                }
+            }
+            self.wait_deadline = None;
+
+            if !keep_alive {
+                response.set_keep_alive(false);
+                self.close_after_response = true;
+            }
+            self.queue_response(response);
+
+            // We have to remove the bytes up to end from receive_buf, by shifting the others to
+            // the beginning of the buffer, and updating receive_buf_left. Also, advance the rwnd
+            // edge of the inner connection.
+            self.receive_buf.copy_within(end..self.receive_buf_left, 0);
+            self.receive_buf_left -= end;
+            self.connection.advance_local_rwnd_edge(end as u32);
+        }
+    }
+
+    // Returns the sequence number which comes right after the last response byte.
+    fn response_end(&self) -> Wrapping<u32> {
+        self.response_seq + Wrapping(self.response_buf.len() as u32)
+    }
+
+    // Writes the response to response_buf. Responses with bodies larger than the send buffer
+    // are streamed: HTTP/1.1 ones with the chunked transfer encoding, and HTTP/1.0 ones as they
+    // are, after announcing their content length.
+    fn queue_response(&mut self, mut response: Response) {
+        // Sanity check because the current logic operates under this assumption.
+        assert!(response.content_length() >= 0);
+
+        // The unwraps are safe because a Vec will allocate more space until all the writes
+        // succeed.
+        if response.content_length() as usize <= self.send_buf_size {
+            response.write_all(&mut self.response_buf).unwrap();
+        } else if response.http_version() == Version::Http11 {
+            let body = response.take_body_for_chunked_encoding();
+            response.write_all(&mut self.response_buf).unwrap();
+            self.streamed_body = body.map(|body| StreamedBody {
+                bytes: body.raw().to_vec(),
+                offset: 0,
+                chunked: true,
+            });
+        } else {
+            let mut bytes = Vec::new();
+            response.write_all(&mut bytes).unwrap();
+            self.streamed_body = Some(StreamedBody {
+                bytes,
+                offset: 0,
+                chunked: false,
+            });
+        }
+
+        self.stream_body();
+    }
+
+    // Moves as much of the streamed body as there's room for to response_buf.
+    fn stream_body(&mut self) {
+        let streamed_body = match self.streamed_body.as_mut() {
+            Some(streamed_body) => streamed_body,
+            None => return,
+        };
+
+        let room = self.send_buf_size.saturating_sub(self.response_buf.len());
+        let left = &streamed_body.bytes[streamed_body.offset..];
+        if streamed_body.chunked {
+            let len = min(left.len(), room.saturating_sub(CHUNK_FRAMING_MAX_LEN));
+            if len > 0 {
+                self.response_buf
+                    .extend_from_slice(format!("{:x}\r\n", len).as_bytes());
+                self.response_buf.extend_from_slice(&left[..len]);
+                self.response_buf.extend_from_slice(b"\r\n");
+                streamed_body.offset += len;
+            }
+            if streamed_body.offset == streamed_body.bytes.len() {
+                self.response_buf.extend_from_slice(LAST_CHUNK);
+            }
+        } else {
+            let len = min(left.len(), room);
+            self.response_buf.extend_from_slice(&left[..len]);
+            streamed_body.offset += len;
+        }
+
+        if streamed_body.offset == streamed_body.bytes.len() {
+            self.streamed_body = None;
+        }
+    }
+
+    // We close our half of the connection after sending every response byte, if the other
+    // endpoint asked for it, or if it closed its own half and there are no more requests to
+    // answer (as long as response_buf has room, and no request is held back, all the complete
+    // requests have been answered).
+    fn close_if_done(&mut self) {
+        let no_more_requests = self.close_after_response
+            || (self.connection.fin_received()
+                && self.response_buf.len() < self.send_buf_size
+                && self.wait_deadline.is_none());
+        if no_more_requests
+            && self.streamed_body.is_none()
+            && self.connection.first_not_sent() == self.response_end()
+        {
+            self.connection.close();
+        }
+    }
+}
+
+// Tells whether the other endpoint wants to keep the connection alive after the response.
+// HTTP/1.1 connections are persistent unless the request says otherwise, while HTTP/1.0 requests
+// have to ask for it.
+fn wants_keep_alive(request: &Request) -> bool {
+    match request.headers.custom_entry(CONNECTION_HEADER) {
+        Some(value) if value.eq_ignore_ascii_case("close") => false,
+        Some(value) if value.eq_ignore_ascii_case("keep-alive") => true,
+        _ => request.http_version() == Version::Http11,
+    }
+}
+
+// Returns the length of the first HTTP 1.x request from buf, once all its headers are there. The
+// request ends with an empty line, followed by as many body bytes as the Content-Length header
+// announces, so the returned length may be larger than buf.
+fn request_len(buf: &[u8]) -> Option<usize> {
+    // The following is some ugly but workable code that attempts to find the end of the request
+    // headers. We're basically looking for a double new line, which can only appear at the end of
+    // the headers of a valid request.
+    let headers_end = (0..buf.len().saturating_sub(1)).find_map(|i| {
+        if buf[i] != b'\n' {
+            None
+        } else if buf[i + 1] == b'\n' {
+            Some(i + 2)
+        } else if i + 3 <= buf.len() && &buf[i + 1..i + 3] == b"\r\n" {
+            Some(i + 3)
+        } else {
+            None
+        }
+    })?;
+
+    // Requests with an invalid Content-Length are rejected when parsed, so we simply assume they
+    // have no body here.
+    let content_length = buf[..headers_end]
+        .split(|&b| b == b'\n')
+        .filter_map(|line| {
+            let line = std::str::from_utf8(line).ok()?;
+            let mut entry = line.splitn(2, ':');
+            let (name, value) = (entry.next()?, entry.next()?);
+            if name.trim().eq_ignore_ascii_case(CONTENT_LENGTH_HEADER) {
+                value.trim().parse::<usize>().ok()
+            } else {
+                None
+            }
+        })
+        .next()
+        .unwrap_or(0);
+
+    Some(headers_end.saturating_add(content_length))
 }
 
 fn build_response(http_version: Version, status_code: StatusCode, body: Body) -> Response {
@@ -311,7 +517,10 @@ fn build_response(http_version: Version, status_code: StatusCode, body: Body) ->
 }
 
 /// Parses the request bytes and builds a `micro_http::Response` by the given callback function.
-fn parse_request_bytes(byte_stream: &[u8], callback: fn(Request) -> Response) -> Response {
+fn parse_request_bytes<F: FnMut(Request) -> Response>(
+    byte_stream: &[u8],
+    mut callback: F,
+) -> Response {
     let request = Request::try_from(byte_stream);
     match request {
         Ok(request) => callback(request),
@@ -369,12 +578,14 @@ fn parse_request_bytes(byte_stream: &[u8], callback: fn(Request) -> Response) ->
 mod tests {
     use super::*;
 
+    use std::cell::Cell;
     use std::fmt;
     use std::str::from_utf8;
 
     use crate::pdu::tcp::Flags as TcpFlags;
     use crate::tcp::connection::tests::ConnectionTester;
-    use crate::tcp::tests::mock_callback;
+    use crate::tcp::tests::{mock_callback, FuzzRng};
+    use crate::tcp::DEFAULT_RECEIVE_BUF_SIZE;
 
     impl Endpoint {
         pub fn set_eviction_threshold(&mut self, value: u64) {
@@ -388,13 +599,161 @@ mod tests {
         }
     }
 
+    // Plays the other end of a connection to an endpoint. Every segment it sends also ACKs all
+    // the bytes received so far.
+    struct Remote {
+        t: ConnectionTester,
+        seq: u32,
+        ack: u32,
+        received: Vec<u8>,
+        fin_received: bool,
+    }
+
+    impl Remote {
+        fn connect(limits: EndpointLimits) -> (Self, Endpoint) {
+            let t = ConnectionTester::new();
+            let mut syn_buf = [0u8; 100];
+            let mut write_buf = [0u8; 2000];
+
+            let syn = t.write_syn(syn_buf.as_mut());
+            let remote_isn = syn.sequence_number();
+            let mut e = Endpoint::new_with_limits(&syn, limits).unwrap();
+            let endpoint_isn = e
+                .write_next_segment(write_buf.as_mut(), t.mss_reserved)
+                .unwrap()
+                .inner()
+                .sequence_number();
+
+            let mut remote = Remote {
+                t,
+                seq: remote_isn.wrapping_add(1),
+                ack: endpoint_isn.wrapping_add(1),
+                received: Vec::new(),
+                fin_received: false,
+            };
+            remote.send(&mut e, b"", mock_callback);
+            assert!(e.connection().is_established());
+            (remote, e)
+        }
+
+        fn send_segment<R: Into<Reply>, F: Fn(Request) -> R>(
+            &mut self,
+            e: &mut Endpoint,
+            data: &[u8],
+            flags: TcpFlags,
+            callback: F,
+        ) {
+            let mut buf = [0u8; 2000];
+            let mut s = if data.is_empty() {
+                self.t.write_ctrl(buf.as_mut())
+            } else {
+                self.t.write_data(buf.as_mut(), data)
+            };
+            s.set_flags_after_ns(flags);
+            s.set_sequence_number(self.seq);
+            s.set_ack_number(self.ack);
+            e.receive_segment(&s, callback);
+            self.seq = self.seq.wrapping_add(data.len() as u32);
+        }
+
+        fn send<R: Into<Reply>, F: Fn(Request) -> R>(
+            &mut self,
+            e: &mut Endpoint,
+            data: &[u8],
+            callback: F,
+        ) {
+            self.send_segment(e, data, TcpFlags::ACK, callback);
+        }
+
+        fn close(&mut self, e: &mut Endpoint) {
+            self.send_segment(e, b"", TcpFlags::ACK | TcpFlags::FIN, mock_callback);
+            // 1 for the FIN.
+            self.seq = self.seq.wrapping_add(1);
+        }
+
+        // Reads every segment the endpoint has available right now.
+        fn read(&mut self, e: &mut Endpoint) {
+            let mut buf = [0u8; 2000];
+            while e.next_segment_status() == NextSegmentStatus::Available {
+                let incomplete = e
+                    .write_next_segment(buf.as_mut(), self.t.mss_reserved)
+                    .unwrap();
+                let s = incomplete.inner();
+                assert!(!s.flags_after_ns().intersects(TcpFlags::RST));
+                if s.sequence_number() == self.ack {
+                    self.received.extend_from_slice(s.payload());
+                    self.ack = self.ack.wrapping_add(s.payload_len() as u32);
+                    if s.flags_after_ns().intersects(TcpFlags::FIN) {
+                        self.fin_received = true;
+                        self.ack = self.ack.wrapping_add(1);
+                    }
+                }
+            }
+        }
+    }
+
+    // Answers with the path and the body of the request.
+    fn echo_callback(request: Request) -> Response {
+        let body = request.body.as_ref().map_or(&[][..], |body| body.raw());
+        let mut response = Response::new(request.http_version(), StatusCode::OK);
+        response.set_body(Body::new(format!(
+            "{} {}",
+            request.uri().get_abs_path(),
+            from_utf8(body).unwrap()
+        )));
+        response
+    }
+
+    // Splits the bytes received by the other end into the heads and the bodies of the responses,
+    // decoding the chunked ones.
+    fn parse_responses(mut bytes: &[u8]) -> Vec<(String, String)> {
+        fn find(bytes: &[u8], sequence: &[u8]) -> usize {
+            bytes
+                .windows(sequence.len())
+                .position(|window| window == sequence)
+                .unwrap()
+        }
+
+        let mut responses = Vec::new();
+        while !bytes.is_empty() {
+            let head_len = find(bytes, b"\r\n\r\n");
+            let head = from_utf8(&bytes[..head_len]).unwrap().to_string();
+            bytes = &bytes[head_len + 4..];
+
+            let mut body = Vec::new();
+            if head.contains("Transfer-Encoding: chunked") {
+                loop {
+                    let line_len = find(bytes, b"\r\n");
+                    let size =
+                        usize::from_str_radix(from_utf8(&bytes[..line_len]).unwrap(), 16).unwrap();
+                    bytes = &bytes[line_len + 2..];
+                    body.extend_from_slice(&bytes[..size]);
+                    bytes = &bytes[size + 2..];
+                    if size == 0 {
+                        break;
+                    }
+                }
+            } else {
+                let prefix = "Content-Length: ";
+                let content_length = head
+                    .lines()
+                    .find(|line| line.starts_with(prefix))
+                    .map_or(0, |line| line[prefix.len()..].parse().unwrap());
+                body.extend_from_slice(&bytes[..content_length]);
+                bytes = &bytes[content_length..];
+            }
+            responses.push((head, String::from_utf8(body).unwrap()));
+        }
+        responses
+    }
+
     #[test]
     #[allow(clippy::cognitive_complexity)]
     fn test_endpoint() {
         let mut buf1 = [0u8; 500];
         let mut buf2 = [0u8; 500];
 
-        let mut write_buf = [0u8; RCV_BUF_MAX_SIZE + 100];
+        let mut write_buf = [0u8; DEFAULT_RECEIVE_BUF_SIZE + 100];
 
         let mut t = ConnectionTester::new();
 
@@ -403,14 +762,14 @@ mod tests {
         // Put another flag on the SYN so it becomes invalid.
         syn.set_flags_after_ns(TcpFlags::ACK);
         assert_eq!(
-            Endpoint::new_with_defaults(&syn).unwrap_err(),
+            Endpoint::new_with_limits(&syn, EndpointLimits::default()).unwrap_err(),
             PassiveOpenError::InvalidSyn
         );
 
         // Fix the SYN and create an endpoint.
         syn.set_flags_after_ns(TcpFlags::SYN);
         let remote_isn = syn.sequence_number();
-        let mut e = Endpoint::new_with_defaults(&syn).unwrap();
+        let mut e = Endpoint::new_with_limits(&syn, EndpointLimits::default()).unwrap();
 
         // Let's complete the three-way handshake. The next segment sent by the endpoint should
         // be a SYNACK.
@@ -569,11 +928,11 @@ mod tests {
 
         // Finally, let's fill self.receive_buf with the following request, and see if we get the
         // reset we expect on the next segment.
-        let request_to_fill = vec![0u8; RCV_BUF_MAX_SIZE - e.receive_buf_left];
+        let request_to_fill = vec![0u8; DEFAULT_RECEIVE_BUF_SIZE - e.receive_buf_left];
 
         {
             // Hack: have to artificially increase t.mss to create this segment which is 2k+.
-            t.mss = RCV_BUF_MAX_SIZE as u16;
+            t.mss = DEFAULT_RECEIVE_BUF_SIZE as u16;
             let mut data = t.write_data(write_buf.as_mut(), request_to_fill.as_ref());
 
             data.set_flags_after_ns(TcpFlags::ACK);
@@ -664,4 +1023,256 @@ mod tests {
         let actual_response = parse_request_bytes(request_bytes, mock_callback);
         assert_eq!(actual_response, expected_response);
     }
+
+    #[test]
+    fn test_pipelining() {
+        let (mut remote, mut e) = Remote::connect(EndpointLimits::default());
+
+        // Requests sent back to back are answered in order.
+        remote.send(
+            &mut e,
+            b"GET /a HTTP/1.1\r\n\r\n\
+              PUT /b HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody\
+              GET /c HTTP/1.1\r\n\r\n",
+            echo_callback,
+        );
+        remote.read(&mut e);
+        let responses = parse_responses(&remote.received);
+        let bodies: Vec<&str> = responses.iter().map(|(_, body)| body.as_str()).collect();
+        assert_eq!(bodies, ["/a ", "/b body", "/c "]);
+        assert!(responses
+            .iter()
+            .all(|(head, _)| head.contains("Connection: keep-alive")));
+        assert!(!remote.fin_received);
+
+        // Requests split across segments are answered once their body is complete.
+        remote.received.clear();
+        remote.send(&mut e, b"PUT /d HTTP/1.1\r\nContent-Le", echo_callback);
+        remote.read(&mut e);
+        remote.send(&mut e, b"ngth: 2\r\n\r\nx", echo_callback);
+        remote.read(&mut e);
+        assert!(remote.received.is_empty());
+        remote.send(&mut e, b"y", echo_callback);
+        remote.read(&mut e);
+        assert_eq!(parse_responses(&remote.received)[0].1, "/d xy");
+
+        // The endpoint closes its half of the connection after the other one does.
+        remote.close(&mut e);
+        remote.read(&mut e);
+        assert!(remote.fin_received);
+        remote.send(&mut e, b"", echo_callback);
+        assert!(e.is_done());
+    }
+
+    #[test]
+    fn test_connection_close() {
+        let requests: [&[u8]; 2] = [
+            b"GET /a HTTP/1.1\r\nConnection: close\r\n\r\n",
+            b"GET /a HTTP/1.0\r\n\r\n",
+        ];
+        for request in requests.iter() {
+            let (mut remote, mut e) = Remote::connect(EndpointLimits::default());
+
+            // The requests following the one which closes the connection are ignored.
+            let pipelined = [*request, b"GET /b HTTP/1.1\r\n\r\n"].concat();
+            remote.send(&mut e, &pipelined, echo_callback);
+            remote.read(&mut e);
+            remote.send(&mut e, b"", echo_callback);
+            remote.read(&mut e);
+
+            let responses = parse_responses(&remote.received);
+            assert_eq!(responses.len(), 1);
+            assert!(responses[0].0.contains("Connection: close"));
+            assert_eq!(responses[0].1, "/a ");
+            assert!(remote.fin_received);
+
+            remote.close(&mut e);
+            assert!(e.is_done());
+        }
+
+        // HTTP/1.0 requests can ask to keep the connection alive.
+        let (mut remote, mut e) = Remote::connect(EndpointLimits::default());
+        remote.send(
+            &mut e,
+            b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+            echo_callback,
+        );
+        remote.read(&mut e);
+        remote.send(&mut e, b"", echo_callback);
+        remote.read(&mut e);
+        assert!(parse_responses(&remote.received)[0]
+            .0
+            .contains("Connection: keep-alive"));
+        assert!(!remote.fin_received);
+    }
+
+    #[test]
+    fn test_streamed_responses() {
+        let limits = EndpointLimits {
+            receive_buf_size: DEFAULT_RECEIVE_BUF_SIZE,
+            send_buf_size: 100,
+        };
+        let body: String = (0..1000u32)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        let callback = |request: Request| {
+            let mut response = Response::new(request.http_version(), StatusCode::OK);
+            response.set_body(Body::new(body.clone()));
+            response
+        };
+
+        for version in ["HTTP/1.1", "HTTP/1.0"].iter() {
+            let (mut remote, mut e) = Remote::connect(limits);
+            let request = format!("GET / {}\r\nConnection: keep-alive\r\n\r\n", version);
+            remote.send(&mut e, request.repeat(2).as_bytes(), callback);
+
+            // The responses do not fit in the send buffer, so they only make progress as the
+            // other end acknowledges the previous bytes.
+            for _ in 0..100 {
+                remote.read(&mut e);
+                assert!(e.response_buf.len() <= 2 * limits.send_buf_size);
+                remote.send(&mut e, b"", callback);
+            }
+
+            let responses = parse_responses(&remote.received);
+            assert_eq!(responses.len(), 2);
+            for (head, response_body) in responses.iter() {
+                assert!(head.starts_with(version));
+                if *version == "HTTP/1.1" {
+                    assert!(head.contains("Transfer-Encoding: chunked"));
+                } else {
+                    assert!(head.contains("Content-Length: 1000"));
+                }
+                assert_eq!(response_body, &body);
+            }
+        }
+    }
+
+    #[test]
+    fn test_waiting_requests() {
+        // Holds the requests back while the version matches the path, and times out after an
+        // hour, unless told otherwise.
+        let version = Cell::new(0);
+        let timeout_ns = Cell::new(3_600_000_000_000);
+        let callback = |request: Request| {
+            let response = echo_callback(request);
+            if response.body().unwrap().raw() == format!("/{} ", version.get()).as_bytes() {
+                Reply::Wait {
+                    timeout_ns: timeout_ns.get(),
+                    response,
+                }
+            } else {
+                Reply::Response(response)
+            }
+        };
+
+        let (mut remote, mut e) = Remote::connect(EndpointLimits::default());
+        remote.send(
+            &mut e,
+            b"GET /0 HTTP/1.1\r\n\r\nGET /a HTTP/1.1\r\n\r\n",
+            callback,
+        );
+        remote.read(&mut e);
+        assert!(e.is_waiting());
+        assert!(!e.wait_expired(get_time_ns(ClockType::Monotonic)));
+        assert!(e.wait_expired(u64::MAX));
+        assert!(remote.received.is_empty());
+
+        // Nothing changes until the version does, and the pipelined requests wait as well.
+        e.poll_waiting_request(callback);
+        remote.read(&mut e);
+        assert!(e.is_waiting());
+        assert!(remote.received.is_empty());
+
+        version.set(1);
+        e.poll_waiting_request(callback);
+        remote.read(&mut e);
+        assert!(!e.is_waiting());
+        assert!(!e.wait_expired(u64::MAX));
+        let responses = parse_responses(&remote.received);
+        let bodies: Vec<&str> = responses.iter().map(|(_, body)| body.as_str()).collect();
+        assert_eq!(bodies, ["/0 ", "/a "]);
+
+        // Requests which time out are answered with the latest response.
+        remote.received.clear();
+        timeout_ns.set(0);
+        remote.send(&mut e, b"GET /1 HTTP/1.1\r\n\r\n", callback);
+        remote.read(&mut e);
+        assert!(!e.is_waiting());
+        assert_eq!(parse_responses(&remote.received)[0].1, "/1 ");
+
+        // The connection stays open while a request waits, even if the other end closed its
+        // half.
+        remote.received.clear();
+        timeout_ns.set(3_600_000_000_000);
+        remote.send(&mut e, b"GET /1 HTTP/1.1\r\n\r\n", callback);
+        remote.send_segment(&mut e, b"", TcpFlags::ACK | TcpFlags::FIN, callback);
+        remote.seq = remote.seq.wrapping_add(1);
+        remote.read(&mut e);
+        assert!(e.is_waiting());
+        assert!(!remote.fin_received);
+
+        version.set(2);
+        e.poll_waiting_request(callback);
+        remote.read(&mut e);
+        assert_eq!(parse_responses(&remote.received)[0].1, "/1 ");
+        assert!(remote.fin_received);
+    }
+
+    #[test]
+    fn test_fuzz_endpoint() {
+        // Sends pipelined requests split at random points, to endpoints with random buffer
+        // sizes, and checks that every request is answered, in order.
+        let mut rng = FuzzRng::new(0x00e1_d901);
+
+        for _ in 0..20 {
+            let limits = EndpointLimits {
+                receive_buf_size: 1024 + rng.below(1024) as usize,
+                send_buf_size: 100 + rng.below(1000) as usize,
+            };
+            let (mut remote, mut e) = Remote::connect(limits);
+
+            let mut requests = Vec::new();
+            let mut expected = Vec::new();
+            for i in 0..30 {
+                if rng.below(2) == 0 {
+                    requests.extend_from_slice(format!("GET /{} HTTP/1.1\r\n\r\n", i).as_bytes());
+                    expected.push(format!("/{} ", i));
+                } else {
+                    let body = "x".repeat(1 + rng.below(200) as usize);
+                    requests.extend_from_slice(
+                        format!(
+                            "PUT /{} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                            i,
+                            body.len(),
+                            body
+                        )
+                        .as_bytes(),
+                    );
+                    expected.push(format!("/{} {}", i, body));
+                }
+            }
+
+            let mut sent = 0;
+            while sent < requests.len() {
+                // Never send beyond the receive window of the endpoint.
+                let window = limits.receive_buf_size - e.receive_buf_left;
+                let len = min(
+                    1 + rng.below(300) as usize,
+                    min(requests.len() - sent, window),
+                );
+                remote.send(&mut e, &requests[sent..sent + len], echo_callback);
+                sent += len;
+                remote.read(&mut e);
+            }
+            for _ in 0..100 {
+                remote.send(&mut e, b"", echo_callback);
+                remote.read(&mut e);
+            }
+
+            let responses = parse_responses(&remote.received);
+            let bodies: Vec<&String> = responses.iter().map(|(_, body)| body).collect();
+            assert_eq!(bodies, expected.iter().collect::<Vec<_>>());
+        }
+    }
 }
@@ -1,22 +1,24 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-//! Exposes simple TCP over IPv4 listener functionality via the [`TcpIPv4Handler`] structure.
+//! Exposes simple TCP over IPv4 or IPv6 listener functionality via the [`TcpIPHandler`]
+//! structure.
 //!
-//! [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
+//! [`TcpIPHandler`]: struct.TcpIPHandler.html
 
 use std::collections::{HashMap, HashSet};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
 
 use crate::pdu::bytes::NetworkBytes;
 use crate::pdu::ipv4::{Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP};
+use crate::pdu::ipv6::{Error as IPv6PacketError, IPv6Packet};
 use crate::pdu::tcp::{Error as TcpSegmentError, Flags as TcpFlags, TcpSegment};
+use crate::pdu::Incomplete;
 use crate::tcp::endpoint::Endpoint;
-use crate::tcp::{NextSegmentStatus, RstConfig};
-use micro_http::{Request, Response};
-
-// TODO: This is currently IPv4 specific. Maybe change it to a more generic implementation.
+use crate::tcp::{EndpointLimits, NextSegmentStatus, Reply, RstConfig};
+use micro_http::Request;
+use utils::time::{get_time_ns, ClockType};
 
 /// Describes events which may occur when the handler receives packets.
 #[cfg_attr(test, derive(Debug, PartialEq))]
@@ -51,12 +53,14 @@ pub enum WriteEvent {
 }
 
 /// Describes errors which may be encountered by the [`receive_packet`] method from
-/// [`TcpIPv4Handler`].
+/// [`TcpIPHandler`].
 ///
-/// [`receive_packet`]: struct.TcpIPv4Handler.html#method.receive_packet
-/// [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
+/// [`receive_packet`]: struct.TcpIPHandler.html#method.receive_packet
+/// [`TcpIPHandler`]: struct.TcpIPHandler.html
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum RecvError {
+    /// The packet and the handler use different IP versions.
+    AddressFamily,
     /// The inner segment has an invalid destination port.
     InvalidPort,
     /// The handler encountered an error while parsing the inner TCP segment.
@@ -64,30 +68,34 @@ pub enum RecvError {
 }
 
 /// Describes errors which may be encountered by the [`write_next_packet`] method from
-/// [`TcpIPv4Handler`].
+/// [`TcpIPHandler`].
 ///
-/// [`write_next_packet`]: struct.TcpIPv4Handler.html#method.write_next_packet
-/// [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
+/// [`write_next_packet`]: struct.TcpIPHandler.html#method.write_next_packet
+/// [`TcpIPHandler`]: struct.TcpIPHandler.html
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum WriteNextError {
+    /// The remote address of a connection and the handler use different IP versions.
+    AddressFamily,
     /// There was an error while writing the contents of the IPv4 packet.
     IPv4Packet(IPv4PacketError),
+    /// There was an error while writing the contents of the IPv6 packet.
+    IPv6Packet(IPv6PacketError),
     /// There was an error while writing the contents of the inner TCP segment.
     TcpSegment(TcpSegmentError),
 }
 
-// Generally speaking, a TCP/IPv4 connection is identified using the four-tuple (src_addr, src_port,
-// dst_addr, dst_port). However, the IPv4 address and TCP port of the MMDS endpoint are fixed, so
+// Generally speaking, a TCP/IP connection is identified using the four-tuple (src_addr, src_port,
+// dst_addr, dst_port). However, the IP address and TCP port of the MMDS endpoint are fixed, so
 // we can get away with uniquely identifying connections using just the remote address and port.
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 #[cfg_attr(test, derive(Debug))]
 struct ConnectionTuple {
-    remote_addr: Ipv4Addr,
+    remote_addr: IpAddr,
     remote_port: u16,
 }
 
 impl ConnectionTuple {
-    fn new(remote_addr: Ipv4Addr, remote_port: u16) -> Self {
+    fn new(remote_addr: IpAddr, remote_port: u16) -> Self {
         ConnectionTuple {
             remote_addr,
             remote_port,
@@ -95,20 +103,21 @@ impl ConnectionTuple {
     }
 }
 
-/// Implements a minimalist TCP over IPv4 listener.
+/// Implements a minimalist TCP over IPv4 or IPv6 listener, depending on the family of the local
+/// address.
 ///
 /// Forwards incoming TCP segments to the appropriate connection object, based on the associated
 /// tuple, or attempts to establish new connections (when receiving `SYN` segments). Aside from
 /// constructors, the handler operation is based on three methods:
 ///
-/// * [`receive_packet`] examines an incoming IPv4 packet. It checks whether the destination
+/// * [`receive_packet`] (or [`receive_ipv6_packet`]) examines an incoming IP packet. It checks whether the destination
 ///   address is correct, the attempts examine the inner TCP segment, making sure the destination
 ///   port number is also correct. Then, it steers valid segments towards exiting connections,
 ///   creates new connections for incoming `SYN` segments, and enqueues `RST` replies in response
 ///   to any segments which cannot be associated with a connection (except other `RST` segments).
 ///   On success, also describes any internal status changes triggered by the reception of the
 ///   packet.
-/// * [`write_next_packet`] writes the next IPv4 packet (if available) that would be sent by the
+/// * [`write_next_packet`] writes the next IP packet (if available) that would be sent by the
 ///   handler itself (right now it can only mean an enqueued `RST`), or one of the existing
 ///   connections. On success, also describes any internal status changes triggered as the packet
 ///   gets transmitted.
@@ -117,12 +126,13 @@ impl ConnectionTuple {
 ///   to send for the moment. This is used to determine whether it's appropriate to call
 ///   [`write_next_packet`].
 ///
-/// [`receive_packet`]: ../handler/struct.TcpIPv4Handler.html#method.receive_packet
-/// [`write_next_packet`]: ../handler/struct.TcpIPv4Handler.html#method.write_next_packet
-/// [`next_segment_status`]: ../handler/struct.TcpIPv4Handler.html#method.next_segment_status
-pub struct TcpIPv4Handler {
-    // Handler IPv4 address used for every connection.
-    local_ipv4_addr: Ipv4Addr,
+/// [`receive_packet`]: ../handler/struct.TcpIPHandler.html#method.receive_packet
+/// [`receive_ipv6_packet`]: ../handler/struct.TcpIPHandler.html#method.receive_ipv6_packet
+/// [`write_next_packet`]: ../handler/struct.TcpIPHandler.html#method.write_next_packet
+/// [`next_segment_status`]: ../handler/struct.TcpIPHandler.html#method.next_segment_status
+pub struct TcpIPHandler {
+    // Handler IP address used for every connection.
+    local_addr: IpAddr,
     // Handler TCP port used for every connection.
     local_port: u16,
     // This map holds the currently active endpoints, identified by their connection tuple.
@@ -131,6 +141,8 @@ pub struct TcpIPv4Handler {
     max_connections: usize,
     // Holds connections which are able to send segments immediately.
     active_connections: HashSet<ConnectionTuple>,
+    // Holds connections whose endpoints hold a request back, waiting to answer it.
+    waiting_connections: HashSet<ConnectionTuple>,
     // Remembers the closest timestamp into the future when one of the connections has to deal
     // with an RTO trigger.
     next_timeout: Option<(u64, ConnectionTuple)>,
@@ -138,6 +150,8 @@ pub struct TcpIPv4Handler {
     rst_queue: Vec<(ConnectionTuple, RstConfig)>,
     // Maximum size of the RST queue.
     max_pending_resets: usize,
+    // Buffer sizes of the endpoints created for new connections.
+    endpoint_limits: EndpointLimits,
 }
 
 // Only used locally, in the receive_packet method, to differentiate between different outcomes
@@ -149,41 +163,100 @@ enum RecvSegmentOutcome {
     UnexpectedSegment(bool),
 }
 
-impl TcpIPv4Handler {
-    /// Creates a new `TcpIPv4Handler`.
+// Only used locally, in the write_next_packet method, to hold the IP packet which encloses the
+// next segment, according to the family of the local address.
+enum IncompleteIPPacket<'a> {
+    V4(Incomplete<IPv4Packet<'a, &'a mut [u8]>>),
+    V6(Incomplete<IPv6Packet<'a, &'a mut [u8]>>),
+}
+
+impl<'a> IncompleteIPPacket<'a> {
+    fn write_header(buf: &'a mut [u8], local_addr: IpAddr) -> Result<Self, WriteNextError> {
+        // The destination address is filled in by finalize().
+        match local_addr {
+            IpAddr::V4(addr) => {
+                IPv4Packet::write_header(buf, PROTOCOL_TCP, addr, Ipv4Addr::LOCALHOST)
+                    .map(IncompleteIPPacket::V4)
+                    .map_err(WriteNextError::IPv4Packet)
+            }
+            IpAddr::V6(addr) => {
+                IPv6Packet::write_header(buf, PROTOCOL_TCP, addr, Ipv6Addr::LOCALHOST)
+                    .map(IncompleteIPPacket::V6)
+                    .map_err(WriteNextError::IPv6Packet)
+            }
+        }
+    }
+
+    fn payload_mut(&mut self) -> &mut [u8] {
+        match self {
+            IncompleteIPPacket::V4(packet) => packet.inner_mut().payload_mut(),
+            IncompleteIPPacket::V6(packet) => packet.inner_mut().payload_mut(),
+        }
+    }
+
+    // Completes the packet, and returns its length.
+    fn finalize(self, remote_addr: IpAddr, payload_len: usize) -> Result<usize, WriteNextError> {
+        match (self, remote_addr) {
+            (IncompleteIPPacket::V4(mut packet), IpAddr::V4(addr)) => {
+                packet.inner_mut().set_destination_address(addr);
+                Ok(packet.with_payload_len_unchecked(payload_len, true).len())
+            }
+            (IncompleteIPPacket::V6(mut packet), IpAddr::V6(addr)) => {
+                packet.inner_mut().set_destination_address(addr);
+                Ok(packet.with_payload_len_unchecked(payload_len).len())
+            }
+            _ => Err(WriteNextError::AddressFamily),
+        }
+    }
+}
+
+impl TcpIPHandler {
+    /// Creates a new `TcpIPHandler`.
     ///
     /// The handler acts as if bound to `local_addr`:`local_port`, and will accept at most
     /// `max_connections` concurrent connections. `RST` segments generated by unexpected incoming
     /// segments are placed in a queue which is at most `max_pending_resets` long.
     #[inline]
     pub fn new(
-        local_ipv4_addr: Ipv4Addr,
+        local_addr: IpAddr,
         local_port: u16,
         max_connections: NonZeroUsize,
         max_pending_resets: NonZeroUsize,
     ) -> Self {
         let max_connections = max_connections.get();
         let max_pending_resets = max_pending_resets.get();
-        TcpIPv4Handler {
-            local_ipv4_addr,
+        TcpIPHandler {
+            local_addr,
             local_port,
             connections: HashMap::with_capacity(max_connections),
             max_connections,
             active_connections: HashSet::with_capacity(max_connections),
+            waiting_connections: HashSet::new(),
             next_timeout: None,
             rst_queue: Vec::with_capacity(max_pending_resets),
             max_pending_resets,
+            endpoint_limits: EndpointLimits::default(),
         }
     }
 
-    /// Setter for the local IPv4 address of this TCP handler.
-    pub fn set_local_ipv4_addr(&mut self, ipv4_addr: Ipv4Addr) {
-        self.local_ipv4_addr = ipv4_addr;
+    /// Setter for the local IP address of this TCP handler.
+    ///
+    /// Existing connections and pending `RST` segments are dropped when the address family
+    /// changes, since they can no longer be reached.
+    pub fn set_local_addr(&mut self, addr: IpAddr) {
+        if addr.is_ipv4() != self.local_addr.is_ipv4() {
+            self.connections.clear();
+            self.active_connections.clear();
+            self.waiting_connections.clear();
+            self.next_timeout = None;
+            self.rst_queue.clear();
+        }
+        self.local_addr = addr;
     }
 
-    /// Returns the local IPv4 address of this TCP handler.
-    pub fn local_ipv4_addr(&self) -> Ipv4Addr {
-        self.local_ipv4_addr
+    /// Returns the local IP address of this TCP handler.
+    pub fn local_addr(&self) -> IpAddr {
+        self.local_addr
     }
 
     /// Returns the local port of this TCP handler.
@@ -201,28 +274,83 @@ impl TcpIPv4Handler {
         self.max_pending_resets
     }
 
-    /// Contains logic for handling incoming segments.
+    /// Setter for the maximum number of concurrent connections, and for the buffer sizes of the
+    /// endpoints created for new connections.
+    ///
+    /// Existing connections keep their buffers. If there are more of them than the new maximum,
+    /// they are evicted as usual to make room for new connections.
+    pub fn set_connection_limits(
+        &mut self,
+        max_connections: NonZeroUsize,
+        endpoint_limits: EndpointLimits,
+    ) {
+        self.max_connections = max_connections.get();
+        self.endpoint_limits = endpoint_limits;
+    }
+
+    /// Returns the buffer sizes of the endpoints created for new connections.
+    pub fn endpoint_limits(&self) -> EndpointLimits {
+        self.endpoint_limits
+    }
+
+    /// Contains logic for handling incoming segments carried by IPv4 packets.
     ///
     /// Any changes to the state if the handler are communicated through an `Ok(RecvEvent)`.
-    pub fn receive_packet<T: NetworkBytes>(
+    pub fn receive_packet<T: NetworkBytes, R: Into<Reply>, F: Fn(Request) -> R>(
         &mut self,
         packet: &IPv4Packet<T>,
-        callback: fn(Request) -> Response,
+        callback: F,
+    ) -> Result<RecvEvent, RecvError> {
+        self.receive_segment(
+            IpAddr::V4(packet.source_address()),
+            packet.payload(),
+            callback,
+        )
+    }
+
+    /// Contains logic for handling incoming segments carried by IPv6 packets.
+    ///
+    /// Any changes to the state if the handler are communicated through an `Ok(RecvEvent)`.
+    pub fn receive_ipv6_packet<T: NetworkBytes, R: Into<Reply>, F: Fn(Request) -> R>(
+        &mut self,
+        packet: &IPv6Packet<T>,
+        callback: F,
     ) -> Result<RecvEvent, RecvError> {
+        self.receive_segment(
+            IpAddr::V6(packet.source_address()),
+            packet.payload(),
+            callback,
+        )
+    }
+
+    fn receive_segment<R: Into<Reply>, F: Fn(Request) -> R>(
+        &mut self,
+        remote_addr: IpAddr,
+        payload: &[u8],
+        callback: F,
+    ) -> Result<RecvEvent, RecvError> {
+        if remote_addr.is_ipv4() != self.local_addr.is_ipv4() {
+            return Err(RecvError::AddressFamily);
+        }
+
         // TODO: We skip verifying the checksum, just in case the device model relies on offloading
         // checksum computation from the guest to some other entity. Clear this up at some point!
         // (Issue #520)
-        let segment =
-            TcpSegment::from_bytes(packet.payload(), None).map_err(RecvError::TcpSegment)?;
+        let segment = TcpSegment::from_bytes(payload, None).map_err(RecvError::TcpSegment)?;
 
         if segment.destination_port() != self.local_port {
             return Err(RecvError::InvalidPort);
         }
 
-        let tuple = ConnectionTuple::new(packet.source_address(), segment.source_port());
+        let tuple = ConnectionTuple::new(remote_addr, segment.source_port());
 
         let outcome = if let Some(endpoint) = self.connections.get_mut(&tuple) {
             endpoint.receive_segment(&segment, callback);
+            if endpoint.is_waiting() {
+                self.waiting_connections.insert(tuple);
+            } else {
+                self.waiting_connections.remove(&tuple);
+            }
             if endpoint.is_done() {
                 RecvSegmentOutcome::EndpointDone
             } else {
@@ -251,7 +379,7 @@ impl TcpIPv4Handler {
                 Ok(RecvEvent::Nothing)
             }
             RecvSegmentOutcome::NewConnection => {
-                let endpoint = match Endpoint::new_with_defaults(&segment) {
+                let endpoint = match Endpoint::new_with_limits(&segment, self.endpoint_limits) {
                     Ok(endpoint) => endpoint,
                     Err(_) => return Ok(RecvEvent::FailedNewConnection),
                 };
@@ -285,6 +413,53 @@ impl TcpIPv4Handler {
         }
     }
 
+    /// Returns true if some of the endpoints hold requests back, in which case
+    /// [`poll_waiting_requests`] should be called every now and then.
+    ///
+    /// [`poll_waiting_requests`]: struct.TcpIPHandler.html#method.poll_waiting_requests
+    pub fn has_waiting_requests(&self) -> bool {
+        !self.waiting_connections.is_empty()
+    }
+
+    /// Hands the requests which are held back to the callback again, so the ones which no longer
+    /// have to wait, or which timed out, are answered.
+    pub fn poll_waiting_requests<R: Into<Reply>, F: Fn(Request) -> R>(&mut self, callback: F) {
+        self.poll_held_requests(false, callback);
+    }
+
+    /// Same as [`poll_waiting_requests`], but only for the requests which timed out, when
+    /// nothing the others wait for changed.
+    ///
+    /// [`poll_waiting_requests`]: struct.TcpIPHandler.html#method.poll_waiting_requests
+    pub fn poll_expired_requests<R: Into<Reply>, F: Fn(Request) -> R>(&mut self, callback: F) {
+        self.poll_held_requests(true, callback);
+    }
+
+    fn poll_held_requests<R: Into<Reply>, F: Fn(Request) -> R>(
+        &mut self,
+        expired_only: bool,
+        callback: F,
+    ) {
+        let now = get_time_ns(ClockType::Monotonic);
+        let tuples: Vec<ConnectionTuple> = self.waiting_connections.iter().copied().collect();
+        for tuple in tuples {
+            // Tuples in self.waiting_connections should also appear as keys in
+            // self.connections.
+            let endpoint = self.connections.get_mut(&tuple).unwrap();
+            if expired_only && !endpoint.wait_expired(now) {
+                continue;
+            }
+            endpoint.poll_waiting_request(&callback);
+            if !endpoint.is_waiting() {
+                self.waiting_connections.remove(&tuple);
+            }
+            let status = endpoint.next_segment_status();
+            if !self.check_next_segment_status(tuple, status) {
+                self.active_connections.remove(&tuple);
+            }
+        }
+    }
+
     fn check_timeout(&mut self, value: u64, tuple: ConnectionTuple) {
         match self.next_timeout {
             Some((t, _)) if t > value => self.next_timeout = Some((value, tuple)),
@@ -341,6 +516,7 @@ impl TcpIPv4Handler {
     fn remove_connection(&mut self, tuple: ConnectionTuple) {
         // Just in case it's in there somewhere.
         self.active_connections.remove(&tuple);
+        self.waiting_connections.remove(&tuple);
         self.connections.remove(&tuple);
 
         if let Some((_, timeout_tuple)) = self.next_timeout {
@@ -386,10 +562,8 @@ impl TcpIPv4Handler {
         let mut writer_status = None;
         let mut event = WriteEvent::Nothing;
 
-        // Write an incomplete Ipv4 packet and complete it afterwards with missing information.
-        let mut packet =
-            IPv4Packet::write_header(buf, PROTOCOL_TCP, Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST)
-                .map_err(WriteNextError::IPv4Packet)?;
+        // Write an incomplete IP packet and complete it afterwards with missing information.
+        let mut packet = IncompleteIPPacket::write_header(buf, self.local_addr)?;
 
         // We set mss_used to 0, because we don't add any IP options.
         // TODO: Maybe get this nicely from packet at some point.
@@ -401,7 +575,7 @@ impl TcpIPv4Handler {
         if let Some((tuple, rst_cfg)) = self.rst_queue.pop() {
             let (seq, ack, flags_after_ns) = rst_cfg.seq_ack_tcp_flags();
             let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
-                packet.inner_mut().payload_mut(),
+                packet.payload_mut(),
                 seq,
                 ack,
                 flags_after_ns,
@@ -414,16 +588,11 @@ impl TcpIPv4Handler {
             .finalize(
                 self.local_port,
                 tuple.remote_port,
-                Some((self.local_ipv4_addr, tuple.remote_addr)),
+                Some((self.local_addr, tuple.remote_addr)),
             )
             .len();
 
-            packet
-                .inner_mut()
-                .set_source_address(self.local_ipv4_addr)
-                .set_destination_address(tuple.remote_addr);
-
-            let packet_len = packet.with_payload_len_unchecked(segment_len, true).len();
+            let packet_len = packet.finalize(tuple.remote_addr, segment_len)?;
             // The unwrap() is safe because packet_len > 0.
             return Ok((
                 Some(NonZeroUsize::new(packet_len).unwrap()),
@@ -440,29 +609,23 @@ impl TcpIPv4Handler {
             // in self.connections.
             let endpoint = self.connections.get_mut(tuple).unwrap();
             // We need this block to clearly delimit the lifetime of the mutable borrow started by
-            // the following packet.inner_mut().
+            // the following packet.payload_mut().
             let segment_len = {
-                let maybe_segment =
-                    endpoint.write_next_segment(packet.inner_mut().payload_mut(), mss_reserved);
+                let maybe_segment = endpoint.write_next_segment(packet.payload_mut(), mss_reserved);
 
                 match maybe_segment {
                     Some(segment) => segment
                         .finalize(
                             self.local_port,
                             tuple.remote_port,
-                            Some((self.local_ipv4_addr, tuple.remote_addr)),
+                            Some((self.local_addr, tuple.remote_addr)),
                         )
                         .len(),
                     None => continue,
                 }
             };
 
-            packet
-                .inner_mut()
-                .set_source_address(self.local_ipv4_addr)
-                .set_destination_address(tuple.remote_addr);
-
-            let ip_len = packet.with_payload_len_unchecked(segment_len, true).len();
+            let ip_len = packet.finalize(tuple.remote_addr, segment_len)?;
 
             // The unwrap is safe because ip_len > 0.
             len = Some(NonZeroUsize::new(ip_len).unwrap());
@@ -517,7 +680,7 @@ mod tests {
 
     #[allow(clippy::type_complexity)]
     fn write_next<'a>(
-        h: &mut TcpIPv4Handler,
+        h: &mut TcpIPHandler,
         buf: &'a mut [u8],
     ) -> Result<(Option<IPv4Packet<'a, &'a mut [u8]>>, WriteEvent), WriteNextError> {
         h.write_next_packet(buf).map(|(o, e)| {
@@ -532,7 +695,7 @@ mod tests {
     }
 
     fn next_written_segment<'a>(
-        h: &mut TcpIPv4Handler,
+        h: &mut TcpIPHandler,
         buf: &'a mut [u8],
         expected_event: WriteEvent,
     ) -> TcpSegment<'a, &'a mut [u8]> {
@@ -550,7 +713,7 @@ mod tests {
     // When successful, returns how many packets were written. The remote_addr argument is used
     // to check the packets are sent to the appropriate destination.
     fn drain_packets(
-        h: &mut TcpIPv4Handler,
+        h: &mut TcpIPHandler,
         src_addr: Ipv4Addr,
         remote_addr: Ipv4Addr,
     ) -> Result<usize, WriteNextError> {
@@ -583,8 +746,8 @@ mod tests {
         let max_connections = 2;
         let max_pending_resets = 2;
 
-        let mut h = TcpIPv4Handler::new(
-            local_addr,
+        let mut h = TcpIPHandler::new(
+            IpAddr::V4(local_addr),
             local_port,
             NonZeroUsize::new(max_connections).unwrap(),
             NonZeroUsize::new(max_pending_resets).unwrap(),
@@ -709,8 +872,8 @@ mod tests {
         assert_eq!(h.next_segment_status(), NextSegmentStatus::Available);
         assert_eq!(drain_packets(&mut h, local_addr, remote_addr), Ok(1));
 
-        let remote_tuple = ConnectionTuple::new(remote_addr, remote_port);
-        let remote_tuple2 = ConnectionTuple::new(remote_addr, remote_port + 1);
+        let remote_tuple = ConnectionTuple::new(remote_addr.into(), remote_port);
+        let remote_tuple2 = ConnectionTuple::new(remote_addr.into(), remote_port + 1);
 
         // Also, there should be a retransmission timer associated with the previous SYNACK now.
         assert_eq!(h.active_connections.len(), 0);
@@ -771,7 +934,7 @@ mod tests {
         // The timeout associated with the SYNACK of the second connection should be next.
         assert_eq!(h.active_connections.len(), 0);
         if let Some((_, tuple)) = h.next_timeout {
-            assert_ne!(tuple, ConnectionTuple::new(remote_addr, remote_port));
+            assert_ne!(tuple, ConnectionTuple::new(remote_addr.into(), remote_port));
         } else {
             panic!("missing third expected timeout");
         }
@@ -823,4 +986,86 @@ mod tests {
         assert_eq!(h.connections.len(), 1);
         assert_eq!(h.active_connections.len(), 0);
     }
+
+    #[test]
+    fn test_ipv6_handler() {
+        let mut buf = [0u8; 100];
+        let mut buf2 = [0u8; 2000];
+
+        let local_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        let local_port = 80;
+        let remote_addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 1, 2, 3, 4);
+        let remote_port = 1012;
+
+        let mut h = TcpIPHandler::new(
+            IpAddr::V6(local_addr),
+            local_port,
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        );
+        assert_eq!(h.local_addr(), IpAddr::V6(local_addr));
+
+        let mut p =
+            IPv6Packet::write_header(buf.as_mut(), PROTOCOL_TCP, remote_addr, local_addr).unwrap();
+        let s_len = TcpSegment::write_segment::<[u8]>(
+            p.inner_mut().payload_mut(),
+            remote_port,
+            local_port,
+            123,
+            456,
+            TcpFlags::SYN,
+            10000,
+            None,
+            100,
+            None,
+            None,
+        )
+        .unwrap()
+        .len();
+        let p = p.with_payload_len_unchecked(s_len);
+
+        assert_eq!(
+            h.receive_ipv6_packet(&p, mock_callback),
+            Ok(RecvEvent::NewConnectionSuccessful)
+        );
+        assert_eq!(h.connections.len(), 1);
+
+        // The SYNACK is sent back over IPv6.
+        {
+            let (len, event) = h.write_next_packet(buf2.as_mut()).unwrap();
+            assert_eq!(event, WriteEvent::Nothing);
+            let packet = IPv6Packet::from_bytes(&buf2[..len.unwrap().get()]).unwrap();
+            assert_eq!(packet.next_header(), PROTOCOL_TCP);
+            assert_eq!(packet.source_address(), local_addr);
+            assert_eq!(packet.destination_address(), remote_addr);
+
+            let s = TcpSegment::from_bytes(
+                packet.payload(),
+                Some((local_addr.into(), remote_addr.into())),
+            )
+            .unwrap();
+            assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+            assert_eq!(s.destination_port(), remote_port);
+        }
+
+        // IPv4 packets are rejected by an IPv6 handler.
+        let ipv4_addr = Ipv4Addr::new(10, 0, 0, 1);
+        let p4 = IPv4Packet::write_header(buf2.as_mut(), PROTOCOL_TCP, ipv4_addr, ipv4_addr)
+            .unwrap()
+            .with_payload_len_unchecked(0, false);
+        assert_eq!(
+            h.receive_packet(&p4, mock_callback),
+            Err(RecvError::AddressFamily)
+        );
+
+        // Switching to an IPv4 address drops the IPv6 connections.
+        h.set_local_addr(IpAddr::V4(ipv4_addr));
+        assert_eq!(h.local_addr(), IpAddr::V4(ipv4_addr));
+        assert!(h.connections.is_empty());
+        assert_eq!(h.next_segment_status(), NextSegmentStatus::Nothing);
+        assert_eq!(
+            h.receive_ipv6_packet(&p, mock_callback),
+            Err(RecvError::AddressFamily)
+        );
+    }
 }
@@ -9,6 +9,7 @@ pub mod handler;
 
 use crate::pdu::bytes::NetworkBytes;
 use crate::pdu::tcp::{Flags as TcpFlags, TcpSegment};
+use micro_http::Response;
 
 use std::num::Wrapping;
 
@@ -19,6 +20,62 @@ pub const MAX_WINDOW_SIZE: u32 = 1_073_725_440;
 /// over the initial handshake.
 pub const MSS_DEFAULT: u16 = 536;
 
+/// The default size of the receive buffer of each connection accepted by a [`TcpIPHandler`].
+///
+/// This is one plus the size of the largest bytestream carrying an HTTP request we are willing to
+/// accept by default. It should be plenty for imaginable regular MMDS requests.
+///
+/// [`TcpIPHandler`]: handler/struct.TcpIPHandler.html
+pub const DEFAULT_RECEIVE_BUF_SIZE: usize = 2500;
+
+/// The default size of the send buffer of each connection accepted by a [`TcpIPHandler`].
+///
+/// [`TcpIPHandler`]: handler/struct.TcpIPHandler.html
+pub const DEFAULT_SEND_BUF_SIZE: usize = 65536;
+
+/// Bounds the memory used by each connection accepted by a [`TcpIPHandler`].
+///
+/// [`TcpIPHandler`]: handler/struct.TcpIPHandler.html
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EndpointLimits {
+    /// Size of the receive buffer, which is also the receive window advertised to the other
+    /// endpoint. Connections carrying requests which do not fit are reset.
+    pub receive_buf_size: usize,
+    /// Size of the send buffer. Responses with larger bodies are streamed as the other endpoint
+    /// acknowledges the previous bytes, and pipelined requests wait while the buffer is full.
+    pub send_buf_size: usize,
+}
+
+impl Default for EndpointLimits {
+    fn default() -> Self {
+        EndpointLimits {
+            receive_buf_size: DEFAULT_RECEIVE_BUF_SIZE,
+            send_buf_size: DEFAULT_SEND_BUF_SIZE,
+        }
+    }
+}
+
+/// The reply of an endpoint callback to a request.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    /// The response is sent right away.
+    Response(Response),
+    /// The request is held back, along with the ones pipelined after it, and handed to the
+    /// callback again each time the endpoint is polled.
+    Wait {
+        /// How long the request may be held back, in nanoseconds, since it first was.
+        timeout_ns: u64,
+        /// The response sent if the request times out.
+        response: Response,
+    },
+}
+
+impl From<Response> for Reply {
+    fn from(response: Response) -> Self {
+        Reply::Response(response)
+    }
+}
+
 /// Describes whether a particular entity (a [`Connection`] for example) has segments to send.
 ///
 /// [`Connection`]: connection/struct.Connection.html
@@ -100,6 +157,29 @@ mod tests {
         Response::new(Version::Http11, StatusCode::OK)
     }
 
+    // A xorshift generator used by the fuzz tests, which is seeded explicitly so that every run
+    // goes through the same sequence of events.
+    pub struct FuzzRng(u32);
+
+    impl FuzzRng {
+        pub fn new(seed: u32) -> Self {
+            // The state of a xorshift generator must not be 0.
+            FuzzRng(seed | 1)
+        }
+
+        pub fn next_u32(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        // Returns a value in the [0, bound) interval.
+        pub fn below(&mut self, bound: u32) -> u32 {
+            self.next_u32() % bound
+        }
+    }
+
     #[test]
     fn test_rst_config() {
         let mut buf = [0u8; 100];
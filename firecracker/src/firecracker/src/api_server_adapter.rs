@@ -136,6 +136,7 @@ pub(crate) fn run_with_api(
     // Channels for both directions between Vmm and Api threads.
     let (to_vmm, from_api) = channel();
     let (to_api, from_vmm) = channel();
+    let (api_affinity_sender, api_affinity_receiver) = channel();
 
     // MMDS only supported with API.
     let mmds_info = MMDS.clone();
@@ -163,6 +164,7 @@ pub(crate) fn run_with_api(
                 start_time_us,
                 start_time_cpu_us,
                 api_seccomp_filter,
+                api_affinity_receiver,
             ) {
                 Ok(_) => (),
                 Err(api_server::Error::Io(inner)) => match inner.kind() {
@@ -234,6 +236,13 @@ pub(crate) fn run_with_api(
         ),
     };
 
+    // The API thread pins itself once the microVM configuration is known.
+    if let Some(api_affinity) = &vm_resources.vm_config().api_affinity {
+        if api_affinity_sender.send(api_affinity.clone()).is_err() {
+            error!("Cannot send the affinity of the API thread.");
+        }
+    }
+
     // Start the metrics.
     firecracker_metrics
         .lock()
@@ -11,6 +11,7 @@ use std::process;
 use std::sync::{Arc, Mutex};
 
 use logger::{error, info, IncMetric, LOGGER, METRICS};
+use mmds::MMDS;
 use polly::event_manager::EventManager;
 use seccomp::{BpfProgram, SeccompLevel};
 use utils::arg_parser::{ArgParser, Argument};
@@ -133,6 +134,16 @@ fn main() {
                 .takes_value(false)
                 .help("Whether or not to load boot timer device for logging elapsed time since InstanceStart command.")
         )
+        .arg(
+            Argument::new("mmds-size-limit")
+                .takes_value(true)
+                .help("Maximum size, in bytes, of the MMDS data stores.")
+        )
+        .arg(
+            Argument::new("metadata")
+                .takes_value(true)
+                .help("Path to a file that contains the metadata, in JSON format, to preload the MMDS with.")
+        )
         .arg(
             Argument::new("version")
                 .takes_value(false)
@@ -174,6 +185,7 @@ fn main() {
         state: "Not started".to_string(),
         vmm_version: FIRECRACKER_VERSION.to_string(),
         app_name: "Firecracker".to_string(),
+        boot_measurements: None,
     };
 
     LOGGER.set_instance_id(instance_id.to_owned());
@@ -211,6 +223,34 @@ fn main() {
         panic!("Could not create seccomp filter: {}", err);
     });
 
+    if let Some(limit) = arguments.single_value("mmds-size-limit") {
+        let limit = limit
+            .parse::<usize>()
+            .expect("'mmds-size-limit' parameter expected to be of 'usize' type.");
+        MMDS.lock()
+            .expect("Poisoned lock")
+            .set_data_store_limit(limit);
+    }
+
+    // Preload the MMDS before the configuration file, whose metadata is merged on top.
+    if let Some(metadata_path) = arguments.single_value("metadata") {
+        let metadata = fs::read_to_string(metadata_path).unwrap_or_else(|err| {
+            error!("Unable to open or read from the metadata file: {}", err);
+            process::exit(i32::from(vmm::FC_EXIT_CODE_BAD_CONFIGURATION));
+        });
+        let metadata = serde_json::from_str(&metadata).unwrap_or_else(|err| {
+            error!("Invalid metadata file: {}", err);
+            process::exit(i32::from(vmm::FC_EXIT_CODE_BAD_CONFIGURATION));
+        });
+        MMDS.lock()
+            .expect("Poisoned lock")
+            .put_data(metadata)
+            .unwrap_or_else(|err| {
+                error!("Cannot preload the MMDS: {}", err);
+                process::exit(i32::from(vmm::FC_EXIT_CODE_BAD_CONFIGURATION));
+            });
+    }
+
     let vmm_config_json = arguments
         .single_value("config-file")
         .map(fs::read_to_string)
@@ -0,0 +1,553 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helper for unpacking kernel bundles, which hold a kernel together with its initrd and
+//! command line in a single file.
+//!
+//! Two bundle formats are recognized:
+//! - the Firecracker kernel bundle: an `FCBUNDLE` magic, a little endian `u32` format version
+//!   (currently 1), a `u32` number of sections and the table of sections. Each entry of the
+//!   table is made of a `u32` section type (1 for the kernel, 2 for the initrd, 3 for the
+//!   command line), a reserved `u32` and the `u64` file offset and size of the section.
+//!   Unknown section types are ignored;
+//! - unified kernel images: PE images holding the `.linux`, `.initrd` and `.cmdline` sections.
+
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+const BUNDLE_MAGIC: &[u8; 8] = b"FCBUNDLE";
+const BUNDLE_VERSION: u32 = 1;
+const BUNDLE_HEADER_SIZE: u64 = 16;
+const BUNDLE_SECTION_KERNEL: u32 = 1;
+const BUNDLE_SECTION_INITRD: u32 = 2;
+const BUNDLE_SECTION_CMDLINE: u32 = 3;
+
+const PE_DOS_MAGIC: &[u8; 2] = b"MZ";
+const PE_HEADER_OFFSET_FIELD: u64 = 0x3c;
+const PE_MAGIC: &[u8; 4] = b"PE\0\0";
+const PE_SECTION_HEADER_SIZE: u64 = 40;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    DuplicateSection,
+    InvalidCommandLine,
+    InvalidSectionBounds,
+    MissingKernel,
+    ReadBundle(&'static str),
+    SeekBundle,
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::DuplicateSection => write!(f, "Kernel bundle holds a section more than once"),
+            Error::InvalidCommandLine => {
+                write!(f, "Kernel bundle command line is not a valid string")
+            }
+            Error::InvalidSectionBounds => {
+                write!(f, "Kernel bundle section exceeds the bundle size")
+            }
+            Error::MissingKernel => write!(f, "Kernel bundle does not hold a kernel"),
+            Error::ReadBundle(ref e) => write!(f, "{}", e),
+            Error::SeekBundle => write!(f, "Failed to seek in the kernel bundle"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "Unsupported kernel bundle version {}", version)
+            }
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Location of a section inside a bundle file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Section {
+    /// Offset of the section from the start of the file.
+    pub offset: u64,
+    /// Size of the section.
+    pub size: u64,
+}
+
+/// Content of a kernel bundle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bundle {
+    /// The kernel image.
+    pub kernel: Section,
+    /// The initrd, if there is one.
+    pub initrd: Option<Section>,
+    /// The embedded kernel command line, if there is one.
+    pub cmdline: Option<String>,
+}
+
+/// Reads the layout of a kernel bundle.
+///
+/// Returns `None` if `image` is not a bundle, in which case it is a plain kernel image.
+pub fn read_bundle<F>(image: &mut F) -> Result<Option<Bundle>>
+where
+    F: Read + Seek,
+{
+    let image_size = image
+        .seek(SeekFrom::End(0))
+        .map_err(|_| Error::SeekBundle)?;
+    image
+        .seek(SeekFrom::Start(0))
+        .map_err(|_| Error::SeekBundle)?;
+    let mut magic = [0u8; 8];
+    // Images smaller than the magic are not bundles.
+    if image.read_exact(&mut magic).is_err() {
+        return Ok(None);
+    }
+
+    let sections = if &magic == BUNDLE_MAGIC {
+        read_bundle_sections(image)?
+    } else if &magic[..2] == PE_DOS_MAGIC {
+        read_pe_sections(image)?
+    } else {
+        return Ok(None);
+    };
+    let (kernel, initrd, cmdline) = match sections {
+        Some(sections) => sections,
+        None => return Ok(None),
+    };
+
+    let kernel = kernel.ok_or(Error::MissingKernel)?;
+    for section in [Some(kernel), initrd, cmdline].iter().flatten() {
+        match section.offset.checked_add(section.size) {
+            Some(end) if end <= image_size => (),
+            _ => return Err(Error::InvalidSectionBounds),
+        }
+    }
+    let cmdline = match cmdline {
+        Some(section) => Some(read_cmdline(image, section)?),
+        None => None,
+    };
+
+    Ok(Some(Bundle {
+        kernel,
+        initrd,
+        cmdline,
+    }))
+}
+
+type Sections = (Option<Section>, Option<Section>, Option<Section>);
+
+fn set_section(slot: &mut Option<Section>, section: Section) -> Result<()> {
+    if slot.replace(section).is_some() {
+        return Err(Error::DuplicateSection);
+    }
+    Ok(())
+}
+
+/// Reads the table of sections of a Firecracker kernel bundle, following its magic.
+fn read_bundle_sections<F>(image: &mut F) -> Result<Option<Sections>>
+where
+    F: Read + Seek,
+{
+    let version = read_u32(image, "Failed to read kernel bundle version")?;
+    if version != BUNDLE_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let count = read_u32(image, "Failed to read kernel bundle section count")?;
+
+    image
+        .seek(SeekFrom::Start(BUNDLE_HEADER_SIZE))
+        .map_err(|_| Error::SeekBundle)?;
+    let (mut kernel, mut initrd, mut cmdline) = (None, None, None);
+    for _ in 0..count {
+        let section_type = read_u32(image, "Failed to read kernel bundle section type")?;
+        read_u32(image, "Failed to read kernel bundle section")?;
+        let section = Section {
+            offset: read_u64(image, "Failed to read kernel bundle section offset")?,
+            size: read_u64(image, "Failed to read kernel bundle section size")?,
+        };
+        match section_type {
+            BUNDLE_SECTION_KERNEL => set_section(&mut kernel, section)?,
+            BUNDLE_SECTION_INITRD => set_section(&mut initrd, section)?,
+            BUNDLE_SECTION_CMDLINE => set_section(&mut cmdline, section)?,
+            _ => (),
+        }
+    }
+
+    Ok(Some((kernel, initrd, cmdline)))
+}
+
+/// Reads the sections of a unified kernel image.
+///
+/// Returns `None` for PE images without a `.linux` section, such as EFI stub kernels.
+fn read_pe_sections<F>(image: &mut F) -> Result<Option<Sections>>
+where
+    F: Read + Seek,
+{
+    image
+        .seek(SeekFrom::Start(PE_HEADER_OFFSET_FIELD))
+        .map_err(|_| Error::SeekBundle)?;
+    let mut pe_offset = [0u8; 4];
+    if image.read_exact(&mut pe_offset).is_err() {
+        return Ok(None);
+    }
+    let pe_offset = u64::from(u32::from_le_bytes(pe_offset));
+    image
+        .seek(SeekFrom::Start(pe_offset))
+        .map_err(|_| Error::SeekBundle)?;
+    let mut magic = [0u8; 4];
+    if image.read_exact(&mut magic).is_err() || &magic != PE_MAGIC {
+        return Ok(None);
+    }
+
+    // COFF file header: machine, number of sections, timestamp, symbol table offset,
+    // number of symbols, size of the optional header and characteristics.
+    read_u16(image, "Failed to read PE file header")?;
+    let section_count = read_u16(image, "Failed to read PE section count")?;
+    image
+        .seek(SeekFrom::Current(12))
+        .map_err(|_| Error::SeekBundle)?;
+    let optional_header_size = read_u16(image, "Failed to read PE optional header size")?;
+    let section_table = pe_offset + 24 + u64::from(optional_header_size);
+
+    let (mut kernel, mut initrd, mut cmdline) = (None, None, None);
+    for index in 0..u64::from(section_count) {
+        image
+            .seek(SeekFrom::Start(
+                section_table + index * PE_SECTION_HEADER_SIZE,
+            ))
+            .map_err(|_| Error::SeekBundle)?;
+        let mut name = [0u8; 8];
+        image
+            .read_exact(&mut name)
+            .map_err(|_| Error::ReadBundle("Failed to read PE section name"))?;
+        let virtual_size = read_u32(image, "Failed to read PE section size")?;
+        read_u32(image, "Failed to read PE section address")?;
+        let raw_size = read_u32(image, "Failed to read PE section size")?;
+        let raw_offset = read_u32(image, "Failed to read PE section offset")?;
+        // The raw data is padded to the file alignment, the virtual size is the actual size.
+        let section = Section {
+            offset: u64::from(raw_offset),
+            size: u64::from(cmp::min(virtual_size, raw_size)),
+        };
+        match &name {
+            b".linux\0\0" => set_section(&mut kernel, section)?,
+            b".initrd\0" => set_section(&mut initrd, section)?,
+            b".cmdline" => set_section(&mut cmdline, section)?,
+            _ => (),
+        }
+    }
+
+    if kernel.is_none() {
+        return Ok(None);
+    }
+    Ok(Some((kernel, initrd, cmdline)))
+}
+
+fn read_cmdline<F>(image: &mut F, section: Section) -> Result<String>
+where
+    F: Read + Seek,
+{
+    let mut cmdline = Vec::new();
+    SectionReader::new(image, section)
+        .read_to_end(&mut cmdline)
+        .map_err(|_| Error::ReadBundle("Failed to read kernel bundle command line"))?;
+    let cmdline = String::from_utf8(cmdline).map_err(|_| Error::InvalidCommandLine)?;
+    // Embedded command lines are usually NUL or newline terminated.
+    Ok(cmdline
+        .trim_end_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string())
+}
+
+fn read_u16<F: Read>(image: &mut F, err: &'static str) -> Result<u16> {
+    let mut bytes = [0u8; 2];
+    image
+        .read_exact(&mut bytes)
+        .map_err(|_| Error::ReadBundle(err))?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32<F: Read>(image: &mut F, err: &'static str) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    image
+        .read_exact(&mut bytes)
+        .map_err(|_| Error::ReadBundle(err))?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<F: Read>(image: &mut F, err: &'static str) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    image
+        .read_exact(&mut bytes)
+        .map_err(|_| Error::ReadBundle(err))?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reader restricted to a section of a bundle, seen as a standalone file.
+pub struct SectionReader<F> {
+    inner: F,
+    section: Section,
+    pos: u64,
+}
+
+impl<F> SectionReader<F>
+where
+    F: Read + Seek,
+{
+    /// Creates a reader over `section` of `inner`.
+    pub fn new(inner: F, section: Section) -> Self {
+        SectionReader {
+            inner,
+            section,
+            pos: 0,
+        }
+    }
+}
+
+impl<F> Read for SectionReader<F>
+where
+    F: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.section.size.saturating_sub(self.pos);
+        let len = cmp::min(buf.len() as u64, remaining) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        self.inner
+            .seek(SeekFrom::Start(self.section.offset + self.pos))?;
+        let count = self.inner.read(&mut buf[..len])?;
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl<F> Seek for SectionReader<F>
+where
+    F: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.section.size, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        let pos = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.wrapping_neg() as u64)
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative offset",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn make_bundle(sections: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut image = Vec::new();
+        image.extend_from_slice(BUNDLE_MAGIC);
+        image.extend_from_slice(&BUNDLE_VERSION.to_le_bytes());
+        image.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+
+        let mut offset = BUNDLE_HEADER_SIZE + 24 * sections.len() as u64;
+        for (section_type, data) in sections {
+            image.extend_from_slice(&section_type.to_le_bytes());
+            image.extend_from_slice(&0u32.to_le_bytes());
+            image.extend_from_slice(&offset.to_le_bytes());
+            image.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            offset += data.len() as u64;
+        }
+        for (_, data) in sections {
+            image.extend_from_slice(data);
+        }
+        image
+    }
+
+    fn make_uki(sections: &[(&[u8; 8], &[u8])]) -> Vec<u8> {
+        const PE_OFFSET: usize = 0x80;
+        const OPTIONAL_HEADER_SIZE: usize = 0xf0;
+        const FILE_ALIGNMENT: usize = 0x200;
+
+        let mut image = vec![0u8; PE_OFFSET];
+        image[..2].copy_from_slice(PE_DOS_MAGIC);
+        image[0x3c..0x40].copy_from_slice(&(PE_OFFSET as u32).to_le_bytes());
+        image.extend_from_slice(PE_MAGIC);
+        // COFF file header.
+        image.extend_from_slice(&0x8664u16.to_le_bytes());
+        image.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+        image.extend_from_slice(&[0u8; 12]);
+        image.extend_from_slice(&(OPTIONAL_HEADER_SIZE as u16).to_le_bytes());
+        image.extend_from_slice(&0u16.to_le_bytes());
+        image.resize(image.len() + OPTIONAL_HEADER_SIZE, 0);
+
+        let mut offset = 2 * FILE_ALIGNMENT;
+        let mut data_offsets = Vec::new();
+        for (name, data) in sections {
+            let raw_size =
+                data.len() + (FILE_ALIGNMENT - data.len() % FILE_ALIGNMENT) % FILE_ALIGNMENT;
+            image.extend_from_slice(*name);
+            image.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            image.extend_from_slice(&0u32.to_le_bytes());
+            image.extend_from_slice(&(raw_size as u32).to_le_bytes());
+            image.extend_from_slice(&(offset as u32).to_le_bytes());
+            image.extend_from_slice(&[0u8; 16]);
+            data_offsets.push(offset);
+            offset += raw_size;
+        }
+        for ((_, data), data_offset) in sections.iter().zip(data_offsets) {
+            image.resize(data_offset, 0);
+            image.extend_from_slice(data);
+        }
+        image.resize(offset, 0);
+        image
+    }
+
+    #[test]
+    fn test_read_bundle() {
+        let image = make_bundle(&[
+            (BUNDLE_SECTION_CMDLINE, b"console=ttyS0\0"),
+            (BUNDLE_SECTION_KERNEL, &[0xaa; 0x20]),
+            (7, &[0xbb; 0x4]),
+            (BUNDLE_SECTION_INITRD, &[0xcc; 0x10]),
+        ]);
+        let bundle = read_bundle(&mut Cursor::new(&image)).unwrap().unwrap();
+        assert_eq!(
+            bundle,
+            Bundle {
+                kernel: Section {
+                    offset: 126,
+                    size: 0x20
+                },
+                initrd: Some(Section {
+                    offset: 162,
+                    size: 0x10
+                }),
+                cmdline: Some("console=ttyS0".to_string()),
+            }
+        );
+
+        let image = make_bundle(&[(BUNDLE_SECTION_KERNEL, &[0xaa; 0x20])]);
+        let bundle = read_bundle(&mut Cursor::new(&image)).unwrap().unwrap();
+        assert_eq!(bundle.initrd, None);
+        assert_eq!(bundle.cmdline, None);
+    }
+
+    #[test]
+    fn test_read_bad_bundle() {
+        let image = make_bundle(&[(BUNDLE_SECTION_INITRD, &[0xcc; 0x10])]);
+        assert_eq!(
+            read_bundle(&mut Cursor::new(&image)),
+            Err(Error::MissingKernel)
+        );
+
+        let image = make_bundle(&[
+            (BUNDLE_SECTION_KERNEL, &[0xaa; 0x20]),
+            (BUNDLE_SECTION_KERNEL, &[0xaa; 0x20]),
+        ]);
+        assert_eq!(
+            read_bundle(&mut Cursor::new(&image)),
+            Err(Error::DuplicateSection)
+        );
+
+        let image = make_bundle(&[
+            (BUNDLE_SECTION_KERNEL, &[0xaa; 0x20]),
+            (BUNDLE_SECTION_CMDLINE, &[0xff, 0xfe]),
+        ]);
+        assert_eq!(
+            read_bundle(&mut Cursor::new(&image)),
+            Err(Error::InvalidCommandLine)
+        );
+
+        let mut image = make_bundle(&[(BUNDLE_SECTION_KERNEL, &[0xaa; 0x20])]);
+        image.truncate(image.len() - 1);
+        assert_eq!(
+            read_bundle(&mut Cursor::new(&image)),
+            Err(Error::InvalidSectionBounds)
+        );
+
+        let mut image = make_bundle(&[(BUNDLE_SECTION_KERNEL, &[0xaa; 0x20])]);
+        image[8] = 2;
+        assert_eq!(
+            read_bundle(&mut Cursor::new(&image)),
+            Err(Error::UnsupportedVersion(2))
+        );
+        image.truncate(10);
+        assert_eq!(
+            read_bundle(&mut Cursor::new(&image)),
+            Err(Error::ReadBundle("Failed to read kernel bundle version"))
+        );
+    }
+
+    #[test]
+    fn test_read_uki() {
+        let image = make_uki(&[
+            (b".text\0\0\0", &[0x11; 0x10]),
+            (b".cmdline", b"console=ttyS0 quiet\n"),
+            (b".linux\0\0", &[0xaa; 0x300]),
+            (b".initrd\0", &[0xcc; 0x10]),
+        ]);
+        let bundle = read_bundle(&mut Cursor::new(&image)).unwrap().unwrap();
+        assert_eq!(
+            bundle,
+            Bundle {
+                kernel: Section {
+                    offset: 0x800,
+                    size: 0x300
+                },
+                initrd: Some(Section {
+                    offset: 0xc00,
+                    size: 0x10
+                }),
+                cmdline: Some("console=ttyS0 quiet".to_string()),
+            }
+        );
+
+        // PE images without a `.linux` section are plain kernels.
+        let image = make_uki(&[(b".text\0\0\0", &[0x11; 0x10])]);
+        assert_eq!(read_bundle(&mut Cursor::new(&image)), Ok(None));
+    }
+
+    #[test]
+    fn test_read_plain_kernel() {
+        assert_eq!(read_bundle(&mut Cursor::new(&[0x7f, b'E', b'L'])), Ok(None));
+        let image = include_bytes!("loader/test_elf.bin");
+        assert_eq!(read_bundle(&mut Cursor::new(&image[..])), Ok(None));
+        let mut image = vec![0u8; 0x40];
+        image[..2].copy_from_slice(PE_DOS_MAGIC);
+        image[0x3c] = 0x80;
+        assert_eq!(read_bundle(&mut Cursor::new(&image)), Ok(None));
+    }
+
+    #[test]
+    fn test_section_reader() {
+        let image: Vec<u8> = (0..0x40).collect();
+        let mut reader = SectionReader::new(
+            Cursor::new(&image),
+            Section {
+                offset: 0x10,
+                size: 0x8,
+            },
+        );
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 0x8);
+        assert_eq!(reader.seek(SeekFrom::Start(0)).unwrap(), 0);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, (0x10..0x18).collect::<Vec<u8>>());
+
+        assert_eq!(reader.seek(SeekFrom::Current(-2)).unwrap(), 0x6);
+        let mut data = [0u8; 4];
+        assert_eq!(reader.read(&mut data).unwrap(), 2);
+        assert_eq!(&data[..2], &[0x16, 0x17]);
+        assert!(reader.seek(SeekFrom::End(-9)).is_err());
+        assert_eq!(reader.seek(SeekFrom::Start(0x20)).unwrap(), 0x20);
+        assert_eq!(reader.read(&mut data).unwrap(), 0);
+    }
+}
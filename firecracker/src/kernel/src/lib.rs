@@ -5,5 +5,6 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+pub mod bundle;
 pub mod cmdline;
 pub mod loader;
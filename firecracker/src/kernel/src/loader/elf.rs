@@ -20,6 +20,7 @@ pub const ELFMAG0: ::std::os::raw::c_uint = 127;
 
 pub const ELFDATA2LSB: ::std::os::raw::c_uint = 1;
 pub const PT_LOAD: ::std::os::raw::c_uint = 1;
+pub const PT_NOTE: ::std::os::raw::c_uint = 4;
 
 pub const ELFMAG1: u8 = b'E';
 pub const ELFMAG2: u8 = b'L';
@@ -85,6 +86,21 @@ impl Clone for elf64_phdr {
 }
 pub type Elf64_Phdr = elf64_phdr;
 
+#[repr(C)]
+#[derive(Debug, Default, Copy)]
+pub struct elf64_note {
+    pub n_namesz: Elf64_Word,
+    pub n_descsz: Elf64_Word,
+    pub n_type: Elf64_Word,
+}
+
+impl Clone for elf64_note {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+pub type Elf64_Nhdr = elf64_note;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -336,4 +352,18 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn bindgen_test_layout_elf64_note() {
+        assert_eq!(
+            ::std::mem::size_of::<elf64_note>(),
+            12usize,
+            concat!("Size of: ", stringify!(elf64_note))
+        );
+        assert_eq!(
+            ::std::mem::align_of::<elf64_note>(),
+            4usize,
+            concat!("Alignment of ", stringify!(elf64_note))
+        );
+    }
 }
@@ -13,6 +13,8 @@ use std::io::{Read, Seek, SeekFrom};
 use std::mem;
 
 use super::cmdline::Error as CmdlineError;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use arch_gen::x86::bootparam::setup_header;
 use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
 
 #[allow(non_camel_case_types)]
@@ -24,19 +26,24 @@ mod elf;
 unsafe impl ByteValued for elf::Elf64_Ehdr {}
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 unsafe impl ByteValued for elf::Elf64_Phdr {}
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe impl ByteValued for elf::Elf64_Nhdr {}
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
     BigEndianElfOnLittle,
+    InvalidBzImage,
     InvalidElfMagicNumber,
     InvalidEntryAddress,
     InvalidProgramHeaderSize,
     InvalidProgramHeaderOffset,
     InvalidProgramHeaderAddress,
+    InvalidPvhNote,
     ReadKernelDataStruct(&'static str),
     ReadKernelImage,
     SeekKernelStart,
     SeekKernelImage,
+    SeekNoteHeader,
     SeekProgramHeader,
 }
 
@@ -47,17 +54,22 @@ impl fmt::Display for Error {
             "{}",
             match *self {
                 Error::BigEndianElfOnLittle => "Unsupported ELF File byte order",
+                Error::InvalidBzImage => {
+                    "Unsupported bzImage, which does not provide a 64-bit entry point"
+                }
                 Error::InvalidElfMagicNumber => "Invalid ELF magic number",
                 Error::InvalidEntryAddress => "Invalid entry address found in ELF header",
                 Error::InvalidProgramHeaderSize => "Invalid ELF program header size",
                 Error::InvalidProgramHeaderOffset => "Invalid ELF program header offset",
                 Error::InvalidProgramHeaderAddress => "Invalid ELF program header address",
+                Error::InvalidPvhNote => "Invalid PVH entry point note found in ELF file",
                 Error::ReadKernelDataStruct(ref e) => e,
                 Error::ReadKernelImage => "Failed to write kernel image to guest memory",
                 Error::SeekKernelStart => {
                     "Failed to seek to file offset as pointed by the ELF program header"
                 }
                 Error::SeekKernelImage => "Failed to seek to offset of kernel image",
+                Error::SeekNoteHeader => "Failed to seek to ELF note header",
                 Error::SeekProgramHeader => "Failed to seek to ELF program header",
             }
         )
@@ -66,21 +78,161 @@ impl fmt::Display for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// Loads a kernel from a vmlinux elf image to a slice
+/// Setup header of a bzImage, as defined by the Linux boot protocol.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SetupHeader(pub setup_header);
+
+// It is safe to initialize SetupHeader which is a wrapper over `setup_header` (a series of ints).
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+unsafe impl ByteValued for SetupHeader {}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl PartialEq for SetupHeader {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+/// Formats of x86_64 kernel images, which select the protocol booting the kernel.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KernelFormat {
+    /// ELF vmlinux, started at its 64-bit entry point.
+    Elf,
+    /// ELF vmlinux advertising a Xen PVH entry point, started through the PVH boot protocol.
+    PvhElf,
+    /// bzImage, started at its 64-bit entry point with the setup header in the boot parameters.
+    BzImage(SetupHeader),
+}
+
+/// Kernel image loaded in guest memory.
+#[derive(Debug, PartialEq)]
+pub struct LoadedKernel {
+    /// Address at which the kernel starts executing.
+    pub entry_addr: GuestAddress,
+    /// Format of the kernel image.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub format: KernelFormat,
+}
+
+/// Loads a kernel from a vmlinux elf image or a bzImage to a slice
 ///
 /// # Arguments
 ///
 /// * `guest_mem` - The guest memory region the kernel is written to.
-/// * `kernel_image` - Input vmlinux image.
+/// * `kernel_image` - Input vmlinux or bzImage image.
 /// * `start_address` - For x86_64, this is the start of the high memory. Kernel should reside above it.
 ///
-/// Returns the entry address of the kernel.
+/// Returns the entry point and the format of the kernel.
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub fn load_kernel<F>(
     guest_mem: &GuestMemoryMmap,
     kernel_image: &mut F,
     start_address: u64,
-) -> Result<GuestAddress>
+) -> Result<LoadedKernel>
+where
+    F: Read + Seek,
+{
+    match read_setup_header(kernel_image)? {
+        Some(setup_header) => load_bzimage(guest_mem, kernel_image, start_address, setup_header),
+        None => load_elf(guest_mem, kernel_image, start_address),
+    }
+}
+
+/// Reads the setup header of a bzImage, or returns `None` if the image is not a bzImage.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn read_setup_header<F>(kernel_image: &mut F) -> Result<Option<SetupHeader>>
+where
+    F: Read + Seek,
+{
+    const SETUP_HEADER_OFFSET: u64 = 0x1f1;
+    const KERNEL_BOOT_FLAG_MAGIC: u16 = 0xaa55;
+    const KERNEL_HDR_MAGIC: u32 = 0x5372_6448;
+
+    kernel_image
+        .seek(SeekFrom::Start(SETUP_HEADER_OFFSET))
+        .map_err(|_| Error::SeekKernelImage)?;
+    let mut setup_header = SetupHeader::default();
+    // Images too small to hold a setup header are not bzImages.
+    if kernel_image
+        .read_exact(setup_header.as_mut_slice())
+        .is_err()
+    {
+        return Ok(None);
+    }
+
+    let hdr = setup_header.0;
+    if { hdr.boot_flag } != KERNEL_BOOT_FLAG_MAGIC || { hdr.header } != KERNEL_HDR_MAGIC {
+        return Ok(None);
+    }
+    Ok(Some(setup_header))
+}
+
+/// Loads the protected-mode kernel of a bzImage at `start_address`, where it decompresses itself.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn load_bzimage<F>(
+    guest_mem: &GuestMemoryMmap,
+    kernel_image: &mut F,
+    start_address: u64,
+    mut setup_header: SetupHeader,
+) -> Result<LoadedKernel>
+where
+    F: Read + Seek,
+{
+    // The 64-bit entry point is described by the boot protocol 2.12 and later.
+    const MIN_BOOT_PROTOCOL_VERSION: u16 = 0x020c;
+    const LOADED_HIGH: u8 = 0x1;
+    const XLF_KERNEL_64: u16 = 0x1;
+    const SECTOR_SIZE: u64 = 512;
+    const ENTRY_64BIT_OFFSET: u64 = 0x200;
+
+    let hdr = &mut setup_header.0;
+    let (version, loadflags, xloadflags) = (hdr.version, hdr.loadflags, hdr.xloadflags);
+    if version < MIN_BOOT_PROTOCOL_VERSION
+        || loadflags & LOADED_HIGH == 0
+        || xloadflags & XLF_KERNEL_64 == 0
+    {
+        return Err(Error::InvalidBzImage);
+    }
+
+    // The real-mode setup code, made of the boot sector and `setup_sects` sectors, is not used.
+    let setup_sects = match hdr.setup_sects {
+        0 => 4,
+        setup_sects => u64::from(setup_sects),
+    };
+    let kernel_offset = (setup_sects + 1) * SECTOR_SIZE;
+    let image_size = kernel_image
+        .seek(SeekFrom::End(0))
+        .map_err(|_| Error::SeekKernelImage)?;
+    let kernel_size = image_size
+        .checked_sub(kernel_offset)
+        .ok_or(Error::InvalidBzImage)?;
+
+    kernel_image
+        .seek(SeekFrom::Start(kernel_offset))
+        .map_err(|_| Error::SeekKernelStart)?;
+    guest_mem
+        .read_from(
+            GuestAddress(start_address),
+            kernel_image,
+            kernel_size as usize,
+        )
+        .map_err(|_| Error::ReadKernelImage)?;
+
+    hdr.code32_start = start_address as u32;
+    Ok(LoadedKernel {
+        entry_addr: GuestAddress(start_address + ENTRY_64BIT_OFFSET),
+        format: KernelFormat::BzImage(setup_header),
+    })
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn load_elf<F>(
+    guest_mem: &GuestMemoryMmap,
+    kernel_image: &mut F,
+    start_address: u64,
+) -> Result<LoadedKernel>
 where
     F: Read + Seek,
 {
@@ -147,7 +299,77 @@ where
             .map_err(|_| Error::ReadKernelImage)?;
     }
 
-    Ok(GuestAddress(ehdr.e_entry))
+    // Kernels advertising a PVH entry point are booted through it.
+    if let Some(pvh_entry_addr) = read_pvh_entry_addr(kernel_image, &phdrs)? {
+        if pvh_entry_addr < start_address {
+            return Err(Error::InvalidEntryAddress);
+        }
+        return Ok(LoadedKernel {
+            entry_addr: GuestAddress(pvh_entry_addr),
+            format: KernelFormat::PvhElf,
+        });
+    }
+
+    Ok(LoadedKernel {
+        entry_addr: GuestAddress(ehdr.e_entry),
+        format: KernelFormat::Elf,
+    })
+}
+
+/// Looks for the Xen note holding the 32-bit PVH entry point in the ELF notes.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn read_pvh_entry_addr<F>(kernel_image: &mut F, phdrs: &[elf::Elf64_Phdr]) -> Result<Option<u64>>
+where
+    F: Read + Seek,
+{
+    const XEN_ELFNOTE_PHYS32_ENTRY: u32 = 18;
+    const XEN_NOTE_NAME: &[u8; 4] = b"Xen\0";
+    let align_up = |size: u32| (u64::from(size) + 3) & !3;
+
+    for phdr in phdrs.iter().filter(|phdr| phdr.p_type == elf::PT_NOTE) {
+        kernel_image
+            .seek(SeekFrom::Start(phdr.p_offset))
+            .map_err(|_| Error::SeekNoteHeader)?;
+
+        let mut offset = 0;
+        while offset < phdr.p_filesz {
+            let mut nhdr = elf::Elf64_Nhdr::default();
+            kernel_image
+                .read_exact(nhdr.as_mut_slice())
+                .map_err(|_| Error::ReadKernelDataStruct("Failed to read ELF note header"))?;
+            let (name_size, desc_size) = (align_up(nhdr.n_namesz), align_up(nhdr.n_descsz));
+            offset += mem::size_of::<elf::Elf64_Nhdr>() as u64 + name_size + desc_size;
+
+            if nhdr.n_type != XEN_ELFNOTE_PHYS32_ENTRY || nhdr.n_namesz != 4 {
+                kernel_image
+                    .seek(SeekFrom::Current((name_size + desc_size) as i64))
+                    .map_err(|_| Error::SeekNoteHeader)?;
+                continue;
+            }
+            let mut name = [0u8; 4];
+            kernel_image
+                .read_exact(&mut name)
+                .map_err(|_| Error::ReadKernelDataStruct("Failed to read ELF note name"))?;
+            if &name != XEN_NOTE_NAME {
+                kernel_image
+                    .seek(SeekFrom::Current(desc_size as i64))
+                    .map_err(|_| Error::SeekNoteHeader)?;
+                continue;
+            }
+
+            // The entry point is a 32-bit physical address, possibly stored in 64 bits.
+            if nhdr.n_descsz < 4 {
+                return Err(Error::InvalidPvhNote);
+            }
+            let mut entry_addr = [0u8; 4];
+            kernel_image
+                .read_exact(&mut entry_addr)
+                .map_err(|_| Error::ReadKernelDataStruct("Failed to read PVH entry point"))?;
+            return Ok(Some(u64::from(u32::from_le_bytes(entry_addr))));
+        }
+    }
+
+    Ok(None)
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -227,7 +449,9 @@ where
         )
         .map_err(|_| Error::ReadKernelImage)?;
 
-    Ok(GuestAddress(kernel_load_offset))
+    Ok(LoadedKernel {
+        entry_addr: GuestAddress(kernel_load_offset),
+    })
 }
 
 /// Writes the command line string to the given memory slice.
@@ -295,7 +519,170 @@ mod tests {
         #[cfg(target_arch = "aarch64")]
         let load_addr = 0x8_0000;
         assert_eq!(
-            Ok(GuestAddress(load_addr)),
+            GuestAddress(load_addr),
+            load_kernel(&gm, &mut Cursor::new(&image), 0)
+                .unwrap()
+                .entry_addr
+        );
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert_eq!(
+            KernelFormat::Elf,
+            load_kernel(&gm, &mut Cursor::new(&image), 0)
+                .unwrap()
+                .format
+        );
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn make_bzimage(setup_sects: u8, version: u16, xloadflags: u16) -> Vec<u8> {
+        let mut setup_header = SetupHeader::default();
+        setup_header.0.setup_sects = setup_sects;
+        setup_header.0.boot_flag = 0xaa55;
+        setup_header.0.header = 0x5372_6448;
+        setup_header.0.version = version;
+        setup_header.0.loadflags = 0x1;
+        setup_header.0.xloadflags = xloadflags;
+
+        let kernel_offset = (usize::from(setup_sects) + 1) * 512;
+        let mut image = vec![0u8; kernel_offset + 0x400];
+        image[0x1f1..0x1f1 + mem::size_of::<SetupHeader>()]
+            .copy_from_slice(setup_header.as_slice());
+        image[kernel_offset..].iter_mut().for_each(|b| *b = 0xaa);
+        image
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn test_load_bzimage() {
+        let gm = create_guest_mem();
+        let image = make_bzimage(2, 0x020f, 0x1);
+        let loaded = load_kernel(&gm, &mut Cursor::new(&image), 0x10_0000).unwrap();
+        assert_eq!(loaded.entry_addr, GuestAddress(0x10_0200));
+        match loaded.format {
+            KernelFormat::BzImage(setup_header) => {
+                assert_eq!({ setup_header.0.code32_start }, 0x10_0000);
+                assert_eq!({ setup_header.0.version }, 0x020f);
+            }
+            format => panic!("Unexpected kernel format {:?}", format),
+        }
+        // Only the protected-mode kernel is loaded.
+        let val: u8 = gm.read_obj(GuestAddress(0x10_0000)).unwrap();
+        assert_eq!(val, 0xaa);
+        let val: u8 = gm.read_obj(GuestAddress(0x10_03ff)).unwrap();
+        assert_eq!(val, 0xaa);
+        let val: u8 = gm.read_obj(GuestAddress(0x10_0400)).unwrap();
+        assert_eq!(val, 0);
+
+        // A setup_sects of 0 stands for 4 sectors.
+        let mut image = make_bzimage(4, 0x020f, 0x1);
+        image[0x1f1] = 0;
+        assert_eq!(
+            GuestAddress(0x10_0200),
+            load_kernel(&gm, &mut Cursor::new(&image), 0x10_0000)
+                .unwrap()
+                .entry_addr
+        );
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn test_load_bad_bzimage() {
+        let gm = create_guest_mem();
+        // The 64-bit entry point is only available starting with the boot protocol 2.12.
+        let image = make_bzimage(2, 0x020b, 0x1);
+        assert_eq!(
+            Err(Error::InvalidBzImage),
+            load_kernel(&gm, &mut Cursor::new(&image), 0x10_0000)
+        );
+        let image = make_bzimage(2, 0x020f, 0x0);
+        assert_eq!(
+            Err(Error::InvalidBzImage),
+            load_kernel(&gm, &mut Cursor::new(&image), 0x10_0000)
+        );
+        let image = make_bzimage(2, 0x020f, 0x1);
+        assert_eq!(
+            Err(Error::ReadKernelImage),
+            load_kernel(&gm, &mut Cursor::new(&image), MEM_SIZE as u64)
+        );
+    }
+
+    // Builds an ELF with a loadable segment and a note segment holding `notes`.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn make_elf_with_notes(notes: &[(&[u8; 4], u32, &[u8])]) -> Vec<u8> {
+        let ehdr_size = mem::size_of::<elf::Elf64_Ehdr>();
+        let phdr_size = mem::size_of::<elf::Elf64_Phdr>();
+        let notes_offset = ehdr_size + 2 * phdr_size;
+
+        let mut note_bytes = Vec::new();
+        for (name, n_type, desc) in notes {
+            let nhdr = elf::Elf64_Nhdr {
+                n_namesz: 4,
+                n_descsz: desc.len() as u32,
+                n_type: *n_type,
+            };
+            note_bytes.extend_from_slice(nhdr.as_slice());
+            note_bytes.extend_from_slice(*name);
+            note_bytes.extend_from_slice(desc);
+            note_bytes.resize((note_bytes.len() + 3) & !3, 0);
+        }
+        let data_offset = notes_offset + note_bytes.len();
+
+        let mut ehdr = elf::Elf64_Ehdr::default();
+        ehdr.e_ident[..4].copy_from_slice(b"\x7fELF");
+        ehdr.e_ident[elf::EI_DATA as usize] = elf::ELFDATA2LSB as u8;
+        ehdr.e_entry = 0x10_0000;
+        ehdr.e_phoff = ehdr_size as u64;
+        ehdr.e_phentsize = phdr_size as u16;
+        ehdr.e_phnum = 2;
+        let load_phdr = elf::Elf64_Phdr {
+            p_type: elf::PT_LOAD,
+            p_offset: data_offset as u64,
+            p_paddr: 0x10_0000,
+            p_filesz: 0x10,
+            ..Default::default()
+        };
+        let note_phdr = elf::Elf64_Phdr {
+            p_type: elf::PT_NOTE,
+            p_offset: notes_offset as u64,
+            p_filesz: note_bytes.len() as u64,
+            ..Default::default()
+        };
+
+        let mut image = Vec::new();
+        image.extend_from_slice(ehdr.as_slice());
+        image.extend_from_slice(load_phdr.as_slice());
+        image.extend_from_slice(note_phdr.as_slice());
+        image.extend_from_slice(&note_bytes);
+        image.extend_from_slice(&[0xaa; 0x10]);
+        image
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn test_load_pvh_kernel() {
+        let gm = create_guest_mem();
+
+        // Without the Xen note, the 64-bit ELF entry point is used.
+        let image = make_elf_with_notes(&[(b"GNU\0", 18, &[0x10, 0, 0x10, 0])]);
+        let loaded = load_kernel(&gm, &mut Cursor::new(&image), 0).unwrap();
+        assert_eq!(loaded.entry_addr, GuestAddress(0x10_0000));
+        assert_eq!(loaded.format, KernelFormat::Elf);
+
+        let image = make_elf_with_notes(&[
+            (b"GNU\0", 3, &[1, 2, 3, 4, 5, 6]),
+            (b"Xen\0", 18, &0x10_0100u64.to_le_bytes()),
+        ]);
+        let loaded = load_kernel(&gm, &mut Cursor::new(&image), 0).unwrap();
+        assert_eq!(loaded.entry_addr, GuestAddress(0x10_0100));
+        assert_eq!(loaded.format, KernelFormat::PvhElf);
+        assert_eq!(
+            Err(Error::InvalidEntryAddress),
+            load_kernel(&gm, &mut Cursor::new(&image), 0x10_0080)
+        );
+
+        let image = make_elf_with_notes(&[(b"Xen\0", 18, &[0x10, 0])]);
+        assert_eq!(
+            Err(Error::InvalidPvhNote),
             load_kernel(&gm, &mut Cursor::new(&image), 0)
         );
     }
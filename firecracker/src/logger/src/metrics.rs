@@ -321,6 +321,10 @@ pub struct PutRequestsMetrics {
     pub boot_source_count: SharedIncMetric,
     /// Number of failures during attaching source of boot.
     pub boot_source_fails: SharedIncMetric,
+    /// Number of PUTs for setting the custom CPU template.
+    pub cpu_cfg_count: SharedIncMetric,
+    /// Number of failures in setting the custom CPU template.
+    pub cpu_cfg_fails: SharedIncMetric,
     /// Number of PUTs triggering a block attach.
     pub drive_count: SharedIncMetric,
     /// Number of failures in attaching a block device.
@@ -469,6 +473,18 @@ pub struct MmdsMetrics {
     pub connections_created: SharedIncMetric,
     /// The number of connections cleaned up by the MMDS TCP handler.
     pub connections_destroyed: SharedIncMetric,
+    /// The number of updates of the guest data store by the guest.
+    pub guest_writes: SharedIncMetric,
+    /// The number of guest writes rejected for exceeding their size or rate limits.
+    pub guest_writes_rejected: SharedIncMetric,
+    /// The number of DNS queries received by the DNS responder.
+    pub dns_rx_count: SharedIncMetric,
+    /// The number of frames sent by the DNS responder.
+    pub dns_tx_count: SharedIncMetric,
+    /// The number of DNS queries refused because the name is not known.
+    pub dns_refused: SharedIncMetric,
+    /// The number of DNS queries or replies dropped by the DNS responder.
+    pub dns_fails: SharedIncMetric,
 }
 
 /// Network-related metrics.
@@ -480,6 +496,12 @@ pub struct NetDeviceMetrics {
     pub cfg_fails: SharedIncMetric,
     //// Number of times the mac address was updated through the config space.
     pub mac_address_updates: SharedIncMetric,
+    /// Number of DHCP messages received from the guest by the built-in DHCP server.
+    pub dhcp_rx_count: SharedIncMetric,
+    /// Number of DHCP replies sent to the guest by the built-in DHCP server.
+    pub dhcp_tx_count: SharedIncMetric,
+    /// Number of DHCP messages from the guest which could not be handled or answered.
+    pub dhcp_fails: SharedIncMetric,
     /// No available buffer for the net device rx queue.
     pub no_rx_avail_buffer: SharedIncMetric,
     /// No available buffer for the net device tx queue.
@@ -1,6 +1,7 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::HashMap;
 use std::result::Result;
 
 use crate::HttpHeaderError;
@@ -69,11 +70,13 @@ impl Header {
 /// Wrapper over the list of headers associated with a Request that we need
 /// in order to parse the request correctly and be able to respond to it.
 ///
-/// The only `Content-Type`s supported are `text/plain` and `application/json`, which are both
-/// in plain text actually and don't influence our parsing process.
+/// The `Content-Type`s supported are `text/plain`, `application/json` and the JSON patch
+/// formats, which are all in plain text actually and don't influence our parsing process. The
+/// type is kept nonetheless, for the users of the request to interpret the body.
 ///
-/// All the other possible header fields are not necessary in order to serve this connection
-/// and, thus, are not of interest to us. However, we still look for header fields that might
+/// All the other possible header fields are not necessary in order to serve this connection,
+/// so they are only kept as custom entries for the users of the request. However, we still look
+/// for header fields that might
 /// invalidate our request as we don't support the full set of HTTP/1.1 specification.
 /// Such header entries are "Transfer-Encoding: identity; q=0", which means a compression
 /// algorithm is applied to the body of the request, or "Expect: 103-checkpoint".
@@ -94,6 +97,11 @@ pub struct Headers {
     /// `Accept` header might be used by HTTP clients to enforce server responses with content
     /// formatted in a specific way.
     accept: MediaType,
+    /// The `Content-Type` header field tells the users of the request how to interpret its body.
+    content_type: MediaType,
+    /// Header fields unknown to us, keyed by their lowercase names, which the users of a
+    /// request may nonetheless be interested in.
+    custom_entries: HashMap<String, String>,
 }
 
 impl Default for Headers {
@@ -106,6 +114,8 @@ impl Default for Headers {
             // The default `Accept` media type is plain text. This is inclusive enough
             // for structured and unstructured text.
             accept: MediaType::PlainText,
+            content_type: MediaType::default(),
+            custom_entries: HashMap::new(),
         }
     }
 }
@@ -156,7 +166,10 @@ impl Headers {
                         },
                         Header::ContentType => {
                             match MediaType::try_from(entry[1].trim().as_bytes()) {
-                                Ok(_) => Ok(()),
+                                Ok(content_type) => {
+                                    self.content_type = content_type;
+                                    Ok(())
+                                }
                                 Err(_) => Err(RequestError::HeaderError(
                                     HttpHeaderError::UnsupportedValue(
                                         entry[0].to_string(),
@@ -206,12 +219,11 @@ impl Headers {
                         Header::AcceptEncoding => Encoding::try_from(entry[1].trim().as_bytes()),
                     }
                 } else {
-                    Err(RequestError::HeaderError(
-                        HttpHeaderError::UnsupportedValue(
-                            entry[0].to_string(),
-                            entry[1].to_string(),
-                        ),
-                    ))
+                    self.custom_entries.insert(
+                        entry[0].trim().to_ascii_lowercase(),
+                        entry[1].trim().to_string(),
+                    );
+                    Ok(())
                 }
             }
             Err(utf8_err) => Err(RequestError::HeaderError(
@@ -242,6 +254,21 @@ impl Headers {
         self.accept
     }
 
+    /// Returns the `Content-Type` header `MediaType`, which is `application/json` if the header
+    /// is missing.
+    pub fn content_type(&self) -> MediaType {
+        self.content_type
+    }
+
+    /// Returns the value of the header field `name`, which is not one of the fields known to us.
+    ///
+    /// Header field names are case-insensitive.
+    pub fn custom_entry(&self, name: &str) -> Option<&str> {
+        self.custom_entries
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
     /// Parses a byte slice into a Headers structure for a HTTP request.
     ///
     /// The byte slice is expected to have the following format: </br>
@@ -355,6 +382,10 @@ pub enum MediaType {
     PlainText,
     /// Media Type: "application/json".
     ApplicationJson,
+    /// Media Type: "application/json-patch+json", as per RFC 6902.
+    ApplicationJsonPatch,
+    /// Media Type: "application/merge-patch+json", as per RFC 7396.
+    ApplicationMergePatch,
 }
 
 impl Default for MediaType {
@@ -389,6 +420,8 @@ impl MediaType {
         match utf8_slice.as_str().trim() {
             "text/plain" => Ok(Self::PlainText),
             "application/json" => Ok(Self::ApplicationJson),
+            "application/json-patch+json" => Ok(Self::ApplicationJsonPatch),
+            "application/merge-patch+json" => Ok(Self::ApplicationMergePatch),
             _ => Err(RequestError::InvalidRequest),
         }
     }
@@ -407,6 +440,8 @@ impl MediaType {
         match self {
             Self::PlainText => "text/plain",
             Self::ApplicationJson => "application/json",
+            Self::ApplicationJsonPatch => "application/json-patch+json",
+            Self::ApplicationMergePatch => "application/merge-patch+json",
         }
     }
 }
@@ -422,6 +457,8 @@ mod tests {
                 expect,
                 chunked,
                 accept: MediaType::PlainText,
+                content_type: MediaType::default(),
+                custom_entries: HashMap::new(),
             }
         }
     }
@@ -432,6 +469,7 @@ mod tests {
         assert_eq!(headers.content_length(), 0);
         assert_eq!(headers.chunked(), false);
         assert_eq!(headers.expect(), false);
+        assert_eq!(headers.content_type(), MediaType::ApplicationJson);
     }
 
     #[test]
@@ -446,6 +484,16 @@ mod tests {
             MediaType::PlainText
         );
 
+        assert_eq!(
+            MediaType::try_from(b"application/json-patch+json").unwrap(),
+            MediaType::ApplicationJsonPatch
+        );
+
+        assert_eq!(
+            MediaType::try_from(b"application/merge-patch+json").unwrap(),
+            MediaType::ApplicationMergePatch
+        );
+
         assert_eq!(
             MediaType::try_from(b"").unwrap_err(),
             RequestError::InvalidRequest
@@ -464,6 +512,12 @@ mod tests {
 
         let media_type = MediaType::PlainText;
         assert_eq!(media_type.as_str(), "text/plain");
+
+        let media_type = MediaType::ApplicationJsonPatch;
+        assert_eq!(media_type.as_str(), "application/json-patch+json");
+
+        let media_type = MediaType::ApplicationMergePatch;
+        assert_eq!(media_type.as_str(), "application/merge-patch+json");
     }
 
     #[test]
@@ -527,6 +581,11 @@ mod tests {
         )
         .unwrap();
         assert_eq!(headers.content_length, 29);
+        assert_eq!(
+            headers.custom_entry("last-modified"),
+            Some("Tue, 15 Nov 1994 12:45:26 GMT")
+        );
+        assert_eq!(headers.custom_entry("If-Modified-Since"), None);
 
         let bytes: [u8; 10] = [130, 140, 150, 130, 140, 150, 130, 140, 150, 160];
         // Invalid headers.
@@ -610,6 +669,11 @@ mod tests {
         assert!(header
             .parse_header_line(b"Content-Type: application/json")
             .is_ok());
+        assert_eq!(header.content_type(), MediaType::ApplicationJson);
+        assert!(header
+            .parse_header_line(b"Content-Type: application/json-patch+json")
+            .is_ok());
+        assert_eq!(header.content_type(), MediaType::ApplicationJsonPatch);
 
         // Test valid accept media type.
         assert!(header
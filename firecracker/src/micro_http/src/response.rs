@@ -23,10 +23,16 @@ pub enum StatusCode {
     NoContent,
     /// 400, Bad Request
     BadRequest,
+    /// 401, Unauthorized
+    Unauthorized,
     /// 404, Not Found
     NotFound,
     /// 405, Method Not Allowed
     MethodNotAllowed,
+    /// 413, Payload Too Large
+    PayloadTooLarge,
+    /// 429, Too Many Requests
+    TooManyRequests,
     /// 500, Internal Server Error
     InternalServerError,
     /// 501, Not Implemented
@@ -41,8 +47,11 @@ impl StatusCode {
             Self::OK => b"200",
             Self::NoContent => b"204",
             Self::BadRequest => b"400",
+            Self::Unauthorized => b"401",
             Self::NotFound => b"404",
             Self::MethodNotAllowed => b"405",
+            Self::PayloadTooLarge => b"413",
+            Self::TooManyRequests => b"429",
             Self::InternalServerError => b"500",
             Self::NotImplemented => b"501",
         }
@@ -83,6 +92,9 @@ pub struct ResponseHeaders {
     server: String,
     allow: Vec<Method>,
     accept_encoding: bool,
+    keep_alive: bool,
+    chunked: bool,
+    custom_entries: Vec<(String, String)>,
 }
 
 impl Default for ResponseHeaders {
@@ -93,6 +105,9 @@ impl Default for ResponseHeaders {
             server: String::from("Firecracker API"),
             allow: Vec::new(),
             accept_encoding: false,
+            keep_alive: true,
+            chunked: false,
+            custom_entries: Vec::new(),
         }
     }
 }
@@ -125,12 +140,33 @@ impl ResponseHeaders {
         buf.write_all(self.server.as_bytes())?;
 
         buf.write_all(&[CR, LF])?;
-        buf.write_all(b"Connection: keep-alive")?;
+        if self.keep_alive {
+            buf.write_all(b"Connection: keep-alive")?;
+        } else {
+            buf.write_all(b"Connection: close")?;
+        }
         buf.write_all(&[CR, LF])?;
 
         self.write_allow_header(buf)?;
 
-        if self.content_length != 0 {
+        for (name, value) in self.custom_entries.iter() {
+            buf.write_all(name.as_bytes())?;
+            buf.write_all(&[COLON, SP])?;
+            buf.write_all(value.as_bytes())?;
+            buf.write_all(&[CR, LF])?;
+        }
+
+        if self.chunked {
+            buf.write_all(Header::ContentType.raw())?;
+            buf.write_all(&[COLON, SP])?;
+            buf.write_all(self.content_type.as_str().as_bytes())?;
+            buf.write_all(&[CR, LF])?;
+
+            buf.write_all(Header::TransferEncoding.raw())?;
+            buf.write_all(&[COLON, SP])?;
+            buf.write_all(b"chunked")?;
+            buf.write_all(&[CR, LF])?;
+        } else if self.content_length != 0 {
             buf.write_all(Header::ContentType.raw())?;
             buf.write_all(&[COLON, SP])?;
             buf.write_all(self.content_type.as_str().as_bytes())?;
@@ -222,6 +258,28 @@ impl Response {
         self.headers.set_server(server);
     }
 
+    /// Sets whether the connection stays open after the `Response` is sent, which is
+    /// announced through the `Connection` header.
+    pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.headers.keep_alive = keep_alive;
+    }
+
+    /// Returns `true` if the connection stays open after the `Response` is sent.
+    pub fn keep_alive(&self) -> bool {
+        self.headers.keep_alive
+    }
+
+    /// Removes the body of the `Response`, so that it can be sent separately using the chunked
+    /// transfer encoding.
+    ///
+    /// The headers written afterwards announce `Transfer-Encoding: chunked` instead of the
+    /// content length, and the caller is responsible for writing the body chunks.
+    pub fn take_body_for_chunked_encoding(&mut self) -> Option<Body> {
+        self.headers.chunked = true;
+        self.headers.set_content_length(0);
+        self.body.take()
+    }
+
     /// Sets the HTTP allowed methods.
     pub fn set_allow(&mut self, methods: Vec<Method>) {
         self.headers.allow = methods;
@@ -232,6 +290,23 @@ impl Response {
         self.headers.allow.push(method);
     }
 
+    /// Adds a header which is not otherwise handled by the `Response`. Such headers are written
+    /// in the order they were added, after the `Allow` header.
+    pub fn add_custom_header(&mut self, name: &str, value: &str) {
+        self.headers
+            .custom_entries
+            .push((name.to_string(), value.to_string()));
+    }
+
+    /// Returns the value of the first custom header with the given name, if any.
+    pub fn custom_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .custom_entries
+            .iter()
+            .find(|(entry_name, _)| entry_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
     fn write_body<T: Write>(&self, mut buf: T) -> Result<(), WriteError> {
         if let Some(ref body) = self.body {
             buf.write_all(body.raw())?;
@@ -332,6 +407,29 @@ mod tests {
         assert!(response.write_all(&mut response_buf.as_mut()).is_err());
     }
 
+    #[test]
+    fn test_write_chunked_response() {
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        response.set_body(Body::new("This is a test"));
+        response.set_keep_alive(false);
+        assert!(!response.keep_alive());
+
+        assert_eq!(
+            response.take_body_for_chunked_encoding().unwrap(),
+            Body::new("This is a test")
+        );
+        assert!(response.body().is_none());
+
+        let expected_response: &'static [u8] = b"HTTP/1.1 200 \r\n\
+            Server: Firecracker API\r\n\
+            Connection: close\r\n\
+            Content-Type: text/plain\r\n\
+            Transfer-Encoding: chunked\r\n\r\n";
+        let mut response_buf = Vec::new();
+        assert!(response.write_all(&mut response_buf).is_ok());
+        assert_eq!(response_buf.as_slice(), expected_response);
+    }
+
     #[test]
     fn test_set_server() {
         let mut response = Response::new(Version::Http10, StatusCode::OK);
@@ -368,8 +466,11 @@ mod tests {
         assert_eq!(StatusCode::OK.raw(), b"200");
         assert_eq!(StatusCode::NoContent.raw(), b"204");
         assert_eq!(StatusCode::BadRequest.raw(), b"400");
+        assert_eq!(StatusCode::Unauthorized.raw(), b"401");
         assert_eq!(StatusCode::NotFound.raw(), b"404");
         assert_eq!(StatusCode::MethodNotAllowed.raw(), b"405");
+        assert_eq!(StatusCode::PayloadTooLarge.raw(), b"413");
+        assert_eq!(StatusCode::TooManyRequests.raw(), b"429");
         assert_eq!(StatusCode::InternalServerError.raw(), b"500");
         assert_eq!(StatusCode::NotImplemented.raw(), b"501");
     }
@@ -381,4 +482,24 @@ mod tests {
         response.allow_method(Method::Put);
         assert_eq!(response.allow(), vec![Method::Get, Method::Put]);
     }
+
+    #[test]
+    fn test_custom_header() {
+        let mut response = Response::new(Version::Http11, StatusCode::OK);
+        response.set_allow(vec![Method::Get]);
+        response.add_custom_header("X-First", "1");
+        response.add_custom_header("X-Second", "two");
+        assert_eq!(response.custom_header("x-second"), Some("two"));
+        assert_eq!(response.custom_header("X-Third"), None);
+
+        let expected_response: &'static [u8] = b"HTTP/1.1 200 \r\n\
+            Server: Firecracker API\r\n\
+            Connection: keep-alive\r\n\
+            Allow: GET\r\n\
+            X-First: 1\r\n\
+            X-Second: two\r\n\r\n";
+        let mut response_buf = Vec::new();
+        assert!(response.write_all(&mut response_buf).is_ok());
+        assert_eq!(response_buf.as_slice(), expected_response);
+    }
 }
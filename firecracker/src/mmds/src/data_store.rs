@@ -1,14 +1,52 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use serde_json::Value;
+use rate_limiter::{BucketReduction, TokenBucket};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::fmt;
 
+use crate::token::{Error as TokenError, TokenAuthority};
+
+/// Default maximum size, in bytes, of the serialized data store.
+pub const DEFAULT_DATA_STORE_LIMIT: usize = 51200;
+
 /// The Mmds is the Microvm Metadata Service represented as an untyped json.
 #[derive(Clone)]
 pub struct Mmds {
     data_store: Value,
+    data_store_limit: usize,
     is_initialized: bool,
+    // Increased on every update of the data store, so that guests can watch for changes.
+    data_version: u64,
+    token_authority: Option<TokenAuthority>,
+    // The data written by the guest, along with the limits of its writes.
+    guest_data_store: Value,
+    guest_write_limits: Option<GuestWriteLimits>,
+}
+
+/// The limits of the writes of the guest to its data store.
+#[derive(Clone, Debug)]
+pub struct GuestWriteLimits {
+    /// Maximum size, in bytes, of the serialized guest data store.
+    pub data_store_limit: usize,
+    /// Rate limiter of the guest writes, where each write takes one token.
+    pub rate_limiter: Option<TokenBucket>,
+}
+
+/// The MMDS version, telling whether the guest needs a session token to access the MMDS.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum MmdsVersion {
+    /// The guest accesses the MMDS without session tokens.
+    V1,
+    /// The guest gets a session token through a PUT request, and presents it to access the MMDS.
+    V2,
+}
+
+impl Default for MmdsVersion {
+    fn default() -> Self {
+        MmdsVersion::V1
+    }
 }
 
 /// MMDS possible outputs.
@@ -17,8 +55,21 @@ pub enum OutputFormat {
     Imds,
 }
 
+/// The semantics of a patch of the data store.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatchFormat {
+    /// A list of operations applied in order, as per JSON Patch (RFC 6902).
+    JsonPatch,
+    /// A partial document merged into the data store, as per JSON Merge Patch (RFC 7396).
+    MergePatch,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Error {
+    DataStoreLimitExceeded,
+    GuestWriteRateExceeded,
+    GuestWritesNotAllowed,
+    InvalidPatch(String),
     NotFound,
     NotInitialized,
     UnsupportedValueType,
@@ -27,6 +78,14 @@ pub enum Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Error::DataStoreLimitExceeded => {
+                write!(f, "The MMDS data store exceeds its size limit.")
+            }
+            Error::GuestWriteRateExceeded => {
+                write!(f, "The guest writes to the MMDS exceed their rate limit.")
+            }
+            Error::GuestWritesNotAllowed => write!(f, "The guest cannot write to the MMDS."),
+            Error::InvalidPatch(ref msg) => write!(f, "Invalid patch of the MMDS: {}", msg),
             Error::NotFound => write!(f, "The MMDS resource does not exist."),
             Error::NotInitialized => write!(f, "The MMDS data store is not initialized."),
             Error::UnsupportedValueType => write!(
@@ -41,7 +100,12 @@ impl Default for Mmds {
     fn default() -> Self {
         Mmds {
             data_store: Value::default(),
+            data_store_limit: DEFAULT_DATA_STORE_LIMIT,
             is_initialized: false,
+            data_version: 0,
+            token_authority: None,
+            guest_data_store: Value::Null,
+            guest_write_limits: None,
         }
     }
 }
@@ -58,18 +122,183 @@ impl Mmds {
         }
     }
 
+    /// Checks that `data` fits in the data store once serialized.
+    fn check_data_store_limit(&self, data: &Value) -> Result<(), Error> {
+        if data.to_string().len() > self.data_store_limit {
+            Err(Error::DataStoreLimitExceeded)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Allows the guest to write its own data store within `limits`, or forbids it if no limits
+    /// are given, dropping the data written so far.
+    pub fn set_guest_write_limits(&mut self, limits: Option<GuestWriteLimits>) {
+        match limits {
+            Some(_) if self.guest_data_store.is_null() => {
+                self.guest_data_store = Value::Object(Map::new())
+            }
+            Some(_) => (),
+            None => self.guest_data_store = Value::Null,
+        }
+        self.guest_write_limits = limits;
+    }
+
+    /// Returns whether the guest can write its own data store.
+    pub fn guest_writes_allowed(&self) -> bool {
+        self.guest_write_limits.is_some()
+    }
+
+    /// Checks that the guest can write `data` to its data store.
+    fn check_guest_write(&mut self, data: &Value) -> Result<(), Error> {
+        let limits = self
+            .guest_write_limits
+            .as_mut()
+            .ok_or(Error::GuestWritesNotAllowed)?;
+        if let Some(rate_limiter) = limits.rate_limiter.as_mut() {
+            if rate_limiter.reduce(1) == BucketReduction::Failure {
+                return Err(Error::GuestWriteRateExceeded);
+            }
+        }
+        if data.to_string().len() > limits.data_store_limit {
+            return Err(Error::DataStoreLimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Replaces the data store of the guest.
+    pub fn put_guest_data(&mut self, data: Value) -> Result<(), Error> {
+        self.check_guest_write(&data)?;
+        self.guest_data_store = data;
+        Ok(())
+    }
+
+    /// Patches the data store of the guest.
+    pub fn patch_guest_data(&mut self, patch_data: Value) -> Result<(), Error> {
+        let mut guest_data_store = self.guest_data_store.clone();
+        super::json_patch(&mut guest_data_store, &patch_data);
+        self.check_guest_write(&guest_data_store)?;
+        self.guest_data_store = guest_data_store;
+        Ok(())
+    }
+
+    /// Returns the data store of the guest.
+    pub fn get_guest_data_str(&self) -> Result<String, Error> {
+        if !self.guest_writes_allowed() {
+            return Err(Error::GuestWritesNotAllowed);
+        }
+        Ok(self.guest_data_store.to_string())
+    }
+
+    /// Returns the subtree of the guest data store located at path.
+    pub fn get_guest_value(&self, path: String, format: OutputFormat) -> Result<String, Error> {
+        if !self.guest_writes_allowed() {
+            return Err(Error::GuestWritesNotAllowed);
+        }
+        Mmds::get_subtree(&self.guest_data_store, path, format)
+    }
+
+    /// Returns the maximum size, in bytes, of the serialized data store.
+    pub fn data_store_limit(&self) -> usize {
+        self.data_store_limit
+    }
+
+    /// Sets the maximum size, in bytes, of the serialized data store, enforced on the following
+    /// updates of the data store.
+    pub fn set_data_store_limit(&mut self, data_store_limit: usize) {
+        self.data_store_limit = data_store_limit;
+    }
+
+    /// Returns the MMDS version.
+    pub fn version(&self) -> MmdsVersion {
+        if self.token_authority.is_some() {
+            MmdsVersion::V2
+        } else {
+            MmdsVersion::V1
+        }
+    }
+
+    /// Sets the MMDS version. Switching to V2 creates the authority generating the session
+    /// tokens, while switching to V1 drops it along with the validity of the tokens it issued.
+    pub fn set_version(&mut self, version: MmdsVersion) -> Result<(), TokenError> {
+        match version {
+            MmdsVersion::V1 => self.token_authority = None,
+            MmdsVersion::V2 => {
+                if self.token_authority.is_none() {
+                    self.token_authority = Some(TokenAuthority::new()?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the authority generating the session tokens, if the MMDS requires them.
+    pub fn token_authority(&self) -> Option<&TokenAuthority> {
+        self.token_authority.as_ref()
+    }
+
     pub fn put_data(&mut self, data: Value) -> Result<(), Error> {
+        self.check_data_store_limit(&data)?;
         self.data_store = data;
         self.is_initialized = true;
+        self.data_version += 1;
         Ok(())
     }
 
     pub fn patch_data(&mut self, patch_data: Value) -> Result<(), Error> {
+        self.update_data_store(|data_store| {
+            super::json_patch(data_store, &patch_data);
+            Ok(())
+        })
+    }
+
+    /// Applies the operations of the JSON Patch document `patch` to the data store, which is left
+    /// untouched if any of them fails.
+    pub fn json_patch_data(&mut self, patch: Value) -> Result<(), Error> {
+        self.update_data_store(|data_store| {
+            super::apply_json_patch(data_store, &patch).map_err(Error::InvalidPatch)
+        })
+    }
+
+    /// Patches the data store with `patch_data` according to `format`.
+    pub fn patch_data_as(&mut self, format: PatchFormat, patch_data: Value) -> Result<(), Error> {
+        match format {
+            PatchFormat::JsonPatch => self.json_patch_data(patch_data),
+            PatchFormat::MergePatch => self.patch_data(patch_data),
+        }
+    }
+
+    /// Merges `data` into the data store as per JSON Merge Patch, or stores it as is if the data
+    /// store is not initialized yet.
+    pub fn merge_data(&mut self, data: Value) -> Result<(), Error> {
+        if self.is_initialized {
+            self.patch_data(data)
+        } else {
+            self.put_data(data)
+        }
+    }
+
+    // Updates a copy of the initialized data store, which replaces the data store only if the
+    // update succeeds and fits within the size limit.
+    fn update_data_store<F>(&mut self, update: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Value) -> Result<(), Error>,
+    {
         self.check_data_store_initialized()?;
-        super::json_patch(&mut self.data_store, &patch_data);
+        let mut data_store = self.data_store.clone();
+        update(&mut data_store)?;
+        self.check_data_store_limit(&data_store)?;
+        self.data_store = data_store;
+        self.data_version += 1;
         Ok(())
     }
 
+    /// Returns the version of the data store, which starts at 0, and increases by one on every
+    /// successful update.
+    pub fn data_version(&self) -> u64 {
+        self.data_version
+    }
+
     pub fn get_data_str(&self) -> String {
         if self.data_store.is_null() {
             return String::from("{}");
@@ -78,7 +307,7 @@ impl Mmds {
     }
 
     /// Returns the serde::Value in IMDS format plaintext.
-    /// Currently, only JSON objects and strings can be IMDS formatted.
+    /// JSON objects, arrays, strings, numbers and booleans can be IMDS formatted.
     ///
     /// See the docs for detailed description of the IMDS format:
     /// https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ec2-instance-metadata.html
@@ -91,18 +320,30 @@ impl Mmds {
     ///         "key11": "value11"
     ///         "key12": "value12"
     ///     }
-    ///     "key2" : "value3"
+    ///     "key2" : ["value21", "value22"]
     ///     "key3" : "value3"
     /// }
     ///```
     ///
-    /// IMDS formatted JSON object:
+    /// IMDS formatted JSON object, where the subtrees end with a "/":
     /// ```text
     /// key1/
-    /// key2
+    /// key2/
     /// key3
     /// ```
     ///
+    /// JSON array:
+    /// ```json
+    /// ["value1", 2, {"key3": "value3"}]
+    /// ```
+    ///
+    /// IMDS formatted array, with one item per line and the subtrees listed by index:
+    /// ```text
+    /// value1
+    /// 2
+    /// 2/
+    /// ```
+    ///
     /// JSON string:
     /// ```json
     /// "value"
@@ -115,18 +356,15 @@ impl Mmds {
     ///
     /// If the `serde_json::Value` is not supported, an `UnsupportedValueType` error is returned.
     fn format_imds(json: &Value) -> Result<String, Error> {
-        // If the `dict` is Value::Null, Error::NotFound is thrown.
-        // If the `dict` is not a dictionary, a Vec with the value corresponding to
-        // the key is returned.
-        match json.as_object() {
-            Some(map) => {
+        match json {
+            Value::Object(map) => {
                 let mut ret = Vec::new();
                 // When the object is a map, push all the keys in the Vec.
-                for key in map.keys() {
+                for (key, value) in map.iter() {
                     let mut key = key.clone();
-                    // If the key corresponds to a dictionary, a "/" is appended
+                    // If the key corresponds to a subtree, a "/" is appended
                     // to the key name.
-                    if map[&key].is_object() {
+                    if value.is_object() || value.is_array() {
                         key.push_str("/");
                     }
 
@@ -134,26 +372,49 @@ impl Mmds {
                 }
                 Ok(ret.join("\n"))
             }
-            None => {
-                // When the object is not a map, return the value.
-                // Support only `Value::String`.
-                match json.as_str() {
-                    Some(str_val) => Ok(str_val.to_string()),
-                    None => Err(Error::UnsupportedValueType),
+            Value::Array(items) => {
+                let mut ret = Vec::new();
+                for (index, item) in items.iter().enumerate() {
+                    // Subtrees are listed by index, the other items by value.
+                    if item.is_object() || item.is_array() {
+                        ret.push(format!("{}/", index));
+                    } else {
+                        ret.push(Mmds::format_imds_leaf(item)?);
+                    }
                 }
+                Ok(ret.join("\n"))
             }
+            _ => Mmds::format_imds_leaf(json),
+        }
+    }
+
+    // Returns the IMDS formatted value of a leaf of the data store.
+    fn format_imds_leaf(json: &Value) -> Result<String, Error> {
+        match json {
+            Value::String(string) => Ok(string.clone()),
+            Value::Number(number) => Ok(number.to_string()),
+            Value::Bool(boolean) => Ok(boolean.to_string()),
+            _ => Err(Error::UnsupportedValueType),
         }
     }
 
     /// Returns the subtree located at path. When the path corresponds to a leaf, it returns the value.
     /// Returns Error::NotFound when the path is invalid.
     pub fn get_value(&self, path: String, format: OutputFormat) -> Result<String, Error> {
+        Mmds::get_subtree(&self.data_store, path, format)
+    }
+
+    fn get_subtree(
+        data_store: &Value,
+        path: String,
+        format: OutputFormat,
+    ) -> Result<String, Error> {
         // The pointer function splits the input by "/". With a trailing "/", pointer does not
         // know how to get the object.
         let value = if path.ends_with('/') {
-            self.data_store.pointer(&path.as_str()[..(path.len() - 1)])
+            data_store.pointer(&path.as_str()[..(path.len() - 1)])
         } else {
-            self.data_store.pointer(path.as_str())
+            data_store.pointer(path.as_str())
         };
 
         if let Some(json) = value {
@@ -196,6 +457,28 @@ mod tests {
         assert_eq!(mmds.get_data_str(), mmds_json);
     }
 
+    #[test]
+    fn test_mmds_version() {
+        let mut mmds = Mmds::default();
+        assert_eq!(mmds.version(), MmdsVersion::V1);
+        assert!(mmds.token_authority().is_none());
+
+        mmds.set_version(MmdsVersion::V2).unwrap();
+        assert_eq!(mmds.version(), MmdsVersion::V2);
+        let token = mmds.token_authority().unwrap().generate_token(60).unwrap();
+
+        // Setting the same version keeps the tokens valid.
+        mmds.set_version(MmdsVersion::V2).unwrap();
+        assert!(mmds.token_authority().unwrap().is_valid(&token));
+
+        // Going through V1 invalidates them.
+        mmds.set_version(MmdsVersion::V1).unwrap();
+        assert_eq!(mmds.version(), MmdsVersion::V1);
+        assert!(mmds.token_authority().is_none());
+        mmds.set_version(MmdsVersion::V2).unwrap();
+        assert!(!mmds.token_authority().unwrap().is_valid(&token));
+    }
+
     #[test]
     fn test_get_value() {
         let mut mmds = Mmds::default();
@@ -245,6 +528,19 @@ mod tests {
             expected_imds
         );
 
+        // The subtrees of the root are marked with a trailing slash.
+        let expected_imds = "age\nbalance\nmember\nname/\nphones/\nshares_percentage";
+        assert_eq!(
+            mmds.get_value("".to_string(), OutputFormat::Imds).unwrap(),
+            expected_imds
+        );
+        let expected_imds = "first\nsecond";
+        assert_eq!(
+            mmds.get_value("/name".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            expected_imds
+        );
+
         // Retrieve an integer.
         assert_eq!(
             mmds.get_value("/age".to_string(), OutputFormat::Json)
@@ -253,9 +549,8 @@ mod tests {
         );
         assert_eq!(
             mmds.get_value("/age".to_string(), OutputFormat::Imds)
-                .err()
                 .unwrap(),
-            Error::UnsupportedValueType
+            "43"
         );
 
         // Test path ends with /; Value is a dictionary.
@@ -273,9 +568,8 @@ mod tests {
         );
         assert_eq!(
             mmds.get_value("/phones/".to_string(), OutputFormat::Imds)
-                .err()
                 .unwrap(),
-            Error::UnsupportedValueType
+            "+401234567\n+441234567"
         );
 
         // Test path does NOT end with /; Value is a dictionary.
@@ -286,9 +580,8 @@ mod tests {
         );
         assert_eq!(
             mmds.get_value("/phones".to_string(), OutputFormat::Imds)
-                .err()
                 .unwrap(),
-            Error::UnsupportedValueType
+            "+401234567\n+441234567"
         );
 
         // Retrieve the first element of an array.
@@ -311,9 +604,8 @@ mod tests {
         );
         assert_eq!(
             mmds.get_value("/member".to_string(), OutputFormat::Imds)
-                .err()
                 .unwrap(),
-            Error::UnsupportedValueType
+            "false"
         );
 
         // Retrieve a float.
@@ -324,9 +616,8 @@ mod tests {
         );
         assert_eq!(
             mmds.get_value("/shares_percentage".to_string(), OutputFormat::Imds)
-                .err()
                 .unwrap(),
-            Error::UnsupportedValueType
+            "12.12"
         );
 
         // Retrieve a negative integer.
@@ -337,15 +628,17 @@ mod tests {
         );
         assert_eq!(
             mmds.get_value("/balance".to_string(), OutputFormat::Imds)
-                .err()
                 .unwrap(),
-            Error::UnsupportedValueType
+            "-24"
         );
     }
 
     #[test]
     fn test_update_data_store() {
         let mut mmds = Mmds::default();
+        assert_eq!(mmds.data_version(), 0);
+        assert!(mmds.patch_data(serde_json::json!({"age": "43"})).is_err());
+        assert_eq!(mmds.data_version(), 0);
 
         let data = r#"{
             "name": {
@@ -356,6 +649,7 @@ mod tests {
         }"#;
         let data_store: Value = serde_json::from_str(data).unwrap();
         assert!(mmds.put_data(data_store).is_ok());
+        assert_eq!(mmds.data_version(), 1);
 
         let data = r#"{
             "name": {
@@ -386,5 +680,182 @@ mod tests {
         }"#;
         let data_store: Value = serde_json::from_str(data).unwrap();
         assert!(mmds.patch_data(data_store).is_ok());
+        assert_eq!(mmds.data_version(), 4);
+    }
+
+    #[test]
+    fn test_guest_data_store() {
+        let mut mmds = Mmds::default();
+        mmds.put_data(serde_json::json!({"host": "data"})).unwrap();
+
+        // Guest writes are opt-in.
+        assert!(!mmds.guest_writes_allowed());
+        assert_eq!(
+            mmds.put_guest_data(serde_json::json!({"boot": "done"})),
+            Err(Error::GuestWritesNotAllowed)
+        );
+        assert_eq!(mmds.get_guest_data_str(), Err(Error::GuestWritesNotAllowed));
+
+        mmds.set_guest_write_limits(Some(GuestWriteLimits {
+            data_store_limit: 32,
+            rate_limiter: None,
+        }));
+        assert_eq!(mmds.get_guest_data_str().unwrap(), "{}");
+        mmds.put_guest_data(serde_json::json!({"boot": "done"}))
+            .unwrap();
+        mmds.patch_guest_data(serde_json::json!({"health": "ok"}))
+            .unwrap();
+        assert_eq!(
+            mmds.get_guest_data_str().unwrap(),
+            r#"{"boot":"done","health":"ok"}"#
+        );
+        assert_eq!(
+            mmds.get_guest_value("/health".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            "ok"
+        );
+        // The host data store is left untouched.
+        assert_eq!(mmds.get_data_str(), r#"{"host":"data"}"#);
+
+        // Size limit.
+        assert_eq!(
+            mmds.patch_guest_data(serde_json::json!({"key": "a much longer value"})),
+            Err(Error::DataStoreLimitExceeded)
+        );
+        assert_eq!(
+            mmds.get_guest_data_str().unwrap(),
+            r#"{"boot":"done","health":"ok"}"#
+        );
+
+        // Updating the limits keeps the data, while forbidding writes drops it.
+        mmds.set_guest_write_limits(Some(GuestWriteLimits {
+            data_store_limit: 64,
+            rate_limiter: None,
+        }));
+        assert_eq!(
+            mmds.get_guest_data_str().unwrap(),
+            r#"{"boot":"done","health":"ok"}"#
+        );
+        mmds.set_guest_write_limits(None);
+        mmds.set_guest_write_limits(Some(GuestWriteLimits {
+            data_store_limit: 64,
+            rate_limiter: None,
+        }));
+        assert_eq!(mmds.get_guest_data_str().unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_guest_write_rate_limit() {
+        let mut mmds = Mmds::default();
+        mmds.set_guest_write_limits(Some(GuestWriteLimits {
+            data_store_limit: DEFAULT_DATA_STORE_LIMIT,
+            // Two writes, refilled in an hour.
+            rate_limiter: TokenBucket::new(2, 0, 3_600_000),
+        }));
+
+        mmds.put_guest_data(serde_json::json!({"write": 1}))
+            .unwrap();
+        mmds.patch_guest_data(serde_json::json!({"write": 2}))
+            .unwrap();
+        assert_eq!(
+            mmds.patch_guest_data(serde_json::json!({"write": 3})),
+            Err(Error::GuestWriteRateExceeded)
+        );
+        assert_eq!(mmds.get_guest_data_str().unwrap(), r#"{"write":2}"#);
+    }
+
+    #[test]
+    fn test_format_imds() {
+        // Subtrees of arrays are listed by index.
+        let data = serde_json::json!(["value", 1, true, {"key": "value"}, ["nested"]]);
+        assert_eq!(Mmds::format_imds(&data).unwrap(), "value\n1\ntrue\n3/\n4/");
+
+        // Null values cannot be IMDS formatted.
+        assert_eq!(
+            Mmds::format_imds(&Value::Null),
+            Err(Error::UnsupportedValueType)
+        );
+        assert_eq!(
+            Mmds::format_imds(&serde_json::json!(["value", null])),
+            Err(Error::UnsupportedValueType)
+        );
+        assert_eq!(
+            Mmds::format_imds(&serde_json::json!({"key": null})).unwrap(),
+            "key"
+        );
+    }
+
+    #[test]
+    fn test_data_store_limit() {
+        let mut mmds = Mmds::default();
+        assert_eq!(mmds.data_store_limit(), DEFAULT_DATA_STORE_LIMIT);
+
+        // The serialized `{"key":"value"}` takes 15 bytes.
+        let data = serde_json::json!({"key": "value"});
+        mmds.set_data_store_limit(14);
+        assert_eq!(
+            mmds.put_data(data.clone()),
+            Err(Error::DataStoreLimitExceeded)
+        );
+        assert!(mmds.check_data_store_initialized().is_err());
+
+        mmds.set_data_store_limit(15);
+        mmds.put_data(data.clone()).unwrap();
+
+        // A patch growing the data store past its limit is rejected as a whole.
+        assert_eq!(
+            mmds.patch_data(serde_json::json!({"key": "value2"})),
+            Err(Error::DataStoreLimitExceeded)
+        );
+        assert_eq!(mmds.get_data_str(), data.to_string());
+        assert_eq!(mmds.data_version(), 1);
+
+        // Shrinking patches are accepted.
+        mmds.patch_data(serde_json::json!({"key": "v"})).unwrap();
+        assert_eq!(mmds.get_data_str(), r#"{"key":"v"}"#);
+    }
+
+    #[test]
+    fn test_json_patch_data() {
+        let mut mmds = Mmds::default();
+        let patch = serde_json::json!([{"op": "add", "path": "/key", "value": "value"}]);
+        assert_eq!(
+            mmds.json_patch_data(patch.clone()),
+            Err(Error::NotInitialized)
+        );
+
+        mmds.put_data(serde_json::json!({})).unwrap();
+        mmds.patch_data_as(PatchFormat::JsonPatch, patch).unwrap();
+        assert_eq!(mmds.get_data_str(), r#"{"key":"value"}"#);
+        assert_eq!(mmds.data_version(), 2);
+
+        // A failing operation leaves the data store untouched.
+        let patch = serde_json::json!([
+            {"op": "remove", "path": "/key"},
+            {"op": "test", "path": "/key", "value": "value"}
+        ]);
+        assert!(matches!(
+            mmds.json_patch_data(patch),
+            Err(Error::InvalidPatch(_))
+        ));
+        assert_eq!(mmds.get_data_str(), r#"{"key":"value"}"#);
+        assert_eq!(mmds.data_version(), 2);
+
+        mmds.patch_data_as(
+            PatchFormat::MergePatch,
+            serde_json::json!({"key": null, "other": "value"}),
+        )
+        .unwrap();
+        assert_eq!(mmds.get_data_str(), r#"{"other":"value"}"#);
+    }
+
+    #[test]
+    fn test_merge_data() {
+        let mut mmds = Mmds::default();
+        mmds.merge_data(serde_json::json!({"a": {"b": "c"}}))
+            .unwrap();
+        mmds.merge_data(serde_json::json!({"a": {"d": "e"}}))
+            .unwrap();
+        assert_eq!(mmds.get_data_str(), r#"{"a":{"b":"c","d":"e"}}"#);
     }
 }
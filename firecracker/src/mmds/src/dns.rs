@@ -0,0 +1,614 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A DNS stub responder, which answers guest queries from a fixed table of records.
+//!
+//! Similarly to the MMDS network stack, the responder inspects the frames sent by the guest and
+//! consumes the ones heading to its IPv4 address, instead of letting them reach the TAP. Queries
+//! for names outside the table are refused, and nothing is ever forwarded to another server.
+
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroUsize;
+use std::result::Result;
+
+use dumbo::pdu::arp::{
+    test_speculative_tpa, Error as ArpFrameError, EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN,
+};
+use dumbo::pdu::dns::{
+    encode_name, DnsMessage, Error as DnsError, CLASS_IN, MAX_UDP_MESSAGE_LEN, OPCODE_QUERY,
+    RCODE_FORMERR, RCODE_NOERROR, RCODE_NOTIMP, RCODE_REFUSED, SERVER_PORT, TYPE_A, TYPE_AAAA,
+    TYPE_PTR,
+};
+use dumbo::pdu::ethernet::{
+    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4,
+};
+use dumbo::pdu::ipv4::{
+    test_speculative_dst_addr, Error as IPv4PacketError, IPv4Packet, PROTOCOL_UDP,
+};
+use dumbo::pdu::udp::{Error as UdpDatagramError, UdpDatagram};
+use logger::{IncMetric, METRICS};
+use utils::net::mac::MacAddr;
+
+/// The default time to live of the answers, in seconds.
+pub const DEFAULT_TTL: u32 = 60;
+/// The maximum number of records the responder can hold.
+pub const MAX_RECORDS: usize = 256;
+
+// The Ethernet MAC address of the DNS responder.
+const DEFAULT_MAC_ADDR: &str = "06:01:23:45:67:03";
+const DEFAULT_IPV4_ADDR: [u8; 4] = [169, 254, 169, 253];
+// Replies beyond this limit are dropped, and the guest resolver retries later.
+const MAX_PENDING_REPLIES: usize = 16;
+
+/// Errors associated with building the records of the responder.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The name of a record, or the target of a PTR record, is not a valid domain name.
+    InvalidName(String),
+    /// There are more records than the responder can hold.
+    TooManyRecords,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Error::InvalidName(name) => write!(f, "Invalid domain name: {}", name),
+            Error::TooManyRecords => write!(
+                f,
+                "The number of DNS records exceeds the maximum of {}.",
+                MAX_RECORDS
+            ),
+        }
+    }
+}
+
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum WriteFrameError {
+    Arp(ArpFrameError),
+    Ethernet(EthernetFrameError),
+    IPv4Packet(IPv4PacketError),
+    UdpDatagram(UdpDatagramError),
+}
+
+/// A resource record served by the responder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DnsRecord {
+    // The owner name, lowercase and without the trailing dot.
+    pub(crate) name: String,
+    pub(crate) record_type: u16,
+    // The record data, as written on the wire.
+    pub(crate) data: Vec<u8>,
+}
+
+impl DnsRecord {
+    // Lowercases `name` and strips the trailing dot, after checking it can be encoded.
+    fn normalize_name(name: &str) -> Result<String, Error> {
+        encode_name(name).map_err(|_| Error::InvalidName(name.to_string()))?;
+        Ok(name.trim_end_matches('.').to_ascii_lowercase())
+    }
+
+    /// Creates an A record mapping `name` to `addr`.
+    pub fn new_a(name: &str, addr: Ipv4Addr) -> Result<Self, Error> {
+        Ok(DnsRecord {
+            name: Self::normalize_name(name)?,
+            record_type: TYPE_A,
+            data: addr.octets().to_vec(),
+        })
+    }
+
+    /// Creates an AAAA record mapping `name` to `addr`.
+    pub fn new_aaaa(name: &str, addr: Ipv6Addr) -> Result<Self, Error> {
+        Ok(DnsRecord {
+            name: Self::normalize_name(name)?,
+            record_type: TYPE_AAAA,
+            data: addr.octets().to_vec(),
+        })
+    }
+
+    /// Creates a PTR record pointing `name` to the domain name `target`.
+    pub fn new_ptr(name: &str, target: &str) -> Result<Self, Error> {
+        Ok(DnsRecord {
+            name: Self::normalize_name(name)?,
+            record_type: TYPE_PTR,
+            data: encode_name(target).map_err(|_| Error::InvalidName(target.to_string()))?,
+        })
+    }
+}
+
+// A reply waiting to be written to the guest.
+struct PendingReply {
+    remote_mac_addr: MacAddr,
+    remote_addr: Ipv4Addr,
+    remote_port: u16,
+    message: Vec<u8>,
+}
+
+pub struct DnsResponder {
+    // The Ethernet MAC address of the responder.
+    mac_addr: MacAddr,
+    // The IPv4 address the responder listens on.
+    pub(crate) ipv4_addr: Ipv4Addr,
+    // The time to live of the answers, in seconds.
+    pub(crate) ttl: u32,
+    pub(crate) records: Vec<DnsRecord>,
+    // The hardware and protocol addresses of the sender of the last ARP request, if not yet
+    // answered.
+    pending_arp_reply: Option<(MacAddr, Ipv4Addr)>,
+    pending_replies: VecDeque<PendingReply>,
+}
+
+impl DnsResponder {
+    /// Creates a responder listening on `ipv4_addr`, which answers queries from `records`.
+    pub fn new(ipv4_addr: Ipv4Addr, ttl: u32, records: Vec<DnsRecord>) -> Result<Self, Error> {
+        if records.len() > MAX_RECORDS {
+            return Err(Error::TooManyRecords);
+        }
+
+        Ok(DnsResponder {
+            // The unwrap() is safe because the address is valid.
+            mac_addr: MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap(),
+            ipv4_addr,
+            ttl,
+            records,
+            pending_arp_reply: None,
+            pending_replies: VecDeque::new(),
+        })
+    }
+
+    /// Returns the address the responder listens on when none is configured.
+    pub fn default_ipv4_addr() -> Ipv4Addr {
+        Ipv4Addr::from(DEFAULT_IPV4_ADDR)
+    }
+
+    /// Returns the IPv4 address the responder listens on.
+    pub fn ipv4_addr(&self) -> Ipv4Addr {
+        self.ipv4_addr
+    }
+
+    /// Inspects the frame sent by the guest, and consumes it if it is heading to the responder.
+    /// Returns whether the frame was consumed.
+    pub fn detour_frame(&mut self, src: &[u8]) -> bool {
+        if !test_speculative_tpa(src, self.ipv4_addr)
+            && !test_speculative_dst_addr(src, self.ipv4_addr)
+        {
+            return false;
+        }
+
+        let eth = match EthernetFrame::from_bytes(src) {
+            Ok(eth) => eth,
+            Err(_) => return false,
+        };
+        match eth.ethertype() {
+            ETHERTYPE_ARP => {
+                if let Ok(arp) = EthIPv4ArpFrame::request_from_bytes(eth.payload()) {
+                    self.pending_arp_reply = Some((arp.sha(), arp.spa()));
+                    return true;
+                }
+            }
+            ETHERTYPE_IPV4 => {
+                // The checksums are not verified, in case the guest driver relies on checksum
+                // offloading.
+                if let Ok(ip) = IPv4Packet::from_bytes(eth.payload(), false) {
+                    // Anything else heading towards the responder is dropped.
+                    if ip.protocol() == PROTOCOL_UDP {
+                        if let Ok(udp) = UdpDatagram::from_bytes(ip.payload(), None) {
+                            if udp.destination_port() == SERVER_PORT {
+                                self.handle_query(
+                                    eth.src_mac(),
+                                    ip.source_address(),
+                                    udp.source_port(),
+                                    udp.payload(),
+                                );
+                            }
+                        }
+                    }
+                    return true;
+                }
+            }
+            _ => (),
+        }
+
+        false
+    }
+
+    // Answers a query from the guest, unless it is malformed or there are too many replies
+    // waiting to be written already.
+    fn handle_query(
+        &mut self,
+        remote_mac_addr: MacAddr,
+        remote_addr: Ipv4Addr,
+        remote_port: u16,
+        bytes: &[u8],
+    ) {
+        METRICS.mmds.dns_rx_count.inc();
+
+        let query = match DnsMessage::from_bytes(bytes) {
+            Ok(query) if !query.is_response() => query,
+            _ => {
+                METRICS.mmds.dns_fails.inc();
+                return;
+            }
+        };
+        if self.pending_replies.len() >= MAX_PENDING_REPLIES {
+            METRICS.mmds.dns_fails.inc();
+            return;
+        }
+
+        match self.write_reply_message(&query) {
+            Ok(message) => self.pending_replies.push_back(PendingReply {
+                remote_mac_addr,
+                remote_addr,
+                remote_port,
+                message,
+            }),
+            Err(_) => METRICS.mmds.dns_fails.inc(),
+        }
+    }
+
+    // Builds the reply to `query` from the records of the responder.
+    fn write_reply_message(&self, query: &DnsMessage<&[u8]>) -> Result<Vec<u8>, DnsError> {
+        let mut buf = vec![0u8; MAX_UDP_MESSAGE_LEN];
+
+        let (rcode, answers) = if query.opcode() != OPCODE_QUERY {
+            (RCODE_NOTIMP, Vec::new())
+        } else {
+            match query.question() {
+                Ok(question) => {
+                    let mut known_name = false;
+                    let mut answers = Vec::new();
+                    if question.qclass == CLASS_IN {
+                        for record in self.records.iter().filter(|r| r.name == question.name) {
+                            known_name = true;
+                            if record.record_type == question.qtype {
+                                answers.push((record.record_type, self.ttl, &record.data[..]));
+                            }
+                        }
+                    }
+                    // Known names without records of the queried type get an empty answer.
+                    if known_name {
+                        (RCODE_NOERROR, answers)
+                    } else {
+                        (RCODE_REFUSED, answers)
+                    }
+                }
+                Err(_) => (RCODE_FORMERR, Vec::new()),
+            }
+        };
+
+        if rcode == RCODE_REFUSED {
+            METRICS.mmds.dns_refused.inc();
+        }
+
+        let len = DnsMessage::write_response(&mut buf[..], query, rcode, rcode == RCODE_NOERROR)?
+            .with_answers(&answers)?
+            .len();
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    // Allows the DNS responder to write a frame to the specified buffer. Will return:
+    // - None, if the responder has no frame to send at this point. The buffer can be used for
+    // something else by the device model.
+    // - Some(len), if a frame of the given length has been written to the specified buffer.
+    pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
+        let result = if let Some((remote_mac_addr, remote_addr)) = self.pending_arp_reply.take() {
+            self.write_arp_reply(buf, remote_mac_addr, remote_addr)
+        } else {
+            let reply = self.pending_replies.pop_front()?;
+            self.write_reply(buf, &reply)
+        };
+
+        match result {
+            Ok(len) => {
+                METRICS.mmds.dns_tx_count.inc();
+                NonZeroUsize::new(len)
+            }
+            Err(_) => {
+                METRICS.mmds.dns_fails.inc();
+                None
+            }
+        }
+    }
+
+    fn write_arp_reply(
+        &self,
+        buf: &mut [u8],
+        remote_mac_addr: MacAddr,
+        remote_addr: Ipv4Addr,
+    ) -> Result<usize, WriteFrameError> {
+        let mut eth =
+            EthernetFrame::write_incomplete(buf, remote_mac_addr, self.mac_addr, ETHERTYPE_ARP)
+                .map_err(WriteFrameError::Ethernet)?;
+
+        let arp_len = EthIPv4ArpFrame::write_reply(
+            eth.inner_mut()
+                .payload_mut()
+                .split_at_mut(ETH_IPV4_FRAME_LEN)
+                .0,
+            self.mac_addr,
+            self.ipv4_addr,
+            remote_mac_addr,
+            remote_addr,
+        )
+        .map_err(WriteFrameError::Arp)?
+        .len();
+
+        Ok(eth.with_payload_len_unchecked(arp_len).len())
+    }
+
+    fn write_reply(&self, buf: &mut [u8], reply: &PendingReply) -> Result<usize, WriteFrameError> {
+        let mut eth = EthernetFrame::write_incomplete(
+            buf,
+            reply.remote_mac_addr,
+            self.mac_addr,
+            ETHERTYPE_IPV4,
+        )
+        .map_err(WriteFrameError::Ethernet)?;
+        let packet_len = {
+            let mut packet = IPv4Packet::write_header(
+                eth.inner_mut().payload_mut(),
+                PROTOCOL_UDP,
+                self.ipv4_addr,
+                reply.remote_addr,
+            )
+            .map_err(WriteFrameError::IPv4Packet)?;
+            let datagram_len = UdpDatagram::write_incomplete_datagram(
+                packet.inner_mut().payload_mut(),
+                &reply.message,
+            )
+            .map_err(WriteFrameError::UdpDatagram)?
+            .finalize(
+                SERVER_PORT,
+                reply.remote_port,
+                Some((self.ipv4_addr, reply.remote_addr)),
+            )
+            .len();
+            packet
+                .with_payload_len_unchecked(datagram_len as usize, true)
+                .len()
+        };
+
+        Ok(eth.with_payload_len_unchecked(packet_len).len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    const REMOTE_MAC_STR: &str = "11:11:11:22:22:22";
+    const REMOTE_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+    const REMOTE_PORT: u16 = 33333;
+
+    fn default_responder() -> DnsResponder {
+        DnsResponder::new(
+            DnsResponder::default_ipv4_addr(),
+            DEFAULT_TTL,
+            vec![
+                DnsRecord::new_a("metadata.internal", Ipv4Addr::new(169, 254, 169, 254)).unwrap(),
+                DnsRecord::new_a("api.internal.", Ipv4Addr::new(10, 0, 0, 1)).unwrap(),
+                DnsRecord::new_a("API.internal", Ipv4Addr::new(10, 0, 0, 3)).unwrap(),
+                DnsRecord::new_aaaa("api.internal", Ipv6Addr::from_str("fd00::1").unwrap())
+                    .unwrap(),
+                DnsRecord::new_ptr("1.0.0.10.in-addr.arpa", "api.internal").unwrap(),
+            ],
+        )
+        .unwrap()
+    }
+
+    // Writes a frame carrying a query from the guest to `buf`, returning its length.
+    fn write_query_frame(buf: &mut [u8], dst_addr: Ipv4Addr, name: &str, qtype: u16) -> usize {
+        let mut message = vec![0u8; 12];
+        message[1] = 0x42;
+        // Recursion desired.
+        message[2] = 1;
+        message[5] = 1;
+        message.extend_from_slice(&encode_name(name).unwrap());
+        message.extend_from_slice(&qtype.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+        let mut eth = EthernetFrame::write_incomplete(
+            buf,
+            MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap(),
+            MacAddr::parse_str(REMOTE_MAC_STR).unwrap(),
+            ETHERTYPE_IPV4,
+        )
+        .unwrap();
+        let packet_len = {
+            let mut packet = IPv4Packet::write_header(
+                eth.inner_mut().payload_mut(),
+                PROTOCOL_UDP,
+                REMOTE_ADDR,
+                dst_addr,
+            )
+            .unwrap();
+            let datagram_len =
+                UdpDatagram::write_incomplete_datagram(packet.inner_mut().payload_mut(), &message)
+                    .unwrap()
+                    .finalize(REMOTE_PORT, SERVER_PORT, None)
+                    .len();
+            packet
+                .with_payload_len_unchecked(datagram_len as usize, true)
+                .len()
+        };
+        eth.with_payload_len_unchecked(packet_len).len()
+    }
+
+    // Sends a query to the responder, and returns the bytes of the DNS reply.
+    fn query(responder: &mut DnsResponder, name: &str, qtype: u16) -> Vec<u8> {
+        let mut buf = [0u8; 1000];
+        let len = write_query_frame(&mut buf, responder.ipv4_addr, name, qtype);
+        assert!(responder.detour_frame(&buf[..len]));
+
+        let len = responder.write_next_frame(&mut buf).unwrap().get();
+        let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(eth.dst_mac(), MacAddr::parse_str(REMOTE_MAC_STR).unwrap());
+        assert_eq!(eth.src_mac(), responder.mac_addr);
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        assert_eq!(ip.source_address(), responder.ipv4_addr);
+        assert_eq!(ip.destination_address(), REMOTE_ADDR);
+        let udp = UdpDatagram::from_bytes(
+            ip.payload(),
+            Some((ip.source_address(), ip.destination_address())),
+        )
+        .unwrap();
+        assert_eq!(udp.source_port(), SERVER_PORT);
+        assert_eq!(udp.destination_port(), REMOTE_PORT);
+        udp.payload().to_vec()
+    }
+
+    #[test]
+    fn test_records() {
+        let record = DnsRecord::new_a("Metadata.Internal.", Ipv4Addr::new(1, 2, 3, 4)).unwrap();
+        assert_eq!(record.name, "metadata.internal");
+        assert_eq!(record.record_type, TYPE_A);
+        assert_eq!(record.data, vec![1, 2, 3, 4]);
+
+        let record = DnsRecord::new_ptr("4.3.2.1.in-addr.arpa", "host.").unwrap();
+        assert_eq!(record.data, vec![4, b'h', b'o', b's', b't', 0]);
+
+        assert_eq!(
+            DnsRecord::new_a("a..b", Ipv4Addr::LOCALHOST).unwrap_err(),
+            Error::InvalidName("a..b".to_string())
+        );
+        assert_eq!(
+            DnsRecord::new_ptr("host", "a..b").unwrap_err(),
+            Error::InvalidName("a..b".to_string())
+        );
+        assert_eq!(
+            DnsResponder::new(
+                DnsResponder::default_ipv4_addr(),
+                DEFAULT_TTL,
+                vec![record; MAX_RECORDS + 1]
+            )
+            .err(),
+            Some(Error::TooManyRecords)
+        );
+        assert_eq!(
+            format!("{}", Error::TooManyRecords),
+            "The number of DNS records exceeds the maximum of 256."
+        );
+    }
+
+    #[test]
+    fn test_answers() {
+        let mut responder = default_responder();
+
+        let reply = query(&mut responder, "API.internal", TYPE_A);
+        let message = DnsMessage::from_bytes(&reply[..]).unwrap();
+        assert_eq!(message.id(), 0x42);
+        assert!(message.is_response());
+        assert!(message.is_authoritative());
+        assert!(message.recursion_desired());
+        assert_eq!(message.rcode(), RCODE_NOERROR);
+        assert_eq!(message.answer_count(), 2);
+        assert_eq!(message.question().unwrap().name, "api.internal");
+        assert_eq!(&reply[reply.len() - 4..], &[10, 0, 0, 3]);
+
+        let reply = query(&mut responder, "api.internal", TYPE_AAAA);
+        let message = DnsMessage::from_bytes(&reply[..]).unwrap();
+        assert_eq!(message.answer_count(), 1);
+        assert_eq!(
+            &reply[reply.len() - 16..],
+            &Ipv6Addr::from_str("fd00::1").unwrap().octets()
+        );
+
+        let reply = query(&mut responder, "1.0.0.10.in-addr.arpa", TYPE_PTR);
+        let message = DnsMessage::from_bytes(&reply[..]).unwrap();
+        assert_eq!(message.answer_count(), 1);
+        assert_eq!(
+            &reply[reply.len() - 14..],
+            &encode_name("api.internal").unwrap()[..]
+        );
+
+        // Known names without records of the queried type.
+        let reply = query(&mut responder, "metadata.internal", TYPE_AAAA);
+        let message = DnsMessage::from_bytes(&reply[..]).unwrap();
+        assert_eq!(message.rcode(), RCODE_NOERROR);
+        assert_eq!(message.answer_count(), 0);
+
+        // Unknown names are refused.
+        let reply = query(&mut responder, "example.com", TYPE_A);
+        let message = DnsMessage::from_bytes(&reply[..]).unwrap();
+        assert_eq!(message.rcode(), RCODE_REFUSED);
+        assert!(!message.is_authoritative());
+        assert_eq!(message.answer_count(), 0);
+
+        // Only standard queries are supported.
+        let mut buf = [0u8; 1000];
+        let len = write_query_frame(&mut buf, responder.ipv4_addr, "api.internal", TYPE_A);
+        // Sets the opcode to STATUS, in the DNS header which follows the UDP header.
+        buf[len - 30 + 2] |= 2 << 3;
+        assert!(responder.detour_frame(&buf[..len]));
+        let len = responder.write_next_frame(&mut buf).unwrap().get();
+        let reply = &buf[len - 30..len];
+        assert_eq!(DnsMessage::from_bytes(reply).unwrap().rcode(), RCODE_NOTIMP);
+
+        assert!(responder.write_next_frame(&mut buf).is_none());
+    }
+
+    #[test]
+    fn test_detour() {
+        let mut responder = default_responder();
+        let mut buf = [0u8; 1000];
+
+        // Traffic heading elsewhere is left alone.
+        let len = write_query_frame(&mut buf, Ipv4Addr::new(8, 8, 8, 8), "api.internal", TYPE_A);
+        assert!(!responder.detour_frame(&buf[..len]));
+        assert!(responder.write_next_frame(&mut buf).is_none());
+
+        // Responses and datagrams heading to other ports are consumed, but not answered.
+        let len = write_query_frame(&mut buf, responder.ipv4_addr, "api.internal", TYPE_A);
+        // The DNS header follows the UDP header, at the end of the frame.
+        let dns_offset = len - 30;
+        buf[dns_offset + 2] |= 0x80;
+        assert!(responder.detour_frame(&buf[..len]));
+        buf[dns_offset - 8 + 3] = 54;
+        assert!(responder.detour_frame(&buf[..len]));
+        assert!(responder.write_next_frame(&mut buf).is_none());
+
+        // Too many pending replies.
+        for _ in 0..=MAX_PENDING_REPLIES {
+            let len = write_query_frame(&mut buf, responder.ipv4_addr, "api.internal", TYPE_A);
+            assert!(responder.detour_frame(&buf[..len]));
+        }
+        for _ in 0..MAX_PENDING_REPLIES {
+            assert!(responder.write_next_frame(&mut buf).is_some());
+        }
+        assert!(responder.write_next_frame(&mut buf).is_none());
+
+        // ARP requests for the responder address.
+        let len = {
+            let mut eth = EthernetFrame::write_incomplete(
+                buf.as_mut(),
+                MacAddr::from_bytes_unchecked(&[0xff; 6]),
+                MacAddr::parse_str(REMOTE_MAC_STR).unwrap(),
+                ETHERTYPE_ARP,
+            )
+            .unwrap();
+            let arp_len = EthIPv4ArpFrame::write_request(
+                eth.inner_mut().payload_mut(),
+                MacAddr::parse_str(REMOTE_MAC_STR).unwrap(),
+                REMOTE_ADDR,
+                MacAddr::from_bytes_unchecked(&[0; 6]),
+                responder.ipv4_addr,
+            )
+            .unwrap()
+            .len();
+            eth.with_payload_len_unchecked(arp_len).len()
+        };
+        assert!(responder.detour_frame(&buf[..len]));
+        let len = responder.write_next_frame(&mut buf).unwrap().get();
+        let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(eth.ethertype(), ETHERTYPE_ARP);
+        let arp = EthIPv4ArpFrame::from_bytes_unchecked(eth.payload());
+        assert_eq!(arp.operation(), 2);
+        assert_eq!(arp.sha(), responder.mac_addr);
+        assert_eq!(arp.spa(), responder.ipv4_addr);
+        assert_eq!(arp.tpa(), REMOTE_ADDR);
+    }
+}
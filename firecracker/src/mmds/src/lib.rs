@@ -2,27 +2,69 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod data_store;
+pub mod dns;
 pub mod ns;
 pub mod persist;
+pub mod token;
 
 use serde_json::{Map, Value};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use crate::data_store::{Error as MmdsError, Mmds, OutputFormat};
+use crate::token::{TokenAuthority, X_METADATA_TOKEN_HEADER, X_METADATA_TOKEN_TTL_SECONDS_HEADER};
+use dumbo::tcp::Reply;
 use lazy_static::lazy_static;
+use logger::{IncMetric, METRICS};
 use micro_http::{Body, MediaType, Method, Request, Response, StatusCode, Version};
+use utils::time::NANOS_PER_SECOND;
 
 lazy_static! {
     // A static reference to a global Mmds instance. We currently use this for ease of access during
     // prototyping. We'll consider something like passing Arc<Mutex<Mmds>> references to the
     // appropriate threads in the future.
     pub static ref MMDS: Arc<Mutex<Mmds>> = Arc::new(Mutex::new(Mmds::default()));
+
+    // The named Mmds instances, each serving its own metadata to the network interfaces it is
+    // attached to. They are created on demand, when first configured.
+    static ref MMDS_INSTANCES: Mutex<HashMap<String, Arc<Mutex<Mmds>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Returns the Mmds instance named `mmds_id`, creating it if it does not exist yet, or the
+/// default instance when no name is given. New instances inherit the data store size limit of
+/// the default instance.
+pub fn mmds_instance(mmds_id: Option<&str>) -> Arc<Mutex<Mmds>> {
+    match mmds_id {
+        Some(mmds_id) => MMDS_INSTANCES
+            .lock()
+            .expect("Poisoned lock")
+            .entry(mmds_id.to_string())
+            .or_insert_with(|| {
+                let mut mmds = Mmds::default();
+                mmds.set_data_store_limit(MMDS.lock().expect("Poisoned lock").data_store_limit());
+                Arc::new(Mutex::new(mmds))
+            })
+            .clone(),
+        None => MMDS.clone(),
+    }
+}
+
+/// Returns the Mmds instance named `mmds_id`, if it was configured.
+pub fn find_mmds_instance(mmds_id: &str) -> Option<Arc<Mutex<Mmds>>> {
+    MMDS_INSTANCES
+        .lock()
+        .expect("Poisoned lock")
+        .get(mmds_id)
+        .cloned()
 }
 
 impl Into<OutputFormat> for MediaType {
     fn into(self) -> OutputFormat {
         match self {
-            MediaType::ApplicationJson => OutputFormat::Json,
+            MediaType::ApplicationJson
+            | MediaType::ApplicationJsonPatch
+            | MediaType::ApplicationMergePatch => OutputFormat::Json,
             MediaType::PlainText => OutputFormat::Imds,
         }
     }
@@ -63,6 +105,162 @@ pub fn json_patch(target: &mut Value, patch: &Value) {
     }
 }
 
+/// Applies the operations of a JSON Patch [RFC 6902](https://tools.ietf.org/html/rfc6902)
+/// document (given as `serde_json::Value`) to the provided JSON document, in order.
+///
+/// The operations applied before a failing one are not undone, so callers wanting the patch to
+/// be atomic should apply it to a copy of the document.
+pub fn apply_json_patch(target: &mut Value, patch: &Value) -> Result<(), String> {
+    let operations = patch
+        .as_array()
+        .ok_or_else(|| "A JSON Patch document must be an array of operations.".to_string())?;
+    for operation in operations {
+        let path = patch_operation_pointer(operation, "path")?;
+        match patch_operation_pointer(operation, "op")? {
+            "add" => {
+                let value = patch_operation_member(operation, "value")?.clone();
+                json_pointer_add(target, path, value)?;
+            }
+            "remove" => {
+                json_pointer_remove(target, path)?;
+            }
+            "replace" => {
+                let value = patch_operation_member(operation, "value")?.clone();
+                *target
+                    .pointer_mut(path)
+                    .ok_or_else(|| format!("The JSON Patch path {} does not exist.", path))? =
+                    value;
+            }
+            "move" => {
+                let from = patch_operation_pointer(operation, "from")?;
+                if path.starts_with(from) && path[from.len()..].starts_with('/') {
+                    return Err(format!("Cannot move {} into one of its children.", from));
+                }
+                let value = json_pointer_remove(target, from)?;
+                json_pointer_add(target, path, value)?;
+            }
+            "copy" => {
+                let from = patch_operation_pointer(operation, "from")?;
+                let value = target
+                    .pointer(from)
+                    .cloned()
+                    .ok_or_else(|| format!("The JSON Patch path {} does not exist.", from))?;
+                json_pointer_add(target, path, value)?;
+            }
+            "test" => {
+                let value = patch_operation_member(operation, "value")?;
+                if target.pointer(path) != Some(value) {
+                    return Err(format!("The JSON Patch test of {} failed.", path));
+                }
+            }
+            op => return Err(format!("Unknown JSON Patch operation `{}`.", op)),
+        }
+    }
+    Ok(())
+}
+
+// Returns the member `name` of a JSON Patch operation.
+fn patch_operation_member<'a>(operation: &'a Value, name: &str) -> Result<&'a Value, String> {
+    operation.get(name).ok_or_else(|| {
+        format!(
+            "The JSON Patch operation {} has no `{}` member.",
+            operation, name
+        )
+    })
+}
+
+// Returns the member `name` of a JSON Patch operation, which has to be a string.
+fn patch_operation_pointer<'a>(operation: &'a Value, name: &str) -> Result<&'a str, String> {
+    patch_operation_member(operation, name)?
+        .as_str()
+        .ok_or_else(|| {
+            format!(
+                "The `{}` member of the JSON Patch operation {} is not a string.",
+                name, operation
+            )
+        })
+}
+
+// Splits a JSON pointer into the pointer to the parent value and the unescaped last reference
+// token, or returns None for the pointer to the whole document.
+fn split_json_pointer(path: &str) -> Result<Option<(&str, String)>, String> {
+    if path.is_empty() {
+        return Ok(None);
+    }
+    if !path.starts_with('/') {
+        return Err(format!("Invalid JSON pointer `{}`.", path));
+    }
+    // Safe to unwrap since the pointer starts with '/'.
+    let separator = path.rfind('/').unwrap();
+    let token = path[separator + 1..].replace("~1", "/").replace("~0", "~");
+    Ok(Some((&path[..separator], token)))
+}
+
+// Parses an array index reference token, which cannot have leading zeros.
+fn parse_array_index(token: &str) -> Option<usize> {
+    if token.is_empty()
+        || !token.bytes().all(|byte| byte.is_ascii_digit())
+        || (token.len() > 1 && token.starts_with('0'))
+    {
+        return None;
+    }
+    token.parse().ok()
+}
+
+// Adds `value` at `path`, replacing the member of an object or shifting the elements of an
+// array, as per the `add` operation of RFC 6902.
+fn json_pointer_add(target: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    let (parent, token) = match split_json_pointer(path)? {
+        Some(split) => split,
+        None => {
+            *target = value;
+            return Ok(());
+        }
+    };
+    match target.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(token, value);
+            Ok(())
+        }
+        Some(Value::Array(array)) => {
+            let index = if token == "-" {
+                Some(array.len())
+            } else {
+                parse_array_index(&token)
+            };
+            match index {
+                Some(index) if index <= array.len() => {
+                    array.insert(index, value);
+                    Ok(())
+                }
+                _ => Err(format!(
+                    "Invalid array index in the JSON Patch path {}.",
+                    path
+                )),
+            }
+        }
+        _ => Err(format!(
+            "The parent of the JSON Patch path {} does not exist.",
+            path
+        )),
+    }
+}
+
+// Removes and returns the value at `path`, as per the `remove` operation of RFC 6902.
+fn json_pointer_remove(target: &mut Value, path: &str) -> Result<Value, String> {
+    let missing = || format!("The JSON Patch path {} does not exist.", path);
+    let (parent, token) = split_json_pointer(path)?
+        .ok_or_else(|| "Cannot remove the whole JSON document.".to_string())?;
+    match target.pointer_mut(parent) {
+        Some(Value::Object(map)) => map.remove(&token).ok_or_else(missing),
+        Some(Value::Array(array)) => match parse_array_index(&token) {
+            Some(index) if index < array.len() => Ok(array.remove(index)),
+            _ => Err(missing()),
+        },
+        _ => Err(missing()),
+    }
+}
+
 // Make the URI a correct JSON pointer value.
 fn sanitize_uri(mut uri: String) -> String {
     let mut len = u32::MAX as usize;
@@ -76,7 +274,170 @@ fn sanitize_uri(mut uri: String) -> String {
     uri
 }
 
-fn convert_to_response(request: Request) -> Response {
+/// Path of the resource generating session tokens.
+const TOKEN_PATH: &str = "/latest/api/token";
+/// Path of the data store the guest can write, when allowed to.
+const GUEST_DATA_PATH: &str = "/latest/guest-data";
+/// Header set by HTTP proxies, whose presence in a token request means the guest may be
+/// forwarding a request it did not mean to issue.
+const X_FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+/// Header of the responses to watch requests, carrying the version of the data store.
+pub const X_METADATA_VERSION_HEADER: &str = "X-metadata-version";
+/// Query parameter turning a GET request into a watch request, which waits for the data store
+/// to change when set to `true`.
+const WAIT_QUERY_PARAM: &str = "wait";
+/// Query parameter of watch requests, holding the data store version known by the guest.
+const VERSION_QUERY_PARAM: &str = "version";
+/// Query parameter of watch requests, holding how many seconds to wait at most.
+const TIMEOUT_QUERY_PARAM: &str = "timeout";
+/// How many seconds watch requests wait for the data store to change, unless told otherwise.
+pub const DEFAULT_WATCH_TIMEOUT_SECONDS: u64 = 30;
+/// Upper bound for the timeout of watch requests, in seconds.
+pub const MAX_WATCH_TIMEOUT_SECONDS: u64 = 300;
+
+// The parameters of a watch request.
+#[derive(Debug, PartialEq)]
+struct WatchQuery<'a> {
+    // The path of the request, without the query.
+    path: &'a str,
+    wait: bool,
+    version: Option<u64>,
+    timeout_seconds: u64,
+}
+
+// Parses the query of a GET request. Returns None if the request does not watch the data store,
+// in which case the query is considered part of the path, as it always was.
+fn parse_watch_query(uri: &str) -> Option<Result<WatchQuery, String>> {
+    let mut parts = uri.splitn(2, '?');
+    let path = parts.next()?;
+    let query = parts.next()?;
+    if !query
+        .split('&')
+        .any(|param| param.splitn(2, '=').next() == Some(WAIT_QUERY_PARAM))
+    {
+        return None;
+    }
+
+    let mut watch_query = WatchQuery {
+        path,
+        wait: false,
+        version: None,
+        timeout_seconds: DEFAULT_WATCH_TIMEOUT_SECONDS,
+    };
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let mut entry = param.splitn(2, '=');
+        // The unwrap is safe because splitn always yields at least one item.
+        let (name, value) = (entry.next().unwrap(), entry.next().unwrap_or(""));
+        let invalid_value = || format!("Invalid value for the `{}` parameter: {}.", name, value);
+        match name {
+            WAIT_QUERY_PARAM => match value.parse() {
+                Ok(wait) => watch_query.wait = wait,
+                Err(_) => return Some(Err(invalid_value())),
+            },
+            VERSION_QUERY_PARAM => match value.parse() {
+                Ok(version) => watch_query.version = Some(version),
+                Err(_) => return Some(Err(invalid_value())),
+            },
+            TIMEOUT_QUERY_PARAM => match value.parse() {
+                Ok(timeout_seconds) if timeout_seconds <= MAX_WATCH_TIMEOUT_SECONDS => {
+                    watch_query.timeout_seconds = timeout_seconds;
+                }
+                _ => return Some(Err(invalid_value())),
+            },
+            _ => return Some(Err(format!("Unsupported query parameter: {}.", name))),
+        }
+    }
+    Some(Ok(watch_query))
+}
+
+fn convert_to_response(mmds: &Mutex<Mmds>, request: Request) -> Response {
+    // The lock can be held by one thread only, so it is safe to unwrap.
+    // If another thread poisoned the lock, we abort the execution.
+    respond_to_request(&mut mmds.lock().expect("Poisoned lock"), &request)
+}
+
+/// Answers the requests of the guest, like `convert_to_response`, except for watch requests
+/// (GET requests with a `wait=true` query parameter), which are held back while the data store
+/// is at the `version` they know about.
+pub fn convert_to_reply(mmds: &Mutex<Mmds>, request: Request) -> Reply {
+    let watch_query = match request.method() {
+        Method::Get => parse_watch_query(request.uri().get_abs_path()),
+        _ => None,
+    };
+    let watch_query = match watch_query {
+        Some(Ok(watch_query)) => watch_query,
+        Some(Err(error_msg)) => {
+            return build_response(
+                request.http_version(),
+                StatusCode::BadRequest,
+                Body::new(error_msg),
+            )
+            .into()
+        }
+        None => return convert_to_response(mmds, request).into(),
+    };
+
+    let mmds = mmds.lock().expect("Poisoned lock");
+    let mut response = match check_token(mmds.token_authority(), &request) {
+        Ok(()) => respond_to_get_request(&mmds, &request, watch_query.path),
+        Err(response) => return response.into(),
+    };
+    let data_version = mmds.data_version();
+    response.add_custom_header(X_METADATA_VERSION_HEADER, &data_version.to_string());
+
+    // Requests for a resource the data store is not able to return never wait.
+    let can_wait = response.status() == StatusCode::OK || response.status() == StatusCode::NotFound;
+    if watch_query.wait && watch_query.version == Some(data_version) && can_wait {
+        Reply::Wait {
+            timeout_ns: watch_query.timeout_seconds * NANOS_PER_SECOND,
+            response,
+        }
+    } else {
+        Reply::Response(response)
+    }
+}
+
+// Returns the guest data store path `json_pointer` refers to, if any.
+fn guest_data_path(json_pointer: &str) -> Option<&str> {
+    if json_pointer == GUEST_DATA_PATH {
+        return Some("");
+    }
+    match json_pointer.get(GUEST_DATA_PATH.len()..) {
+        Some(path) if json_pointer.starts_with(GUEST_DATA_PATH) && path.starts_with('/') => {
+            Some(path)
+        }
+        _ => None,
+    }
+}
+
+// Checks the session token of a request, when the Mmds requires one.
+fn check_token(
+    token_authority: Option<&TokenAuthority>,
+    request: &Request,
+) -> Result<(), Response> {
+    let token_authority = match token_authority {
+        Some(token_authority) => token_authority,
+        None => return Ok(()),
+    };
+    match request.headers.custom_entry(X_METADATA_TOKEN_HEADER) {
+        Some(token) if token_authority.is_valid(token) => Ok(()),
+        Some(_) => Err(build_response(
+            request.http_version(),
+            StatusCode::Unauthorized,
+            Body::new("MMDS token not valid."),
+        )),
+        None => Err(build_response(
+            request.http_version(),
+            StatusCode::Unauthorized,
+            Body::new(format!(
+                "No MMDS token provided. Use `{}` header to specify the session token.",
+                X_METADATA_TOKEN_HEADER
+            )),
+        )),
+    }
+}
+
+fn respond_to_request(mmds: &mut Mmds, request: &Request) -> Response {
     let uri = request.uri().get_abs_path();
     if uri.is_empty() {
         return build_response(
@@ -86,28 +447,101 @@ fn convert_to_response(request: Request) -> Response {
         );
     }
 
-    if request.method() != Method::Get {
-        let mut response = build_response(
-            request.http_version(),
-            StatusCode::MethodNotAllowed,
-            Body::new("Not allowed HTTP method."),
-        );
-        response.allow_method(Method::Get);
-        return response;
+    let is_guest_write = mmds.guest_writes_allowed()
+        && (request.method() == Method::Put || request.method() == Method::Patch)
+        && sanitize_uri(uri.to_string()) == GUEST_DATA_PATH;
+    if is_guest_write {
+        return match check_token(mmds.token_authority(), request) {
+            Ok(()) => respond_to_guest_write(mmds, request),
+            Err(response) => response,
+        };
     }
 
+    match (request.method(), mmds.token_authority()) {
+        (Method::Get, token_authority) => match check_token(token_authority, request) {
+            Ok(()) => respond_to_get_request(mmds, request, uri),
+            Err(response) => response,
+        },
+        (Method::Put, Some(token_authority)) => respond_to_put_request(token_authority, request),
+        (_, token_authority) => {
+            let mut response = build_response(
+                request.http_version(),
+                StatusCode::MethodNotAllowed,
+                Body::new("Not allowed HTTP method."),
+            );
+            response.allow_method(Method::Get);
+            if token_authority.is_some() || mmds.guest_writes_allowed() {
+                response.allow_method(Method::Put);
+            }
+            if mmds.guest_writes_allowed() {
+                response.allow_method(Method::Patch);
+            }
+            response
+        }
+    }
+}
+
+fn respond_to_guest_write(mmds: &mut Mmds, request: &Request) -> Response {
+    let data = match request
+        .body
+        .as_ref()
+        .map(|body| serde_json::from_slice::<Value>(body.raw()))
+    {
+        Some(Ok(data)) => data,
+        Some(Err(e)) => {
+            return build_response(
+                request.http_version(),
+                StatusCode::BadRequest,
+                Body::new(format!("Invalid guest data: {}.", e)),
+            )
+        }
+        None => {
+            return build_response(
+                request.http_version(),
+                StatusCode::BadRequest,
+                Body::new("Missing guest data."),
+            )
+        }
+    };
+
+    let result = if request.method() == Method::Put {
+        mmds.put_guest_data(data)
+    } else {
+        mmds.patch_guest_data(data)
+    };
+    match result {
+        Ok(()) => {
+            METRICS.mmds.guest_writes.inc();
+            Response::new(request.http_version(), StatusCode::NoContent)
+        }
+        Err(e) => {
+            METRICS.mmds.guest_writes_rejected.inc();
+            let status_code = match e {
+                MmdsError::DataStoreLimitExceeded => StatusCode::PayloadTooLarge,
+                MmdsError::GuestWriteRateExceeded => StatusCode::TooManyRequests,
+                _ => StatusCode::BadRequest,
+            };
+            build_response(
+                request.http_version(),
+                status_code,
+                Body::new(e.to_string()),
+            )
+        }
+    }
+}
+
+fn respond_to_get_request(mmds: &Mmds, request: &Request, uri: &str) -> Response {
     // The data store expects a strict json path, so we need to
     // sanitize the URI.
     let json_pointer = sanitize_uri(uri.to_string());
 
-    // The lock can be held by one thread only, so it is safe to unwrap.
-    // If another thread poisoned the lock, we abort the execution.
-    let response = MMDS
-        .lock()
-        .expect("Poisoned lock")
-        .get_value(json_pointer, request.headers.accept().into());
-
-    match response {
+    let value = match guest_data_path(&json_pointer) {
+        Some(path) if mmds.guest_writes_allowed() => {
+            mmds.get_guest_value(path.to_string(), request.headers.accept().into())
+        }
+        _ => mmds.get_value(json_pointer, request.headers.accept().into()),
+    };
+    match value {
         Ok(response_body) => build_response(
             request.http_version(),
             StatusCode::OK,
@@ -127,14 +561,109 @@ fn convert_to_response(request: Request) -> Response {
                 StatusCode::NotImplemented,
                 Body::new(e.to_string()),
             ),
-            MmdsError::NotInitialized => unreachable!(),
+            MmdsError::DataStoreLimitExceeded
+            | MmdsError::GuestWriteRateExceeded
+            | MmdsError::GuestWritesNotAllowed
+            | MmdsError::InvalidPatch(_)
+            | MmdsError::NotInitialized => unreachable!(),
         },
     }
 }
 
+fn respond_to_put_request(token_authority: &TokenAuthority, request: &Request) -> Response {
+    let uri = request.uri().get_abs_path();
+    if sanitize_uri(uri.to_string()) != TOKEN_PATH {
+        return build_response(
+            request.http_version(),
+            StatusCode::NotFound,
+            Body::new(format!("Resource not found: {}.", uri)),
+        );
+    }
+
+    if request
+        .headers
+        .custom_entry(X_FORWARDED_FOR_HEADER)
+        .is_some()
+    {
+        return build_response(
+            request.http_version(),
+            StatusCode::BadRequest,
+            Body::new(format!(
+                "Invalid header. Reason: Unsupported header name. Key: {}",
+                X_FORWARDED_FOR_HEADER
+            )),
+        );
+    }
+
+    let ttl_seconds = match request
+        .headers
+        .custom_entry(X_METADATA_TOKEN_TTL_SECONDS_HEADER)
+    {
+        Some(value) => value,
+        None => {
+            return build_response(
+                request.http_version(),
+                StatusCode::BadRequest,
+                Body::new(format!(
+                    "Token time to live value not found. Use `{}` header to specify the \
+                     token's lifetime.",
+                    X_METADATA_TOKEN_TTL_SECONDS_HEADER
+                )),
+            )
+        }
+    };
+
+    let token = match ttl_seconds.parse::<u32>() {
+        Ok(ttl_seconds) => token_authority
+            .generate_token(ttl_seconds)
+            .map_err(|e| e.to_string()),
+        Err(_) => Err(format!(
+            "Invalid time to live value provided for token: {}.",
+            ttl_seconds
+        )),
+    };
+    match token {
+        Ok(token) => build_response(request.http_version(), StatusCode::OK, Body::new(token)),
+        Err(error_msg) => build_response(
+            request.http_version(),
+            StatusCode::BadRequest,
+            Body::new(error_msg),
+        ),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::data_store::{GuestWriteLimits, MmdsVersion};
+    use rate_limiter::TokenBucket;
+
+    #[test]
+    fn test_mmds_instance() {
+        assert!(Arc::ptr_eq(&mmds_instance(None), &MMDS));
+
+        assert!(find_mmds_instance("lib_tenant").is_none());
+        let tenant = mmds_instance(Some("lib_tenant"));
+        assert!(!Arc::ptr_eq(&tenant, &MMDS));
+        assert!(Arc::ptr_eq(&mmds_instance(Some("lib_tenant")), &tenant));
+        assert!(Arc::ptr_eq(
+            &find_mmds_instance("lib_tenant").unwrap(),
+            &tenant
+        ));
+
+        // Each instance holds its own metadata.
+        tenant
+            .lock()
+            .unwrap()
+            .put_data(serde_json::json!({"tenant": "data"}))
+            .unwrap();
+        let request = Request::try_from(b"GET /tenant HTTP/1.0\r\n\r\n").unwrap();
+        let response = convert_to_response(&tenant, request);
+        assert_eq!(response.status(), StatusCode::OK);
+        let request = Request::try_from(b"GET /tenant HTTP/1.0\r\n\r\n").unwrap();
+        let response = convert_to_response(&mmds_instance(Some("lib_other")), request);
+        assert_eq!(response.status(), StatusCode::NotFound);
+    }
 
     #[test]
     fn test_sanitize_uri() {
@@ -163,6 +692,7 @@ mod tests {
                 "second": "Doe"
             },
             "age": 43,
+            "nothing": null,
             "phones": {
                 "home": {
                     "RO": "+401234567",
@@ -181,16 +711,24 @@ mod tests {
         let request = Request::try_from(request_bytes).unwrap();
         let mut expected_response = Response::new(Version::Http10, StatusCode::NotFound);
         expected_response.set_body(Body::new("Resource not found: /invalid.".to_string()));
-        let actual_response = convert_to_response(request);
+        let actual_response = convert_to_response(&MMDS, request);
         assert_eq!(actual_response, expected_response);
 
-        // Test NotImplemented.
+        // Test IMDS formatted number.
         let request_bytes = b"GET /age HTTP/1.1\r\n\r\n";
         let request = Request::try_from(request_bytes).unwrap();
+        let mut expected_response = Response::new(Version::Http11, StatusCode::OK);
+        expected_response.set_body(Body::new("43".to_string()));
+        let actual_response = convert_to_response(&MMDS, request);
+        assert_eq!(actual_response, expected_response);
+
+        // Test NotImplemented.
+        let request_bytes = b"GET /nothing HTTP/1.1\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
         let mut expected_response = Response::new(Version::Http11, StatusCode::NotImplemented);
         let body = "Cannot retrieve value. The value has an unsupported type.".to_string();
         expected_response.set_body(Body::new(body));
-        let actual_response = convert_to_response(request);
+        let actual_response = convert_to_response(&MMDS, request);
         assert_eq!(actual_response, expected_response);
 
         // Test not allowed HTTP Method.
@@ -202,7 +740,7 @@ mod tests {
                 Response::new(Version::Http10, StatusCode::MethodNotAllowed);
             expected_response.set_body(Body::new("Not allowed HTTP method.".to_string()));
             expected_response.allow_method(Method::Get);
-            let actual_response = convert_to_response(request);
+            let actual_response = convert_to_response(&MMDS, request);
             assert_eq!(actual_response, expected_response);
         }
 
@@ -211,7 +749,7 @@ mod tests {
         let request = Request::try_from(request_bytes).unwrap();
         let mut expected_response = Response::new(Version::Http10, StatusCode::BadRequest);
         expected_response.set_body(Body::new("Invalid URI.".to_string()));
-        let actual_response = convert_to_response(request);
+        let actual_response = convert_to_response(&MMDS, request);
         assert_eq!(actual_response, expected_response);
 
         // Test Ok path.
@@ -225,6 +763,7 @@ mod tests {
                     "first": "John",
                     "second": "Doe"
                 },
+                "nothing": null,
                 "phones": {
                     "home": {
                         "RO": "+401234567",
@@ -236,10 +775,325 @@ mod tests {
         .to_string();
         body.retain(|c| !c.is_whitespace());
         expected_response.set_body(Body::new(body));
-        let actual_response = convert_to_response(request);
+        let actual_response = convert_to_response(&MMDS, request);
         assert_eq!(actual_response, expected_response);
     }
 
+    #[test]
+    fn test_respond_to_request_with_tokens() {
+        let mut mmds = Mmds::default();
+        mmds.put_data(serde_json::json!({"user-data": "1522850095"}))
+            .unwrap();
+        mmds.set_version(MmdsVersion::V2).unwrap();
+
+        // Generate a token.
+        let request_bytes = b"PUT http://169.254.169.254/latest/api/token HTTP/1.0\r\n\
+                              X-metadata-token-ttl-seconds: 60\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        let response = respond_to_request(&mut mmds, &request);
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = String::from_utf8(response.body().unwrap().body).unwrap();
+
+        // Get a value with the token, header names being case-insensitive.
+        let request_bytes = format!(
+            "GET http://169.254.169.254/user-data HTTP/1.0\r\n\
+             x-METADATA-token: {}\r\n\r\n",
+            token
+        );
+        let request = Request::try_from(request_bytes.as_bytes()).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::OK);
+        expected_response.set_body(Body::new("1522850095".to_string()));
+        assert_eq!(respond_to_request(&mut mmds, &request), expected_response);
+
+        // Test missing token.
+        let request_bytes = b"GET http://169.254.169.254/user-data HTTP/1.0\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::Unauthorized);
+        expected_response.set_body(Body::new(
+            "No MMDS token provided. Use `X-metadata-token` header to specify the session token."
+                .to_string(),
+        ));
+        assert_eq!(respond_to_request(&mut mmds, &request), expected_response);
+
+        // Test forged token.
+        let request_bytes = format!(
+            "GET http://169.254.169.254/user-data HTTP/1.0\r\n\
+             X-metadata-token: {}\r\n\r\n",
+            "0".repeat(token.len())
+        );
+        let request = Request::try_from(request_bytes.as_bytes()).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::Unauthorized);
+        expected_response.set_body(Body::new("MMDS token not valid.".to_string()));
+        assert_eq!(respond_to_request(&mut mmds, &request), expected_response);
+
+        // Test token request to another resource.
+        let request_bytes = b"PUT http://169.254.169.254/user-data HTTP/1.0\r\n\
+                              X-metadata-token-ttl-seconds: 60\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::NotFound);
+        expected_response.set_body(Body::new("Resource not found: /user-data.".to_string()));
+        assert_eq!(respond_to_request(&mut mmds, &request), expected_response);
+
+        // Test missing and invalid token lifetimes.
+        let request_bytes = b"PUT http://169.254.169.254/latest/api/token HTTP/1.0\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::BadRequest);
+        expected_response.set_body(Body::new(
+            "Token time to live value not found. Use `X-metadata-token-ttl-seconds` header to \
+             specify the token's lifetime."
+                .to_string(),
+        ));
+        assert_eq!(respond_to_request(&mut mmds, &request), expected_response);
+
+        for (ttl, error_msg) in [
+            ("0", token::Error::InvalidTtlValue(0).to_string()),
+            ("21601", token::Error::InvalidTtlValue(21601).to_string()),
+            (
+                "-1",
+                "Invalid time to live value provided for token: -1.".to_string(),
+            ),
+        ]
+        .iter()
+        {
+            let request_bytes = format!(
+                "PUT http://169.254.169.254/latest/api/token HTTP/1.0\r\n\
+                 X-metadata-token-ttl-seconds: {}\r\n\r\n",
+                ttl
+            );
+            let request = Request::try_from(request_bytes.as_bytes()).unwrap();
+            let mut expected_response = Response::new(Version::Http10, StatusCode::BadRequest);
+            expected_response.set_body(Body::new(error_msg.clone()));
+            assert_eq!(respond_to_request(&mut mmds, &request), expected_response);
+        }
+
+        // Test token request going through a proxy.
+        let request_bytes = b"PUT http://169.254.169.254/latest/api/token HTTP/1.0\r\n\
+                              X-metadata-token-ttl-seconds: 60\r\n\
+                              X-Forwarded-For: 203.0.113.195\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        let response = respond_to_request(&mut mmds, &request);
+        assert_eq!(response.status(), StatusCode::BadRequest);
+
+        // Test not allowed HTTP Method.
+        let request_bytes = b"PATCH http://169.254.169.254/ HTTP/1.0\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::MethodNotAllowed);
+        expected_response.set_body(Body::new("Not allowed HTTP method.".to_string()));
+        expected_response.allow_method(Method::Get);
+        expected_response.allow_method(Method::Put);
+        assert_eq!(respond_to_request(&mut mmds, &request), expected_response);
+    }
+
+    #[test]
+    fn test_respond_to_guest_write() {
+        let mut mmds = Mmds::default();
+        mmds.put_data(serde_json::json!({"user-data": "1522850095"}))
+            .unwrap();
+        let guest_write = |method: &str, body: &str| {
+            let request_bytes = format!(
+                "{} http://169.254.169.254/latest/guest-data HTTP/1.0\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                method,
+                body.len(),
+                body
+            );
+            Request::try_from(request_bytes.as_bytes()).unwrap()
+        };
+
+        // Guest writes are not allowed by default.
+        let response = respond_to_request(&mut mmds, &guest_write("PUT", r#"{"boot":"done"}"#));
+        assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+
+        mmds.set_guest_write_limits(Some(GuestWriteLimits {
+            data_store_limit: 32,
+            rate_limiter: None,
+        }));
+        let writes_before = METRICS.mmds.guest_writes.count();
+        let response = respond_to_request(&mut mmds, &guest_write("PUT", r#"{"boot":"done"}"#));
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let response = respond_to_request(&mut mmds, &guest_write("PATCH", r#"{"health":"ok"}"#));
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_eq!(METRICS.mmds.guest_writes.count(), writes_before + 2);
+        assert_eq!(
+            mmds.get_guest_data_str().unwrap(),
+            r#"{"boot":"done","health":"ok"}"#
+        );
+
+        // The guest reads back its own data.
+        let request = Request::try_from(
+            b"GET http://169.254.169.254/latest/guest-data/boot HTTP/1.0\r\n\r\n",
+        )
+        .unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::OK);
+        expected_response.set_body(Body::new("done".to_string()));
+        assert_eq!(respond_to_request(&mut mmds, &request), expected_response);
+
+        // Invalid and oversized writes are rejected.
+        let response = respond_to_request(&mut mmds, &guest_write("PUT", "invalid"));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        let response = respond_to_request(
+            &mut mmds,
+            &guest_write("PATCH", r#"{"key":"a much longer value"}"#),
+        );
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+
+        // Writes are rate limited.
+        mmds.set_guest_write_limits(Some(GuestWriteLimits {
+            data_store_limit: 32,
+            rate_limiter: TokenBucket::new(1, 0, 3_600_000),
+        }));
+        let response = respond_to_request(&mut mmds, &guest_write("PATCH", r#"{"health":"ko"}"#));
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let response = respond_to_request(&mut mmds, &guest_write("PATCH", r#"{"health":"ok"}"#));
+        assert_eq!(response.status(), StatusCode::TooManyRequests);
+
+        // The host data store cannot be written by the guest.
+        let request_bytes = b"PUT http://169.254.169.254/user-data HTTP/1.0\r\n\
+                              Content-Length: 2\r\n\r\n{}";
+        let request = Request::try_from(request_bytes).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::MethodNotAllowed);
+        expected_response.set_body(Body::new("Not allowed HTTP method.".to_string()));
+        expected_response.allow_method(Method::Get);
+        expected_response.allow_method(Method::Put);
+        expected_response.allow_method(Method::Patch);
+        assert_eq!(respond_to_request(&mut mmds, &request), expected_response);
+
+        // With session tokens, guest writes need a token as well.
+        mmds.set_version(MmdsVersion::V2).unwrap();
+        let response = respond_to_request(&mut mmds, &guest_write("PUT", r#"{"boot":"done"}"#));
+        assert_eq!(response.status(), StatusCode::Unauthorized);
+    }
+
+    #[test]
+    fn test_parse_watch_query() {
+        assert_eq!(parse_watch_query("/a"), None);
+        assert_eq!(parse_watch_query("/a?version=1"), None);
+        assert_eq!(
+            parse_watch_query("/a?wait=true&version=3&timeout=10"),
+            Some(Ok(WatchQuery {
+                path: "/a",
+                wait: true,
+                version: Some(3),
+                timeout_seconds: 10,
+            }))
+        );
+        assert_eq!(
+            parse_watch_query("/?wait=false&"),
+            Some(Ok(WatchQuery {
+                path: "/",
+                wait: false,
+                version: None,
+                timeout_seconds: DEFAULT_WATCH_TIMEOUT_SECONDS,
+            }))
+        );
+
+        assert_eq!(
+            parse_watch_query("/a?wait=yes"),
+            Some(Err(
+                "Invalid value for the `wait` parameter: yes.".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_watch_query("/a?wait=true&version=-1"),
+            Some(Err(
+                "Invalid value for the `version` parameter: -1.".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_watch_query("/a?wait=true&timeout=301"),
+            Some(Err(
+                "Invalid value for the `timeout` parameter: 301.".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_watch_query("/a?wait=true&since=1"),
+            Some(Err("Unsupported query parameter: since.".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_convert_to_reply() {
+        let mmds = Mutex::new(Mmds::default());
+        mmds.lock()
+            .unwrap()
+            .put_data(serde_json::json!({"config": "1"}))
+            .unwrap();
+        let get = |uri: &str| {
+            let request_bytes = format!("GET {} HTTP/1.1\r\n\r\n", uri);
+            convert_to_reply(&mmds, Request::try_from(request_bytes.as_bytes()).unwrap())
+        };
+
+        // Requests which do not watch the data store are answered as usual.
+        let mut expected_response = Response::new(Version::Http11, StatusCode::OK);
+        expected_response.set_body(Body::new("1"));
+        assert_eq!(get("/config"), Reply::Response(expected_response));
+        assert_eq!(
+            get("/config?version=1"),
+            Reply::Response(convert_to_response(
+                &mmds,
+                Request::try_from(b"GET /config?version=1 HTTP/1.1\r\n\r\n").unwrap()
+            ))
+        );
+
+        // Watch requests learn the version of the data store.
+        let mut expected_response = Response::new(Version::Http11, StatusCode::OK);
+        expected_response.set_body(Body::new("1"));
+        expected_response.add_custom_header(X_METADATA_VERSION_HEADER, "1");
+        assert_eq!(get("/config?wait=true"), Reply::Response(expected_response));
+
+        // They wait while the guest knows about the latest version.
+        let mut expected_response = Response::new(Version::Http11, StatusCode::OK);
+        expected_response.set_body(Body::new("1"));
+        expected_response.add_custom_header(X_METADATA_VERSION_HEADER, "1");
+        assert_eq!(
+            get("/config?wait=true&version=1&timeout=5"),
+            Reply::Wait {
+                timeout_ns: 5 * NANOS_PER_SECOND,
+                response: expected_response,
+            }
+        );
+        match get("/missing?wait=true&version=1") {
+            Reply::Wait {
+                timeout_ns,
+                response,
+            } => {
+                assert_eq!(timeout_ns, DEFAULT_WATCH_TIMEOUT_SECONDS * NANOS_PER_SECOND);
+                assert_eq!(response.status(), StatusCode::NotFound);
+            }
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+
+        // And are answered once the data store changes.
+        mmds.lock()
+            .unwrap()
+            .patch_data(serde_json::json!({"config": "2"}))
+            .unwrap();
+        let mut expected_response = Response::new(Version::Http11, StatusCode::OK);
+        expected_response.set_body(Body::new("2"));
+        expected_response.add_custom_header(X_METADATA_VERSION_HEADER, "2");
+        assert_eq!(
+            get("/config?wait=true&version=1"),
+            Reply::Response(expected_response)
+        );
+
+        // Invalid queries are rejected.
+        let mut expected_response = Response::new(Version::Http11, StatusCode::BadRequest);
+        expected_response.set_body(Body::new("Unsupported query parameter: since."));
+        assert_eq!(
+            get("/config?wait=true&since=1"),
+            Reply::Response(expected_response)
+        );
+
+        // Watch requests need a session token, when the MMDS requires one.
+        mmds.lock().unwrap().set_version(MmdsVersion::V2).unwrap();
+        match get("/config?wait=true&version=2") {
+            Reply::Response(response) => {
+                assert_eq!(response.status(), StatusCode::Unauthorized)
+            }
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+    }
+
     #[test]
     fn test_json_patch() {
         let mut data = serde_json::json!({
@@ -293,4 +1147,81 @@ mod tests {
             patch["phones"]["mobile"]["UK"]
         );
     }
+
+    #[test]
+    fn test_apply_json_patch() {
+        let mut data = serde_json::json!({
+            "name": {
+                "first": "John",
+                "second": "Doe"
+            },
+            "tags": ["a", "c"],
+            "a/b": 1,
+            "m~n": 2
+        });
+
+        let patch = serde_json::json!([
+            { "op": "test", "path": "/name/first", "value": "John" },
+            { "op": "add", "path": "/tags/1", "value": "b" },
+            { "op": "add", "path": "/tags/-", "value": "d" },
+            { "op": "replace", "path": "/name/first", "value": "Jane" },
+            { "op": "remove", "path": "/name/second" },
+            { "op": "copy", "from": "/tags/0", "path": "/first_tag" },
+            { "op": "move", "from": "/a~1b", "path": "/m~0n" }
+        ]);
+        apply_json_patch(&mut data, &patch).unwrap();
+        assert_eq!(
+            data,
+            serde_json::json!({
+                "name": { "first": "Jane" },
+                "tags": ["a", "b", "c", "d"],
+                "first_tag": "a",
+                "m~n": 1
+            })
+        );
+
+        // Adding at the root replaces the whole document.
+        apply_json_patch(
+            &mut data,
+            &serde_json::json!([{ "op": "add", "path": "", "value": { "k": "v" } }]),
+        )
+        .unwrap();
+        assert_eq!(data, serde_json::json!({ "k": "v" }));
+
+        // Invalid patches.
+        let invalid_patches = [
+            serde_json::json!({ "op": "add", "path": "/k", "value": "v" }),
+            serde_json::json!([{ "path": "/k" }]),
+            serde_json::json!([{ "op": "add", "path": "/k" }]),
+            serde_json::json!([{ "op": "add", "path": "k", "value": "v" }]),
+            serde_json::json!([{ "op": "add", "path": "/missing/k", "value": "v" }]),
+            serde_json::json!([{ "op": "remove", "path": "/missing" }]),
+            serde_json::json!([{ "op": "remove", "path": "" }]),
+            serde_json::json!([{ "op": "replace", "path": "/missing", "value": "v" }]),
+            serde_json::json!([{ "op": "test", "path": "/k", "value": "w" }]),
+            serde_json::json!([{ "op": "move", "from": "", "path": "/k" }]),
+            serde_json::json!([{ "op": "copy", "from": "/missing", "path": "/k" }]),
+            serde_json::json!([{ "op": "merge", "path": "/k", "value": "v" }]),
+        ];
+        for patch in invalid_patches.iter() {
+            assert!(apply_json_patch(&mut data, patch).is_err());
+        }
+        assert_eq!(data, serde_json::json!({ "k": "v" }));
+
+        // Array indices.
+        let mut data = serde_json::json!({ "list": [0, 1] });
+        for index in ["01", "+1", "3", "-1"].iter() {
+            let patch = serde_json::json!([{
+                "op": "add",
+                "path": format!("/list/{}", index),
+                "value": 2
+            }]);
+            assert!(apply_json_patch(&mut data, &patch).is_err());
+        }
+        let patch = serde_json::json!([{ "op": "remove", "path": "/list/2" }]);
+        assert!(apply_json_patch(&mut data, &patch).is_err());
+        let patch = serde_json::json!([{ "op": "remove", "path": "/list/0" }]);
+        apply_json_patch(&mut data, &patch).unwrap();
+        assert_eq!(data, serde_json::json!({ "list": [1] }));
+    }
 }
@@ -5,32 +5,45 @@
 #![allow(missing_docs)]
 
 use std::convert::From;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
 use std::result::Result;
+use std::sync::{Arc, Mutex};
 
 use dumbo::pdu::arp::{
     test_speculative_tpa, Error as ArpFrameError, EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN,
 };
 use dumbo::pdu::ethernet::{
-    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4,
+    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6,
 };
+use dumbo::pdu::icmpv6::{Error as Icmpv6Error, NeighborMessage, FLAG_OVERRIDE, FLAG_SOLICITED};
 use dumbo::pdu::ipv4::{
     test_speculative_dst_addr, Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP,
 };
+use dumbo::pdu::ipv6::{
+    self, solicited_node_multicast_addr, Error as IPv6PacketError, IPv6Packet, DEFAULT_HOP_LIMIT,
+    PROTOCOL_ICMPV6,
+};
 use dumbo::pdu::tcp::Error as TcpSegmentError;
 use dumbo::pdu::Incomplete;
-use dumbo::tcp::handler::{self, RecvEvent, TcpIPv4Handler, WriteEvent};
+use dumbo::tcp::handler::{self, RecvError, RecvEvent, TcpIPHandler, WriteEvent};
 use dumbo::tcp::NextSegmentStatus;
 use logger::{IncMetric, METRICS};
 use utils::net::mac::MacAddr;
 use utils::time::timestamp_cycles;
 
+use crate::data_store::Mmds;
+
+pub use dumbo::tcp::{EndpointLimits, DEFAULT_RECEIVE_BUF_SIZE, DEFAULT_SEND_BUF_SIZE};
+
 const DEFAULT_MAC_ADDR: &str = "06:01:23:45:67:01";
 const DEFAULT_IPV4_ADDR: [u8; 4] = [169, 254, 169, 254];
 const DEFAULT_TCP_PORT: u16 = 80;
-const DEFAULT_MAX_CONNECTIONS: usize = 30;
+pub const DEFAULT_MAX_CONNECTIONS: usize = 30;
 const DEFAULT_MAX_PENDING_RESETS: usize = 100;
+/// How often the requests which wait for the data store to change should be polled, while
+/// there are any.
+pub const WAITING_REQUESTS_POLL_INTERVAL_MS: u64 = 100;
 
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum WriteArpFrameError {
@@ -39,22 +52,65 @@ enum WriteArpFrameError {
     Ethernet(EthernetFrameError),
 }
 
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum WriteNeighborAdvertisementError {
+    NoPendingNeighborAdvertisement,
+    Ethernet(EthernetFrameError),
+    Icmpv6(Icmpv6Error),
+    IPv6Packet(IPv6PacketError),
+}
+
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum WritePacketError {
+    AddressFamily,
     IPv4Packet(IPv4PacketError),
+    IPv6Packet(IPv6PacketError),
     Ethernet(EthernetFrameError),
+    NoIPv6Handler,
     TcpSegment(TcpSegmentError),
 }
 
 impl From<handler::WriteNextError> for WritePacketError {
     fn from(error: handler::WriteNextError) -> Self {
         match error {
+            handler::WriteNextError::AddressFamily => WritePacketError::AddressFamily,
             handler::WriteNextError::IPv4Packet(inner) => WritePacketError::IPv4Packet(inner),
+            handler::WriteNextError::IPv6Packet(inner) => WritePacketError::IPv6Packet(inner),
             handler::WriteNextError::TcpSegment(inner) => WritePacketError::TcpSegment(inner),
         }
     }
 }
 
+// Updates the metrics according to the outcome of a TCP handler receiving a packet.
+fn record_recv_result(result: Result<RecvEvent, RecvError>) {
+    match result {
+        Ok(event) => {
+            METRICS.mmds.rx_count.inc();
+            match event {
+                RecvEvent::NewConnectionSuccessful => METRICS.mmds.connections_created.inc(),
+                RecvEvent::NewConnectionReplacing => {
+                    METRICS.mmds.connections_created.inc();
+                    METRICS.mmds.connections_destroyed.inc();
+                }
+                RecvEvent::EndpointDone => {
+                    METRICS.mmds.connections_destroyed.inc();
+                }
+                _ => (),
+            }
+        }
+        Err(_) => METRICS.mmds.rx_accepted_err.inc(),
+    }
+}
+
+// Tells whether the handler has a segment to send right now.
+fn should_write(tcp_handler: &TcpIPHandler) -> bool {
+    match tcp_handler.next_segment_status() {
+        NextSegmentStatus::Available => true,
+        NextSegmentStatus::Timeout(value) => timestamp_cycles() >= value,
+        NextSegmentStatus::Nothing => false,
+    }
+}
+
 pub struct MmdsNetworkStack {
     // Network interface MAC address used by frames/packets heading to MMDS server.
     remote_mac_addr: MacAddr,
@@ -67,7 +123,19 @@ pub struct MmdsNetworkStack {
     // routes the packets.
     pending_arp_reply_dest: Option<Ipv4Addr>,
     // This handles MMDS<->guest interaction at the TCP level.
-    pub(crate) tcp_handler: TcpIPv4Handler,
+    pub(crate) tcp_handler: TcpIPHandler,
+    // MMDS server IPv6 address, if the MMDS is also reachable over IPv6.
+    pub(crate) ipv6_addr: Option<Ipv6Addr>,
+    // Neighbor advertisement destination IPv6 address (sender of the neighbor solicitation).
+    pending_neighbor_advertisement_dest: Option<Ipv6Addr>,
+    // This handles MMDS<->guest interaction at the TCP level over IPv6, when enabled.
+    pub(crate) tcp_ipv6_handler: Option<TcpIPHandler>,
+    // The name of the MMDS instance served to the guest, or None for the default instance.
+    pub(crate) mmds_id: Option<String>,
+    // The MMDS instance served to the guest.
+    pub(crate) mmds: Arc<Mutex<Mmds>>,
+    // The version of the data store when the waiting requests were last polled.
+    polled_data_version: Option<u64>,
 }
 
 impl MmdsNetworkStack {
@@ -83,12 +151,18 @@ impl MmdsNetworkStack {
             mac_addr,
             ipv4_addr,
             pending_arp_reply_dest: None,
-            tcp_handler: TcpIPv4Handler::new(
-                ipv4_addr,
+            tcp_handler: TcpIPHandler::new(
+                ipv4_addr.into(),
                 tcp_port,
                 max_connections,
                 max_pending_resets,
             ),
+            ipv6_addr: None,
+            pending_neighbor_advertisement_dest: None,
+            tcp_ipv6_handler: None,
+            mmds_id: None,
+            mmds: super::MMDS.clone(),
+            polled_data_version: None,
         }
     }
 
@@ -109,19 +183,120 @@ impl MmdsNetworkStack {
 
     pub fn set_ipv4_addr(&mut self, ipv4_addr: Ipv4Addr) {
         self.ipv4_addr = ipv4_addr;
-        self.tcp_handler.set_local_ipv4_addr(ipv4_addr);
+        self.tcp_handler.set_local_addr(ipv4_addr.into());
+    }
+
+    /// Makes the MMDS reachable at the given IPv6 address as well, or only over IPv4 if no
+    /// address is given.
+    pub fn set_ipv6_addr(&mut self, ipv6_addr: Option<Ipv6Addr>) {
+        match ipv6_addr {
+            Some(addr) => match self.tcp_ipv6_handler.as_mut() {
+                Some(tcp_handler) => tcp_handler.set_local_addr(addr.into()),
+                None => {
+                    // The unwrap()s are safe because the IPv4 handler limits are greater than 0.
+                    let max_connections =
+                        NonZeroUsize::new(self.tcp_handler.max_connections()).unwrap();
+                    let mut tcp_handler = TcpIPHandler::new(
+                        addr.into(),
+                        self.tcp_handler.local_port(),
+                        max_connections,
+                        NonZeroUsize::new(self.tcp_handler.max_pending_resets()).unwrap(),
+                    );
+                    tcp_handler
+                        .set_connection_limits(max_connections, self.tcp_handler.endpoint_limits());
+                    self.tcp_ipv6_handler = Some(tcp_handler);
+                }
+            },
+            None => {
+                self.tcp_ipv6_handler = None;
+                self.pending_neighbor_advertisement_dest = None;
+            }
+        }
+        self.ipv6_addr = ipv6_addr;
+    }
+
+    /// Sets the maximum number of concurrent guest connections, and the buffer sizes of each new
+    /// connection, over both IPv4 and IPv6.
+    pub fn set_connection_limits(
+        &mut self,
+        max_connections: NonZeroUsize,
+        endpoint_limits: EndpointLimits,
+    ) {
+        self.tcp_handler
+            .set_connection_limits(max_connections, endpoint_limits);
+        if let Some(tcp_handler) = self.tcp_ipv6_handler.as_mut() {
+            tcp_handler.set_connection_limits(max_connections, endpoint_limits);
+        }
+    }
+
+    /// Serves the MMDS instance named `mmds_id` to the guest, or the default instance if no name
+    /// is given.
+    pub fn set_mmds_instance(&mut self, mmds_id: Option<String>) {
+        self.mmds = super::mmds_instance(mmds_id.as_deref());
+        self.mmds_id = mmds_id;
+        self.polled_data_version = None;
+    }
+
+    /// Returns the name of the MMDS instance served to the guest, if it is not the default one.
+    pub fn mmds_id(&self) -> Option<&str> {
+        self.mmds_id.as_deref()
+    }
+
+    /// Returns the IPv6 address of the MMDS, if it is reachable over IPv6.
+    pub fn ipv6_addr(&self) -> Option<Ipv6Addr> {
+        self.ipv6_addr
     }
 
     pub fn default_ipv4_addr() -> Ipv4Addr {
         Ipv4Addr::from(DEFAULT_IPV4_ADDR)
     }
 
+    /// Returns true if some requests wait for the data store to change, in which case
+    /// `write_next_frame` should be called at least every `WAITING_REQUESTS_POLL_INTERVAL_MS`
+    /// milliseconds, so they are answered in time.
+    pub fn has_waiting_requests(&self) -> bool {
+        self.tcp_handler.has_waiting_requests()
+            || self
+                .tcp_ipv6_handler
+                .as_ref()
+                .map_or(false, TcpIPHandler::has_waiting_requests)
+    }
+
+    // Answers the waiting requests which no longer have to wait, or which timed out. Requests
+    // only stop waiting when the data store changes, so until then only the ones which timed out
+    // are answered again, instead of all of them being rendered anew on every poll.
+    fn poll_waiting_requests(&mut self) {
+        let data_version = self.mmds.lock().expect("Poisoned lock").data_version();
+        let data_changed = self.polled_data_version != Some(data_version);
+        self.polled_data_version = Some(data_version);
+
+        let mmds = &self.mmds;
+        let poll = |tcp_handler: &mut TcpIPHandler| {
+            let callback = |request| super::convert_to_reply(mmds, request);
+            if data_changed {
+                tcp_handler.poll_waiting_requests(callback);
+            } else {
+                tcp_handler.poll_expired_requests(callback);
+            }
+        };
+        poll(&mut self.tcp_handler);
+        if let Some(tcp_handler) = self.tcp_ipv6_handler.as_mut() {
+            poll(tcp_handler);
+        }
+    }
+
     // This is the entry point into the MMDS network stack. The src slice should hold the contents
     // of an Ethernet frame (of that exact size, without the CRC).
     pub fn detour_frame(&mut self, src: &[u8]) -> bool {
-        // The frame cannot possibly contain an ARP request or IPv4 packet for the MMDS.
+        // The frame cannot possibly contain an ARP request, a neighbor solicitation, or an IP
+        // packet for the MMDS.
+        let maybe_ipv6 = self.ipv6_addr.map_or(false, |addr| {
+            ipv6::test_speculative_dst_addr(src, addr)
+                || ipv6::test_speculative_dst_addr(src, solicited_node_multicast_addr(addr))
+        });
         if !test_speculative_tpa(src, self.ipv4_addr)
             && !test_speculative_dst_addr(src, self.ipv4_addr)
+            && !maybe_ipv6
         {
             return false;
         }
@@ -130,6 +305,7 @@ impl MmdsNetworkStack {
             match eth.ethertype() {
                 ETHERTYPE_ARP => return self.detour_arp(eth),
                 ETHERTYPE_IPV4 => return self.detour_ipv4(eth),
+                ETHERTYPE_IPV6 => return self.detour_ipv6(eth),
                 _ => (),
             };
         } else {
@@ -160,28 +336,11 @@ impl MmdsNetworkStack {
                 // Note-2: For every routed packet we will have a single source MAC address, because
                 // each MmdsNetworkStack routes packets for only one network device.
                 self.remote_mac_addr = eth.src_mac();
-                match self
-                    .tcp_handler
-                    .receive_packet(&ip, super::convert_to_response)
-                {
-                    Ok(event) => {
-                        METRICS.mmds.rx_count.inc();
-                        match event {
-                            RecvEvent::NewConnectionSuccessful => {
-                                METRICS.mmds.connections_created.inc()
-                            }
-                            RecvEvent::NewConnectionReplacing => {
-                                METRICS.mmds.connections_created.inc();
-                                METRICS.mmds.connections_destroyed.inc();
-                            }
-                            RecvEvent::EndpointDone => {
-                                METRICS.mmds.connections_destroyed.inc();
-                            }
-                            _ => (),
-                        }
-                    }
-                    Err(_) => METRICS.mmds.rx_accepted_err.inc(),
-                }
+                let mmds = &self.mmds;
+                record_recv_result(
+                    self.tcp_handler
+                        .receive_packet(&ip, |request| super::convert_to_reply(mmds, request)),
+                );
             } else {
                 // A non-TCP IPv4 packet heading towards the MMDS; we consider it unusual.
                 METRICS.mmds.rx_accepted_unusual.inc();
@@ -192,12 +351,83 @@ impl MmdsNetworkStack {
         false
     }
 
+    fn detour_ipv6(&mut self, eth: EthernetFrame<&[u8]>) -> bool {
+        let ipv6_addr = match self.ipv6_addr {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        let ip = match IPv6Packet::from_bytes(eth.payload()) {
+            Ok(ip) => ip,
+            Err(_) => return false,
+        };
+
+        if ip.destination_address() != ipv6_addr {
+            // Only neighbor solicitations looking for the MMDS are taken out of the multicast
+            // traffic; everything else goes on its way.
+            return self.detour_neighbor_solicitation(&eth, &ip, ipv6_addr);
+        }
+
+        match ip.next_header() {
+            PROTOCOL_TCP => {
+                // Same as for IPv4, the remote MAC address is the one of the network device.
+                self.remote_mac_addr = eth.src_mac();
+                let mmds = &self.mmds;
+                if let Some(tcp_handler) = self.tcp_ipv6_handler.as_mut() {
+                    record_recv_result(tcp_handler.receive_ipv6_packet(&ip, |request| {
+                        super::convert_to_reply(mmds, request)
+                    }));
+                }
+            }
+            PROTOCOL_ICMPV6 if self.detour_neighbor_solicitation(&eth, &ip, ipv6_addr) => (),
+            // A non-TCP IPv6 packet heading towards the MMDS; we consider it unusual.
+            _ => METRICS.mmds.rx_accepted_unusual.inc(),
+        }
+
+        true
+    }
+
+    fn detour_neighbor_solicitation(
+        &mut self,
+        eth: &EthernetFrame<&[u8]>,
+        ip: &IPv6Packet<&[u8]>,
+        ipv6_addr: Ipv6Addr,
+    ) -> bool {
+        // Neighbor discovery messages which may have been forwarded by a router are invalid, and
+        // so are the ones used for duplicate address detection, which are sent from the
+        // unspecified address.
+        if ip.next_header() != PROTOCOL_ICMPV6
+            || ip.hop_limit() != DEFAULT_HOP_LIMIT
+            || ip.source_address().is_unspecified()
+        {
+            return false;
+        }
+
+        match NeighborMessage::solicitation_from_bytes(
+            ip.payload(),
+            Some((ip.source_address(), ip.destination_address())),
+        ) {
+            Ok(solicitation) if solicitation.target_address() == ipv6_addr => {
+                self.remote_mac_addr = solicitation
+                    .source_link_layer_addr()
+                    .unwrap_or_else(|| eth.src_mac());
+                self.pending_neighbor_advertisement_dest = Some(ip.source_address());
+                true
+            }
+            _ => false,
+        }
+    }
+
     // Allows the MMDS network stack to write a frame to the specified buffer. Will return:
     // - None, if the MMDS network stack has no frame to send at this point. The buffer can be
     // used for something else by the device model.
     // - Some(len), if a frame of the given length has been written to the specified buffer.
     pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
-        // We try to send ARP replies first.
+        if self.has_waiting_requests() {
+            self.poll_waiting_requests();
+        }
+
+        // We try to send ARP replies and neighbor advertisements first.
         if self.pending_arp_reply_dest.is_some() {
             return match self.write_arp_reply(buf) {
                 Ok(something) => {
@@ -210,15 +440,29 @@ impl MmdsNetworkStack {
                     None
                 }
             };
+        } else if self.pending_neighbor_advertisement_dest.is_some() {
+            return match self.write_neighbor_advertisement(buf) {
+                Ok(something) => {
+                    METRICS.mmds.tx_count.inc();
+                    self.pending_neighbor_advertisement_dest = None;
+                    something
+                }
+                Err(_) => {
+                    METRICS.mmds.tx_errors.inc();
+                    None
+                }
+            };
         } else {
-            let call_write = match self.tcp_handler.next_segment_status() {
-                NextSegmentStatus::Available => true,
-                NextSegmentStatus::Timeout(value) => timestamp_cycles() >= value,
-                NextSegmentStatus::Nothing => false,
+            let ethertype = if should_write(&self.tcp_handler) {
+                Some(ETHERTYPE_IPV4)
+            } else if self.tcp_ipv6_handler.as_ref().map_or(false, should_write) {
+                Some(ETHERTYPE_IPV6)
+            } else {
+                None
             };
 
-            if call_write {
-                return match self.write_packet(buf) {
+            if let Some(ethertype) = ethertype {
+                return match self.write_packet(buf, ethertype) {
                     Ok(something) => {
                         METRICS.mmds.tx_count.inc();
                         something
@@ -270,14 +514,66 @@ impl MmdsNetworkStack {
         ))
     }
 
-    fn write_packet(&mut self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WritePacketError> {
+    fn write_neighbor_advertisement(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<Option<NonZeroUsize>, WriteNeighborAdvertisementError> {
+        let (ipv6_addr, dest) = match (self.ipv6_addr, self.pending_neighbor_advertisement_dest) {
+            (Some(addr), Some(dest)) => (addr, dest),
+            _ => return Err(WriteNeighborAdvertisementError::NoPendingNeighborAdvertisement),
+        };
+
         let mut eth_unsized = self
-            .prepare_eth_unsized(buf, ETHERTYPE_IPV4)
+            .prepare_eth_unsized(buf, ETHERTYPE_IPV6)
+            .map_err(WriteNeighborAdvertisementError::Ethernet)?;
+
+        let packet_len = {
+            let mut packet = IPv6Packet::write_header(
+                eth_unsized.inner_mut().payload_mut(),
+                PROTOCOL_ICMPV6,
+                ipv6_addr,
+                dest,
+            )
+            .map_err(WriteNeighborAdvertisementError::IPv6Packet)?;
+
+            let message_len = NeighborMessage::write_advertisement(
+                packet.inner_mut().payload_mut(),
+                FLAG_SOLICITED | FLAG_OVERRIDE,
+                ipv6_addr,
+                self.mac_addr,
+                (ipv6_addr, dest),
+            )
+            .map_err(WriteNeighborAdvertisementError::Icmpv6)?
+            .len();
+
+            packet.with_payload_len_unchecked(message_len).len()
+        };
+
+        Ok(Some(
+            // The unwrap() is safe because packet_len > 0.
+            NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(packet_len).len()).unwrap(),
+        ))
+    }
+
+    fn write_packet(
+        &mut self,
+        buf: &mut [u8],
+        ethertype: u16,
+    ) -> Result<Option<NonZeroUsize>, WritePacketError> {
+        let mut eth_unsized = self
+            .prepare_eth_unsized(buf, ethertype)
             .map_err(WritePacketError::Ethernet)?;
 
-        let (maybe_len, event) = self
-            .tcp_handler
-            .write_next_packet(eth_unsized.inner_mut().payload_mut())?;
+        let tcp_handler = if ethertype == ETHERTYPE_IPV6 {
+            self.tcp_ipv6_handler
+                .as_mut()
+                .ok_or(WritePacketError::NoIPv6Handler)?
+        } else {
+            &mut self.tcp_handler
+        };
+
+        let (maybe_len, event) =
+            tcp_handler.write_next_packet(eth_unsized.inner_mut().payload_mut())?;
 
         if let WriteEvent::EndpointDone = event {
             METRICS.mmds.connections_destroyed.inc()
@@ -310,6 +606,8 @@ mod tests {
     // all we're interested in is having some address different from the MMDS one.
     const REMOTE_ADDR: Ipv4Addr = Ipv4Addr::LOCALHOST;
     const REMOTE_MAC_STR: &str = "11:11:11:22:22:22";
+    const REMOTE_IPV6_ADDR: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 1, 2, 3, 4);
+    const MMDS_IPV6_ADDR: Ipv6Addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
     const MMDS_PORT: u16 = 80;
     const REMOTE_PORT: u16 = 1235;
     const SEQ_NUMBER: u32 = 123;
@@ -364,7 +662,11 @@ mod tests {
                     None,
                 )
                 .unwrap()
-                .finalize(REMOTE_PORT, MMDS_PORT, Some((REMOTE_ADDR, addr)))
+                .finalize(
+                    REMOTE_PORT,
+                    MMDS_PORT,
+                    Some((REMOTE_ADDR.into(), addr.into())),
+                )
                 .len();
 
                 packet.with_payload_len_unchecked(segment_len, true).len()
@@ -373,6 +675,82 @@ mod tests {
             eth_unsized.with_payload_len_unchecked(packet_len).len()
         }
 
+        fn write_neighbor_solicitation(&self, buf: &mut [u8], target: Ipv6Addr) -> usize {
+            let dst_addr = solicited_node_multicast_addr(target);
+            let mut eth_unsized = self.prepare_eth_unsized(buf, ETHERTYPE_IPV6).unwrap();
+            eth_unsized
+                .inner_mut()
+                .set_src_mac(MacAddr::parse_str(REMOTE_MAC_STR).unwrap());
+            let packet_len = {
+                let mut packet = IPv6Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_ICMPV6,
+                    REMOTE_IPV6_ADDR,
+                    dst_addr,
+                )
+                .unwrap();
+
+                // Write an advertisement carrying the remote MAC, and turn it into a solicitation.
+                let payload = packet.inner_mut().payload_mut();
+                let message_len = NeighborMessage::write_advertisement(
+                    &mut payload[..],
+                    0,
+                    target,
+                    MacAddr::parse_str(REMOTE_MAC_STR).unwrap(),
+                    (REMOTE_IPV6_ADDR, dst_addr),
+                )
+                .unwrap()
+                .len();
+                // Type 135 with the source link-layer address option (type 1).
+                payload[0] = 135;
+                payload[24] = 1;
+                let mut message =
+                    NeighborMessage::from_bytes_unchecked(&mut payload[..message_len]);
+                message.set_checksum(0);
+                let checksum = message.compute_checksum(REMOTE_IPV6_ADDR, dst_addr);
+                message.set_checksum(checksum);
+
+                packet.with_payload_len_unchecked(message_len).len()
+            };
+
+            eth_unsized.with_payload_len_unchecked(packet_len).len()
+        }
+
+        fn write_incoming_tcp6_segment(&self, buf: &mut [u8], flags: TcpFlags) -> usize {
+            let mut eth_unsized = self.prepare_eth_unsized(buf, ETHERTYPE_IPV6).unwrap();
+            let packet_len = {
+                let mut packet = IPv6Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_TCP,
+                    REMOTE_IPV6_ADDR,
+                    MMDS_IPV6_ADDR,
+                )
+                .unwrap();
+
+                let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
+                    packet.inner_mut().payload_mut(),
+                    SEQ_NUMBER,
+                    1234,
+                    flags,
+                    10000,
+                    None,
+                    0,
+                    None,
+                )
+                .unwrap()
+                .finalize(
+                    REMOTE_PORT,
+                    MMDS_PORT,
+                    Some((REMOTE_IPV6_ADDR.into(), MMDS_IPV6_ADDR.into())),
+                )
+                .len();
+
+                packet.with_payload_len_unchecked(segment_len).len()
+            };
+
+            eth_unsized.with_payload_len_unchecked(packet_len).len()
+        }
+
         fn next_frame_as_ipv4_packet<'a>(&mut self, buf: &'a mut [u8]) -> IPv4Packet<&'a [u8]> {
             let len = self.write_next_frame(buf).unwrap().get();
             let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
@@ -473,7 +851,7 @@ mod tests {
 
             let s = TcpSegment::from_bytes(
                 ip.payload(),
-                Some((ip.source_address(), ip.destination_address())),
+                Some((ip.source_address().into(), ip.destination_address().into())),
             )
             .unwrap();
             assert_eq!(s.flags_after_ns(), TcpFlags::RST);
@@ -498,7 +876,7 @@ mod tests {
 
             let s = TcpSegment::from_bytes(
                 ip.payload(),
-                Some((ip.source_address(), ip.destination_address())),
+                Some((ip.source_address().into(), ip.destination_address().into())),
             )
             .unwrap();
             assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
@@ -515,10 +893,139 @@ mod tests {
     fn test_set_ipv4_addr() {
         let mut ns = MmdsNetworkStack::new_with_defaults(None);
         assert_ne!(ns.ipv4_addr, Ipv4Addr::LOCALHOST);
-        assert_ne!(ns.tcp_handler.local_ipv4_addr(), Ipv4Addr::LOCALHOST);
+        assert_ne!(ns.tcp_handler.local_addr(), Ipv4Addr::LOCALHOST);
         ns.set_ipv4_addr(Ipv4Addr::LOCALHOST);
         assert_eq!(ns.ipv4_addr, Ipv4Addr::LOCALHOST);
-        assert_eq!(ns.tcp_handler.local_ipv4_addr(), Ipv4Addr::LOCALHOST);
+        assert_eq!(ns.tcp_handler.local_addr(), Ipv4Addr::LOCALHOST);
+    }
+
+    #[test]
+    fn test_set_ipv6_addr() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        assert_eq!(ns.ipv6_addr, None);
+        assert!(ns.tcp_ipv6_handler.is_none());
+
+        ns.set_ipv6_addr(Some(MMDS_IPV6_ADDR));
+        assert_eq!(ns.ipv6_addr, Some(MMDS_IPV6_ADDR));
+        let tcp_handler = ns.tcp_ipv6_handler.as_ref().unwrap();
+        assert_eq!(tcp_handler.local_addr(), MMDS_IPV6_ADDR);
+        assert_eq!(tcp_handler.local_port(), ns.tcp_handler.local_port());
+        assert_eq!(
+            tcp_handler.max_connections(),
+            ns.tcp_handler.max_connections()
+        );
+
+        ns.set_ipv6_addr(Some(Ipv6Addr::LOCALHOST));
+        assert_eq!(
+            ns.tcp_ipv6_handler.as_ref().unwrap().local_addr(),
+            Ipv6Addr::LOCALHOST
+        );
+
+        // The connection limits apply to both handlers, and to the ones created afterwards.
+        let endpoint_limits = EndpointLimits {
+            receive_buf_size: 4096,
+            send_buf_size: 8192,
+        };
+        ns.set_connection_limits(NonZeroUsize::new(5).unwrap(), endpoint_limits);
+        for tcp_handler in [&ns.tcp_handler, ns.tcp_ipv6_handler.as_ref().unwrap()].iter() {
+            assert_eq!(tcp_handler.max_connections(), 5);
+            assert_eq!(tcp_handler.endpoint_limits(), endpoint_limits);
+        }
+        ns.set_ipv6_addr(None);
+        ns.set_ipv6_addr(Some(Ipv6Addr::LOCALHOST));
+        let tcp_handler = ns.tcp_ipv6_handler.as_ref().unwrap();
+        assert_eq!(tcp_handler.max_connections(), 5);
+        assert_eq!(tcp_handler.endpoint_limits(), endpoint_limits);
+
+        ns.set_ipv6_addr(None);
+        assert_eq!(ns.ipv6_addr, None);
+        assert!(ns.tcp_ipv6_handler.is_none());
+    }
+
+    #[test]
+    fn test_ns_ipv6() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        let mut buf = [0u8; 2000];
+        let remote_mac = MacAddr::parse_str(REMOTE_MAC_STR).unwrap();
+        let multicast_addr = solicited_node_multicast_addr(MMDS_IPV6_ADDR);
+
+        // IPv6 is disabled by default.
+        let len = ns.write_neighbor_solicitation(buf.as_mut(), MMDS_IPV6_ADDR);
+        assert!(!ns.detour_frame(&buf[..len]));
+        let len = ns.write_incoming_tcp6_segment(buf.as_mut(), TcpFlags::SYN);
+        assert!(!ns.detour_frame(&buf[..len]));
+
+        ns.set_ipv6_addr(Some(MMDS_IPV6_ADDR));
+
+        // Solicitations for other addresses are not detoured.
+        let len = ns.write_neighbor_solicitation(buf.as_mut(), REMOTE_IPV6_ADDR);
+        assert!(!ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // Solicitations for the MMDS get a neighbor advertisement in response.
+        let len = ns.write_neighbor_solicitation(buf.as_mut(), MMDS_IPV6_ADDR);
+        assert!(ns.detour_frame(&buf[..len]));
+        assert_eq!(ns.remote_mac_addr, remote_mac);
+        {
+            let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+            let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(eth.ethertype(), ETHERTYPE_IPV6);
+            assert_eq!(eth.dst_mac(), remote_mac);
+            let ip = IPv6Packet::from_bytes(eth.payload()).unwrap();
+            assert_eq!(ip.next_header(), PROTOCOL_ICMPV6);
+            assert_eq!(ip.hop_limit(), DEFAULT_HOP_LIMIT);
+            assert_eq!(ip.source_address(), MMDS_IPV6_ADDR);
+            assert_eq!(ip.destination_address(), REMOTE_IPV6_ADDR);
+
+            let na = NeighborMessage::from_bytes_unchecked(ip.payload());
+            assert_eq!(na.target_address(), MMDS_IPV6_ADDR);
+            assert_eq!(na.target_link_layer_addr(), Some(ns.mac_addr));
+            assert_eq!(na.flags(), FLAG_SOLICITED | FLAG_OVERRIDE);
+            assert_eq!(na.compute_checksum(MMDS_IPV6_ADDR, REMOTE_IPV6_ADDR), 0);
+        }
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // Duplicate address detection probes are ignored.
+        let len = ns.write_neighbor_solicitation(buf.as_mut(), MMDS_IPV6_ADDR);
+        {
+            let mut eth = EthernetFrame::from_bytes_unchecked(&mut buf[..len]);
+            IPv6Packet::from_bytes_unchecked(eth.payload_mut())
+                .set_source_address(Ipv6Addr::UNSPECIFIED);
+        }
+        assert!(!ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // Other multicast traffic goes on its way.
+        let len = ns.write_incoming_tcp6_segment(buf.as_mut(), TcpFlags::SYN);
+        {
+            let mut eth = EthernetFrame::from_bytes_unchecked(&mut buf[..len]);
+            IPv6Packet::from_bytes_unchecked(eth.payload_mut())
+                .set_destination_address(multicast_addr);
+        }
+        assert!(!ns.detour_frame(&buf[..len]));
+
+        // A TCP SYN gets a SYNACK over IPv6.
+        let len = ns.write_incoming_tcp6_segment(buf.as_mut(), TcpFlags::SYN);
+        assert!(ns.detour_frame(&buf[..len]));
+        {
+            let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+            let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(eth.ethertype(), ETHERTYPE_IPV6);
+            let ip = IPv6Packet::from_bytes(eth.payload()).unwrap();
+            assert_eq!(ip.source_address(), MMDS_IPV6_ADDR);
+            assert_eq!(ip.destination_address(), REMOTE_IPV6_ADDR);
+
+            let s = TcpSegment::from_bytes(
+                ip.payload(),
+                Some((ip.source_address().into(), ip.destination_address().into())),
+            )
+            .unwrap();
+            assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+            assert_eq!(s.source_port(), MMDS_PORT);
+            assert_eq!(s.destination_port(), REMOTE_PORT);
+            assert_eq!(s.ack_number(), SEQ_NUMBER.wrapping_add(1));
+        }
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
     }
 
     #[test]
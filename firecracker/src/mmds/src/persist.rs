@@ -1,16 +1,50 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-//! Defines the structures needed for saving/restoring MmdsNetworkStack.
+//! Defines the structures needed for saving/restoring MmdsNetworkStack and DnsResponder.
 
-use std::net::Ipv4Addr;
+use std::convert::TryFrom;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroUsize;
 
+use dumbo::tcp::{EndpointLimits, DEFAULT_RECEIVE_BUF_SIZE, DEFAULT_SEND_BUF_SIZE};
 use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 
+use super::data_store::MmdsVersion;
+use super::dns::{DnsRecord, DnsResponder, Error as DnsError};
 use super::ns::MmdsNetworkStack;
+use super::token::Error as TokenError;
+
+/// The serializable state of the MMDS version.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub enum MmdsVersionState {
+    /// The guest accesses the MMDS without session tokens.
+    V1,
+    /// The guest needs session tokens to access the MMDS.
+    V2,
+}
+
+impl From<MmdsVersion> for MmdsVersionState {
+    fn from(version: MmdsVersion) -> Self {
+        match version {
+            MmdsVersion::V1 => MmdsVersionState::V1,
+            MmdsVersion::V2 => MmdsVersionState::V2,
+        }
+    }
+}
+
+impl From<MmdsVersionState> for MmdsVersion {
+    fn from(state: MmdsVersionState) -> Self {
+        match state {
+            MmdsVersionState::V1 => MmdsVersion::V1,
+            MmdsVersionState::V2 => MmdsVersion::V2,
+        }
+    }
+}
 
 /// State of a MmdsNetworkStack.
 #[derive(Clone, Versionize)]
@@ -21,12 +55,57 @@ pub struct MmdsNetworkStackState {
     tcp_port: u16,
     max_connections: usize,
     max_pending_resets: usize,
+    #[version(start = 2, ser_fn = "mmds_id_ser")]
+    mmds_id: Option<String>,
+    #[version(start = 2, default_fn = "default_mmds_version")]
+    mmds_version: MmdsVersionState,
+    #[version(start = 2, ser_fn = "ipv6_addr_ser")]
+    ipv6_addr: Option<Vec<u8>>,
+    #[version(start = 2, default_fn = "default_receive_buf_size")]
+    receive_buf_size: usize,
+    #[version(start = 2, default_fn = "default_send_buf_size")]
+    send_buf_size: usize,
+}
+
+impl MmdsNetworkStackState {
+    fn mmds_id_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        // Falling back to the default instance would expose its metadata to the guest.
+        if target_version < 2 && self.mmds_id.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the MMDS instances.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_mmds_version(_source_version: u16) -> MmdsVersionState {
+        MmdsVersionState::V1
+    }
+
+    fn ipv6_addr_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.ipv6_addr.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the MMDS over IPv6.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_receive_buf_size(_source_version: u16) -> usize {
+        DEFAULT_RECEIVE_BUF_SIZE
+    }
+
+    fn default_send_buf_size(_source_version: u16) -> usize {
+        DEFAULT_SEND_BUF_SIZE
+    }
 }
 
 impl Persist<'_> for MmdsNetworkStack {
     type State = MmdsNetworkStackState;
     type ConstructorArgs = ();
-    type Error = ();
+    type Error = TokenError;
 
     fn save(&self) -> Self::State {
         let mut mac_addr = [0; MAC_ADDR_LEN];
@@ -38,6 +117,11 @@ impl Persist<'_> for MmdsNetworkStack {
             tcp_port: self.tcp_handler.local_port(),
             max_connections: self.tcp_handler.max_connections(),
             max_pending_resets: self.tcp_handler.max_pending_resets(),
+            mmds_id: self.mmds_id.clone(),
+            mmds_version: self.mmds.lock().expect("Poisoned lock").version().into(),
+            ipv6_addr: self.ipv6_addr.map(|addr| addr.octets().to_vec()),
+            receive_buf_size: self.tcp_handler.endpoint_limits().receive_buf_size,
+            send_buf_size: self.tcp_handler.endpoint_limits().send_buf_size,
         }
     }
 
@@ -45,19 +129,101 @@ impl Persist<'_> for MmdsNetworkStack {
         _: Self::ConstructorArgs,
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
-        Ok(MmdsNetworkStack::new(
+        let max_connections = NonZeroUsize::new(state.max_connections).unwrap();
+        let mut ns = MmdsNetworkStack::new(
             MacAddr::from_bytes_unchecked(&state.mac_addr),
             Ipv4Addr::from(state.ipv4_addr),
             state.tcp_port,
-            std::num::NonZeroUsize::new(state.max_connections).unwrap(),
-            std::num::NonZeroUsize::new(state.max_pending_resets).unwrap(),
-        ))
+            max_connections,
+            NonZeroUsize::new(state.max_pending_resets).unwrap(),
+        );
+        ns.set_connection_limits(
+            max_connections,
+            EndpointLimits {
+                receive_buf_size: state.receive_buf_size,
+                send_buf_size: state.send_buf_size,
+            },
+        );
+        ns.set_mmds_instance(state.mmds_id.clone());
+        ns.set_ipv6_addr(
+            state
+                .ipv6_addr
+                .as_ref()
+                .and_then(|octets| <[u8; 16]>::try_from(octets.as_slice()).ok())
+                .map(Ipv6Addr::from),
+        );
+        ns.mmds
+            .lock()
+            .expect("Poisoned lock")
+            .set_version(state.mmds_version.into())?;
+
+        Ok(ns)
+    }
+}
+
+/// State of a DnsRecord.
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct DnsRecordState {
+    name: String,
+    record_type: u16,
+    data: Vec<u8>,
+}
+
+/// State of a DnsResponder.
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct DnsResponderState {
+    ipv4_addr: u32,
+    ttl: u32,
+    records: Vec<DnsRecordState>,
+}
+
+impl Persist<'_> for DnsResponder {
+    type State = DnsResponderState;
+    type ConstructorArgs = ();
+    type Error = DnsError;
+
+    fn save(&self) -> Self::State {
+        DnsResponderState {
+            ipv4_addr: self.ipv4_addr.into(),
+            ttl: self.ttl,
+            records: self
+                .records
+                .iter()
+                .map(|record| DnsRecordState {
+                    name: record.name.clone(),
+                    record_type: record.record_type,
+                    data: record.data.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn restore(
+        _: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        DnsResponder::new(
+            Ipv4Addr::from(state.ipv4_addr),
+            state.ttl,
+            state
+                .records
+                .iter()
+                .map(|record| DnsRecord {
+                    name: record.name.clone(),
+                    record_type: record.record_type,
+                    data: record.data.clone(),
+                })
+                .collect(),
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Arc;
 
     #[test]
     fn test_persistence() {
@@ -90,5 +256,146 @@ mod tests {
             restored_ns.tcp_handler.max_pending_resets(),
             ns.tcp_handler.max_pending_resets()
         );
+        assert_eq!(restored_ns.mmds_id(), None);
+        assert_eq!(restored_ns.ipv6_addr, None);
+        // Snapshots taken before the limits were configurable restore the defaults.
+        assert_eq!(
+            restored_ns.tcp_handler.endpoint_limits(),
+            EndpointLimits::default()
+        );
+    }
+
+    #[test]
+    fn test_persistence_of_connection_limits() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        let endpoint_limits = EndpointLimits {
+            receive_buf_size: 4096,
+            send_buf_size: 1024,
+        };
+        ns.set_connection_limits(NonZeroUsize::new(5).unwrap(), endpoint_limits);
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(MmdsNetworkStackState::type_id(), 2);
+
+        ns.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_ns = MmdsNetworkStack::restore(
+            (),
+            &MmdsNetworkStackState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored_ns.tcp_handler.max_connections(), 5);
+        assert_eq!(restored_ns.tcp_handler.endpoint_limits(), endpoint_limits);
+    }
+
+    #[test]
+    fn test_persistence_of_ipv6_addr() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        let ipv6_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        ns.set_ipv6_addr(Some(ipv6_addr));
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(MmdsNetworkStackState::type_id(), 2);
+
+        // Older versions would silently stop serving the MMDS over IPv6.
+        assert!(matches!(
+            ns.save()
+                .serialize(&mut mem.as_mut_slice(), &version_map, 1),
+            Err(VersionizeError::Semantic(_))
+        ));
+
+        ns.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_ns = MmdsNetworkStack::restore(
+            (),
+            &MmdsNetworkStackState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored_ns.ipv6_addr, Some(ipv6_addr));
+        assert_eq!(
+            restored_ns.tcp_ipv6_handler.as_ref().unwrap().local_addr(),
+            ipv6_addr
+        );
+    }
+
+    #[test]
+    fn test_persistence_of_mmds_instance() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        ns.set_mmds_instance(Some("persisted".to_string()));
+        ns.mmds
+            .lock()
+            .unwrap()
+            .set_version(MmdsVersion::V2)
+            .unwrap();
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(MmdsNetworkStackState::type_id(), 2);
+
+        // Older versions cannot tell which instance the guest accesses.
+        assert!(matches!(
+            ns.save()
+                .serialize(&mut mem.as_mut_slice(), &version_map, 1),
+            Err(VersionizeError::Semantic(_))
+        ));
+
+        ns.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        // Start the restored instance from a blank state.
+        ns.mmds
+            .lock()
+            .unwrap()
+            .set_version(MmdsVersion::V1)
+            .unwrap();
+
+        let restored_ns = MmdsNetworkStack::restore(
+            (),
+            &MmdsNetworkStackState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored_ns.mmds_id(), Some("persisted"));
+        assert!(Arc::ptr_eq(&restored_ns.mmds, &ns.mmds));
+        assert_eq!(restored_ns.mmds.lock().unwrap().version(), MmdsVersion::V2);
+    }
+
+    #[test]
+    fn test_persistence_of_dns_responder() {
+        let records = vec![
+            DnsRecord::new_a("api.internal", Ipv4Addr::new(10, 0, 0, 1)).unwrap(),
+            DnsRecord::new_ptr("1.0.0.10.in-addr.arpa", "api.internal").unwrap(),
+        ];
+        let responder =
+            DnsResponder::new(Ipv4Addr::new(169, 254, 0, 53), 30, records.clone()).unwrap();
+
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        responder
+            .save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_responder = DnsResponder::restore(
+            (),
+            &DnsResponderState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored_responder.ipv4_addr, Ipv4Addr::new(169, 254, 0, 53));
+        assert_eq!(restored_responder.ttl, 30);
+        assert_eq!(restored_responder.records, records);
     }
 }
@@ -0,0 +1,181 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Session tokens guarding the access of the guest to the MMDS.
+//!
+//! A token holds its expiry time, on the monotonic clock of the VMM, authenticated with an
+//! HMAC-SHA-256 code under a key drawn from the host entropy pool when the token authority is
+//! created. The key never leaves the VMM, so the guest cannot forge or extend tokens.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+
+use utils::sha256::{self, HmacSha256, DIGEST_LEN};
+use utils::time::{get_time_us, ClockType};
+
+/// Header holding the session token in the requests of the guest.
+pub const X_METADATA_TOKEN_HEADER: &str = "X-metadata-token";
+/// Header holding the lifetime, in seconds, of the session token requested by the guest.
+pub const X_METADATA_TOKEN_TTL_SECONDS_HEADER: &str = "X-metadata-token-ttl-seconds";
+/// Minimum lifetime of a session token, in seconds.
+pub const MIN_TOKEN_TTL_SECONDS: u32 = 1;
+/// Maximum lifetime of a session token, in seconds.
+pub const MAX_TOKEN_TTL_SECONDS: u32 = 21600;
+
+const KEY_LEN: usize = 32;
+const EXPIRY_LEN: usize = 8;
+const TOKEN_LEN: usize = EXPIRY_LEN + DIGEST_LEN;
+
+/// Errors of the token authority.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to read the key from the host entropy pool.
+    EntropyPool(io::Error),
+    /// The requested token lifetime is out of bounds.
+    InvalidTtlValue(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::EntropyPool(err) => write!(
+                f,
+                "Cannot read the token key from the host entropy pool: {}",
+                err
+            ),
+            Error::InvalidTtlValue(ttl) => write!(
+                f,
+                "Invalid time to live value provided for token: {}. Please provide a value \
+                 between {} and {}.",
+                ttl, MIN_TOKEN_TTL_SECONDS, MAX_TOKEN_TTL_SECONDS
+            ),
+        }
+    }
+}
+
+/// Generates and validates the session tokens of the MMDS.
+#[derive(Clone)]
+pub struct TokenAuthority {
+    key: [u8; KEY_LEN],
+}
+
+impl TokenAuthority {
+    /// Creates a token authority with a fresh key.
+    pub fn new() -> Result<Self, Error> {
+        let mut key = [0u8; KEY_LEN];
+        File::open("/dev/urandom")
+            .and_then(|mut urandom| urandom.read_exact(&mut key))
+            .map_err(Error::EntropyPool)?;
+        Ok(TokenAuthority { key })
+    }
+
+    /// Generates a token valid for `ttl_seconds` seconds.
+    pub fn generate_token(&self, ttl_seconds: u32) -> Result<String, Error> {
+        if ttl_seconds < MIN_TOKEN_TTL_SECONDS || ttl_seconds > MAX_TOKEN_TTL_SECONDS {
+            return Err(Error::InvalidTtlValue(ttl_seconds));
+        }
+
+        let expiry_ms = now_ms() + u64::from(ttl_seconds) * 1000;
+        let mut token = expiry_ms.to_be_bytes().to_vec();
+        token.extend_from_slice(&HmacSha256::mac(&self.key, &token));
+        Ok(sha256::to_hex(&token))
+    }
+
+    /// Checks that `token` was generated by this authority and has not expired yet.
+    pub fn is_valid(&self, token: &str) -> bool {
+        let token = match from_hex(token) {
+            Some(token) if token.len() == TOKEN_LEN => token,
+            _ => return false,
+        };
+        let (expiry, mac) = token.split_at(EXPIRY_LEN);
+
+        let mut hmac = HmacSha256::new(&self.key);
+        hmac.update(expiry);
+        if !hmac.verify(mac) {
+            return false;
+        }
+
+        let mut expiry_ms = [0u8; EXPIRY_LEN];
+        expiry_ms.copy_from_slice(expiry);
+        now_ms() < u64::from_be_bytes(expiry_ms)
+    }
+}
+
+fn now_ms() -> u64 {
+    get_time_us(ClockType::Monotonic) / 1000
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_token() {
+        let authority = TokenAuthority::new().unwrap();
+
+        let token = authority.generate_token(MIN_TOKEN_TTL_SECONDS).unwrap();
+        assert_eq!(token.len(), 2 * TOKEN_LEN);
+        assert!(authority.is_valid(&token));
+        assert!(authority
+            .generate_token(MAX_TOKEN_TTL_SECONDS)
+            .map(|token| authority.is_valid(&token))
+            .unwrap());
+
+        // Out of bounds lifetimes.
+        assert!(matches!(
+            authority.generate_token(MIN_TOKEN_TTL_SECONDS - 1),
+            Err(Error::InvalidTtlValue(0))
+        ));
+        assert!(matches!(
+            authority.generate_token(MAX_TOKEN_TTL_SECONDS + 1),
+            Err(Error::InvalidTtlValue(_))
+        ));
+
+        let err = Error::InvalidTtlValue(0);
+        assert_eq!(
+            err.to_string(),
+            "Invalid time to live value provided for token: 0. Please provide a value between \
+             1 and 21600."
+        );
+    }
+
+    #[test]
+    fn test_is_valid() {
+        let authority = TokenAuthority::new().unwrap();
+        let token = authority.generate_token(60).unwrap();
+        assert!(authority.is_valid(&token));
+
+        // Tokens of another authority are rejected.
+        assert!(!TokenAuthority::new().unwrap().is_valid(&token));
+
+        // Tampering with the expiry time is detected.
+        let mut forged = from_hex(&token).unwrap();
+        forged[EXPIRY_LEN - 1] ^= 0x01;
+        assert!(!authority.is_valid(&sha256::to_hex(&forged)));
+
+        // Malformed tokens.
+        assert!(!authority.is_valid(""));
+        assert!(!authority.is_valid(&token[1..]));
+        assert!(!authority.is_valid(&token[2..]));
+        assert!(!authority.is_valid(&"z".repeat(2 * TOKEN_LEN)));
+        assert!(!authority.is_valid(&"+1".repeat(TOKEN_LEN)));
+        assert!(!authority.is_valid(&"é".repeat(TOKEN_LEN)));
+
+        // Expired tokens.
+        let expiry_ms = now_ms() - 1;
+        let mut expired = expiry_ms.to_be_bytes().to_vec();
+        expired.extend_from_slice(&HmacSha256::mac(&authority.key, &expired));
+        assert!(!authority.is_valid(&sha256::to_hex(&expired)));
+    }
+}
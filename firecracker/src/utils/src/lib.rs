@@ -8,11 +8,15 @@
 pub use vmm_sys_util::{
     epoll, errno, eventfd, fam, ioctl, rand, syscall, tempdir, tempfile, terminal,
 };
-pub use vmm_sys_util::{ioctl_expr, ioctl_ioc_nr, ioctl_iow_nr};
+pub use vmm_sys_util::{
+    ioctl_expr, ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr, ioctl_iowr_nr,
+};
 
 pub mod arg_parser;
 pub mod byte_order;
 pub mod net;
+pub mod sched;
+pub mod sha256;
 pub mod signal;
 pub mod sm;
 pub mod time;
@@ -0,0 +1,63 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::net::Ipv6Addr;
+
+/// Checks if an IPv6 address is only reachable from the local network, being either a
+/// link-local unicast address (RFC 4291) or a unique local address (RFC 4193).
+/// # Examples
+///
+/// ```
+/// use std::net::Ipv6Addr;
+/// use utils::net::ipv6addr::is_local_unicast_valid;
+///
+/// is_local_unicast_valid(Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254));
+///
+pub fn is_local_unicast_valid(ipv6_addr: Ipv6Addr) -> bool {
+    match ipv6_addr.segments()[0] {
+        // fe80::/10
+        segment if segment & 0xffc0 == 0xfe80 => true,
+        // fc00::/7
+        segment if segment & 0xfe00 == 0xfc00 => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::ipv6addr::is_local_unicast_valid;
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn test_is_local_unicast_valid() {
+        // Global, loopback and multicast addresses.
+        assert!(!is_local_unicast_valid(Ipv6Addr::new(
+            0x2001, 0xdb8, 0, 0, 0, 0, 0, 1
+        )));
+        assert!(!is_local_unicast_valid(Ipv6Addr::LOCALHOST));
+        assert!(!is_local_unicast_valid(Ipv6Addr::UNSPECIFIED));
+        assert!(!is_local_unicast_valid(Ipv6Addr::new(
+            0xff02, 0, 0, 0, 0, 0, 0, 1
+        )));
+        // Just outside the link-local range.
+        assert!(!is_local_unicast_valid(Ipv6Addr::new(
+            0xfec0, 0, 0, 0, 0, 0, 0, 1
+        )));
+
+        // Link-local addresses.
+        assert!(is_local_unicast_valid(Ipv6Addr::new(
+            0xfe80, 0, 0, 0, 0, 0, 0, 1
+        )));
+        assert!(is_local_unicast_valid(Ipv6Addr::new(
+            0xfebf, 0, 0, 0, 0, 0, 0, 1
+        )));
+
+        // Unique local addresses.
+        assert!(is_local_unicast_valid(Ipv6Addr::new(
+            0xfc00, 0, 0, 0, 0, 0, 0, 1
+        )));
+        assert!(is_local_unicast_valid(Ipv6Addr::new(
+            0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254
+        )));
+    }
+}
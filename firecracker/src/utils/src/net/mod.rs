@@ -13,4 +13,5 @@
 
 /// Provides IPv4 address utility methods.
 pub mod ipv4addr;
+pub mod ipv6addr;
 pub mod mac;
@@ -0,0 +1,103 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers placing threads on host CPUs and setting their scheduling policy.
+
+use std::mem;
+
+use crate::errno::{Error, Result};
+
+/// Number of host CPUs that fit in a CPU set.
+pub const MAX_CPUS: usize = 8 * mem::size_of::<libc::cpu_set_t>();
+
+/// Restricts the calling thread to run on the host CPUs in `cpus`.
+///
+/// # Panics
+///
+/// Panics if a CPU of `cpus` is not lower than `MAX_CPUS`.
+pub fn set_current_thread_affinity(cpus: &[usize]) -> Result<()> {
+    // Safe because `cpu_set_t` is a plain bit mask.
+    let mut cpu_set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for cpu in cpus {
+        assert!(*cpu < MAX_CPUS);
+        // Safe because the CPU is within the bounds of the set.
+        unsafe { libc::CPU_SET(*cpu, &mut cpu_set) };
+    }
+
+    // Safe because the size matches the set, and the return value is checked. A pid of 0
+    // stands for the calling thread, which is the only one the seccomp filters let a thread
+    // pin.
+    if unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &cpu_set) } < 0 {
+        return Err(Error::last());
+    }
+    Ok(())
+}
+
+/// Schedules the calling thread with the real-time `SCHED_FIFO` policy, at the static
+/// `priority`.
+pub fn set_current_thread_fifo_priority(priority: u8) -> Result<()> {
+    let param = libc::sched_param {
+        sched_priority: i32::from(priority),
+    };
+    // Safe because `param` is valid, and the return value is checked. A pid of 0 stands for the
+    // calling thread.
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } < 0 {
+        return Err(Error::last());
+    }
+    Ok(())
+}
+
+/// Sets the nice value of the calling thread.
+pub fn set_current_thread_nice(nice: i8) -> Result<()> {
+    // Safe because the return value is checked. On Linux, `PRIO_PROCESS` with an id of 0 only
+    // applies to the calling thread.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, i32::from(nice)) } < 0 {
+        return Err(Error::last());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current_thread_affinity() -> Vec<usize> {
+        let mut cpu_set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        assert_eq!(
+            unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut cpu_set) },
+            0
+        );
+        (0..MAX_CPUS)
+            .filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &cpu_set) })
+            .collect()
+    }
+
+    #[test]
+    fn test_set_current_thread_affinity() {
+        // Run in a separate thread, to leave the affinity of the test thread untouched.
+        std::thread::spawn(|| {
+            let cpus = current_thread_affinity();
+            set_current_thread_affinity(&cpus[..1]).unwrap();
+            assert_eq!(current_thread_affinity(), vec![cpus[0]]);
+
+            // An empty set of CPUs is rejected by the kernel.
+            assert_eq!(
+                set_current_thread_affinity(&[]).unwrap_err().errno(),
+                libc::EINVAL
+            );
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_set_current_thread_nice() {
+        std::thread::spawn(|| {
+            // Lowering the priority of a thread is always allowed.
+            set_current_thread_nice(19).unwrap();
+            assert_eq!(unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) }, 19);
+        })
+        .join()
+        .unwrap();
+    }
+}
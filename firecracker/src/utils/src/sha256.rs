@@ -0,0 +1,204 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! SHA-256 (FIPS 180-4) and HMAC-SHA-256 (RFC 2104) helpers.
+//!
+//! These are thin wrappers over the `sha2`, `hmac` and `subtle` crates, giving the VMM fixed
+//! size digests and a `std::io::Write` interface for hashing.
+
+use std::io;
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Digest as _;
+use subtle::ConstantTimeEq;
+
+/// Size in bytes of a SHA-256 digest.
+pub const DIGEST_LEN: usize = 32;
+
+/// A SHA-256 digest.
+pub type Digest = [u8; DIGEST_LEN];
+
+/// Incremental SHA-256 hasher.
+///
+/// Data can be fed either through [`update`](struct.Sha256.html#method.update) or through the
+/// `std::io::Write` implementation, which makes it possible to hash the output of any writer
+/// based API (e.g. guest memory dumps) without intermediate buffers.
+#[derive(Clone, Default)]
+pub struct Sha256(sha2::Sha256);
+
+impl Sha256 {
+    /// Creates a new hasher.
+    pub fn new() -> Self {
+        Sha256(sha2::Sha256::new())
+    }
+
+    /// Computes the digest of `data` in one go.
+    pub fn digest(data: &[u8]) -> Digest {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Feeds `data` into the hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// Consumes the hasher and returns the digest of all the data fed so far.
+    pub fn finalize(self) -> Digest {
+        let mut digest = [0u8; DIGEST_LEN];
+        digest.copy_from_slice(&self.0.finalize());
+        digest
+    }
+}
+
+impl io::Write for Sha256 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Incremental HMAC-SHA-256 authenticator.
+#[derive(Clone)]
+pub struct HmacSha256(Hmac<sha2::Sha256>);
+
+impl HmacSha256 {
+    /// Creates a new authenticator keyed with `key`.
+    pub fn new(key: &[u8]) -> Self {
+        // HMAC takes keys of any length, hashing the ones longer than a block.
+        HmacSha256(Hmac::new_from_slice(key).expect("HMAC accepts keys of any length"))
+    }
+
+    /// Computes the authentication code of `data` under `key` in one go.
+    pub fn mac(key: &[u8], data: &[u8]) -> Digest {
+        let mut hmac = HmacSha256::new(key);
+        hmac.update(data);
+        hmac.finalize()
+    }
+
+    /// Feeds `data` into the authenticator.
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// Consumes the authenticator and returns the authentication code.
+    pub fn finalize(self) -> Digest {
+        let mut digest = [0u8; DIGEST_LEN];
+        digest.copy_from_slice(&self.0.finalize().into_bytes());
+        digest
+    }
+
+    /// Consumes the authenticator and checks the result against `expected` in constant time.
+    pub fn verify(self, expected: &[u8]) -> bool {
+        self.0.verify(expected).is_ok()
+    }
+}
+
+/// Compares two byte slices without short-circuiting on the first differing byte.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Returns the lowercase hexadecimal representation of `bytes`.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_sha256_vectors() {
+        // Test vectors from FIPS 180-4 examples and NIST CAVP.
+        assert_eq!(
+            to_hex(&Sha256::digest(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            to_hex(&Sha256::digest(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            to_hex(&Sha256::digest(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            to_hex(&Sha256::digest(&[b'a'; 1_000_000])),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn test_sha256_incremental() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let expected = Sha256::digest(&data);
+
+        // Feed the data in chunks which are not aligned to the block size.
+        for chunk_size in &[1, 3, 55, 56, 63, 64, 65, 128, 999] {
+            let mut hasher = Sha256::new();
+            for chunk in data.chunks(*chunk_size) {
+                hasher.write_all(chunk).unwrap();
+            }
+            hasher.flush().unwrap();
+            assert_eq!(hasher.finalize(), expected);
+        }
+    }
+
+    #[test]
+    fn test_hmac_sha256_vectors() {
+        // Test vectors from RFC 4231.
+        assert_eq!(
+            to_hex(&HmacSha256::mac(&[0x0b; 20], b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            to_hex(&HmacSha256::mac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            to_hex(&HmacSha256::mac(&[0xaa; 20], &[0xdd; 50])),
+            "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"
+        );
+        // Key larger than the block size.
+        assert_eq!(
+            to_hex(&HmacSha256::mac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_hmac_verify() {
+        let mac = HmacSha256::mac(b"key", b"data");
+
+        let mut hmac = HmacSha256::new(b"key");
+        hmac.update(b"da");
+        hmac.update(b"ta");
+        assert!(hmac.clone().verify(&mac));
+
+        let mut forged = mac;
+        forged[DIGEST_LEN - 1] ^= 1;
+        assert!(!hmac.clone().verify(&forged));
+        assert!(!hmac.verify(&mac[..DIGEST_LEN - 1]));
+        assert!(!HmacSha256::new(b"other key").verify(&mac));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
@@ -13,6 +13,7 @@
 //! This implementation is mmap-ing the memory of the guest into the current process.
 
 use std::borrow::Borrow;
+use std::fs::File;
 use std::io::{Error as IoError, Read, Write};
 use std::ops::Deref;
 use std::os::unix::io::AsRawFd;
@@ -46,6 +47,26 @@ static MAX_ACCESS_CHUNK: usize = 4096;
 // The number of guard pages per region is a multiple of 2.
 const GUARD_NUMBER: usize = 2;
 
+// The filesystem magic number of hugetlbfs, as defined in `include/uapi/linux/magic.h`.
+const HUGETLBFS_MAGIC: u32 = 0x9584_58f6;
+
+/// Returns the size of the huge pages backing `file`, or `None` if `file` does not live on
+/// hugetlbfs (or if its filesystem cannot be queried).
+pub fn hugetlbfs_page_size(file: &File) -> Option<usize> {
+    let mut statfs: libc::statfs = unsafe { std::mem::zeroed() };
+    // Safe because the file descriptor is valid and we check the return value.
+    if unsafe { libc::fstatfs(file.as_raw_fd(), &mut statfs) } < 0 {
+        return None;
+    }
+    // The type of `f_type` differs between libc implementations, but only its
+    // lower 32 bits are meaningful.
+    if statfs.f_type as u32 == HUGETLBFS_MAGIC {
+        Some(statfs.f_bsize as usize)
+    } else {
+        None
+    }
+}
+
 /// [`GuestMemoryRegion`](trait.GuestMemoryRegion.html) implementation that mmaps the guest's
 /// memory region in the current process.
 ///
@@ -108,6 +129,9 @@ impl GuestRegionMmap {
     /// Creates a guarded mapping based on the provided arguments.
     /// Guard pages will be created at the beginning and the end of the range.
     ///
+    /// Mappings of hugetlbfs files are aligned to the huge page size of the file,
+    /// as the kernel refuses to map them otherwise.
+    ///
     /// # Arguments
     /// * `file_offset` - if provided, the method will create a file mapping at offset
     ///                   `file_offset.start` in the file referred to by `file_offset.file`.
@@ -123,9 +147,14 @@ impl GuestRegionMmap {
         flags: i32,
     ) -> Result<MmapRegion, MmapRegionError> {
         let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+        let alignment = file_offset
+            .as_ref()
+            .and_then(|f_off| hugetlbfs_page_size(f_off.file()))
+            .map_or(page_size, |huge_page_size| huge_page_size.max(page_size));
         // Create the guarded range size (received size + X pages),
-        // where X is defined as a constant GUARD_NUMBER.
-        let guarded_size = size + GUARD_NUMBER * page_size;
+        // where X is defined as a constant GUARD_NUMBER. Larger alignments
+        // need some slack to fit the aligned range in between the guards.
+        let guarded_size = size + GUARD_NUMBER * page_size + (alignment - page_size);
 
         // Map the guarded range to PROT_NONE
         let guard_addr = unsafe {
@@ -150,7 +179,8 @@ impl GuestRegionMmap {
             (-1, 0)
         };
 
-        let map_addr = guard_addr as usize + page_size * (GUARD_NUMBER / 2);
+        let map_addr = (guard_addr as usize + page_size * (GUARD_NUMBER / 2) + alignment - 1)
+            & !(alignment - 1);
 
         // Inside the protected range, starting with guard_addr + PAGE_SIZE,
         // map the requested range with received protection and flags
@@ -539,6 +569,13 @@ impl GuestMemoryMmap {
                 let create_flags = libc::MAP_NORESERVE | libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
 
                 if let Some(ref f_off) = file_offset {
+                    // Huge pages are reserved when mapping them, so that an exhausted
+                    // pool fails here rather than with a SIGBUS on first access.
+                    let file_flags = if hugetlbfs_page_size(f_off.file()).is_some() {
+                        file_flags & !libc::MAP_NORESERVE
+                    } else {
+                        file_flags
+                    };
                     GuestRegionMmap::build_guarded(Some(f_off.clone()), size, prot, file_flags)
                 } else {
                     GuestRegionMmap::build_guarded(None, size, prot, create_flags)
@@ -751,8 +788,8 @@ mod tests {
     use super::*;
     use vm_memory_upstream::GuestAddressSpace;
 
-    use std::fs::File;
     use std::mem;
+    use std::os::unix::io::FromRawFd;
     use std::path::Path;
     use vmm_sys_util::tempfile::TempFile;
 
@@ -1816,6 +1853,26 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_hugetlbfs_page_size() {
+        let file = TempFile::new().unwrap().into_file();
+        assert!(hugetlbfs_page_size(&file).is_none());
+
+        // Huge pages may not be available on the host running the tests.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_memfd_create,
+                b"test\0".as_ptr(),
+                libc::MFD_CLOEXEC | libc::MFD_HUGETLB,
+            )
+        };
+        if fd >= 0 {
+            let file = unsafe { File::from_raw_fd(fd as i32) };
+            let huge_page_size = hugetlbfs_page_size(&file).unwrap();
+            assert!(huge_page_size > unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize });
+        }
+    }
+
     #[test]
     fn test_regions_guarded() {
         let region_size = 0x10000;
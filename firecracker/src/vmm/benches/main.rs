@@ -81,6 +81,8 @@ fn create_microvm_state(is_diff: bool) -> MicrovmState {
                 snapshot_path: snapshot_file.as_path().to_path_buf(),
                 mem_file_path: memory_file.as_path().to_path_buf(),
                 version: None,
+                integrity_key_path: None,
+                background: false,
             };
 
             {
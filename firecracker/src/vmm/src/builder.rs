@@ -5,8 +5,9 @@
 
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
+use std::fs::File;
 use std::io::{self, Read, Seek, SeekFrom};
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::sync::{Arc, Mutex};
 
 #[cfg(target_arch = "aarch64")]
@@ -15,8 +16,11 @@ use crate::construct_kvm_mpidrs;
 use crate::device_manager::legacy::PortIODeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
 use crate::device_manager::persist::MMIODevManagerConstructorArgs;
+use crate::measured_boot;
 use crate::persist::{MicrovmState, MicrovmStateError};
 use crate::vmm_config::boot_source::BootConfig;
+use crate::vmm_config::machine_config::MemoryBackend;
+use crate::vmm_config::tpm::TpmConfig;
 use crate::vstate::{
     system::KvmContext,
     vcpu::{Vcpu, VcpuConfig},
@@ -24,10 +28,13 @@ use crate::vstate::{
 };
 use crate::{device_manager, Error, Vmm, VmmEventsObserver};
 
-use arch::InitrdConfig;
+use arch::{EventLogConfig, InitrdConfig};
 use devices::legacy::Serial;
+use devices::tpm::{Emulator, Tpm};
 use devices::virtio::{Balloon, Block, MmioTransport, Net, VirtioDevice, Vsock, VsockUnixBackend};
+use kernel::bundle::SectionReader;
 use kernel::cmdline::Cmdline as KernelCmdline;
+use kernel::loader::LoadedKernel;
 use logger::{error, warn};
 use polly::event_manager::{Error as EventManagerError, EventManager, Subscriber};
 use seccomp::{BpfProgramRef, SeccompFilter};
@@ -35,7 +42,7 @@ use snapshot::Persist;
 use utils::eventfd::EventFd;
 use utils::terminal::Terminal;
 use utils::time::TimestampUs;
-use vm_memory::{GuestAddress, GuestMemoryMmap};
+use vm_memory::{FileOffset, GuestAddress, GuestMemoryMmap};
 
 /// Errors associated with starting the instance.
 #[derive(Debug)]
@@ -48,6 +55,13 @@ pub enum StartMicrovmError {
     CreateNetDevice(devices::virtio::net::Error),
     /// Failed to create a `RateLimiter` object.
     CreateRateLimiter(io::Error),
+    /// Cannot create the TPM device.
+    CreateTpmDevice(devices::tpm::Error),
+    /// Cannot start the GDB server.
+    #[cfg(feature = "gdb")]
+    GdbServer(crate::gdb::Error),
+    /// Cannot create the memory file backing the guest memory.
+    GuestMemoryFile(io::Error),
     /// Memory regions are overlapping or mmap fails.
     GuestMemoryMmap(vm_memory::Error),
     /// Cannot load initrd due to an invalid memory configuration.
@@ -62,6 +76,8 @@ pub enum StartMicrovmError {
     KernelLoader(kernel::loader::Error),
     /// Cannot load command line string.
     LoadCommandline(kernel::cmdline::Error),
+    /// Cannot measure the boot components.
+    MeasuredBoot(measured_boot::Error),
     /// Cannot start the VM because the kernel was not configured.
     MissingKernelConfig,
     /// Cannot start the VM because the size of the guest memory  was not specified.
@@ -76,6 +92,8 @@ pub enum StartMicrovmError {
     RegisterMmioDevice(device_manager::mmio::Error),
     /// Cannot restore microvm state.
     RestoreMicrovmState(MicrovmStateError),
+    /// Cannot set the affinity of the VMM thread.
+    VmmThreadAffinity(utils::errno::Error),
 }
 
 /// It's convenient to automatically convert `kernel::cmdline::Error`s
@@ -95,12 +113,16 @@ impl Display for StartMicrovmError {
             }
             ConfigureSystem(e) => write!(f, "System configuration error: {:?}", e),
             CreateRateLimiter(err) => write!(f, "Cannot create RateLimiter: {}", err),
+            CreateTpmDevice(err) => write!(f, "Cannot create TPM device: {}", err),
             CreateNetDevice(err) => {
                 let mut err_msg = format!("{:?}", err);
                 err_msg = err_msg.replace("\"", "");
 
                 write!(f, "Cannot create network device. {}", err_msg)
             }
+            #[cfg(feature = "gdb")]
+            GdbServer(err) => write!(f, "Cannot start the GDB server: {}", err),
+            GuestMemoryFile(err) => write!(f, "Cannot create guest memory file: {}", err),
             GuestMemoryMmap(err) => {
                 // Remove imbricated quotes from error message.
                 let mut err_msg = format!("{:?}", err);
@@ -129,6 +151,7 @@ impl Display for StartMicrovmError {
                 err_msg = err_msg.replace("\"", "");
                 write!(f, "Cannot load command line string. {}", err_msg)
             }
+            MeasuredBoot(err) => write!(f, "Cannot measure the boot components: {}", err),
             MissingKernelConfig => write!(f, "Cannot start microvm without kernel configuration."),
             MissingMemSizeConfig => {
                 write!(f, "Cannot start microvm without guest mem_size config.")
@@ -153,6 +176,9 @@ impl Display for StartMicrovmError {
                 )
             }
             RestoreMicrovmState(err) => write!(f, "Cannot restore microvm state. Error: {}", err),
+            VmmThreadAffinity(err) => {
+                write!(f, "Cannot set the affinity of the VMM thread: {}", err)
+            }
         }
     }
 }
@@ -266,8 +292,16 @@ fn create_vmm_and_vcpus(
         events_observer: Some(Box::new(SerialStdin::get())),
         guest_memory,
         vcpus_handles: Vec::new(),
+        online_vcpu_count: 0,
+        vcpus_paused: true,
         exit_evt,
         vm,
+        cpu_template: None,
+        custom_cpu_template: None,
+        ht_enabled: false,
+        mem_backend: MemoryBackend::Anonymous,
+        background_snapshot: None,
+        boot_measurements: None,
         mmio_device_manager,
         #[cfg(target_arch = "x86_64")]
         pio_device_manager,
@@ -292,15 +326,18 @@ pub fn build_microvm_for_boot(
     let boot_config = vm_resources.boot_source().ok_or(MissingKernelConfig)?;
 
     let track_dirty_pages = vm_resources.track_dirty_pages();
-    let guest_memory = create_guest_memory(
-        vm_resources
-            .vm_config()
-            .mem_size_mib
-            .ok_or(MissingMemSizeConfig)?,
-        track_dirty_pages,
-    )?;
+    let mem_backend = vm_resources.mem_backend();
+    let mem_size_mib = vm_resources
+        .vm_config()
+        .mem_size_mib
+        .ok_or(MissingMemSizeConfig)?;
+    let guest_memory = create_guest_memory(mem_size_mib, track_dirty_pages, mem_backend)?;
     let vcpu_config = vm_resources.vcpu_config();
-    let entry_addr = load_kernel(boot_config, &guest_memory)?;
+    let kernel = load_kernel(boot_config, &guest_memory)?;
+    #[cfg(target_arch = "x86_64")]
+    if boot_config.measured_boot && kernel.format == kernel::loader::KernelFormat::PvhElf {
+        return Err(MeasuredBoot(measured_boot::Error::PvhBoot));
+    }
     let initrd = load_initrd_from_config(boot_config, &guest_memory)?;
     // Clone the command-line so that a failed boot doesn't pollute the original.
     #[allow(unused_mut)]
@@ -342,21 +379,104 @@ pub fn build_microvm_for_boot(
     if let Some(unix_vsock) = vm_resources.vsock.get() {
         attach_unixsock_vsock_device(&mut vmm, &mut boot_cmdline, unix_vsock, event_manager)?;
     }
+    if let Some(tpm_config) = vm_resources.tpm_config.as_ref() {
+        attach_tpm_device(&mut vmm, &mut boot_cmdline, tpm_config)?;
+    }
 
     #[cfg(target_arch = "aarch64")]
     attach_legacy_devices_aarch64(event_manager, &mut vmm, &mut boot_cmdline).map_err(Internal)?;
 
+    vmm.cpu_template = vcpu_config.cpu_template;
+    vmm.custom_cpu_template = vcpu_config.custom_cpu_template.clone();
+    vmm.ht_enabled = vcpu_config.ht_enabled;
+    vmm.mem_backend = mem_backend;
+
+    // All the vcpus are described to the guest, which only brings up the first `maxcpus` ones
+    // and leaves the other ones offline until the online vcpu count grows.
+    let online_vcpu_count = vm_resources.vm_config().vcpu_count.unwrap();
+    if online_vcpu_count < vcpu_config.vcpu_count {
+        boot_cmdline.insert("maxcpus", online_vcpu_count.to_string().as_str())?;
+    }
+
+    // The boot components are measured once the command line and the devices are final.
+    let mut event_log = None;
+    if boot_config.measured_boot {
+        let boot_log = measured_boot::measure_boot(
+            boot_config,
+            &vcpu_config,
+            mem_size_mib,
+            vmm.mmio_device_manager.get_device_info(),
+            boot_cmdline.as_str(),
+        )
+        .map_err(MeasuredBoot)?;
+        event_log = Some(
+            boot_log
+                .write_to_guest(vmm.guest_memory())
+                .map_err(MeasuredBoot)?,
+        );
+        vmm.boot_measurements = Some(boot_log.measurements());
+    }
+
     configure_system_for_boot(
         &vmm,
         vcpus.as_mut(),
         vcpu_config,
-        entry_addr,
+        &kernel,
         &initrd,
+        &event_log,
         boot_cmdline,
     )?;
 
+    // The vcpu threads are placed on their host CPUs before they enter `KVM_RUN`.
+    let vm_config = vm_resources.vm_config();
+    for vcpu in vcpus.iter_mut() {
+        let host_cpus = vm_config
+            .vcpu_affinity
+            .as_ref()
+            .map(|vcpu_affinity| vcpu_affinity[vcpu.kvm_vcpu.index as usize].clone());
+        vcpu.set_thread_config(host_cpus, vm_config.vcpu_sched_policy);
+    }
+
+    // The vcpus report to the GDB server, which holds them until a debugger lets them run.
+    #[cfg(feature = "gdb")]
+    let gdb_socket_path = vm_resources.vm_config().gdb_socket_path.clone();
+    #[cfg(feature = "gdb")]
+    let debug_receiver = gdb_socket_path.as_ref().map(|_| {
+        let (debug_sender, debug_receiver) = std::sync::mpsc::channel();
+        for vcpu in vcpus.iter_mut() {
+            vcpu.set_debug_sender(debug_sender.clone());
+        }
+        debug_receiver
+    });
+
     // Move vcpus to their own threads and start their state machine in the 'Paused' state.
     vmm.start_vcpus(vcpus, seccomp_filter).map_err(Internal)?;
+    vmm.online_vcpu_count = online_vcpu_count;
+
+    // The GDB server thread loads the seccomp filters of the VMM thread before serving
+    // debuggers.
+    #[cfg(feature = "gdb")]
+    {
+        if let (Some(socket_path), Some(debug_receiver)) = (gdb_socket_path, debug_receiver) {
+            let debug_handles = vmm
+                .vcpus_handles
+                .iter()
+                .map(|handle| handle.debug_handle())
+                .collect();
+            crate::gdb::start_server(
+                &socket_path,
+                debug_handles,
+                debug_receiver,
+                vmm.guest_memory().clone(),
+                seccomp_filter.to_vec(),
+            )
+            .map_err(GdbServer)?;
+        }
+    }
+
+    if let Some(vmm_affinity) = &vm_config.vmm_affinity {
+        utils::sched::set_current_thread_affinity(vmm_affinity).map_err(VmmThreadAffinity)?;
+    }
 
     // Load seccomp filters for the VMM thread.
     // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping filters
@@ -411,6 +531,22 @@ pub fn build_microvm_from_snapshot(
             .map_err(RestoreMicrovmState)?;
     }
 
+    vmm.cpu_template = microvm_state.vm_info.cpu_template.map(Into::into);
+    vmm.custom_cpu_template = microvm_state
+        .vm_info
+        .custom_cpu_template
+        .as_ref()
+        .map(Into::into);
+    vmm.ht_enabled = microvm_state.vm_info.ht_enabled;
+    vmm.mem_backend = microvm_state.vm_info.mem_backend.into();
+    vmm.boot_measurements = microvm_state.vm_info.boot_measurements.clone();
+    mmds::MMDS
+        .lock()
+        .expect("Poisoned lock")
+        .set_version(microvm_state.vm_info.mmds_version.into())
+        .map_err(MicrovmStateError::RestoreMmdsVersion)
+        .map_err(RestoreMicrovmState)?;
+
     // Restore kvm vm state.
     #[cfg(target_arch = "x86_64")]
     vmm.vm
@@ -436,6 +572,14 @@ pub fn build_microvm_from_snapshot(
     // Restore vcpus kvm state.
     vmm.restore_vcpu_states(microvm_state.vcpu_states)
         .map_err(RestoreMicrovmState)?;
+    // Snapshots which don't record the online vcpu count have all their vcpus online.
+    vmm.online_vcpu_count = match microvm_state.vm_info.online_vcpu_count {
+        Some(online_vcpu_count) if online_vcpu_count == 0 || online_vcpu_count > vcpu_count => {
+            return Err(RestoreMicrovmState(MicrovmStateError::InvalidInput));
+        }
+        Some(online_vcpu_count) => online_vcpu_count,
+        None => vcpu_count,
+    };
 
     let vmm = Arc::new(Mutex::new(vmm));
     event_manager
@@ -455,30 +599,87 @@ pub fn build_microvm_from_snapshot(
 pub fn create_guest_memory(
     mem_size_mib: usize,
     track_dirty_pages: bool,
+    mem_backend: MemoryBackend,
 ) -> std::result::Result<GuestMemoryMmap, StartMicrovmError> {
     let mem_size = mem_size_mib << 20;
     let arch_mem_regions = arch::arch_memory_regions(mem_size);
 
-    Ok(
-        GuestMemoryMmap::from_ranges_guarded(&arch_mem_regions, track_dirty_pages)
-            .map_err(StartMicrovmError::GuestMemoryMmap)?,
-    )
+    create_guest_memory_from_ranges(&arch_mem_regions, track_dirty_pages, mem_backend)
+}
+
+/// Creates GuestMemory made of the `ranges` regions, backed by `mem_backend` memory.
+///
+/// Shared memory backends carve all the regions out of a single memory file, one
+/// after the other, which matches the layout of the memory file of a full snapshot.
+pub(crate) fn create_guest_memory_from_ranges(
+    ranges: &[(GuestAddress, usize)],
+    track_dirty_pages: bool,
+    mem_backend: MemoryBackend,
+) -> std::result::Result<GuestMemoryMmap, StartMicrovmError> {
+    use self::StartMicrovmError::{GuestMemoryFile, GuestMemoryMmap as GuestMemoryMmapError};
+
+    if !mem_backend.is_shared() {
+        return GuestMemoryMmap::from_ranges_guarded(ranges, track_dirty_pages)
+            .map_err(GuestMemoryMmapError);
+    }
+
+    let mem_size: usize = ranges.iter().map(|(_, size)| size).sum();
+    let mem_file = Arc::new(create_memfd(mem_size, mem_backend).map_err(GuestMemoryFile)?);
+    let mut offset = 0;
+    let file_ranges: Vec<(GuestAddress, usize, Option<FileOffset>)> = ranges
+        .iter()
+        .map(|&(guest_address, size)| {
+            let file_offset = FileOffset::from_arc(mem_file.clone(), offset);
+            offset += size as u64;
+            (guest_address, size, Some(file_offset))
+        })
+        .collect();
+
+    GuestMemoryMmap::from_ranges_with_files_guarded(file_ranges, track_dirty_pages)
+        .map_err(GuestMemoryMmapError)
+}
+
+// The huge page size is encoded in the `memfd_create` flags as its base 2 logarithm,
+// starting at this bit.
+const MFD_HUGE_SHIFT: u32 = 26;
+
+fn create_memfd(size: usize, mem_backend: MemoryBackend) -> io::Result<File> {
+    let mut flags = libc::MFD_CLOEXEC;
+    if let Some(huge_page_size) = mem_backend.huge_page_size() {
+        flags |= libc::MFD_HUGETLB | (huge_page_size.trailing_zeros() << MFD_HUGE_SHIFT);
+    }
+
+    // Safe because the name is a valid NUL-terminated string and we check the return value.
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_create, b"guest_mem\0".as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safe because the file descriptor was just created and is owned by nobody else.
+    let mem_file = unsafe { File::from_raw_fd(fd as RawFd) };
+    mem_file.set_len(size as u64)?;
+
+    Ok(mem_file)
 }
 
 fn load_kernel(
     boot_config: &BootConfig,
     guest_memory: &GuestMemoryMmap,
-) -> std::result::Result<GuestAddress, StartMicrovmError> {
+) -> std::result::Result<LoadedKernel, StartMicrovmError> {
     let mut kernel_file = boot_config
         .kernel_file
         .try_clone()
         .map_err(|e| StartMicrovmError::Internal(Error::KernelFile(e)))?;
 
-    let entry_addr =
-        kernel::loader::load_kernel(guest_memory, &mut kernel_file, arch::get_kernel_start())
-            .map_err(StartMicrovmError::KernelLoader)?;
-
-    Ok(entry_addr)
+    let kernel_start = arch::get_kernel_start();
+    match boot_config.bundle.as_ref() {
+        Some(bundle) => kernel::loader::load_kernel(
+            guest_memory,
+            &mut SectionReader::new(kernel_file, bundle.kernel),
+            kernel_start,
+        ),
+        None => kernel::loader::load_kernel(guest_memory, &mut kernel_file, kernel_start),
+    }
+    .map_err(StartMicrovmError::KernelLoader)
 }
 
 fn load_initrd_from_config(
@@ -487,6 +688,14 @@ fn load_initrd_from_config(
 ) -> std::result::Result<Option<InitrdConfig>, StartMicrovmError> {
     use self::StartMicrovmError::InitrdRead;
 
+    if let Some(initrd) = boot_cfg.bundle.as_ref().and_then(|bundle| bundle.initrd) {
+        let kernel_file = boot_cfg.kernel_file.try_clone().map_err(InitrdRead)?;
+        return Ok(Some(load_initrd(
+            vm_memory,
+            &mut SectionReader::new(kernel_file, initrd),
+        )?));
+    }
+
     Ok(match &boot_cfg.initrd_file {
         Some(f) => Some(load_initrd(
             vm_memory,
@@ -669,18 +878,28 @@ pub fn configure_system_for_boot(
     vmm: &Vmm,
     vcpus: &mut [Vcpu],
     vcpu_config: VcpuConfig,
-    entry_addr: GuestAddress,
+    kernel: &LoadedKernel,
     initrd: &Option<InitrdConfig>,
+    event_log: &Option<EventLogConfig>,
     boot_cmdline: KernelCmdline,
 ) -> std::result::Result<(), StartMicrovmError> {
     use self::StartMicrovmError::*;
     #[cfg(target_arch = "x86_64")]
     {
+        use arch::x86_64::BootProtocol;
+        use kernel::loader::KernelFormat;
+
+        let (boot_protocol, setup_header) = match kernel.format {
+            KernelFormat::Elf => (BootProtocol::Linux, None),
+            KernelFormat::PvhElf => (BootProtocol::Pvh, None),
+            KernelFormat::BzImage(setup_header) => (BootProtocol::Linux, Some(setup_header.0)),
+        };
         for vcpu in vcpus.iter_mut() {
             vcpu.kvm_vcpu
                 .configure(
                     vmm.guest_memory(),
-                    entry_addr,
+                    kernel.entry_addr,
+                    boot_protocol,
                     &vcpu_config,
                     vmm.vm.supported_cpuid().clone(),
                 )
@@ -701,7 +920,10 @@ pub fn configure_system_for_boot(
             vm_memory::GuestAddress(arch::x86_64::layout::CMDLINE_START),
             boot_cmdline.len() + 1,
             initrd,
+            event_log,
             vcpus.len() as u8,
+            boot_protocol,
+            setup_header,
         )
         .map_err(ConfigureSystem)?;
     }
@@ -709,7 +931,7 @@ pub fn configure_system_for_boot(
     {
         for vcpu in vcpus.iter_mut() {
             vcpu.kvm_vcpu
-                .configure(vmm.guest_memory(), entry_addr)
+                .configure(vmm.guest_memory(), kernel.entry_addr, &vcpu_config)
                 .map_err(Error::VcpuConfigure)
                 .map_err(Internal)?;
         }
@@ -725,6 +947,7 @@ pub fn configure_system_for_boot(
             vmm.mmio_device_manager.get_device_info(),
             vmm.vm.get_irqchip(),
             initrd,
+            event_log,
         )
         .map_err(ConfigureSystem)?;
     }
@@ -832,6 +1055,24 @@ fn attach_balloon_device(
     attach_virtio_device(event_manager, vmm, id, balloon.clone(), cmdline)
 }
 
+fn attach_tpm_device(
+    vmm: &mut Vmm,
+    _cmdline: &mut KernelCmdline,
+    tpm_config: &TpmConfig,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    let emulator = Emulator::new(&tpm_config.socket).map_err(CreateTpmDevice)?;
+    let tpm = Tpm::new(emulator).map_err(CreateTpmDevice)?;
+    vmm.mmio_device_manager
+        .register_mmio_tpm(Arc::new(Mutex::new(tpm)), None)
+        .map_err(RegisterMmioDevice)?;
+    #[cfg(target_arch = "x86_64")]
+    MMIODeviceManager::add_tpm_to_cmdline(_cmdline).map_err(RegisterMmioDevice)?;
+
+    Ok(())
+}
+
 // Adds `O_NONBLOCK` to the stdout flags.
 pub(crate) fn set_stdout_nonblocking() {
     let flags = unsafe { libc::fcntl(libc::STDOUT_FILENO, libc::F_GETFL, 0) };
@@ -853,9 +1094,11 @@ pub mod tests {
     use crate::vmm_config::boot_source::DEFAULT_KERNEL_CMDLINE;
     use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, CacheType};
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
+    use crate::vmm_config::tpm::TpmConfig;
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::vsock::{VsockBuilder, VsockDeviceConfig};
     use arch::DeviceType;
+    use devices::tpm::test_utils::StandInEmulator;
     use devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_VSOCK};
     use kernel::cmdline::Cmdline;
     use polly::event_manager::EventManager;
@@ -909,7 +1152,7 @@ pub mod tests {
     }
 
     pub(crate) fn default_vmm() -> Vmm {
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory = create_guest_memory(128, false, MemoryBackend::Anonymous).unwrap();
 
         let exit_evt = EventFd::new(libc::EFD_NONBLOCK)
             .map_err(Error::EventFd)
@@ -935,8 +1178,16 @@ pub mod tests {
             events_observer: Some(Box::new(SerialStdin::get())),
             guest_memory,
             vcpus_handles: Vec::new(),
+            online_vcpu_count: 0,
+            vcpus_paused: true,
             exit_evt,
             vm,
+            cpu_template: None,
+            custom_cpu_template: None,
+            ht_enabled: false,
+            mem_backend: MemoryBackend::Anonymous,
+            background_snapshot: None,
+            boot_measurements: None,
             mmio_device_manager,
             #[cfg(target_arch = "x86_64")]
             pio_device_manager,
@@ -1092,25 +1343,84 @@ pub mod tests {
 
     #[test]
     fn test_create_guest_memory() {
+        use vm_memory::{GuestMemory, GuestMemoryRegion};
+
         let mem_size = 4096 * 2;
 
         // Case 1: create guest memory without dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, false).unwrap();
+            let guest_memory =
+                create_guest_memory(mem_size, false, MemoryBackend::Anonymous).unwrap();
             assert!(!guest_memory.is_dirty_tracking_enabled());
         }
 
         // Case 2: create guest memory with dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, true).unwrap();
+            let guest_memory =
+                create_guest_memory(mem_size, true, MemoryBackend::Anonymous).unwrap();
             assert!(guest_memory.is_dirty_tracking_enabled());
         }
+
+        // Case 3: create guest memory backed by a shared memory file
+        {
+            let guest_memory =
+                create_guest_memory(mem_size, true, MemoryBackend::SharedMemfd).unwrap();
+            assert!(guest_memory.is_dirty_tracking_enabled());
+            let _: std::result::Result<(), ()> = guest_memory.with_regions(|_, region| {
+                assert!(region.file_offset().is_some());
+                assert_ne!(region.flags() & libc::MAP_SHARED, 0);
+                Ok(())
+            });
+        }
+    }
+
+    #[test]
+    fn test_create_guest_memory_from_ranges() {
+        use vm_memory::{Bytes, GuestMemory, GuestMemoryRegion};
+
+        let page_size = 0x1000;
+        let ranges = [
+            (GuestAddress(0), page_size * 2),
+            (GuestAddress(page_size as u64 * 3), page_size),
+        ];
+        let guest_memory =
+            create_guest_memory_from_ranges(&ranges, false, MemoryBackend::SharedMemfd).unwrap();
+
+        // The regions are laid out back to back in a single memory file.
+        let mut offsets = Vec::new();
+        let _: std::result::Result<(), ()> = guest_memory.with_regions(|_, region| {
+            let file_offset = region.file_offset().unwrap();
+            assert_eq!(
+                file_offset.file().metadata().unwrap().len(),
+                page_size as u64 * 3
+            );
+            offsets.push(file_offset.start());
+            Ok(())
+        });
+        assert_eq!(offsets, vec![0, page_size as u64 * 2]);
+
+        // Writes through the guest memory are visible in the memory file.
+        guest_memory
+            .write_obj(0xaau8, GuestAddress(page_size as u64 * 3))
+            .unwrap();
+        let mut file = guest_memory
+            .find_region(GuestAddress(0))
+            .unwrap()
+            .file_offset()
+            .unwrap()
+            .file()
+            .try_clone()
+            .unwrap();
+        file.seek(SeekFrom::Start(page_size as u64 * 2)).unwrap();
+        let mut byte = [0u8];
+        file.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], 0xaa);
     }
 
     #[test]
     fn test_create_vcpus() {
         let vcpu_count = 2;
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory = create_guest_memory(128, false, MemoryBackend::Anonymous).unwrap();
 
         #[allow(unused_mut)]
         let mut vm = setup_kvm_vm(&guest_memory, false).unwrap();
@@ -1135,6 +1445,7 @@ pub mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: true,
+            dhcp: None,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1377,6 +1688,46 @@ pub mod tests {
             .contains("virtio_mmio.device=4K@0xd0000000:5"));
     }
 
+    #[test]
+    fn test_attach_tpm_device() {
+        let mut vmm = default_vmm();
+        let stand_in = StandInEmulator::new();
+        let tpm_config = TpmConfig {
+            socket: stand_in.socket_path().to_str().unwrap().to_string(),
+        };
+
+        let mut cmdline = default_kernel_cmdline();
+        attach_tpm_device(&mut vmm, &mut cmdline, &tpm_config).unwrap();
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Tpm, &DeviceType::Tpm.to_string())
+            .is_some());
+        #[cfg(target_arch = "x86_64")]
+        {
+            assert!(cmdline
+                .as_str()
+                .contains("tpm_tis.force=1 tpm_tis.interrupts=0"));
+            assert_eq!(
+                vmm.mmio_device_manager.get_device_info()
+                    [&(DeviceType::Tpm, DeviceType::Tpm.to_string())]
+                    .addr,
+                arch::x86_64::layout::TPM_TIS_START
+            );
+        }
+
+        // The emulator has to be listening on the socket.
+        let mut vmm = default_vmm();
+        let tpm_config = TpmConfig {
+            socket: "/invalid/path".to_string(),
+        };
+        assert!(matches!(
+            attach_tpm_device(&mut vmm, &mut cmdline, &tpm_config),
+            Err(StartMicrovmError::CreateTpmDevice(
+                devices::tpm::Error::Connect(_)
+            ))
+        ));
+    }
+
     #[test]
     fn test_error_messages() {
         use crate::builder::StartMicrovmError::*;
@@ -1391,6 +1742,9 @@ pub mod tests {
         let err = CreateRateLimiter(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = CreateTpmDevice(devices::tpm::Error::MissingCapabilities(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = Internal(Error::Serial(io::Error::from_raw_os_error(0)));
         let _ = format!("{}{:?}", err, err);
 
@@ -1403,6 +1757,12 @@ pub mod tests {
         let err = LoadCommandline(kernel::cmdline::Error::TooLarge);
         let _ = format!("{}{:?}", err, err);
 
+        let err = MeasuredBoot(measured_boot::Error::EventLogTooLarge(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = MeasuredBoot(measured_boot::Error::PvhBoot);
+        let _ = format!("{}{:?}", err, err);
+
         let err = MissingKernelConfig;
         let _ = format!("{}{:?}", err, err);
 
@@ -1419,6 +1779,9 @@ pub mod tests {
             io::Error::from_raw_os_error(0),
         ));
         let _ = format!("{}{:?}", err, err);
+
+        let err = VmmThreadAffinity(utils::errno::Error::new(libc::EINVAL));
+        let _ = format!("{}{:?}", err, err);
     }
 
     #[test]
@@ -28,12 +28,23 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             ),
             // Called for expanding the heap
             allow_syscall(libc::SYS_brk),
+            // Used by `fork()` to spawn the process dumping guest memory for background
+            // snapshots
+            allow_syscall_if(
+                libc::SYS_clone,
+                or![and![Cond::new(
+                    0,
+                    ArgLen::QWORD,
+                    Eq,
+                    super::CLONE_FORK_FLAGS
+                )?],],
+            ),
             // Used for metrics and logging, via the helpers in utils/src/time.rs
             // It's not called on some platforms, because of vdso optimisations. In those cases,
             // musl falls back to the regular syscall.
             allow_syscall(libc::SYS_clock_gettime),
             allow_syscall(libc::SYS_close),
-            // Needed for vsock
+            // Needed for vsock and for streaming snapshots to Unix sockets
             allow_syscall(libc::SYS_connect),
             allow_syscall(libc::SYS_epoll_ctl),
             allow_syscall(libc::SYS_epoll_pwait),
@@ -49,6 +60,10 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                     Cond::new(2, ArgLen::DWORD, Eq, super::FCNTL_FD_CLOEXEC)?,
                 ],],
             ),
+            // Used by musl's `fork()` to spawn the process dumping guest memory for background
+            // snapshots
+            #[cfg(all(target_env = "musl", target_arch = "x86_64"))]
+            allow_syscall(libc::SYS_fork),
             // Used for drive patching & rescanning, for reading the local timezone
             allow_syscall(libc::SYS_fstat),
             // Used for snapshotting
@@ -74,16 +89,15 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall_if(libc::SYS_ioctl, super::create_ioctl_seccomp_rule()?),
             // Used by the block device
             allow_syscall(libc::SYS_lseek),
-            // Triggered by musl for some customer workloads
-            #[cfg(target_env = "musl")]
             allow_syscall_if(
                 libc::SYS_madvise,
-                or![and![Cond::new(
-                    2,
-                    ArgLen::DWORD,
-                    Eq,
-                    libc::MADV_DONTNEED as u64
-                )?],],
+                or![
+                    // Triggered by musl for some customer workloads and used by the
+                    // balloon device on private memory
+                    and![Cond::new(2, ArgLen::DWORD, Eq, libc::MADV_DONTNEED as u64)?],
+                    // Used by the balloon device on shared memory
+                    and![Cond::new(2, ArgLen::DWORD, Eq, libc::MADV_REMOVE as u64)?],
+                ],
             ),
             // Used for re-allocating large memory regions, for example vectors
             allow_syscall(libc::SYS_mremap),
@@ -107,12 +121,37 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_open),
             #[cfg(target_arch = "aarch64")]
             allow_syscall(libc::SYS_openat),
+            // Used to collect the outcome of background snapshots
+            allow_syscall_if(
+                libc::SYS_pipe2,
+                or![and![Cond::new(
+                    1,
+                    ArgLen::DWORD,
+                    Eq,
+                    libc::O_CLOEXEC as u64
+                )?],],
+            ),
             allow_syscall(libc::SYS_read),
             // Used by the API thread and vsock
             allow_syscall(libc::SYS_recvfrom),
             // SYS_rt_sigreturn is needed in case a fault does occur, so that the signal handler
             // can return. Otherwise we get stuck in a fault loop.
             allow_syscall(libc::SYS_rt_sigreturn),
+            // Used by the API thread to pin itself on its host CPUs, once the microVM is
+            // started. A pid of 0 stands for the calling thread.
+            allow_syscall_if(
+                libc::SYS_sched_setaffinity,
+                or![and![Cond::new(0, ArgLen::DWORD, Eq, 0u64)?],],
+            ),
+            // Used by the GDB server thread to poll the debugger connection while the guest runs
+            #[cfg(feature = "gdb")]
+            allow_syscall_if(
+                libc::SYS_setsockopt,
+                or![and![
+                    Cond::new(1, ArgLen::DWORD, Eq, libc::SOL_SOCKET as u64)?,
+                    Cond::new(2, ArgLen::DWORD, Eq, libc::SO_RCVTIMEO as u64)?
+                ],],
+            ),
             // Used by the API thread and vsock
             allow_syscall_if(
                 libc::SYS_socket,
@@ -159,6 +198,8 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                 or![and![Cond::new(1, ArgLen::DWORD, Eq, 0u64)?],],
             ),
             allow_syscall(libc::SYS_fsync),
+            // Used to reap the process dumping guest memory for background snapshots
+            allow_syscall(libc::SYS_wait4),
             allow_syscall(libc::SYS_write),
         ]
         .into_iter()
@@ -13,6 +13,14 @@ mod filters;
 pub use self::filters::default_filter;
 pub use self::filters::get_seccomp_filter;
 
+// See include/uapi/linux/sched.h in the kernel code. These are the flags with which `fork()`
+// calls `clone`, on the libc implementations and architectures that don't use `SYS_fork`.
+#[cfg(target_env = "gnu")]
+const CLONE_FORK_FLAGS: u64 =
+    (libc::CLONE_CHILD_SETTID | libc::CLONE_CHILD_CLEARTID | libc::SIGCHLD) as u64;
+#[cfg(target_env = "musl")]
+const CLONE_FORK_FLAGS: u64 = libc::SIGCHLD as u64;
+
 // See include/uapi/asm-generic/fcntl.h in the kernel code.
 const FCNTL_FD_CLOEXEC: u64 = 1;
 const FCNTL_F_SETFD: u64 = 2;
@@ -71,6 +79,10 @@ mod arch_specific_constants {
     pub const KVM_SET_XSAVE: u64 = 0x5000_aea5;
     pub const KVM_GET_XCRS: u64 = 0x8188_aea6;
     pub const KVM_SET_XCRS: u64 = 0x4188_aea7;
+    #[cfg(feature = "gdb")]
+    pub const KVM_SET_GUEST_DEBUG: u64 = 0x4048_ae9b;
+    #[cfg(feature = "gdb")]
+    pub const KVM_TRANSLATE: u64 = 0xc018_ae85;
 }
 
 // Use this mod to define ioctl params that are architecture specific.
@@ -120,6 +132,17 @@ fn create_arch_specific_ioctl_conditions() -> Result<Vec<SeccompRule>, Error> {
     ]);
 }
 
+// Issued by the vCPU threads on behalf of the GDB stub.
+#[cfg(feature = "gdb")]
+fn create_gdb_ioctl_conditions() -> Result<Vec<SeccompRule>, Error> {
+    use arch_specific_constants::*;
+
+    Ok(or![
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_GUEST_DEBUG)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_TRANSLATE)?],
+    ])
+}
+
 fn create_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
     let mut rule = or![
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_RUN)?],
@@ -143,6 +166,8 @@ fn create_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
     ];
 
     rule.append(&mut create_arch_specific_ioctl_conditions()?);
+    #[cfg(feature = "gdb")]
+    rule.append(&mut create_gdb_ioctl_conditions()?);
 
     Ok(rule)
 }
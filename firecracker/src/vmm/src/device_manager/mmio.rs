@@ -13,6 +13,7 @@ use std::{fmt, io};
 use arch::aarch64::DeviceInfoForFDT;
 use arch::DeviceType;
 use devices::pseudo::BootTimer;
+use devices::tpm::Tpm;
 use devices::virtio::{
     Balloon, Block, MmioTransport, Net, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_NET,
     TYPE_VSOCK,
@@ -306,6 +307,58 @@ impl MMIODeviceManager {
         self.register_mmio_device(identifier, slot, Arc::new(Mutex::new(device)))
     }
 
+    /// Register a TPM device at the specified MMIO address if given as parameter, otherwise at
+    /// the address where the guest kernel expects it on x86_64 and at a new MMIO slot on aarch64.
+    pub fn register_mmio_tpm(
+        &mut self,
+        tpm: Arc<Mutex<Tpm>>,
+        dev_info_opt: Option<MMIODeviceInfo>,
+    ) -> Result<()> {
+        // The TPM has no interrupt, its driver polls the status register.
+        let slot = match dev_info_opt {
+            Some(slot) => slot,
+            #[cfg(target_arch = "x86_64")]
+            None => MMIODeviceInfo {
+                addr: arch::x86_64::layout::TPM_TIS_START,
+                len: MMIO_LEN,
+                irqs: Vec::new(),
+            },
+            #[cfg(target_arch = "aarch64")]
+            None => self.allocate_new_slot(0)?,
+        };
+
+        let identifier = (DeviceType::Tpm, DeviceType::Tpm.to_string());
+        self.register_mmio_device(identifier, slot, tpm)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Append the parameters probing the registered TPM device to the kernel cmdline.
+    pub fn add_tpm_to_cmdline(cmdline: &mut kernel_cmdline::Cmdline) -> Result<()> {
+        // Without ACPI, the TIS driver has to be forced to probe the device at its default
+        // address, and not to look for interrupts.
+        cmdline
+            .insert("tpm_tis.force", "1")
+            .map_err(Error::Cmdline)?;
+        cmdline
+            .insert("tpm_tis.interrupts", "0")
+            .map_err(Error::Cmdline)
+    }
+
+    /// Fetches the state of the emulator backing the TPM device, if any, so that it gets saved
+    /// along with the device.
+    pub fn fetch_tpm_state(&self) -> std::result::Result<(), devices::tpm::Error> {
+        if let Some(busdev) = self.get_device(DeviceType::Tpm, &DeviceType::Tpm.to_string()) {
+            busdev
+                .lock()
+                .expect("Poisoned lock")
+                .as_mut_any()
+                .downcast_mut::<Tpm>()
+                .expect("Unexpected BusDevice type")
+                .fetch_emulator_state()?;
+        }
+        Ok(())
+    }
+
     /// Gets the information of the devices registered up to some point in time.
     pub fn get_device_info(&self) -> &HashMap<(DeviceType, String), MMIODeviceInfo> {
         &self.id_to_dev_info
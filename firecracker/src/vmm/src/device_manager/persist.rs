@@ -11,6 +11,8 @@ use super::mmio::*;
 
 #[cfg(target_arch = "aarch64")]
 use arch::DeviceType;
+use devices::tpm::persist::TpmState;
+use devices::tpm::{Error as TpmError, Tpm};
 use devices::virtio::balloon::persist::{BalloonConstructorArgs, BalloonState};
 use devices::virtio::balloon::{Balloon, Error as BalloonError};
 use devices::virtio::block::persist::{BlockConstructorArgs, BlockState};
@@ -41,6 +43,7 @@ pub enum Error {
     #[cfg(target_arch = "aarch64")]
     Legacy(crate::Error),
     Net(NetError),
+    Tpm(TpmError),
     Vsock(VsockError),
     VsockUnixBackend(VsockUnixBackendError),
 }
@@ -101,6 +104,16 @@ pub struct ConnectedVsockState {
     pub mmio_slot: MMIODeviceInfo,
 }
 
+#[derive(Clone, Versionize)]
+/// Holds the state of a TPM device connected to the MMIO space.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct ConnectedTpmState {
+    /// Device state.
+    pub device_state: TpmState,
+    /// VmmResources.
+    pub mmio_slot: MMIODeviceInfo,
+}
+
 #[cfg(target_arch = "aarch64")]
 #[derive(Clone, Versionize)]
 /// Holds the state of a legacy device connected to the MMIO space.
@@ -127,6 +140,9 @@ pub struct DeviceStates {
     /// Balloon device state.
     #[version(start = 2, ser_fn = "balloon_serialize")]
     pub balloon_device: Option<ConnectedBalloonState>,
+    /// TPM device state.
+    #[version(start = 3, ser_fn = "tpm_serialize")]
+    pub tpm_device: Option<ConnectedTpmState>,
 }
 
 impl DeviceStates {
@@ -139,6 +155,16 @@ impl DeviceStates {
 
         Ok(())
     }
+
+    fn tpm_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 3 && self.tpm_device.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the TPM device.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 pub struct MMIODevManagerConstructorArgs<'a> {
@@ -158,6 +184,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             block_devices: Vec::new(),
             net_devices: Vec::new(),
             vsock_device: None,
+            tpm_device: None,
             #[cfg(target_arch = "aarch64")]
             legacy_devices: Vec::new(),
         };
@@ -167,6 +194,19 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                 return Ok(());
             }
 
+            if *devtype == arch::DeviceType::Tpm {
+                let locked_bus_dev = bus_dev.lock().expect("Poisoned lock");
+                let tpm = locked_bus_dev
+                    .as_any()
+                    .downcast_ref::<Tpm>()
+                    .expect("Unexpected BusDevice type");
+                states.tpm_device = Some(ConnectedTpmState {
+                    device_state: tpm.save(),
+                    mmio_slot: devinfo.clone(),
+                });
+                return Ok(());
+            }
+
             #[cfg(target_arch = "aarch64")]
             {
                 if *devtype == DeviceType::Serial || *devtype == DeviceType::RTC {
@@ -390,6 +430,16 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             )?;
         }
 
+        if let Some(tpm_state) = &state.tpm_device {
+            dev_manager
+                .slot_sanity_check(&tpm_state.mmio_slot)
+                .map_err(Error::DeviceManager)?;
+            let tpm = Tpm::restore((), &tpm_state.device_state).map_err(Error::Tpm)?;
+            dev_manager
+                .register_mmio_tpm(Arc::new(Mutex::new(tpm)), Some(tpm_state.mmio_slot.clone()))
+                .map_err(Error::DeviceManager)?;
+        }
+
         Ok(dev_manager)
     }
 }
@@ -401,6 +451,8 @@ mod tests {
     use crate::vmm_config::balloon::BalloonDeviceConfig;
     use crate::vmm_config::net::NetworkInterfaceConfig;
     use crate::vmm_config::vsock::VsockDeviceConfig;
+    use devices::tpm::test_utils::StandInEmulator;
+    use devices::tpm::Emulator;
     use devices::virtio::block::CacheType;
     use polly::event_manager::EventManager;
     use utils::tempfile::TempFile;
@@ -473,12 +525,30 @@ mod tests {
         }
     }
 
+    impl PartialEq for ConnectedTpmState {
+        fn eq(&self, other: &ConnectedTpmState) -> bool {
+            // Actual device state equality is checked by the device's tests.
+            self.mmio_slot == other.mmio_slot
+        }
+    }
+
+    impl std::fmt::Debug for ConnectedTpmState {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "ConnectedTpmDevice {{ mmio_slot: {:?} }}",
+                self.mmio_slot
+            )
+        }
+    }
+
     impl PartialEq for DeviceStates {
         fn eq(&self, other: &DeviceStates) -> bool {
             self.balloon_device == other.balloon_device
                 && self.block_devices == other.block_devices
                 && self.net_devices == other.net_devices
                 && self.vsock_device == other.vsock_device
+                && self.tpm_device == other.tpm_device
         }
     }
 
@@ -533,6 +603,7 @@ mod tests {
         let _block_files;
         let mut tmp_sock_file = TempFile::new().unwrap();
         tmp_sock_file.remove().unwrap();
+        let tpm_stand_in = StandInEmulator::new();
         // Set up a vmm with one of each device, and get the serialized DeviceStates.
         let original_mmio_device_manager = {
             let mut event_manager = EventManager::new().expect("Unable to create EventManager");
@@ -565,6 +636,7 @@ mod tests {
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
                 allow_mmds_requests: true,
+                dhcp: None,
             };
             insert_net_device(
                 &mut vmm,
@@ -580,6 +652,12 @@ mod tests {
                 uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
             };
             insert_vsock_device(&mut vmm, &mut cmdline, &mut event_manager, vsock_config);
+            // Add a TPM device.
+            let tpm = Tpm::new(Emulator::new(tpm_stand_in.socket_path()).unwrap()).unwrap();
+            vmm.mmio_device_manager
+                .register_mmio_tpm(Arc::new(Mutex::new(tpm)), None)
+                .unwrap();
+            vmm.mmio_device_manager.fetch_tpm_state().unwrap();
 
             assert_eq!(
                 vmm.mmio_device_manager
@@ -593,9 +671,21 @@ mod tests {
             version_map
                 .new_version()
                 .set_type_version(DeviceStates::type_id(), 2);
+            assert_eq!(
+                vmm.mmio_device_manager
+                    .save()
+                    .serialize(&mut buf.as_mut_slice(), &version_map, 2),
+                Err(VersionizeError::Semantic(
+                    "Target version does not implement the TPM device.".to_string()
+                ))
+            );
+
+            version_map
+                .new_version()
+                .set_type_version(DeviceStates::type_id(), 3);
             vmm.mmio_device_manager
                 .save()
-                .serialize(&mut buf.as_mut_slice(), &version_map, 2)
+                .serialize(&mut buf.as_mut_slice(), &version_map, 3)
                 .unwrap();
 
             // We only want to keep the device map from the original MmioDeviceManager.
@@ -606,7 +696,8 @@ mod tests {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
         let vmm = default_vmm();
         let device_states: DeviceStates =
-            DeviceStates::deserialize(&mut buf.as_slice(), &version_map, 2).unwrap();
+            DeviceStates::deserialize(&mut buf.as_slice(), &version_map, 3).unwrap();
+        assert!(device_states.tpm_device.is_some());
         let restore_args = MMIODevManagerConstructorArgs {
             mem: vmm.guest_memory().clone(),
             vm: vmm.vm.fd(),
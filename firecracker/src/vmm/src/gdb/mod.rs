@@ -0,0 +1,393 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! GDB remote serial protocol server, for debugging guest kernels.
+//!
+//! The server listens on a Unix socket and serves one debugger at a time, from its own thread.
+//! It reaches the vCPUs through `VcpuEvent::Debug` requests, which the vCPU threads answer on a
+//! dedicated channel.
+
+#[cfg(not(target_arch = "x86_64"))]
+compile_error!("The gdb feature is only supported on x86_64");
+
+mod packet;
+mod stub;
+
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use self::packet::{encode, Incoming, Parser};
+use self::stub::{GdbStub, Registers, Reply, Target};
+use crate::vstate::vcpu::{DebugHandle, DebugMessage, DebugRequest, DebugResponse};
+use logger::{error, info, warn};
+use seccomp::{BpfProgram, SeccompFilter};
+use vm_memory::{Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
+
+/// How long to wait for a vCPU to answer a request.
+const VCPU_RESPONSE_TIMEOUT: Duration = Duration::from_millis(1000);
+/// How often to check for stopped vCPUs while the guest runs.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Errors associated with the GDB server.
+#[derive(Debug)]
+pub enum Error {
+    /// Cannot bind to the socket.
+    Bind(io::Error),
+    /// Cannot communicate with the debugger.
+    Connection(io::Error),
+    /// Cannot access guest memory.
+    GuestMemory(GuestMemoryError),
+    /// Cannot spawn the server thread.
+    Spawn(io::Error),
+    /// A vCPU answered a request with an unexpected response.
+    UnexpectedResponse(u8),
+    /// The guest virtual address is not mapped.
+    UnmappedAddress(u64),
+    /// A vCPU failed to carry out a request.
+    Vcpu(crate::vstate::vcpu::Error),
+    /// A vCPU did not answer a request in time.
+    VcpuTimeout(u8),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+
+        match self {
+            Bind(e) => write!(f, "Cannot bind to the GDB socket: {}", e),
+            Connection(e) => write!(f, "Cannot communicate with the debugger: {}", e),
+            GuestMemory(e) => write!(f, "Cannot access guest memory: {}", e),
+            Spawn(e) => write!(f, "Cannot spawn the GDB server thread: {}", e),
+            UnexpectedResponse(index) => {
+                write!(f, "vCPU {} sent an unexpected debug response", index)
+            }
+            UnmappedAddress(addr) => write!(f, "Guest address {:#x} is not mapped", addr),
+            Vcpu(e) => write!(f, "vCPU debug request failed: {}", e),
+            VcpuTimeout(index) => write!(f, "vCPU {} did not answer the debug request", index),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Reaches the vCPUs and the memory of the guest for the stub.
+struct VcpuTarget {
+    debug_handles: Vec<DebugHandle>,
+    debug_receiver: Receiver<DebugMessage>,
+    // vCPUs which stopped while the stub waited for another answer.
+    pending_stops: VecDeque<u8>,
+    // Sequence number of the last request, which tells its answer apart from the late answers
+    // to requests which timed out.
+    last_seq: u64,
+    guest_memory: GuestMemoryMmap,
+}
+
+impl VcpuTarget {
+    // Sends `request` to the vCPU and waits for its answer.
+    fn request(&mut self, vcpu: u8, request: DebugRequest) -> Result<DebugResponse> {
+        self.last_seq = self.last_seq.wrapping_add(1);
+        let seq = self.last_seq;
+        self.debug_handles[vcpu as usize]
+            .send_request(seq, request)
+            .map_err(Error::Vcpu)?;
+        loop {
+            match self.debug_receiver.recv_timeout(VCPU_RESPONSE_TIMEOUT) {
+                Ok((index, _, DebugResponse::Stopped)) => self.pending_stops.push_back(index),
+                Ok((index, Some(response_seq), response))
+                    if index == vcpu && response_seq == seq =>
+                {
+                    return match response {
+                        DebugResponse::Error(e) => Err(Error::Vcpu(e)),
+                        response => Ok(response),
+                    };
+                }
+                Ok((index, _, _)) => warn!("{}", Error::UnexpectedResponse(index)),
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::VcpuTimeout(vcpu))
+                }
+            }
+        }
+    }
+
+    fn read_core_registers(
+        &mut self,
+        vcpu: u8,
+    ) -> Result<(kvm_bindings::kvm_regs, kvm_bindings::kvm_sregs)> {
+        match self.request(vcpu, DebugRequest::ReadRegs)? {
+            DebugResponse::Regs(regs, sregs) => Ok((*regs, *sregs)),
+            _ => Err(Error::UnexpectedResponse(vcpu)),
+        }
+    }
+
+    // Calls `access` on the guest physical address of every page in the range.
+    fn for_each_page<F>(&mut self, vcpu: u8, addr: u64, len: usize, mut access: F) -> Result<()>
+    where
+        F: FnMut(&GuestMemoryMmap, GuestAddress, std::ops::Range<usize>) -> Result<()>,
+    {
+        let mut offset = 0;
+        while offset < len {
+            let gva = addr.wrapping_add(offset as u64);
+            let chunk = std::cmp::min(len - offset, (PAGE_SIZE - gva % PAGE_SIZE) as usize);
+            let gpa = match self.request(vcpu, DebugRequest::TranslateGva(gva))? {
+                DebugResponse::Translated(Some(gpa)) => gpa,
+                _ => return Err(Error::UnmappedAddress(gva)),
+            };
+            access(
+                &self.guest_memory,
+                GuestAddress(gpa),
+                offset..offset + chunk,
+            )?;
+            offset += chunk;
+        }
+        Ok(())
+    }
+}
+
+impl Target for VcpuTarget {
+    type Error = Error;
+
+    fn vcpu_count(&self) -> u8 {
+        self.debug_handles.len() as u8
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        for vcpu in 0..self.vcpu_count() {
+            self.request(vcpu, DebugRequest::Pause)?;
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self, single_step: Option<u8>, sw_breakpoints: bool) -> Result<()> {
+        self.pending_stops.clear();
+        let vcpus = match single_step {
+            Some(vcpu) => vcpu..vcpu + 1,
+            None => 0..self.vcpu_count(),
+        };
+        for vcpu in vcpus {
+            self.request(
+                vcpu,
+                DebugRequest::Resume {
+                    single_step: single_step.is_some(),
+                    sw_breakpoints,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn poll_stop(&mut self) -> Option<u8> {
+        if let Some(vcpu) = self.pending_stops.pop_front() {
+            return Some(vcpu);
+        }
+        while let Ok((index, _, response)) = self.debug_receiver.try_recv() {
+            if let DebugResponse::Stopped = response {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    fn read_registers(&mut self, vcpu: u8) -> Result<Registers> {
+        let (regs, sregs) = self.read_core_registers(vcpu)?;
+        Ok(Registers {
+            gprs: [
+                regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp,
+                regs.r8, regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15,
+            ],
+            rip: regs.rip,
+            eflags: regs.rflags as u32,
+            segments: [
+                u32::from(sregs.cs.selector),
+                u32::from(sregs.ss.selector),
+                u32::from(sregs.ds.selector),
+                u32::from(sregs.es.selector),
+                u32::from(sregs.fs.selector),
+                u32::from(sregs.gs.selector),
+            ],
+        })
+    }
+
+    fn write_registers(&mut self, vcpu: u8, registers: &Registers) -> Result<()> {
+        // Segment selectors cannot be changed without their descriptors, so they are left as is.
+        let (mut regs, _) = self.read_core_registers(vcpu)?;
+        let gprs = &registers.gprs;
+        regs.rax = gprs[0];
+        regs.rbx = gprs[1];
+        regs.rcx = gprs[2];
+        regs.rdx = gprs[3];
+        regs.rsi = gprs[4];
+        regs.rdi = gprs[5];
+        regs.rbp = gprs[6];
+        regs.rsp = gprs[7];
+        regs.r8 = gprs[8];
+        regs.r9 = gprs[9];
+        regs.r10 = gprs[10];
+        regs.r11 = gprs[11];
+        regs.r12 = gprs[12];
+        regs.r13 = gprs[13];
+        regs.r14 = gprs[14];
+        regs.r15 = gprs[15];
+        regs.rip = registers.rip;
+        regs.rflags = (regs.rflags & !0xffff_ffff) | u64::from(registers.eflags);
+        self.request(vcpu, DebugRequest::WriteRegs(regs))
+            .map(|_| ())
+    }
+
+    fn read_memory(&mut self, vcpu: u8, addr: u64, data: &mut [u8]) -> Result<()> {
+        self.for_each_page(vcpu, addr, data.len(), |memory, gpa, range| {
+            memory
+                .read_slice(&mut data[range], gpa)
+                .map_err(Error::GuestMemory)
+        })
+    }
+
+    fn write_memory(&mut self, vcpu: u8, addr: u64, data: &[u8]) -> Result<()> {
+        self.for_each_page(vcpu, addr, data.len(), |memory, gpa, range| {
+            memory
+                .write_slice(&data[range], gpa)
+                .map_err(Error::GuestMemory)
+        })
+    }
+}
+
+/// Starts serving debuggers on the Unix socket at `socket_path`.
+///
+/// # Arguments
+///
+/// * `socket_path` - The path of the socket to create.
+/// * `debug_handles` - The handles of the vCPUs, in index order.
+/// * `debug_receiver` - The channel on which the vCPUs answer the debug requests.
+/// * `guest_memory` - The guest memory.
+/// * `seccomp_filter` - The seccomp filter the server thread loads before serving debuggers.
+pub fn start_server(
+    socket_path: &str,
+    debug_handles: Vec<DebugHandle>,
+    debug_receiver: Receiver<DebugMessage>,
+    guest_memory: GuestMemoryMmap,
+    seccomp_filter: BpfProgram,
+) -> Result<()> {
+    let listener = UnixListener::bind(socket_path).map_err(Error::Bind)?;
+    let mut stub = GdbStub::new(VcpuTarget {
+        debug_handles,
+        debug_receiver,
+        pending_stops: VecDeque::new(),
+        last_seq: 0,
+        guest_memory,
+    });
+
+    thread::Builder::new()
+        .name("fc_gdb".to_string())
+        .spawn(move || {
+            // Load the seccomp filters of the VMM thread before accepting any connection.
+            // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping
+            // filters altogether is the desired behaviour.
+            if let Err(e) = SeccompFilter::apply(seccomp_filter) {
+                panic!(
+                    "Failed to set the requested seccomp filters on the GDB server thread: \
+                     Error: {}",
+                    e
+                );
+            }
+
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("Cannot accept the debugger connection: {}", e);
+                        continue;
+                    }
+                };
+                info!("Debugger attached");
+                if let Err(e) = serve(&mut stub, stream) {
+                    error!("GDB session failed: {}", e);
+                }
+                if let Err(e) = stub.detach() {
+                    error!("Cannot detach the debugger: {}", e);
+                }
+                info!("Debugger detached");
+            }
+        })
+        .map_err(Error::Spawn)?;
+
+    Ok(())
+}
+
+// Handles a debugger connection until it detaches or disconnects.
+fn serve(stub: &mut GdbStub<VcpuTarget>, mut stream: UnixStream) -> Result<()> {
+    stream
+        .set_read_timeout(Some(STOP_POLL_INTERVAL))
+        .map_err(Error::Connection)?;
+    stub.attach()?;
+
+    let mut parser = Parser::default();
+    let mut last_packet = Vec::new();
+    let mut running = false;
+    let mut buf = [0u8; 4096];
+    loop {
+        if running {
+            if let Some(vcpu) = stub.target().poll_stop() {
+                running = false;
+                last_packet = encode(&stub.on_stop(vcpu)?);
+                stream.write_all(&last_packet).map_err(Error::Connection)?;
+            }
+        }
+
+        let count = match stream.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(count) => count,
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(e) => return Err(Error::Connection(e)),
+        };
+
+        for &byte in &buf[..count] {
+            let reply = match parser.push(byte) {
+                Some(Incoming::Packet(packet)) => {
+                    stream.write_all(b"+").map_err(Error::Connection)?;
+                    if running {
+                        // Only interrupts are expected until the guest stops.
+                        continue;
+                    }
+                    match stub.handle_packet(&packet) {
+                        Reply::Packet(reply) => reply,
+                        Reply::Resumed => {
+                            running = true;
+                            continue;
+                        }
+                        Reply::Detach => {
+                            stream
+                                .write_all(&encode(b"OK"))
+                                .map_err(Error::Connection)?;
+                            return Ok(());
+                        }
+                        Reply::Kill => return Ok(()),
+                    }
+                }
+                Some(Incoming::Interrupt) if running => {
+                    running = false;
+                    stub.on_interrupt()?
+                }
+                Some(Incoming::Nack) => {
+                    stream.write_all(&last_packet).map_err(Error::Connection)?;
+                    continue;
+                }
+                Some(Incoming::Invalid) => {
+                    stream.write_all(b"-").map_err(Error::Connection)?;
+                    continue;
+                }
+                Some(Incoming::Interrupt) | Some(Incoming::Ack) | None => continue,
+            };
+            last_packet = encode(&reply);
+            stream.write_all(&last_packet).map_err(Error::Connection)?;
+        }
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Framing of the GDB remote serial protocol.
+//!
+//! Packets are sent as `$<data>#<checksum>`, where the checksum is the modulo 256 sum of the
+//! data bytes, written as two hex digits. The receiver acknowledges every packet with `+`, or
+//! asks for a retransmission with `-`. A lone `0x03` byte interrupts the running target.
+
+use std::fmt::Write;
+
+const PACKET_START: u8 = b'$';
+const CHECKSUM_START: u8 = b'#';
+const ESCAPE: u8 = b'}';
+const ESCAPE_XOR: u8 = 0x20;
+const INTERRUPT: u8 = 0x03;
+const ACK: u8 = b'+';
+const NACK: u8 = b'-';
+
+/// What the debugger sent.
+#[derive(Debug, PartialEq)]
+pub enum Incoming {
+    /// A packet with a valid checksum, with its data unescaped.
+    Packet(Vec<u8>),
+    /// Request to stop the running target.
+    Interrupt,
+    /// The last packet was received.
+    Ack,
+    /// The last packet has to be sent again.
+    Nack,
+    /// A packet with an invalid checksum.
+    Invalid,
+}
+
+enum State {
+    Idle,
+    Data,
+    Checksum(Option<u8>),
+}
+
+/// Splits the byte stream of the debugger into `Incoming` items.
+pub struct Parser {
+    state: State,
+    data: Vec<u8>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser {
+            state: State::Idle,
+            data: Vec::new(),
+        }
+    }
+}
+
+impl Parser {
+    /// Consumes a byte, returning the item it completes, if any.
+    pub fn push(&mut self, byte: u8) -> Option<Incoming> {
+        match self.state {
+            State::Idle => match byte {
+                PACKET_START => {
+                    self.data.clear();
+                    self.state = State::Data;
+                    None
+                }
+                INTERRUPT => Some(Incoming::Interrupt),
+                ACK => Some(Incoming::Ack),
+                NACK => Some(Incoming::Nack),
+                // Anything else between packets is noise.
+                _ => None,
+            },
+            State::Data => {
+                if byte == CHECKSUM_START {
+                    self.state = State::Checksum(None);
+                } else {
+                    self.data.push(byte);
+                }
+                None
+            }
+            State::Checksum(None) => {
+                self.state = State::Checksum(Some(byte));
+                None
+            }
+            State::Checksum(Some(high)) => {
+                self.state = State::Idle;
+                let expected = parse_hex(&[high, byte]);
+                if expected != Some(u64::from(checksum(&self.data))) {
+                    return Some(Incoming::Invalid);
+                }
+                Some(Incoming::Packet(unescape(&self.data)))
+            }
+        }
+    }
+}
+
+/// Computes the checksum of the data of a packet.
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Frames `data` into a packet.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        match byte {
+            PACKET_START | CHECKSUM_START | ESCAPE | b'*' => {
+                escaped.push(ESCAPE);
+                escaped.push(byte ^ ESCAPE_XOR);
+            }
+            _ => escaped.push(byte),
+        }
+    }
+
+    let mut packet = Vec::with_capacity(escaped.len() + 4);
+    packet.push(PACKET_START);
+    packet.extend_from_slice(&escaped);
+    packet.push(CHECKSUM_START);
+    packet.extend_from_slice(format!("{:02x}", checksum(&escaped)).as_bytes());
+    packet
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == ESCAPE {
+            if let Some(&escaped) = bytes.next() {
+                unescaped.push(escaped ^ ESCAPE_XOR);
+            }
+        } else {
+            unescaped.push(byte);
+        }
+    }
+    unescaped
+}
+
+/// Parses a big endian hex number, such as an address.
+pub fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, &digit| {
+        (digit as char)
+            .to_digit(16)
+            .map(|digit| (value << 4) | u64::from(digit))
+    })
+}
+
+/// Decodes a hex string into the bytes it represents.
+pub fn decode_hex(digits: &[u8]) -> Option<Vec<u8>> {
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| parse_hex(pair).map(|byte| byte as u8))
+        .collect()
+}
+
+/// Encodes bytes as a hex string.
+pub fn encode_hex(bytes: &[u8]) -> String {
+    let mut digits = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        // Writing to a String cannot fail.
+        let _ = write!(digits, "{:02x}", byte);
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> Vec<Incoming> {
+        let mut parser = Parser::default();
+        bytes.iter().filter_map(|byte| parser.push(*byte)).collect()
+    }
+
+    #[test]
+    fn test_parser() {
+        assert_eq!(
+            parse(b"+$g#67"),
+            vec![Incoming::Ack, Incoming::Packet(b"g".to_vec())]
+        );
+        assert_eq!(
+            parse(b"$m10,4#00-\x03"),
+            vec![Incoming::Invalid, Incoming::Nack, Incoming::Interrupt]
+        );
+        // Escaped bytes are counted in the checksum as sent.
+        let packet = encode(b"a#b");
+        assert_eq!(packet, b"$a}\x03b#43".to_vec());
+        assert_eq!(parse(&packet), vec![Incoming::Packet(b"a#b".to_vec())]);
+        // Noise between packets is dropped.
+        assert_eq!(parse(b"xx$OK#9a"), vec![Incoming::Packet(b"OK".to_vec())]);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(b""), b"$#00".to_vec());
+        assert_eq!(encode(b"OK"), b"$OK#9a".to_vec());
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(parse_hex(b"ffffffff81000000"), Some(0xffff_ffff_8100_0000));
+        assert_eq!(parse_hex(b"1A"), Some(0x1a));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"1g"), None);
+        assert_eq!(parse_hex(b"10000000000000000"), None);
+
+        assert_eq!(decode_hex(b"00ff10"), Some(vec![0x00, 0xff, 0x10]));
+        assert_eq!(decode_hex(b"0"), None);
+        assert_eq!(decode_hex(b"zz"), None);
+        assert_eq!(encode_hex(&[0x00, 0xff, 0x10]), "00ff10");
+    }
+}
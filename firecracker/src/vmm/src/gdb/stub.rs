@@ -0,0 +1,654 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Handling of the GDB remote serial protocol packets, independent of how the vCPUs are reached.
+//!
+//! The stub works in all-stop mode: whenever a vCPU stops, all the others are paused before the
+//! stop is reported. GDB threads map to vCPUs, thread `n` being the vCPU with index `n - 1`.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use super::packet::{decode_hex, encode_hex, parse_hex};
+use logger::warn;
+
+/// The `int3` instruction.
+const SW_BREAKPOINT_INSN: u8 = 0xcc;
+/// The largest packet GDB may send, in bytes.
+const MAX_PACKET_SIZE: usize = 0x1000;
+/// The largest memory access, so that its hex encoding fits in a packet.
+const MAX_MEMORY_ACCESS: usize = MAX_PACKET_SIZE / 2 - 16;
+
+/// Number of general purpose registers, from `rax` to `r15`.
+pub const GPR_COUNT: usize = 16;
+/// Number of segment selectors, from `cs` to `gs`.
+pub const SEGMENT_COUNT: usize = 6;
+
+/// The registers of a vCPU, as exchanged in the `g` and `G` packets.
+///
+/// The layout follows the amd64 register numbering of GDB. The registers it does not contain are
+/// reported as unavailable.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Registers {
+    /// `rax`, `rbx`, `rcx`, `rdx`, `rsi`, `rdi`, `rbp`, `rsp` and `r8` to `r15`.
+    pub gprs: [u64; GPR_COUNT],
+    /// The instruction pointer.
+    pub rip: u64,
+    /// The flags register.
+    pub eflags: u32,
+    /// `cs`, `ss`, `ds`, `es`, `fs` and `gs`.
+    pub segments: [u32; SEGMENT_COUNT],
+}
+
+const REGISTERS_SIZE: usize = (GPR_COUNT + 1) * 8 + (1 + SEGMENT_COUNT) * 4;
+
+impl Registers {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(REGISTERS_SIZE);
+        for gpr in self.gprs.iter() {
+            bytes.extend_from_slice(&gpr.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.rip.to_le_bytes());
+        bytes.extend_from_slice(&self.eflags.to_le_bytes());
+        for segment in self.segments.iter() {
+            bytes.extend_from_slice(&segment.to_le_bytes());
+        }
+        bytes
+    }
+
+    // GDB sends all the registers it knows of, only the ones we report are used.
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < REGISTERS_SIZE {
+            return None;
+        }
+
+        let mut u64s = bytes.chunks_exact(8).map(|chunk| {
+            u64::from_le_bytes([
+                chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7],
+            ])
+        });
+        let mut registers = Registers::default();
+        for gpr in registers.gprs.iter_mut() {
+            *gpr = u64s.next()?;
+        }
+        registers.rip = u64s.next()?;
+
+        let mut u32s = bytes[(GPR_COUNT + 1) * 8..]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        registers.eflags = u32s.next()?;
+        for segment in registers.segments.iter_mut() {
+            *segment = u32s.next()?;
+        }
+        Some(registers)
+    }
+}
+
+/// The operations the stub carries out on the guest.
+pub trait Target {
+    /// Error reported by the operations.
+    type Error: Display;
+
+    /// Returns the number of vCPUs.
+    fn vcpu_count(&self) -> u8;
+    /// Pauses all the vCPUs.
+    fn pause(&mut self) -> Result<(), Self::Error>;
+    /// Resumes all the vCPUs, or only the vCPU to single-step.
+    fn resume(&mut self, single_step: Option<u8>, sw_breakpoints: bool) -> Result<(), Self::Error>;
+    /// Returns the index of a vCPU that stopped since it was resumed, if any.
+    fn poll_stop(&mut self) -> Option<u8>;
+    /// Reads the registers of a paused vCPU.
+    fn read_registers(&mut self, vcpu: u8) -> Result<Registers, Self::Error>;
+    /// Writes the registers of a paused vCPU.
+    fn write_registers(&mut self, vcpu: u8, registers: &Registers) -> Result<(), Self::Error>;
+    /// Reads guest memory at a virtual address, as seen by a vCPU.
+    fn read_memory(&mut self, vcpu: u8, addr: u64, data: &mut [u8]) -> Result<(), Self::Error>;
+    /// Writes guest memory at a virtual address, as seen by a vCPU.
+    fn write_memory(&mut self, vcpu: u8, addr: u64, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// What to do after handling a packet.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    /// Send the data as a packet.
+    Packet(Vec<u8>),
+    /// The vCPUs are running, the reply is sent once they stop.
+    Resumed,
+    /// Send `OK` and end the session.
+    Detach,
+    /// End the session without replying.
+    Kill,
+}
+
+/// State of a debugging session.
+pub struct GdbStub<T: Target> {
+    target: T,
+    // The vCPU targeted by register and memory accesses.
+    current_vcpu: u8,
+    // The vCPU to single-step, if not the current one.
+    step_vcpu: Option<u8>,
+    // The original bytes at the addresses of the inserted software breakpoints.
+    breakpoints: BTreeMap<u64, u8>,
+}
+
+fn error_reply(code: u8) -> Reply {
+    Reply::Packet(format!("E{:02x}", code).into_bytes())
+}
+
+fn ok_reply() -> Reply {
+    Reply::Packet(b"OK".to_vec())
+}
+
+// Parses `addr,len` and the remaining data after `separator`, if any.
+fn parse_memory_args(args: &[u8], separator: Option<u8>) -> Option<(u64, usize, &[u8])> {
+    let (args, data) = match separator {
+        Some(separator) => {
+            let position = args.iter().position(|&byte| byte == separator)?;
+            (&args[..position], &args[position + 1..])
+        }
+        None => (args, &[][..]),
+    };
+    let comma = args.iter().position(|&byte| byte == b',')?;
+    let addr = parse_hex(&args[..comma])?;
+    let len = parse_hex(&args[comma + 1..])? as usize;
+    Some((addr, len, data))
+}
+
+impl<T: Target> GdbStub<T> {
+    /// Creates a stub operating on `target`.
+    pub fn new(target: T) -> Self {
+        GdbStub {
+            target,
+            current_vcpu: 0,
+            step_vcpu: None,
+            breakpoints: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the target.
+    pub fn target(&mut self) -> &mut T {
+        &mut self.target
+    }
+
+    /// Starts a session, pausing the guest.
+    pub fn attach(&mut self) -> Result<(), T::Error> {
+        self.current_vcpu = 0;
+        self.step_vcpu = None;
+        self.target.pause()
+    }
+
+    /// Ends a session, removing the breakpoints and letting the guest run.
+    pub fn detach(&mut self) -> Result<(), T::Error> {
+        self.target.pause()?;
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+        for (addr, original) in breakpoints {
+            if let Err(e) = self
+                .target
+                .write_memory(self.current_vcpu, addr, &[original])
+            {
+                warn!("Cannot remove breakpoint at {:#x}: {}", addr, e);
+            }
+        }
+        self.target.resume(None, false)
+    }
+
+    /// Pauses the guest after `vcpu` stopped, returning the stop reply.
+    pub fn on_stop(&mut self, vcpu: u8) -> Result<Vec<u8>, T::Error> {
+        self.target.pause()?;
+        self.current_vcpu = vcpu;
+        let rip = self.target.read_registers(vcpu)?.rip;
+        // The instruction pointer of KVM is not advanced past the `int3`, which GDB has to know.
+        let reason = if self.breakpoints.contains_key(&rip) {
+            "swbreak:;"
+        } else {
+            ""
+        };
+        Ok(format!("T05{}thread:{:x};", reason, u32::from(vcpu) + 1).into_bytes())
+    }
+
+    /// Pauses the guest on request of the debugger, returning the stop reply.
+    pub fn on_interrupt(&mut self) -> Result<Vec<u8>, T::Error> {
+        self.target.pause()?;
+        Ok(format!("T02thread:{:x};", u32::from(self.current_vcpu) + 1).into_bytes())
+    }
+
+    /// Handles the data of a packet sent while the guest is paused.
+    pub fn handle_packet(&mut self, packet: &[u8]) -> Reply {
+        if packet.is_empty() {
+            return Reply::Packet(Vec::new());
+        }
+        let args = &packet[1..];
+        match packet[0] {
+            b'?' => Reply::Packet(
+                format!("T05thread:{:x};", u32::from(self.current_vcpu) + 1).into_bytes(),
+            ),
+            b'q' => self.handle_query(args),
+            b'H' => self.handle_set_thread(args),
+            b'T' => match self.parse_thread(args) {
+                Some(Some(_)) => ok_reply(),
+                _ => error_reply(1),
+            },
+            b'g' => match self.target.read_registers(self.current_vcpu) {
+                Ok(registers) => Reply::Packet(encode_hex(&registers.to_bytes()).into_bytes()),
+                Err(e) => self.target_error("read registers", e),
+            },
+            b'G' => match decode_hex(args).and_then(|bytes| Registers::from_bytes(&bytes)) {
+                Some(registers) => match self.target.write_registers(self.current_vcpu, &registers)
+                {
+                    Ok(()) => ok_reply(),
+                    Err(e) => self.target_error("write registers", e),
+                },
+                None => error_reply(1),
+            },
+            b'm' => self.handle_read_memory(args),
+            b'M' => self.handle_write_memory(args),
+            b'c' => self.handle_resume(args, None),
+            b's' => self.handle_resume(args, Some(self.step_vcpu.unwrap_or(self.current_vcpu))),
+            b'Z' => self.handle_breakpoint(args, true),
+            b'z' => self.handle_breakpoint(args, false),
+            b'D' => Reply::Detach,
+            b'k' => Reply::Kill,
+            // An empty reply tells GDB that the packet is not supported.
+            _ => Reply::Packet(Vec::new()),
+        }
+    }
+
+    fn target_error(&self, action: &str, error: T::Error) -> Reply {
+        warn!("GDB stub cannot {}: {}", action, error);
+        error_reply(0xe)
+    }
+
+    fn handle_query(&mut self, args: &[u8]) -> Reply {
+        let reply = if args.starts_with(b"Supported") {
+            format!("PacketSize={:x};swbreak+", MAX_PACKET_SIZE)
+        } else if args == b"Attached" {
+            // Detaching leaves the guest running.
+            "1".to_string()
+        } else if args == b"C" {
+            format!("QC{:x}", u32::from(self.current_vcpu) + 1)
+        } else if args == b"fThreadInfo" {
+            let threads: Vec<String> = (1..=u32::from(self.target.vcpu_count()))
+                .map(|thread| format!("{:x}", thread))
+                .collect();
+            format!("m{}", threads.join(","))
+        } else if args == b"sThreadInfo" {
+            "l".to_string()
+        } else if args.starts_with(b"ThreadExtraInfo,") {
+            match self.parse_thread(&args[b"ThreadExtraInfo,".len()..]) {
+                Some(Some(vcpu)) => encode_hex(format!("vCPU {}", vcpu).as_bytes()),
+                _ => return error_reply(1),
+            }
+        } else {
+            String::new()
+        };
+        Reply::Packet(reply.into_bytes())
+    }
+
+    // Returns `Some(None)` for "any thread", `Some(Some(vcpu))` for a specific one.
+    fn parse_thread(&self, thread: &[u8]) -> Option<Option<u8>> {
+        if thread == b"-1" || thread == b"0" {
+            return Some(None);
+        }
+        let thread = parse_hex(thread)?;
+        if thread == 0 || thread > u64::from(self.target.vcpu_count()) {
+            return None;
+        }
+        Some(Some((thread - 1) as u8))
+    }
+
+    fn handle_set_thread(&mut self, args: &[u8]) -> Reply {
+        if args.is_empty() {
+            return error_reply(1);
+        }
+        let thread = match self.parse_thread(&args[1..]) {
+            Some(thread) => thread,
+            None => return error_reply(1),
+        };
+        match args[0] {
+            b'g' => {
+                if let Some(vcpu) = thread {
+                    self.current_vcpu = vcpu;
+                }
+            }
+            b'c' => self.step_vcpu = thread,
+            _ => return error_reply(1),
+        }
+        ok_reply()
+    }
+
+    fn handle_read_memory(&mut self, args: &[u8]) -> Reply {
+        let (addr, len) = match parse_memory_args(args, None) {
+            Some((addr, len, _)) if len <= MAX_MEMORY_ACCESS => (addr, len),
+            _ => return error_reply(1),
+        };
+        let mut data = vec![0u8; len];
+        if let Err(e) = self.target.read_memory(self.current_vcpu, addr, &mut data) {
+            return self.target_error("read memory", e);
+        }
+        // Hide the inserted breakpoints from the debugger.
+        for (bp_addr, original) in self
+            .breakpoints
+            .range(addr..addr.saturating_add(len as u64))
+        {
+            data[(bp_addr - addr) as usize] = *original;
+        }
+        Reply::Packet(encode_hex(&data).into_bytes())
+    }
+
+    fn handle_write_memory(&mut self, args: &[u8]) -> Reply {
+        let (addr, mut data) = match parse_memory_args(args, Some(b':')) {
+            Some((addr, len, data)) => match decode_hex(data) {
+                Some(data) if data.len() == len => (addr, data),
+                _ => return error_reply(1),
+            },
+            None => return error_reply(1),
+        };
+        // Keep the inserted breakpoints, and restore the new bytes when removing them.
+        for (bp_addr, original) in self
+            .breakpoints
+            .range_mut(addr..addr.saturating_add(data.len() as u64))
+        {
+            let offset = (bp_addr - addr) as usize;
+            *original = data[offset];
+            data[offset] = SW_BREAKPOINT_INSN;
+        }
+        match self.target.write_memory(self.current_vcpu, addr, &data) {
+            Ok(()) => ok_reply(),
+            Err(e) => self.target_error("write memory", e),
+        }
+    }
+
+    fn handle_resume(&mut self, args: &[u8], single_step: Option<u8>) -> Reply {
+        // Resuming at another address is not supported.
+        if !args.is_empty() {
+            return Reply::Packet(Vec::new());
+        }
+        match self
+            .target
+            .resume(single_step, !self.breakpoints.is_empty())
+        {
+            Ok(()) => Reply::Resumed,
+            Err(e) => self.target_error("resume", e),
+        }
+    }
+
+    fn handle_breakpoint(&mut self, args: &[u8], insert: bool) -> Reply {
+        // Only software breakpoints are supported.
+        if !args.starts_with(b"0,") {
+            return Reply::Packet(Vec::new());
+        }
+        let addr = match parse_memory_args(&args[2..], None) {
+            Some((addr, _, _)) => addr,
+            None => return error_reply(1),
+        };
+
+        if insert {
+            if self.breakpoints.contains_key(&addr) {
+                return ok_reply();
+            }
+            let mut original = [0u8];
+            if let Err(e) = self
+                .target
+                .read_memory(self.current_vcpu, addr, &mut original)
+            {
+                return self.target_error("insert breakpoint", e);
+            }
+            if let Err(e) = self
+                .target
+                .write_memory(self.current_vcpu, addr, &[SW_BREAKPOINT_INSN])
+            {
+                return self.target_error("insert breakpoint", e);
+            }
+            self.breakpoints.insert(addr, original[0]);
+        } else if let Some(original) = self.breakpoints.remove(&addr) {
+            if let Err(e) = self
+                .target
+                .write_memory(self.current_vcpu, addr, &[original])
+            {
+                return self.target_error("remove breakpoint", e);
+            }
+        }
+        ok_reply()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct MockTarget {
+        paused: bool,
+        single_step: Option<u8>,
+        sw_breakpoints: bool,
+        stops: Vec<u8>,
+        registers: [Registers; 2],
+        memory: Vec<u8>,
+    }
+
+    impl Target for MockTarget {
+        type Error = String;
+
+        fn vcpu_count(&self) -> u8 {
+            2
+        }
+
+        fn pause(&mut self) -> Result<(), String> {
+            self.paused = true;
+            Ok(())
+        }
+
+        fn resume(&mut self, single_step: Option<u8>, sw_breakpoints: bool) -> Result<(), String> {
+            self.paused = false;
+            self.single_step = single_step;
+            self.sw_breakpoints = sw_breakpoints;
+            Ok(())
+        }
+
+        fn poll_stop(&mut self) -> Option<u8> {
+            self.stops.pop()
+        }
+
+        fn read_registers(&mut self, vcpu: u8) -> Result<Registers, String> {
+            Ok(self.registers[vcpu as usize].clone())
+        }
+
+        fn write_registers(&mut self, vcpu: u8, registers: &Registers) -> Result<(), String> {
+            self.registers[vcpu as usize] = registers.clone();
+            Ok(())
+        }
+
+        fn read_memory(&mut self, _vcpu: u8, addr: u64, data: &mut [u8]) -> Result<(), String> {
+            let addr = addr as usize;
+            let memory = self
+                .memory
+                .get(addr..addr + data.len())
+                .ok_or_else(|| "unmapped".to_string())?;
+            data.copy_from_slice(memory);
+            Ok(())
+        }
+
+        fn write_memory(&mut self, _vcpu: u8, addr: u64, data: &[u8]) -> Result<(), String> {
+            let addr = addr as usize;
+            self.memory
+                .get_mut(addr..addr + data.len())
+                .ok_or_else(|| "unmapped".to_string())?
+                .copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> Reply {
+        Reply::Packet(data.as_bytes().to_vec())
+    }
+
+    fn stub() -> GdbStub<MockTarget> {
+        let mut stub = GdbStub::new(MockTarget {
+            memory: (0..0x20).collect(),
+            ..Default::default()
+        });
+        stub.attach().unwrap();
+        stub
+    }
+
+    #[test]
+    fn test_registers() {
+        let registers = Registers {
+            gprs: [0x1122_3344_5566_7788; GPR_COUNT],
+            rip: 0xffff_ffff_8100_0000,
+            eflags: 0x246,
+            segments: [0x10, 0x18, 0x18, 0x18, 0, 0],
+        };
+        let bytes = registers.to_bytes();
+        assert_eq!(bytes.len(), REGISTERS_SIZE);
+        assert_eq!(
+            &bytes[..8],
+            &[0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11]
+        );
+        assert_eq!(Registers::from_bytes(&bytes), Some(registers.clone()));
+
+        // Trailing registers are ignored.
+        let mut longer = bytes.clone();
+        longer.extend_from_slice(&[0u8; 64]);
+        assert_eq!(Registers::from_bytes(&longer), Some(registers));
+        assert_eq!(Registers::from_bytes(&bytes[1..]), None);
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut stub = stub();
+        assert!(stub.target().paused);
+
+        assert_eq!(
+            stub.handle_packet(b"qSupported:multiprocess+;swbreak+"),
+            packet("PacketSize=1000;swbreak+")
+        );
+        assert_eq!(stub.handle_packet(b"qAttached"), packet("1"));
+        assert_eq!(stub.handle_packet(b"qfThreadInfo"), packet("m1,2"));
+        assert_eq!(stub.handle_packet(b"qsThreadInfo"), packet("l"));
+        assert_eq!(
+            stub.handle_packet(b"qThreadExtraInfo,2"),
+            packet(&encode_hex(b"vCPU 1"))
+        );
+        assert_eq!(stub.handle_packet(b"qC"), packet("QC1"));
+        assert_eq!(stub.handle_packet(b"?"), packet("T05thread:1;"));
+        assert_eq!(stub.handle_packet(b"qUnknown"), packet(""));
+        assert_eq!(stub.handle_packet(b"vMustReplyEmpty"), packet(""));
+
+        assert_eq!(stub.handle_packet(b"T2"), packet("OK"));
+        assert_eq!(stub.handle_packet(b"T3"), packet("E01"));
+        assert_eq!(stub.handle_packet(b"Hg2"), packet("OK"));
+        assert_eq!(stub.handle_packet(b"qC"), packet("QC2"));
+        assert_eq!(stub.handle_packet(b"Hg0"), packet("OK"));
+        assert_eq!(stub.handle_packet(b"qC"), packet("QC2"));
+        assert_eq!(stub.handle_packet(b"Hg3"), packet("E01"));
+        assert_eq!(stub.handle_packet(b"Hx1"), packet("E01"));
+    }
+
+    #[test]
+    fn test_registers_packets() {
+        let mut stub = stub();
+        stub.target().registers[1].rip = 0x1000;
+
+        assert_eq!(stub.handle_packet(b"Hg2"), packet("OK"));
+        let registers = match stub.handle_packet(b"g") {
+            Reply::Packet(data) => data,
+            other => panic!("Unexpected reply: {:?}", other),
+        };
+        assert_eq!(registers.len(), REGISTERS_SIZE * 2);
+        assert_eq!(&registers[256..272], b"0010000000000000");
+
+        let mut update = Registers::default();
+        update.gprs[0] = 42;
+        let mut update_packet = b"G".to_vec();
+        update_packet.extend_from_slice(encode_hex(&update.to_bytes()).as_bytes());
+        assert_eq!(stub.handle_packet(&update_packet), packet("OK"));
+        assert_eq!(stub.target().registers[1], update);
+        assert_eq!(stub.target().registers[0], Registers::default());
+
+        assert_eq!(stub.handle_packet(b"G00"), packet("E01"));
+    }
+
+    #[test]
+    fn test_memory_packets() {
+        let mut stub = stub();
+
+        assert_eq!(stub.handle_packet(b"m2,3"), packet("020304"));
+        assert_eq!(stub.handle_packet(b"m1e,4"), packet("E0e"));
+        assert_eq!(stub.handle_packet(b"m2"), packet("E01"));
+        assert_eq!(stub.handle_packet(b"m0,10000"), packet("E01"));
+
+        assert_eq!(stub.handle_packet(b"M2,2:aabb"), packet("OK"));
+        assert_eq!(&stub.target().memory[1..5], &[0x01, 0xaa, 0xbb, 0x04]);
+        assert_eq!(stub.handle_packet(b"M2,2:aa"), packet("E01"));
+        assert_eq!(stub.handle_packet(b"M2,1"), packet("E01"));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut stub = stub();
+
+        assert_eq!(stub.handle_packet(b"Z0,4,1"), packet("OK"));
+        assert_eq!(stub.target().memory[4], SW_BREAKPOINT_INSN);
+        // Inserting twice does not lose the original byte.
+        assert_eq!(stub.handle_packet(b"Z0,4,1"), packet("OK"));
+        // The breakpoint is hidden from reads.
+        assert_eq!(stub.handle_packet(b"m3,3"), packet("030405"));
+        // Writes over the breakpoint update the byte it restores.
+        assert_eq!(stub.handle_packet(b"M4,1:ff"), packet("OK"));
+        assert_eq!(stub.target().memory[4], SW_BREAKPOINT_INSN);
+        assert_eq!(stub.handle_packet(b"m4,1"), packet("ff"));
+        // Hardware breakpoints and watchpoints are not supported.
+        assert_eq!(stub.handle_packet(b"Z1,4,1"), packet(""));
+        assert_eq!(stub.handle_packet(b"Z0,40,1"), packet("E0e"));
+
+        assert_eq!(stub.handle_packet(b"c"), Reply::Resumed);
+        assert!(!stub.target().paused);
+        assert!(stub.target().sw_breakpoints);
+        assert_eq!(stub.target().single_step, None);
+
+        // A vCPU stopping on the breakpoint pauses the others.
+        stub.target().registers[1].rip = 4;
+        assert_eq!(stub.on_stop(1).unwrap(), b"T05swbreak:;thread:2;".to_vec());
+        assert!(stub.target().paused);
+        assert_eq!(stub.handle_packet(b"qC"), packet("QC2"));
+
+        assert_eq!(stub.handle_packet(b"z0,4,1"), packet("OK"));
+        assert_eq!(stub.target().memory[4], 0xff);
+        assert_eq!(stub.on_stop(1).unwrap(), b"T05thread:2;".to_vec());
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut stub = stub();
+
+        assert_eq!(stub.handle_packet(b"s"), Reply::Resumed);
+        assert_eq!(stub.target().single_step, Some(0));
+        assert!(!stub.target().sw_breakpoints);
+
+        assert_eq!(stub.handle_packet(b"Hc2"), packet("OK"));
+        assert_eq!(stub.handle_packet(b"s"), Reply::Resumed);
+        assert_eq!(stub.target().single_step, Some(1));
+        assert_eq!(stub.handle_packet(b"c1000"), packet(""));
+
+        assert_eq!(stub.on_interrupt().unwrap(), b"T02thread:1;".to_vec());
+        assert!(stub.target().paused);
+
+        assert_eq!(stub.handle_packet(b"D"), Reply::Detach);
+        assert_eq!(stub.handle_packet(b"k"), Reply::Kill);
+    }
+
+    #[test]
+    fn test_detach() {
+        let mut stub = stub();
+
+        assert_eq!(stub.handle_packet(b"Z0,4,1"), packet("OK"));
+        assert_eq!(stub.handle_packet(b"Z0,8,1"), packet("OK"));
+        stub.detach().unwrap();
+        assert_eq!(stub.target().memory[4], 4);
+        assert_eq!(stub.target().memory[8], 8);
+        assert!(!stub.target().paused);
+        assert!(!stub.target().sw_breakpoints);
+        assert_eq!(stub.target().single_step, None);
+    }
+}
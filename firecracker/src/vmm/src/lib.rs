@@ -15,6 +15,10 @@ pub mod builder;
 /// Syscalls allowed through the seccomp filter.
 pub mod default_syscalls;
 pub(crate) mod device_manager;
+/// GDB server for debugging guest kernels.
+#[cfg(feature = "gdb")]
+mod gdb;
+pub mod measured_boot;
 pub mod memory_snapshot;
 /// Save/restore utilities.
 pub mod persist;
@@ -35,6 +39,7 @@ mod vstate;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::io;
+use std::ops::Range;
 use std::os::unix::io::AsRawFd;
 use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Mutex;
@@ -43,8 +48,15 @@ use std::time::Duration;
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::legacy::PortIODeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
+use crate::measured_boot::BootMeasurements;
 use crate::memory_snapshot::SnapshotMemory;
-use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
+use crate::persist::{
+    BackgroundSnapshot, CpuTemplateState, MicrovmState, MicrovmStateError, VmInfo,
+};
+use crate::vmm_config::cpu_config::CustomCpuTemplate;
+use crate::vmm_config::machine_config::{CpuFeaturesTemplate, MemoryBackend};
+use crate::vmm_config::snapshot::SnapshotCreateStatus;
+use crate::vmm_config::vcpu_stats::VcpuStats;
 use crate::vstate::vcpu::VcpuState;
 use crate::vstate::{
     vcpu::{Vcpu, VcpuEvent, VcpuHandle, VcpuResponse},
@@ -149,6 +161,8 @@ pub enum Error {
     VcpuResume,
     /// Vcpu send message failed.
     VcpuMessage,
+    /// Cannot park a vCPU which is online in the guest.
+    VcpuOnline(usize),
     /// Cannot spawn a new Vcpu thread.
     VcpuSpawn(io::Error),
     /// Vm error.
@@ -190,6 +204,11 @@ impl Display for Error {
             VcpuExit => write!(f, "Failed to exit the vCPUs."),
             VcpuResume => write!(f, "Failed to resume the vCPUs."),
             VcpuMessage => write!(f, "Failed to message the vCPUs."),
+            VcpuOnline(index) => write!(
+                f,
+                "Cannot park vCPU {}, which is still online in the guest.",
+                index
+            ),
             VcpuSpawn(e) => write!(f, "Cannot spawn Vcpu thread: {}", e),
             Vm(e) => write!(f, "Vm error: {}", e),
             VmmObserverInit(e) => write!(
@@ -235,8 +254,23 @@ pub struct Vmm {
     guest_memory: GuestMemoryMmap,
 
     vcpus_handles: Vec<VcpuHandle>,
+    // Number of vcpus running the guest, the first ones of `vcpus_handles`. The other vcpus are
+    // parked in the `Paused` state until the online vcpu count grows.
+    online_vcpu_count: u8,
+    // Whether the vcpus were paused by the VMM, so that the vcpus started later stay paused.
+    vcpus_paused: bool,
     exit_evt: EventFd,
     vm: Vm,
+    // CPUID configuration used at boot, carried over into snapshots.
+    cpu_template: Option<CpuFeaturesTemplate>,
+    custom_cpu_template: Option<CustomCpuTemplate>,
+    ht_enabled: bool,
+    // Type of memory backing the guest memory, carried over into snapshots.
+    mem_backend: MemoryBackend,
+    // The latest snapshot whose guest memory is written in the background.
+    background_snapshot: Option<BackgroundSnapshot>,
+    // Measurements of the boot components, if the microVM was booted with measured boot.
+    boot_measurements: Option<BootMeasurements>,
 
     // Guest VM devices.
     mmio_device_manager: MMIODeviceManager,
@@ -270,6 +304,7 @@ impl Vmm {
 
         self.vcpus_handles.reserve(vcpu_count as usize);
 
+        self.online_vcpu_count = vcpu_count as u8;
         for mut vcpu in vcpus.drain(..) {
             vcpu.set_mmio_bus(self.mmio_device_manager.bus.clone());
             #[cfg(target_arch = "x86_64")]
@@ -285,12 +320,13 @@ impl Vmm {
         Ok(())
     }
 
-    // Checks that the vCPUs respond with the `_expected_response`.
+    // Checks that the vCPUs in `vcpus` respond with the `_expected_response`.
     fn check_vcpus_response(
         &mut self,
+        vcpus: Range<usize>,
         _expected_response: VcpuResponse,
     ) -> std::result::Result<(), ()> {
-        for handle in self.vcpus_handles.iter() {
+        for handle in self.vcpus_handles[vcpus].iter() {
             match handle
                 .response_receiver()
                 .recv_timeout(Duration::from_millis(1000))
@@ -302,17 +338,25 @@ impl Vmm {
         Ok(())
     }
 
-    /// Sends a resume command to the vCPUs.
+    /// Sends a resume command to the vCPUs running the guest. The parked vCPUs stay paused.
     pub fn resume_vm(&mut self) -> Result<()> {
         self.mmio_device_manager.kick_devices();
-        self.broadcast_vcpu_event(VcpuEvent::Resume, VcpuResponse::Resumed)
-            .map_err(|_| Error::VcpuResume)
+        self.send_vcpus_event(
+            0..self.online_vcpu_count as usize,
+            VcpuEvent::Resume,
+            VcpuResponse::Resumed,
+        )
+        .map_err(|_| Error::VcpuResume)?;
+        self.vcpus_paused = false;
+        Ok(())
     }
 
     /// Sends a pause command to the vCPUs.
     pub fn pause_vm(&mut self) -> Result<()> {
         self.broadcast_vcpu_event(VcpuEvent::Pause, VcpuResponse::Paused)
-            .map_err(|_| Error::VcpuPause)
+            .map_err(|_| Error::VcpuPause)?;
+        self.vcpus_paused = true;
+        Ok(())
     }
 
     /// Sends an exit command to the vCPUs.
@@ -323,6 +367,86 @@ impl Vmm {
         )
         .map_err(|_| Error::VcpuExit)
     }
+
+    /// Returns the number of vCPUs running the guest.
+    pub fn online_vcpu_count(&self) -> u8 {
+        self.online_vcpu_count
+    }
+
+    /// Returns the number of vCPUs created at boot, which bounds the number of vCPUs that can
+    /// run the guest.
+    pub fn max_vcpu_count(&self) -> u8 {
+        self.vcpus_handles.len() as u8
+    }
+
+    /// Starts or parks vCPUs created at boot, so that `vcpu_count` vCPUs run the guest.
+    ///
+    /// No vCPU is created and the guest is not notified: the started vCPUs run once the guest
+    /// brings them online, while the parked vCPUs have to be taken offline by the guest
+    /// beforehand. No vCPU is parked if any of them is still online.
+    pub fn set_online_vcpu_count(&mut self, vcpu_count: u8) -> Result<()> {
+        let (current, target) = (self.online_vcpu_count as usize, vcpu_count as usize);
+        if target > current {
+            // Started vCPUs only run along with the other vCPUs.
+            if !self.vcpus_paused {
+                self.send_vcpus_event(current..target, VcpuEvent::Resume, VcpuResponse::Resumed)
+                    .map_err(|_| Error::VcpuResume)?;
+            }
+        } else if target < current {
+            self.park_vcpus(target..current)?;
+        }
+        self.online_vcpu_count = vcpu_count;
+        Ok(())
+    }
+
+    // Parks the vCPUs in `vcpus` if the guest took all of them offline. Otherwise, or if any
+    // of them fails to respond, the vCPUs parked in the meantime run the guest again.
+    fn park_vcpus(&mut self, vcpus: Range<usize>) -> Result<()> {
+        let mut error = None;
+        let mut sent = vcpus.start..vcpus.start;
+        for index in vcpus {
+            if self.vcpus_handles[index]
+                .send_event(VcpuEvent::Park)
+                .is_err()
+            {
+                error = Some(Error::VcpuMessage);
+                break;
+            }
+            sent.end = index + 1;
+        }
+
+        let mut parked = Vec::new();
+        let mut online = None;
+        for index in sent {
+            match self.vcpus_handles[index]
+                .response_receiver()
+                .recv_timeout(Duration::from_millis(1000))
+            {
+                Ok(VcpuResponse::Paused) => parked.push(index),
+                Ok(VcpuResponse::NotAllowed(_)) => online = online.or(Some(index)),
+                _ => error = error.or(Some(Error::VcpuPause)),
+            }
+        }
+
+        let error = match (error, online) {
+            (None, None) => return Ok(()),
+            (Some(error), _) => error,
+            (None, Some(index)) => Error::VcpuOnline(index),
+        };
+        if !self.vcpus_paused {
+            let mut resumed = true;
+            for index in parked {
+                resumed &= self
+                    .send_vcpus_event(index..index + 1, VcpuEvent::Resume, VcpuResponse::Resumed)
+                    .is_ok();
+            }
+            if !resumed {
+                return Err(Error::VcpuResume);
+            }
+        }
+        Err(error)
+    }
+
     /// Returns a reference to the inner `GuestMemoryMmap` object if present, or `None` otherwise.
     pub fn guest_memory(&self) -> &GuestMemoryMmap {
         &self.guest_memory
@@ -377,13 +501,25 @@ impl Vmm {
                 self.vm.save_state(&mpidrs).map_err(SaveVmState)?
             }
         };
+        self.mmio_device_manager
+            .fetch_tpm_state()
+            .map_err(MicrovmStateError::SaveTpmState)?;
         let device_states = self.mmio_device_manager.save();
 
         let mem_size_mib = mem_size_mib(self.guest_memory());
         let memory_state = self.guest_memory().describe();
 
         Ok(MicrovmState {
-            vm_info: VmInfo { mem_size_mib },
+            vm_info: VmInfo {
+                mem_size_mib,
+                cpu_template: self.cpu_template.map(CpuTemplateState::from),
+                custom_cpu_template: self.custom_cpu_template.as_ref().map(Into::into),
+                ht_enabled: self.ht_enabled,
+                mem_backend: self.mem_backend.into(),
+                online_vcpu_count: Some(self.online_vcpu_count),
+                boot_measurements: self.boot_measurements.clone(),
+                mmds_version: mmds::MMDS.lock().expect("Poisoned lock").version().into(),
+            },
             memory_state,
             vm_state,
             vcpu_states,
@@ -430,13 +566,23 @@ impl Vmm {
         event: VcpuEvent,
         expected_response: VcpuResponse,
     ) -> Result<()> {
-        for handle in self.vcpus_handles.iter() {
+        self.send_vcpus_event(0..self.vcpus_handles.len(), event, expected_response)
+    }
+
+    // Sends an event to the vCPUs in `vcpus` and waits for a response.
+    fn send_vcpus_event(
+        &mut self,
+        vcpus: Range<usize>,
+        event: VcpuEvent,
+        expected_response: VcpuResponse,
+    ) -> Result<()> {
+        for handle in self.vcpus_handles[vcpus.clone()].iter() {
             handle
                 .send_event(event.clone())
                 .map_err(|_| Error::VcpuMessage)?;
         }
 
-        self.check_vcpus_response(expected_response)
+        self.check_vcpus_response(vcpus, expected_response)
             .map_err(|_| Error::VcpuMessage)
     }
 
@@ -482,6 +628,34 @@ impl Vmm {
         Ok(())
     }
 
+    /// Returns the KVM exit counts and run time of every vCPU.
+    pub fn vcpu_stats(&self) -> Vec<VcpuStats> {
+        self.vcpus_handles
+            .iter()
+            .enumerate()
+            .map(|(index, handle)| handle.stats().read(index as u8))
+            .collect()
+    }
+
+    /// Returns the measurements of the boot components, if the microVM was booted with
+    /// measured boot.
+    pub fn boot_measurements(&self) -> Option<BootMeasurements> {
+        self.boot_measurements.clone()
+    }
+
+    /// Returns the status of the latest background snapshot creation.
+    pub fn snapshot_create_status(&mut self) -> SnapshotCreateStatus {
+        self.background_snapshot
+            .as_mut()
+            .map(BackgroundSnapshot::status)
+            .unwrap_or_default()
+    }
+
+    /// Keeps track of a snapshot whose guest memory is written in the background.
+    pub(crate) fn set_background_snapshot(&mut self, background_snapshot: BackgroundSnapshot) {
+        self.background_snapshot = Some(background_snapshot);
+    }
+
     /// Retrieves the KVM dirty bitmap for each of the guest's memory regions.
     pub fn get_dirty_bitmap(&self) -> Result<DirtyBitmap> {
         let mut bitmap: DirtyBitmap = HashMap::new();
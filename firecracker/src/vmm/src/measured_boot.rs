@@ -0,0 +1,360 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Measurements of the components a microVM boots from.
+//!
+//! The SHA-256 digests of the kernel image, initrd, kernel command line and boot-time device
+//! configuration are recorded in an event log which is written to a memory region reserved for
+//! the guest, and are reported through the instance information of the microVM.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::device_manager::mmio::MMIODeviceInfo;
+use crate::vmm_config::boot_source::BootConfig;
+use crate::vmm_config::cpu_config::CustomCpuTemplate;
+use crate::vmm_config::machine_config::CpuFeaturesTemplate;
+use crate::vstate::vcpu::VcpuConfig;
+use arch::{DeviceType, EventLogConfig};
+use kernel::bundle::SectionReader;
+use serde::Serialize;
+use utils::sha256::{to_hex, Digest, Sha256};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+
+/// Magic number at the start of the event log.
+pub const EVENT_LOG_MAGIC: &[u8; 8] = b"FCEVTLOG";
+/// Version of the event log format.
+pub const EVENT_LOG_VERSION: u32 = 1;
+
+/// Errors associated with measured boot.
+#[derive(Debug)]
+pub enum Error {
+    /// The event log does not fit in the memory region reserved for it.
+    EventLogTooLarge(usize),
+    /// The kernel boots through the PVH entry point, which has no way to find the event log.
+    PvhBoot,
+    /// Cannot read a boot image.
+    ReadImage(io::Error),
+    /// Cannot serialize the device configuration.
+    SerializeDevices(serde_json::Error),
+    /// Cannot write the event log into the guest memory.
+    WriteEventLog(vm_memory::GuestMemoryError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+        match self {
+            EventLogTooLarge(size) => write!(
+                f,
+                "The event log size ({} bytes) exceeds the reserved region size ({} bytes).",
+                size,
+                arch::EVENT_LOG_MAX_SIZE
+            ),
+            PvhBoot => write!(
+                f,
+                "Measured boot is not supported with kernels booted through the PVH entry point."
+            ),
+            ReadImage(err) => write!(f, "Cannot read boot image: {}", err),
+            SerializeDevices(err) => {
+                write!(f, "Cannot serialize the device configuration: {}", err)
+            }
+            WriteEventLog(err) => write!(f, "Cannot write the event log: {}", err),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Boot components recorded in the event log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventType {
+    /// The kernel image.
+    Kernel = 1,
+    /// The initrd image.
+    Initrd = 2,
+    /// The kernel command line.
+    Cmdline = 3,
+    /// The boot-time device configuration.
+    Devices = 4,
+}
+
+/// The hex encoded SHA-256 measurements of the boot components of a microVM.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct BootMeasurements {
+    /// Digest of the kernel image.
+    pub kernel: String,
+    /// Digest of the initrd image, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initrd: Option<String>,
+    /// Digest of the kernel command line passed to the guest.
+    pub cmdline: String,
+    /// Digest of the boot-time device configuration.
+    pub devices: String,
+}
+
+struct Event {
+    event_type: EventType,
+    digest: Digest,
+    data: Vec<u8>,
+}
+
+/// Log of the measured boot components, in the order they were measured.
+#[derive(Default)]
+pub struct EventLog {
+    events: Vec<Event>,
+}
+
+impl EventLog {
+    /// Measures a whole image, from its start.
+    pub fn measure_image<R: Read + Seek>(
+        &mut self,
+        event_type: EventType,
+        image: &mut R,
+    ) -> Result<()> {
+        let mut hasher = Sha256::new();
+        image.seek(SeekFrom::Start(0)).map_err(Error::ReadImage)?;
+        io::copy(image, &mut hasher).map_err(Error::ReadImage)?;
+        self.events.push(Event {
+            event_type,
+            digest: hasher.finalize(),
+            data: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Measures `data`, which is also recorded in the event log.
+    pub fn measure_data(&mut self, event_type: EventType, data: Vec<u8>) {
+        self.events.push(Event {
+            event_type,
+            digest: Sha256::digest(&data),
+            data,
+        });
+    }
+
+    fn digest(&self, event_type: EventType) -> Option<String> {
+        self.events
+            .iter()
+            .find(|event| event.event_type == event_type)
+            .map(|event| to_hex(&event.digest))
+    }
+
+    /// Returns the hex encoded measurements of the boot components.
+    pub fn measurements(&self) -> BootMeasurements {
+        BootMeasurements {
+            kernel: self.digest(EventType::Kernel).unwrap_or_default(),
+            initrd: self.digest(EventType::Initrd),
+            cmdline: self.digest(EventType::Cmdline).unwrap_or_default(),
+            devices: self.digest(EventType::Devices).unwrap_or_default(),
+        }
+    }
+
+    /// Serializes the event log.
+    ///
+    /// The log starts with the `EVENT_LOG_MAGIC` magic number, followed by the format version
+    /// and the number of events as little endian `u32`s. Each event is made of its type and the
+    /// length of its data as little endian `u32`s, the SHA-256 digest and the data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(EVENT_LOG_MAGIC);
+        bytes.extend_from_slice(&EVENT_LOG_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+        for event in self.events.iter() {
+            bytes.extend_from_slice(&(event.event_type as u32).to_le_bytes());
+            bytes.extend_from_slice(&(event.data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&event.digest);
+            bytes.extend_from_slice(&event.data);
+        }
+        bytes
+    }
+
+    /// Writes the event log into the guest memory region reserved for it.
+    pub fn write_to_guest(&self, guest_memory: &GuestMemoryMmap) -> Result<EventLogConfig> {
+        let bytes = self.to_bytes();
+        if bytes.len() > arch::EVENT_LOG_MAX_SIZE {
+            return Err(Error::EventLogTooLarge(bytes.len()));
+        }
+
+        let address = GuestAddress(arch::event_log_addr(guest_memory));
+        guest_memory
+            .write_slice(&bytes, address)
+            .map_err(Error::WriteEventLog)?;
+        Ok(EventLogConfig {
+            address,
+            size: bytes.len(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct MeasuredDevice<'a> {
+    #[serde(rename = "type")]
+    device_type: String,
+    id: &'a str,
+    addr: u64,
+    len: u64,
+    irqs: &'a [u32],
+}
+
+#[derive(Serialize)]
+struct MeasuredConfig<'a> {
+    vcpu_count: u8,
+    mem_size_mib: usize,
+    ht_enabled: bool,
+    cpu_template: Option<CpuFeaturesTemplate>,
+    custom_cpu_template: Option<&'a CustomCpuTemplate>,
+    devices: Vec<MeasuredDevice<'a>>,
+}
+
+fn device_type_name(device_type: &DeviceType) -> String {
+    match device_type {
+        DeviceType::Virtio(virtio_type) => format!("virtio-{}", virtio_type),
+        #[cfg(target_arch = "aarch64")]
+        DeviceType::Serial => "serial".to_string(),
+        #[cfg(target_arch = "aarch64")]
+        DeviceType::RTC => "rtc".to_string(),
+        DeviceType::BootTimer => "boot-timer".to_string(),
+        DeviceType::Tpm => "tpm".to_string(),
+    }
+}
+
+/// Returns the JSON description of the boot-time device configuration which gets measured.
+///
+/// The MMIO devices are sorted by address so that the description does not depend on the
+/// order they were registered in.
+fn device_config(
+    vcpu_config: &VcpuConfig,
+    mem_size_mib: usize,
+    device_info: &HashMap<(DeviceType, String), MMIODeviceInfo>,
+) -> Result<Vec<u8>> {
+    let mut devices: Vec<MeasuredDevice> = device_info
+        .iter()
+        .map(|((device_type, id), info)| MeasuredDevice {
+            device_type: device_type_name(device_type),
+            id,
+            addr: info.addr,
+            len: info.len,
+            irqs: &info.irqs,
+        })
+        .collect();
+    devices.sort_by_key(|device| device.addr);
+
+    serde_json::to_vec(&MeasuredConfig {
+        vcpu_count: vcpu_config.vcpu_count,
+        mem_size_mib,
+        ht_enabled: vcpu_config.ht_enabled,
+        cpu_template: vcpu_config.cpu_template,
+        custom_cpu_template: vcpu_config.custom_cpu_template.as_ref(),
+        devices,
+    })
+    .map_err(Error::SerializeDevices)
+}
+
+/// Measures the boot components of a microVM, once its command line and devices are final.
+pub(crate) fn measure_boot(
+    boot_config: &BootConfig,
+    vcpu_config: &VcpuConfig,
+    mem_size_mib: usize,
+    device_info: &HashMap<(DeviceType, String), MMIODeviceInfo>,
+    cmdline: &str,
+) -> Result<EventLog> {
+    let mut event_log = EventLog::default();
+    let mut kernel_file = &boot_config.kernel_file;
+    match boot_config.bundle.as_ref() {
+        Some(bundle) => {
+            event_log.measure_image(
+                EventType::Kernel,
+                &mut SectionReader::new(kernel_file, bundle.kernel),
+            )?;
+            if let Some(initrd) = bundle.initrd {
+                event_log.measure_image(
+                    EventType::Initrd,
+                    &mut SectionReader::new(kernel_file, initrd),
+                )?;
+            }
+        }
+        None => event_log.measure_image(EventType::Kernel, &mut kernel_file)?,
+    }
+    if let Some(mut initrd_file) = boot_config.initrd_file.as_ref() {
+        event_log.measure_image(EventType::Initrd, &mut initrd_file)?;
+    }
+    event_log.measure_data(EventType::Cmdline, cmdline.as_bytes().to_vec());
+    event_log.measure_data(
+        EventType::Devices,
+        device_config(vcpu_config, mem_size_mib, device_info)?,
+    );
+
+    Ok(event_log)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use vm_memory::Address;
+
+    #[test]
+    fn test_event_log() {
+        let mut event_log = EventLog::default();
+        let mut kernel = Cursor::new(b"kernel".to_vec());
+        // The image is measured from its start, whatever its current offset.
+        kernel.seek(SeekFrom::End(0)).unwrap();
+        event_log
+            .measure_image(EventType::Kernel, &mut kernel)
+            .unwrap();
+        event_log.measure_data(EventType::Cmdline, b"console=ttyS0".to_vec());
+
+        let measurements = event_log.measurements();
+        assert_eq!(measurements.kernel, to_hex(&Sha256::digest(b"kernel")));
+        assert_eq!(measurements.initrd, None);
+        assert_eq!(
+            measurements.cmdline,
+            to_hex(&Sha256::digest(b"console=ttyS0"))
+        );
+
+        let bytes = event_log.to_bytes();
+        assert_eq!(&bytes[..8], EVENT_LOG_MAGIC);
+        assert_eq!(bytes[8..12], EVENT_LOG_VERSION.to_le_bytes());
+        assert_eq!(bytes[12..16], 2u32.to_le_bytes());
+        // The kernel event has no data.
+        assert_eq!(bytes[16..20], (EventType::Kernel as u32).to_le_bytes());
+        assert_eq!(bytes[20..24], 0u32.to_le_bytes());
+        assert_eq!(bytes[24..56], Sha256::digest(b"kernel"));
+        // The command line event holds the command line.
+        assert_eq!(bytes[56..60], (EventType::Cmdline as u32).to_le_bytes());
+        assert_eq!(bytes[60..64], 13u32.to_le_bytes());
+        assert_eq!(bytes[64..96], Sha256::digest(b"console=ttyS0"));
+        assert_eq!(&bytes[96..], b"console=ttyS0");
+    }
+
+    #[test]
+    fn test_write_to_guest() {
+        let guest_memory = crate::builder::create_guest_memory(
+            128,
+            false,
+            crate::vmm_config::machine_config::MemoryBackend::Anonymous,
+        )
+        .unwrap();
+        let mut event_log = EventLog::default();
+        event_log.measure_data(EventType::Devices, b"{}".to_vec());
+
+        let config = event_log.write_to_guest(&guest_memory).unwrap();
+        assert_eq!(
+            config.address.raw_value(),
+            arch::event_log_addr(&guest_memory)
+        );
+        let mut bytes = vec![0u8; config.size];
+        guest_memory.read_slice(&mut bytes, config.address).unwrap();
+        assert_eq!(bytes, event_log.to_bytes());
+
+        event_log.measure_data(EventType::Cmdline, vec![b'a'; arch::EVENT_LOG_MAX_SIZE]);
+        assert!(matches!(
+            event_log.write_to_guest(&guest_memory),
+            Err(Error::EventLogTooLarge(_))
+        ));
+    }
+}
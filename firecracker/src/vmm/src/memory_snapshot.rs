@@ -5,7 +5,7 @@
 
 use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::SeekFrom;
+use std::io::{Read, SeekFrom, Write};
 
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -16,6 +16,24 @@ use vm_memory::{
 
 use crate::DirtyBitmap;
 use utils::errno;
+use utils::sha256::{Digest, Sha256};
+
+/// Magic value at the start of a streamed diff memory snapshot, followed by the
+/// stream format version in the lowest byte.
+pub const DIFF_STREAM_MAGIC: u64 = 0x4643_4449_4646_5300 | DIFF_STREAM_VERSION;
+/// The version of the streamed diff memory snapshot format.
+const DIFF_STREAM_VERSION: u64 = 1;
+
+/// A contiguous range of guest memory, as laid out in the memory file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryChunk {
+    /// Offset of the chunk in the memory file.
+    pub file_offset: u64,
+    /// Host address of the chunk.
+    pub host_address: *const u8,
+    /// Size of the chunk, in bytes.
+    pub len: usize,
+}
 
 /// State of a guest memory region saved to file/buffer.
 #[derive(Debug, PartialEq, Versionize)]
@@ -52,6 +70,21 @@ where
         writer: &mut T,
         dirty_bitmap: &DirtyBitmap,
     ) -> std::result::Result<(), Error>;
+    /// Dumps all pages of GuestMemoryMmap present in `dirty_bitmap` to a writer
+    /// that cannot seek, using the streamed diff format.
+    fn dump_dirty_stream<T: std::io::Write>(
+        &self,
+        writer: &mut T,
+        dirty_bitmap: &DirtyBitmap,
+    ) -> std::result::Result<(), Error>;
+    /// Lists the chunks of GuestMemoryMmap written by `dump`, or by `dump_dirty` if
+    /// `dirty_bitmap` is present, without resetting the dirty pages tracked by Firecracker.
+    fn dump_chunks(
+        &self,
+        dirty_bitmap: Option<&DirtyBitmap>,
+    ) -> std::result::Result<Vec<MemoryChunk>, Error>;
+    /// Computes the SHA-256 digest of each region of GuestMemoryMmap.
+    fn digest_regions(&self) -> std::result::Result<Vec<Digest>, Error>;
     /// Creates a GuestMemoryMmap given a `file` containing the data
     /// and a `state` containing mapping information.
     fn restore(
@@ -59,6 +92,27 @@ where
         state: &GuestMemoryState,
         track_dirty_pages: bool,
     ) -> std::result::Result<Self, Error>;
+    /// Creates a GuestMemoryMmap backed by anonymous memory, given a `reader`
+    /// streaming a full memory dump and a `state` containing mapping information.
+    fn restore_from_reader<T: std::io::Read>(
+        reader: &mut T,
+        state: &GuestMemoryState,
+        track_dirty_pages: bool,
+    ) -> std::result::Result<Self, Error>;
+    /// Loads the contents of GuestMemoryMmap, laid out as described by `state`,
+    /// from a `reader` streaming a full memory dump.
+    fn load_from_reader<T: std::io::Read>(
+        &self,
+        reader: &mut T,
+        state: &GuestMemoryState,
+    ) -> std::result::Result<(), Error>;
+    /// Applies a streamed diff memory snapshot, read from `reader`, on top of the contents of
+    /// GuestMemoryMmap, laid out as described by `state`.
+    fn load_diff_from_reader<T: std::io::Read>(
+        &self,
+        reader: &mut T,
+        state: &GuestMemoryState,
+    ) -> std::result::Result<(), Error>;
 }
 
 /// Errors associated with dumping guest memory to file.
@@ -70,8 +124,14 @@ pub enum Error {
     CreateMemory(vm_memory::Error),
     /// Cannot create region.
     CreateRegion(vm_memory::mmap::MmapRegionError),
+    /// Streamed diff memory snapshot is malformed.
+    InvalidStream(String),
     /// Cannot fetch system's page size.
     PageSize(errno::Error),
+    /// Cannot load memory.
+    ReadMemory(GuestMemoryError),
+    /// Cannot access memory stream.
+    Stream(std::io::Error),
     /// Cannot dump memory.
     WriteMemory(GuestMemoryError),
 }
@@ -83,7 +143,10 @@ impl Display for Error {
             FileHandle(err) => write!(f, "Cannot access file: {:?}", err),
             CreateMemory(err) => write!(f, "Cannot create memory: {:?}", err),
             CreateRegion(err) => write!(f, "Cannot create memory region: {:?}", err),
+            InvalidStream(err) => write!(f, "Invalid memory stream: {}", err),
             PageSize(err) => write!(f, "Cannot fetch system's page size: {:?}", err),
+            ReadMemory(err) => write!(f, "Cannot load memory: {:?}", err),
+            Stream(err) => write!(f, "Cannot access memory stream: {:?}", err),
             WriteMemory(err) => write!(f, "Cannot dump memory: {:?}", err),
         }
     }
@@ -126,49 +189,110 @@ impl SnapshotMemory for GuestMemoryMmap {
 
         self.with_regions_mut(|slot, region| {
             let kvm_bitmap = dirty_bitmap.get(&slot).unwrap();
-            let firecracker_bitmap = region.dirty_bitmap().unwrap();
-            let mut write_size = 0;
-            let mut dirty_batch_start: u64 = 0;
-
-            for (i, v) in kvm_bitmap.iter().enumerate() {
-                for j in 0..64 {
-                    let is_kvm_page_dirty = ((v >> j) & 1u64) != 0u64;
-                    let page_offset = ((i * 64) + j) * page_size;
-                    let is_firecracker_page_dirty = firecracker_bitmap.is_addr_set(page_offset);
-                    if is_kvm_page_dirty || is_firecracker_page_dirty {
-                        // We are at the start of a new batch of dirty pages.
-                        if write_size == 0 {
-                            // Seek forward over the unmodified pages.
-                            writer
-                                .seek(SeekFrom::Start(writer_offset + page_offset as u64))
-                                .unwrap();
-                            dirty_batch_start = page_offset as u64;
-                        }
-                        write_size += page_size;
-                    } else if write_size > 0 {
-                        // We are at the end of a batch of dirty pages.
-                        region.write_all_to(
-                            MemoryRegionAddress(dirty_batch_start),
-                            writer,
-                            write_size,
-                        )?;
-                        write_size = 0;
-                    }
-                }
-            }
-
-            if write_size > 0 {
-                region.write_all_to(MemoryRegionAddress(dirty_batch_start), writer, write_size)?;
-            }
+            for_each_dirty_batch(region, kvm_bitmap, page_size, |batch_start, batch_size| {
+                // Seek forward over the unmodified pages.
+                writer
+                    .seek(SeekFrom::Start(writer_offset + batch_start))
+                    .unwrap();
+                region.write_all_to(MemoryRegionAddress(batch_start), writer, batch_size)
+            })?;
 
             writer_offset += region.len();
-            firecracker_bitmap.reset();
+            region.dirty_bitmap().unwrap().reset();
 
             Ok(())
         })
         .map_err(Error::WriteMemory)
     }
 
+    /// Dumps all pages of GuestMemoryMmap present in `dirty_bitmap` to a writer
+    /// that cannot seek, using the streamed diff format.
+    fn dump_dirty_stream<T: std::io::Write>(
+        &self,
+        writer: &mut T,
+        dirty_bitmap: &DirtyBitmap,
+    ) -> std::result::Result<(), Error> {
+        let mut writer_offset = 0;
+        let page_size = get_page_size()?;
+
+        writer
+            .write_all(&DIFF_STREAM_MAGIC.to_le_bytes())
+            .map_err(Error::Stream)?;
+        self.with_regions_mut(|slot, region| {
+            let kvm_bitmap = dirty_bitmap.get(&slot).unwrap();
+            for_each_dirty_batch(region, kvm_bitmap, page_size, |batch_start, batch_size| {
+                write_diff_record_header(writer, writer_offset + batch_start, batch_size as u64)
+                    .map_err(GuestMemoryError::IOError)?;
+                region.write_all_to(MemoryRegionAddress(batch_start), writer, batch_size)
+            })?;
+
+            writer_offset += region.len();
+            region.dirty_bitmap().unwrap().reset();
+
+            Ok(())
+        })
+        .map_err(Error::WriteMemory)?;
+
+        // The stream ends with an empty record holding the size of the memory file.
+        write_diff_record_header(writer, writer_offset, 0).map_err(Error::Stream)
+    }
+
+    /// Lists the chunks of GuestMemoryMmap written by `dump`, or by `dump_dirty` if
+    /// `dirty_bitmap` is present, without resetting the dirty pages tracked by Firecracker.
+    fn dump_chunks(
+        &self,
+        dirty_bitmap: Option<&DirtyBitmap>,
+    ) -> std::result::Result<Vec<MemoryChunk>, Error> {
+        let mut chunks = Vec::new();
+        let mut region_offset = 0;
+        let page_size = get_page_size()?;
+
+        self.with_regions_mut(|slot, region| {
+            let host_address = region.get_host_address(MemoryRegionAddress(0))? as *const u8;
+            match dirty_bitmap {
+                Some(dirty_bitmap) => {
+                    let kvm_bitmap = dirty_bitmap.get(&slot).unwrap();
+                    for_each_dirty_batch(
+                        region,
+                        kvm_bitmap,
+                        page_size,
+                        |batch_start, batch_size| {
+                            chunks.push(MemoryChunk {
+                                file_offset: region_offset + batch_start,
+                                host_address: host_address.wrapping_add(batch_start as usize),
+                                len: batch_size,
+                            });
+                            Ok(())
+                        },
+                    )?;
+                }
+                None => chunks.push(MemoryChunk {
+                    file_offset: region_offset,
+                    host_address,
+                    len: region.len() as usize,
+                }),
+            }
+
+            region_offset += region.len();
+            Ok(())
+        })
+        .map_err(Error::WriteMemory)?;
+        Ok(chunks)
+    }
+
+    /// Computes the SHA-256 digest of each region of GuestMemoryMmap.
+    fn digest_regions(&self) -> std::result::Result<Vec<Digest>, Error> {
+        let mut digests = Vec::new();
+        self.with_regions_mut(|_, region| {
+            let mut hasher = Sha256::new();
+            region.write_all_to(MemoryRegionAddress(0), &mut hasher, region.len() as usize)?;
+            digests.push(hasher.finalize());
+            Ok(())
+        })
+        .map_err(Error::WriteMemory)?;
+        Ok(digests)
+    }
+
     /// Creates a GuestMemoryMmap given a `file` containing the data
     /// and a `state` containing mapping information.
     fn restore(
@@ -202,6 +326,216 @@ impl SnapshotMemory for GuestMemoryMmap {
 
         Ok(Self::from_regions(mmap_regions).map_err(Error::CreateMemory)?)
     }
+
+    /// Creates a GuestMemoryMmap backed by anonymous memory, given a `reader`
+    /// streaming a full memory dump and a `state` containing mapping information.
+    fn restore_from_reader<T: std::io::Read>(
+        reader: &mut T,
+        state: &GuestMemoryState,
+        track_dirty_pages: bool,
+    ) -> std::result::Result<Self, Error> {
+        let ranges: Vec<(GuestAddress, usize)> = state
+            .regions
+            .iter()
+            .map(|region| (GuestAddress(region.base_address), region.size))
+            .collect();
+        let guest_memory =
+            Self::from_ranges_guarded(&ranges, track_dirty_pages).map_err(Error::CreateMemory)?;
+        guest_memory.load_from_reader(reader, state)?;
+
+        Ok(guest_memory)
+    }
+
+    /// Loads the contents of GuestMemoryMmap, laid out as described by `state`,
+    /// from a `reader` streaming a full memory dump.
+    fn load_from_reader<T: std::io::Read>(
+        &self,
+        reader: &mut T,
+        state: &GuestMemoryState,
+    ) -> std::result::Result<(), Error> {
+        // The regions are read in the order in which they are laid out in the stream.
+        let mut regions: Vec<&GuestMemoryRegionState> = state.regions.iter().collect();
+        regions.sort_by_key(|region| region.offset);
+        let mut reader_offset = 0;
+        for region_state in regions {
+            if region_state.offset < reader_offset {
+                return Err(Error::InvalidStream(format!(
+                    "Memory region at offset {:#x} overlaps the previous one.",
+                    region_state.offset
+                )));
+            }
+            // Skip over the data that doesn't belong to any region.
+            let gap = region_state.offset - reader_offset;
+            let skipped = std::io::copy(&mut reader.by_ref().take(gap), &mut std::io::sink())
+                .map_err(Error::Stream)?;
+            if skipped != gap {
+                return Err(Error::Stream(std::io::Error::from(
+                    std::io::ErrorKind::UnexpectedEof,
+                )));
+            }
+
+            let region = self
+                .find_region(GuestAddress(region_state.base_address))
+                .ok_or_else(|| {
+                    Error::InvalidStream(format!(
+                        "No memory region at address {:#x}.",
+                        region_state.base_address
+                    ))
+                })?;
+            region
+                .read_exact_from(MemoryRegionAddress(0), reader, region_state.size)
+                .map_err(Error::ReadMemory)?;
+            reader_offset = region_state.offset + region_state.size as u64;
+        }
+
+        reset_dirty_bitmaps(self);
+        Ok(())
+    }
+
+    /// Applies a streamed diff memory snapshot, read from `reader`, on top of the contents of
+    /// GuestMemoryMmap, laid out as described by `state`.
+    fn load_diff_from_reader<T: std::io::Read>(
+        &self,
+        reader: &mut T,
+        state: &GuestMemoryState,
+    ) -> std::result::Result<(), Error> {
+        let magic = read_le_u64(reader)?;
+        if magic != DIFF_STREAM_MAGIC {
+            return Err(Error::InvalidStream(format!(
+                "Unexpected magic value: {:#x}.",
+                magic
+            )));
+        }
+
+        loop {
+            let offset = read_le_u64(reader)?;
+            let len = read_le_u64(reader)?;
+            if len == 0 {
+                // The final record holds the size of the memory file.
+                let mem_file_len = state
+                    .regions
+                    .iter()
+                    .map(|region| region.offset + region.size as u64)
+                    .max()
+                    .unwrap_or(0);
+                if offset != mem_file_len {
+                    return Err(Error::InvalidStream(format!(
+                        "Stream describes a memory file of {:#x} bytes instead of {:#x}.",
+                        offset, mem_file_len
+                    )));
+                }
+                break;
+            }
+
+            // Each record holds a batch of pages of a single region.
+            let region_state = state
+                .regions
+                .iter()
+                .find(|region| {
+                    offset >= region.offset
+                        && offset
+                            .checked_add(len)
+                            .map_or(false, |end| end <= region.offset + region.size as u64)
+                })
+                .ok_or_else(|| {
+                    Error::InvalidStream(format!(
+                        "Record at offset {:#x} doesn't fit in a memory region.",
+                        offset
+                    ))
+                })?;
+            let region = self
+                .find_region(GuestAddress(region_state.base_address))
+                .ok_or_else(|| {
+                    Error::InvalidStream(format!(
+                        "No memory region at address {:#x}.",
+                        region_state.base_address
+                    ))
+                })?;
+            region
+                .read_exact_from(
+                    MemoryRegionAddress(offset - region_state.offset),
+                    reader,
+                    len as usize,
+                )
+                .map_err(Error::ReadMemory)?;
+        }
+
+        reset_dirty_bitmaps(self);
+        Ok(())
+    }
+}
+
+// Loading the memory doesn't count as dirtying it.
+fn reset_dirty_bitmaps(guest_memory: &GuestMemoryMmap) {
+    let _: std::result::Result<(), ()> = guest_memory.with_regions(|_, region| {
+        if let Some(bitmap) = region.dirty_bitmap() {
+            bitmap.reset();
+        }
+        Ok(())
+    });
+}
+
+/// Calls `f` with the offset and the size of each batch of contiguous pages of `region`
+/// which are dirty either in `kvm_bitmap` or in the Firecracker bitmap.
+fn for_each_dirty_batch<F>(
+    region: &GuestRegionMmap,
+    kvm_bitmap: &[u64],
+    page_size: usize,
+    mut f: F,
+) -> std::result::Result<(), GuestMemoryError>
+where
+    F: FnMut(u64, usize) -> std::result::Result<(), GuestMemoryError>,
+{
+    let firecracker_bitmap = region.dirty_bitmap().unwrap();
+    let mut write_size = 0;
+    let mut dirty_batch_start: u64 = 0;
+
+    for (i, v) in kvm_bitmap.iter().enumerate() {
+        for j in 0..64 {
+            let is_kvm_page_dirty = ((v >> j) & 1u64) != 0u64;
+            let page_offset = ((i * 64) + j) * page_size;
+            let is_firecracker_page_dirty = firecracker_bitmap.is_addr_set(page_offset);
+            if is_kvm_page_dirty || is_firecracker_page_dirty {
+                // We are at the start of a new batch of dirty pages.
+                if write_size == 0 {
+                    dirty_batch_start = page_offset as u64;
+                }
+                write_size += page_size;
+            } else if write_size > 0 {
+                // We are at the end of a batch of dirty pages.
+                f(dirty_batch_start, write_size)?;
+                write_size = 0;
+            }
+        }
+    }
+
+    if write_size > 0 {
+        f(dirty_batch_start, write_size)?;
+    }
+
+    Ok(())
+}
+
+/// Encodes the header of a streamed diff record of `len` bytes at `offset` in the memory file.
+pub fn diff_record_header(offset: u64, len: u64) -> [u8; 16] {
+    let mut header = [0u8; 16];
+    header[..8].copy_from_slice(&offset.to_le_bytes());
+    header[8..].copy_from_slice(&len.to_le_bytes());
+    header
+}
+
+fn write_diff_record_header<T: Write>(
+    writer: &mut T,
+    offset: u64,
+    len: u64,
+) -> std::io::Result<()> {
+    writer.write_all(&diff_record_header(offset, len))
+}
+
+fn read_le_u64<T: Read>(reader: &mut T) -> std::result::Result<u64, Error> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes).map_err(Error::Stream)?;
+    Ok(u64::from_le_bytes(bytes))
 }
 
 fn get_page_size() -> Result<usize, Error> {
@@ -392,4 +726,165 @@ mod tests {
             assert_eq!(expected_first_region, diff_file_content);
         }
     }
+
+    #[test]
+    fn test_restore_from_reader() {
+        let page_size: usize = get_page_size().unwrap();
+
+        // Two regions of two pages each, with a one page gap between them.
+        let mem_regions = [
+            (GuestAddress(0), page_size * 2),
+            (GuestAddress(page_size as u64 * 3), page_size * 2),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&mem_regions[..]).unwrap();
+        let first_region = vec![1u8; page_size * 2];
+        guest_memory
+            .write(&first_region[..], GuestAddress(0))
+            .unwrap();
+        let second_region = vec![2u8; page_size * 2];
+        guest_memory
+            .write(&second_region[..], GuestAddress(page_size as u64 * 3))
+            .unwrap();
+        let memory_state = guest_memory.describe();
+
+        let mut dump = Vec::new();
+        guest_memory.dump(&mut dump).unwrap();
+
+        let restored_guest_memory =
+            GuestMemoryMmap::restore_from_reader(&mut dump.as_slice(), &memory_state, true)
+                .unwrap();
+        assert_eq!(
+            restored_guest_memory.digest_regions().unwrap(),
+            guest_memory.digest_regions().unwrap()
+        );
+        // Loading the memory doesn't dirty it.
+        let _res: std::result::Result<(), Error> = restored_guest_memory.with_regions(|_, r| {
+            assert!(!r.dirty_bitmap().unwrap().is_bit_set(0));
+            Ok(())
+        });
+
+        // A truncated stream is rejected.
+        assert!(GuestMemoryMmap::restore_from_reader(
+            &mut &dump[..page_size * 3],
+            &memory_state,
+            false
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_dump_dirty_stream() {
+        let page_size: usize = get_page_size().unwrap();
+
+        // Two regions of two pages each, with a one page gap between them.
+        let mem_regions = [
+            (GuestAddress(0), page_size * 2),
+            (GuestAddress(page_size as u64 * 3), page_size * 2),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges_with_tracking(&mem_regions[..]).unwrap();
+        let ones = vec![1u8; page_size];
+        let twos = vec![2u8; page_size];
+        let zeros = vec![0u8; page_size];
+        guest_memory.write(&ones[..], GuestAddress(0)).unwrap();
+        guest_memory
+            .write(&twos[..], GuestAddress(page_size as u64 * 4))
+            .unwrap();
+
+        // KVM Bitmap
+        // First region pages: [clean, clean]
+        // Second region pages: [dirty, clean]
+        // Firecracker Bitmap
+        // First region pages: [dirty, clean]
+        // Second region pages: [clean, dirty]
+        let mut dirty_bitmap: DirtyBitmap = HashMap::new();
+        dirty_bitmap.insert(0, vec![0b00; 1]);
+        dirty_bitmap.insert(1, vec![0b01; 1]);
+
+        let mut stream = Vec::new();
+        guest_memory
+            .dump_dirty_stream(&mut stream, &dirty_bitmap)
+            .unwrap();
+        // The magic, one record for the first page, one record for the second region
+        // and the final record.
+        assert_eq!(
+            stream.len(),
+            8 + (16 + page_size) + (16 + page_size * 2) + 16
+        );
+
+        // Applying the stream on a base produces the same memory as a diff dump.
+        let base_memory = GuestMemoryMmap::from_ranges_with_tracking(&mem_regions[..]).unwrap();
+        let threes = vec![3u8; page_size * 4];
+        let state = guest_memory.describe();
+        base_memory
+            .load_from_reader(&mut threes.as_slice(), &state)
+            .unwrap();
+        base_memory
+            .load_diff_from_reader(&mut stream.as_slice(), &state)
+            .unwrap();
+        let mut restored_memory_file = Vec::new();
+        base_memory.dump(&mut restored_memory_file).unwrap();
+        let expected_memory_file = [
+            ones.as_slice(),
+            vec![3u8; page_size].as_slice(),
+            zeros.as_slice(),
+            twos.as_slice(),
+        ]
+        .concat();
+        assert_eq!(restored_memory_file, expected_memory_file);
+
+        // The Firecracker bitmap was reset.
+        let _res: std::result::Result<(), Error> = guest_memory.with_regions(|_, r| {
+            assert!(!r.dirty_bitmap().unwrap().is_bit_set(0));
+            assert!(!r.dirty_bitmap().unwrap().is_bit_set(1));
+            Ok(())
+        });
+
+        // Malformed streams are rejected.
+        assert!(base_memory
+            .load_diff_from_reader(&mut &stream[8..], &state)
+            .is_err());
+        assert!(base_memory
+            .load_diff_from_reader(&mut &stream[..stream.len() - 16], &state)
+            .is_err());
+        // The stream doesn't match the layout of the memory.
+        let mut small_state = guest_memory.describe();
+        small_state.regions.pop();
+        assert!(base_memory
+            .load_diff_from_reader(&mut stream.as_slice(), &small_state)
+            .is_err());
+    }
+
+    #[test]
+    fn test_digest_regions() {
+        let page_size: usize = get_page_size().unwrap();
+
+        let mem_regions = [
+            (GuestAddress(0), page_size),
+            (GuestAddress(page_size as u64 * 2), page_size),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&mem_regions[..]).unwrap();
+        let ones = vec![1u8; page_size];
+        guest_memory.write(&ones[..], GuestAddress(0)).unwrap();
+
+        let digests = guest_memory.digest_regions().unwrap();
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests[0], Sha256::digest(&ones));
+        assert_eq!(digests[1], Sha256::digest(&vec![0u8; page_size]));
+
+        // The digests of a restored memory must match the ones of the original memory.
+        let memory_file = TempFile::new().unwrap();
+        guest_memory.dump(&mut memory_file.as_file()).unwrap();
+        let restored_guest_memory =
+            GuestMemoryMmap::restore(&memory_file.as_file(), &guest_memory.describe(), false)
+                .unwrap();
+        assert_eq!(restored_guest_memory.digest_regions().unwrap(), digests);
+
+        // Changing a single byte changes the digest of its region only.
+        guest_memory
+            .write(&[2u8], GuestAddress(page_size as u64 * 2))
+            .unwrap();
+        let new_digests = guest_memory.digest_regions().unwrap();
+        assert_eq!(new_digests[0], digests[0]);
+        assert_ne!(new_digests[1], digests[1]);
+    }
 }
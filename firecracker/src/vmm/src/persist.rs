@@ -3,48 +3,375 @@
 
 //! Defines state structures for saving/restoring a Firecracker microVM.
 
+use std::ffi::CString;
 use std::fmt::{Display, Formatter};
 use std::fs::{File, OpenOptions};
-use std::io;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 use crate::builder::{self, StartMicrovmError};
 use crate::device_manager::persist::Error as DevicePersistError;
+use crate::measured_boot::BootMeasurements;
 use crate::mem_size_mib;
-use crate::vmm_config::machine_config::MAX_SUPPORTED_VCPUS;
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
+use crate::vmm_config::cpu_config::{CpuidModifier, CpuidRegister, CustomCpuTemplate, MsrModifier};
+use crate::vmm_config::machine_config::{CpuFeaturesTemplate, MemoryBackend, MAX_SUPPORTED_VCPUS};
+use crate::vmm_config::snapshot::{
+    CreateSnapshotParams, LoadSnapshotParams, SnapshotCreateState, SnapshotCreateStatus,
+    SnapshotType,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::vstate::vcpu::{build_vcpu_cpuid, VcpuConfig};
 use crate::vstate::{self, vcpu::VcpuState, vm::VmState};
 
 use crate::device_manager::persist::DeviceStates;
 use crate::memory_snapshot;
-use crate::memory_snapshot::{GuestMemoryState, SnapshotMemory};
+use crate::memory_snapshot::{
+    diff_record_header, GuestMemoryState, MemoryChunk, SnapshotMemory, DIFF_STREAM_MAGIC,
+};
 use crate::version_map::FC_VERSION_TO_SNAP_VERSION;
-use crate::{Error as VmmError, Vmm};
+use crate::{DirtyBitmap, Error as VmmError, Vmm};
 #[cfg(target_arch = "x86_64")]
 use cpuid::common::{get_vendor_id_from_cpuid, get_vendor_id_from_host};
+#[cfg(target_arch = "x86_64")]
+use cpuid::features::{mask_features, missing_features};
+#[cfg(target_arch = "x86_64")]
+use kvm_bindings::{CpuId, KVM_MAX_CPUID_ENTRIES};
 
 #[cfg(target_arch = "aarch64")]
 use arch::regs::{get_manufacturer_id_from_host, get_manufacturer_id_from_state};
-use logger::{error, info};
+use logger::{error, info, warn};
+use mmds::persist::MmdsVersionState;
 use polly::event_manager::EventManager;
 use seccomp::BpfProgramRef;
 use snapshot::Snapshot;
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use utils::errno;
+use utils::sha256::{HmacSha256, DIGEST_LEN};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
-use vm_memory::GuestMemoryMmap;
+use vm_memory::{GuestAddress, GuestMemoryMmap};
 
 #[cfg(target_arch = "x86_64")]
 const FC_V0_23_SNAP_VERSION: u16 = 1;
 #[cfg(target_arch = "x86_64")]
 const FC_V0_23_MAX_DEVICES: u32 = 11;
 
+/// Minimum accepted size of a snapshot integrity key. Shorter keys would weaken the
+/// HMAC-SHA256 signature below the strength of the underlying hash.
+const MIN_INTEGRITY_KEY_LEN: usize = DIGEST_LEN;
+
+/// The serializable state of a CPU template.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub enum CpuTemplateState {
+    /// C3 Template.
+    C3,
+    /// T2 Template.
+    T2,
+    /// T2 Template for AMD hosts.
+    T2A,
+    /// Neoverse N1 Template for aarch64 hosts.
+    V1N1,
+}
+
+impl From<CpuFeaturesTemplate> for CpuTemplateState {
+    fn from(template: CpuFeaturesTemplate) -> Self {
+        match template {
+            CpuFeaturesTemplate::C3 => CpuTemplateState::C3,
+            CpuFeaturesTemplate::T2 => CpuTemplateState::T2,
+            CpuFeaturesTemplate::T2A => CpuTemplateState::T2A,
+            CpuFeaturesTemplate::V1N1 => CpuTemplateState::V1N1,
+        }
+    }
+}
+
+impl From<CpuTemplateState> for CpuFeaturesTemplate {
+    fn from(state: CpuTemplateState) -> Self {
+        match state {
+            CpuTemplateState::C3 => CpuFeaturesTemplate::C3,
+            CpuTemplateState::T2 => CpuFeaturesTemplate::T2,
+            CpuTemplateState::T2A => CpuFeaturesTemplate::T2A,
+            CpuTemplateState::V1N1 => CpuFeaturesTemplate::V1N1,
+        }
+    }
+}
+
+/// The serializable state of a memory backend.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub enum MemoryBackendState {
+    /// Private anonymous memory.
+    Anonymous,
+    /// Shared memory file.
+    SharedMemfd,
+    /// Hugetlbfs memory file using 2 MiB pages.
+    Hugetlbfs2M,
+    /// Hugetlbfs memory file using 1 GiB pages.
+    Hugetlbfs1G,
+}
+
+impl From<MemoryBackend> for MemoryBackendState {
+    fn from(backend: MemoryBackend) -> Self {
+        match backend {
+            MemoryBackend::Anonymous => MemoryBackendState::Anonymous,
+            MemoryBackend::SharedMemfd => MemoryBackendState::SharedMemfd,
+            MemoryBackend::Hugetlbfs2M => MemoryBackendState::Hugetlbfs2M,
+            MemoryBackend::Hugetlbfs1G => MemoryBackendState::Hugetlbfs1G,
+        }
+    }
+}
+
+impl From<MemoryBackendState> for MemoryBackend {
+    fn from(state: MemoryBackendState) -> Self {
+        match state {
+            MemoryBackendState::Anonymous => MemoryBackend::Anonymous,
+            MemoryBackendState::SharedMemfd => MemoryBackend::SharedMemfd,
+            MemoryBackendState::Hugetlbfs2M => MemoryBackend::Hugetlbfs2M,
+            MemoryBackendState::Hugetlbfs1G => MemoryBackend::Hugetlbfs1G,
+        }
+    }
+}
+
+/// The serializable state of a CPUID register.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub enum CpuidRegisterState {
+    /// EAX register.
+    Eax,
+    /// EBX register.
+    Ebx,
+    /// ECX register.
+    Ecx,
+    /// EDX register.
+    Edx,
+}
+
+impl From<CpuidRegister> for CpuidRegisterState {
+    fn from(register: CpuidRegister) -> Self {
+        match register {
+            CpuidRegister::Eax => CpuidRegisterState::Eax,
+            CpuidRegister::Ebx => CpuidRegisterState::Ebx,
+            CpuidRegister::Ecx => CpuidRegisterState::Ecx,
+            CpuidRegister::Edx => CpuidRegisterState::Edx,
+        }
+    }
+}
+
+impl From<CpuidRegisterState> for CpuidRegister {
+    fn from(state: CpuidRegisterState) -> Self {
+        match state {
+            CpuidRegisterState::Eax => CpuidRegister::Eax,
+            CpuidRegisterState::Ebx => CpuidRegister::Ebx,
+            CpuidRegisterState::Ecx => CpuidRegister::Ecx,
+            CpuidRegisterState::Edx => CpuidRegister::Edx,
+        }
+    }
+}
+
+/// The serializable state of a CPUID modifier of a custom CPU template.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct CpuidModifierState {
+    leaf: u32,
+    subleaf: u32,
+    register: CpuidRegisterState,
+    mask: u32,
+    value: u32,
+}
+
+/// The serializable state of an MSR modifier of a custom CPU template.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct MsrModifierState {
+    addr: u32,
+    mask: u64,
+    value: u64,
+}
+
+/// The serializable state of a custom CPU template.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct CustomCpuTemplateState {
+    cpuid_modifiers: Vec<CpuidModifierState>,
+    msr_modifiers: Vec<MsrModifierState>,
+}
+
+impl From<&CustomCpuTemplate> for CustomCpuTemplateState {
+    fn from(template: &CustomCpuTemplate) -> Self {
+        CustomCpuTemplateState {
+            cpuid_modifiers: template
+                .cpuid_modifiers
+                .iter()
+                .map(|modifier| CpuidModifierState {
+                    leaf: modifier.leaf,
+                    subleaf: modifier.subleaf,
+                    register: modifier.register.into(),
+                    mask: modifier.mask,
+                    value: modifier.value,
+                })
+                .collect(),
+            msr_modifiers: template
+                .msr_modifiers
+                .iter()
+                .map(|modifier| MsrModifierState {
+                    addr: modifier.addr,
+                    mask: modifier.mask,
+                    value: modifier.value,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&CustomCpuTemplateState> for CustomCpuTemplate {
+    fn from(state: &CustomCpuTemplateState) -> Self {
+        CustomCpuTemplate {
+            cpuid_modifiers: state
+                .cpuid_modifiers
+                .iter()
+                .map(|modifier| CpuidModifier {
+                    leaf: modifier.leaf,
+                    subleaf: modifier.subleaf,
+                    register: modifier.register.into(),
+                    mask: modifier.mask,
+                    value: modifier.value,
+                })
+                .collect(),
+            msr_modifiers: state
+                .msr_modifiers
+                .iter()
+                .map(|modifier| MsrModifier {
+                    addr: modifier.addr,
+                    mask: modifier.mask,
+                    value: modifier.value,
+                })
+                .collect(),
+        }
+    }
+}
+
 /// Holds information related to the VM that is not part of VmState.
 #[derive(Debug, PartialEq, Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct VmInfo {
     /// Guest memory size.
     pub mem_size_mib: u64,
+    /// CPU template used when booting the microVM.
+    #[version(
+        start = 2,
+        ser_fn = "cpu_template_ser",
+        default_fn = "default_cpu_template"
+    )]
+    pub cpu_template: Option<CpuTemplateState>,
+    /// Whether hyperthreading was enabled in the CPUID used when booting the microVM.
+    #[version(start = 2, default_fn = "default_ht_enabled")]
+    pub ht_enabled: bool,
+    /// Type of memory backing the guest memory, which is also used after restoring.
+    #[version(
+        start = 2,
+        ser_fn = "mem_backend_ser",
+        default_fn = "default_mem_backend"
+    )]
+    pub mem_backend: MemoryBackendState,
+    /// Custom CPU template used when booting the microVM.
+    #[version(
+        start = 2,
+        ser_fn = "custom_cpu_template_ser",
+        default_fn = "default_custom_cpu_template"
+    )]
+    pub custom_cpu_template: Option<CustomCpuTemplateState>,
+    /// Number of vCPUs running the guest, the other ones being parked. `None` stands for all
+    /// the vCPUs.
+    #[version(start = 2, default_fn = "default_online_vcpu_count")]
+    pub online_vcpu_count: Option<u8>,
+    /// Measurements of the boot components, if the microVM was booted with measured boot.
+    #[version(start = 2, default_fn = "default_boot_measurements")]
+    pub boot_measurements: Option<BootMeasurements>,
+    /// MMDS version, which the MMDS of the restored microVM keeps using.
+    #[version(
+        start = 2,
+        ser_fn = "mmds_version_ser",
+        default_fn = "default_mmds_version"
+    )]
+    pub mmds_version: MmdsVersionState,
+}
+
+impl VmInfo {
+    fn cpu_template_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.cpu_template.is_some() {
+            warn!(
+                "Target version does not record the CPU template. The snapshot can only be \
+                 restored on hosts supporting all the CPU features of the current host."
+            );
+        }
+
+        Ok(())
+    }
+
+    fn default_cpu_template(_source_version: u16) -> Option<CpuTemplateState> {
+        None
+    }
+
+    fn default_ht_enabled(_source_version: u16) -> bool {
+        false
+    }
+
+    fn mem_backend_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.mem_backend != MemoryBackendState::Anonymous {
+            warn!(
+                "Target version does not record the memory backend. The snapshot will be \
+                 restored using anonymous memory."
+            );
+        }
+
+        Ok(())
+    }
+
+    fn default_mem_backend(_source_version: u16) -> MemoryBackendState {
+        MemoryBackendState::Anonymous
+    }
+
+    fn custom_cpu_template_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.custom_cpu_template.is_some() {
+            warn!(
+                "Target version does not record the custom CPU template. The snapshot can only \
+                 be restored on hosts supporting all the CPU features of the current host."
+            );
+        }
+
+        Ok(())
+    }
+
+    fn default_custom_cpu_template(_source_version: u16) -> Option<CustomCpuTemplateState> {
+        None
+    }
+
+    fn default_online_vcpu_count(_source_version: u16) -> Option<u8> {
+        None
+    }
+
+    fn default_boot_measurements(_source_version: u16) -> Option<BootMeasurements> {
+        None
+    }
+
+    fn mmds_version_ser(&mut self, target_version: u16) -> VersionizeResult<()> {
+        // Falling back to V1 would expose the MMDS to the guest without session tokens.
+        if target_version < 2 && self.mmds_version != MmdsVersionState::V1 {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the MMDS session tokens.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn default_mmds_version(_source_version: u16) -> MmdsVersionState {
+        MmdsVersionState::V1
+    }
 }
 
 /// Contains the necesary state for saving/restoring a microVM.
@@ -63,6 +390,26 @@ pub struct MicrovmState {
     pub device_states: DeviceStates,
 }
 
+/// Describes the guest memory file of an authenticated snapshot.
+///
+/// An authenticated state file has the following layout, where the signature is an
+/// HMAC-SHA256 computed over everything following it:
+///
+///  |----------------------------|
+///  |     HMAC-SHA256 (32 B)     |
+///  |----------------------------|
+///  |      SnapshotManifest      |
+///  |----------------------------|
+///  |   Snapshot (with CRC64)    |
+///  |----------------------------|
+#[derive(Debug, Default, PartialEq, Versionize)]
+// NOTICE: This structure is serialized at version 1 with an empty version map, independently
+// of the snapshot data version. Any changes to it require a version map of its own.
+pub struct SnapshotManifest {
+    /// SHA-256 digest of each guest memory region, in the order of `GuestMemoryState::regions`.
+    pub memory_region_digests: Vec<Vec<u8>>,
+}
+
 /// Errors related to saving and restoring Microvm state.
 #[derive(Debug)]
 pub enum MicrovmStateError {
@@ -70,12 +417,16 @@ pub enum MicrovmStateError {
     InvalidInput,
     /// Operation not allowed.
     NotAllowed(String),
+    /// Failed to restore the MMDS version.
+    RestoreMmdsVersion(mmds::token::Error),
     /// Failed to restore devices.
     RestoreDevices(DevicePersistError),
     /// Failed to restore Vcpu state.
     RestoreVcpuState(vstate::vcpu::Error),
     /// Failed to restore VM state.
     RestoreVmState(vstate::vm::Error),
+    /// Failed to save the state of the TPM emulator.
+    SaveTpmState(devices::tpm::Error),
     /// Failed to save Vcpu state.
     SaveVcpuState(vstate::vcpu::Error),
     /// Failed to save VM state.
@@ -92,9 +443,13 @@ impl Display for MicrovmStateError {
         match self {
             InvalidInput => write!(f, "Provided MicroVM state is invalid."),
             NotAllowed(msg) => write!(f, "Operation not allowed: {}", msg),
+            RestoreMmdsVersion(err) => {
+                write!(f, "Cannot restore the MMDS version. Error: {}", err)
+            }
             RestoreDevices(err) => write!(f, "Cannot restore devices. Error: {:?}", err),
             RestoreVcpuState(err) => write!(f, "Cannot restore Vcpu state. Error: {:?}", err),
             RestoreVmState(err) => write!(f, "Cannot restore Vm state. Error: {:?}", err),
+            SaveTpmState(err) => write!(f, "Cannot save TPM state. Error: {}", err),
             SaveVcpuState(err) => write!(f, "Cannot save Vcpu state. Error: {:?}", err),
             SaveVmState(err) => write!(f, "Cannot save Vm state. Error: {:?}", err),
             SignalVcpu(err) => write!(f, "Cannot signal Vcpu: {:?}", err),
@@ -106,8 +461,16 @@ impl Display for MicrovmStateError {
 /// Errors associated with creating a snapshot.
 #[derive(Debug)]
 pub enum CreateSnapshotError {
+    /// Failed to spawn the background memory dump.
+    BackgroundDump(io::Error),
+    /// A background memory dump is still in progress.
+    BackgroundDumpInProgress,
+    /// Background memory dumps need private guest memory.
+    BackgroundDumpSharedMemory(MemoryBackend),
     /// Failed to get dirty bitmap.
     DirtyBitmap,
+    /// Failed to read the snapshot integrity key.
+    IntegrityKey(io::Error),
     /// Failed to translate microVM version to snapshot data version.
     InvalidVersion,
     /// Failed to save VM state.
@@ -131,7 +494,18 @@ impl Display for CreateSnapshotError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         use self::CreateSnapshotError::*;
         match self {
+            BackgroundDump(err) => write!(f, "Cannot start background memory dump: {}", err),
+            BackgroundDumpInProgress => write!(
+                f,
+                "Cannot create a snapshot while the previous one is still being written"
+            ),
+            BackgroundDumpSharedMemory(backend) => write!(
+                f,
+                "Cannot create a snapshot in the background with the {} memory backend",
+                backend
+            ),
             DirtyBitmap => write!(f, "Cannot get dirty bitmap"),
+            IntegrityKey(err) => write!(f, "Cannot read snapshot integrity key: {}", err),
             InvalidVersion => write!(
                 f,
                 "Cannot translate microVM version to snapshot data version"
@@ -162,6 +536,10 @@ pub enum LoadSnapshotError {
     DeserializeMemory(memory_snapshot::Error),
     /// Failed to deserialize microVM state.
     DeserializeMicrovmState(snapshot::Error),
+    /// Snapshot files do not match their signature or memory digests.
+    IntegrityCheck(String),
+    /// Failed to read the snapshot integrity key.
+    IntegrityKey(io::Error),
     /// Failed to open memory backing file.
     MemoryBackingFile(io::Error),
     /// Failed to resume Vm after loading snapshot.
@@ -172,6 +550,8 @@ pub enum LoadSnapshotError {
     SnapshotBackingFileMetadata(io::Error),
     /// Snapshot cpu vendor differs than host cpu vendor.
     CpuVendorMismatch(String),
+    /// Snapshot cpu features are not supported by the host.
+    CpuFeaturesMismatch(String),
     /// Snapshot failed sanity checks.
     InvalidSnapshot(String),
 }
@@ -183,87 +563,560 @@ impl Display for LoadSnapshotError {
             BuildMicroVm(err) => write!(f, "Cannot build a microVM from snapshot: {}", err),
             DeserializeMemory(err) => write!(f, "Cannot deserialize memory: {}", err),
             DeserializeMicrovmState(err) => write!(f, "Cannot deserialize MicrovmState: {:?}", err),
+            IntegrityCheck(err) => write!(f, "Snapshot integrity check failed: {}", err),
+            IntegrityKey(err) => write!(f, "Cannot read snapshot integrity key: {}", err),
             MemoryBackingFile(err) => write!(f, "Cannot open memory file: {}", err),
             ResumeMicroVm(err) => write!(f, "Failed to resume Vm after loading snapshot: {}", err),
             SnapshotBackingFile(err) => write!(f, "Cannot open snapshot file: {}", err),
             SnapshotBackingFileMetadata(err) => write!(f, "Cannot retrieve file metadata: {}", err),
             CpuVendorMismatch(err) => write!(f, "Snapshot cpu vendor mismatch: {}", err),
+            CpuFeaturesMismatch(err) => write!(f, "Snapshot cpu features mismatch: {}", err),
             InvalidSnapshot(err) => write!(f, "Snapshot sanity check failed: {}", err),
         }
     }
 }
 
+/// Tracks a guest memory dump running in a forked child process.
+///
+/// The child sees guest memory as it was when the snapshot was taken, because the memory
+/// is mapped privately and thus shared copy-on-write with the parent, so the guest can be
+/// resumed while the memory file is being written.
+pub struct BackgroundSnapshot {
+    pid: libc::pid_t,
+    // Read end of the pipe on which the child reports why the snapshot failed.
+    error_pipe: File,
+    // The final status, once the child has been reaped.
+    outcome: Option<SnapshotCreateStatus>,
+}
+
+impl BackgroundSnapshot {
+    /// Returns the status of the snapshot creation, reaping the child if it has exited.
+    pub fn status(&mut self) -> SnapshotCreateStatus {
+        if let Some(outcome) = self.outcome.as_ref() {
+            return outcome.clone();
+        }
+
+        let mut wait_status = 0;
+        // Safe because `pid` is our child and we pass a valid pointer to a local variable.
+        let ret = unsafe { libc::waitpid(self.pid, &mut wait_status, libc::WNOHANG) };
+        let outcome = match ret {
+            0 => {
+                return SnapshotCreateStatus {
+                    state: SnapshotCreateState::InProgress,
+                    error: None,
+                }
+            }
+            -1 => Some(format!(
+                "Cannot wait for the memory dump process: {}",
+                io::Error::last_os_error()
+            )),
+            _ if libc::WIFEXITED(wait_status) && libc::WEXITSTATUS(wait_status) == 0 => None,
+            _ => {
+                // Both write ends are closed by now, so this doesn't block.
+                let mut record = Vec::new();
+                let _ = self.error_pipe.read_to_end(&mut record);
+                let step = record
+                    .get(..4)
+                    .and_then(|step| BackgroundDumpStep::from_u32(read_le_u32(step)));
+                Some(match (step, record.get(4..8)) {
+                    (Some(step), Some(errno)) => format!(
+                        "{}: {}",
+                        step,
+                        io::Error::from_raw_os_error(read_le_u32(errno) as i32)
+                    ),
+                    _ => format!(
+                        "The memory dump process terminated with status {:#x}",
+                        wait_status
+                    ),
+                })
+            }
+        };
+        let outcome = SnapshotCreateStatus {
+            state: match outcome {
+                None => SnapshotCreateState::Completed,
+                Some(_) => SnapshotCreateState::Failed,
+            },
+            error: outcome,
+        };
+        self.outcome = Some(outcome.clone());
+        outcome
+    }
+}
+
+// `bytes` must be 4 bytes long.
+fn read_le_u32(bytes: &[u8]) -> u32 {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(bytes);
+    u32::from_le_bytes(value)
+}
+
 /// Creates a Microvm snapshot.
 pub fn create_snapshot(
     vmm: &mut Vmm,
     params: &CreateSnapshotParams,
     version_map: VersionMap,
 ) -> std::result::Result<(), CreateSnapshotError> {
+    if vmm.snapshot_create_status().state == SnapshotCreateState::InProgress {
+        return Err(CreateSnapshotError::BackgroundDumpInProgress);
+    }
+    // The background process only gets a frozen copy of private guest memory. Shared
+    // memory keeps being written by the guest while it is dumped.
+    if params.background && vmm.mem_backend.is_shared() {
+        return Err(CreateSnapshotError::BackgroundDumpSharedMemory(
+            vmm.mem_backend,
+        ));
+    }
+
     let microvm_state = vmm
         .save_state()
         .map_err(CreateSnapshotError::MicrovmState)?;
 
-    snapshot_memory_to_file(vmm, &params.mem_file_path, &params.snapshot_type)?;
-
     let snapshot_data_version = get_snapshot_data_version(&params.version, &version_map, &vmm)?;
 
-    snapshot_state_to_file(
-        &microvm_state,
-        &params.snapshot_path,
-        snapshot_data_version,
-        version_map,
-    )?;
+    let integrity_key = params
+        .integrity_key_path
+        .as_ref()
+        .map(read_integrity_key)
+        .transpose()
+        .map_err(CreateSnapshotError::IntegrityKey)?;
+
+    let guest_memory = vmm.guest_memory();
+    // The KVM dirty log is reset when read, so it has to be collected by the process that
+    // keeps running the guest. The dirty pages tracked by Firecracker, on the other hand,
+    // are not cleared in background mode, which means that the next diff snapshot will
+    // include them again.
+    let dirty_bitmap = match params.snapshot_type {
+        SnapshotType::Diff => Some(
+            vmm.get_dirty_bitmap()
+                .map_err(|_| CreateSnapshotError::DirtyBitmap)?,
+        ),
+        SnapshotType::Full => None,
+    };
+
+    let state_data = match integrity_key {
+        Some(key) => {
+            let manifest = SnapshotManifest {
+                memory_region_digests: guest_memory
+                    .digest_regions()
+                    .map_err(CreateSnapshotError::Memory)?
+                    .iter()
+                    .map(|digest| digest.to_vec())
+                    .collect(),
+            };
+            signed_snapshot_state_data(
+                &microvm_state,
+                &manifest,
+                &key,
+                snapshot_data_version,
+                version_map,
+            )
+        }
+        None => snapshot_state_data(&microvm_state, snapshot_data_version, version_map),
+    }?;
+
+    if params.background {
+        let background_dump =
+            BackgroundDumpJob::new(guest_memory, params, state_data, dirty_bitmap.as_ref())?;
+        let background_snapshot = spawn_background_snapshot(&background_dump)?;
+        vmm.set_background_snapshot(background_snapshot);
+        return Ok(());
+    }
+
+    // The state is written first, so that it can be consumed before the memory when
+    // both are streamed. Opening a named pipe blocks until its reader shows up, so the
+    // memory file is only opened once the state is written.
+    open_snapshot_destination(&params.snapshot_path)
+        .and_then(|mut snapshot_file| snapshot_file.write_all(&state_data))
+        .map_err(CreateSnapshotError::SnapshotBackingFile)?;
+    let mut mem_file = memory_file(guest_memory, &params.mem_file_path)?;
+    snapshot_memory_to_file(guest_memory, &mut mem_file, dirty_bitmap.as_ref())
+}
+
+/// The steps of a background memory dump, as reported by the child when they fail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BackgroundDumpStep {
+    OpenSnapshotFile = 1,
+    WriteSnapshotFile = 2,
+    OpenMemoryFile = 3,
+    WriteMemoryFile = 4,
+}
+
+impl BackgroundDumpStep {
+    fn from_u32(step: u32) -> Option<Self> {
+        use self::BackgroundDumpStep::*;
+        match step {
+            1 => Some(OpenSnapshotFile),
+            2 => Some(WriteSnapshotFile),
+            3 => Some(OpenMemoryFile),
+            4 => Some(WriteMemoryFile),
+            _ => None,
+        }
+    }
+}
+
+impl Display for BackgroundDumpStep {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::BackgroundDumpStep::*;
+        match self {
+            OpenSnapshotFile => write!(f, "Cannot open snapshot file"),
+            WriteSnapshotFile => write!(f, "Cannot write snapshot file"),
+            OpenMemoryFile => write!(f, "Cannot open memory file"),
+            WriteMemoryFile => write!(f, "Cannot write memory file"),
+        }
+    }
+}
+
+/// A snapshot file written by the background memory dump process.
+enum BackgroundDestination {
+    /// A regular file or a connected Unix socket.
+    File(File),
+    /// A named pipe, which is opened by the child, since opening it blocks until its
+    /// reader shows up.
+    Fifo(CString),
+}
+
+impl BackgroundDestination {
+    // Returns the path to pass to `open` if `path` is a named pipe.
+    fn fifo_path(path: &PathBuf) -> io::Result<Option<CString>> {
+        if !std::fs::metadata(path)
+            .map(|metadata| metadata.file_type().is_fifo())
+            .unwrap_or(false)
+        {
+            return Ok(None);
+        }
+        CString::new(path.as_os_str().as_bytes())
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+    }
+
+    // Only makes async-signal-safe calls.
+    fn open(&self) -> std::result::Result<RawFd, i32> {
+        match self {
+            BackgroundDestination::File(file) => Ok(file.as_raw_fd()),
+            BackgroundDestination::Fifo(path) => {
+                // Safe because `path` is a valid, nul terminated string.
+                match unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) } {
+                    -1 => Err(errno::Error::last().errno()),
+                    fd => Ok(fd),
+                }
+            }
+        }
+    }
+}
+
+/// How the background memory dump process lays out the memory chunks in the memory file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MemoryLayout {
+    /// The chunks are contiguous and written one after the other.
+    Sequential,
+    /// The chunks are written at their offset in the memory file.
+    Seek,
+    /// The chunks are written in the streamed diff format, for a memory file of the given size.
+    DiffStream(u64),
+}
+
+/// Everything the background memory dump process needs, prepared before forking so that
+/// the child doesn't have to allocate.
+struct BackgroundDumpJob {
+    state_data: Vec<u8>,
+    snapshot_file: BackgroundDestination,
+    mem_file: BackgroundDestination,
+    mem_chunks: Vec<MemoryChunk>,
+    mem_layout: MemoryLayout,
+}
+
+impl BackgroundDumpJob {
+    fn new(
+        guest_memory: &GuestMemoryMmap,
+        params: &CreateSnapshotParams,
+        state_data: Vec<u8>,
+        dirty_bitmap: Option<&DirtyBitmap>,
+    ) -> std::result::Result<Self, CreateSnapshotError> {
+        use self::CreateSnapshotError::*;
+        let snapshot_file = match BackgroundDestination::fifo_path(&params.snapshot_path)
+            .map_err(SnapshotBackingFile)?
+        {
+            Some(path) => BackgroundDestination::Fifo(path),
+            None => BackgroundDestination::File(
+                open_snapshot_destination(&params.snapshot_path).map_err(SnapshotBackingFile)?,
+            ),
+        };
+        let mem_file = match BackgroundDestination::fifo_path(&params.mem_file_path)
+            .map_err(MemoryBackingFile)?
+        {
+            Some(path) => BackgroundDestination::Fifo(path),
+            None => BackgroundDestination::File(memory_file(guest_memory, &params.mem_file_path)?),
+        };
+
+        let mem_seekable = match &mem_file {
+            BackgroundDestination::File(file) => {
+                file.metadata().map_err(MemoryBackingFile)?.is_file()
+            }
+            BackgroundDestination::Fifo(_) => false,
+        };
+        let mem_layout = match dirty_bitmap {
+            Some(_) if mem_seekable => MemoryLayout::Seek,
+            Some(_) => MemoryLayout::DiffStream(
+                guest_memory
+                    .describe()
+                    .regions
+                    .iter()
+                    .map(|region| region.size as u64)
+                    .sum(),
+            ),
+            None => MemoryLayout::Sequential,
+        };
 
+        Ok(BackgroundDumpJob {
+            state_data,
+            snapshot_file,
+            mem_file,
+            mem_chunks: guest_memory.dump_chunks(dirty_bitmap).map_err(Memory)?,
+            mem_layout,
+        })
+    }
+
+    /// Writes the snapshot files, returning the failed step and `errno` on error.
+    ///
+    /// This runs in the child forked from the multithreaded Firecracker process, so it only
+    /// makes async-signal-safe calls: it doesn't allocate, take locks or log.
+    fn write(&self) -> std::result::Result<(), (BackgroundDumpStep, i32)> {
+        use self::BackgroundDumpStep::*;
+
+        let snapshot_fd = self
+            .snapshot_file
+            .open()
+            .map_err(|errno| (OpenSnapshotFile, errno))?;
+        write_all_raw(snapshot_fd, self.state_data.as_ptr(), self.state_data.len())
+            .map_err(|errno| (WriteSnapshotFile, errno))?;
+        // The child exits without running destructors, so the state file is closed here
+        // for its reader to see the end of it before the memory is written.
+        // Safe because `snapshot_fd` is a valid file descriptor which isn't used anymore.
+        unsafe { libc::close(snapshot_fd) };
+
+        let mem_fd = self
+            .mem_file
+            .open()
+            .map_err(|errno| (OpenMemoryFile, errno))?;
+        let write_memory = |buf: *const u8, len: usize| {
+            write_all_raw(mem_fd, buf, len).map_err(|errno| (WriteMemoryFile, errno))
+        };
+        if let MemoryLayout::DiffStream(_) = self.mem_layout {
+            let magic = DIFF_STREAM_MAGIC.to_le_bytes();
+            write_memory(magic.as_ptr(), magic.len())?;
+        }
+        for chunk in self.mem_chunks.iter() {
+            match self.mem_layout {
+                MemoryLayout::Sequential => (),
+                MemoryLayout::Seek => {
+                    // Safe because `mem_fd` is a valid file descriptor.
+                    if unsafe {
+                        libc::lseek(mem_fd, chunk.file_offset as libc::off_t, libc::SEEK_SET)
+                    } < 0
+                    {
+                        return Err((WriteMemoryFile, errno::Error::last().errno()));
+                    }
+                }
+                MemoryLayout::DiffStream(_) => {
+                    let header = diff_record_header(chunk.file_offset, chunk.len as u64);
+                    write_memory(header.as_ptr(), header.len())?;
+                }
+            }
+            write_memory(chunk.host_address, chunk.len)?;
+        }
+        if let MemoryLayout::DiffStream(mem_file_len) = self.mem_layout {
+            // The stream ends with an empty record holding the size of the memory file.
+            let header = diff_record_header(mem_file_len, 0);
+            write_memory(header.as_ptr(), header.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes `len` bytes starting at `buf` to `fd`, only making async-signal-safe calls.
+fn write_all_raw(fd: RawFd, mut buf: *const u8, mut len: usize) -> std::result::Result<(), i32> {
+    while len > 0 {
+        // Safe because the caller guarantees that `buf` points to `len` readable bytes.
+        match unsafe { libc::write(fd, buf as *const libc::c_void, len) } {
+            -1 => {
+                let errno = errno::Error::last().errno();
+                if errno != libc::EINTR {
+                    return Err(errno);
+                }
+            }
+            written => {
+                buf = buf.wrapping_add(written as usize);
+                len -= written as usize;
+            }
+        }
+    }
     Ok(())
 }
 
-fn snapshot_state_to_file(
+/// Writes the snapshot files described by `background_dump` in a forked child process.
+fn spawn_background_snapshot(
+    background_dump: &BackgroundDumpJob,
+) -> std::result::Result<BackgroundSnapshot, CreateSnapshotError> {
+    use self::CreateSnapshotError::BackgroundDump;
+
+    let mut fds = [-1; 2];
+    // Safe because we pass a valid pointer to an array of two file descriptors.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(BackgroundDump(io::Error::last_os_error()));
+    }
+    // Safe because the file descriptors have just been created and nothing else owns them.
+    let (error_pipe, error_writer) =
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // Safe because the child only makes async-signal-safe calls before exiting: everything
+    // it needs is allocated, serialized and opened beforehand, and it only writes these
+    // buffers and the guest memory to file descriptors. This matters because the other
+    // threads of the parent may hold locks, e.g. the allocator's, at the time of the fork.
+    match unsafe { libc::fork() } {
+        -1 => Err(BackgroundDump(io::Error::last_os_error())),
+        0 => {
+            let exit_code = match background_dump.write() {
+                Ok(()) => 0,
+                Err((step, errno)) => {
+                    let mut record = [0u8; 8];
+                    record[..4].copy_from_slice(&(step as u32).to_le_bytes());
+                    record[4..].copy_from_slice(&errno.to_le_bytes());
+                    // Nothing more can be done if the parent can't be told about the error;
+                    // it still sees the non-zero exit code.
+                    let _ = write_all_raw(error_writer.as_raw_fd(), record.as_ptr(), record.len());
+                    1
+                }
+            };
+            // Safe because the child must not run the exit handlers of the parent.
+            unsafe { libc::_exit(exit_code) }
+        }
+        pid => Ok(BackgroundSnapshot {
+            pid,
+            error_pipe,
+            outcome: None,
+        }),
+    }
+}
+
+/// Serializes `microvm_state` into the contents of the snapshot file.
+fn snapshot_state_data(
     microvm_state: &MicrovmState,
-    snapshot_path: &PathBuf,
     snapshot_data_version: u16,
     version_map: VersionMap,
-) -> std::result::Result<(), CreateSnapshotError> {
-    use self::CreateSnapshotError::*;
-    let mut snapshot_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(snapshot_path)
-        .map_err(SnapshotBackingFile)?;
+) -> std::result::Result<Vec<u8>, CreateSnapshotError> {
+    let mut snapshot_data = Vec::new();
+    let mut snapshot = Snapshot::new(version_map, snapshot_data_version);
+    snapshot
+        .save(&mut snapshot_data, microvm_state)
+        .map_err(CreateSnapshotError::SerializeMicrovmState)?;
+    Ok(snapshot_data)
+}
 
+/// Serializes `microvm_state` and `manifest` into the contents of a snapshot file
+/// signed with `key`.
+fn signed_snapshot_state_data(
+    microvm_state: &MicrovmState,
+    manifest: &SnapshotManifest,
+    key: &[u8],
+    snapshot_data_version: u16,
+    version_map: VersionMap,
+) -> std::result::Result<Vec<u8>, CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+    let mut signed_data = Vec::new();
+    manifest
+        .serialize(&mut signed_data, &VersionMap::new(), 1)
+        .map_err(|err| SerializeMicrovmState(snapshot::Error::Versionize(err)))?;
     let mut snapshot = Snapshot::new(version_map, snapshot_data_version);
     snapshot
-        .save(&mut snapshot_file, microvm_state)
+        .save(&mut signed_data, microvm_state)
         .map_err(SerializeMicrovmState)?;
 
-    Ok(())
+    let mut snapshot_data = HmacSha256::mac(key, &signed_data).to_vec();
+    snapshot_data.extend_from_slice(&signed_data);
+    Ok(snapshot_data)
 }
 
-fn snapshot_memory_to_file(
-    vmm: &Vmm,
-    mem_file_path: &PathBuf,
-    snapshot_type: &SnapshotType,
-) -> std::result::Result<(), CreateSnapshotError> {
-    use self::CreateSnapshotError::*;
-    let mut file = OpenOptions::new()
+/// Reads a snapshot integrity key from `key_path`.
+fn read_integrity_key(key_path: &PathBuf) -> io::Result<Vec<u8>> {
+    let key = std::fs::read(key_path)?;
+    if key.len() < MIN_INTEGRITY_KEY_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the key must be at least {} bytes long",
+                MIN_INTEGRITY_KEY_LEN
+            ),
+        ));
+    }
+    Ok(key)
+}
+
+/// Opens `path` for writing a snapshot file, connecting to it if it is a Unix socket.
+fn open_snapshot_destination(path: &PathBuf) -> io::Result<File> {
+    if std::fs::metadata(path)
+        .map(|metadata| metadata.file_type().is_socket())
+        .unwrap_or(false)
+    {
+        let stream = UnixStream::connect(path)?;
+        // Safe because we take over the ownership of the socket file descriptor.
+        return Ok(unsafe { File::from_raw_fd(stream.into_raw_fd()) });
+    }
+
+    OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
-        .open(mem_file_path)
-        .map_err(MemoryBackingFile)?;
+        .open(path)
+}
+
+/// Opens `path` for reading a snapshot file, connecting to it if it is a Unix socket.
+fn open_snapshot_source(path: &PathBuf) -> io::Result<File> {
+    if std::fs::metadata(path)?.file_type().is_socket() {
+        let stream = UnixStream::connect(path)?;
+        // Safe because we take over the ownership of the socket file descriptor.
+        return Ok(unsafe { File::from_raw_fd(stream.into_raw_fd()) });
+    }
+
+    File::open(path)
+}
+
+/// Creates the memory file, sized to the full guest memory unless it is a stream.
+fn memory_file(
+    guest_memory: &GuestMemoryMmap,
+    mem_file_path: &PathBuf,
+) -> std::result::Result<File, CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+    let file = open_snapshot_destination(mem_file_path).map_err(MemoryBackingFile)?;
+
+    if file.metadata().map_err(MemoryBackingFile)?.is_file() {
+        // Set the length of the file to the full size of the memory area.
+        let mem_size_mib = mem_size_mib(guest_memory);
+        file.set_len((mem_size_mib * 1024 * 1024) as u64)
+            .map_err(MemoryBackingFile)?;
+    }
 
-    // Set the length of the file to the full size of the memory area.
-    let mem_size_mib = mem_size_mib(vmm.guest_memory());
-    file.set_len((mem_size_mib * 1024 * 1024) as u64)
-        .map_err(MemoryBackingFile)?;
+    Ok(file)
+}
 
-    match snapshot_type {
-        SnapshotType::Diff => {
-            let dirty_bitmap = vmm.get_dirty_bitmap().map_err(|_| DirtyBitmap)?;
-            vmm.guest_memory()
-                .dump_dirty(&mut file, &dirty_bitmap)
-                .map_err(Memory)
+/// Dumps the guest memory, or only the pages in `dirty_bitmap` if present.
+///
+/// Diff snapshots are written in the streamed diff format when `file` cannot seek.
+fn snapshot_memory_to_file(
+    guest_memory: &GuestMemoryMmap,
+    file: &mut File,
+    dirty_bitmap: Option<&DirtyBitmap>,
+) -> std::result::Result<(), CreateSnapshotError> {
+    use self::CreateSnapshotError::{Memory, MemoryBackingFile};
+    let seekable = file.metadata().map_err(MemoryBackingFile)?.is_file();
+    match dirty_bitmap {
+        Some(dirty_bitmap) if seekable => {
+            guest_memory.dump_dirty(file, dirty_bitmap).map_err(Memory)
         }
-        SnapshotType::Full => vmm.guest_memory().dump(&mut file).map_err(Memory),
+        Some(dirty_bitmap) => guest_memory
+            .dump_dirty_stream(file, dirty_bitmap)
+            .map_err(Memory),
+        None => guest_memory.dump(file).map_err(Memory),
     }
 }
 
@@ -317,6 +1170,68 @@ pub fn validate_x86_64_cpu_vendor(
     Ok(())
 }
 
+/// Validates that the host supports the CPU features exposed to the snapshotted vCPUs.
+///
+/// If the microVM was booted with a CPU template, the CPUID of each vCPU is rebuilt from
+/// `host_cpuid` using that template, so that identification, topology and cache information
+/// match the current host, while the features exposed to the guest stay the same.
+///
+/// # Arguments
+///
+/// * `microvm_state` - The snapshot state, whose vCPU CPUIDs are normalized in place.
+/// * `host_cpuid` - The CPUID supported by the host, as reported by KVM.
+#[cfg(target_arch = "x86_64")]
+pub fn validate_x86_64_cpu_features(
+    microvm_state: &mut MicrovmState,
+    host_cpuid: &CpuId,
+) -> std::result::Result<(), LoadSnapshotError> {
+    use self::LoadSnapshotError::CpuFeaturesMismatch;
+    let vcpu_config = VcpuConfig {
+        vcpu_count: microvm_state.vcpu_states.len() as u8,
+        ht_enabled: microvm_state.vm_info.ht_enabled,
+        cpu_template: microvm_state.vm_info.cpu_template.map(Into::into),
+        custom_cpu_template: microvm_state
+            .vm_info
+            .custom_cpu_template
+            .as_ref()
+            .map(Into::into),
+    };
+    let has_template =
+        vcpu_config.cpu_template.is_some() || vcpu_config.custom_cpu_template.is_some();
+
+    for (index, vcpu_state) in microvm_state.vcpu_states.iter_mut().enumerate() {
+        let mut cpuid = host_cpuid.clone();
+        if has_template {
+            build_vcpu_cpuid(&mut cpuid, index as u8, &vcpu_config).map_err(|err| {
+                CpuFeaturesMismatch(format!("Cannot build CPUID for vCPU {}: {}", index, err))
+            })?;
+        }
+
+        let missing = missing_features(&vcpu_state.cpuid, &cpuid);
+        if !missing.is_empty() {
+            let error_string = format!(
+                "Host does not support the following features of vCPU {}: {}",
+                index,
+                missing
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+            error!("{}", error_string);
+            return Err(CpuFeaturesMismatch(error_string));
+        }
+
+        if has_template {
+            // Do not expose features the guest has not seen at boot time.
+            mask_features(&mut cpuid, &vcpu_state.cpuid);
+            vcpu_state.cpuid = cpuid;
+        }
+    }
+
+    Ok(())
+}
+
 /// Validate that Snapshot Manufacturer ID matches
 /// the one from the Host
 ///
@@ -386,16 +1301,48 @@ pub fn restore_from_snapshot(
 ) -> std::result::Result<Arc<Mutex<Vmm>>, LoadSnapshotError> {
     use self::LoadSnapshotError::*;
     let track_dirty_pages = params.enable_diff_snapshots;
-    let microvm_state = snapshot_state_from_file(&params.snapshot_path, version_map)?;
+    let integrity_key = params
+        .integrity_key_path
+        .as_ref()
+        .map(read_integrity_key)
+        .transpose()
+        .map_err(IntegrityKey)?;
+
+    #[cfg_attr(target_arch = "aarch64", allow(unused_mut))]
+    let (mut microvm_state, manifest) = match integrity_key {
+        Some(key) => {
+            let (microvm_state, manifest) =
+                signed_snapshot_state_from_file(&params.snapshot_path, &key, version_map)?;
+            (microvm_state, Some(manifest))
+        }
+        None => (
+            snapshot_state_from_file(&params.snapshot_path, version_map)?,
+            None,
+        ),
+    };
 
     // Some sanity checks before building the microvm.
     snapshot_state_sanity_check(&microvm_state)?;
+    #[cfg(target_arch = "x86_64")]
+    {
+        let host_cpuid = kvm_ioctls::Kvm::new()
+            .and_then(|kvm| kvm.get_supported_cpuid(KVM_MAX_CPUID_ENTRIES))
+            .map_err(|err| {
+                CpuFeaturesMismatch(format!("Cannot get the host supported CPUID: {}", err))
+            })?;
+        validate_x86_64_cpu_features(&mut microvm_state, &host_cpuid)?;
+    }
 
     let guest_memory = guest_memory_from_file(
         &params.mem_file_path,
+        params.mem_base_path.as_ref(),
         &microvm_state.memory_state,
         track_dirty_pages,
+        microvm_state.vm_info.mem_backend.into(),
     )?;
+    if let Some(manifest) = manifest {
+        validate_guest_memory_digests(&guest_memory, &manifest)?;
+    }
     builder::build_microvm_from_snapshot(
         event_manager,
         microvm_state,
@@ -413,19 +1360,132 @@ fn snapshot_state_from_file(
     use self::LoadSnapshotError::{
         DeserializeMicrovmState, SnapshotBackingFile, SnapshotBackingFileMetadata,
     };
-    let mut snapshot_reader = File::open(snapshot_path).map_err(SnapshotBackingFile)?;
-    let metadata = std::fs::metadata(snapshot_path).map_err(SnapshotBackingFileMetadata)?;
+    let mut snapshot_reader = open_snapshot_source(snapshot_path).map_err(SnapshotBackingFile)?;
+    let metadata = snapshot_reader
+        .metadata()
+        .map_err(SnapshotBackingFileMetadata)?;
+    if !metadata.is_file() {
+        // The length of a stream is only known once it has been read entirely.
+        let mut snapshot_data = Vec::new();
+        snapshot_reader
+            .read_to_end(&mut snapshot_data)
+            .map_err(SnapshotBackingFile)?;
+        let snapshot_len = snapshot_data.len();
+        return Snapshot::load(&mut snapshot_data.as_slice(), snapshot_len, version_map)
+            .map_err(DeserializeMicrovmState);
+    }
     let snapshot_len = metadata.len() as usize;
     Snapshot::load(&mut snapshot_reader, snapshot_len, version_map).map_err(DeserializeMicrovmState)
 }
 
+fn signed_snapshot_state_from_file(
+    snapshot_path: &PathBuf,
+    key: &[u8],
+    version_map: VersionMap,
+) -> std::result::Result<(MicrovmState, SnapshotManifest), LoadSnapshotError> {
+    use self::LoadSnapshotError::{DeserializeMicrovmState, IntegrityCheck, SnapshotBackingFile};
+    let mut snapshot_data = Vec::new();
+    open_snapshot_source(snapshot_path)
+        .and_then(|mut file| file.read_to_end(&mut snapshot_data))
+        .map_err(SnapshotBackingFile)?;
+
+    if snapshot_data.len() < DIGEST_LEN {
+        return Err(IntegrityCheck("State file is not signed.".to_owned()));
+    }
+    let (signature, signed_data) = snapshot_data.split_at(DIGEST_LEN);
+    let mut hmac = HmacSha256::new(key);
+    hmac.update(signed_data);
+    if !hmac.verify(signature) {
+        error!("Snapshot state file signature mismatch.");
+        return Err(IntegrityCheck(
+            "State file signature does not match.".to_owned(),
+        ));
+    }
+
+    // The signature covers the manifest and the state, so deserializing them is safe now.
+    let mut reader = signed_data;
+    let manifest = SnapshotManifest::deserialize(&mut reader, &VersionMap::new(), 1)
+        .map_err(|err| DeserializeMicrovmState(snapshot::Error::Versionize(err)))?;
+    let snapshot_len = reader.len();
+    let microvm_state =
+        Snapshot::load(&mut reader, snapshot_len, version_map).map_err(DeserializeMicrovmState)?;
+
+    Ok((microvm_state, manifest))
+}
+
+/// Validates that the guest memory matches the digests recorded in an authenticated snapshot.
+pub fn validate_guest_memory_digests(
+    guest_memory: &GuestMemoryMmap,
+    manifest: &SnapshotManifest,
+) -> std::result::Result<(), LoadSnapshotError> {
+    use self::LoadSnapshotError::{DeserializeMemory, IntegrityCheck};
+    let digests = guest_memory.digest_regions().map_err(DeserializeMemory)?;
+    if digests.len() != manifest.memory_region_digests.len() {
+        return Err(IntegrityCheck(format!(
+            "Memory file describes {} regions, signed manifest describes {}.",
+            digests.len(),
+            manifest.memory_region_digests.len()
+        )));
+    }
+
+    for (idx, (digest, expected)) in digests
+        .iter()
+        .zip(manifest.memory_region_digests.iter())
+        .enumerate()
+    {
+        if !utils::sha256::constant_time_eq(digest, expected) {
+            let error_string = format!("Memory region {} digest does not match.", idx);
+            error!("{}", error_string);
+            return Err(IntegrityCheck(error_string));
+        }
+    }
+
+    Ok(())
+}
+
 fn guest_memory_from_file(
     mem_file_path: &PathBuf,
+    mem_base_path: Option<&PathBuf>,
     mem_state: &GuestMemoryState,
     track_dirty_pages: bool,
+    mem_backend: MemoryBackend,
 ) -> std::result::Result<GuestMemoryMmap, LoadSnapshotError> {
-    use self::LoadSnapshotError::{DeserializeMemory, MemoryBackingFile};
-    let mem_file = File::open(mem_file_path).map_err(MemoryBackingFile)?;
+    use self::LoadSnapshotError::{BuildMicroVm, DeserializeMemory, MemoryBackingFile};
+    let mut mem_file = open_snapshot_source(mem_file_path).map_err(MemoryBackingFile)?;
+    if mem_backend.is_shared() || mem_base_path.is_some() {
+        // Mapping the memory file privately would not give the guest the requested backend,
+        // and a streamed diff has to be merged with its base, so the memory is copied to
+        // freshly created memory instead.
+        let ranges: Vec<(GuestAddress, usize)> = mem_state
+            .regions
+            .iter()
+            .map(|region| (GuestAddress(region.base_address), region.size))
+            .collect();
+        let guest_memory =
+            builder::create_guest_memory_from_ranges(&ranges, track_dirty_pages, mem_backend)
+                .map_err(BuildMicroVm)?;
+        match mem_base_path {
+            Some(mem_base_path) => {
+                let mut mem_base_file =
+                    open_snapshot_source(mem_base_path).map_err(MemoryBackingFile)?;
+                guest_memory
+                    .load_from_reader(&mut mem_base_file, mem_state)
+                    .map_err(DeserializeMemory)?;
+                guest_memory
+                    .load_diff_from_reader(&mut mem_file, mem_state)
+                    .map_err(DeserializeMemory)?;
+            }
+            None => guest_memory
+                .load_from_reader(&mut mem_file, mem_state)
+                .map_err(DeserializeMemory)?,
+        }
+        return Ok(guest_memory);
+    }
+    if !mem_file.metadata().map_err(MemoryBackingFile)?.is_file() {
+        // Streams cannot be mapped, so their content is copied to anonymous memory.
+        return GuestMemoryMmap::restore_from_reader(&mut mem_file, mem_state, track_dirty_pages)
+            .map_err(DeserializeMemory);
+    }
     GuestMemoryMmap::restore(&mem_file, mem_state, track_dirty_pages).map_err(DeserializeMemory)
 }
 
@@ -492,6 +1552,7 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: true,
+            dhcp: None,
         };
         insert_net_device(
             &mut vmm,
@@ -528,7 +1589,16 @@ mod tests {
             device_states: states,
             memory_state,
             vcpu_states: vec![VcpuState::default()],
-            vm_info: VmInfo { mem_size_mib: 1u64 },
+            vm_info: VmInfo {
+                mem_size_mib: 1u64,
+                cpu_template: None,
+                ht_enabled: false,
+                mem_backend: MemoryBackendState::Anonymous,
+                custom_cpu_template: None,
+                online_vcpu_count: None,
+                boot_measurements: None,
+                mmds_version: MmdsVersionState::V1,
+            },
             #[cfg(target_arch = "aarch64")]
             vm_state: vmm.vm.save_state(&[1]).unwrap(),
             #[cfg(target_arch = "x86_64")]
@@ -559,6 +1629,412 @@ mod tests {
         )
     }
 
+    fn default_microvm_state(vmm: &Vmm) -> MicrovmState {
+        MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            memory_state: vmm.guest_memory().describe(),
+            vcpu_states: vec![VcpuState::default()],
+            vm_info: VmInfo {
+                mem_size_mib: 1u64,
+                cpu_template: None,
+                ht_enabled: false,
+                mem_backend: MemoryBackendState::Anonymous,
+                custom_cpu_template: None,
+                online_vcpu_count: None,
+                boot_measurements: None,
+                mmds_version: MmdsVersionState::V1,
+            },
+            #[cfg(target_arch = "aarch64")]
+            vm_state: vmm.vm.save_state(&[1]).unwrap(),
+            #[cfg(target_arch = "x86_64")]
+            vm_state: vmm.vm.save_state().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_vm_info_versionize() {
+        let mut vm_info = VmInfo {
+            mem_size_mib: 1u64,
+            cpu_template: Some(CpuTemplateState::T2),
+            ht_enabled: true,
+            mem_backend: MemoryBackendState::Hugetlbfs2M,
+            custom_cpu_template: Some(CustomCpuTemplateState::from(&CustomCpuTemplate::default())),
+            online_vcpu_count: Some(1),
+            boot_measurements: Some(BootMeasurements::default()),
+            mmds_version: MmdsVersionState::V2,
+        };
+        let mut buf = vec![0; 200];
+
+        vm_info
+            .serialize(
+                &mut buf.as_mut_slice(),
+                &VERSION_MAP,
+                VERSION_MAP.latest_version(),
+            )
+            .unwrap();
+        let restored_vm_info = VmInfo::deserialize(
+            &mut buf.as_slice(),
+            &VERSION_MAP,
+            VERSION_MAP.latest_version(),
+        )
+        .unwrap();
+        assert_eq!(restored_vm_info, vm_info);
+
+        // Older snapshot versions cannot keep the MMDS session tokens.
+        assert!(vm_info
+            .serialize(&mut buf.as_mut_slice(), &VERSION_MAP, 2)
+            .is_err());
+        vm_info.mmds_version = MmdsVersionState::V1;
+
+        // Older snapshot versions do not record the boot CPU configuration.
+        vm_info
+            .serialize(&mut buf.as_mut_slice(), &VERSION_MAP, 2)
+            .unwrap();
+        let restored_vm_info = VmInfo::deserialize(&mut buf.as_slice(), &VERSION_MAP, 2).unwrap();
+        assert_eq!(restored_vm_info.mem_size_mib, vm_info.mem_size_mib);
+        assert_eq!(restored_vm_info.cpu_template, None);
+        assert!(!restored_vm_info.ht_enabled);
+        assert_eq!(restored_vm_info.mem_backend, MemoryBackendState::Anonymous);
+        assert_eq!(restored_vm_info.online_vcpu_count, None);
+        assert_eq!(restored_vm_info.boot_measurements, None);
+        assert_eq!(restored_vm_info.mmds_version, MmdsVersionState::V1);
+    }
+
+    #[test]
+    fn test_memory_backend_state() {
+        for backend in [
+            MemoryBackend::Anonymous,
+            MemoryBackend::SharedMemfd,
+            MemoryBackend::Hugetlbfs2M,
+            MemoryBackend::Hugetlbfs1G,
+        ]
+        .iter()
+        {
+            assert_eq!(
+                MemoryBackend::from(MemoryBackendState::from(*backend)),
+                *backend
+            );
+        }
+    }
+
+    #[test]
+    fn test_custom_cpu_template_state() {
+        let template = CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidModifier {
+                leaf: 0x7,
+                subleaf: 0,
+                register: CpuidRegister::Ebx,
+                mask: 1 << 5,
+                value: 0,
+            }],
+            msr_modifiers: vec![MsrModifier {
+                addr: 0x1a0,
+                mask: 1,
+                value: 0,
+            }],
+        };
+        let state = CustomCpuTemplateState::from(&template);
+
+        let mut buf = vec![0; 1000];
+        let version_map = VersionMap::new();
+        state
+            .serialize(&mut buf.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_state =
+            CustomCpuTemplateState::deserialize(&mut buf.as_slice(), &version_map, 1).unwrap();
+        assert_eq!(CustomCpuTemplate::from(&restored_state), template);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_validate_x86_64_cpu_features() {
+        use cpuid::common::VENDOR_ID_INTEL;
+        use kvm_ioctls::Kvm;
+
+        let vmm = default_vmm();
+        let host_cpuid = Kvm::new()
+            .unwrap()
+            .get_supported_cpuid(KVM_MAX_CPUID_ENTRIES)
+            .unwrap();
+        let mut microvm_state = default_microvm_state(&vmm);
+        let vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            ht_enabled: false,
+            cpu_template: None,
+            custom_cpu_template: None,
+        };
+        let mut guest_cpuid = host_cpuid.clone();
+        build_vcpu_cpuid(&mut guest_cpuid, 0, &vcpu_config).unwrap();
+        microvm_state.vcpu_states[0].cpuid = guest_cpuid.clone();
+
+        // Without a template, the saved CPUID is restored as is.
+        validate_x86_64_cpu_features(&mut microvm_state, &host_cpuid).unwrap();
+        assert_eq!(
+            microvm_state.vcpu_states[0].cpuid.as_slice(),
+            guest_cpuid.as_slice()
+        );
+
+        // A host lacking a feature exposed to the guest is rejected.
+        let mut older_host_cpuid = host_cpuid.clone();
+        for entry in older_host_cpuid.as_mut_slice().iter_mut() {
+            if entry.function == 0x1 {
+                // FPU.
+                entry.edx &= !1;
+            }
+        }
+        match validate_x86_64_cpu_features(&mut microvm_state, &older_host_cpuid) {
+            Err(LoadSnapshotError::CpuFeaturesMismatch(err)) => {
+                assert!(err.contains("fpu (CPUID.(EAX=0x1,ECX=0x0):EDX[0])"))
+            }
+            _ => panic!("Unexpected result."),
+        }
+
+        // CPU templates are only supported on Intel hosts.
+        if get_vendor_id_from_host().unwrap() != *VENDOR_ID_INTEL {
+            return;
+        }
+
+        // With a template, the CPUID is rebuilt from the host CPUID.
+        let vcpu_config = VcpuConfig {
+            cpu_template: Some(CpuFeaturesTemplate::T2),
+            ..vcpu_config
+        };
+        let mut guest_cpuid = host_cpuid.clone();
+        build_vcpu_cpuid(&mut guest_cpuid, 0, &vcpu_config).unwrap();
+        let mut saved_cpuid = guest_cpuid.clone();
+        for entry in saved_cpuid.as_mut_slice().iter_mut() {
+            if entry.function == 0x8000_0002 {
+                // Brand string of a different host.
+                entry.eax = 0;
+            }
+        }
+        microvm_state.vm_info.cpu_template = Some(CpuTemplateState::T2);
+        microvm_state.vcpu_states[0].cpuid = saved_cpuid;
+        validate_x86_64_cpu_features(&mut microvm_state, &host_cpuid).unwrap();
+        assert_eq!(
+            microvm_state.vcpu_states[0].cpuid.as_slice(),
+            guest_cpuid.as_slice()
+        );
+    }
+
+    #[test]
+    fn test_signed_snapshot_state() {
+        let vmm = default_vmm_with_devices();
+        let microvm_state = default_microvm_state(&vmm);
+        let manifest = SnapshotManifest {
+            memory_region_digests: vec![vec![1u8; DIGEST_LEN], vec![2u8; DIGEST_LEN]],
+        };
+        let key = vec![0xAAu8; MIN_INTEGRITY_KEY_LEN];
+        let snapshot_file = TempFile::new().unwrap();
+        let snapshot_path = snapshot_file.as_path().to_path_buf();
+
+        let snapshot_data = signed_snapshot_state_data(
+            &microvm_state,
+            &manifest,
+            &key,
+            VERSION_MAP.latest_version(),
+            VERSION_MAP.clone(),
+        )
+        .unwrap();
+        std::fs::write(&snapshot_path, &snapshot_data).unwrap();
+
+        let (restored_microvm_state, restored_manifest) =
+            signed_snapshot_state_from_file(&snapshot_path, &key, VERSION_MAP.clone()).unwrap();
+        assert_eq!(restored_manifest, manifest);
+        assert_eq!(restored_microvm_state.vm_info, microvm_state.vm_info);
+        assert_eq!(
+            restored_microvm_state.device_states,
+            microvm_state.device_states
+        );
+
+        // A different key must be rejected.
+        let wrong_key = vec![0xBBu8; MIN_INTEGRITY_KEY_LEN];
+        match signed_snapshot_state_from_file(&snapshot_path, &wrong_key, VERSION_MAP.clone()) {
+            Err(LoadSnapshotError::IntegrityCheck(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+
+        // A signed state file cannot be loaded as an unsigned one.
+        assert!(snapshot_state_from_file(&snapshot_path, VERSION_MAP.clone()).is_err());
+
+        // Tampering with any byte of the state file must be detected.
+        let mut snapshot_data = std::fs::read(&snapshot_path).unwrap();
+        let last = snapshot_data.len() - 1;
+        snapshot_data[last] ^= 1;
+        std::fs::write(&snapshot_path, &snapshot_data).unwrap();
+        match signed_snapshot_state_from_file(&snapshot_path, &key, VERSION_MAP.clone()) {
+            Err(LoadSnapshotError::IntegrityCheck(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+
+        // A file too small to hold a signature is rejected as well.
+        std::fs::write(&snapshot_path, &[0u8; 4]).unwrap();
+        match signed_snapshot_state_from_file(&snapshot_path, &key, VERSION_MAP.clone()) {
+            Err(LoadSnapshotError::IntegrityCheck(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    // Replaces `file` with a named pipe and returns its path.
+    fn make_fifo(file: &TempFile) -> PathBuf {
+        let path = file.as_path().to_path_buf();
+        std::fs::remove_file(&path).unwrap();
+        let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        // Safe because we pass a valid, nul terminated path.
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        path
+    }
+
+    #[test]
+    fn test_snapshot_streams() {
+        use std::os::unix::net::UnixListener;
+        use vm_memory::{Bytes, GuestAddress};
+
+        let vmm = default_vmm_with_devices();
+        let microvm_state = default_microvm_state(&vmm);
+        let snapshot_data = snapshot_state_data(
+            &microvm_state,
+            VERSION_MAP.latest_version(),
+            VERSION_MAP.clone(),
+        )
+        .unwrap();
+
+        // Load the state from a named pipe.
+        let fifo_file = TempFile::new().unwrap();
+        let fifo_path = make_fifo(&fifo_file);
+        let writer = {
+            let fifo_path = fifo_path.clone();
+            let snapshot_data = snapshot_data.clone();
+            std::thread::spawn(move || std::fs::write(fifo_path, snapshot_data).unwrap())
+        };
+        let restored_microvm_state =
+            snapshot_state_from_file(&fifo_path, VERSION_MAP.clone()).unwrap();
+        writer.join().unwrap();
+        assert_eq!(restored_microvm_state.vm_info, microvm_state.vm_info);
+
+        // Save the state to a Unix socket.
+        let socket_file = TempFile::new().unwrap();
+        let socket_path = socket_file.as_path().to_path_buf();
+        std::fs::remove_file(&socket_path).unwrap();
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut data = Vec::new();
+            listener.accept().unwrap().0.read_to_end(&mut data).unwrap();
+            data
+        });
+        open_snapshot_destination(&socket_path)
+            .unwrap()
+            .write_all(&snapshot_data)
+            .unwrap();
+        assert_eq!(reader.join().unwrap(), snapshot_data);
+        std::fs::remove_file(&socket_path).unwrap();
+
+        // Load the guest memory from a named pipe.
+        let guest_memory = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x2000)]).unwrap();
+        guest_memory
+            .write(&[1u8; 0x1000], GuestAddress(0x1000))
+            .unwrap();
+        let mut mem_data = Vec::new();
+        guest_memory.dump(&mut mem_data).unwrap();
+        let writer = {
+            let fifo_path = fifo_path.clone();
+            std::thread::spawn(move || std::fs::write(fifo_path, mem_data).unwrap())
+        };
+        let restored_guest_memory = guest_memory_from_file(
+            &fifo_path,
+            None,
+            &guest_memory.describe(),
+            false,
+            MemoryBackend::default(),
+        )
+        .unwrap();
+        writer.join().unwrap();
+        assert_eq!(
+            restored_guest_memory.digest_regions().unwrap(),
+            guest_memory.digest_regions().unwrap()
+        );
+
+        // Load a streamed diff from a named pipe, on top of a base memory file.
+        let base_file = TempFile::new().unwrap();
+        std::fs::write(base_file.as_path(), vec![2u8; 0x2000]).unwrap();
+        let mut diff_data = DIFF_STREAM_MAGIC.to_le_bytes().to_vec();
+        for field in [0x1000u64, 0x1000].iter() {
+            diff_data.extend_from_slice(&field.to_le_bytes());
+        }
+        diff_data.extend_from_slice(&[1u8; 0x1000]);
+        for field in [0x2000u64, 0].iter() {
+            diff_data.extend_from_slice(&field.to_le_bytes());
+        }
+        let writer = {
+            let fifo_path = fifo_path.clone();
+            std::thread::spawn(move || std::fs::write(fifo_path, diff_data).unwrap())
+        };
+        let restored_guest_memory = guest_memory_from_file(
+            &fifo_path,
+            Some(&base_file.as_path().to_path_buf()),
+            &guest_memory.describe(),
+            false,
+            MemoryBackend::default(),
+        )
+        .unwrap();
+        writer.join().unwrap();
+        let mut restored_data = vec![0u8; 0x2000];
+        restored_guest_memory
+            .read_slice(&mut restored_data, GuestAddress(0))
+            .unwrap();
+        assert_eq!(
+            restored_data,
+            [vec![2u8; 0x1000], vec![1u8; 0x1000]].concat()
+        );
+    }
+
+    #[test]
+    fn test_validate_guest_memory_digests() {
+        let vmm = default_vmm();
+        let guest_memory = vmm.guest_memory();
+        let mut manifest = SnapshotManifest {
+            memory_region_digests: guest_memory
+                .digest_regions()
+                .unwrap()
+                .iter()
+                .map(|digest| digest.to_vec())
+                .collect(),
+        };
+        validate_guest_memory_digests(guest_memory, &manifest).unwrap();
+
+        manifest.memory_region_digests[0][0] ^= 1;
+        match validate_guest_memory_digests(guest_memory, &manifest) {
+            Err(LoadSnapshotError::IntegrityCheck(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+
+        manifest.memory_region_digests.push(vec![0u8; DIGEST_LEN]);
+        match validate_guest_memory_digests(guest_memory, &manifest) {
+            Err(LoadSnapshotError::IntegrityCheck(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_read_integrity_key() {
+        let key_file = TempFile::new().unwrap();
+        let key_path = key_file.as_path().to_path_buf();
+
+        std::fs::write(&key_path, &[1u8; MIN_INTEGRITY_KEY_LEN - 1]).unwrap();
+        assert_eq!(
+            read_integrity_key(&key_path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        std::fs::write(&key_path, &[1u8; MIN_INTEGRITY_KEY_LEN]).unwrap();
+        assert_eq!(
+            read_integrity_key(&key_path).unwrap(),
+            vec![1u8; MIN_INTEGRITY_KEY_LEN]
+        );
+
+        assert!(read_integrity_key(&PathBuf::from("/invalid/key/path")).is_err());
+    }
+
     #[test]
     fn test_get_snapshot_data_version() {
         let vmm = default_vmm_with_devices();
@@ -586,14 +2062,125 @@ mod tests {
         }
     }
 
+    fn wait_for_background_snapshot(
+        background_snapshot: &mut BackgroundSnapshot,
+    ) -> SnapshotCreateStatus {
+        loop {
+            let status = background_snapshot.status();
+            if status.state != SnapshotCreateState::InProgress {
+                return status;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_background_snapshot() {
+        use vm_memory::{Bytes, GuestAddress};
+
+        let page_size = 0x1000;
+        let guest_memory =
+            GuestMemoryMmap::from_ranges_with_tracking(&[(GuestAddress(0), page_size * 4)])
+                .unwrap();
+        guest_memory
+            .write(&vec![1u8; page_size], GuestAddress(page_size as u64))
+            .unwrap();
+        let snapshot_file = TempFile::new().unwrap();
+        let mem_file = TempFile::new().unwrap();
+        let mut params = CreateSnapshotParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: snapshot_file.as_path().to_path_buf(),
+            mem_file_path: mem_file.as_path().to_path_buf(),
+            version: None,
+            integrity_key_path: None,
+            background: true,
+        };
+
+        // A full snapshot, to regular files.
+        let background_dump =
+            BackgroundDumpJob::new(&guest_memory, &params, b"snapshot".to_vec(), None).unwrap();
+        assert_eq!(background_dump.mem_layout, MemoryLayout::Sequential);
+        let mut background_snapshot = spawn_background_snapshot(&background_dump).unwrap();
+        let status = wait_for_background_snapshot(&mut background_snapshot);
+        assert_eq!(status.state, SnapshotCreateState::Completed);
+        assert!(status.error.is_none());
+        assert_eq!(
+            std::fs::read(snapshot_file.as_path()).unwrap(),
+            b"snapshot".to_vec()
+        );
+        let mut mem_data = Vec::new();
+        guest_memory.dump(&mut mem_data).unwrap();
+        assert_eq!(std::fs::read(mem_file.as_path()).unwrap(), mem_data);
+        // The outcome is kept once the child has been reaped.
+        assert_eq!(background_snapshot.status(), status);
+
+        // A diff snapshot, streamed to a named pipe.
+        let fifo_file = TempFile::new().unwrap();
+        params.mem_file_path = make_fifo(&fifo_file);
+        let mut dirty_bitmap: DirtyBitmap = std::collections::HashMap::new();
+        dirty_bitmap.insert(0, vec![0b1000; 1]);
+        let background_dump = BackgroundDumpJob::new(
+            &guest_memory,
+            &params,
+            b"snapshot".to_vec(),
+            Some(&dirty_bitmap),
+        )
+        .unwrap();
+        assert_eq!(
+            background_dump.mem_layout,
+            MemoryLayout::DiffStream(page_size as u64 * 4)
+        );
+        let reader = {
+            let fifo_path = params.mem_file_path.clone();
+            std::thread::spawn(move || std::fs::read(fifo_path).unwrap())
+        };
+        let mut background_snapshot = spawn_background_snapshot(&background_dump).unwrap();
+        let status = wait_for_background_snapshot(&mut background_snapshot);
+        assert_eq!(status.state, SnapshotCreateState::Completed);
+        let mut diff_data = Vec::new();
+        guest_memory
+            .dump_dirty_stream(&mut diff_data, &dirty_bitmap)
+            .unwrap();
+        assert_eq!(reader.join().unwrap(), diff_data);
+
+        // The child reports which step failed.
+        let mut background_dump =
+            BackgroundDumpJob::new(&guest_memory, &params, Vec::new(), None).unwrap();
+        background_dump.snapshot_file =
+            BackgroundDestination::Fifo(CString::new("/nonexistent/snapshot").unwrap());
+        let mut background_snapshot = spawn_background_snapshot(&background_dump).unwrap();
+        let status = wait_for_background_snapshot(&mut background_snapshot);
+        assert_eq!(status.state, SnapshotCreateState::Failed);
+        assert_eq!(
+            status.error,
+            Some(format!(
+                "{}: {}",
+                BackgroundDumpStep::OpenSnapshotFile,
+                io::Error::from_raw_os_error(libc::ENOENT)
+            ))
+        );
+    }
+
     #[test]
     fn test_create_snapshot_error_display() {
         use crate::persist::CreateSnapshotError::*;
         use vm_memory::GuestMemoryError;
 
+        let err = BackgroundDump(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = BackgroundDumpInProgress;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = BackgroundDumpSharedMemory(MemoryBackend::SharedMemfd);
+        let _ = format!("{}{:?}", err, err);
+
         let err = DirtyBitmap;
         let _ = format!("{}{:?}", err, err);
 
+        let err = IntegrityKey(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = InvalidVersion;
         let _ = format!("{}{:?}", err, err);
 
@@ -639,6 +2226,12 @@ mod tests {
         let err = DeserializeMicrovmState(snapshot::Error::Io(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = IntegrityCheck(String::new());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = IntegrityKey(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = MemoryBackingFile(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
@@ -650,6 +2243,9 @@ mod tests {
 
         let err = CpuVendorMismatch(String::new());
         let _ = format!("{}{:?}", err, err);
+
+        let err = CpuFeaturesMismatch(String::new());
+        let _ = format!("{}{:?}", err, err);
     }
 
     #[test]
@@ -662,6 +2258,9 @@ mod tests {
         let err = NotAllowed(String::from(""));
         let _ = format!("{}{:?}", err, err);
 
+        let err = RestoreMmdsVersion(mmds::token::Error::InvalidTtlValue(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = RestoreDevices(DevicePersistError::MmioTransport);
         let _ = format!("{}{:?}", err, err);
 
@@ -671,6 +2270,9 @@ mod tests {
         let err = RestoreVmState(vstate::vm::Error::NotEnoughMemorySlots);
         let _ = format!("{}{:?}", err, err);
 
+        let err = SaveTpmState(devices::tpm::Error::InvalidResponseSize(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = SaveVcpuState(vstate::vcpu::Error::VcpuTlsNotPresent);
         let _ = format!("{}{:?}", err, err);
 
@@ -3,25 +3,37 @@
 
 #![deny(warnings)]
 
+use std::collections::HashMap;
 use std::fs::File;
 
 use crate::vmm_config::balloon::*;
 use crate::vmm_config::boot_source::{
-    BootConfig, BootSourceConfig, BootSourceConfigError, DEFAULT_KERNEL_CMDLINE,
+    BootArgsPolicy, BootConfig, BootSourceConfig, BootSourceConfigError, DEFAULT_KERNEL_CMDLINE,
 };
+use crate::vmm_config::cpu_config::{CpuConfigError, CustomCpuTemplate};
+use crate::vmm_config::dns::{DnsConfig, DnsConfigError};
 use crate::vmm_config::drive::*;
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::logger::{init_logger, LoggerConfig, LoggerConfigError};
-use crate::vmm_config::machine_config::{VmConfig, VmConfigError, DEFAULT_MEM_SIZE_MIB};
+use crate::vmm_config::machine_config::{
+    validate_cpu_affinity, MemoryBackend, VmConfig, VmConfigError, DEFAULT_MEM_SIZE_MIB,
+};
 use crate::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
-use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
+use crate::vmm_config::mmds::{
+    MmdsConfig, MmdsConfigError, MmdsConnectionLimitsConfig, MmdsGuestWriteConfig,
+};
 use crate::vmm_config::net::*;
+use crate::vmm_config::tpm::{TpmConfig, TpmConfigError};
 use crate::vmm_config::vsock::*;
 use crate::vstate::vcpu::VcpuConfig;
+use devices::virtio::Net;
+use mmds::data_store::Error as MmdsError;
 use mmds::ns::MmdsNetworkStack;
 use utils::net::ipv4addr::is_link_local_valid;
+use utils::net::ipv6addr::is_local_unicast_valid;
 
 use serde::Deserialize;
+use serde_json::Value;
 
 type Result<E> = std::result::Result<(), E>;
 
@@ -34,16 +46,24 @@ pub enum Error {
     BlockDevice(DriveError),
     /// Boot source configuration error.
     BootSource(BootSourceConfigError),
+    /// Custom CPU template error.
+    CpuConfig(CpuConfigError),
+    /// DNS responder configuration error.
+    DnsConfig(DnsConfigError),
     /// JSON is invalid.
     InvalidJson,
     /// Logger configuration error.
     Logger(LoggerConfigError),
+    /// Error preloading the MMDS data store.
+    Metadata(MmdsError),
     /// Metrics system configuration error.
     Metrics(MetricsConfigError),
     /// MMDS configuration error.
     MmdsConfig(MmdsConfigError),
     /// Net device configuration error.
     NetDevice(NetworkInterfaceError),
+    /// TPM device configuration error.
+    TpmDevice(TpmConfigError),
     /// microVM vCpus or memory configuration error.
     VmConfig(VmConfigError),
     /// Vsock device configuration error.
@@ -59,16 +79,26 @@ pub struct VmmConfig {
     block_devices: Vec<BlockDeviceConfig>,
     #[serde(rename = "boot-source")]
     boot_source: BootSourceConfig,
+    #[serde(rename = "cpu-config")]
+    cpu_config: Option<CustomCpuTemplate>,
+    #[serde(rename = "dns")]
+    dns_config: Option<DnsConfig>,
     #[serde(rename = "logger")]
     logger: Option<LoggerConfig>,
     #[serde(rename = "machine-config")]
     machine_config: Option<VmConfig>,
+    #[serde(rename = "metadata")]
+    metadata: Option<Value>,
     #[serde(rename = "metrics")]
     metrics: Option<MetricsConfig>,
     #[serde(rename = "mmds-config")]
     mmds_config: Option<MmdsConfig>,
+    #[serde(rename = "mmds-instances", default)]
+    mmds_instances: Vec<MmdsConfig>,
     #[serde(rename = "network-interfaces", default)]
     net_devices: Vec<NetworkInterfaceConfig>,
+    #[serde(rename = "tpm")]
+    tpm_device: Option<TpmConfig>,
     #[serde(rename = "vsock")]
     vsock_device: Option<VsockDeviceConfig>,
 }
@@ -81,6 +111,8 @@ pub struct VmResources {
     vm_config: VmConfig,
     /// The boot configuration for this microVM.
     boot_config: Option<BootConfig>,
+    /// The custom CPU template for this microVM.
+    cpu_config: Option<CustomCpuTemplate>,
     /// The block devices.
     pub block: BlockBuilder,
     /// The vsock device.
@@ -91,6 +123,12 @@ pub struct VmResources {
     pub net_builder: NetBuilder,
     /// The configuration for `MmdsNetworkStack`.
     pub mmds_config: Option<MmdsConfig>,
+    /// The configurations of the named MMDS instances.
+    pub mmds_instances: HashMap<String, MmdsConfig>,
+    /// The configuration of the DNS responder.
+    pub dns_config: Option<DnsConfig>,
+    /// The configuration of the TPM device.
+    pub tpm_config: Option<TpmConfig>,
     /// Whether or not to load boot timer device.
     pub boot_timer: bool,
 }
@@ -119,6 +157,12 @@ impl VmResources {
                 .map_err(Error::VmConfig)?;
         }
 
+        if let Some(cpu_config) = vmm_config.cpu_config {
+            resources
+                .set_cpu_config(cpu_config)
+                .map_err(Error::CpuConfig)?;
+        }
+
         resources
             .set_boot_source(vmm_config.boot_source)
             .map_err(Error::BootSource)?;
@@ -141,6 +185,12 @@ impl VmResources {
                 .map_err(Error::VsockDevice)?;
         }
 
+        if let Some(tpm_config) = vmm_config.tpm_device {
+            resources
+                .set_tpm_device(tpm_config)
+                .map_err(Error::TpmDevice)?;
+        }
+
         if let Some(balloon_config) = vmm_config.balloon_device {
             resources
                 .set_balloon_device(balloon_config)
@@ -153,30 +203,72 @@ impl VmResources {
                 .map_err(Error::MmdsConfig)?;
         }
 
+        for mmds_config in vmm_config.mmds_instances.into_iter() {
+            resources
+                .set_mmds_config(mmds_config)
+                .map_err(Error::MmdsConfig)?;
+        }
+
+        if let Some(dns_config) = vmm_config.dns_config {
+            resources
+                .set_dns_config(dns_config)
+                .map_err(Error::DnsConfig)?;
+        }
+
+        // The metadata is merged into whatever the default MMDS instance was preloaded with.
+        if let Some(metadata) = vmm_config.metadata {
+            mmds::MMDS
+                .lock()
+                .expect("Poisoned lock")
+                .merge_data(metadata)
+                .map_err(Error::Metadata)?;
+        }
+
         Ok(resources)
     }
 
     /// Returns a VcpuConfig based on the vm config.
+    ///
+    /// The vcpus that can be started after boot are created at boot as well, so the vcpu count
+    /// of the configuration is the maximum one.
     pub fn vcpu_config(&self) -> VcpuConfig {
         // The unwraps are ok to use because the values are initialized using defaults if not
         // supplied by the user.
         VcpuConfig {
-            vcpu_count: self.vm_config().vcpu_count.unwrap(),
+            vcpu_count: self.max_vcpu_count(),
             ht_enabled: self.vm_config().ht_enabled.unwrap(),
             cpu_template: self.vm_config().cpu_template,
+            custom_cpu_template: self.cpu_config.clone(),
         }
     }
 
+    /// Returns the number of vcpus created at boot.
+    pub fn max_vcpu_count(&self) -> u8 {
+        self.vm_config()
+            .max_vcpu_count
+            .unwrap_or_else(|| self.vm_config().vcpu_count.unwrap())
+    }
+
     /// Returns whether dirty page tracking is enabled or not.
     pub fn track_dirty_pages(&self) -> bool {
         self.vm_config().track_dirty_pages
     }
 
+    /// Returns the type of memory backing the guest memory.
+    pub fn mem_backend(&self) -> MemoryBackend {
+        self.vm_config().mem_backend
+    }
+
     /// Returns the VmConfig.
     pub fn vm_config(&self) -> &VmConfig {
         &self.vm_config
     }
 
+    /// Records the number of vcpus running the guest, after vcpus were started or parked.
+    pub fn set_online_vcpu_count(&mut self, vcpu_count: u8) {
+        self.vm_config.vcpu_count = Some(vcpu_count);
+    }
+
     /// Set the machine configuration of the microVM.
     pub fn set_vm_config(&mut self, machine_config: &VmConfig) -> Result<VmConfigError> {
         if machine_config.vcpu_count == Some(0) {
@@ -217,10 +309,76 @@ impl VmResources {
             return Err(VmConfigError::InvalidVcpuCount);
         }
 
+        let max_vcpu_count = machine_config
+            .max_vcpu_count
+            .or(self.vm_config.max_vcpu_count);
+        if let Some(max_vcpu_count) = max_vcpu_count {
+            if max_vcpu_count < vcpu_count_value {
+                return Err(VmConfigError::InvalidMaxVcpuCount);
+            }
+            if ht_enabled && max_vcpu_count > 1 && max_vcpu_count % 2 == 1 {
+                return Err(VmConfigError::InvalidVcpuCount);
+            }
+        }
+
+        if let Some(template) = machine_config.cpu_template {
+            if !template.is_supported_by_arch() {
+                return Err(VmConfigError::CpuTemplateNotSupported(template));
+            }
+            // KVM only lets the ID registers be lowered on recent host kernels, so this is
+            // found out now rather than when the microVM starts.
+            #[cfg(target_arch = "aarch64")]
+            crate::vstate::vcpu::validate_cpu_template(template).map_err(|err| {
+                VmConfigError::CpuTemplateNotApplicable(template, err.to_string())
+            })?;
+        }
+
+        // Every vCPU, including the ones that can be started later, needs its own set of host CPUs,
+        // also when the vCPU number changes after the affinities were set.
+        if let Some(vcpu_affinity) = machine_config
+            .vcpu_affinity
+            .as_ref()
+            .or_else(|| self.vm_config.vcpu_affinity.as_ref())
+        {
+            if vcpu_affinity.len() != max_vcpu_count.unwrap_or(vcpu_count_value) as usize {
+                return Err(VmConfigError::VcpuAffinityCountMismatch);
+            }
+        }
+        for cpus in machine_config
+            .vcpu_affinity
+            .iter()
+            .flatten()
+            .chain(machine_config.vmm_affinity.iter())
+            .chain(machine_config.api_affinity.iter())
+        {
+            validate_cpu_affinity(cpus)?;
+        }
+        if let Some(sched_policy) = machine_config.vcpu_sched_policy {
+            sched_policy.validate()?;
+        }
+
+        // Huge pages cannot be split, so every memory region has to be made of whole ones.
+        if let Some(huge_page_size) = machine_config.mem_backend.huge_page_size() {
+            let mem_size_mib = machine_config
+                .mem_size_mib
+                .or(self.vm_config.mem_size_mib)
+                .unwrap_or(DEFAULT_MEM_SIZE_MIB);
+            if arch::arch_memory_regions(mem_size_mib << 20)
+                .iter()
+                .any(|(_, size)| size % huge_page_size != 0)
+            {
+                return Err(VmConfigError::MemorySizeNotPageAligned(
+                    machine_config.mem_backend,
+                ));
+            }
+        }
+
         // Update all the fields that have a new value.
         self.vm_config.vcpu_count = Some(vcpu_count_value);
+        self.vm_config.max_vcpu_count = max_vcpu_count;
         self.vm_config.ht_enabled = Some(ht_enabled);
         self.vm_config.track_dirty_pages = machine_config.track_dirty_pages;
+        self.vm_config.mem_backend = machine_config.mem_backend;
 
         if machine_config.mem_size_mib.is_some() {
             self.vm_config.mem_size_mib = machine_config.mem_size_mib;
@@ -230,6 +388,38 @@ impl VmResources {
             self.vm_config.cpu_template = machine_config.cpu_template;
         }
 
+        if machine_config.vcpu_affinity.is_some() {
+            self.vm_config.vcpu_affinity = machine_config.vcpu_affinity.clone();
+        }
+        if machine_config.vmm_affinity.is_some() {
+            self.vm_config.vmm_affinity = machine_config.vmm_affinity.clone();
+        }
+        if machine_config.api_affinity.is_some() {
+            self.vm_config.api_affinity = machine_config.api_affinity.clone();
+        }
+        if machine_config.vcpu_sched_policy.is_some() {
+            self.vm_config.vcpu_sched_policy = machine_config.vcpu_sched_policy;
+        }
+
+        #[cfg(feature = "gdb")]
+        {
+            if machine_config.gdb_socket_path.is_some() {
+                self.vm_config.gdb_socket_path = machine_config.gdb_socket_path.clone();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets a reference to the custom CPU template.
+    pub fn cpu_config(&self) -> Option<&CustomCpuTemplate> {
+        self.cpu_config.as_ref()
+    }
+
+    /// Sets the custom CPU template, after checking that the host supports it.
+    pub fn set_cpu_config(&mut self, cpu_config: CustomCpuTemplate) -> Result<CpuConfigError> {
+        cpu_config.validate()?;
+        self.cpu_config = Some(cpu_config);
         Ok(())
     }
 
@@ -264,29 +454,49 @@ impl VmResources {
         boot_source_cfg: BootSourceConfig,
     ) -> Result<BootSourceConfigError> {
         use self::BootSourceConfigError::{
-            InvalidInitrdPath, InvalidKernelCommandLine, InvalidKernelPath,
+            BundleInitrdConflict, InvalidInitrdPath, InvalidKernelBundle, InvalidKernelCommandLine,
+            InvalidKernelPath,
         };
 
         // Validate boot source config.
-        let kernel_file =
+        let mut kernel_file =
             File::open(&boot_source_cfg.kernel_image_path).map_err(InvalidKernelPath)?;
+        let bundle = kernel::bundle::read_bundle(&mut kernel_file).map_err(InvalidKernelBundle)?;
         let initrd_file: Option<File> = match &boot_source_cfg.initrd_path {
+            Some(_) if bundle.as_ref().map_or(false, |b| b.initrd.is_some()) => {
+                return Err(BundleInitrdConflict)
+            }
             Some(path) => Some(File::open(path).map_err(InvalidInitrdPath)?),
             None => None,
         };
+
         let mut cmdline = kernel::cmdline::Cmdline::new(arch::CMDLINE_MAX_SIZE);
-        let boot_args = match boot_source_cfg.boot_args.as_ref() {
-            None => DEFAULT_KERNEL_CMDLINE,
-            Some(str) => str.as_str(),
+        let boot_args = boot_source_cfg.boot_args.as_deref();
+        let embedded_cmdline = bundle.as_ref().and_then(|b| b.cmdline.as_deref());
+        let cmdline_parts = match (embedded_cmdline, boot_args) {
+            (None, None) => vec![DEFAULT_KERNEL_CMDLINE],
+            (None, Some(boot_args)) => vec![boot_args],
+            (Some(embedded), None) => vec![embedded],
+            (Some(embedded), Some(boot_args)) => {
+                match boot_source_cfg.boot_args_policy.unwrap_or_default() {
+                    BootArgsPolicy::Append => vec![embedded, boot_args],
+                    BootArgsPolicy::Replace => vec![boot_args],
+                    BootArgsPolicy::Ignore => vec![embedded],
+                }
+            }
         };
-        cmdline
-            .insert_str(boot_args)
-            .map_err(|e| InvalidKernelCommandLine(e.to_string()))?;
+        for part in cmdline_parts.into_iter().filter(|part| !part.is_empty()) {
+            cmdline
+                .insert_str(part)
+                .map_err(|e| InvalidKernelCommandLine(e.to_string()))?;
+        }
 
         self.boot_config = Some(BootConfig {
             cmdline,
             kernel_file,
             initrd_file,
+            bundle,
+            measured_boot: boot_source_cfg.measured_boot.unwrap_or(false),
         });
         Ok(())
     }
@@ -306,17 +516,12 @@ impl VmResources {
         &mut self,
         body: NetworkInterfaceConfig,
     ) -> Result<NetworkInterfaceError> {
-        self.net_builder.build(body).map(|net_device| {
-            // Update `Net` device `MmdsNetworkStack` IPv4 address.
-            match &self.mmds_config {
-                Some(cfg) => cfg.ipv4_addr().map_or((), |ipv4_addr| {
-                    if let Some(mmds_ns) = net_device.lock().expect("Poisoned lock").mmds_ns_mut() {
-                        mmds_ns.set_ipv4_addr(ipv4_addr);
-                    };
-                }),
-                None => (),
-            };
-        })
+        let net_device = self.net_builder.build(body)?;
+        let mut net_device = net_device.lock().expect("Poisoned lock");
+        // Update `Net` device `MmdsNetworkStack` IPv4 address and MMDS instance.
+        self.configure_mmds_network_stack(&mut net_device);
+        self.configure_dns_responder(&mut net_device);
+        Ok(())
     }
 
     /// Sets a vsock device to be attached when the VM starts.
@@ -324,42 +529,189 @@ impl VmResources {
         self.vsock.insert(config)
     }
 
+    /// Sets a TPM device to be attached when the VM starts.
+    pub fn set_tpm_device(&mut self, config: TpmConfig) -> Result<TpmConfigError> {
+        config.validate()?;
+        self.tpm_config = Some(config);
+        Ok(())
+    }
+
     /// Setter for mmds config.
     pub fn set_mmds_config(&mut self, config: MmdsConfig) -> Result<MmdsConfigError> {
         // Check IPv4 address validity.
-        let ipv4_addr = match config.ipv4_addr() {
-            Some(ipv4_addr) if is_link_local_valid(ipv4_addr) => Ok(ipv4_addr),
-            None => Ok(MmdsNetworkStack::default_ipv4_addr()),
-            _ => Err(MmdsConfigError::InvalidIpv4Addr),
-        }?;
-
-        // Update existing built network device `MmdsNetworkStack` IPv4 address.
-        for net_device in self.net_builder.iter_mut() {
-            if let Some(mmds_ns) = net_device.lock().expect("Poisoned lock").mmds_ns_mut() {
-                mmds_ns.set_ipv4_addr(ipv4_addr)
+        if let Some(ipv4_addr) = config.ipv4_addr() {
+            if !is_link_local_valid(ipv4_addr) {
+                return Err(MmdsConfigError::InvalidIpv4Addr);
+            }
+        }
+        if let Some(ipv6_addr) = config.ipv6_addr() {
+            if !is_local_unicast_valid(ipv6_addr) {
+                return Err(MmdsConfigError::InvalidIpv6Addr);
+            }
+        }
+
+        match config.mmds_id.as_deref() {
+            Some("") => return Err(MmdsConfigError::InvalidMmdsId),
+            Some(mmds_id) if config.network_interfaces.is_empty() => {
+                return Err(MmdsConfigError::MissingNetworkInterfaces(
+                    mmds_id.to_string(),
+                ))
+            }
+            _ => (),
+        }
+
+        config.connection_limits().validate()?;
+
+        // Each network interface serves a single MMDS instance, for the lifetime of the microVM.
+        for iface_id in config.network_interfaces.iter() {
+            if !self
+                .net_builder
+                .iter()
+                .any(|net_device| net_device.lock().expect("Poisoned lock").id() == iface_id)
+            {
+                return Err(MmdsConfigError::InvalidNetworkInterfaceId(iface_id.clone()));
+            }
+            if self.mmds_configs().any(|other| {
+                other.mmds_id != config.mmds_id && other.network_interfaces.contains(iface_id)
+            }) {
+                return Err(MmdsConfigError::NetworkInterfaceInUse(iface_id.clone()));
+            }
+        }
+        if let Some(iface_id) = self
+            .mmds_configs()
+            .filter(|other| other.mmds_id == config.mmds_id)
+            .flat_map(|other| other.network_interfaces.iter())
+            .find(|iface_id| !config.network_interfaces.contains(*iface_id))
+        {
+            return Err(MmdsConfigError::NetworkInterfaceDetached(iface_id.clone()));
+        }
+
+        {
+            let mmds_instance = mmds::mmds_instance(config.mmds_id.as_deref());
+            let mut mmds_instance = mmds_instance.lock().expect("Poisoned lock");
+            mmds_instance
+                .set_version(config.version)
+                .map_err(MmdsConfigError::TokenAuthority)?;
+            mmds_instance.set_guest_write_limits(
+                config
+                    .guest_write
+                    .as_ref()
+                    .map(MmdsGuestWriteConfig::guest_write_limits),
+            );
+        }
+
+        match config.mmds_id.clone() {
+            Some(mmds_id) => {
+                self.mmds_instances.insert(mmds_id, config);
             }
+            None => self.mmds_config = Some(config),
+        }
+
+        // Update existing built network device `MmdsNetworkStack` IPv4 address and MMDS instance.
+        for net_device in self.net_builder.iter() {
+            self.configure_mmds_network_stack(&mut net_device.lock().expect("Poisoned lock"));
         }
 
-        self.mmds_config = Some(config);
         Ok(())
     }
+
+    /// Sets the DNS responder answering the queries of the guest on the listed network
+    /// interfaces, replacing the previous one.
+    pub fn set_dns_config(&mut self, config: DnsConfig) -> Result<DnsConfigError> {
+        if config.network_interfaces.is_empty() {
+            return Err(DnsConfigError::MissingNetworkInterfaces);
+        }
+        for iface_id in config.network_interfaces.iter() {
+            if !self
+                .net_builder
+                .iter()
+                .any(|net_device| net_device.lock().expect("Poisoned lock").id() == iface_id)
+            {
+                return Err(DnsConfigError::InvalidNetworkInterfaceId(iface_id.clone()));
+            }
+        }
+        // Checks the address and the records before changing anything.
+        config.responder()?;
+
+        self.dns_config = Some(config);
+        for net_device in self.net_builder.iter() {
+            self.configure_dns_responder(&mut net_device.lock().expect("Poisoned lock"));
+        }
+
+        Ok(())
+    }
+
+    // Lets the net device answer the DNS queries of the guest, if the DNS configuration lists it.
+    fn configure_dns_responder(&self, net: &mut Net) {
+        net.set_dns_responder(
+            self.dns_config
+                .as_ref()
+                .filter(|config| config.network_interfaces.contains(net.id()))
+                // The configuration was validated when set.
+                .and_then(|config| config.responder().ok()),
+        );
+    }
+
+    // Iterates over the configurations of the default and of the named MMDS instances.
+    fn mmds_configs(&self) -> impl Iterator<Item = &MmdsConfig> {
+        self.mmds_config.iter().chain(self.mmds_instances.values())
+    }
+
+    // Attaches the net device to the MMDS instance it serves, if any. Net devices allowing MMDS
+    // requests which are not listed by any configuration serve the default instance.
+    fn configure_mmds_network_stack(&self, net: &mut Net) {
+        let mmds_ipv4_addr = |config: &MmdsConfig| {
+            config
+                .ipv4_addr()
+                .unwrap_or_else(MmdsNetworkStack::default_ipv4_addr)
+        };
+
+        let iface_id = net.id().clone();
+        match self
+            .mmds_configs()
+            .find(|config| config.network_interfaces.contains(&iface_id))
+        {
+            Some(config) => {
+                net.configure_mmds_network_stack(mmds_ipv4_addr(config), config.mmds_id.clone());
+                if let Some(mmds_ns) = net.mmds_ns_mut() {
+                    mmds_ns.set_ipv6_addr(config.ipv6_addr());
+                    let (max_connections, endpoint_limits) = config.connection_limits().limits();
+                    mmds_ns.set_connection_limits(max_connections, endpoint_limits);
+                }
+            }
+            None => {
+                if let (Some(config), Some(mmds_ns)) = (&self.mmds_config, net.mmds_ns_mut()) {
+                    mmds_ns.set_ipv4_addr(mmds_ipv4_addr(config));
+                    mmds_ns.set_ipv6_addr(config.ipv6_addr());
+                    let (max_connections, endpoint_limits) = config.connection_limits().limits();
+                    mmds_ns.set_connection_limits(max_connections, endpoint_limits);
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs::File;
+    use std::io::Write;
+    use std::net::{Ipv4Addr, Ipv6Addr};
     use std::os::linux::fs::MetadataExt;
 
     use super::*;
     use crate::resources::VmResources;
     use crate::vmm_config::boot_source::{BootConfig, BootSourceConfig, DEFAULT_KERNEL_CMDLINE};
     use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
-    use crate::vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig, VmConfigError};
+    use crate::vmm_config::machine_config::{
+        CpuFeaturesTemplate, MemoryBackend, ThreadSchedPolicy, VmConfig, VmConfigError,
+    };
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::RateLimiterConfig;
     use crate::vstate::vcpu::VcpuConfig;
+    use devices::tpm::test_utils::StandInEmulator;
     use logger::{LevelFilter, LOGGER};
+    use mmds::data_store::MmdsVersion;
     use utils::net::mac::MacAddr;
     use utils::tempfile::TempFile;
 
@@ -377,6 +729,7 @@ mod tests {
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
             allow_mmds_requests: false,
+            dhcp: None,
         }
     }
 
@@ -418,6 +771,8 @@ mod tests {
             cmdline: kernel_cmdline,
             kernel_file: File::open(tmp_file.as_path()).unwrap(),
             initrd_file: Some(File::open(tmp_file.as_path()).unwrap()),
+            bundle: None,
+            measured_boot: false,
         }
     }
 
@@ -425,11 +780,15 @@ mod tests {
         VmResources {
             vm_config: VmConfig::default(),
             boot_config: Some(default_boot_cfg()),
+            cpu_config: None,
             block: default_blocks(),
             vsock: Default::default(),
             balloon: Default::default(),
             net_builder: default_net_builder(),
             mmds_config: None,
+            mmds_instances: HashMap::new(),
+            dns_config: None,
+            tpm_config: None,
             boot_timer: false,
         }
     }
@@ -437,6 +796,8 @@ mod tests {
     impl PartialEq for BootConfig {
         fn eq(&self, other: &Self) -> bool {
             self.cmdline.as_str().eq(other.cmdline.as_str())
+                && self.bundle == other.bundle
+                && self.measured_boot == other.measured_boot
                 && self.kernel_file.metadata().unwrap().st_ino()
                     == other.kernel_file.metadata().unwrap().st_ino()
                 && self
@@ -466,6 +827,7 @@ mod tests {
             state: "Not started".to_string(),
             vmm_version: "SOME_VERSION".to_string(),
             app_name: "".to_string(),
+            boot_measurements: None,
         };
 
         // We will test different scenarios with invalid resources configuration and
@@ -677,6 +1039,48 @@ mod tests {
             _ => unreachable!(),
         }
 
+        // Test a custom CPU template setting bits outside of its mask.
+        json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "cpu-config": {{
+                        "cpuid_modifiers": [
+                            {{"leaf": 1, "register": "ecx", "mask": 1, "value": 2}}
+                        ]
+                    }},
+                    "drives": []
+            }}"#,
+            kernel_file.as_path().to_str().unwrap()
+        );
+
+        match VmResources::from_json(json.as_str(), &default_instance_info) {
+            Err(Error::CpuConfig(_)) => (),
+            _ => unreachable!(),
+        }
+
+        // Test a TPM device with an invalid control socket.
+        json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "drives": [],
+                    "tpm": {{
+                        "socket": "/invalid/path"
+                    }}
+            }}"#,
+            kernel_file.as_path().to_str().unwrap()
+        );
+
+        match VmResources::from_json(json.as_str(), &default_instance_info) {
+            Err(Error::TpmDevice(TpmConfigError::InvalidSocket(_))) => (),
+            _ => unreachable!(),
+        }
+
         // Let's try now passing a valid configuration. We won't include any logger
         // or metrics configuration because these were already initialized in other
         // tests of this module and the reinitialization of them will cause crashing.
@@ -750,7 +1154,13 @@ mod tests {
                         "mem_size_mib": 1024,
                         "ht_enabled": false
                     }},
-                    "mmds-config": {{}}
+                    "mmds-config": {{}},
+                    "mmds-instances": [
+                        {{
+                            "mmds_id": "from_json",
+                            "network_interfaces": ["netif"]
+                        }}
+                    ]
             }}"#,
             kernel_file.as_path().to_str().unwrap(),
             rootfs_file.as_path().to_str().unwrap(),
@@ -758,6 +1168,261 @@ mod tests {
         assert!(VmResources::from_json(json.as_str(), &default_instance_info).is_ok());
     }
 
+    #[test]
+    fn test_from_json_metadata() {
+        let kernel_file = TempFile::new().unwrap();
+        let default_instance_info = InstanceInfo {
+            id: "".to_string(),
+            state: "Not started".to_string(),
+            vmm_version: "SOME_VERSION".to_string(),
+            app_name: "".to_string(),
+            boot_measurements: None,
+        };
+
+        // The metadata is merged into the data the default MMDS instance was preloaded with.
+        let json = format!(
+            r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}",
+                        "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+                    }},
+                    "drives": [],
+                    "metadata": {{
+                        "from": "config-file"
+                    }}
+            }}"#,
+            kernel_file.as_path().to_str().unwrap(),
+        );
+        // The default MMDS instance is shared with the other tests, so the data store is reset
+        // under the lock, and only the keys of this test are checked.
+        mmds::MMDS
+            .lock()
+            .unwrap()
+            .put_data(serde_json::json!({"from": "metadata-file", "other": "value"}))
+            .unwrap();
+        assert!(VmResources::from_json(json.as_str(), &default_instance_info).is_ok());
+        let data: Value = serde_json::from_str(&mmds::MMDS.lock().unwrap().get_data_str()).unwrap();
+        assert_eq!(data["from"], "config-file");
+        assert_eq!(data["other"], "value");
+    }
+
+    #[test]
+    fn test_set_mmds_config() {
+        let mut vm_resources = default_vm_resources();
+
+        vm_resources
+            .set_mmds_config(MmdsConfig {
+                ipv4_address: None,
+                ipv6_address: None,
+                version: MmdsVersion::V2,
+                mmds_id: None,
+                network_interfaces: vec![],
+                guest_write: None,
+                connection_limits: None,
+            })
+            .unwrap();
+        assert_eq!(mmds::MMDS.lock().unwrap().version(), MmdsVersion::V2);
+
+        vm_resources
+            .set_mmds_config(MmdsConfig {
+                ipv4_address: Some(Ipv4Addr::new(169, 254, 170, 2)),
+                ipv6_address: None,
+                version: MmdsVersion::V1,
+                mmds_id: None,
+                network_interfaces: vec![],
+                guest_write: None,
+                connection_limits: None,
+            })
+            .unwrap();
+        assert_eq!(mmds::MMDS.lock().unwrap().version(), MmdsVersion::V1);
+
+        // The IPv4 address has to be link local.
+        assert!(matches!(
+            vm_resources.set_mmds_config(MmdsConfig {
+                ipv4_address: Some(Ipv4Addr::new(10, 0, 0, 1)),
+                ipv6_address: None,
+                version: MmdsVersion::V2,
+                mmds_id: None,
+                network_interfaces: vec![],
+                guest_write: None,
+                connection_limits: None,
+            }),
+            Err(MmdsConfigError::InvalidIpv4Addr)
+        ));
+        assert_eq!(mmds::MMDS.lock().unwrap().version(), MmdsVersion::V1);
+
+        // The IPv6 address has to be link local or unique local.
+        assert!(matches!(
+            vm_resources.set_mmds_config(MmdsConfig {
+                ipv4_address: None,
+                ipv6_address: Some(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+                version: MmdsVersion::V2,
+                mmds_id: None,
+                network_interfaces: vec![],
+                guest_write: None,
+                connection_limits: None,
+            }),
+            Err(MmdsConfigError::InvalidIpv6Addr)
+        ));
+        assert_eq!(mmds::MMDS.lock().unwrap().version(), MmdsVersion::V1);
+
+        // The network interfaces allowed to reach the MMDS serve it over IPv6 as well.
+        let mut net_cfg = default_net_cfg();
+        net_cfg.allow_mmds_requests = true;
+        vm_resources.build_net_device(net_cfg).unwrap();
+        let mmds_ipv6_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        vm_resources
+            .set_mmds_config(MmdsConfig {
+                ipv4_address: None,
+                ipv6_address: Some(mmds_ipv6_addr),
+                version: MmdsVersion::V1,
+                mmds_id: None,
+                network_interfaces: vec![],
+                guest_write: None,
+                connection_limits: None,
+            })
+            .unwrap();
+        let net = vm_resources.net_builder.iter().next().unwrap();
+        assert_eq!(
+            net.lock().unwrap().mmds_ns_mut().unwrap().ipv6_addr(),
+            Some(mmds_ipv6_addr)
+        );
+
+        // The connection limits have to be within bounds.
+        let config = |connection_limits| MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+            version: MmdsVersion::V1,
+            mmds_id: None,
+            network_interfaces: vec![],
+            guest_write: None,
+            connection_limits: Some(connection_limits),
+        };
+        assert!(matches!(
+            vm_resources.set_mmds_config(config(MmdsConnectionLimitsConfig {
+                max_connections: 0,
+                ..Default::default()
+            })),
+            Err(MmdsConfigError::InvalidConnectionLimits)
+        ));
+        assert!(matches!(
+            vm_resources.set_mmds_config(config(MmdsConnectionLimitsConfig {
+                receive_buffer_size: 65536,
+                ..Default::default()
+            })),
+            Err(MmdsConfigError::InvalidConnectionLimits)
+        ));
+        vm_resources
+            .set_mmds_config(config(MmdsConnectionLimitsConfig {
+                max_connections: 5,
+                receive_buffer_size: 4096,
+                send_buffer_size: 4096,
+            }))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_set_mmds_instance_config() {
+        let mut vm_resources = default_vm_resources();
+        let mut mgmt_net_cfg = default_net_cfg();
+        mgmt_net_cfg.iface_id = "net_if2".to_string();
+        mgmt_net_cfg.guest_mac = Some(MacAddr::parse_str("01:23:45:67:89:0b").unwrap());
+        mgmt_net_cfg.allow_mmds_requests = true;
+        vm_resources.build_net_device(default_net_cfg()).unwrap();
+        vm_resources.build_net_device(mgmt_net_cfg).unwrap();
+
+        let mmds_id_of = |vm_resources: &VmResources, iface_id: &str| {
+            vm_resources.net_builder.iter().find_map(|net| {
+                let mut net = net.lock().unwrap();
+                if net.id() != iface_id {
+                    return None;
+                }
+                net.mmds_ns_mut()
+                    .map(|mmds_ns| mmds_ns.mmds_id().map(str::to_string))
+            })
+        };
+        let instance_config = |mmds_id: &str, network_interfaces: &[&str]| MmdsConfig {
+            ipv4_address: Some(Ipv4Addr::new(169, 254, 170, 2)),
+            ipv6_address: None,
+            version: MmdsVersion::V2,
+            mmds_id: Some(mmds_id.to_string()),
+            network_interfaces: network_interfaces.iter().map(|id| id.to_string()).collect(),
+            guest_write: None,
+            connection_limits: None,
+        };
+
+        // Named instances need a name and network interfaces.
+        assert!(matches!(
+            vm_resources.set_mmds_config(instance_config("", &["net_if1"])),
+            Err(MmdsConfigError::InvalidMmdsId)
+        ));
+        assert!(matches!(
+            vm_resources.set_mmds_config(instance_config("resources_tenant", &[])),
+            Err(MmdsConfigError::MissingNetworkInterfaces(_))
+        ));
+        assert!(matches!(
+            vm_resources.set_mmds_config(instance_config("resources_tenant", &["net_if3"])),
+            Err(MmdsConfigError::InvalidNetworkInterfaceId(_))
+        ));
+        assert!(mmds::find_mmds_instance("resources_tenant").is_none());
+
+        vm_resources
+            .set_mmds_config(instance_config("resources_tenant", &["net_if1"]))
+            .unwrap();
+        assert_eq!(
+            mmds_id_of(&vm_resources, "net_if1"),
+            Some(Some("resources_tenant".to_string()))
+        );
+        assert_eq!(mmds_id_of(&vm_resources, "net_if2"), Some(None));
+        assert_eq!(
+            mmds::find_mmds_instance("resources_tenant")
+                .unwrap()
+                .lock()
+                .unwrap()
+                .version(),
+            MmdsVersion::V2
+        );
+
+        // A network interface serves a single instance, for the lifetime of the microVM.
+        assert!(matches!(
+            vm_resources.set_mmds_config(instance_config("resources_other", &["net_if1"])),
+            Err(MmdsConfigError::NetworkInterfaceInUse(_))
+        ));
+        assert!(matches!(
+            vm_resources.set_mmds_config(instance_config("resources_tenant", &["net_if2"])),
+            Err(MmdsConfigError::NetworkInterfaceDetached(_))
+        ));
+
+        // The network interface serves the instance after being updated.
+        vm_resources.build_net_device(default_net_cfg()).unwrap();
+        assert_eq!(
+            mmds_id_of(&vm_resources, "net_if1"),
+            Some(Some("resources_tenant".to_string()))
+        );
+
+        // The guest can write its own data store once allowed to.
+        let tenant = mmds::find_mmds_instance("resources_tenant").unwrap();
+        assert!(!tenant.lock().unwrap().guest_writes_allowed());
+        let mut config = instance_config("resources_tenant", &["net_if1"]);
+        config.guest_write = Some(MmdsGuestWriteConfig {
+            size_limit: 16,
+            rate_limiter: None,
+        });
+        vm_resources.set_mmds_config(config).unwrap();
+        assert!(tenant.lock().unwrap().guest_writes_allowed());
+        assert_eq!(
+            tenant
+                .lock()
+                .unwrap()
+                .put_guest_data(serde_json::json!({"key": "a value too long"})),
+            Err(mmds::data_store::Error::DataStoreLimitExceeded)
+        );
+        vm_resources
+            .set_mmds_config(instance_config("resources_tenant", &["net_if1"]))
+            .unwrap();
+        assert!(!tenant.lock().unwrap().guest_writes_allowed());
+    }
+
     #[test]
     fn test_vcpu_config() {
         let vm_resources = default_vm_resources();
@@ -765,6 +1430,7 @@ mod tests {
             vcpu_count: vm_resources.vm_config().vcpu_count.unwrap(),
             ht_enabled: vm_resources.vm_config().ht_enabled.unwrap(),
             cpu_template: vm_resources.vm_config().cpu_template,
+            custom_cpu_template: None,
         };
 
         let vcpu_config = vm_resources.vcpu_config();
@@ -781,15 +1447,41 @@ mod tests {
 
     #[test]
     fn test_set_vm_config() {
+        #[cfg(target_arch = "x86_64")]
+        let (template, foreign_template) = (CpuFeaturesTemplate::T2, CpuFeaturesTemplate::V1N1);
+        #[cfg(target_arch = "aarch64")]
+        let (template, foreign_template) = (CpuFeaturesTemplate::V1N1, CpuFeaturesTemplate::T2);
+
         let mut vm_resources = default_vm_resources();
         let mut aux_vm_config = VmConfig {
             vcpu_count: Some(32),
+            max_vcpu_count: None,
             mem_size_mib: Some(512),
             ht_enabled: Some(true),
-            cpu_template: Some(CpuFeaturesTemplate::T2),
+            cpu_template: Some(template),
             track_dirty_pages: false,
+            mem_backend: MemoryBackend::Anonymous,
+            vcpu_affinity: None,
+            vmm_affinity: None,
+            api_affinity: None,
+            vcpu_sched_policy: None,
+            #[cfg(feature = "gdb")]
+            gdb_socket_path: None,
         };
 
+        // Whether V1N1 can be applied depends on the host kernel.
+        #[cfg(target_arch = "aarch64")]
+        if let Err(err) = crate::vstate::vcpu::validate_cpu_template(template) {
+            assert_eq!(
+                vm_resources.set_vm_config(&aux_vm_config),
+                Err(VmConfigError::CpuTemplateNotApplicable(
+                    template,
+                    err.to_string()
+                ))
+            );
+            aux_vm_config.cpu_template = None;
+        }
+
         assert_ne!(vm_resources.vm_config, aux_vm_config);
         vm_resources.set_vm_config(&aux_vm_config).unwrap();
         assert_eq!(vm_resources.vm_config, aux_vm_config);
@@ -807,6 +1499,15 @@ mod tests {
         );
         aux_vm_config.vcpu_count = Some(32);
 
+        // CPU template for another architecture.
+        let valid_template = aux_vm_config.cpu_template;
+        aux_vm_config.cpu_template = Some(foreign_template);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::CpuTemplateNotSupported(foreign_template))
+        );
+        aux_vm_config.cpu_template = valid_template;
+
         // Invalid mem_size_mib.
         aux_vm_config.mem_size_mib = Some(0);
         assert_eq!(
@@ -832,6 +1533,146 @@ mod tests {
         // mem_size_mib compatible with balloon size.
         aux_vm_config.mem_size_mib = Some(256);
         assert!(vm_resources.set_vm_config(&aux_vm_config).is_ok());
+
+        // mem_size_mib not made of whole huge pages.
+        aux_vm_config.mem_size_mib = Some(257);
+        aux_vm_config.mem_backend = MemoryBackend::Hugetlbfs2M;
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::MemorySizeNotPageAligned(
+                MemoryBackend::Hugetlbfs2M
+            ))
+        );
+        aux_vm_config.mem_size_mib = Some(1536);
+        aux_vm_config.mem_backend = MemoryBackend::Hugetlbfs1G;
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::MemorySizeNotPageAligned(
+                MemoryBackend::Hugetlbfs1G
+            ))
+        );
+
+        // Memory backends with a compatible mem_size_mib.
+        aux_vm_config.mem_size_mib = Some(2048);
+        assert!(vm_resources.set_vm_config(&aux_vm_config).is_ok());
+        assert_eq!(vm_resources.mem_backend(), MemoryBackend::Hugetlbfs1G);
+        aux_vm_config.mem_size_mib = Some(258);
+        aux_vm_config.mem_backend = MemoryBackend::Hugetlbfs2M;
+        assert!(vm_resources.set_vm_config(&aux_vm_config).is_ok());
+        aux_vm_config.mem_size_mib = Some(257);
+        aux_vm_config.mem_backend = MemoryBackend::SharedMemfd;
+        assert!(vm_resources.set_vm_config(&aux_vm_config).is_ok());
+        assert_eq!(vm_resources.mem_backend(), MemoryBackend::SharedMemfd);
+    }
+
+    #[test]
+    fn test_set_vm_config_threads() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            vcpu_count: Some(2),
+            vcpu_affinity: Some(vec![vec![2], vec![3]]),
+            vmm_affinity: Some(vec![0]),
+            api_affinity: Some(vec![0, 1]),
+            vcpu_sched_policy: Some(ThreadSchedPolicy::Fifo { priority: 10 }),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config, aux_vm_config);
+
+        // The affinities are kept when they are not part of the update.
+        let update = VmConfig {
+            vcpu_count: None,
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&update).unwrap();
+        assert_eq!(
+            vm_resources.vm_config.vcpu_affinity,
+            Some(vec![vec![2], vec![3]])
+        );
+        assert_eq!(vm_resources.vm_config.api_affinity, Some(vec![0, 1]));
+
+        // The vCPU number cannot change without the vCPU affinities.
+        let update = VmConfig {
+            vcpu_count: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&update),
+            Err(VmConfigError::VcpuAffinityCountMismatch)
+        );
+        aux_vm_config.vcpu_affinity = Some(vec![vec![2]]);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::VcpuAffinityCountMismatch)
+        );
+
+        // Empty sets of host CPUs.
+        aux_vm_config.vcpu_affinity = Some(vec![vec![2], vec![]]);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidCpuAffinity)
+        );
+        aux_vm_config.vcpu_affinity = Some(vec![vec![2], vec![3]]);
+        aux_vm_config.api_affinity = Some(vec![]);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidCpuAffinity)
+        );
+        aux_vm_config.api_affinity = None;
+
+        // Out of range scheduling priority.
+        aux_vm_config.vcpu_sched_policy = Some(ThreadSchedPolicy::Fifo { priority: 100 });
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidSchedPriority(100))
+        );
+    }
+
+    #[test]
+    fn test_set_vm_config_max_vcpus() {
+        let mut vm_resources = default_vm_resources();
+        let mut aux_vm_config = VmConfig {
+            vcpu_count: Some(2),
+            max_vcpu_count: Some(4),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
+        assert_eq!(vm_resources.max_vcpu_count(), 4);
+        assert_eq!(vm_resources.vcpu_config().vcpu_count, 4);
+
+        // The maximum vCPU number is kept when it is not part of the update.
+        let update = VmConfig {
+            vcpu_count: Some(3),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&update).unwrap();
+        assert_eq!(vm_resources.vm_config.max_vcpu_count, Some(4));
+        let update = VmConfig {
+            vcpu_count: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&update),
+            Err(VmConfigError::InvalidMaxVcpuCount)
+        );
+
+        // The maximum vCPU number follows the hyperthreading rule as well.
+        aux_vm_config.max_vcpu_count = Some(3);
+        aux_vm_config.ht_enabled = Some(true);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidVcpuCount)
+        );
+
+        // Every vCPU that can be started later needs an affinity.
+        aux_vm_config.max_vcpu_count = Some(4);
+        aux_vm_config.vcpu_affinity = Some(vec![vec![0], vec![1]]);
+        assert_eq!(
+            vm_resources.set_vm_config(&aux_vm_config),
+            Err(VmConfigError::VcpuAffinityCountMismatch)
+        );
+        aux_vm_config.vcpu_affinity = Some(vec![vec![0], vec![1], vec![2], vec![3]]);
+        vm_resources.set_vm_config(&aux_vm_config).unwrap();
     }
 
     #[test]
@@ -839,11 +1680,15 @@ mod tests {
         let mut vm_resources = VmResources {
             vm_config: VmConfig::default(),
             boot_config: Some(default_boot_cfg()),
+            cpu_config: None,
             block: default_blocks(),
             vsock: Default::default(),
             balloon: BalloonBuilder::new(),
             net_builder: default_net_builder(),
             mmds_config: None,
+            mmds_instances: HashMap::new(),
+            dns_config: None,
+            tpm_config: None,
             boot_timer: false,
         };
         let mut new_balloon_cfg = BalloonDeviceConfig {
@@ -870,11 +1715,15 @@ mod tests {
         vm_resources = VmResources {
             vm_config: VmConfig::default(),
             boot_config: Some(default_boot_cfg()),
+            cpu_config: None,
             block: default_blocks(),
             vsock: Default::default(),
             balloon: BalloonBuilder::new(),
             net_builder: default_net_builder(),
             mmds_config: None,
+            mmds_instances: HashMap::new(),
+            dns_config: None,
+            tpm_config: None,
             boot_timer: false,
         };
         new_balloon_cfg.amount_mb = 256;
@@ -898,6 +1747,8 @@ mod tests {
             kernel_image_path: String::from(tmp_file.as_path().to_str().unwrap()),
             initrd_path: Some(String::from(tmp_file.as_path().to_str().unwrap())),
             boot_args: Some(cmdline.to_string()),
+            boot_args_policy: None,
+            measured_boot: Some(true),
         };
 
         let mut vm_resources = default_vm_resources();
@@ -920,6 +1771,7 @@ mod tests {
         vm_resources.set_boot_source(expected_boot_cfg).unwrap();
         let boot_cfg = vm_resources.boot_source().unwrap();
         assert_eq!(boot_cfg.cmdline.as_str(), cmdline);
+        assert!(boot_cfg.measured_boot);
         assert_eq!(boot_cfg.kernel_file.metadata().unwrap().st_ino(), tmp_ino);
         assert_eq!(
             boot_cfg
@@ -933,6 +1785,77 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_set_boot_source_bundle() {
+        // Bundle with a kernel, an initrd and the `console=ttyS0` command line.
+        let mut bundle = b"FCBUNDLE".to_vec();
+        bundle.extend_from_slice(&1u32.to_le_bytes());
+        bundle.extend_from_slice(&3u32.to_le_bytes());
+        for (section_type, offset, size) in &[(1u32, 88u64, 16u64), (2, 104, 8), (3, 112, 13)] {
+            bundle.extend_from_slice(&section_type.to_le_bytes());
+            bundle.extend_from_slice(&0u32.to_le_bytes());
+            bundle.extend_from_slice(&offset.to_le_bytes());
+            bundle.extend_from_slice(&size.to_le_bytes());
+        }
+        bundle.extend_from_slice(&[0xaa; 24]);
+        bundle.extend_from_slice(b"console=ttyS0");
+        let bundle_file = TempFile::new().unwrap();
+        bundle_file.as_file().write_all(&bundle).unwrap();
+        let bundle_path = String::from(bundle_file.as_path().to_str().unwrap());
+
+        let mut vm_resources = default_vm_resources();
+        let mut boot_source_cfg = BootSourceConfig {
+            kernel_image_path: bundle_path,
+            initrd_path: None,
+            boot_args: None,
+            boot_args_policy: None,
+            measured_boot: None,
+        };
+        vm_resources
+            .set_boot_source(boot_source_cfg.clone())
+            .unwrap();
+        let boot_cfg = vm_resources.boot_source().unwrap();
+        assert_eq!(boot_cfg.cmdline.as_str(), "console=ttyS0");
+        assert!(boot_cfg.initrd_file.is_none());
+        let bundle = boot_cfg.bundle.as_ref().unwrap();
+        assert_eq!(bundle.kernel.offset, 88);
+        assert_eq!(bundle.initrd.unwrap().size, 8);
+
+        // The boot arguments are merged according to the policy, appended by default.
+        boot_source_cfg.boot_args = Some("reboot=k".to_string());
+        for (policy, expected_cmdline) in &[
+            (None, "console=ttyS0 reboot=k"),
+            (Some(BootArgsPolicy::Append), "console=ttyS0 reboot=k"),
+            (Some(BootArgsPolicy::Replace), "reboot=k"),
+            (Some(BootArgsPolicy::Ignore), "console=ttyS0"),
+        ] {
+            boot_source_cfg.boot_args_policy = *policy;
+            vm_resources
+                .set_boot_source(boot_source_cfg.clone())
+                .unwrap();
+            let boot_cfg = vm_resources.boot_source().unwrap();
+            assert_eq!(boot_cfg.cmdline.as_str(), *expected_cmdline);
+        }
+
+        // The initrd cannot be provided twice.
+        let initrd_file = TempFile::new().unwrap();
+        boot_source_cfg.initrd_path = Some(String::from(initrd_file.as_path().to_str().unwrap()));
+        match vm_resources.set_boot_source(boot_source_cfg.clone()) {
+            Err(BootSourceConfigError::BundleInitrdConflict) => (),
+            _ => unreachable!(),
+        }
+
+        // Corrupted bundles are rejected.
+        bundle_file.as_file().set_len(100).unwrap();
+        boot_source_cfg.initrd_path = None;
+        match vm_resources.set_boot_source(boot_source_cfg) {
+            Err(BootSourceConfigError::InvalidKernelBundle(
+                kernel::bundle::Error::InvalidSectionBounds,
+            )) => (),
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn test_set_block_device() {
         let mut vm_resources = default_vm_resources();
@@ -962,6 +1885,94 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_set_tpm_device() {
+        let mut vm_resources = default_vm_resources();
+        let stand_in = StandInEmulator::new();
+        let tpm_config = TpmConfig {
+            socket: stand_in.socket_path().to_str().unwrap().to_string(),
+        };
+        assert!(vm_resources.tpm_config.is_none());
+        vm_resources.set_tpm_device(tpm_config.clone()).unwrap();
+        assert_eq!(vm_resources.tpm_config, Some(tpm_config));
+
+        // The configuration is left unchanged when the socket is invalid.
+        let invalid_config = TpmConfig {
+            socket: "/invalid/path".to_string(),
+        };
+        assert!(matches!(
+            vm_resources.set_tpm_device(invalid_config),
+            Err(TpmConfigError::InvalidSocket(_))
+        ));
+        assert!(vm_resources.tpm_config.is_some());
+    }
+
+    #[test]
+    fn test_set_dns_config() {
+        let mut vm_resources = default_vm_resources();
+        let mut dns_config = DnsConfig {
+            ipv4_address: None,
+            network_interfaces: vec![],
+            ttl: 60,
+            records: vec![],
+        };
+        assert_eq!(
+            vm_resources.set_dns_config(dns_config.clone()),
+            Err(DnsConfigError::MissingNetworkInterfaces)
+        );
+
+        dns_config.network_interfaces = vec!["invalid".to_string()];
+        assert_eq!(
+            vm_resources.set_dns_config(dns_config.clone()),
+            Err(DnsConfigError::InvalidNetworkInterfaceId(
+                "invalid".to_string()
+            ))
+        );
+
+        dns_config.network_interfaces = vec!["net_if1".to_string()];
+        dns_config.ipv4_address = Some(Ipv4Addr::new(10, 0, 0, 53));
+        assert_eq!(
+            vm_resources.set_dns_config(dns_config.clone()),
+            Err(DnsConfigError::InvalidIpv4Addr)
+        );
+        assert!(vm_resources.dns_config.is_none());
+
+        dns_config.ipv4_address = Some(Ipv4Addr::new(169, 254, 0, 53));
+        vm_resources.set_dns_config(dns_config.clone()).unwrap();
+        assert_eq!(vm_resources.dns_config, Some(dns_config));
+        let net_device = vm_resources.net_builder.iter().next().unwrap();
+        assert_eq!(
+            net_device
+                .lock()
+                .unwrap()
+                .dns_responder()
+                .unwrap()
+                .ipv4_addr(),
+            Ipv4Addr::new(169, 254, 0, 53)
+        );
+
+        // Net devices built afterwards answer the queries as well.
+        let has_dns_responder = |vm_resources: &VmResources, iface_id: &str| {
+            vm_resources
+                .net_builder
+                .iter()
+                .find(|net| net.lock().unwrap().id() == iface_id)
+                .unwrap()
+                .lock()
+                .unwrap()
+                .dns_responder()
+                .is_some()
+        };
+        vm_resources.build_net_device(default_net_cfg()).unwrap();
+        assert!(has_dns_responder(&vm_resources, "net_if1"));
+
+        let mut other_net_cfg = default_net_cfg();
+        other_net_cfg.iface_id = "net_if2".to_string();
+        other_net_cfg.guest_mac = Some(MacAddr::parse_str("01:23:45:67:89:0b").unwrap());
+        vm_resources.build_net_device(other_net_cfg).unwrap();
+        assert!(!has_dns_responder(&vm_resources, "net_if2"));
+    }
+
     #[test]
     fn test_set_net_device() {
         let mut vm_resources = default_vm_resources();
@@ -12,6 +12,7 @@ use super::{
     resources::VmResources, Vmm,
 };
 use crate::builder::StartMicrovmError;
+use crate::measured_boot::BootMeasurements;
 use crate::persist::{CreateSnapshotError, LoadSnapshotError};
 use crate::version_map::VERSION_MAP;
 use crate::vmm_config::balloon::{
@@ -19,16 +20,22 @@ use crate::vmm_config::balloon::{
     BalloonUpdateStatsConfig,
 };
 use crate::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
+use crate::vmm_config::cpu_config::{CpuConfigError, CustomCpuTemplate};
+use crate::vmm_config::dns::{DnsConfig, DnsConfigError};
 use crate::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError};
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::logger::{LoggerConfig, LoggerConfigError};
-use crate::vmm_config::machine_config::{VmConfig, VmConfigError};
+use crate::vmm_config::machine_config::{OnlineVcpuCountUpdate, VmConfig, VmConfigError};
 use crate::vmm_config::metrics::{MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::{
     NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
 };
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
+use crate::vmm_config::snapshot::{
+    CreateSnapshotParams, LoadSnapshotParams, SnapshotCreateStatus, SnapshotType,
+};
+use crate::vmm_config::tpm::{TpmConfig, TpmConfigError};
+use crate::vmm_config::vcpu_stats::VcpuStats;
 use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
 use crate::vmm_config::{self, RateLimiterUpdate};
 use logger::{info, update_metric_with_elapsed_time, METRICS};
@@ -60,6 +67,15 @@ pub enum VmmAction {
     GetBalloonConfig,
     /// Get the ballon device latest statistics.
     GetBalloonStats,
+    /// Get the measurements of the boot components. There are none before the microVM has
+    /// booted or if it was not booted with measured boot.
+    GetBootMeasurements,
+    /// Get the status of the latest background snapshot creation. This action can only be
+    /// called after the microVM has booted.
+    GetSnapshotCreateStatus,
+    /// Get the KVM exit statistics of the vCPUs. This action can only be called after the
+    /// microVM has booted.
+    GetVcpuStats,
     /// Get the configuration of the microVM.
     GetVmConfiguration,
     /// Flush the metrics. This action can only be called after the logger has been configured.
@@ -83,8 +99,17 @@ pub enum VmmAction {
     /// `BalloonDeviceConfig` as input. This action can only be called before the microVM
     /// has booted.
     SetBalloonDevice(BalloonDeviceConfig),
+    /// Set the custom CPU template using `CustomCpuTemplate` as input. This action can only be
+    /// called before the microVM has booted.
+    SetCpuConfiguration(CustomCpuTemplate),
+    /// Set the DNS responder answering the queries of the guest using `DnsConfig` as input.
+    /// This action can only be called before the microVM has booted.
+    SetDnsConfiguration(DnsConfig),
     /// Set the MMDS configuration.
     SetMmdsConfiguration(MmdsConfig),
+    /// Set the TPM device or update the one that already exists using the `TpmConfig` as
+    /// input. This action can only be called before the microVM has booted.
+    SetTpmDevice(TpmConfig),
     /// Set the vsock device or update the one that already exists using the
     /// `VsockDeviceConfig` as input. This action can only be called before the microVM has
     /// booted.
@@ -107,6 +132,9 @@ pub enum VmmAction {
     /// Update a network interface, after microVM start. Currently, the only updatable properties
     /// are the RX and TX rate limiters.
     UpdateNetworkInterface(NetworkInterfaceUpdateConfig),
+    /// Update the number of vCPUs. Before the microVM has booted, this sets the number of vCPUs
+    /// to boot with, while after boot it starts or parks the vCPUs created at boot.
+    UpdateOnlineVcpuCount(OnlineVcpuCountUpdate),
 }
 
 /// Wrapper for all errors associated with VMM actions.
@@ -116,8 +144,12 @@ pub enum VmmActionError {
     BalloonConfig(BalloonConfigError),
     /// The action `ConfigureBootSource` failed because of bad user input.
     BootSource(BootSourceConfigError),
+    /// The action `SetCpuConfiguration` failed because of bad user input.
+    CpuConfig(CpuConfigError),
     /// The action `CreateSnapshot` failed.
     CreateSnapshot(CreateSnapshotError),
+    /// The action `SetDnsConfiguration` failed because of bad user input.
+    DnsConfig(DnsConfigError),
     /// One of the actions `InsertBlockDevice` or `UpdateBlockDevicePath`
     /// failed because of bad user input.
     DriveConfig(DriveError),
@@ -145,6 +177,8 @@ pub enum VmmActionError {
     OperationNotSupportedPreBoot,
     /// The action `StartMicroVm` failed because of an internal error.
     StartMicrovm(StartMicrovmError),
+    /// The action `SetTpmDevice` failed because of bad user input.
+    TpmConfig(TpmConfigError),
     /// The action `SetVsockDevice` failed because of bad user input.
     VsockConfig(VsockConfigError),
 }
@@ -159,7 +193,9 @@ impl Display for VmmActionError {
             match self {
                 BalloonConfig(err) => err.to_string(),
                 BootSource(err) => err.to_string(),
+                CpuConfig(err) => err.to_string(),
                 CreateSnapshot(err) => err.to_string(),
+                DnsConfig(err) => err.to_string(),
                 DriveConfig(err) => err.to_string(),
                 InternalVmm(err) => format!("Internal Vmm error: {}", err),
                 LoadSnapshot(err) => format!("Load microVM snapshot error: {}", err),
@@ -182,6 +218,7 @@ impl Display for VmmActionError {
                         .to_string()
                 }
                 StartMicrovm(err) => err.to_string(),
+                TpmConfig(err) => err.to_string(),
                 // The action `SetVsockDevice` failed because of bad user input.
                 VsockConfig(err) => err.to_string(),
             }
@@ -197,10 +234,16 @@ pub enum VmmData {
     BalloonConfig(BalloonDeviceConfig),
     /// The latest balloon device statistics.
     BalloonStats(BalloonStats),
+    /// The measurements of the boot components, if any.
+    BootMeasurements(Option<BootMeasurements>),
     /// No data is sent on the channel.
     Empty,
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(VmConfig),
+    /// The status of the latest background snapshot creation.
+    SnapshotCreateStatus(SnapshotCreateStatus),
+    /// The KVM exit statistics of the vCPUs.
+    VcpuStats(Vec<VcpuStats>),
 }
 
 /// Shorthand result type for external VMM commands.
@@ -291,6 +334,7 @@ impl<'a> PrebootApiController<'a> {
                 .map(|()| VmmData::Empty)
                 .map_err(VmmActionError::Metrics),
             GetBalloonConfig => self.balloon_config(),
+            GetBootMeasurements => Ok(VmmData::BootMeasurements(None)),
             GetVmConfiguration => Ok(VmmData::MachineConfiguration(
                 self.vm_resources.vm_config().clone(),
             )),
@@ -298,16 +342,25 @@ impl<'a> PrebootApiController<'a> {
             InsertNetworkDevice(config) => self.insert_net_device(config),
             LoadSnapshot(config) => self.load_snapshot(&config),
             SetBalloonDevice(config) => self.set_balloon_device(config),
+            SetCpuConfiguration(config) => self.set_cpu_config(config),
+            SetDnsConfiguration(config) => self.set_dns_config(config),
+            SetTpmDevice(config) => self.set_tpm_device(config),
             SetVsockDevice(config) => self.set_vsock_device(config),
             SetVmConfiguration(config) => self.set_vm_config(config),
             SetMmdsConfiguration(config) => self.set_mmds_config(config),
             StartMicroVm => self.start_microvm(),
+            UpdateOnlineVcpuCount(update) => self.set_vm_config(VmConfig {
+                vcpu_count: Some(update.vcpu_count),
+                ..self.vm_resources.vm_config().clone()
+            }),
             // Operations not allowed pre-boot.
             CreateSnapshot(_)
             | FlushMetrics
             | Pause
             | Resume
             | GetBalloonStats
+            | GetSnapshotCreateStatus
+            | GetVcpuStats
             | UpdateBalloon(_)
             | UpdateBalloonStatistics(_)
             | UpdateBlockDevice(_)
@@ -357,6 +410,22 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::BootSource)
     }
 
+    fn set_cpu_config(&mut self, cfg: CustomCpuTemplate) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_cpu_config(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::CpuConfig)
+    }
+
+    fn set_dns_config(&mut self, cfg: DnsConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_dns_config(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::DnsConfig)
+    }
+
     fn set_mmds_config(&mut self, cfg: MmdsConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -373,6 +442,14 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::MachineConfig)
     }
 
+    fn set_tpm_device(&mut self, cfg: TpmConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_tpm_device(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::TpmConfig)
+    }
+
     fn set_vsock_device(&mut self, cfg: VsockDeviceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -463,6 +540,18 @@ impl RuntimeApiController {
                 .latest_balloon_stats()
                 .map(VmmData::BalloonStats)
                 .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            GetBootMeasurements => Ok(VmmData::BootMeasurements(
+                self.vmm.lock().expect("Poisoned lock").boot_measurements(),
+            )),
+            GetSnapshotCreateStatus => Ok(VmmData::SnapshotCreateStatus(
+                self.vmm
+                    .lock()
+                    .expect("Poisoned lock")
+                    .snapshot_create_status(),
+            )),
+            GetVcpuStats => Ok(VmmData::VcpuStats(
+                self.vmm.lock().expect("Poisoned lock").vcpu_stats(),
+            )),
             GetVmConfiguration => Ok(VmmData::MachineConfiguration(
                 self.vm_resources.vm_config().clone(),
             )),
@@ -486,6 +575,7 @@ impl RuntimeApiController {
                 .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
             UpdateBlockDevice(new_cfg) => self.update_block_device(new_cfg),
             UpdateNetworkInterface(netif_update) => self.update_net_rate_limiters(netif_update),
+            UpdateOnlineVcpuCount(update) => self.update_online_vcpu_count(update),
 
             // Operations not allowed post-boot.
             ConfigureBootSource(_)
@@ -495,6 +585,9 @@ impl RuntimeApiController {
             | InsertNetworkDevice(_)
             | LoadSnapshot(_)
             | SetBalloonDevice(_)
+            | SetCpuConfiguration(_)
+            | SetDnsConfiguration(_)
+            | SetTpmDevice(_)
             | SetVsockDevice(_)
             | SetMmdsConfiguration(_)
             | SetVmConfiguration(_)
@@ -647,6 +740,28 @@ impl RuntimeApiController {
             .map_err(NetworkInterfaceError::DeviceUpdate)
             .map_err(VmmActionError::NetworkConfig)
     }
+
+    /// Changes the online vCPU count. Only the vCPUs created at boot can be started.
+    fn update_online_vcpu_count(&mut self, update: OnlineVcpuCountUpdate) -> ActionResult {
+        let vcpu_count = update.vcpu_count;
+        let ht_enabled = self.vm_resources.vm_config().ht_enabled.unwrap_or(false);
+        if vcpu_count == 0 || (ht_enabled && vcpu_count > 1 && vcpu_count % 2 == 1) {
+            return Err(VmmActionError::MachineConfig(
+                VmConfigError::InvalidVcpuCount,
+            ));
+        }
+
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        if vcpu_count > vmm.max_vcpu_count() {
+            return Err(VmmActionError::MachineConfig(
+                VmConfigError::InvalidMaxVcpuCount,
+            ));
+        }
+        vmm.set_online_vcpu_count(vcpu_count)
+            .map_err(VmmActionError::InternalVmm)?;
+        self.vm_resources.set_online_vcpu_count(vcpu_count);
+        Ok(VmmData::Empty)
+    }
 }
 
 #[cfg(test)]
@@ -655,9 +770,11 @@ mod tests {
     use crate::vmm_config::balloon::BalloonBuilder;
     use crate::vmm_config::drive::CacheType;
     use crate::vmm_config::logger::LoggerLevel;
+    use crate::vmm_config::machine_config::ThreadSchedPolicy;
     use crate::vmm_config::vsock::VsockBuilder;
     use devices::virtio::balloon::{BalloonConfig, Error as BalloonError};
     use devices::virtio::VsockError;
+    use mmds::data_store::MmdsVersion;
     use seccomp::BpfProgramRef;
 
     use std::path::PathBuf;
@@ -668,7 +785,9 @@ mod tests {
             match (self, other) {
                 (BalloonConfig(_), BalloonConfig(_)) => true,
                 (BootSource(_), BootSource(_)) => true,
+                (CpuConfig(_), CpuConfig(_)) => true,
                 (CreateSnapshot(_), CreateSnapshot(_)) => true,
+                (DnsConfig(_), DnsConfig(_)) => true,
                 (DriveConfig(_), DriveConfig(_)) => true,
                 (InternalVmm(_), InternalVmm(_)) => true,
                 (LoadSnapshot(_), LoadSnapshot(_)) => true,
@@ -682,6 +801,7 @@ mod tests {
                 (OperationNotSupportedPostBoot, OperationNotSupportedPostBoot) => true,
                 (OperationNotSupportedPreBoot, OperationNotSupportedPreBoot) => true,
                 (StartMicrovm(_), StartMicrovm(_)) => true,
+                (TpmConfig(_), TpmConfig(_)) => true,
                 (VsockConfig(_), VsockConfig(_)) => true,
                 _ => false,
             }
@@ -698,6 +818,9 @@ mod tests {
         balloon_set: bool,
         boot_cfg_set: bool,
         block_set: bool,
+        cpu_config_set: bool,
+        dns_set: bool,
+        tpm_set: bool,
         vsock_set: bool,
         net_set: bool,
         mmds_set: bool,
@@ -711,6 +834,10 @@ mod tests {
             &self.vm_config
         }
 
+        pub fn set_online_vcpu_count(&mut self, vcpu_count: u8) {
+            self.vm_config.vcpu_count = Some(vcpu_count);
+        }
+
         pub fn balloon_config(&mut self) -> Result<BalloonConfig, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -770,6 +897,14 @@ mod tests {
             Ok(())
         }
 
+        pub fn set_tpm_device(&mut self, _: TpmConfig) -> Result<(), TpmConfigError> {
+            if self.force_errors {
+                return Err(TpmConfigError::InvalidSocket(String::new()));
+            }
+            self.tpm_set = true;
+            Ok(())
+        }
+
         pub fn set_vsock_device(&mut self, _: VsockDeviceConfig) -> Result<(), VsockConfigError> {
             if self.force_errors {
                 return Err(VsockConfigError::CreateVsockDevice(
@@ -780,6 +915,14 @@ mod tests {
             Ok(())
         }
 
+        pub fn set_cpu_config(&mut self, _: CustomCpuTemplate) -> Result<(), CpuConfigError> {
+            if self.force_errors {
+                return Err(CpuConfigError::Unsupported);
+            }
+            self.cpu_config_set = true;
+            Ok(())
+        }
+
         pub fn set_mmds_config(&mut self, _: MmdsConfig) -> Result<(), MmdsConfigError> {
             if self.force_errors {
                 return Err(MmdsConfigError::InvalidIpv4Addr);
@@ -787,6 +930,14 @@ mod tests {
             self.mmds_set = true;
             Ok(())
         }
+
+        pub fn set_dns_config(&mut self, _: DnsConfig) -> Result<(), DnsConfigError> {
+            if self.force_errors {
+                return Err(DnsConfigError::MissingNetworkInterfaces);
+            }
+            self.dns_set = true;
+            Ok(())
+        }
     }
 
     // Mock `Vmm` used for testing.
@@ -795,6 +946,7 @@ mod tests {
         pub balloon_config_called: bool,
         pub latest_balloon_stats_called: bool,
         pub pause_called: bool,
+        pub snapshot_create_status_called: bool,
         pub resume_called: bool,
         #[cfg(target_arch = "x86_64")]
         pub send_ctrl_alt_del_called: bool,
@@ -802,6 +954,7 @@ mod tests {
         pub update_balloon_stats_config_called: bool,
         pub update_block_device_path_called: bool,
         pub update_net_rate_limiters_called: bool,
+        pub set_online_vcpu_count_called: bool,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
     }
@@ -850,6 +1003,31 @@ mod tests {
             Ok(BalloonStats::default())
         }
 
+        pub fn snapshot_create_status(&mut self) -> SnapshotCreateStatus {
+            self.snapshot_create_status_called = true;
+            SnapshotCreateStatus::default()
+        }
+
+        pub fn vcpu_stats(&self) -> Vec<VcpuStats> {
+            vec![VcpuStats::default()]
+        }
+
+        pub fn boot_measurements(&self) -> Option<BootMeasurements> {
+            Some(BootMeasurements::default())
+        }
+
+        pub fn max_vcpu_count(&self) -> u8 {
+            4
+        }
+
+        pub fn set_online_vcpu_count(&mut self, _: u8) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::VcpuResume);
+            }
+            self.set_online_vcpu_count_called = true;
+            Ok(())
+        }
+
         pub fn update_balloon_config(&mut self, _: u32) -> Result<(), BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -943,6 +1121,7 @@ mod tests {
             state: "Not started".to_string(),
             vmm_version: String::new(),
             app_name: String::new(),
+            boot_measurements: None,
         };
         PrebootApiController::new(
             BpfProgram::new(),
@@ -990,6 +1169,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_get_boot_measurements() {
+        let req = VmmAction::GetBootMeasurements;
+        check_preboot_request(req, |result, _| {
+            assert_eq!(result, Ok(VmmData::BootMeasurements(None)))
+        });
+    }
+
     #[test]
     fn test_preboot_get_vm_config() {
         let req = VmmAction::GetVmConfiguration;
@@ -1032,6 +1219,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_update_online_vcpu_count() {
+        let req = VmmAction::UpdateOnlineVcpuCount(OnlineVcpuCountUpdate { vcpu_count: 2 });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vm_res.vm_config.vcpu_count, Some(2));
+        });
+
+        let req = VmmAction::UpdateOnlineVcpuCount(OnlineVcpuCountUpdate { vcpu_count: 2 });
+        check_preboot_request_err(
+            req,
+            VmmActionError::MachineConfig(VmConfigError::InvalidVcpuCount),
+        );
+    }
+
     #[test]
     fn test_preboot_set_balloon_dev() {
         let req = VmmAction::SetBalloonDevice(BalloonDeviceConfig::default());
@@ -1087,6 +1289,7 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            dhcp: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1100,6 +1303,7 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            dhcp: None,
         });
         check_preboot_request_err(
             req,
@@ -1134,15 +1338,87 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_set_tpm_dev() {
+        let req = VmmAction::SetTpmDevice(TpmConfig {
+            socket: String::new(),
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.tpm_set)
+        });
+
+        let req = VmmAction::SetTpmDevice(TpmConfig {
+            socket: String::new(),
+        });
+        check_preboot_request_err(
+            req,
+            VmmActionError::TpmConfig(TpmConfigError::InvalidSocket(String::new())),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_cpu_config() {
+        let req = VmmAction::SetCpuConfiguration(CustomCpuTemplate::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.cpu_config_set)
+        });
+
+        let req = VmmAction::SetCpuConfiguration(CustomCpuTemplate::default());
+        check_preboot_request_err(req, VmmActionError::CpuConfig(CpuConfigError::Unsupported));
+    }
+
+    #[test]
+    fn test_preboot_set_dns_config() {
+        let req = VmmAction::SetDnsConfiguration(DnsConfig {
+            ipv4_address: None,
+            network_interfaces: vec![],
+            ttl: 60,
+            records: vec![],
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.dns_set)
+        });
+
+        let req = VmmAction::SetDnsConfiguration(DnsConfig {
+            ipv4_address: None,
+            network_interfaces: vec![],
+            ttl: 60,
+            records: vec![],
+        });
+        check_preboot_request_err(
+            req,
+            VmmActionError::DnsConfig(DnsConfigError::MissingNetworkInterfaces),
+        );
+    }
+
     #[test]
     fn test_preboot_set_mmds_config() {
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None });
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+            version: MmdsVersion::V1,
+            mmds_id: None,
+            network_interfaces: vec![],
+            guest_write: None,
+            connection_limits: None,
+        });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
             assert!(vm_res.mmds_set)
         });
 
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None });
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+            version: MmdsVersion::V1,
+            mmds_id: None,
+            network_interfaces: vec![],
+            guest_write: None,
+            connection_limits: None,
+        });
         check_preboot_request_err(
             req,
             VmmActionError::MmdsConfig(MmdsConfigError::InvalidIpv4Addr),
@@ -1160,8 +1436,10 @@ mod tests {
         let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
             snapshot_path: PathBuf::new(),
             mem_file_path: PathBuf::new(),
+            mem_base_path: None,
             enable_diff_snapshots: false,
             resume_vm: false,
+            integrity_key_path: None,
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1173,8 +1451,10 @@ mod tests {
         let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
             snapshot_path: PathBuf::new(),
             mem_file_path: PathBuf::new(),
+            mem_base_path: None,
             enable_diff_snapshots: false,
             resume_vm: true,
+            integrity_key_path: None,
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1203,6 +1483,14 @@ mod tests {
             VmmAction::GetBalloonStats,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::GetSnapshotCreateStatus,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetVcpuStats,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         check_preboot_request_err(
             VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mb: 0 }),
             VmmActionError::OperationNotSupportedPreBoot,
@@ -1231,6 +1519,8 @@ mod tests {
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
                 version: None,
+                integrity_key_path: None,
+                background: false,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
@@ -1279,6 +1569,7 @@ mod tests {
                 state: "Not started".to_string(),
                 vmm_version: String::new(),
                 app_name: String::new(),
+                boot_measurements: None,
             },
             commands,
             expected_resp,
@@ -1393,6 +1684,73 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_get_snapshot_create_status() {
+        let req = VmmAction::GetSnapshotCreateStatus;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::SnapshotCreateStatus(
+                    SnapshotCreateStatus::default()
+                ))
+            );
+            assert!(vmm.snapshot_create_status_called)
+        });
+    }
+
+    #[test]
+    fn test_runtime_get_vcpu_stats() {
+        let req = VmmAction::GetVcpuStats;
+        check_runtime_request(req, |result, _| {
+            assert_eq!(result, Ok(VmmData::VcpuStats(vec![VcpuStats::default()])));
+        });
+    }
+
+    #[test]
+    fn test_runtime_get_boot_measurements() {
+        let req = VmmAction::GetBootMeasurements;
+        check_runtime_request(req, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::BootMeasurements(Some(BootMeasurements::default())))
+            );
+        });
+    }
+
+    #[test]
+    fn test_runtime_update_online_vcpu_count() {
+        let req = VmmAction::UpdateOnlineVcpuCount(OnlineVcpuCountUpdate { vcpu_count: 4 });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.set_online_vcpu_count_called)
+        });
+
+        let req = VmmAction::UpdateOnlineVcpuCount(OnlineVcpuCountUpdate { vcpu_count: 0 });
+        check_runtime_request(req, |result, vmm| {
+            assert!(matches!(
+                result,
+                Err(VmmActionError::MachineConfig(
+                    VmConfigError::InvalidVcpuCount
+                ))
+            ));
+            assert!(!vmm.set_online_vcpu_count_called)
+        });
+
+        // Only the vCPUs created at boot can be started.
+        let req = VmmAction::UpdateOnlineVcpuCount(OnlineVcpuCountUpdate { vcpu_count: 5 });
+        check_runtime_request(req, |result, _| {
+            assert!(matches!(
+                result,
+                Err(VmmActionError::MachineConfig(
+                    VmConfigError::InvalidMaxVcpuCount
+                ))
+            ));
+        });
+
+        let req = VmmAction::UpdateOnlineVcpuCount(OnlineVcpuCountUpdate { vcpu_count: 1 });
+        check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuResume));
+    }
+
     #[test]
     fn test_runtime_update_balloon_config() {
         let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mb: 0 });
@@ -1516,6 +1874,7 @@ mod tests {
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
                 allow_mmds_requests: false,
+                dhcp: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -1540,7 +1899,34 @@ mod tests {
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
-            VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None }),
+            VmmAction::SetTpmDevice(TpmConfig {
+                socket: String::new(),
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetCpuConfiguration(CustomCpuTemplate::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetDnsConfiguration(DnsConfig {
+                ipv4_address: None,
+                network_interfaces: vec![],
+                ttl: 60,
+                records: vec![],
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetMmdsConfiguration(MmdsConfig {
+                ipv4_address: None,
+                ipv6_address: None,
+                version: MmdsVersion::V1,
+                mmds_id: None,
+                network_interfaces: vec![],
+                guest_write: None,
+                connection_limits: None,
+            }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
@@ -1551,8 +1937,10 @@ mod tests {
             VmmAction::LoadSnapshot(LoadSnapshotParams {
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
+                mem_base_path: None,
                 enable_diff_snapshots: false,
                 resume_vm: false,
+                integrity_key_path: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -1569,8 +1957,10 @@ mod tests {
         let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
             snapshot_path: PathBuf::new(),
             mem_file_path: PathBuf::new(),
+            mem_base_path: None,
             enable_diff_snapshots: false,
             resume_vm: false,
+            integrity_key_path: None,
         });
         let err = preboot.handle_preboot_request(req);
         assert_eq!(
@@ -1605,6 +1995,7 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            dhcp: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertNetworkDevice");
 
@@ -1618,10 +2009,45 @@ mod tests {
         });
         verify_load_snap_disallowed_after_boot_resources(req, "SetVsockDevice");
 
+        let req = VmmAction::SetTpmDevice(TpmConfig {
+            socket: String::new(),
+        });
+        verify_load_snap_disallowed_after_boot_resources(req, "SetTpmDevice");
+
         let req = VmmAction::SetVmConfiguration(VmConfig::default());
         verify_load_snap_disallowed_after_boot_resources(req, "SetVmConfiguration");
 
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None });
+        // The thread affinities and scheduling policy only apply at boot, so they cannot be
+        // configured for a microVM loaded from a snapshot either.
+        let req = VmmAction::SetVmConfiguration(VmConfig {
+            vcpu_affinity: Some(vec![vec![0]]),
+            vmm_affinity: Some(vec![0]),
+            api_affinity: Some(vec![0]),
+            vcpu_sched_policy: Some(ThreadSchedPolicy::Other { nice: 0 }),
+            ..Default::default()
+        });
+        verify_load_snap_disallowed_after_boot_resources(req, "SetVmConfiguration (affinity)");
+
+        let req = VmmAction::SetCpuConfiguration(CustomCpuTemplate::default());
+        verify_load_snap_disallowed_after_boot_resources(req, "SetCpuConfiguration");
+
+        let req = VmmAction::SetDnsConfiguration(DnsConfig {
+            ipv4_address: None,
+            network_interfaces: vec![],
+            ttl: 60,
+            records: vec![],
+        });
+        verify_load_snap_disallowed_after_boot_resources(req, "SetDnsConfiguration");
+
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+            version: MmdsVersion::V1,
+            mmds_id: None,
+            network_interfaces: vec![],
+            guest_write: None,
+            connection_limits: None,
+        });
         verify_load_snap_disallowed_after_boot_resources(req, "SetMmdsConfiguration");
     }
 }
@@ -43,6 +43,8 @@ impl MockBootSourceConfig {
             kernel_image_path: kernel_image_path(None),
             initrd_path: None,
             boot_args: None,
+            boot_args_policy: None,
+            measured_boot: None,
         })
     }
 
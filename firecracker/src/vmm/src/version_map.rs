@@ -6,7 +6,10 @@
 use std::collections::HashMap;
 
 use crate::device_manager::persist::DeviceStates;
+use crate::persist::VmInfo;
 use devices::virtio::block::persist::BlockState;
+use devices::virtio::net::persist::NetState;
+use mmds::persist::MmdsNetworkStackState;
 
 use lazy_static::lazy_static;
 use versionize::VersionMap;
@@ -18,7 +21,13 @@ lazy_static! {
     pub static ref VERSION_MAP: VersionMap = {
         let mut version_map = VersionMap::new();
         version_map.new_version().set_type_version(DeviceStates::type_id(), 2);
-        version_map.new_version().set_type_version(BlockState::type_id(), 2);
+        version_map
+            .new_version()
+            .set_type_version(BlockState::type_id(), 2)
+            .set_type_version(DeviceStates::type_id(), 3)
+            .set_type_version(MmdsNetworkStackState::type_id(), 2)
+            .set_type_version(NetState::type_id(), 2)
+            .set_type_version(VmInfo::type_id(), 2);
         version_map
     };
 
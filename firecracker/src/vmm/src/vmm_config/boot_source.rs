@@ -4,6 +4,7 @@
 use std::fmt::{Display, Formatter, Result};
 use std::io;
 
+use kernel::bundle::{Bundle, Error as BundleError};
 use serde::{Deserialize, Serialize};
 
 /// Default guest kernel command line:
@@ -19,12 +20,31 @@ use serde::{Deserialize, Serialize};
 pub const DEFAULT_KERNEL_CMDLINE: &str = "reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0 \
                                           i8042.noaux i8042.nomux i8042.nopnp i8042.dumbkbd";
 
+/// Policy merging the `boot_args` with the command line embedded in a kernel bundle.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BootArgsPolicy {
+    /// The boot arguments are appended to the embedded command line.
+    Append,
+    /// The boot arguments replace the embedded command line.
+    Replace,
+    /// The boot arguments are ignored in favor of the embedded command line.
+    Ignore,
+}
+
+impl Default for BootArgsPolicy {
+    fn default() -> Self {
+        BootArgsPolicy::Append
+    }
+}
+
 /// Strongly typed data structure used to configure the boot source of the
 /// microvm.
-#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BootSourceConfig {
-    /// Path of the kernel image.
+    /// Path of the kernel image, or of a kernel bundle holding the kernel together with its
+    /// initrd and command line.
     pub kernel_image_path: String,
     /// Path of the initrd, if there is one.
     pub initrd_path: Option<String>,
@@ -32,6 +52,14 @@ pub struct BootSourceConfig {
     /// kernel command line is used: `reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0`.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub boot_args: Option<String>,
+    /// How the boot arguments are merged with the command line embedded in a kernel bundle.
+    /// Defaults to appending them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_args_policy: Option<BootArgsPolicy>,
+    /// Whether the boot components are measured into an event log handed to the guest.
+    /// Defaults to false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub measured_boot: Option<bool>,
 }
 
 /// Errors associated with actions on `BootSourceConfig`.
@@ -43,6 +71,10 @@ pub enum BootSourceConfigError {
     InvalidInitrdPath(io::Error),
     /// The kernel command line is invalid.
     InvalidKernelCommandLine(String),
+    /// The kernel bundle cannot be unpacked.
+    InvalidKernelBundle(BundleError),
+    /// The initrd is provided both by the kernel bundle and by `initrd_path`.
+    BundleInitrdConflict,
 }
 
 impl Display for BootSourceConfigError {
@@ -59,6 +91,11 @@ impl Display for BootSourceConfigError {
             InvalidKernelCommandLine(ref e) => {
                 write!(f, "The kernel command line is invalid: {}", e.as_str())
             }
+            InvalidKernelBundle(ref e) => write!(f, "The kernel bundle is invalid: {}", e),
+            BundleInitrdConflict => write!(
+                f,
+                "The initrd cannot be provided both by the kernel bundle and by initrd_path."
+            ),
         }
     }
 }
@@ -72,4 +109,8 @@ pub struct BootConfig {
     pub kernel_file: std::fs::File,
     /// The descriptor to the initrd file, if there is one
     pub initrd_file: Option<std::fs::File>,
+    /// The layout of `kernel_file`, if it is a kernel bundle.
+    pub bundle: Option<Bundle>,
+    /// Whether the boot components are measured.
+    pub measured_boot: bool,
 }
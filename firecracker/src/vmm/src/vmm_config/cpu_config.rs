@@ -0,0 +1,362 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Auxiliary module for configuring custom CPU templates.
+use std::fmt::{Display, Formatter};
+
+#[cfg(target_arch = "x86_64")]
+use cpuid::custom::CpuidRegisterModifier;
+#[cfg(target_arch = "x86_64")]
+use cpuid::features::{missing_features, Register};
+#[cfg(target_arch = "x86_64")]
+use kvm_bindings::{CpuId, MsrList};
+use serde::{Deserialize, Serialize};
+
+/// Errors associated with configuring a custom CPU template.
+#[derive(Debug, PartialEq)]
+pub enum CpuConfigError {
+    /// The template enables CPU features that the host does not support.
+    FeaturesNotSupported(String),
+    /// Cannot get the CPUID or the MSRs supported by the host.
+    HostCapabilities(String),
+    /// A modifier sets bits outside of its mask.
+    InvalidModifier(String),
+    /// The leaf (first value) and subleaf (second value) are not supported by the host.
+    LeafNotSupported(u32, u32),
+    /// The MSR is not supported by the host.
+    MsrNotSupported(u32),
+    /// Custom CPU templates are not supported on this architecture.
+    Unsupported,
+}
+
+impl Display for CpuConfigError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::CpuConfigError::*;
+        match self {
+            FeaturesNotSupported(features) => write!(
+                f,
+                "The host does not support the following CPU features: {}",
+                features
+            ),
+            HostCapabilities(err) => write!(f, "Cannot get the host CPU capabilities: {}", err),
+            InvalidModifier(modifier) => write!(
+                f,
+                "The modifier of {} sets bits outside of its mask.",
+                modifier
+            ),
+            LeafNotSupported(leaf, subleaf) => write!(
+                f,
+                "The host does not support CPUID leaf {:#x}, subleaf {:#x}.",
+                leaf, subleaf
+            ),
+            MsrNotSupported(addr) => write!(f, "The host does not support MSR {:#x}.", addr),
+            Unsupported => write!(
+                f,
+                "Custom CPU templates are not supported on this architecture."
+            ),
+        }
+    }
+}
+
+/// Registers of a CPUID leaf.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuidRegister {
+    /// EAX register.
+    Eax,
+    /// EBX register.
+    Ebx,
+    /// ECX register.
+    Ecx,
+    /// EDX register.
+    Edx,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl From<CpuidRegister> for Register {
+    fn from(register: CpuidRegister) -> Self {
+        match register {
+            CpuidRegister::Eax => Register::EAX,
+            CpuidRegister::Ebx => Register::EBX,
+            CpuidRegister::Ecx => Register::ECX,
+            CpuidRegister::Edx => Register::EDX,
+        }
+    }
+}
+
+/// Overrides the bits of a CPUID register selected by `mask` with the ones of `value`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CpuidModifier {
+    /// The CPUID leaf.
+    pub leaf: u32,
+    /// The CPUID subleaf. Ignored for leaves that do not have subleaves.
+    #[serde(default)]
+    pub subleaf: u32,
+    /// The modified register.
+    pub register: CpuidRegister,
+    /// The bits of the register that are overridden.
+    pub mask: u32,
+    /// The values of the overridden bits.
+    pub value: u32,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl From<&CpuidModifier> for CpuidRegisterModifier {
+    fn from(modifier: &CpuidModifier) -> Self {
+        CpuidRegisterModifier {
+            leaf: modifier.leaf,
+            subleaf: modifier.subleaf,
+            register: modifier.register.into(),
+            mask: modifier.mask,
+            value: modifier.value,
+        }
+    }
+}
+
+/// Overrides the bits of a model specific register selected by `mask` with the ones of `value`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MsrModifier {
+    /// The address of the MSR.
+    pub addr: u32,
+    /// The bits of the MSR that are overridden.
+    pub mask: u64,
+    /// The values of the overridden bits.
+    pub value: u64,
+}
+
+impl MsrModifier {
+    /// Returns the value of the MSR after the modifier is applied.
+    pub fn apply(&self, data: u64) -> u64 {
+        (data & !self.mask) | (self.value & self.mask)
+    }
+}
+
+/// A user-defined CPU template, applied on top of the CPU template of the machine
+/// configuration, if any.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomCpuTemplate {
+    /// Modifications of the CPUID, applied in order.
+    #[serde(default)]
+    pub cpuid_modifiers: Vec<CpuidModifier>,
+    /// Modifications of the MSRs set up at boot time, applied in order.
+    #[serde(default)]
+    pub msr_modifiers: Vec<MsrModifier>,
+}
+
+impl CustomCpuTemplate {
+    /// Returns the modifications of the CPUID understood by the `cpuid` crate.
+    #[cfg(target_arch = "x86_64")]
+    pub fn cpuid_register_modifiers(&self) -> Vec<CpuidRegisterModifier> {
+        self.cpuid_modifiers.iter().map(Into::into).collect()
+    }
+
+    /// Checks that the template only exposes CPU features and MSRs supported by the host.
+    #[cfg(target_arch = "x86_64")]
+    pub fn validate(&self) -> Result<(), CpuConfigError> {
+        let kvm = kvm_ioctls::Kvm::new()
+            .map_err(|err| CpuConfigError::HostCapabilities(err.to_string()))?;
+        let supported_cpuid = kvm
+            .get_supported_cpuid(kvm_bindings::KVM_MAX_CPUID_ENTRIES)
+            .map_err(|err| CpuConfigError::HostCapabilities(err.to_string()))?;
+        let supported_msrs = arch::x86_64::msr::supported_guest_msrs(&kvm)
+            .map_err(|err| CpuConfigError::HostCapabilities(format!("{:?}", err)))?;
+
+        self.validate_against(&supported_cpuid, &supported_msrs)
+    }
+
+    /// Custom CPU templates are only supported on x86_64.
+    #[cfg(target_arch = "aarch64")]
+    pub fn validate(&self) -> Result<(), CpuConfigError> {
+        Err(CpuConfigError::Unsupported)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn validate_against(
+        &self,
+        supported_cpuid: &CpuId,
+        supported_msrs: &MsrList,
+    ) -> Result<(), CpuConfigError> {
+        if let Some(modifier) = self
+            .cpuid_modifiers
+            .iter()
+            .find(|modifier| modifier.value & !modifier.mask != 0)
+        {
+            return Err(CpuConfigError::InvalidModifier(format!(
+                "CPUID leaf {:#x}, subleaf {:#x}",
+                modifier.leaf, modifier.subleaf
+            )));
+        }
+        if let Some(modifier) = self
+            .msr_modifiers
+            .iter()
+            .find(|modifier| modifier.value & !modifier.mask != 0)
+        {
+            return Err(CpuConfigError::InvalidModifier(format!(
+                "MSR {:#x}",
+                modifier.addr
+            )));
+        }
+
+        let mut cpuid = supported_cpuid.clone();
+        cpuid::custom::set_cpuid_entries(&mut cpuid, &self.cpuid_register_modifiers()).map_err(
+            |err| match err {
+                cpuid::Error::LeafNotFound(leaf, subleaf) => {
+                    CpuConfigError::LeafNotSupported(leaf, subleaf)
+                }
+                err => CpuConfigError::HostCapabilities(format!("{:?}", err)),
+            },
+        )?;
+        let missing = missing_features(&cpuid, supported_cpuid);
+        if !missing.is_empty() {
+            return Err(CpuConfigError::FeaturesNotSupported(
+                missing
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ));
+        }
+
+        if let Some(modifier) = self
+            .msr_modifiers
+            .iter()
+            .find(|modifier| !supported_msrs.as_slice().contains(&modifier.addr))
+        {
+            return Err(CpuConfigError::MsrNotSupported(modifier.addr));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_cpu_template_deserialization() {
+        let json = r#"{
+            "cpuid_modifiers": [
+                {"leaf": 1, "register": "ecx", "mask": 3, "value": 1}
+            ],
+            "msr_modifiers": [
+                {"addr": 416, "mask": 1, "value": 0}
+            ]
+        }"#;
+        let template: CustomCpuTemplate = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            template,
+            CustomCpuTemplate {
+                cpuid_modifiers: vec![CpuidModifier {
+                    leaf: 1,
+                    subleaf: 0,
+                    register: CpuidRegister::Ecx,
+                    mask: 3,
+                    value: 1,
+                }],
+                msr_modifiers: vec![MsrModifier {
+                    addr: 416,
+                    mask: 1,
+                    value: 0,
+                }],
+            }
+        );
+
+        assert_eq!(
+            serde_json::from_str::<CustomCpuTemplate>("{}").unwrap(),
+            CustomCpuTemplate::default()
+        );
+        assert!(serde_json::from_str::<CustomCpuTemplate>(
+            r#"{"cpuid_modifiers": [{"leaf": 1, "register": "rax", "mask": 1, "value": 1}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_msr_modifier_apply() {
+        let modifier = MsrModifier {
+            addr: 0,
+            mask: 0xff00,
+            value: 0x1200,
+        };
+        assert_eq!(modifier.apply(0xffff), 0x12ff);
+        assert_eq!(modifier.apply(0), 0x1200);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_validate() {
+        use kvm_bindings::{kvm_cpuid_entry2, KVM_CPUID_FLAG_SIGNIFCANT_INDEX};
+
+        let avx2 = 1 << 5;
+        let supported_cpuid = CpuId::from_entries(&[kvm_cpuid_entry2 {
+            function: 0x7,
+            index: 0,
+            flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+            ebx: avx2,
+            ..Default::default()
+        }])
+        .unwrap();
+        let supported_msrs = MsrList::from_entries(&[0x1a0]).unwrap();
+        let cpuid_modifier = |value| CpuidModifier {
+            leaf: 0x7,
+            subleaf: 0,
+            register: CpuidRegister::Ebx,
+            mask: avx2 | 1 << 16,
+            value,
+        };
+        let msr_modifier = |addr| MsrModifier {
+            addr,
+            mask: 1,
+            value: 1,
+        };
+
+        // Features supported by the host can be enabled and disabled.
+        let mut template = CustomCpuTemplate {
+            cpuid_modifiers: vec![cpuid_modifier(avx2), cpuid_modifier(0)],
+            msr_modifiers: vec![msr_modifier(0x1a0)],
+        };
+        template
+            .validate_against(&supported_cpuid, &supported_msrs)
+            .unwrap();
+
+        // AVX512F is not supported by the host.
+        template.cpuid_modifiers = vec![cpuid_modifier(1 << 16)];
+        match template.validate_against(&supported_cpuid, &supported_msrs) {
+            Err(CpuConfigError::FeaturesNotSupported(features)) => {
+                assert!(features.starts_with("avx512f"))
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // Bits outside of the mask cannot be set.
+        template.cpuid_modifiers = vec![cpuid_modifier(1)];
+        assert_eq!(
+            template.validate_against(&supported_cpuid, &supported_msrs),
+            Err(CpuConfigError::InvalidModifier(
+                "CPUID leaf 0x7, subleaf 0x0".to_string()
+            ))
+        );
+
+        // Leaves have to be supported by the host.
+        template.cpuid_modifiers = vec![CpuidModifier {
+            leaf: 0x1,
+            ..cpuid_modifier(0)
+        }];
+        assert_eq!(
+            template.validate_against(&supported_cpuid, &supported_msrs),
+            Err(CpuConfigError::LeafNotSupported(0x1, 0))
+        );
+
+        // MSRs have to be supported by the host.
+        template.cpuid_modifiers = vec![];
+        template.msr_modifiers = vec![msr_modifier(0x10)];
+        assert_eq!(
+            template.validate_against(&supported_cpuid, &supported_msrs),
+            Err(CpuConfigError::MsrNotSupported(0x10))
+        );
+    }
+}
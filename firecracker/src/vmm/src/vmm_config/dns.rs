@@ -0,0 +1,189 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use mmds::dns::{DnsRecord, DnsResponder, Error as DnsError, DEFAULT_TTL};
+use serde::{Deserialize, Serialize};
+use utils::net::ipv4addr::is_link_local_valid;
+
+fn default_ttl() -> u32 {
+    DEFAULT_TTL
+}
+
+/// Errors associated with the DNS responder configuration.
+#[derive(Debug, PartialEq)]
+pub enum DnsConfigError {
+    /// The provided IPv4 address is not link-local valid.
+    InvalidIpv4Addr,
+    /// The network interface does not exist.
+    InvalidNetworkInterfaceId(String),
+    /// The value of a record does not match its type.
+    InvalidRecordValue(String),
+    /// No network interface serves the DNS responder.
+    MissingNetworkInterfaces,
+    /// The records cannot be served by the responder.
+    Records(DnsError),
+}
+
+impl fmt::Display for DnsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DnsConfigError::*;
+        match self {
+            InvalidIpv4Addr => write!(f, "The DNS responder IPv4 address is not link local."),
+            InvalidNetworkInterfaceId(iface_id) => {
+                write!(f, "The network interface {} does not exist.", iface_id)
+            }
+            InvalidRecordValue(name) => write!(
+                f,
+                "The value of the DNS record {} does not match its type.",
+                name
+            ),
+            MissingNetworkInterfaces => write!(
+                f,
+                "No network interface was provided for the DNS responder."
+            ),
+            Records(err) => write!(f, "Invalid DNS records: {}", err),
+        }
+    }
+}
+
+/// The types of the records served by the DNS responder.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum DnsRecordType {
+    /// Maps a name to an IPv4 address.
+    A,
+    /// Maps a name to an IPv6 address.
+    #[serde(rename = "AAAA")]
+    Aaaa,
+    /// Maps a name, usually under `in-addr.arpa` or `ip6.arpa`, to another name.
+    #[serde(rename = "PTR")]
+    Ptr,
+}
+
+/// A record served by the DNS responder.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsRecordConfig {
+    /// The name the record answers for.
+    pub name: String,
+    /// The type of the record.
+    #[serde(rename = "type")]
+    pub record_type: DnsRecordType,
+    /// An IPv4 address, an IPv6 address or a domain name, depending on the type.
+    pub value: String,
+}
+
+impl DnsRecordConfig {
+    fn record(&self) -> Result<DnsRecord, DnsConfigError> {
+        let invalid_value = || DnsConfigError::InvalidRecordValue(self.name.clone());
+        match self.record_type {
+            DnsRecordType::A => {
+                DnsRecord::new_a(&self.name, self.value.parse().map_err(|_| invalid_value())?)
+            }
+            DnsRecordType::Aaaa => DnsRecord::new_aaaa(
+                &self.name,
+                self.value
+                    .parse::<Ipv6Addr>()
+                    .map_err(|_| invalid_value())?,
+            ),
+            DnsRecordType::Ptr => DnsRecord::new_ptr(&self.name, &self.value),
+        }
+        .map_err(DnsConfigError::Records)
+    }
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from DNS responder related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsConfig {
+    /// The IPv4 address the responder listens on.
+    pub ipv4_address: Option<Ipv4Addr>,
+    /// IDs of the network interfaces whose guest queries are answered.
+    pub network_interfaces: Vec<String>,
+    /// Time to live of the answers, in seconds.
+    #[serde(default = "default_ttl")]
+    pub ttl: u32,
+    /// The records served to the guest. Queries for other names are refused.
+    #[serde(default)]
+    pub records: Vec<DnsRecordConfig>,
+}
+
+impl DnsConfig {
+    /// Returns the IPv4 address the responder listens on.
+    pub fn ipv4_addr(&self) -> Ipv4Addr {
+        self.ipv4_address
+            .unwrap_or_else(DnsResponder::default_ipv4_addr)
+    }
+
+    /// Builds a DNS responder serving the configured records.
+    pub fn responder(&self) -> Result<DnsResponder, DnsConfigError> {
+        if !is_link_local_valid(self.ipv4_addr()) {
+            return Err(DnsConfigError::InvalidIpv4Addr);
+        }
+
+        let records = self
+            .records
+            .iter()
+            .map(DnsRecordConfig::record)
+            .collect::<Result<Vec<_>, _>>()?;
+        DnsResponder::new(self.ipv4_addr(), self.ttl, records).map_err(DnsConfigError::Records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, record_type: DnsRecordType, value: &str) -> DnsRecordConfig {
+        DnsRecordConfig {
+            name: name.to_string(),
+            record_type,
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_responder() {
+        let mut config = DnsConfig {
+            ipv4_address: None,
+            network_interfaces: vec!["eth0".to_string()],
+            ttl: default_ttl(),
+            records: vec![
+                record("api.internal", DnsRecordType::A, "10.0.0.1"),
+                record("api.internal", DnsRecordType::Aaaa, "fd00::1"),
+                record("1.0.0.10.in-addr.arpa", DnsRecordType::Ptr, "api.internal"),
+            ],
+        };
+        assert_eq!(
+            config.responder().unwrap().ipv4_addr(),
+            Ipv4Addr::new(169, 254, 169, 253)
+        );
+
+        config.ipv4_address = Some(Ipv4Addr::new(10, 0, 0, 53));
+        assert_eq!(
+            config.responder().err(),
+            Some(DnsConfigError::InvalidIpv4Addr)
+        );
+
+        config.ipv4_address = Some(Ipv4Addr::new(169, 254, 0, 53));
+        assert_eq!(
+            config.responder().unwrap().ipv4_addr(),
+            Ipv4Addr::new(169, 254, 0, 53)
+        );
+
+        config.records = vec![record("api.internal", DnsRecordType::A, "fd00::1")];
+        assert_eq!(
+            config.responder().err().unwrap().to_string(),
+            "The value of the DNS record api.internal does not match its type."
+        );
+
+        config.records = vec![record("api..internal", DnsRecordType::A, "10.0.0.1")];
+        assert_eq!(
+            config.responder().err().unwrap().to_string(),
+            "Invalid DNS records: Invalid domain name: api..internal"
+        );
+    }
+}
@@ -2,6 +2,8 @@
 // SPDX-License-Identifier: Apache-2.0
 use serde::Serialize;
 
+use crate::measured_boot::BootMeasurements;
+
 /// The strongly typed that contains general information about the microVM.
 #[derive(Clone, Debug, Serialize)]
 pub struct InstanceInfo {
@@ -13,4 +15,7 @@ pub struct InstanceInfo {
     pub vmm_version: String,
     /// The name of the application that runs the microVM.
     pub app_name: String,
+    /// The measurements of the boot components, if the microVM was booted with measured boot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_measurements: Option<BootMeasurements>,
 }
@@ -168,6 +168,7 @@ mod tests {
             state: "Not started".to_string(),
             vmm_version: "some_version".to_string(),
             app_name: "".to_string(),
+            boot_measurements: None,
         };
 
         // Error case: initializing logger with invalid pipe returns error.
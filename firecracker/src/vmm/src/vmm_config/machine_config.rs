@@ -17,12 +17,28 @@ pub enum VmConfigError {
     IncompatibleBalloonSize,
     /// The memory size is invalid. The memory can only be an unsigned integer.
     InvalidMemorySize,
+    /// The memory regions cannot be backed by pages of the size used by the memory backend.
+    MemorySizeNotPageAligned(MemoryBackend),
     /// The vcpu count is invalid. When hyperthreading is enabled, the `cpu_count` must be either
     /// 1 or an even number.
     InvalidVcpuCount,
+    /// The vcpu count is greater than the maximum vcpu count, which is fixed at boot.
+    InvalidMaxVcpuCount,
     /// Could not get the config of the balloon device from the VM resources, even though a
     /// balloon device was previously installed.
     InvalidVmState,
+    /// The CPU template is meant for a different architecture than the host's.
+    CpuTemplateNotSupported(CpuFeaturesTemplate),
+    /// KVM cannot apply the CPU template on this host.
+    CpuTemplateNotApplicable(CpuFeaturesTemplate, String),
+    /// A set of host CPUs is empty or holds a CPU beyond the supported ones.
+    InvalidCpuAffinity,
+    /// The number of vCPU affinities does not match the number of vCPUs.
+    VcpuAffinityCountMismatch,
+    /// The priority of the `Fifo` scheduling policy is not between 1 and 99.
+    InvalidSchedPriority(u8),
+    /// The nice value of the `Other` scheduling policy is not between -20 and 19.
+    InvalidNiceValue(i8),
 }
 
 impl fmt::Display for VmConfigError {
@@ -35,16 +51,55 @@ impl fmt::Display for VmConfigError {
                  set balloon device target size.",
             ),
             InvalidMemorySize => write!(f, "The memory size (MiB) is invalid.",),
+            MemorySizeNotPageAligned(backend) => write!(
+                f,
+                "The memory size (MiB) does not split into memory regions that are a \
+                 multiple of the page size of the {} memory backend.",
+                backend
+            ),
             InvalidVcpuCount => write!(
                 f,
                 "The vCPU number is invalid! The vCPU number can only \
                  be 1 or an even number when hyperthreading is enabled.",
             ),
+            InvalidMaxVcpuCount => write!(
+                f,
+                "The vCPU number cannot be greater than the maximum vCPU number.",
+            ),
             InvalidVmState => write!(
                 f,
                 "Could not get the configuration of the previously \
                  installed balloon device to validate the memory size.",
             ),
+            CpuTemplateNotSupported(template) => write!(
+                f,
+                "The {} CPU template is not supported on this architecture.",
+                template
+            ),
+            CpuTemplateNotApplicable(template, ref err) => write!(
+                f,
+                "The {} CPU template cannot be applied on this host: {}",
+                template, err
+            ),
+            InvalidCpuAffinity => write!(
+                f,
+                "A set of host CPUs is empty or holds a CPU greater than {}.",
+                utils::sched::MAX_CPUS - 1
+            ),
+            VcpuAffinityCountMismatch => write!(
+                f,
+                "The number of vCPU affinities does not match the vCPU number."
+            ),
+            InvalidSchedPriority(priority) => write!(
+                f,
+                "The scheduling priority {} is invalid. It must be between 1 and 99.",
+                priority
+            ),
+            InvalidNiceValue(nice) => write!(
+                f,
+                "The nice value {} is invalid. It must be between -20 and 19.",
+                nice
+            ),
         }
     }
 }
@@ -61,6 +116,13 @@ pub struct VmConfig {
         deserialize_with = "validate_vcpu_num"
     )]
     pub vcpu_count: Option<u8>,
+    /// Number of vcpu created at boot, up to which the online vcpu count can grow.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "validate_vcpu_num"
+    )]
+    pub max_vcpu_count: Option<u8>,
     /// The memory size in MiB.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mem_size_mib: Option<usize>,
@@ -73,16 +135,43 @@ pub struct VmConfig {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(default)]
     pub track_dirty_pages: bool,
+    /// The type of host memory backing the guest memory.
+    #[serde(default)]
+    pub mem_backend: MemoryBackend,
+    /// The host CPUs on which each vCPU thread runs, indexed by vCPU.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vcpu_affinity: Option<Vec<Vec<usize>>>,
+    /// The host CPUs on which the VMM thread runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vmm_affinity: Option<Vec<usize>>,
+    /// The host CPUs on which the API thread runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_affinity: Option<Vec<usize>>,
+    /// The scheduling policy of the vCPU threads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vcpu_sched_policy: Option<ThreadSchedPolicy>,
+    /// The path of the Unix socket on which a GDB server waits for a debugger.
+    #[cfg(feature = "gdb")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gdb_socket_path: Option<String>,
 }
 
 impl Default for VmConfig {
     fn default() -> Self {
         VmConfig {
             vcpu_count: Some(1),
+            max_vcpu_count: None,
             mem_size_mib: Some(DEFAULT_MEM_SIZE_MIB),
             ht_enabled: Some(false),
             cpu_template: None,
             track_dirty_pages: false,
+            mem_backend: MemoryBackend::Anonymous,
+            vcpu_affinity: None,
+            vmm_affinity: None,
+            api_affinity: None,
+            vcpu_sched_policy: None,
+            #[cfg(feature = "gdb")]
+            gdb_socket_path: None,
         }
     }
 }
@@ -98,8 +187,13 @@ impl fmt::Display for VmConfig {
         write!(
             f,
             "{{ \"vcpu_count\": {:?}, \"mem_size_mib\": {:?}, \"ht_enabled\": {:?}, \
-             \"cpu_template\": {:?}, \"track_dirty_pages\": {:?} }}",
-            vcpu_count, mem_size, ht_enabled, cpu_template, self.track_dirty_pages
+             \"cpu_template\": {:?}, \"track_dirty_pages\": {:?}, \"mem_backend\": {:?} }}",
+            vcpu_count,
+            mem_size,
+            ht_enabled,
+            cpu_template,
+            self.track_dirty_pages,
+            self.mem_backend.to_string()
         )
     }
 }
@@ -120,6 +214,62 @@ where
     Ok(val)
 }
 
+/// Update of the number of vCPUs running the guest, which starts or parks vCPUs after boot.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OnlineVcpuCountUpdate {
+    /// The new number of vCPUs.
+    #[serde(deserialize_with = "validate_vcpu_count")]
+    pub vcpu_count: u8,
+}
+
+fn validate_vcpu_count<'de, D>(d: D) -> std::result::Result<u8, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    validate_vcpu_num(d)?.ok_or_else(|| de::Error::custom("missing vCPU number"))
+}
+
+/// Scheduling policies of the host threads running the vCPUs.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "policy", deny_unknown_fields)]
+pub enum ThreadSchedPolicy {
+    /// The default time-sharing policy, with a nice value from -20 (highest priority) to 19.
+    Other {
+        /// Nice value of the threads.
+        nice: i8,
+    },
+    /// The real-time first-in first-out policy, with a static priority from 1 to 99
+    /// (highest priority).
+    Fifo {
+        /// Static priority of the threads.
+        priority: u8,
+    },
+}
+
+impl ThreadSchedPolicy {
+    /// Checks that the nice value or priority is in the range accepted by the host.
+    pub fn validate(self) -> std::result::Result<(), VmConfigError> {
+        match self {
+            ThreadSchedPolicy::Other { nice } if !(-20..=19).contains(&nice) => {
+                Err(VmConfigError::InvalidNiceValue(nice))
+            }
+            ThreadSchedPolicy::Fifo { priority } if !(1..=99).contains(&priority) => {
+                Err(VmConfigError::InvalidSchedPriority(priority))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Checks that a set of host CPUs can be used as the affinity of a thread.
+pub fn validate_cpu_affinity(cpus: &[usize]) -> std::result::Result<(), VmConfigError> {
+    if cpus.is_empty() || cpus.iter().any(|cpu| *cpu >= utils::sched::MAX_CPUS) {
+        return Err(VmConfigError::InvalidCpuAffinity);
+    }
+    Ok(())
+}
+
 /// Template types available for configuring the CPU features that map
 /// to EC2 instances.
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
@@ -128,6 +278,22 @@ pub enum CpuFeaturesTemplate {
     C3,
     /// T2 Template.
     T2,
+    /// T2 Template for AMD hosts.
+    T2A,
+    /// Neoverse N1 Template for aarch64 hosts.
+    V1N1,
+}
+
+impl CpuFeaturesTemplate {
+    /// Returns whether the template can be applied on the architecture of the host.
+    pub fn is_supported_by_arch(self) -> bool {
+        match self {
+            CpuFeaturesTemplate::C3 | CpuFeaturesTemplate::T2 | CpuFeaturesTemplate::T2A => {
+                cfg!(target_arch = "x86_64")
+            }
+            CpuFeaturesTemplate::V1N1 => cfg!(target_arch = "aarch64"),
+        }
+    }
 }
 
 impl fmt::Display for CpuFeaturesTemplate {
@@ -135,6 +301,55 @@ impl fmt::Display for CpuFeaturesTemplate {
         match self {
             CpuFeaturesTemplate::C3 => write!(f, "C3"),
             CpuFeaturesTemplate::T2 => write!(f, "T2"),
+            CpuFeaturesTemplate::T2A => write!(f, "T2A"),
+            CpuFeaturesTemplate::V1N1 => write!(f, "V1N1"),
+        }
+    }
+}
+
+/// Types of host memory that can back the guest memory.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum MemoryBackend {
+    /// Private anonymous memory, using the host's base page size.
+    Anonymous,
+    /// A memory file that other processes can map through `/proc/<pid>/fd`.
+    SharedMemfd,
+    /// A hugetlbfs memory file using 2 MiB pages.
+    Hugetlbfs2M,
+    /// A hugetlbfs memory file using 1 GiB pages.
+    Hugetlbfs1G,
+}
+
+impl MemoryBackend {
+    /// Returns the size of the huge pages backing the guest memory, if any.
+    pub fn huge_page_size(self) -> Option<usize> {
+        match self {
+            MemoryBackend::Anonymous | MemoryBackend::SharedMemfd => None,
+            MemoryBackend::Hugetlbfs2M => Some(2 << 20),
+            MemoryBackend::Hugetlbfs1G => Some(1 << 30),
+        }
+    }
+
+    /// Returns whether the guest memory is a shared mapping of a memory file,
+    /// as opposed to private memory that is copied on write after a `fork`.
+    pub fn is_shared(self) -> bool {
+        self != MemoryBackend::Anonymous
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        MemoryBackend::Anonymous
+    }
+}
+
+impl fmt::Display for MemoryBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryBackend::Anonymous => write!(f, "Anonymous"),
+            MemoryBackend::SharedMemfd => write!(f, "SharedMemfd"),
+            MemoryBackend::Hugetlbfs2M => write!(f, "Hugetlbfs2M"),
+            MemoryBackend::Hugetlbfs1G => write!(f, "Hugetlbfs1G"),
         }
     }
 }
@@ -147,6 +362,84 @@ mod tests {
     fn test_display_cpu_features_template() {
         assert_eq!(CpuFeaturesTemplate::C3.to_string(), "C3".to_string());
         assert_eq!(CpuFeaturesTemplate::T2.to_string(), "T2".to_string());
+        assert_eq!(CpuFeaturesTemplate::T2A.to_string(), "T2A".to_string());
+        assert_eq!(CpuFeaturesTemplate::V1N1.to_string(), "V1N1".to_string());
+    }
+
+    #[test]
+    fn test_cpu_features_template_arch() {
+        let x86_64 = cfg!(target_arch = "x86_64");
+        assert_eq!(CpuFeaturesTemplate::C3.is_supported_by_arch(), x86_64);
+        assert_eq!(CpuFeaturesTemplate::T2.is_supported_by_arch(), x86_64);
+        assert_eq!(CpuFeaturesTemplate::T2A.is_supported_by_arch(), x86_64);
+        assert_eq!(CpuFeaturesTemplate::V1N1.is_supported_by_arch(), !x86_64);
+    }
+
+    #[test]
+    fn test_thread_sched_policy() {
+        assert!(ThreadSchedPolicy::Other { nice: -20 }.validate().is_ok());
+        assert!(ThreadSchedPolicy::Other { nice: 19 }.validate().is_ok());
+        assert_eq!(
+            ThreadSchedPolicy::Other { nice: 20 }.validate(),
+            Err(VmConfigError::InvalidNiceValue(20))
+        );
+        assert!(ThreadSchedPolicy::Fifo { priority: 99 }.validate().is_ok());
+        assert_eq!(
+            ThreadSchedPolicy::Fifo { priority: 0 }.validate(),
+            Err(VmConfigError::InvalidSchedPriority(0))
+        );
+
+        let policy: ThreadSchedPolicy =
+            serde_json::from_str(r#"{"policy": "Fifo", "priority": 10}"#).unwrap();
+        assert_eq!(policy, ThreadSchedPolicy::Fifo { priority: 10 });
+        assert!(serde_json::from_str::<ThreadSchedPolicy>(r#"{"policy": "Other"}"#).is_err());
+    }
+
+    #[test]
+    fn test_vcpu_count_update() {
+        let update: OnlineVcpuCountUpdate = serde_json::from_str(r#"{"vcpu_count": 4}"#).unwrap();
+        assert_eq!(update, OnlineVcpuCountUpdate { vcpu_count: 4 });
+        assert!(serde_json::from_str::<OnlineVcpuCountUpdate>(r#"{"vcpu_count": 33}"#).is_err());
+        assert!(serde_json::from_str::<OnlineVcpuCountUpdate>(r#"{"vcpu_count": null}"#).is_err());
+        assert!(serde_json::from_str::<OnlineVcpuCountUpdate>(
+            r#"{"vcpu_count": 4, "mem_size_mib": 256}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_cpu_affinity() {
+        assert!(validate_cpu_affinity(&[0, 3]).is_ok());
+        assert_eq!(
+            validate_cpu_affinity(&[]),
+            Err(VmConfigError::InvalidCpuAffinity)
+        );
+        assert_eq!(
+            validate_cpu_affinity(&[utils::sched::MAX_CPUS]),
+            Err(VmConfigError::InvalidCpuAffinity)
+        );
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        assert_eq!(MemoryBackend::default(), MemoryBackend::Anonymous);
+        assert_eq!(MemoryBackend::Anonymous.to_string(), "Anonymous");
+        assert_eq!(MemoryBackend::SharedMemfd.to_string(), "SharedMemfd");
+        assert_eq!(MemoryBackend::Hugetlbfs2M.to_string(), "Hugetlbfs2M");
+        assert_eq!(MemoryBackend::Hugetlbfs1G.to_string(), "Hugetlbfs1G");
+
+        assert!(!MemoryBackend::Anonymous.is_shared());
+        assert!(MemoryBackend::SharedMemfd.is_shared());
+        assert!(MemoryBackend::Hugetlbfs2M.is_shared());
+        assert!(MemoryBackend::Hugetlbfs1G.is_shared());
+
+        assert_eq!(MemoryBackend::Anonymous.huge_page_size(), None);
+        assert_eq!(MemoryBackend::SharedMemfd.huge_page_size(), None);
+        assert_eq!(MemoryBackend::Hugetlbfs2M.huge_page_size(), Some(0x20_0000));
+        assert_eq!(
+            MemoryBackend::Hugetlbfs1G.huge_page_size(),
+            Some(0x4000_0000)
+        );
     }
 
     #[test]
@@ -155,7 +448,30 @@ mod tests {
                             be 1 or an even number when hyperthreading is enabled.";
         assert_eq!(VmConfigError::InvalidVcpuCount.to_string(), expected_str);
 
+        let expected_str = "The vCPU number cannot be greater than the maximum vCPU number.";
+        assert_eq!(VmConfigError::InvalidMaxVcpuCount.to_string(), expected_str);
+
         let expected_str = "The memory size (MiB) is invalid.";
         assert_eq!(VmConfigError::InvalidMemorySize.to_string(), expected_str);
+
+        let expected_str = "The memory size (MiB) does not split into memory regions that are \
+                            a multiple of the page size of the Hugetlbfs1G memory backend.";
+        assert_eq!(
+            VmConfigError::MemorySizeNotPageAligned(MemoryBackend::Hugetlbfs1G).to_string(),
+            expected_str
+        );
+
+        let expected_str = "The V1N1 CPU template is not supported on this architecture.";
+        assert_eq!(
+            VmConfigError::CpuTemplateNotSupported(CpuFeaturesTemplate::V1N1).to_string(),
+            expected_str
+        );
+
+        let expected_str = "The V1N1 CPU template cannot be applied on this host: error";
+        assert_eq!(
+            VmConfigError::CpuTemplateNotApplicable(CpuFeaturesTemplate::V1N1, "error".to_string())
+                .to_string(),
+            expected_str
+        );
     }
 }
@@ -1,9 +1,124 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use super::TokenBucketConfig;
+use mmds::data_store::{GuestWriteLimits, MmdsVersion};
+use mmds::ns::{
+    EndpointLimits, DEFAULT_MAX_CONNECTIONS, DEFAULT_RECEIVE_BUF_SIZE, DEFAULT_SEND_BUF_SIZE,
+};
+use rate_limiter::TokenBucket;
 use serde::{export::Formatter, Deserialize};
 use std::fmt::{Display, Result};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroUsize;
+
+/// Default maximum size, in bytes, of the data store written by the guest.
+pub const DEFAULT_GUEST_DATA_STORE_LIMIT: usize = 4096;
+
+fn default_guest_data_store_limit() -> usize {
+    DEFAULT_GUEST_DATA_STORE_LIMIT
+}
+
+/// Maximum number of concurrent guest connections to the MMDS.
+pub const MAX_CONNECTIONS_LIMIT: usize = 1024;
+/// Bounds of the receive buffer size, in bytes, of each guest connection to the MMDS. The upper
+/// bound is the largest window advertised without the window scaling option.
+pub const RECEIVE_BUFFER_SIZE_RANGE: (usize, usize) = (1024, 65535);
+/// Bounds of the send buffer size, in bytes, of each guest connection to the MMDS.
+pub const SEND_BUFFER_SIZE_RANGE: (usize, usize) = (1024, 1 << 20);
+
+fn default_max_connections() -> usize {
+    DEFAULT_MAX_CONNECTIONS
+}
+
+fn default_receive_buffer_size() -> usize {
+    DEFAULT_RECEIVE_BUF_SIZE
+}
+
+fn default_send_buffer_size() -> usize {
+    DEFAULT_SEND_BUF_SIZE
+}
+
+/// Keeps the limits of the guest connections to the MMDS.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MmdsConnectionLimitsConfig {
+    /// Maximum number of concurrent connections. The idle ones are evicted to make room for new
+    /// connections.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// Size, in bytes, of the receive window of each connection, which bounds the size of the
+    /// requests.
+    #[serde(default = "default_receive_buffer_size")]
+    pub receive_buffer_size: usize,
+    /// Size, in bytes, of the send buffer of each connection. Larger responses are streamed.
+    #[serde(default = "default_send_buffer_size")]
+    pub send_buffer_size: usize,
+}
+
+impl Default for MmdsConnectionLimitsConfig {
+    fn default() -> Self {
+        MmdsConnectionLimitsConfig {
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            receive_buffer_size: DEFAULT_RECEIVE_BUF_SIZE,
+            send_buffer_size: DEFAULT_SEND_BUF_SIZE,
+        }
+    }
+}
+
+impl MmdsConnectionLimitsConfig {
+    /// Checks that the limits are within bounds.
+    pub fn validate(&self) -> std::result::Result<(), MmdsConfigError> {
+        let within = |value: usize, (min, max): (usize, usize)| min <= value && value <= max;
+        if within(self.max_connections, (1, MAX_CONNECTIONS_LIMIT))
+            && within(self.receive_buffer_size, RECEIVE_BUFFER_SIZE_RANGE)
+            && within(self.send_buffer_size, SEND_BUFFER_SIZE_RANGE)
+        {
+            Ok(())
+        } else {
+            Err(MmdsConfigError::InvalidConnectionLimits)
+        }
+    }
+
+    /// Returns the maximum number of connections, and the buffer sizes of each connection.
+    pub fn limits(&self) -> (NonZeroUsize, EndpointLimits) {
+        (
+            // The unwrap is safe because the configuration is validated before use.
+            NonZeroUsize::new(self.max_connections).unwrap(),
+            EndpointLimits {
+                receive_buf_size: self.receive_buffer_size,
+                send_buf_size: self.send_buffer_size,
+            },
+        )
+    }
+}
+
+/// Keeps the configuration of the data store the guest writes through the MMDS.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MmdsGuestWriteConfig {
+    /// Maximum size, in bytes, of the data store written by the guest.
+    #[serde(default = "default_guest_data_store_limit")]
+    pub size_limit: usize,
+    /// Rate limiter of the guest writes, where each write takes one token.
+    pub rate_limiter: Option<TokenBucketConfig>,
+}
+
+impl MmdsGuestWriteConfig {
+    /// Returns the limits the guest writes are subject to.
+    pub fn guest_write_limits(&self) -> GuestWriteLimits {
+        GuestWriteLimits {
+            data_store_limit: self.size_limit,
+            rate_limiter: self.rate_limiter.and_then(|tb_cfg| {
+                TokenBucket::new(
+                    tb_cfg.size,
+                    tb_cfg.one_time_burst.unwrap_or(0),
+                    tb_cfg.refill_time,
+                )
+            }),
+        }
+    }
+}
 
 /// Keeps the MMDS configuration.
 #[derive(Debug, Deserialize, PartialEq)]
@@ -11,6 +126,20 @@ use std::net::Ipv4Addr;
 pub struct MmdsConfig {
     /// MMDS IPv4 configured address.
     pub ipv4_address: Option<Ipv4Addr>,
+    /// MMDS IPv6 configured address. The MMDS is only reachable over IPv6 if one is given.
+    pub ipv6_address: Option<Ipv6Addr>,
+    /// MMDS version, telling whether the guest needs session tokens.
+    #[serde(default)]
+    pub version: MmdsVersion,
+    /// Name of the MMDS instance. The default instance is configured if no name is given.
+    pub mmds_id: Option<String>,
+    /// IDs of the network interfaces serving the MMDS instance to the guest.
+    #[serde(default)]
+    pub network_interfaces: Vec<String>,
+    /// Allows the guest to write its own data store, within the given limits.
+    pub guest_write: Option<MmdsGuestWriteConfig>,
+    /// Limits of the guest connections to the MMDS. The defaults apply if none are given.
+    pub connection_limits: Option<MmdsConnectionLimitsConfig>,
 }
 
 impl MmdsConfig {
@@ -19,21 +148,84 @@ impl MmdsConfig {
     pub fn ipv4_addr(&self) -> Option<Ipv4Addr> {
         self.ipv4_address
     }
+
+    /// Returns the MMDS IPv6 address if one was configured.
+    /// Otherwise returns None.
+    pub fn ipv6_addr(&self) -> Option<Ipv6Addr> {
+        self.ipv6_address
+    }
+
+    /// Returns the configured connection limits, or the default ones.
+    pub fn connection_limits(&self) -> MmdsConnectionLimitsConfig {
+        self.connection_limits.unwrap_or_default()
+    }
 }
 
 /// MMDS configuration related errors.
 #[derive(Debug)]
 pub enum MmdsConfigError {
+    /// The connection limits are out of bounds.
+    InvalidConnectionLimits,
     /// The provided IPv4 address is not link-local valid.
     InvalidIpv4Addr,
+    /// The provided IPv6 address is neither link-local nor unique local.
+    InvalidIpv6Addr,
+    /// The name of the MMDS instance is empty.
+    InvalidMmdsId,
+    /// The network interface does not exist.
+    InvalidNetworkInterfaceId(String),
+    /// No network interface serves the named MMDS instance.
+    MissingNetworkInterfaces(String),
+    /// The network interface cannot be detached from the MMDS instance it serves.
+    NetworkInterfaceDetached(String),
+    /// The network interface already serves another MMDS instance.
+    NetworkInterfaceInUse(String),
+    /// Failed to set up the generation of session tokens.
+    TokenAuthority(mmds::token::Error),
 }
 
 impl Display for MmdsConfigError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         match self {
+            MmdsConfigError::InvalidConnectionLimits => write!(
+                f,
+                "The MMDS connection limits are out of bounds: at most {} connections, receive \
+                 buffers of {} to {} bytes and send buffers of {} to {} bytes are supported.",
+                MAX_CONNECTIONS_LIMIT,
+                RECEIVE_BUFFER_SIZE_RANGE.0,
+                RECEIVE_BUFFER_SIZE_RANGE.1,
+                SEND_BUFFER_SIZE_RANGE.0,
+                SEND_BUFFER_SIZE_RANGE.1
+            ),
             MmdsConfigError::InvalidIpv4Addr => {
                 write!(f, "The MMDS IPv4 address is not link local.")
             }
+            MmdsConfigError::InvalidIpv6Addr => write!(
+                f,
+                "The MMDS IPv6 address is neither link local nor unique local."
+            ),
+            MmdsConfigError::InvalidMmdsId => write!(f, "The MMDS instance name is empty."),
+            MmdsConfigError::InvalidNetworkInterfaceId(iface_id) => {
+                write!(f, "The network interface {} does not exist.", iface_id)
+            }
+            MmdsConfigError::MissingNetworkInterfaces(mmds_id) => write!(
+                f,
+                "No network interface was provided for the MMDS instance {}.",
+                mmds_id
+            ),
+            MmdsConfigError::NetworkInterfaceDetached(iface_id) => write!(
+                f,
+                "The network interface {} cannot be detached from its MMDS instance.",
+                iface_id
+            ),
+            MmdsConfigError::NetworkInterfaceInUse(iface_id) => write!(
+                f,
+                "The network interface {} already serves another MMDS instance.",
+                iface_id
+            ),
+            MmdsConfigError::TokenAuthority(err) => {
+                write!(f, "Cannot set up the MMDS session tokens: {}", err)
+            }
         }
     }
 }
@@ -16,6 +16,10 @@ use rate_limiter::{BucketUpdate, RateLimiter, TokenBucket};
 pub mod balloon;
 /// Wrapper for configuring the microVM boot source.
 pub mod boot_source;
+/// Wrapper for configuring custom CPU templates.
+pub mod cpu_config;
+/// Wrapper for configuring the DNS responder answering the queries of the guest.
+pub mod dns;
 /// Wrapper for configuring the block devices.
 pub mod drive;
 /// Wrapper over the microVM general information attached to the microVM.
@@ -32,6 +36,10 @@ pub mod mmds;
 pub mod net;
 /// Wrapper for configuring microVM snapshots and the microVM state.
 pub mod snapshot;
+/// Wrapper for configuring the TPM device attached to the microVM.
+pub mod tpm;
+/// Wrapper over the KVM exit statistics of the vCPUs.
+pub mod vcpu_stats;
 /// Wrapper for configuring the vsock devices attached to the microVM.
 pub mod vsock;
 
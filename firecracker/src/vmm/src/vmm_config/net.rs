@@ -1,14 +1,16 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
+use std::net::Ipv4Addr;
 use std::result;
 use std::sync::{Arc, Mutex};
 
 use super::RateLimiterConfig;
 use crate::Error as VmmError;
-use devices::virtio::net::TapError;
+use devices::virtio::net::dhcp::MAX_DNS_SERVERS;
+use devices::virtio::net::{DhcpServer, DhcpServerConfig, TapError};
 use devices::virtio::Net;
 use utils::net::mac::MacAddr;
 
@@ -36,6 +38,10 @@ pub struct NetworkInterfaceConfig {
     /// same address are intercepted by the device model, and do not reach
     /// the associated TAP device.
     pub allow_mmds_requests: bool,
+    /// If this field is set, the device model will reply to the DHCP messages sent
+    /// by the guest via this interface, leasing it the configured address. These
+    /// messages do not reach the associated TAP device.
+    pub dhcp: Option<DhcpConfig>,
 }
 
 // Serde does not allow specifying a default value for a field
@@ -45,6 +51,67 @@ fn default_allow_mmds_requests() -> bool {
     false
 }
 
+/// The network configuration leased to the guest by the DHCP server of a network interface.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DhcpConfig {
+    /// IPv4 address leased to the guest.
+    pub guest_ip: Ipv4Addr,
+    /// Length of the network prefix of the leased address.
+    pub prefix_len: u8,
+    /// Default gateway of the guest.
+    pub gateway: Option<Ipv4Addr>,
+    /// DNS servers of the guest.
+    #[serde(default)]
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// MTU of the guest network interface.
+    pub mtu: Option<u16>,
+    /// Lease duration, in seconds.
+    #[serde(default = "default_lease_time")]
+    pub lease_time: u32,
+}
+
+fn default_lease_time() -> u32 {
+    86400
+}
+
+// The minimum MTU which hosts must accept, as per RFC 791.
+const MIN_MTU: u16 = 68;
+
+impl TryFrom<DhcpConfig> for DhcpServerConfig {
+    type Error = NetworkInterfaceError;
+
+    fn try_from(config: DhcpConfig) -> Result<Self> {
+        let invalid =
+            |reason: &str| Err(NetworkInterfaceError::InvalidDhcpConfig(reason.to_string()));
+
+        if config.prefix_len > 32 {
+            return invalid("the prefix length cannot exceed 32");
+        }
+        if config.dns_servers.len() > MAX_DNS_SERVERS {
+            return invalid(&format!(
+                "at most {} DNS servers can be configured",
+                MAX_DNS_SERVERS
+            ));
+        }
+        if config.mtu.map_or(false, |mtu| mtu < MIN_MTU) {
+            return invalid(&format!("the MTU cannot be lower than {}", MIN_MTU));
+        }
+        if config.lease_time == 0 {
+            return invalid("the lease time cannot be 0");
+        }
+
+        Ok(DhcpServerConfig {
+            guest_addr: config.guest_ip,
+            prefix_len: config.prefix_len,
+            gateway: config.gateway,
+            dns_servers: config.dns_servers,
+            mtu: config.mtu,
+            lease_time: config.lease_time,
+        })
+    }
+}
+
 /// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
 /// can be updated.
 #[derive(Debug, Deserialize, PartialEq, Clone)]
@@ -71,6 +138,8 @@ pub enum NetworkInterfaceError {
     GuestMacAddressInUse(String),
     /// Error during interface update (patch).
     DeviceUpdate(VmmError),
+    /// The DHCP configuration is invalid.
+    InvalidDhcpConfig(String),
     /// Cannot open/create tap device.
     OpenTap(TapError),
 }
@@ -87,6 +156,7 @@ impl fmt::Display for NetworkInterfaceError {
                 format!("The guest MAC address {} is already in use.", mac_addr)
             ),
             DeviceUpdate(e) => write!(f, "Error during interface update (patch): {}", e),
+            InvalidDhcpConfig(reason) => write!(f, "Invalid DHCP configuration: {}.", reason),
             OpenTap(e) => {
                 // We are propagating the Tap Error. This error can contain
                 // imbricated quotes which would result in an invalid json.
@@ -179,8 +249,10 @@ impl NetBuilder {
             .transpose()
             .map_err(NetworkInterfaceError::CreateRateLimiter)?;
 
+        let dhcp_server_config = cfg.dhcp.map(DhcpServerConfig::try_from).transpose()?;
+
         // Create and return the Net device
-        devices::virtio::net::Net::new_with_tap(
+        let mut net = devices::virtio::net::Net::new_with_tap(
             cfg.iface_id,
             cfg.host_dev_name.clone(),
             cfg.guest_mac.as_ref(),
@@ -188,7 +260,10 @@ impl NetBuilder {
             tx_rate_limiter.unwrap_or_default(),
             cfg.allow_mmds_requests,
         )
-        .map_err(NetworkInterfaceError::CreateNetworkDevice)
+        .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        net.set_dhcp_server(dhcp_server_config.map(DhcpServer::new));
+
+        Ok(net)
     }
 }
 
@@ -216,6 +291,7 @@ mod tests {
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
             allow_mmds_requests: false,
+            dhcp: None,
         }
     }
 
@@ -228,6 +304,7 @@ mod tests {
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
                 allow_mmds_requests: self.allow_mmds_requests,
+                dhcp: self.dhcp.clone(),
             }
         }
     }
@@ -340,6 +417,11 @@ mod tests {
             NetworkInterfaceError::DeviceUpdate(VmmError::VcpuExit),
             NetworkInterfaceError::DeviceUpdate(VmmError::VcpuExit)
         );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::InvalidDhcpConfig("reason".to_string()),
+            NetworkInterfaceError::InvalidDhcpConfig("reason".to_string())
+        );
         let _ = format!(
             "{}{:?}",
             NetworkInterfaceError::OpenTap(TapError::InvalidIfname),
@@ -359,5 +441,57 @@ mod tests {
             MacAddr::parse_str(guest_mac).unwrap()
         );
         assert_eq!(net_if.allow_mmds_requests, false);
+        assert!(net_if.dhcp.is_none());
+    }
+
+    #[test]
+    fn test_dhcp_config() {
+        let json = r#"{
+            "guest_ip": "192.168.241.2",
+            "prefix_len": 24,
+            "gateway": "192.168.241.1",
+            "dns_servers": ["8.8.8.8"],
+            "mtu": 1500
+        }"#;
+        let config: DhcpConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.lease_time, 86400);
+
+        let server_config = DhcpServerConfig::try_from(config.clone()).unwrap();
+        assert_eq!(server_config.guest_addr, Ipv4Addr::new(192, 168, 241, 2));
+        assert_eq!(server_config.prefix_len, 24);
+        assert_eq!(server_config.gateway, Some(Ipv4Addr::new(192, 168, 241, 1)));
+        assert_eq!(server_config.dns_servers, vec![Ipv4Addr::new(8, 8, 8, 8)]);
+        assert_eq!(server_config.mtu, Some(1500));
+        assert_eq!(server_config.lease_time, 86400);
+
+        // The DNS servers are optional.
+        let json = r#"{"guest_ip": "192.168.241.2", "prefix_len": 24}"#;
+        let minimal_config: DhcpConfig = serde_json::from_str(json).unwrap();
+        assert!(minimal_config.dns_servers.is_empty());
+
+        let invalid_configs = vec![
+            DhcpConfig {
+                prefix_len: 33,
+                ..config.clone()
+            },
+            DhcpConfig {
+                dns_servers: vec![Ipv4Addr::new(8, 8, 8, 8); MAX_DNS_SERVERS + 1],
+                ..config.clone()
+            },
+            DhcpConfig {
+                mtu: Some(MIN_MTU - 1),
+                ..config.clone()
+            },
+            DhcpConfig {
+                lease_time: 0,
+                ..config
+            },
+        ];
+        for invalid_config in invalid_configs {
+            assert!(matches!(
+                DhcpServerConfig::try_from(invalid_config),
+                Err(NetworkInterfaceError::InvalidDhcpConfig(_))
+            ));
+        }
     }
 }
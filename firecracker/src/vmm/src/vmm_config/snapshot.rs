@@ -38,6 +38,49 @@ pub struct CreateSnapshotParams {
     /// Optional field for the microVM version. The default
     /// value is the current version.
     pub version: Option<String>,
+    /// Path to the file holding the key used to authenticate the snapshot.
+    /// When present, the state file is signed with HMAC-SHA256 and carries
+    /// a digest of each guest memory region.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrity_key_path: Option<PathBuf>,
+    /// When set to true, only the microVM state is saved synchronously, while the
+    /// guest memory is dumped in the background from a copy-on-write view of it.
+    /// The microVM can be resumed as soon as the request returns.
+    #[serde(default)]
+    pub background: bool,
+}
+
+/// The states of a snapshot creation.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum SnapshotCreateState {
+    /// No background snapshot creation was requested.
+    NotStarted,
+    /// The guest memory is still being dumped.
+    InProgress,
+    /// The snapshot files are complete.
+    Completed,
+    /// The snapshot creation failed.
+    Failed,
+}
+
+/// Describes the progress of the latest background snapshot creation.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotCreateStatus {
+    /// The state of the snapshot creation.
+    pub state: SnapshotCreateState,
+    /// The reason of the failure, if the snapshot creation failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Default for SnapshotCreateStatus {
+    fn default() -> Self {
+        SnapshotCreateStatus {
+            state: SnapshotCreateState::NotStarted,
+            error: None,
+        }
+    }
 }
 
 /// Stores the configuration that will be used for loading a snapshot.
@@ -48,6 +91,10 @@ pub struct LoadSnapshotParams {
     pub snapshot_path: PathBuf,
     /// Path to the file that contains the guest memory to be loaded.
     pub mem_file_path: PathBuf,
+    /// Path to the full guest memory snapshot which the streamed diff memory snapshot
+    /// at `mem_file_path` is applied on top of.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_base_path: Option<PathBuf>,
     /// Setting this flag will enable KVM dirty page tracking and will
     /// allow taking subsequent incremental snapshots.
     #[serde(default)]
@@ -56,6 +103,11 @@ pub struct LoadSnapshotParams {
     /// is successful.
     #[serde(default)]
     pub resume_vm: bool,
+    /// Path to the file holding the key used to authenticate the snapshot.
+    /// When present, loading fails unless the state file signature and the
+    /// guest memory region digests match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrity_key_path: Option<PathBuf>,
 }
 
 /// The microVM state options.
@@ -0,0 +1,82 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+
+use serde::{Deserialize, Serialize};
+
+/// Errors associated with the TPM configuration.
+#[derive(Debug)]
+pub enum TpmConfigError {
+    /// The path of the TPM emulator control socket is not a socket.
+    InvalidSocket(String),
+}
+
+impl fmt::Display for TpmConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::TpmConfigError::*;
+        match self {
+            InvalidSocket(path) => write!(
+                f,
+                "The TPM emulator control socket {} does not exist or is not a socket.",
+                path
+            ),
+        }
+    }
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from TPM related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TpmConfig {
+    /// Path of the control socket of the software TPM emulator.
+    pub socket: String,
+}
+
+impl TpmConfig {
+    /// Checks that the control socket of the emulator exists.
+    ///
+    /// The emulator is only connected to when the microVM starts.
+    pub fn validate(&self) -> std::result::Result<(), TpmConfigError> {
+        match fs::metadata(&self.socket) {
+            Ok(metadata) if metadata.file_type().is_socket() => Ok(()),
+            _ => Err(TpmConfigError::InvalidSocket(self.socket.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devices::tpm::test_utils::StandInEmulator;
+    use utils::tempfile::TempFile;
+
+    #[test]
+    fn test_validate() {
+        let stand_in = StandInEmulator::new();
+        let config = TpmConfig {
+            socket: stand_in.socket_path().to_str().unwrap().to_string(),
+        };
+        assert!(config.validate().is_ok());
+
+        let tmp_file = TempFile::new().unwrap();
+        let config = TpmConfig {
+            socket: tmp_file.as_path().to_str().unwrap().to_string(),
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(TpmConfigError::InvalidSocket(path)) if path == config.socket
+        ));
+
+        let config = TpmConfig {
+            socket: "/invalid/path".to_string(),
+        };
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "The TPM emulator control socket /invalid/path does not exist or is not a socket."
+        );
+    }
+}
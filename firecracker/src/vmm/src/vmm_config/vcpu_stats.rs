@@ -0,0 +1,62 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// Number of KVM exits of a vCPU, by exit reason.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VcpuExitCounts {
+    /// Port IO reads.
+    pub io_in: u64,
+    /// Port IO writes.
+    pub io_out: u64,
+    /// MMIO reads.
+    pub mmio_read: u64,
+    /// MMIO writes.
+    pub mmio_write: u64,
+    /// `hlt` instructions.
+    pub hlt: u64,
+    /// Triple faults.
+    pub shutdown: u64,
+    /// Guest reset or shutdown requests.
+    pub system_event: u64,
+    /// Hardware failures to enter the guest.
+    pub fail_entry: u64,
+    /// KVM internal errors.
+    pub internal_error: u64,
+    /// `KVM_RUN` calls interrupted to handle a request of the VMM.
+    pub interrupted: u64,
+    /// Any other exit reason.
+    pub other: u64,
+}
+
+/// Number of MMIO exits caused by the accesses to a device.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MmioDeviceExitCounts {
+    /// Guest physical address of the MMIO range of the device.
+    pub base: u64,
+    /// Length of the MMIO range of the device.
+    pub len: u64,
+    /// MMIO reads in the range.
+    pub reads: u64,
+    /// MMIO writes in the range.
+    pub writes: u64,
+}
+
+/// KVM exit counts and run time of a vCPU, since it was started.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VcpuStats {
+    /// Index of the vCPU.
+    pub vcpu_index: u8,
+    /// Exit counts, by exit reason.
+    pub exits: VcpuExitCounts,
+    /// MMIO exit counts, by device.
+    pub mmio_devices: Vec<MmioDeviceExitCounts>,
+    /// Time spent running the guest in `KVM_RUN`, in microseconds.
+    pub kvm_run_us: u64,
+    /// Time spent handling the exits in the VMM, in microseconds.
+    pub vmm_us: u64,
+}
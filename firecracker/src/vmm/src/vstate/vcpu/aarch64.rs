@@ -10,7 +10,12 @@ use std::{
     result,
 };
 
-use crate::vstate::{vcpu::VcpuEmulation, vm::Vm};
+use crate::vmm_config::machine_config::CpuFeaturesTemplate;
+use crate::vstate::{
+    vcpu::{VcpuConfig, VcpuEmulation},
+    vm::Vm,
+};
+use arch::aarch64::regs::VcpuRegisters;
 use kvm_ioctls::*;
 use logger::{error, IncMetric, METRICS};
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -20,18 +25,30 @@ use vm_memory::{Address, GuestAddress, GuestMemoryMmap};
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug)]
 pub enum Error {
+    /// Error applying the CPU template to the ID registers.
+    ApplyCpuTemplate(arch::aarch64::regs::Error),
     /// Error configuring the general purpose aarch64 registers.
     ConfigureRegisters(arch::aarch64::regs::Error),
+    /// The host kernel does not allow the ID registers to be changed.
+    CpuTemplateUnsupportedOnHost(CpuFeaturesTemplate),
     /// Cannot open the kvm related file descriptor.
     CreateFd(kvm_ioctls::Error),
+    /// Error getting the multiprocessing state of the Vcpu.
+    GetMpState(arch::aarch64::regs::Error),
     /// Error getting the Vcpu preferred target on Arm.
     GetPreferredTarget(kvm_ioctls::Error),
+    /// Error getting the writable bits of the ID registers.
+    GetIdRegisterMasks(arch::aarch64::regs::Error),
+    /// Cannot create a scratch vcpu to validate the CPU template.
+    ScratchVcpu(kvm_ioctls::Error),
     /// Error doing Vcpu Init on Arm.
     Init(kvm_ioctls::Error),
     /// Failed to set value for some arm specific register.
     RestoreState(arch::aarch64::regs::Error),
     /// Failed to fetch value for some arm specific register.
     SaveState(arch::aarch64::regs::Error),
+    /// The CPU template is meant for another architecture.
+    UnsupportedCpuTemplate(CpuFeaturesTemplate),
 }
 
 impl Display for Error {
@@ -39,14 +56,29 @@ impl Display for Error {
         use self::Error::*;
 
         match self {
+            ApplyCpuTemplate(e) => write!(f, "Error applying the CPU template: {}", e),
             ConfigureRegisters(e) => {
                 write!(f, "Error configuring the general purpose registers: {}", e)
             }
+            CpuTemplateUnsupportedOnHost(template) => write!(
+                f,
+                "The {} CPU template is not supported on this host: KVM does not allow the ID \
+                 registers to be changed",
+                template
+            ),
             CreateFd(e) => write!(f, "Error in opening the VCPU file descriptor: {}", e),
+            GetMpState(e) => write!(f, "Error retrieving the vcpu mp state: {}", e),
             GetPreferredTarget(e) => write!(f, "Error retrieving the vcpu preferred target: {}", e),
+            GetIdRegisterMasks(e) => write!(f, "Error retrieving the ID register masks: {}", e),
+            ScratchVcpu(e) => write!(f, "Error creating a scratch vcpu: {}", e),
             Init(e) => write!(f, "Error initializing the vcpu: {}", e),
             RestoreState(e) => write!(f, "Failed to restore the state of the vcpu: {}", e),
             SaveState(e) => write!(f, "Failed to save the state of the vcpu: {}", e),
+            UnsupportedCpuTemplate(template) => write!(
+                f,
+                "The {} CPU template is not supported on aarch64",
+                template
+            ),
         }
     }
 }
@@ -61,6 +93,8 @@ pub struct KvmVcpu {
     pub mmio_bus: Option<devices::Bus>,
 
     mpidr: u64,
+    // The ID register bits which KVM lets the CPU templates change, if any.
+    id_register_masks: Option<Vec<u64>>,
 }
 
 impl KvmVcpu {
@@ -72,12 +106,15 @@ impl KvmVcpu {
     /// * `vm` - The vm to which this vcpu will get attached.
     pub fn new(index: u8, vm: &Vm) -> Result<Self> {
         let kvm_vcpu = vm.fd().create_vcpu(index.into()).map_err(Error::CreateFd)?;
+        let id_register_masks = arch::aarch64::regs::get_id_register_writable_masks(vm.fd())
+            .map_err(Error::GetIdRegisterMasks)?;
 
         Ok(KvmVcpu {
             index,
             fd: kvm_vcpu,
             mmio_bus: None,
             mpidr: 0,
+            id_register_masks,
         })
     }
 
@@ -93,10 +130,12 @@ impl KvmVcpu {
     /// * `vm_fd` - The kvm `VmFd` for this microvm.
     /// * `guest_mem` - The guest memory used by this microvm.
     /// * `kernel_load_addr` - Offset from `guest_mem` at which the kernel is loaded.
+    /// * `vcpu_config` - The vCPU configuration.
     pub fn configure(
         &mut self,
         guest_mem: &GuestMemoryMmap,
         kernel_load_addr: GuestAddress,
+        vcpu_config: &VcpuConfig,
     ) -> Result<()> {
         arch::aarch64::regs::setup_boot_regs(
             &self.fd,
@@ -106,6 +145,10 @@ impl KvmVcpu {
         )
         .map_err(Error::ConfigureRegisters)?;
 
+        if let Some(template) = vcpu_config.cpu_template {
+            apply_cpu_template(&self.fd, self.id_register_masks.as_deref(), template)?;
+        }
+
         self.mpidr =
             arch::aarch64::regs::read_mpidr(&self.fd).map_err(Error::ConfigureRegisters)?;
 
@@ -126,6 +169,9 @@ impl KvmVcpu {
             .map_err(Error::GetPreferredTarget)?;
         // We already checked that the capability is supported.
         kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_PSCI_0_2;
+        // SVE and pointer authentication are left out on purpose: KVM only exposes them to
+        // the guest when their features are requested here, which is why the CPU templates
+        // don't have to hide them through the ID registers.
         // Non-boot cpus are powered off initially.
         if self.index > 0 {
             kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_POWER_OFF;
@@ -133,6 +179,12 @@ impl KvmVcpu {
         self.fd.vcpu_init(&kvi).map_err(Error::Init)
     }
 
+    /// Returns whether the guest took the vcpu offline, i.e. powered it off through PSCI.
+    pub fn is_offline(&self) -> Result<bool> {
+        let mp_state = arch::regs::get_mpstate(&self.fd).map_err(Error::GetMpState)?;
+        Ok(mp_state.mp_state == kvm_bindings::KVM_MP_STATE_STOPPED)
+    }
+
     /// Save the KVM internal state.
     pub fn save_state(&self) -> Result<VcpuState> {
         let mut state = VcpuState::default();
@@ -170,6 +222,49 @@ impl KvmVcpu {
     }
 }
 
+// Returns the ID register fields capped by `template`.
+fn template_id_register_fields(
+    template: CpuFeaturesTemplate,
+) -> Result<&'static [arch::aarch64::regs::IdRegisterField]> {
+    match template {
+        CpuFeaturesTemplate::V1N1 => Ok(&arch::aarch64::regs::V1N1_ID_REGISTER_FIELDS),
+        CpuFeaturesTemplate::C3 | CpuFeaturesTemplate::T2 | CpuFeaturesTemplate::T2A => {
+            Err(Error::UnsupportedCpuTemplate(template))
+        }
+    }
+}
+
+// Applies `template` to the ID registers of `vcpu`, given the ID register bits which KVM
+// lets userspace change on this host, if any.
+fn apply_cpu_template<V: VcpuRegisters>(
+    vcpu: &V,
+    id_register_masks: Option<&[u64]>,
+    template: CpuFeaturesTemplate,
+) -> Result<()> {
+    let fields = template_id_register_fields(template)?;
+    let masks = id_register_masks.ok_or(Error::CpuTemplateUnsupportedOnHost(template))?;
+    arch::aarch64::regs::cap_id_register_fields(vcpu, masks, fields)
+        .map_err(Error::ApplyCpuTemplate)
+}
+
+/// Checks that `template` can be applied to the vcpus of this host, by applying it to a
+/// scratch vcpu. KVM only lets the ID registers be lowered since Linux 6.7.
+pub fn validate_cpu_template(template: CpuFeaturesTemplate) -> Result<()> {
+    template_id_register_fields(template)?;
+    let kvm = Kvm::new().map_err(Error::ScratchVcpu)?;
+    let vm_fd = kvm.create_vm().map_err(Error::ScratchVcpu)?;
+    let masks = arch::aarch64::regs::get_id_register_writable_masks(&vm_fd)
+        .map_err(Error::GetIdRegisterMasks)?
+        .ok_or(Error::CpuTemplateUnsupportedOnHost(template))?;
+    let vcpu_fd = vm_fd.create_vcpu(0).map_err(Error::ScratchVcpu)?;
+    let mut kvi = kvm_bindings::kvm_vcpu_init::default();
+    vm_fd
+        .get_preferred_target(&mut kvi)
+        .map_err(Error::GetPreferredTarget)?;
+    vcpu_fd.vcpu_init(&kvi).map_err(Error::Init)?;
+    apply_cpu_template(&vcpu_fd, Some(masks.as_slice()), template)
+}
+
 /// Structure holding VCPU kvm state.
 #[derive(Clone, Default, Versionize)]
 pub struct VcpuState {
@@ -183,10 +278,12 @@ pub struct VcpuState {
 
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
     use std::os::unix::io::AsRawFd;
 
     use super::*;
     use crate::vstate::vm::{tests::setup_vm, Vm};
+    use arch::aarch64::regs::{ID_AA64ISAR0_EL1, ID_AA64ISAR1_EL1};
     use kvm_bindings::kvm_one_reg;
     use vm_memory::GuestMemoryMmap;
 
@@ -199,6 +296,30 @@ mod tests {
         (vm, vcpu, vm_mem)
     }
 
+    // The ID_AA64ISAR0_EL1 and ID_AA64ISAR1_EL1 registers of a vcpu.
+    struct IdRegisters(RefCell<[u64; 2]>);
+
+    impl IdRegisters {
+        fn index(reg_id: u64) -> result::Result<usize, kvm_ioctls::Error> {
+            match reg_id {
+                ID_AA64ISAR0_EL1 => Ok(0),
+                ID_AA64ISAR1_EL1 => Ok(1),
+                _ => Err(kvm_ioctls::Error::new(libc::ENOENT)),
+            }
+        }
+    }
+
+    impl VcpuRegisters for IdRegisters {
+        fn get_one_reg(&self, reg_id: u64) -> result::Result<u64, kvm_ioctls::Error> {
+            Ok(self.0.borrow()[Self::index(reg_id)?])
+        }
+
+        fn set_one_reg(&self, reg_id: u64, data: u64) -> result::Result<(), kvm_ioctls::Error> {
+            self.0.borrow_mut()[Self::index(reg_id)?] = data;
+            Ok(())
+        }
+    }
+
     fn init_vcpu(vcpu: &VcpuFd, vm: &VmFd) {
         let mut kvi: kvm_bindings::kvm_vcpu_init = kvm_bindings::kvm_vcpu_init::default();
         vm.get_preferred_target(&mut kvi).unwrap();
@@ -223,14 +344,41 @@ mod tests {
     #[test]
     fn test_configure_vcpu() {
         let (_vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        let mut vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            ht_enabled: false,
+            cpu_template: None,
+            custom_cpu_template: None,
+        };
 
         assert!(vcpu
-            .configure(&vm_mem, GuestAddress(arch::get_kernel_start()),)
+            .configure(
+                &vm_mem,
+                GuestAddress(arch::get_kernel_start()),
+                &vcpu_config
+            )
             .is_ok());
 
+        // Templates for other architectures are rejected.
+        vcpu_config.cpu_template = Some(CpuFeaturesTemplate::T2);
+        let err = vcpu.configure(
+            &vm_mem,
+            GuestAddress(arch::get_kernel_start()),
+            &vcpu_config,
+        );
+        assert_eq!(
+            err.err().unwrap().to_string(),
+            "The T2 CPU template is not supported on aarch64".to_string()
+        );
+        vcpu_config.cpu_template = None;
+
         unsafe { libc::close(vcpu.fd.as_raw_fd()) };
 
-        let err = vcpu.configure(&vm_mem, GuestAddress(arch::get_kernel_start()));
+        let err = vcpu.configure(
+            &vm_mem,
+            GuestAddress(arch::get_kernel_start()),
+            &vcpu_config,
+        );
         assert!(err.is_err());
         assert_eq!(
             err.err().unwrap().to_string(),
@@ -240,7 +388,11 @@ mod tests {
 
         let (_vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
         unsafe { libc::close(vcpu.fd.as_raw_fd()) };
-        let err = vcpu.configure(&vm_mem, GuestAddress(arch::get_kernel_start()));
+        let err = vcpu.configure(
+            &vm_mem,
+            GuestAddress(arch::get_kernel_start()),
+            &vcpu_config,
+        );
         assert!(err.is_err());
         assert_eq!(
             err.err().unwrap().to_string(),
@@ -249,6 +401,65 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_validate_cpu_template() {
+        assert_eq!(
+            validate_cpu_template(CpuFeaturesTemplate::T2)
+                .unwrap_err()
+                .to_string(),
+            "The T2 CPU template is not supported on aarch64".to_string()
+        );
+
+        // V1N1 is rejected exactly when the host kernel does not let the ID registers be
+        // changed, and the fields it lowers are all writable otherwise.
+        let kvm = Kvm::new().unwrap();
+        let vm_fd = kvm.create_vm().unwrap();
+        let masks = arch::aarch64::regs::get_id_register_writable_masks(&vm_fd).unwrap();
+        let res = validate_cpu_template(CpuFeaturesTemplate::V1N1);
+        if masks.is_some() {
+            res.unwrap();
+        } else {
+            assert_eq!(
+                res.unwrap_err().to_string(),
+                "The V1N1 CPU template is not supported on this host: KVM does not allow the ID \
+                 registers to be changed"
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_cpu_template() {
+        let (_vm, vcpu, _) = setup_vcpu(0x10000);
+        let isar0 = vcpu.fd.get_one_reg(ID_AA64ISAR0_EL1).unwrap();
+        let isar1 = vcpu.fd.get_one_reg(ID_AA64ISAR1_EL1).unwrap();
+
+        // The probe fails: nothing is written.
+        assert!(matches!(
+            apply_cpu_template(&vcpu.fd, None, CpuFeaturesTemplate::V1N1),
+            Err(Error::CpuTemplateUnsupportedOnHost(
+                CpuFeaturesTemplate::V1N1
+            ))
+        ));
+        assert_eq!(vcpu.fd.get_one_reg(ID_AA64ISAR0_EL1).unwrap(), isar0);
+        assert_eq!(vcpu.fd.get_one_reg(ID_AA64ISAR1_EL1).unwrap(), isar1);
+
+        // Templates for other architectures are rejected before probing.
+        assert!(matches!(
+            apply_cpu_template(&vcpu.fd, None, CpuFeaturesTemplate::T2),
+            Err(Error::UnsupportedCpuTemplate(CpuFeaturesTemplate::T2))
+        ));
+
+        // The probe succeeds: the lowered fields read back at the template levels. The
+        // ID registers of a host newer than a Neoverse N1 stand in for the vcpu ones.
+        let vcpu = IdRegisters(RefCell::new([0x2222_2222_2222_2222; 2]));
+        let masks = vec![!0; arch::aarch64::regs::ID_REGISTER_MASKS_LEN];
+        apply_cpu_template(&vcpu, Some(masks.as_slice()), CpuFeaturesTemplate::V1N1).unwrap();
+        for field in arch::aarch64::regs::V1N1_ID_REGISTER_FIELDS.iter() {
+            let value = vcpu.get_one_reg(field.reg).unwrap();
+            assert_eq!((value >> field.shift) & 0xf, field.max);
+        }
+    }
+
     #[test]
     fn test_faulty_init_vcpu() {
         let (vm, vcpu, _) = setup_vcpu(0x10000);
@@ -16,13 +16,21 @@ use std::{
     io, result,
     sync::atomic::{fence, Ordering},
     sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    sync::Arc,
     thread,
+    time::Instant,
 };
 
 use crate::{
-    vmm_config::machine_config::CpuFeaturesTemplate, vstate::vm::Vm, FC_EXIT_CODE_GENERIC_ERROR,
-    FC_EXIT_CODE_OK,
+    vmm_config::{
+        cpu_config::CustomCpuTemplate,
+        machine_config::{CpuFeaturesTemplate, ThreadSchedPolicy},
+    },
+    vstate::vm::Vm,
+    FC_EXIT_CODE_GENERIC_ERROR, FC_EXIT_CODE_OK,
 };
+#[cfg(feature = "gdb")]
+use kvm_bindings::{kvm_regs, kvm_sregs};
 use kvm_bindings::{KVM_SYSTEM_EVENT_RESET, KVM_SYSTEM_EVENT_SHUTDOWN};
 use kvm_ioctls::VcpuExit;
 use logger::{error, info, IncMetric, METRICS};
@@ -36,6 +44,7 @@ use utils::{
 
 #[cfg(target_arch = "aarch64")]
 pub(crate) mod aarch64;
+mod stats;
 #[cfg(target_arch = "x86_64")]
 pub(crate) mod x86_64;
 
@@ -45,6 +54,8 @@ pub(crate) use aarch64::{Error as VcpuError, *};
 #[cfg(target_arch = "x86_64")]
 pub(crate) use x86_64::{Error as VcpuError, *};
 
+use self::stats::VcpuStatsCounters;
+
 /// Signal number (SIGRTMIN) used to kick Vcpus.
 pub(crate) const VCPU_RTSIG_OFFSET: i32 = 0;
 
@@ -61,6 +72,8 @@ pub enum Error {
     VcpuResponse(VcpuError),
     /// Cannot spawn a new vCPU thread.
     VcpuSpawn(io::Error),
+    /// Cannot set the affinity or scheduling policy of the vCPU thread.
+    VcpuThreadConfig(errno::Error),
     /// Cannot cleanly initialize vcpu TLS.
     VcpuTlsInit,
     /// Vcpu not present in TLS.
@@ -77,6 +90,7 @@ impl Display for Error {
             UnhandledKvmExit(ref e) => write!(f, "Unexpected kvm exit received: {}", e),
             VcpuResponse(e) => write!(f, "Failed to run action on vcpu: {}", e),
             VcpuSpawn(e) => write!(f, "Cannot spawn a new vCPU thread: {}", e),
+            VcpuThreadConfig(e) => write!(f, "Cannot configure the vCPU thread: {}", e),
             VcpuTlsInit => write!(f, "Cannot clean init vcpu TLS"),
             VcpuTlsNotPresent => write!(f, "Vcpu not present in TLS"),
         }
@@ -94,6 +108,8 @@ pub struct VcpuConfig {
     pub ht_enabled: bool,
     /// CPUID template to use.
     pub cpu_template: Option<CpuFeaturesTemplate>,
+    /// User-defined CPU template, applied on top of `cpu_template`.
+    pub custom_cpu_template: Option<CustomCpuTemplate>,
 }
 
 // Using this for easier explicit type-casting to help IDEs interpret the code.
@@ -114,6 +130,21 @@ pub struct Vcpu {
     response_receiver: Option<Receiver<VcpuResponse>>,
     // The transmitting end of the responses channel owned by the vcpu side.
     response_sender: Sender<VcpuResponse>,
+    // Exit counters and run time, shared with the handler.
+    stats: Arc<VcpuStatsCounters>,
+    // The host CPUs on which the vcpu thread runs, if restricted.
+    host_cpus: Option<Vec<usize>>,
+    // The scheduling policy of the vcpu thread, if not inherited from the VMM thread.
+    sched_policy: Option<ThreadSchedPolicy>,
+    // The transmitting end of the channel reporting to the GDB stub, if a debugger is enabled.
+    #[cfg(feature = "gdb")]
+    debug_sender: Option<Sender<DebugMessage>>,
+    // Whether the debugger keeps the vcpu paused, regardless of the VMM resuming it.
+    #[cfg(feature = "gdb")]
+    debug_hold: bool,
+    // Whether the VMM paused the vcpu, regardless of the debugger resuming it.
+    #[cfg(feature = "gdb")]
+    vmm_paused: bool,
 
     // Exit reason used to test run_emulation function.
     #[cfg(test)]
@@ -223,6 +254,15 @@ impl Vcpu {
             response_receiver: Some(response_receiver),
             response_sender,
             kvm_vcpu,
+            stats: Arc::new(VcpuStatsCounters::default()),
+            host_cpus: None,
+            sched_policy: None,
+            #[cfg(feature = "gdb")]
+            debug_sender: None,
+            #[cfg(feature = "gdb")]
+            debug_hold: false,
+            #[cfg(feature = "gdb")]
+            vmm_paused: true,
             #[cfg(test)]
             vcpu_exit_reason: Mutex::new(None),
         })
@@ -230,17 +270,66 @@ impl Vcpu {
 
     /// Sets a MMIO bus for this vcpu.
     pub fn set_mmio_bus(&mut self, mmio_bus: devices::Bus) {
+        self.stats = Arc::new(VcpuStatsCounters::new(&mmio_bus));
         self.kvm_vcpu.mmio_bus = Some(mmio_bus);
     }
 
+    /// Reports the debug events of this vcpu to `debug_sender`.
+    ///
+    /// The vcpu is held paused until the debugger resumes it, so that the guest can be
+    /// debugged from its first instruction.
+    #[cfg(feature = "gdb")]
+    pub fn set_debug_sender(&mut self, debug_sender: Sender<DebugMessage>) {
+        self.debug_sender = Some(debug_sender);
+        self.debug_hold = true;
+    }
+
+    /// Sets the host CPUs and the scheduling policy of the vcpu thread, applied when the thread
+    /// starts.
+    pub fn set_thread_config(
+        &mut self,
+        host_cpus: Option<Vec<usize>>,
+        sched_policy: Option<ThreadSchedPolicy>,
+    ) {
+        self.host_cpus = host_cpus;
+        self.sched_policy = sched_policy;
+    }
+
+    // Places the calling thread on the host CPUs of the vcpu and sets its scheduling policy.
+    fn configure_thread(&self) -> result::Result<(), errno::Error> {
+        if let Some(host_cpus) = &self.host_cpus {
+            utils::sched::set_current_thread_affinity(host_cpus)?;
+        }
+        match self.sched_policy {
+            Some(ThreadSchedPolicy::Other { nice }) => utils::sched::set_current_thread_nice(nice),
+            Some(ThreadSchedPolicy::Fifo { priority }) => {
+                utils::sched::set_current_thread_fifo_priority(priority)
+            }
+            None => Ok(()),
+        }
+    }
+
     /// Moves the vcpu to its own thread and constructs a VcpuHandle.
     /// The handle can be used to control the remote vcpu.
     pub fn start_threaded(mut self, seccomp_filter: BpfProgram) -> Result<VcpuHandle> {
         let event_sender = self.event_sender.take().expect("vCPU already started");
         let response_receiver = self.response_receiver.take().unwrap();
+        let stats = self.stats.clone();
+        let (thread_config_sender, thread_config_receiver) = channel();
         let vcpu_thread = thread::Builder::new()
             .name(format!("fc_vcpu {}", self.kvm_vcpu.index))
             .spawn(move || {
+                // The thread is configured before it loads its seccomp filters, so that it can
+                // still exit cleanly when the configuration fails.
+                let thread_config = self.configure_thread();
+                let configured = thread_config.is_ok();
+                thread_config_sender
+                    .send(thread_config)
+                    .expect("vcpu channel unexpectedly closed");
+                if !configured {
+                    return;
+                }
+
                 // We don't need to install the signal block mask on VCPU threads since they inherit
                 // the one from the VMM thread.
                 self.init_thread_local_data()
@@ -250,9 +339,17 @@ impl Vcpu {
             })
             .map_err(Error::VcpuSpawn)?;
 
+        // The vcpu only enters `KVM_RUN` once it runs on its host CPUs with its scheduling
+        // policy.
+        thread_config_receiver
+            .recv()
+            .expect("vcpu channel unexpectedly closed")
+            .map_err(Error::VcpuThreadConfig)?;
+
         Ok(VcpuHandle::new(
             event_sender,
             response_receiver,
+            stats,
             vcpu_thread,
         ))
     }
@@ -295,6 +392,13 @@ impl Vcpu {
                 // seccomp failure because musl calls `sigprocmask` as part of `pthread_exit`.
                 // So we pause vCPU0 and send a signal to the emulation thread to stop the VMM.
                 Ok(VcpuEmulation::Stopped) => return self.exit(FC_EXIT_CODE_OK),
+                // The guest hit a breakpoint or completed a single step, let the debugger know.
+                #[cfg(feature = "gdb")]
+                Ok(VcpuEmulation::DebugStopped) => {
+                    self.debug_hold = true;
+                    self.send_debug_response(None, DebugResponse::Stopped);
+                    return StateMachine::next(Self::paused);
+                }
                 // Emulation errors lead to vCPU exit.
                 Err(_) => return self.exit(FC_EXIT_CODE_GENERIC_ERROR),
             }
@@ -307,7 +411,10 @@ impl Vcpu {
         match self.event_receiver.try_recv() {
             // Running ---- Pause ----> Paused
             Ok(VcpuEvent::Pause) => {
-                // Nothing special to do.
+                #[cfg(feature = "gdb")]
+                {
+                    self.vmm_paused = true;
+                }
                 self.response_sender
                     .send(VcpuResponse::Paused)
                     .expect("failed to send pause status");
@@ -318,6 +425,12 @@ impl Vcpu {
                 // Move to 'paused' state.
                 state = StateMachine::next(Self::paused);
             }
+            // Running ---- Park ----> Paused, if the guest took the vcpu offline.
+            Ok(VcpuEvent::Park) => {
+                if self.park() {
+                    state = StateMachine::next(Self::paused);
+                }
+            }
             Ok(VcpuEvent::Resume) => {
                 self.response_sender
                     .send(VcpuResponse::Resumed)
@@ -332,6 +445,8 @@ impl Vcpu {
                     .expect("failed to send save not allowed status");
             }
             Ok(VcpuEvent::Exit) => return self.exit(FC_EXIT_CODE_GENERIC_ERROR),
+            #[cfg(feature = "gdb")]
+            Ok(VcpuEvent::Debug(seq, request)) => state = self.handle_debug_request(seq, request),
             // Unhandled exit of the other end.
             Err(TryRecvError::Disconnected) => {
                 // Move to 'exited' state.
@@ -349,19 +464,34 @@ impl Vcpu {
         match self.event_receiver.recv() {
             // Paused ---- Resume ----> Running
             Ok(VcpuEvent::Resume) => {
-                // Nothing special to do.
                 self.response_sender
                     .send(VcpuResponse::Resumed)
                     .expect("vcpu channel unexpectedly closed");
+                // A vcpu held by the debugger only runs once the debugger resumes it.
+                #[cfg(feature = "gdb")]
+                {
+                    self.vmm_paused = false;
+                    if self.debug_hold {
+                        return StateMachine::next(Self::paused);
+                    }
+                }
                 // Move to 'running' state.
                 StateMachine::next(Self::running)
             }
             Ok(VcpuEvent::Pause) => {
+                #[cfg(feature = "gdb")]
+                {
+                    self.vmm_paused = true;
+                }
                 self.response_sender
                     .send(VcpuResponse::Paused)
                     .expect("vcpu channel unexpectedly closed");
                 StateMachine::next(Self::paused)
             }
+            Ok(VcpuEvent::Park) => {
+                self.park();
+                StateMachine::next(Self::paused)
+            }
             Ok(VcpuEvent::SaveState) => {
                 // Save vcpu state.
                 self.kvm_vcpu
@@ -396,6 +526,8 @@ impl Vcpu {
                 StateMachine::next(Self::paused)
             }
             Ok(VcpuEvent::Exit) => self.exit(FC_EXIT_CODE_GENERIC_ERROR),
+            #[cfg(feature = "gdb")]
+            Ok(VcpuEvent::Debug(seq, request)) => self.handle_debug_request(seq, request),
             // Unhandled exit of the other end.
             Err(_) => {
                 // Move to 'exited' state.
@@ -404,6 +536,78 @@ impl Vcpu {
         }
     }
 
+    // Answers a park request, returning whether the vcpu can be parked. Parking a vcpu which is
+    // online in the guest would stall the other vcpus, e.g. when they wait for it to answer an
+    // inter-processor interrupt.
+    fn park(&mut self) -> bool {
+        let response = match self.kvm_vcpu.is_offline() {
+            Ok(true) => VcpuResponse::Paused,
+            Ok(false) => VcpuResponse::NotAllowed(String::from("vcpu is online in the guest")),
+            Err(e) => VcpuResponse::Error(Error::VcpuResponse(e)),
+        };
+        let parked = matches!(response, VcpuResponse::Paused);
+        #[cfg(feature = "gdb")]
+        {
+            self.vmm_paused |= parked;
+        }
+        self.response_sender
+            .send(response)
+            .expect("vcpu channel unexpectedly closed");
+        parked
+    }
+
+    // Carries out a request of the GDB stub, and moves to the state expected by both the VMM
+    // and the debugger.
+    #[cfg(feature = "gdb")]
+    fn handle_debug_request(&mut self, seq: u64, request: DebugRequest) -> StateMachine<Self> {
+        let response = match request {
+            DebugRequest::Pause => {
+                self.debug_hold = true;
+                Ok(DebugResponse::Paused)
+            }
+            DebugRequest::Resume {
+                single_step,
+                sw_breakpoints,
+            } => self
+                .kvm_vcpu
+                .set_guest_debug(single_step, sw_breakpoints)
+                .map(|()| {
+                    self.debug_hold = false;
+                    DebugResponse::Resumed
+                }),
+            DebugRequest::ReadRegs => self
+                .kvm_vcpu
+                .get_core_regs()
+                .map(|(regs, sregs)| DebugResponse::Regs(Box::new(regs), Box::new(sregs))),
+            DebugRequest::WriteRegs(regs) => self
+                .kvm_vcpu
+                .set_core_regs(&regs)
+                .map(|()| DebugResponse::RegsWritten),
+            DebugRequest::TranslateGva(gva) => self
+                .kvm_vcpu
+                .translate_gva(gva)
+                .map(DebugResponse::Translated),
+        };
+        self.send_debug_response(
+            Some(seq),
+            response.unwrap_or_else(|e| DebugResponse::Error(Error::VcpuResponse(e))),
+        );
+
+        if self.debug_hold || self.vmm_paused {
+            StateMachine::next(Self::paused)
+        } else {
+            StateMachine::next(Self::running)
+        }
+    }
+
+    #[cfg(feature = "gdb")]
+    fn send_debug_response(&self, seq: Option<u64>, response: DebugResponse) {
+        if let Some(debug_sender) = &self.debug_sender {
+            // The stub only goes away along with the whole process.
+            let _ = debug_sender.send((self.kvm_vcpu.index, seq, response));
+        }
+    }
+
     #[cfg(not(test))]
     // Transition to the exited state.
     fn exit(&mut self, exit_code: u8) -> StateMachine<Self> {
@@ -450,7 +654,23 @@ impl Vcpu {
     ///
     /// Returns error or enum specifying whether emulation was handled or interrupted.
     pub fn run_emulation(&self) -> Result<VcpuEmulation> {
-        match self.emulate() {
+        let run_start = Instant::now();
+        let exit = self.emulate();
+        let exit_start = Instant::now();
+        self.stats.record_exit(&exit);
+
+        let emulation = self.handle_exit(exit);
+        self.stats
+            .add_run_time(exit_start - run_start, exit_start.elapsed());
+        emulation
+    }
+
+    // Handles the outcome of a `KVM_RUN`.
+    fn handle_exit(
+        &self,
+        exit: std::result::Result<VcpuExit, errno::Error>,
+    ) -> Result<VcpuEmulation> {
+        match exit {
             Ok(run) => match run {
                 VcpuExit::MmioRead(addr, data) => {
                     if let Some(mmio_bus) = &self.kvm_vcpu.mmio_bus {
@@ -474,6 +694,8 @@ impl Vcpu {
                     info!("Received KVM_EXIT_SHUTDOWN signal");
                     Ok(VcpuEmulation::Stopped)
                 }
+                #[cfg(feature = "gdb")]
+                VcpuExit::Debug => Ok(VcpuEmulation::DebugStopped),
                 // Documentation specifies that below kvm exits are considered
                 // errors.
                 VcpuExit::FailEntry => {
@@ -560,14 +782,66 @@ pub enum VcpuEvent {
     Exit,
     /// Pause the Vcpu.
     Pause,
+    /// Pause the Vcpu, provided that the guest took it offline.
+    Park,
     /// Event to resume the Vcpu.
     Resume,
     /// Event to restore the state of a paused Vcpu.
     RestoreState(Box<VcpuState>),
     /// Event to save the state of a paused Vcpu.
     SaveState,
+    /// Request of the GDB stub, answered on the debug channel of the Vcpu along with the
+    /// sequence number of the request.
+    #[cfg(feature = "gdb")]
+    Debug(u64, DebugRequest),
+}
+
+/// List of requests that the GDB stub sends to a Vcpu.
+#[cfg(feature = "gdb")]
+#[derive(Clone)]
+pub enum DebugRequest {
+    /// Hold the Vcpu paused until the debugger resumes it.
+    Pause,
+    /// Let the Vcpu run, unless the VMM paused it.
+    Resume {
+        /// Stop after executing a single instruction.
+        single_step: bool,
+        /// Stop on `int3` instructions.
+        sw_breakpoints: bool,
+    },
+    /// Read the general purpose and segment registers.
+    ReadRegs,
+    /// Write the general purpose registers.
+    WriteRegs(kvm_regs),
+    /// Translate a guest virtual address with the page tables of the Vcpu.
+    TranslateGva(u64),
+}
+
+/// List of responses and events that a Vcpu reports to the GDB stub.
+#[cfg(feature = "gdb")]
+pub enum DebugResponse {
+    /// Requested action encountered an error.
+    Error(Error),
+    /// Vcpu is held paused by the debugger.
+    Paused,
+    /// Registers of the Vcpu.
+    Regs(Box<kvm_regs>, Box<kvm_sregs>),
+    /// Registers of the Vcpu are written.
+    RegsWritten,
+    /// Vcpu is released by the debugger.
+    Resumed,
+    /// Vcpu stopped on a breakpoint or after a single step.
+    Stopped,
+    /// Guest physical address, if the guest virtual address is mapped.
+    Translated(Option<u64>),
 }
 
+/// A message of a Vcpu to the GDB stub: the index of the Vcpu, the sequence number of the
+/// request answered, or `None` for the events that the Vcpu reports on its own, and the
+/// response itself.
+#[cfg(feature = "gdb")]
+pub type DebugMessage = (u8, Option<u64>, DebugResponse);
+
 /// List of responses that the Vcpu reports.
 pub enum VcpuResponse {
     /// Requested action encountered an error.
@@ -590,6 +864,7 @@ pub enum VcpuResponse {
 pub struct VcpuHandle {
     event_sender: Sender<VcpuEvent>,
     response_receiver: Receiver<VcpuResponse>,
+    stats: Arc<VcpuStatsCounters>,
     // Rust JoinHandles have to be wrapped in Option if you ever plan on 'join()'ing them.
     // We want to be able to join these threads in tests.
     vcpu_thread: Option<thread::JoinHandle<()>>,
@@ -599,11 +874,13 @@ impl VcpuHandle {
     pub fn new(
         event_sender: Sender<VcpuEvent>,
         response_receiver: Receiver<VcpuResponse>,
+        stats: Arc<VcpuStatsCounters>,
         vcpu_thread: thread::JoinHandle<()>,
     ) -> Self {
         Self {
             event_sender,
             response_receiver,
+            stats,
             vcpu_thread: Some(vcpu_thread),
         }
     }
@@ -626,6 +903,50 @@ impl VcpuHandle {
     pub fn response_receiver(&self) -> &Receiver<VcpuResponse> {
         &self.response_receiver
     }
+
+    /// Returns the exit counters and run time of the Vcpu.
+    pub fn stats(&self) -> &VcpuStatsCounters {
+        &self.stats
+    }
+
+    /// Returns a handle through which the GDB stub sends requests to the Vcpu.
+    #[cfg(feature = "gdb")]
+    pub fn debug_handle(&self) -> DebugHandle {
+        DebugHandle {
+            event_sender: self.event_sender.clone(),
+            // Safe to unwrap since constructor make this 'Some'.
+            vcpu_thread: self.vcpu_thread.as_ref().unwrap().pthread_handle(),
+        }
+    }
+}
+
+/// Sends requests to a Vcpu from the thread of the GDB stub.
+#[cfg(feature = "gdb")]
+pub struct DebugHandle {
+    event_sender: Sender<VcpuEvent>,
+    vcpu_thread: libc::pthread_t,
+}
+
+#[cfg(feature = "gdb")]
+impl DebugHandle {
+    /// Sends `request` to the Vcpu, which answers on its debug channel along with `seq`.
+    pub fn send_request(&self, seq: u64, request: DebugRequest) -> Result<()> {
+        self.event_sender
+            .send(VcpuEvent::Debug(seq, request))
+            .expect("event sender channel closed on vcpu end.");
+        // Kick the vcpu so it picks up the message.
+        self.kill(sigrtmin() + VCPU_RTSIG_OFFSET)
+            .map_err(Error::SignalVcpu)
+    }
+}
+
+// Safe because Vcpu threads are never joined outside of tests, so the handle stays valid for
+// the lifetime of the process.
+#[cfg(feature = "gdb")]
+unsafe impl Killable for DebugHandle {
+    fn pthread_handle(&self) -> libc::pthread_t {
+        self.vcpu_thread
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -633,6 +954,8 @@ pub enum VcpuEmulation {
     Handled,
     Interrupted,
     Stopped,
+    #[cfg(feature = "gdb")]
+    DebugStopped,
 }
 
 #[cfg(test)]
@@ -786,6 +1109,14 @@ mod tests {
         let res = vcpu.run_emulation();
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), VcpuEmulation::Handled);
+
+        // The exits are counted for the device they target.
+        let stats = vcpu.stats.read(0);
+        assert_eq!(stats.exits.mmio_read, 1);
+        assert_eq!(stats.exits.mmio_write, 1);
+        assert_eq!(stats.mmio_devices.len(), 1);
+        assert_eq!(stats.mmio_devices[0].reads, 1);
+        assert_eq!(stats.mmio_devices[0].writes, 1);
     }
 
     impl PartialEq for VcpuResponse {
@@ -876,7 +1207,9 @@ mod tests {
 
         let mut kernel_file = File::open(kernel_path).expect("Cannot open kernel file");
 
-        kernel::loader::load_kernel(vm_memory, &mut kernel_file, 0).expect("Failed to load kernel")
+        kernel::loader::load_kernel(vm_memory, &mut kernel_file, 0)
+            .expect("Failed to load kernel")
+            .entry_addr
     }
 
     fn vcpu_configured_for_boot() -> (VcpuHandle, utils::eventfd::EventFd) {
@@ -890,21 +1223,23 @@ mod tests {
         // Needs a kernel since we'll actually run this vcpu.
         let entry_addr = load_good_kernel(&vm_mem);
 
+        let vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            ht_enabled: false,
+            cpu_template: None,
+            custom_cpu_template: None,
+        };
         #[cfg(target_arch = "aarch64")]
         vcpu.kvm_vcpu
-            .configure(&vm_mem, entry_addr)
+            .configure(&vm_mem, entry_addr, &vcpu_config)
             .expect("failed to configure vcpu");
         #[cfg(target_arch = "x86_64")]
         {
-            let vcpu_config = VcpuConfig {
-                vcpu_count: 1,
-                ht_enabled: false,
-                cpu_template: None,
-            };
             vcpu.kvm_vcpu
                 .configure(
                     &vm_mem,
                     entry_addr,
+                    arch::x86_64::BootProtocol::Linux,
                     &vcpu_config,
                     _vm.supported_cpuid().clone(),
                 )
@@ -927,6 +1262,24 @@ mod tests {
         assert!(vcpu.kvm_vcpu.mmio_bus.is_some());
     }
 
+    #[test]
+    fn test_start_threaded_thread_config() {
+        // The thread of the vcpu cannot be restricted to an empty set of host CPUs.
+        let (_vm, mut vcpu, _) = setup_vcpu(0x1000);
+        vcpu.set_thread_config(Some(vec![]), None);
+        let seccomp_filter = seccomp::SeccompFilter::empty().try_into().unwrap();
+        match vcpu.start_threaded(seccomp_filter) {
+            Err(Error::VcpuThreadConfig(e)) => assert_eq!(e.errno(), libc::EINVAL),
+            _ => panic!("Expected a thread configuration error."),
+        }
+
+        // Lowering the priority of the thread is always allowed.
+        let (_vm, mut vcpu, _) = setup_vcpu(0x1000);
+        vcpu.set_thread_config(None, Some(ThreadSchedPolicy::Other { nice: 19 }));
+        let seccomp_filter = seccomp::SeccompFilter::empty().try_into().unwrap();
+        assert!(vcpu.start_threaded(seccomp_filter).is_ok());
+    }
+
     #[test]
     fn test_vcpu_tls() {
         let (_, mut vcpu, _) = setup_vcpu(0x1000);
@@ -1053,6 +1406,26 @@ mod tests {
         queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
     }
 
+    #[test]
+    fn test_vcpu_park() {
+        let (vcpu_handle, _vcpu_exit_evt) = vcpu_configured_for_boot();
+
+        // The boot vcpu is online, so it can't be parked, neither while running nor paused.
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::Park,
+            VcpuResponse::NotAllowed(String::new()),
+        );
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Pause, VcpuResponse::Paused);
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::Park,
+            VcpuResponse::NotAllowed(String::new()),
+        );
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+    }
+
     #[test]
     fn test_vcpu_save_restore_state_events() {
         let (vcpu_handle, _vcpu_exit_evt) = vcpu_configured_for_boot();
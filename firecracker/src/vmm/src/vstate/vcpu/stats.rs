@@ -0,0 +1,228 @@
+// Copyright 2021 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::vmm_config::vcpu_stats::{MmioDeviceExitCounts, VcpuExitCounts, VcpuStats};
+use kvm_ioctls::VcpuExit;
+use utils::errno;
+
+// The counters are only written by the vCPU thread and read by the VMM thread, so relaxed
+// atomics are enough: readers only need an eventually consistent view.
+fn inc(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+fn load(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+#[derive(Default)]
+struct ExitCounters {
+    io_in: AtomicU64,
+    io_out: AtomicU64,
+    mmio_read: AtomicU64,
+    mmio_write: AtomicU64,
+    hlt: AtomicU64,
+    shutdown: AtomicU64,
+    system_event: AtomicU64,
+    fail_entry: AtomicU64,
+    internal_error: AtomicU64,
+    interrupted: AtomicU64,
+    other: AtomicU64,
+}
+
+struct MmioDeviceCounters {
+    len: u64,
+    reads: AtomicU64,
+    writes: AtomicU64,
+}
+
+/// Counts the KVM exits of a vCPU and the time spent in and out of `KVM_RUN`.
+#[derive(Default)]
+pub struct VcpuStatsCounters {
+    exits: ExitCounters,
+    // Counters of the MMIO devices, by base address.
+    mmio_devices: BTreeMap<u64, MmioDeviceCounters>,
+    kvm_run_ns: AtomicU64,
+    vmm_ns: AtomicU64,
+}
+
+impl VcpuStatsCounters {
+    /// Creates counters for the devices of `mmio_bus`.
+    pub fn new(mmio_bus: &devices::Bus) -> Self {
+        VcpuStatsCounters {
+            mmio_devices: mmio_bus
+                .ranges()
+                .map(|(base, len)| {
+                    (
+                        base,
+                        MmioDeviceCounters {
+                            len,
+                            reads: AtomicU64::new(0),
+                            writes: AtomicU64::new(0),
+                        },
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn mmio_device(&self, addr: u64) -> Option<&MmioDeviceCounters> {
+        self.mmio_devices
+            .range(..=addr)
+            .next_back()
+            .filter(|(base, device)| addr - *base < device.len)
+            .map(|(_, device)| device)
+    }
+
+    /// Counts the outcome of a `KVM_RUN`.
+    pub fn record_exit(&self, exit: &std::result::Result<VcpuExit, errno::Error>) {
+        let exits = &self.exits;
+        match exit {
+            Ok(VcpuExit::IoIn(_, _)) => inc(&exits.io_in),
+            Ok(VcpuExit::IoOut(_, _)) => inc(&exits.io_out),
+            Ok(VcpuExit::MmioRead(addr, _)) => {
+                inc(&exits.mmio_read);
+                if let Some(device) = self.mmio_device(*addr) {
+                    inc(&device.reads);
+                }
+            }
+            Ok(VcpuExit::MmioWrite(addr, _)) => {
+                inc(&exits.mmio_write);
+                if let Some(device) = self.mmio_device(*addr) {
+                    inc(&device.writes);
+                }
+            }
+            Ok(VcpuExit::Hlt) => inc(&exits.hlt),
+            Ok(VcpuExit::Shutdown) => inc(&exits.shutdown),
+            Ok(VcpuExit::SystemEvent(_, _)) => inc(&exits.system_event),
+            Ok(VcpuExit::FailEntry) => inc(&exits.fail_entry),
+            Ok(VcpuExit::InternalError) => inc(&exits.internal_error),
+            Err(e) if e.errno() == libc::EINTR || e.errno() == libc::EAGAIN => {
+                inc(&exits.interrupted)
+            }
+            _ => inc(&exits.other),
+        }
+    }
+
+    /// Accounts the time spent in `KVM_RUN` and handling the exit it returned.
+    pub fn add_run_time(&self, kvm_run: Duration, vmm: Duration) {
+        self.kvm_run_ns
+            .fetch_add(kvm_run.as_nanos() as u64, Ordering::Relaxed);
+        self.vmm_ns
+            .fetch_add(vmm.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the current values of the counters.
+    pub fn read(&self, vcpu_index: u8) -> VcpuStats {
+        let exits = &self.exits;
+        VcpuStats {
+            vcpu_index,
+            exits: VcpuExitCounts {
+                io_in: load(&exits.io_in),
+                io_out: load(&exits.io_out),
+                mmio_read: load(&exits.mmio_read),
+                mmio_write: load(&exits.mmio_write),
+                hlt: load(&exits.hlt),
+                shutdown: load(&exits.shutdown),
+                system_event: load(&exits.system_event),
+                fail_entry: load(&exits.fail_entry),
+                internal_error: load(&exits.internal_error),
+                interrupted: load(&exits.interrupted),
+                other: load(&exits.other),
+            },
+            mmio_devices: self
+                .mmio_devices
+                .iter()
+                .map(|(base, device)| MmioDeviceExitCounts {
+                    base: *base,
+                    len: device.len,
+                    reads: load(&device.reads),
+                    writes: load(&device.writes),
+                })
+                .collect(),
+            kvm_run_us: load(&self.kvm_run_ns) / 1000,
+            vmm_us: load(&self.vmm_ns) / 1000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct DummyDevice;
+    impl devices::BusDevice for DummyDevice {}
+
+    #[test]
+    fn test_record_exit() {
+        let mut bus = devices::Bus::new();
+        let device = Arc::new(Mutex::new(DummyDevice));
+        bus.insert(device.clone(), 0x1000, 0x1000).unwrap();
+        bus.insert(device, 0x3000, 0x100).unwrap();
+        let counters = VcpuStatsCounters::new(&bus);
+
+        let mut data = [0u8; 4];
+        counters.record_exit(&Ok(VcpuExit::MmioRead(0x1000, &mut data)));
+        counters.record_exit(&Ok(VcpuExit::MmioRead(0x1fff, &mut data)));
+        counters.record_exit(&Ok(VcpuExit::MmioWrite(0x3010, &data)));
+        // Accesses outside of the device ranges are only counted by reason.
+        counters.record_exit(&Ok(VcpuExit::MmioWrite(0x2000, &data)));
+        counters.record_exit(&Ok(VcpuExit::MmioRead(0x100, &mut data)));
+        counters.record_exit(&Ok(VcpuExit::IoIn(0x3f8, &mut data)));
+        counters.record_exit(&Ok(VcpuExit::IoOut(0x3f8, &data)));
+        counters.record_exit(&Ok(VcpuExit::Hlt));
+        counters.record_exit(&Ok(VcpuExit::Shutdown));
+        counters.record_exit(&Ok(VcpuExit::SystemEvent(0, 0)));
+        counters.record_exit(&Ok(VcpuExit::FailEntry));
+        counters.record_exit(&Ok(VcpuExit::InternalError));
+        counters.record_exit(&Ok(VcpuExit::Intr));
+        counters.record_exit(&Err(errno::Error::new(libc::EINTR)));
+        counters.record_exit(&Err(errno::Error::new(libc::EAGAIN)));
+        counters.record_exit(&Err(errno::Error::new(libc::ENOSYS)));
+        counters.add_run_time(Duration::from_micros(30), Duration::from_nanos(2500));
+        counters.add_run_time(Duration::from_micros(10), Duration::from_nanos(500));
+
+        assert_eq!(
+            counters.read(2),
+            VcpuStats {
+                vcpu_index: 2,
+                exits: VcpuExitCounts {
+                    io_in: 1,
+                    io_out: 1,
+                    mmio_read: 3,
+                    mmio_write: 2,
+                    hlt: 1,
+                    shutdown: 1,
+                    system_event: 1,
+                    fail_entry: 1,
+                    internal_error: 1,
+                    interrupted: 2,
+                    other: 2,
+                },
+                mmio_devices: vec![
+                    MmioDeviceExitCounts {
+                        base: 0x1000,
+                        len: 0x1000,
+                        reads: 2,
+                        writes: 0,
+                    },
+                    MmioDeviceExitCounts {
+                        base: 0x3000,
+                        len: 0x100,
+                        reads: 0,
+                        writes: 1,
+                    },
+                ],
+                kvm_run_us: 40,
+                vmm_us: 3,
+            }
+        );
+    }
+}
@@ -10,15 +10,18 @@ use std::{
     result,
 };
 
+use crate::vmm_config::cpu_config::MsrModifier;
 use crate::vmm_config::machine_config::CpuFeaturesTemplate;
 use crate::vstate::{
     vcpu::{VcpuConfig, VcpuEmulation},
     vm::Vm,
 };
-use cpuid::{c3, filter_cpuid, t2, VmSpec};
+use arch::x86_64::BootProtocol;
+use cpuid::{c3, custom, filter_cpuid, t2, t2a, VmSpec};
 use kvm_bindings::{
-    kvm_debugregs, kvm_lapic_state, kvm_mp_state, kvm_regs, kvm_sregs, kvm_vcpu_events, kvm_xcrs,
-    kvm_xsave, CpuId, MsrList, Msrs,
+    kvm_debugregs, kvm_lapic_state, kvm_mp_state, kvm_msr_entry, kvm_regs, kvm_sregs,
+    kvm_vcpu_events, kvm_xcrs, kvm_xsave, CpuId, MsrList, Msrs, KVM_MP_STATE_HALTED,
+    KVM_MP_STATE_INIT_RECEIVED, KVM_MP_STATE_SIPI_RECEIVED, KVM_MP_STATE_UNINITIALIZED,
 };
 use kvm_ioctls::{VcpuExit, VcpuFd};
 use logger::{error, IncMetric, METRICS};
@@ -26,6 +29,25 @@ use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::{Address, GuestAddress, GuestMemoryMmap};
 
+#[cfg(feature = "gdb")]
+use kvm_bindings::{
+    kvm_guest_debug, kvm_translation, KVMIO, KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP,
+    KVM_GUESTDBG_USE_SW_BP,
+};
+#[cfg(feature = "gdb")]
+use utils::ioctl::{ioctl_with_mut_ref, ioctl_with_ref};
+#[cfg(feature = "gdb")]
+use utils::{ioctl_expr, ioctl_ioc_nr, ioctl_iow_nr, ioctl_iowr_nr};
+
+// Not wrapped by kvm-ioctls at the version we depend on.
+#[cfg(feature = "gdb")]
+ioctl_iow_nr!(KVM_SET_GUEST_DEBUG, KVMIO, 0x9b, kvm_guest_debug);
+#[cfg(feature = "gdb")]
+ioctl_iowr_nr!(KVM_TRANSLATE, KVMIO, 0x85, kvm_translation);
+
+// The interrupt enable flag of the RFLAGS register.
+const X86_EFLAGS_IF: u64 = 1 << 9;
+
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug)]
 pub enum Error {
@@ -43,6 +65,8 @@ pub enum Error {
     REGSConfiguration(arch::x86_64::regs::Error),
     /// Error configuring the special registers
     SREGSConfiguration(arch::x86_64::regs::Error),
+    /// The CPU template is meant for another architecture.
+    UnsupportedCpuTemplate(CpuFeaturesTemplate),
     /// Cannot open the VCPU file descriptor.
     VcpuFd(kvm_ioctls::Error),
     /// Failed to get KVM vcpu debug regs.
@@ -71,6 +95,9 @@ pub enum Error {
     VcpuSetCpuid(kvm_ioctls::Error),
     /// Failed to set KVM vcpu debug regs.
     VcpuSetDebugRegs(kvm_ioctls::Error),
+    /// Failed to set KVM vcpu guest debug flags.
+    #[cfg(feature = "gdb")]
+    VcpuSetGuestDebug(utils::errno::Error),
     /// Failed to set KVM vcpu lapic.
     VcpuSetLapic(kvm_ioctls::Error),
     /// Failed to set KVM vcpu mp state.
@@ -87,6 +114,9 @@ pub enum Error {
     VcpuSetXcrs(kvm_ioctls::Error),
     /// Failed to set KVM vcpu xsave.
     VcpuSetXsave(kvm_ioctls::Error),
+    /// Failed to translate a guest virtual address.
+    #[cfg(feature = "gdb")]
+    VcpuTranslate(utils::errno::Error),
 }
 
 impl Display for Error {
@@ -108,6 +138,11 @@ impl Display for Error {
                 e
             ),
             SREGSConfiguration(e) => write!(f, "Error configuring the special registers: {:?}", e),
+            UnsupportedCpuTemplate(template) => write!(
+                f,
+                "The {} CPU template is not supported on x86_64",
+                template
+            ),
             FamError(e) => write!(f, "Failed FamStructWrapper operation: {:?}", e),
             FPUConfiguration(e) => write!(
                 f,
@@ -127,6 +162,8 @@ impl Display for Error {
             VcpuGetCpuid(e) => write!(f, "Failed to get KVM vcpu cpuid: {}", e),
             VcpuSetCpuid(e) => write!(f, "Failed to set KVM vcpu cpuid: {}", e),
             VcpuSetDebugRegs(e) => write!(f, "Failed to set KVM vcpu debug regs: {}", e),
+            #[cfg(feature = "gdb")]
+            VcpuSetGuestDebug(e) => write!(f, "Failed to set KVM vcpu guest debug: {}", e),
             VcpuSetLapic(e) => write!(f, "Failed to set KVM vcpu lapic: {}", e),
             VcpuSetMpState(e) => write!(f, "Failed to set KVM vcpu mp state: {}", e),
             VcpuSetMsrs(e) => write!(f, "Failed to set KVM vcpu msrs: {}", e),
@@ -135,12 +172,61 @@ impl Display for Error {
             VcpuSetVcpuEvents(e) => write!(f, "Failed to set KVM vcpu event: {}", e),
             VcpuSetXcrs(e) => write!(f, "Failed to set KVM vcpu xcrs: {}", e),
             VcpuSetXsave(e) => write!(f, "Failed to set KVM vcpu xsave: {}", e),
+            #[cfg(feature = "gdb")]
+            VcpuTranslate(e) => write!(f, "Failed to translate guest virtual address: {}", e),
         }
     }
 }
 
 type Result<T> = result::Result<T, Error>;
 
+/// Turns the CPUID supported by the host into the CPUID exposed to a vCPU.
+///
+/// # Arguments
+///
+/// * `cpuid` - The CPUID supported by the host, updated in place.
+/// * `vcpu_index` - The index of the vCPU.
+/// * `vcpu_config` - The vCPU configuration.
+pub(crate) fn build_vcpu_cpuid(
+    cpuid: &mut CpuId,
+    vcpu_index: u8,
+    vcpu_config: &VcpuConfig,
+) -> Result<()> {
+    let cpuid_vm_spec = VmSpec::new(vcpu_index, vcpu_config.vcpu_count, vcpu_config.ht_enabled)
+        .map_err(Error::CpuId)?;
+
+    filter_cpuid(cpuid, &cpuid_vm_spec).map_err(|e| {
+        METRICS.vcpu.filter_cpuid.inc();
+        error!(
+            "Failure in configuring CPUID for vcpu {}: {:?}",
+            vcpu_index, e
+        );
+        Error::CpuId(e)
+    })?;
+
+    if let Some(template) = vcpu_config.cpu_template {
+        match template {
+            CpuFeaturesTemplate::T2 => {
+                t2::set_cpuid_entries(cpuid, &cpuid_vm_spec).map_err(Error::CpuId)?
+            }
+            CpuFeaturesTemplate::C3 => {
+                c3::set_cpuid_entries(cpuid, &cpuid_vm_spec).map_err(Error::CpuId)?
+            }
+            CpuFeaturesTemplate::T2A => {
+                t2a::set_cpuid_entries(cpuid, &cpuid_vm_spec).map_err(Error::CpuId)?
+            }
+            CpuFeaturesTemplate::V1N1 => return Err(Error::UnsupportedCpuTemplate(template)),
+        }
+    }
+
+    if let Some(template) = &vcpu_config.custom_cpu_template {
+        custom::set_cpuid_entries(cpuid, &template.cpuid_register_modifiers())
+            .map_err(Error::CpuId)?;
+    }
+
+    Ok(())
+}
+
 /// A wrapper around creating and using a kvm x86_64 vcpu.
 pub struct KvmVcpu {
     pub index: u8,
@@ -177,54 +263,108 @@ impl KvmVcpu {
     ///
     /// * `guest_mem` - The guest memory used by this microvm.
     /// * `kernel_start_addr` - Offset from `guest_mem` at which the kernel starts.
+    /// * `boot_protocol` - The protocol used to start the kernel.
     /// * `vcpu_config` - The vCPU configuration.
     /// * `cpuid` - The capabilities exposed by this vCPU.
     pub fn configure(
         &mut self,
         guest_mem: &GuestMemoryMmap,
         kernel_start_addr: GuestAddress,
+        boot_protocol: BootProtocol,
         vcpu_config: &VcpuConfig,
         mut cpuid: CpuId,
     ) -> Result<()> {
-        let cpuid_vm_spec = VmSpec::new(self.index, vcpu_config.vcpu_count, vcpu_config.ht_enabled)
-            .map_err(Error::CpuId)?;
-
-        filter_cpuid(&mut cpuid, &cpuid_vm_spec).map_err(|e| {
-            METRICS.vcpu.filter_cpuid.inc();
-            error!(
-                "Failure in configuring CPUID for vcpu {}: {:?}",
-                self.index, e
-            );
-            Error::CpuId(e)
-        })?;
-
-        if let Some(template) = vcpu_config.cpu_template {
-            match template {
-                CpuFeaturesTemplate::T2 => {
-                    t2::set_cpuid_entries(&mut cpuid, &cpuid_vm_spec).map_err(Error::CpuId)?
-                }
-                CpuFeaturesTemplate::C3 => {
-                    c3::set_cpuid_entries(&mut cpuid, &cpuid_vm_spec).map_err(Error::CpuId)?
-                }
-            }
-        }
+        build_vcpu_cpuid(&mut cpuid, self.index, vcpu_config)?;
 
         self.fd.set_cpuid2(&cpuid).map_err(Error::VcpuSetCpuid)?;
 
         arch::x86_64::msr::setup_msrs(&self.fd).map_err(Error::MSRSConfiguration)?;
-        arch::x86_64::regs::setup_regs(&self.fd, kernel_start_addr.raw_value() as u64)
-            .map_err(Error::REGSConfiguration)?;
+        if let Some(template) = &vcpu_config.custom_cpu_template {
+            self.apply_msr_modifiers(&template.msr_modifiers)?;
+        }
+        arch::x86_64::regs::setup_regs(
+            &self.fd,
+            kernel_start_addr.raw_value() as u64,
+            boot_protocol,
+        )
+        .map_err(Error::REGSConfiguration)?;
         arch::x86_64::regs::setup_fpu(&self.fd).map_err(Error::FPUConfiguration)?;
-        arch::x86_64::regs::setup_sregs(guest_mem, &self.fd).map_err(Error::SREGSConfiguration)?;
+        arch::x86_64::regs::setup_sregs(guest_mem, &self.fd, boot_protocol)
+            .map_err(Error::SREGSConfiguration)?;
         arch::x86_64::interrupts::set_lint(&self.fd).map_err(Error::LocalIntConfiguration)?;
         Ok(())
     }
 
+    /// Overrides the MSRs set up at boot time as described by `modifiers`.
+    fn apply_msr_modifiers(&self, modifiers: &[MsrModifier]) -> Result<()> {
+        if modifiers.is_empty() {
+            return Ok(());
+        }
+
+        let mut entries: Vec<kvm_msr_entry> = Vec::new();
+        for modifier in modifiers {
+            if !entries.iter().any(|entry| entry.index == modifier.addr) {
+                entries.push(kvm_msr_entry {
+                    index: modifier.addr,
+                    ..Default::default()
+                });
+            }
+        }
+        let mut msrs = Msrs::from_entries(&entries).map_err(Error::FamError)?;
+        let nmsrs = self.fd.get_msrs(&mut msrs).map_err(Error::VcpuGetMsrs)?;
+        if nmsrs != entries.len() {
+            return Err(Error::VcpuGetMSRSIncomplete);
+        }
+
+        // Modifiers of the same MSR are applied in order.
+        for modifier in modifiers {
+            if let Some(entry) = msrs
+                .as_mut_slice()
+                .iter_mut()
+                .find(|entry| entry.index == modifier.addr)
+            {
+                entry.data = modifier.apply(entry.data);
+            }
+        }
+
+        let nmsrs = self.fd.set_msrs(&msrs).map_err(Error::VcpuSetMsrs)?;
+        if nmsrs != entries.len() {
+            return Err(Error::MSRSConfiguration(
+                arch::x86_64::msr::Error::SetModelSpecificRegistersCount,
+            ));
+        }
+
+        Ok(())
+    }
+
     /// Sets a Port Mapped IO bus for this vcpu.
     pub fn set_pio_bus(&mut self, pio_bus: devices::Bus) {
         self.pio_bus = Some(pio_bus);
     }
 
+    /// Returns whether the guest took the vcpu offline.
+    ///
+    /// The vcpu is offline if it waits for an INIT or a startup IPI, or if it is halted with
+    /// interrupts disabled, which is how Linux parks offline cpus. An idle vcpu, on the other
+    /// hand, halts with interrupts enabled.
+    pub fn is_offline(&self) -> Result<bool> {
+        match self
+            .fd
+            .get_mp_state()
+            .map_err(Error::VcpuGetMpState)?
+            .mp_state
+        {
+            KVM_MP_STATE_UNINITIALIZED
+            | KVM_MP_STATE_INIT_RECEIVED
+            | KVM_MP_STATE_SIPI_RECEIVED => Ok(true),
+            KVM_MP_STATE_HALTED => {
+                let regs = self.fd.get_regs().map_err(Error::VcpuGetRegs)?;
+                Ok(regs.rflags & X86_EFLAGS_IF == 0)
+            }
+            _ => Ok(false),
+        }
+    }
+
     /// Save the KVM internal state.
     pub fn save_state(&self) -> Result<VcpuState> {
         /*
@@ -345,6 +485,67 @@ impl KvmVcpu {
         Ok(())
     }
 
+    /// Enables guest debugging, making KVM exit on `int3` instructions and/or after every
+    /// instruction.
+    #[cfg(feature = "gdb")]
+    pub fn set_guest_debug(&self, single_step: bool, sw_breakpoints: bool) -> Result<()> {
+        let mut debug = kvm_guest_debug {
+            control: KVM_GUESTDBG_ENABLE,
+            ..Default::default()
+        };
+        if single_step {
+            debug.control |= KVM_GUESTDBG_SINGLESTEP;
+        }
+        if sw_breakpoints {
+            debug.control |= KVM_GUESTDBG_USE_SW_BP;
+        }
+
+        // Safe because we know that our file is a vCPU fd, we know the kernel will only read the
+        // correct amount of memory from our pointer, and we verify the return result.
+        let ret = unsafe { ioctl_with_ref(&self.fd, KVM_SET_GUEST_DEBUG(), &debug) };
+        if ret < 0 {
+            return Err(Error::VcpuSetGuestDebug(utils::errno::Error::last()));
+        }
+        Ok(())
+    }
+
+    /// Returns the general purpose and segment registers.
+    #[cfg(feature = "gdb")]
+    pub fn get_core_regs(&self) -> Result<(kvm_regs, kvm_sregs)> {
+        let regs = self.fd.get_regs().map_err(Error::VcpuGetRegs)?;
+        let sregs = self.fd.get_sregs().map_err(Error::VcpuGetSregs)?;
+        Ok((regs, sregs))
+    }
+
+    /// Sets the general purpose registers.
+    #[cfg(feature = "gdb")]
+    pub fn set_core_regs(&self, regs: &kvm_regs) -> Result<()> {
+        self.fd.set_regs(regs).map_err(Error::VcpuSetRegs)
+    }
+
+    /// Translates a guest virtual address with the current page tables of the vCPU.
+    ///
+    /// Returns `None` if the address is not mapped.
+    #[cfg(feature = "gdb")]
+    pub fn translate_gva(&self, gva: u64) -> Result<Option<u64>> {
+        let mut translation = kvm_translation {
+            linear_address: gva,
+            ..Default::default()
+        };
+
+        // Safe because we know that our file is a vCPU fd, we know the kernel will only write
+        // the correct amount of memory to our pointer, and we verify the return result.
+        let ret = unsafe { ioctl_with_mut_ref(&self.fd, KVM_TRANSLATE(), &mut translation) };
+        if ret < 0 {
+            return Err(Error::VcpuTranslate(utils::errno::Error::last()));
+        }
+        Ok(if translation.valid != 0 {
+            Some(translation.physical_address)
+        } else {
+            None
+        })
+    }
+
     /// Runs the vCPU in KVM context and handles the kvm exit reason.
     ///
     /// Returns error or enum specifying whether emulation was handled or interrupted.
@@ -402,7 +603,7 @@ mod tests {
 
     use super::*;
     use crate::vstate::vm::{tests::setup_vm, Vm};
-    use cpuid::common::{get_vendor_id_from_host, VENDOR_ID_INTEL};
+    use cpuid::common::{get_vendor_id_from_host, VENDOR_ID_AMD, VENDOR_ID_INTEL};
 
     impl Default for VcpuState {
         fn default() -> Self {
@@ -436,12 +637,14 @@ mod tests {
             vcpu_count: 1,
             ht_enabled: false,
             cpu_template: None,
+            custom_cpu_template: None,
         };
 
         assert!(vcpu
             .configure(
                 &vm_mem,
                 GuestAddress(0),
+                BootProtocol::Linux,
                 &vcpu_config,
                 vm.supported_cpuid().clone()
             )
@@ -452,6 +655,7 @@ mod tests {
         let t2_res = vcpu.configure(
             &vm_mem,
             GuestAddress(arch::get_kernel_start()),
+            BootProtocol::Linux,
             &vcpu_config,
             vm.supported_cpuid().clone(),
         );
@@ -461,6 +665,17 @@ mod tests {
         let c3_res = vcpu.configure(
             &vm_mem,
             GuestAddress(0),
+            BootProtocol::Linux,
+            &vcpu_config,
+            vm.supported_cpuid().clone(),
+        );
+
+        // Test configure while using the T2A template.
+        vcpu_config.cpu_template = Some(CpuFeaturesTemplate::T2A);
+        let t2a_res = vcpu.configure(
+            &vm_mem,
+            GuestAddress(0),
+            BootProtocol::Linux,
             &vcpu_config,
             vm.supported_cpuid().clone(),
         );
@@ -469,12 +684,121 @@ mod tests {
             VENDOR_ID_INTEL => {
                 assert!(t2_res.is_ok());
                 assert!(c3_res.is_ok());
+                assert!(t2a_res.is_err());
+            }
+            VENDOR_ID_AMD => {
+                assert!(t2_res.is_err());
+                assert!(c3_res.is_err());
+                assert!(t2a_res.is_ok());
             }
             _ => {
                 assert!(t2_res.is_err());
                 assert!(c3_res.is_err());
+                assert!(t2a_res.is_err());
             }
         }
+
+        // Templates for other architectures are rejected.
+        vcpu_config.cpu_template = Some(CpuFeaturesTemplate::V1N1);
+        match vcpu.configure(
+            &vm_mem,
+            GuestAddress(0),
+            BootProtocol::Linux,
+            &vcpu_config,
+            vm.supported_cpuid().clone(),
+        ) {
+            Err(Error::UnsupportedCpuTemplate(CpuFeaturesTemplate::V1N1)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_configure_vcpu_custom_template() {
+        use crate::vmm_config::cpu_config::{
+            CpuidModifier, CpuidRegister, CustomCpuTemplate, MsrModifier,
+        };
+
+        let (vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        // IA32_MISC_ENABLE, which is set up with fast string operations enabled.
+        let misc_enable = 0x1a0;
+        let hypervisor = 1 << 31;
+        let vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            ht_enabled: false,
+            cpu_template: None,
+            custom_cpu_template: Some(CustomCpuTemplate {
+                cpuid_modifiers: vec![CpuidModifier {
+                    leaf: 0x1,
+                    subleaf: 0,
+                    register: CpuidRegister::Ecx,
+                    mask: hypervisor,
+                    value: 0,
+                }],
+                msr_modifiers: vec![
+                    MsrModifier {
+                        addr: misc_enable,
+                        mask: 0x1,
+                        value: 0x1,
+                    },
+                    // Modifiers of the same MSR are applied in order.
+                    MsrModifier {
+                        addr: misc_enable,
+                        mask: 0x1,
+                        value: 0x0,
+                    },
+                ],
+            }),
+        };
+
+        vcpu.configure(
+            &vm_mem,
+            GuestAddress(0),
+            BootProtocol::Linux,
+            &vcpu_config,
+            vm.supported_cpuid().clone(),
+        )
+        .unwrap();
+
+        let cpuid = vcpu
+            .fd
+            .get_cpuid2(kvm_bindings::KVM_MAX_CPUID_ENTRIES)
+            .unwrap();
+        let leaf_0x1 = cpuid
+            .as_slice()
+            .iter()
+            .find(|entry| entry.function == 0x1)
+            .unwrap();
+        assert_eq!(leaf_0x1.ecx & hypervisor, 0);
+
+        let mut msrs = Msrs::from_entries(&[kvm_msr_entry {
+            index: misc_enable,
+            ..Default::default()
+        }])
+        .unwrap();
+        assert_eq!(vcpu.fd.get_msrs(&mut msrs).unwrap(), 1);
+        assert_eq!(msrs.as_slice()[0].data & 0x1, 0x0);
+
+        // Leaves missing from the CPUID cannot be modified.
+        let mut vcpu_config = vcpu_config;
+        vcpu_config.custom_cpu_template = Some(CustomCpuTemplate {
+            cpuid_modifiers: vec![CpuidModifier {
+                leaf: 0x4000_00ff,
+                subleaf: 0,
+                register: CpuidRegister::Eax,
+                mask: 1,
+                value: 1,
+            }],
+            msr_modifiers: vec![],
+        });
+        assert!(vcpu
+            .configure(
+                &vm_mem,
+                GuestAddress(0),
+                BootProtocol::Linux,
+                &vcpu_config,
+                vm.supported_cpuid().clone()
+            )
+            .is_err());
     }
 
     #[test]